@@ -4,6 +4,8 @@ use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tracing::warn;
 pub mod config;
+pub mod secrets;
+pub mod trace;
 use bytes::BytesMut;
 use config::GpuModelConfig;
 
@@ -28,7 +30,7 @@ pub struct DeviceInfo {
 }
 
 // Device information from client to server (max num: 8)
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone)]
 pub struct DevicesInfo {
     //pod info
     pub num: u16,
@@ -51,6 +53,95 @@ pub struct DevicesInfo {
     pub powerlimit_w: u128,
 }
 
+// Standardized on-device benchmark, measured once before login
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, Default, PartialEq)]
+pub struct BenchmarkResult {
+    /// Single-precision matrix multiply throughput
+    pub gflops: f32,
+    /// Sustained memory copy bandwidth
+    pub memory_bandwidth_gbps: f32,
+    /// Decode speed of a 7B Q4_0 model, bound by the measured bandwidth
+    pub tokens_per_sec: f32,
+    pub duration_ms: u32,
+}
+
+// Speed of a local model on a worker, measured with `gpuf-c bench`
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Default, PartialEq)]
+pub struct ModelBenchmark {
+    /// File name of the GGUF model
+    pub model: String,
+    pub model_bytes: u64,
+    /// Layers offloaded to the GPU
+    pub n_gpu_layers: u32,
+    pub runs: Vec<ModelBenchmarkRun>,
+}
+
+/// Prompt processing and generation speed with one context and batch size
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, Default, PartialEq)]
+pub struct ModelBenchmarkRun {
+    pub n_ctx: u32,
+    pub n_batch: u32,
+    /// Prompt tokens evaluated in batches of `n_batch`
+    pub n_prompt: u32,
+    /// Tokens generated one at a time
+    pub n_gen: u32,
+    pub prompt_tokens_per_sec: f32,
+    pub gen_tokens_per_sec: f32,
+    /// Memory the model and context take by the worker's memory plan
+    pub vram_bytes: u64,
+    pub ram_bytes: u64,
+}
+
+// Link quality between a worker and the server, probed periodically by the worker
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetworkQuality {
+    /// TCP connect round trip to the control port
+    pub rtt_ms: u32,
+    /// Worker to server
+    pub upload_mbps: f32,
+    /// Server to worker
+    pub download_mbps: f32,
+}
+
+impl NetworkQuality {
+    /// Estimated time to send `bytes` from the server to the worker
+    pub fn download_secs(&self, bytes: usize) -> f32 {
+        let transfer = if self.download_mbps > 0.0 {
+            bytes as f32 * 8.0 / (self.download_mbps * 1_000_000.0)
+        } else {
+            f32::INFINITY
+        };
+        self.rtt_ms as f32 / 1000.0 + transfer
+    }
+}
+
+// Battery and thermal state of a mobile worker, sent with heartbeats
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PowerStatus {
+    /// 0-100
+    pub battery_pct: u8,
+    pub charging: bool,
+    /// Hottest thermal zone
+    pub temperature_c: u8,
+    /// Android PowerManager thermal status, 0 (none) to 6 (shutdown)
+    pub thermal_status: u8,
+}
+
+// Per-GPU telemetry read from NVML, sent with every heartbeat
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Default, PartialEq, Eq)]
+pub struct GpuMetrics {
+    pub index: u32,
+    pub name: String,
+    pub utilization_pct: u8,
+    pub memory_used_mb: u64,
+    pub memory_total_mb: u64,
+    pub temperature_c: u32,
+    pub power_draw_mw: u32,
+    pub power_limit_mw: u32,
+    pub graphics_clock_mhz: u32,
+    pub memory_clock_mhz: u32,
+}
+
 #[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone)]
 pub struct PodModel {
     pub pod_id: u16,
@@ -60,7 +151,142 @@ pub struct PodModel {
     pub expected_size: Option<u64>,
 }
 
-#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+// Model assignment of protocol 2, PodModel with the fields added since
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone)]
+pub struct PodModelV2 {
+    pub pod_id: u16,
+    pub model_name: Option<String>,
+    pub download_url: Option<String>,
+    pub checksum: Option<String>,
+    pub expected_size: Option<u64>,
+    /// Publisher's base64 Ed25519 signature over the file's SHA-256 digest
+    pub signature: Option<String>,
+    /// Catalog version; a worker holding another version of the model updates it
+    pub version: Option<String>,
+    /// Chunk hashes of this version, to update an older file by delta
+    pub chunk_manifest_url: Option<String>,
+    /// Small model of the same vocabulary that drafts tokens for
+    /// speculative decoding on llama.cpp workers
+    pub draft_download_url: Option<String>,
+    pub draft_checksum: Option<String>,
+}
+
+impl From<PodModel> for PodModelV2 {
+    fn from(pod: PodModel) -> Self {
+        Self {
+            pod_id: pod.pod_id,
+            model_name: pod.model_name,
+            download_url: pod.download_url,
+            checksum: pod.checksum,
+            expected_size: pod.expected_size,
+            signature: None,
+            version: None,
+            chunk_manifest_url: None,
+            draft_download_url: None,
+            draft_checksum: None,
+        }
+    }
+}
+
+impl From<PodModelV2> for PodModel {
+    fn from(pod: PodModelV2) -> Self {
+        Self {
+            pod_id: pod.pod_id,
+            model_name: pod.model_name,
+            download_url: pod.download_url,
+            checksum: pod.checksum,
+            expected_size: pod.expected_size,
+        }
+    }
+}
+
+// Latest gpuf-c release the server offers its workers
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct WorkerRelease {
+    /// Dotted version, compared with the worker's own
+    pub version: String,
+    pub builds: Vec<WorkerBuild>,
+}
+
+// Binary of a WorkerRelease for one platform
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct WorkerBuild {
+    /// `{arch}-{os}-{accelerator}`, e.g. `x86_64-linux-cuda` or `aarch64-macos-metal`
+    pub target: String,
+    pub url: String,
+    pub size: Option<u64>,
+    /// Base64 Ed25519 signature over the binary's SHA-256 digest
+    pub signature: String,
+}
+
+// Build and platform of a worker, sent with its heartbeats
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkerVersion {
+    /// gpuf-c version, e.g. `0.2.0`
+    pub version: String,
+    pub engines: Vec<EngineVersion>,
+    /// Cargo features the binary was built with, e.g. `cuda` or `nvml`
+    pub features: Vec<String>,
+    /// OS name, version and kernel release, e.g. `Linux 22.04 Ubuntu (6.8.0-45-generic)`
+    pub os_version: String,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct EngineVersion {
+    /// e.g. `llama.cpp`, `vllm` or `ollama`
+    pub engine: String,
+    pub version: String,
+}
+
+impl WorkerVersion {
+    /// Whether the worker runs `version` or a newer one
+    pub fn at_least(&self, version: &str) -> bool {
+        !is_newer_version(version, &self.version)
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    pub fn engine_version(&self, engine: &str) -> Option<&str> {
+        self.engines
+            .iter()
+            .find(|e| e.engine == engine)
+            .map(|e| e.version.as_str())
+    }
+}
+
+/// Whether dotted `version` is newer than `than`. Parts compare as numbers, a
+/// missing part counts as 0 and a suffix like `-rc1` is ignored.
+pub fn is_newer_version(version: &str, than: &str) -> bool {
+    let parse = |version: &str| -> Vec<u64> {
+        version
+            .trim()
+            .trim_start_matches('v')
+            .split(['.', '-', '+'])
+            .map_while(|part| part.parse().ok())
+            .collect()
+    };
+    let (version, than) = (parse(version), parse(than));
+    for i in 0..version.len().max(than.len()) {
+        let part = version.get(i).copied().unwrap_or(0);
+        let than_part = than.get(i).copied().unwrap_or(0);
+        if part != than_part {
+            return part > than_part;
+        }
+    }
+    false
+}
+
+/// Whether `version` is only dotted numbers like `1.2.0`, as worker releases
+/// must be since the version goes into file names
+pub fn is_release_version(version: &str) -> bool {
+    version
+        .split('.')
+        .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DownloadStatus {
     Pending,
     Downloading,
@@ -68,13 +294,51 @@ pub enum DownloadStatus {
     Failed,
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone)]
 pub struct ChatMessage {
     pub role: String,
     pub content: String,
 }
 
- #[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+/// Leading messages of a chat that other requests share, e.g. a system prompt
+/// or RAG header. Workers keep the KV state of the prefix to skip evaluating
+/// it again for the next request carrying the same hash.
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct PromptPrefix {
+    /// Hex SHA-256 of the roles and contents of the prefix messages
+    pub hash: String,
+    /// Number of leading messages in the prefix
+    pub messages: u32,
+}
+
+/// What a worker does when the prompt and `max_tokens` exceed its context
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextOverflow {
+    /// Fail the request with [`CONTEXT_LENGTH_EXCEEDED`]
+    #[default]
+    Error,
+    /// Drop the oldest prompt tokens until the request fits
+    TruncateLeft,
+    /// Drop the oldest prompt tokens until a quarter of the context is free,
+    /// then shift the oldest tokens out whenever generation fills it
+    SlidingWindow,
+}
+
+/// Error prefix of requests that do not fit the worker's context
+pub const CONTEXT_LENGTH_EXCEEDED: &str = "context_length_exceeded";
+
+/// Error prefix of requests over a size limit, e.g. of the prompt
+pub const REQUEST_TOO_LARGE: &str = "request_too_large";
+
+/// Error prefix of requests asking for more than a limit allows, e.g. a
+/// `max_tokens` above the ceiling
+pub const LIMIT_EXCEEDED: &str = "limit_exceeded";
+
+/// Error prefix of tasks that ran past a stage timeout or their deadline
+pub const DEADLINE_EXCEEDED: &str = "deadline_exceeded";
+
+ #[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
  pub enum OutputPhase {
      Unknown,
      Analysis,
@@ -150,14 +414,23 @@ pub struct SystemInfo {
     pub network_tx: u64,
 }
 
-/// Commands exchanged between client and server.
+/// Protocol version workers send at login. Version 1 is the original command
+/// set: those workers log in with `Login` and are sent the original variants
+/// only. Workers on version 2 log in with `LoginV2`, answer challenges and
+/// understand every command of this crate.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Commands exchanged between client and server. The bincode encoding is not
+/// self-describing and tags variants by their index, so existing variants
+/// never change: new data goes into new variants at the end of the enum, sent
+/// to peers whose protocol version knows them.
 #[derive(Encode, Decode, Debug, Clone)]
 pub enum Command {
     V1(CommandV1),
     V2(CommandV2),
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone)]
 pub enum CommandV1 {
     /// Request a new proxy connection. Sent from gpuf-s to a chosen gpuf-c.
     RequestNewProxyConn {
@@ -274,6 +547,629 @@ pub enum CommandV1 {
         status: DownloadStatus,
         error: Option<String>,
     },
+
+    // Worker stops or resumes taking inference, e.g. while a phone is hot or low on battery
+    WorkerBusy {
+        client_id: [u8; 16],
+        busy: bool,
+        reason: Option<String>,
+    },
+
+    // Runtime settings from server to client, applied without restart
+    UpdateConfig {
+        version: u64,
+        config: WorkerConfig,
+    },
+
+    // Result of applying an UpdateConfig, from client to server
+    UpdateConfigAck {
+        client_id: [u8; 16],
+        version: u64,
+        success: bool,
+        error: Option<String>,
+    },
+
+    // Models the worker cannot fit into its free memory, from client to server.
+    // The server answers with PullModelResult holding a smaller model, or none.
+    RequestSmallerModel {
+        client_id: [u8; 16],
+        pod_id: u16,
+        rejected_models: Vec<String>,
+        max_model_bytes: u64,
+    },
+
+    // Inference engine health changed, from client to server
+    EngineStatus {
+        client_id: [u8; 16],
+        state: EngineState,
+        /// Automatic restarts since the worker started
+        restarts: u32,
+        error: Option<String>,
+    },
+
+    // Typed job from server to client, answered with JobUpdate
+    Job {
+        job_id: String,
+        spec: JobSpec,
+        /// 0 runs the job without a time limit
+        timeout_secs: u32,
+    },
+
+    // Status of a job, from client to server: Running once accepted, then
+    // how it ended
+    JobUpdate {
+        client_id: [u8; 16],
+        job_id: String,
+        status: JobStatus,
+        output: Option<JobOutput>,
+        error: Option<String>,
+    },
+
+    // Liveness challenge from server to client, answered with
+    // ChallengeResponse within deadline_ms
+    Challenge {
+        challenge_id: [u8; 16],
+        task: ChallengeTask,
+        deadline_ms: u32,
+    },
+
+    // Answer to a Challenge, from client to server
+    ChallengeResponse {
+        client_id: [u8; 16],
+        challenge_id: [u8; 16],
+        answer: Vec<u8>,
+    },
+
+    // Prompt tokens an inference task dropped to fit the worker's context,
+    // from client to server before the task's first result chunk
+    ContextOverflowApplied {
+        task_id: String,
+        strategy: ContextOverflow,
+        dropped_tokens: u32,
+    },
+
+    // Model loaded and warmed up, or not (yet), from client to server.
+    // Interactive requests avoid cold workers while warm ones can take them.
+    ModelWarmth {
+        client_id: [u8; 16],
+        warm: bool,
+    },
+
+    // Server is shutting down, from server to client. The client reconnects
+    // after delay_ms, to reconnect_to (same ports) when set, else to the
+    // same address once the server is back.
+    ServerRestarting {
+        reconnect_to: Option<String>,
+        delay_ms: u32,
+    },
+
+    // Move to another gpuf-s, from server to client, for blue/green
+    // migrations of the fleet. The client disconnects and logs in to
+    // server_addr on the given ports after a random delay of up to
+    // delay_window_ms.
+    MigrateServer {
+        server_addr: String,
+        control_port: u16,
+        proxy_port: u16,
+        delay_window_ms: u32,
+    },
+
+    // Latest worker release, from server to client after login and when an
+    // operator publishes one. A client on an older version with a signed
+    // build for its platform updates itself once idle.
+    WorkerUpdate {
+        release: WorkerRelease,
+    },
+    // Results of `gpuf-c bench --upload`, from client to server, which keeps
+    // the latest per client and model
+    ModelBenchmarkReport {
+        client_id: [u8; 16],
+        benchmark: ModelBenchmark,
+    },
+
+    /// Bandwidth probe on a proxy connection instead of NewProxyConn. Sent from
+    /// gpuf-c, gpuf-s answers each one with `reply_bytes` of payload.
+    NetworkProbe {
+        payload: Vec<u8>,
+        reply_bytes: u32,
+    },
+
+    /// Login of workers on protocol 2 and later, see [`PROTOCOL_VERSION`]
+    LoginV2 {
+        client_id: [u8; 16],
+        version: u32,
+        os_type: OsType,
+        auto_models: bool,
+        system_info: SystemInfo,
+        device_memtotal_gb: u32,
+        device_total_tflops: u32,
+        devices_info: Vec<DevicesInfo>,
+        benchmark: Option<BenchmarkResult>,
+        /// Token that gets a new worker approved without an operator
+        enrollment_token: Option<String>,
+        /// Region the worker says it is in, e.g. `eu-west`
+        region: Option<String>,
+    },
+
+    /// LoginResult for workers on protocol 2 and later
+    LoginResultV2 {
+        success: bool,
+        pods_model: Vec<PodModelV2>,
+        error: Option<String>,
+    },
+
+    /// Heartbeat of workers on protocol 2 and later
+    HeartbeatV2 {
+        client_id: [u8; 16],
+        system_info: SystemInfo,
+        device_count: u16,
+        device_memtotal_gb: u32,
+        device_total_tflops: u32,
+        devices_info: Vec<DevicesInfo>,
+        gpu_metrics: Vec<GpuMetrics>,
+        network_quality: Option<NetworkQuality>,
+        power: Option<PowerStatus>,
+        /// Build and engine versions, None from workers that do not report them
+        version: Option<WorkerVersion>,
+    },
+
+    /// PullModelResult for workers on protocol 2 and later
+    PullModelResultV2 {
+        pods_model: Vec<PodModelV2>,
+        error: Option<String>,
+    },
+
+    /// InferenceTask for workers on protocol 2 and later
+    InferenceTaskV2 {
+        task_id: String,
+        /// `request-id` and W3C trace context of the originating request
+        request_id: Option<String>,
+        traceparent: Option<String>,
+        prompt: String,
+        max_tokens: u32,
+        temperature: f32,
+        top_k: u32,
+        top_p: f32,
+        repeat_penalty: f32,
+        repeat_last_n: i32,
+        min_keep: u32,
+        overflow: ContextOverflow,
+        /// Milliseconds the caller still waits for the result, counted from
+        /// when the task is received; the worker drops it afterwards
+        deadline_ms: Option<u64>,
+    },
+
+    /// ChatInferenceTask for workers on protocol 2 and later
+    ChatInferenceTaskV2 {
+        task_id: String,
+        request_id: Option<String>,
+        traceparent: Option<String>,
+        model: String,
+        messages: Vec<ChatMessage>,
+        max_tokens: u32,
+        temperature: f32,
+        top_k: u32,
+        top_p: f32,
+        repeat_penalty: f32,
+        repeat_last_n: i32,
+        min_keep: u32,
+        /// Shared prefix whose KV state the worker may reuse
+        prefix: Option<PromptPrefix>,
+        overflow: ContextOverflow,
+        deadline_ms: Option<u64>,
+    },
+}
+
+impl CommandV1 {
+    /// The current variant of a command of protocol 1, with the fields that
+    /// protocol lacks left empty
+    pub fn upgrade(self) -> Self {
+        match self {
+            Self::Login {
+                client_id,
+                version,
+                os_type,
+                auto_models,
+                system_info,
+                device_memtotal_gb,
+                device_total_tflops,
+                devices_info,
+            } => Self::LoginV2 {
+                client_id,
+                version,
+                os_type,
+                auto_models,
+                system_info,
+                device_memtotal_gb,
+                device_total_tflops,
+                devices_info,
+                benchmark: None,
+                enrollment_token: None,
+                region: None,
+            },
+            Self::LoginResult {
+                success,
+                pods_model,
+                error,
+            } => Self::LoginResultV2 {
+                success,
+                pods_model: pods_model.into_iter().map(Into::into).collect(),
+                error,
+            },
+            Self::Heartbeat {
+                client_id,
+                system_info,
+                device_count,
+                device_memtotal_gb,
+                device_total_tflops,
+                devices_info,
+            } => Self::HeartbeatV2 {
+                client_id,
+                system_info,
+                device_count,
+                device_memtotal_gb,
+                device_total_tflops,
+                devices_info,
+                gpu_metrics: Vec::new(),
+                network_quality: None,
+                power: None,
+                version: None,
+            },
+            Self::PullModelResult { pods_model, error } => Self::PullModelResultV2 {
+                pods_model: pods_model.into_iter().map(Into::into).collect(),
+                error,
+            },
+            Self::InferenceTask {
+                task_id,
+                prompt,
+                max_tokens,
+                temperature,
+                top_k,
+                top_p,
+                repeat_penalty,
+                repeat_last_n,
+                min_keep,
+            } => Self::InferenceTaskV2 {
+                task_id,
+                request_id: None,
+                traceparent: None,
+                prompt,
+                max_tokens,
+                temperature,
+                top_k,
+                top_p,
+                repeat_penalty,
+                repeat_last_n,
+                min_keep,
+                overflow: ContextOverflow::default(),
+                deadline_ms: None,
+            },
+            Self::ChatInferenceTask {
+                task_id,
+                model,
+                messages,
+                max_tokens,
+                temperature,
+                top_k,
+                top_p,
+                repeat_penalty,
+                repeat_last_n,
+                min_keep,
+            } => Self::ChatInferenceTaskV2 {
+                task_id,
+                request_id: None,
+                traceparent: None,
+                model,
+                messages,
+                max_tokens,
+                temperature,
+                top_k,
+                top_p,
+                repeat_penalty,
+                repeat_last_n,
+                min_keep,
+                prefix: None,
+                overflow: ContextOverflow::default(),
+                deadline_ms: None,
+            },
+            command => command,
+        }
+    }
+
+    /// This command as protocol 1 has it, dropping the fields added since.
+    /// None for commands that protocol does not know.
+    pub fn downgrade(self) -> Option<Self> {
+        let command = match self {
+            Self::RequestNewProxyConn { .. }
+            | Self::NewProxyConn { .. }
+            | Self::Login { .. }
+            | Self::LoginResult { .. }
+            | Self::Heartbeat { .. }
+            | Self::PullModelResult { .. }
+            | Self::ModelStatus { .. }
+            | Self::InferenceTask { .. }
+            | Self::ChatInferenceTask { .. }
+            | Self::CancelInference { .. }
+            | Self::InferenceResult { .. }
+            | Self::InferenceResultChunk { .. }
+            | Self::ModelDownloadProgress { .. } => self,
+            Self::LoginV2 {
+                client_id,
+                version,
+                os_type,
+                auto_models,
+                system_info,
+                device_memtotal_gb,
+                device_total_tflops,
+                devices_info,
+                ..
+            } => Self::Login {
+                client_id,
+                version,
+                os_type,
+                auto_models,
+                system_info,
+                device_memtotal_gb,
+                device_total_tflops,
+                devices_info,
+            },
+            Self::LoginResultV2 {
+                success,
+                pods_model,
+                error,
+            } => Self::LoginResult {
+                success,
+                pods_model: pods_model.into_iter().map(Into::into).collect(),
+                error,
+            },
+            Self::HeartbeatV2 {
+                client_id,
+                system_info,
+                device_count,
+                device_memtotal_gb,
+                device_total_tflops,
+                devices_info,
+                ..
+            } => Self::Heartbeat {
+                client_id,
+                system_info,
+                device_count,
+                device_memtotal_gb,
+                device_total_tflops,
+                devices_info,
+            },
+            Self::PullModelResultV2 { pods_model, error } => Self::PullModelResult {
+                pods_model: pods_model.into_iter().map(Into::into).collect(),
+                error,
+            },
+            Self::InferenceTaskV2 {
+                task_id,
+                prompt,
+                max_tokens,
+                temperature,
+                top_k,
+                top_p,
+                repeat_penalty,
+                repeat_last_n,
+                min_keep,
+                ..
+            } => Self::InferenceTask {
+                task_id,
+                prompt,
+                max_tokens,
+                temperature,
+                top_k,
+                top_p,
+                repeat_penalty,
+                repeat_last_n,
+                min_keep,
+            },
+            Self::ChatInferenceTaskV2 {
+                task_id,
+                model,
+                messages,
+                max_tokens,
+                temperature,
+                top_k,
+                top_p,
+                repeat_penalty,
+                repeat_last_n,
+                min_keep,
+                ..
+            } => Self::ChatInferenceTask {
+                task_id,
+                model,
+                messages,
+                max_tokens,
+                temperature,
+                top_k,
+                top_p,
+                repeat_penalty,
+                repeat_last_n,
+                min_keep,
+            },
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
+impl Command {
+    /// This command for a peer on protocol `version`, None when the peer
+    /// does not know it
+    pub fn for_protocol(self, version: u32) -> Option<Self> {
+        match self {
+            Self::V1(command) if version < PROTOCOL_VERSION => command.downgrade().map(Self::V1),
+            command => Some(command),
+        }
+    }
+
+    /// Commands of protocol 1 as their current variant, so handlers only
+    /// match those
+    fn upgrade(self) -> Self {
+        match self {
+            Self::V1(command) => Self::V1(command.upgrade()),
+            command => command,
+        }
+    }
+}
+
+/// Work the server dispatches to a worker besides proxied HTTP
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq)]
+pub enum JobSpec {
+    /// Generate text from a prompt on the worker's engine
+    Inference {
+        /// The engine's first model when unset
+        model: Option<String>,
+        prompt: String,
+        max_tokens: u32,
+        /// Uploaded text documents the prompt is answered from, read into
+        /// the context ahead of it
+        #[serde(default)]
+        inputs: Vec<JobInput>,
+    },
+    /// Embed texts through the engine's `/v1/embeddings`
+    Embedding {
+        model: Option<String>,
+        input: Vec<String>,
+    },
+    /// Run the login benchmark again
+    Benchmark,
+    /// Download a file into the worker's model directory ahead of its use
+    Prefetch { url: String, file_name: String },
+    /// Probe the engine's health now
+    HealthProbe,
+}
+
+/// A file uploaded to the gateway that a job takes as input. Dispatching
+/// the job only needs the id; the server fills in the rest.
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq)]
+pub struct JobInput {
+    pub upload_id: String,
+    /// Signed URL the worker downloads the file from
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub size: u64,
+    /// Hex SHA-256 of the content
+    #[serde(default)]
+    pub sha256: String,
+}
+
+impl JobSpec {
+    pub fn kind(&self) -> JobKind {
+        match self {
+            JobSpec::Inference { .. } => JobKind::Inference,
+            JobSpec::Embedding { .. } => JobKind::Embedding,
+            JobSpec::Benchmark => JobKind::Benchmark,
+            JobSpec::Prefetch { .. } => JobKind::Prefetch,
+            JobSpec::HealthProbe => JobKind::HealthProbe,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JobKind {
+    Inference,
+    Embedding,
+    Benchmark,
+    Prefetch,
+    HealthProbe,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+    /// Not run: the worker has no handler for its kind, or already runs a
+    /// job with its id
+    Rejected,
+}
+
+/// What a finished job produced
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq)]
+pub enum JobOutput {
+    Text {
+        text: String,
+        completion_tokens: u32,
+    },
+    Embeddings(Vec<Vec<f32>>),
+    Benchmark(BenchmarkResult),
+    Prefetched {
+        path: String,
+        bytes: u64,
+    },
+    /// Engine answered its health probe after this long
+    Healthy {
+        latency_ms: u32,
+    },
+}
+
+/// Work a worker proves it is alive with, so spoofed heartbeats alone earn
+/// nothing. Solving takes the worker many hashes, checking the answer takes
+/// the server one.
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq)]
+pub enum ChallengeTask {
+    /// A nonce whose SHA-256 after `seed` has `difficulty` leading zero
+    /// bits, about 2^difficulty hashes to find
+    Work { seed: [u8; 32], difficulty: u8 },
+}
+
+impl ChallengeTask {
+    /// The first nonce that passes, as little-endian bytes
+    pub fn solve(&self) -> Vec<u8> {
+        (0u64..)
+            .map(|nonce| nonce.to_le_bytes().to_vec())
+            .find(|answer| self.verify(answer))
+            .unwrap_or_default()
+    }
+
+    pub fn verify(&self, answer: &[u8]) -> bool {
+        use sha2::{Digest, Sha256};
+
+        match self {
+            ChallengeTask::Work { seed, difficulty } => {
+                let hash = Sha256::new()
+                    .chain_update(seed)
+                    .chain_update(answer)
+                    .finalize();
+                let mut zeros = 0;
+                for byte in hash {
+                    zeros += byte.leading_zeros();
+                    if byte != 0 {
+                        break;
+                    }
+                }
+                zeros >= u32::from(*difficulty)
+            }
+        }
+    }
+}
+
+/// Health of a worker's inference engine as seen by its supervisor
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineState {
+    Healthy,
+    /// Failing health checks, not restarted yet
+    Unhealthy,
+    Restarting,
+    /// Restarted too often in a short time, retried with the longest backoff
+    CrashLoop,
+}
+
+/// Worker settings pushed by the server. Unset fields keep their current value.
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Default, PartialEq)]
+pub struct WorkerConfig {
+    pub heartbeat_interval_secs: Option<u32>,
+    /// Interval of the model status report
+    pub monitor_interval_secs: Option<u32>,
+    /// 0 removes the limit
+    pub max_concurrent_requests: Option<u32>,
+    /// One of trace, debug, info, warn, error
+    pub log_level: Option<String>,
+    /// Unload a model unused for this long, 0 keeps it loaded
+    pub idle_unload_secs: Option<u32>,
 }
 
 #[derive(Encode, Decode, Debug, Clone)]
@@ -395,7 +1291,7 @@ pub enum P2PConnectionType {
     TURN,   // through TURN server
 }
 
-#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq)]
 pub enum OsType {
     MACOS,
     WINDOWS,
@@ -406,7 +1302,7 @@ pub enum OsType {
 }
 
 #[repr(i16)]
-#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, PartialEq)]
 pub enum EngineType {
     Ollama = 1,
     Vllm = 2,
@@ -414,6 +1310,8 @@ pub enum EngineType {
     ONNX = 4,
     Llama = 6,
     None = 5,
+    External = 7,
+    StableDiffusion = 8,
 }
 
 impl EngineType {
@@ -425,6 +1323,8 @@ impl EngineType {
             EngineType::ONNX => 4,
             EngineType::Llama => 6,
             EngineType::None => 5,
+            EngineType::External => 7,
+            EngineType::StableDiffusion => 8,
         }
     }
 }
@@ -439,6 +1339,8 @@ impl fmt::Display for EngineType {
             EngineType::ONNX => write!(f, "ONNX"),
             EngineType::Llama => write!(f, "Llama"),
             EngineType::None => write!(f, "None"),
+            EngineType::External => write!(f, "External"),
+            EngineType::StableDiffusion => write!(f, "Stable Diffusion"),
         }
     }
 }
@@ -476,9 +1378,9 @@ pub async fn read_command<R: AsyncRead + Unpin>(
     buf.resize(len, 0);
     reader.read_exact(buf).await?;
 
-    let (command, _) = bincode::decode_from_slice(buf.as_ref(), config)
+    let (command, _): (Command, _) = bincode::decode_from_slice(buf.as_ref(), config)
         .map_err(|e| anyhow!("Failed to deserialize command: {}", e))?;
-    Ok(command)
+    Ok(command.upgrade())
 }
 
 /// Writes a command to an async writer.
@@ -503,6 +1405,26 @@ pub async fn write_command<W: AsyncWrite + Unpin>(writer: &mut W, command: &Comm
     Ok(())
 }
 
+/// Writes `command` in the form a peer on protocol `version` understands.
+/// Returns false, writing nothing, when the peer does not know the command.
+pub async fn write_command_for<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    command: &Command,
+    version: u32,
+) -> Result<bool> {
+    if version >= PROTOCOL_VERSION {
+        write_command(writer, command).await?;
+        return Ok(true);
+    }
+    match command.clone().for_protocol(version) {
+        Some(command) => {
+            write_command(writer, &command).await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
 /// Synchronous version: Reads a command from a blocking reader.
 /// The format is a 4-byte length prefix (u32) followed by the bincode-encoded command.
 pub fn read_command_sync<R: std::io::Read>(reader: &mut R) -> Result<Command> {
@@ -525,9 +1447,9 @@ pub fn read_command_sync<R: std::io::Read>(reader: &mut R) -> Result<Command> {
     let mut buf = vec![0u8; len];
     reader.read_exact(&mut buf)?;
 
-    let (command, _) = bincode::decode_from_slice(&buf, config)
+    let (command, _): (Command, _) = bincode::decode_from_slice(&buf, config)
         .map_err(|e| anyhow!("Failed to deserialize command: {}", e))?;
-    Ok(command)
+    Ok(command.upgrade())
 }
 
 /// Synchronous version: Writes a command to a blocking writer.
@@ -717,6 +1639,58 @@ fn test_format_bytes() {
     assert_eq!(value, value2);
 }
 
+#[test]
+fn test_challenge_task() {
+    let task = ChallengeTask::Work {
+        seed: [7; 32],
+        difficulty: 12,
+    };
+    let answer = task.solve();
+    assert!(task.verify(&answer));
+    // Every smaller nonce fails, as solve returns the first that passes
+    let nonce = u64::from_le_bytes(answer.as_slice().try_into().unwrap());
+    assert!((0..nonce).all(|n| !task.verify(&n.to_le_bytes())));
+    assert!(ChallengeTask::Work {
+        seed: [7; 32],
+        difficulty: 0,
+    }
+    .verify(&[]));
+}
+
+#[test]
+fn test_worker_version() {
+    assert!(is_newer_version("0.2.0", "0.1.9"));
+    assert!(is_newer_version("v1.10", "1.9.3"));
+    assert!(is_newer_version("1.0.1-rc1", "1.0.0"));
+    assert!(!is_newer_version("1.0", "1.0.0"));
+    assert!(!is_newer_version("0.9.9", "1.0.0"));
+    assert!(!is_newer_version("latest", "1.0.0"));
+    assert!(is_release_version("1.2.0"));
+    assert!(is_release_version("10"));
+    assert!(!is_release_version(""));
+    assert!(!is_release_version("1..0"));
+    assert!(!is_release_version("v1.2.0"));
+    assert!(!is_release_version("1.2.0-rc1"));
+    assert!(!is_release_version("1/../../bin/sh"));
+
+    let version = WorkerVersion {
+        version: "0.2.1".to_string(),
+        engines: vec![EngineVersion {
+            engine: "vllm".to_string(),
+            version: "0.6.3".to_string(),
+        }],
+        features: vec!["cuda".to_string()],
+        os_version: String::new(),
+    };
+    assert!(version.at_least("0.2"));
+    assert!(version.at_least("0.2.1"));
+    assert!(!version.at_least("0.3.0"));
+    assert!(version.has_feature("cuda"));
+    assert!(!version.has_feature("metal"));
+    assert_eq!(version.engine_version("vllm"), Some("0.6.3"));
+    assert_eq!(version.engine_version("ollama"), None);
+}
+
 #[tokio::test]
 async fn test_command_serialization_roundtrip() {
     // Create a Vec<u8> buffer for writing
@@ -725,7 +1699,7 @@ async fn test_command_serialization_roundtrip() {
     let mut writer = tokio::io::BufWriter::new(&mut buf);
 
     // Test data using CommandV1
-    let cmd = Command::V1(CommandV1::Login {
+    let cmd = Command::V1(CommandV1::LoginV2 {
         auto_models: false,
         client_id: [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
         os_type: OsType::MACOS,
@@ -736,7 +1710,7 @@ async fn test_command_serialization_roundtrip() {
             network_rx: 0,
             network_tx: 0,
         },
-        version: 1,
+        version: PROTOCOL_VERSION,
         device_memtotal_gb: 256,
         device_total_tflops: 0,
         devices_info: vec![DevicesInfo {
@@ -757,6 +1731,14 @@ async fn test_command_serialization_roundtrip() {
             power_usage: 250,
             temp: 123,
         }],
+        benchmark: Some(BenchmarkResult {
+            gflops: 12.5,
+            memory_bandwidth_gbps: 40.0,
+            tokens_per_sec: 10.5,
+            duration_ms: 300,
+        }),
+        enrollment_token: Some("enroll-1".to_string()),
+        region: Some("eu-west".to_string()),
     });
 
     // Serialize and write the command
@@ -781,7 +1763,7 @@ async fn test_command_serialization_roundtrip() {
 
             match (cmd_v1, deser_v1) {
                 (
-                    CommandV1::Login {
+                    CommandV1::LoginV2 {
                         auto_models: _,
                         client_id: original_id,
                         os_type: _,
@@ -790,8 +1772,11 @@ async fn test_command_serialization_roundtrip() {
                         version: _,
                         device_memtotal_gb: _,
                         device_total_tflops: _,
+                        benchmark: original_benchmark,
+                        enrollment_token: original_token,
+                        region: original_region,
                     },
-                    CommandV1::Login {
+                    CommandV1::LoginV2 {
                         auto_models: _,
                         client_id: deserialized_id,
                         os_type: _,
@@ -800,9 +1785,21 @@ async fn test_command_serialization_roundtrip() {
                         version: _,
                         device_memtotal_gb: _,
                         device_total_tflops: _,
+                        benchmark: deserialized_benchmark,
+                        enrollment_token: deserialized_token,
+                        region: deserialized_region,
                     },
                 ) => {
                     assert_eq!(original_id, deserialized_id, "client_id mismatch");
+                    assert_eq!(
+                        original_benchmark, deserialized_benchmark,
+                        "benchmark mismatch"
+                    );
+                    assert_eq!(
+                        original_token, deserialized_token,
+                        "enrollment_token mismatch"
+                    );
+                    assert_eq!(original_region, deserialized_region, "region mismatch");
                     assert_eq!(
                         original_sys.cpu_usage, deserialized_sys.cpu_usage,
                         "cpu_usage mismatch"
@@ -838,3 +1835,129 @@ async fn test_command_serialization_roundtrip() {
         _ => panic!("Command version mismatch"),
     }
 }
+
+#[tokio::test]
+async fn test_model_benchmark_roundtrip() {
+    let cmd = Command::V1(CommandV1::ModelBenchmarkReport {
+        client_id: [7; 16],
+        benchmark: ModelBenchmark {
+            model: "tinyllama-1.1b-chat-v1.0.Q4_K_M.gguf".to_string(),
+            model_bytes: 668_788_096,
+            n_gpu_layers: 23,
+            runs: vec![ModelBenchmarkRun {
+                n_ctx: 2048,
+                n_batch: 512,
+                n_prompt: 512,
+                n_gen: 128,
+                prompt_tokens_per_sec: 4210.5,
+                gen_tokens_per_sec: 187.2,
+                vram_bytes: 1 << 30,
+                ram_bytes: 256 << 20,
+            }],
+        },
+    });
+    let mut written = Vec::new();
+    write_command(&mut written, &cmd).await.unwrap();
+    let mut read_buf = BytesMut::with_capacity(MAX_MESSAGE_SIZE);
+    let read = read_command(&mut std::io::Cursor::new(&written[..]), &mut read_buf)
+        .await
+        .unwrap();
+    match read {
+        Command::V1(CommandV1::ModelBenchmarkReport {
+            client_id,
+            benchmark,
+        }) => {
+            assert_eq!(client_id, [7; 16]);
+            assert_eq!(benchmark.runs.len(), 1);
+            assert_eq!(benchmark.runs[0].gen_tokens_per_sec, 187.2);
+        }
+        other => panic!("unexpected command {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_protocol_1_compatibility() {
+    let config = bincode_config::standard()
+        .with_fixed_int_encoding()
+        .with_little_endian();
+    let login = Command::V1(CommandV1::Login {
+        client_id: [3; 16],
+        version: 1,
+        os_type: OsType::LINUX,
+        auto_models: false,
+        system_info: SystemInfo {
+            cpu_usage: 0,
+            memory_usage: 0,
+            disk_usage: 0,
+            network_rx: 0,
+            network_tx: 0,
+        },
+        device_memtotal_gb: 24,
+        device_total_tflops: 80,
+        devices_info: vec![],
+    });
+    // Tags of the original variants are what version 1 workers send
+    let encoded = bincode::encode_to_vec(&login, config).unwrap();
+    assert_eq!(encoded[..8], [0, 0, 0, 0, 2, 0, 0, 0]);
+
+    let mut written = Vec::new();
+    write_command(&mut written, &login).await.unwrap();
+    let mut read_buf = BytesMut::with_capacity(MAX_MESSAGE_SIZE);
+    let read = read_command(&mut std::io::Cursor::new(&written[..]), &mut read_buf)
+        .await
+        .unwrap();
+    match read {
+        Command::V1(CommandV1::LoginV2 {
+            client_id,
+            version,
+            enrollment_token,
+            ..
+        }) => {
+            assert_eq!(client_id, [3; 16]);
+            assert_eq!(version, 1);
+            assert_eq!(enrollment_token, None);
+        }
+        other => panic!("unexpected command {:?}", other),
+    }
+
+    let task = Command::V1(CommandV1::InferenceTaskV2 {
+        task_id: "t1".to_string(),
+        request_id: Some("r1".to_string()),
+        traceparent: None,
+        prompt: "hi".to_string(),
+        max_tokens: 16,
+        temperature: 0.7,
+        top_k: 40,
+        top_p: 0.9,
+        repeat_penalty: 1.1,
+        repeat_last_n: 64,
+        min_keep: 1,
+        overflow: ContextOverflow::TruncateLeft,
+        deadline_ms: Some(1000),
+    });
+    let mut written = Vec::new();
+    assert!(write_command_for(&mut written, &task, 1).await.unwrap());
+    let (legacy, _): (Command, _) = bincode::decode_from_slice(&written[4..], config).unwrap();
+    match legacy {
+        Command::V1(CommandV1::InferenceTask {
+            task_id,
+            max_tokens,
+            ..
+        }) => {
+            assert_eq!(task_id, "t1");
+            assert_eq!(max_tokens, 16);
+        }
+        other => panic!("unexpected command {:?}", other),
+    }
+
+    let mut written = Vec::new();
+    let restarting = Command::V1(CommandV1::ServerRestarting {
+        reconnect_to: None,
+        delay_ms: 0,
+    });
+    let sent = write_command_for(&mut written, &restarting, 1)
+        .await
+        .unwrap();
+    assert!(!sent);
+    assert!(written.is_empty());
+}
@@ -0,0 +1,96 @@
+//! Credentials kept out of command lines, so they do not show up in process
+//! listings: read from files such as Docker and Kubernetes secret mounts, or
+//! from another environment variable.
+
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+
+/// Prefix of a value naming the environment variable that holds it
+const ENV_PREFIX: &str = "env:";
+
+/// Content of a secret file, without the trailing newline editors and
+/// `kubectl create secret --from-file` leave behind
+pub fn read_secret(path: impl AsRef<Path>) -> Result<String> {
+    let path = path.as_ref();
+    let secret = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read secret file {}", path.display()))?;
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// `<dir>/<name>` when that file exists, e.g. `/run/secrets/database_url`
+pub fn secret_from_dir(dir: impl AsRef<Path>, name: &str) -> Result<Option<String>> {
+    let path = dir.as_ref().join(name);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    read_secret(path).map(Some)
+}
+
+/// `env:NAME` is replaced by the variable NAME, other values are kept
+pub fn resolve_secret(value: &str) -> Result<String> {
+    match value.strip_prefix(ENV_PREFIX) {
+        Some(name) => {
+            std::env::var(name).map_err(|_| anyhow!("Environment variable {} is not set", name))
+        }
+        None => Ok(value.to_string()),
+    }
+}
+
+/// A secret from the first source that has it: `file`, then `<dir>/<name>`
+/// when `dir` is set and `value` is only a default, then `value` with
+/// `env:` indirection resolved
+pub fn load_secret(
+    name: &str,
+    value: Option<&str>,
+    defaulted: bool,
+    file: Option<&str>,
+    dir: Option<&str>,
+) -> Result<Option<String>> {
+    if let Some(file) = file {
+        return read_secret(file).map(Some);
+    }
+    if let (Some(dir), true) = (dir, defaulted) {
+        if let Some(secret) = secret_from_dir(dir, name)? {
+            return Ok(Some(secret));
+        }
+    }
+    value.map(resolve_secret).transpose()
+}
+
+#[test]
+fn test_load_secret() {
+    let dir = std::env::temp_dir().join(format!("gpuf-secrets-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("api_key"), "from-dir\n").unwrap();
+    std::fs::write(dir.join("token"), "from-file\r\n").unwrap();
+    let dir_str = dir.to_str();
+    let file = dir.join("token");
+    let file_str = file.to_str();
+
+    // A file given for the secret wins
+    assert_eq!(
+        load_secret("api_key", Some("flag"), false, file_str, dir_str).unwrap(),
+        Some("from-file".to_string())
+    );
+    // The directory only replaces defaults
+    assert_eq!(
+        load_secret("api_key", Some("default"), true, None, dir_str).unwrap(),
+        Some("from-dir".to_string())
+    );
+    assert_eq!(
+        load_secret("api_key", Some("flag"), false, None, dir_str).unwrap(),
+        Some("flag".to_string())
+    );
+    assert_eq!(
+        load_secret("admin_key", None, true, None, dir_str).unwrap(),
+        None
+    );
+    assert!(load_secret("api_key", None, true, dir.join("missing").to_str(), None).is_err());
+
+    std::env::set_var("GPUF_TEST_SECRET", "from-env");
+    assert_eq!(resolve_secret("env:GPUF_TEST_SECRET").unwrap(), "from-env");
+    assert_eq!(resolve_secret("postgres://db").unwrap(), "postgres://db");
+    assert!(resolve_secret("env:GPUF_TEST_SECRET_UNSET").is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,211 @@
+//! W3C trace context (`traceparent`) carried from the public gpuf-s endpoint
+//! to the worker, plus OTLP span export behind the `otel` feature.
+
+use std::fmt;
+use uuid::Uuid;
+
+/// Parsed `traceparent` value, version 00 only
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl TraceParent {
+    /// Start a new trace for a request that did not bring one
+    pub fn new_root() -> Self {
+        Self {
+            trace_id: *Uuid::new_v4().as_bytes(),
+            span_id: new_span_id(),
+            sampled: true,
+        }
+    }
+
+    /// Parse a `traceparent` header, `None` if malformed or all-zero
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split('-');
+        let (version, trace_id, span_id, flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        if version != "00" || parts.next().is_some() {
+            return None;
+        }
+
+        let mut parent = Self {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            sampled: false,
+        };
+        hex::decode_to_slice(trace_id, &mut parent.trace_id).ok()?;
+        hex::decode_to_slice(span_id, &mut parent.span_id).ok()?;
+        let mut flags_byte = [0u8; 1];
+        hex::decode_to_slice(flags, &mut flags_byte).ok()?;
+        parent.sampled = flags_byte[0] & 0x01 != 0;
+
+        if parent.trace_id == [0; 16] || parent.span_id == [0; 8] {
+            return None;
+        }
+        Some(parent)
+    }
+
+    /// Parse the header if present and valid, otherwise start a new trace
+    pub fn from_header_or_root(value: Option<&str>) -> Self {
+        value.and_then(Self::parse).unwrap_or_else(Self::new_root)
+    }
+
+    /// Same trace with a new span id, for the next hop
+    pub fn child(&self) -> Self {
+        Self {
+            span_id: new_span_id(),
+            ..*self
+        }
+    }
+
+    pub fn trace_id_hex(&self) -> String {
+        hex::encode(self.trace_id)
+    }
+
+    pub fn span_id_hex(&self) -> String {
+        hex::encode(self.span_id)
+    }
+}
+
+impl fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{:02x}",
+            self.trace_id_hex(),
+            self.span_id_hex(),
+            self.sampled as u8
+        )
+    }
+}
+
+/// Request ID for calls that did not send a `request-id` header, 32 hex chars
+pub fn new_request_id() -> String {
+    hex::encode(Uuid::new_v4().as_bytes())
+}
+
+fn new_span_id() -> [u8; 8] {
+    let mut span_id = [0u8; 8];
+    span_id.copy_from_slice(&Uuid::new_v4().as_bytes()[..8]);
+    span_id
+}
+
+/// Attach `span` to the remote parent so exported spans join the caller's trace
+pub fn set_span_parent(span: &tracing::Span, parent: &TraceParent) {
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::global;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let carrier =
+            std::collections::HashMap::from([("traceparent".to_string(), parent.to_string())]);
+        let cx = global::get_text_map_propagator(|p| p.extract(&carrier));
+        span.set_parent(cx);
+    }
+    #[cfg(not(feature = "otel"))]
+    let _ = (span, parent);
+}
+
+/// `traceparent` to send downstream from within `span`. With OTLP export this
+/// is the exported span itself, otherwise a child of `parent`.
+pub fn outgoing_traceparent(span: &tracing::Span, parent: &TraceParent) -> TraceParent {
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::trace::TraceContextExt;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let cx = span.context();
+        let span_ref = cx.span();
+        let span_context = span_ref.span_context();
+        if span_context.is_valid() {
+            return TraceParent {
+                trace_id: span_context.trace_id().to_bytes(),
+                span_id: span_context.span_id().to_bytes(),
+                sampled: span_context.is_sampled(),
+            };
+        }
+    }
+    #[cfg(not(feature = "otel"))]
+    let _ = span;
+    parent.child()
+}
+
+/// OTLP export layer, enabled when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+/// Must be called from within a Tokio runtime.
+#[cfg(feature = "otel")]
+pub fn otlp_layer<S>(
+    service_name: &'static str,
+) -> Option<tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry::{global, KeyValue};
+    use opentelemetry_sdk::{
+        propagation::TraceContextPropagator, runtime, trace::TracerProvider, Resource,
+    };
+
+    std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT")?;
+    let exporter = match opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .build()
+    {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            // Logging is not up yet
+            eprintln!("Failed to create OTLP exporter: {}", e);
+            return None;
+        }
+    };
+
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            service_name,
+        )]))
+        .build();
+    let tracer = provider.tracer(service_name);
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(provider);
+
+    Some(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Flush spans that are still buffered, call before exit
+pub fn shutdown_tracing() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[test]
+fn test_traceparent_roundtrip() {
+    let raw = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    let parent = TraceParent::parse(raw).unwrap();
+    assert!(parent.sampled);
+    assert_eq!(parent.trace_id_hex(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(parent.to_string(), raw);
+
+    let child = parent.child();
+    assert_eq!(child.trace_id, parent.trace_id);
+    assert_ne!(child.span_id, parent.span_id);
+}
+
+#[test]
+fn test_traceparent_rejects_invalid() {
+    assert!(TraceParent::parse("").is_none());
+    assert!(
+        TraceParent::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").is_none()
+    );
+    assert!(
+        TraceParent::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none()
+    );
+    assert!(TraceParent::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7").is_none());
+    assert!(TraceParent::parse("00-xyz-00f067aa0ba902b7-01").is_none());
+
+    let root = TraceParent::from_header_or_root(Some("garbage"));
+    assert_eq!(TraceParent::parse(&root.to_string()), Some(root));
+}
@@ -15,6 +15,9 @@ fn main() {
         // println!("cargo:warning=CUDA feature enabled - adding -fPIC flag"); // Commented out to reduce warning noise
     }
 
+    // Reported in heartbeats as the llama.cpp version
+    emit_llama_cpp_version();
+
     // Bundle CUDA runtime DLLs on Windows so gpuf-c.exe can run without requiring users to edit PATH.
     // This is best-effort: if DLLs are not found, we only emit warnings.
     if target_os == "windows" && cfg!(feature = "cuda") {
@@ -185,15 +188,17 @@ fn main() {
     if target_os == "android" || target_os == "ios" {
         let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
 
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        println!("cargo:rerun-if-changed=src");
+
+        let config = cbindgen::Config::from_file(PathBuf::from(&crate_dir).join("cbindgen.toml"))
+            .expect("Unable to read cbindgen.toml");
         cbindgen::Builder::new()
-            .with_crate(crate_dir)
-            .with_language(cbindgen::Language::C)
-            .with_pragma_once(true)
-            .with_include_guard("GPUF_C_H")
-            .with_documentation(true)
+            .with_crate(&crate_dir)
+            .with_config(config)
             .generate()
             .expect("Unable to generate bindings")
-            .write_to_file("gpuf_c.h");
+            .write_to_file(PathBuf::from(&crate_dir).join("gpuf_c.h"));
     }
 
     // For Android, link the static llama.cpp library
@@ -651,3 +656,25 @@ fn bundle_nvml_dll_windows() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// Set `GPUF_LLAMA_CPP_VERSION` to the llama-cpp-sys-2 version in the
+/// workspace's Cargo.lock, which pins the llama.cpp revision it builds
+fn emit_llama_cpp_version() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let lock_file = PathBuf::from(&manifest_dir)
+        .parent()
+        .map(|p| p.join("Cargo.lock"))
+        .unwrap_or_else(|| PathBuf::from(&manifest_dir).join("Cargo.lock"));
+    println!("cargo:rerun-if-changed={}", lock_file.display());
+
+    let version = fs::read_to_string(&lock_file)
+        .ok()
+        .and_then(|lock| {
+            let mut lines = lock.lines();
+            lines.find(|line| *line == "name = \"llama-cpp-sys-2\"")?;
+            let version = lines.next()?.strip_prefix("version = \"")?;
+            Some(version.trim_end_matches('"').to_string())
+        })
+        .unwrap_or_default();
+    println!("cargo:rustc-env=GPUF_LLAMA_CPP_VERSION={}", version);
+}
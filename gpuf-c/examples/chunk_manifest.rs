@@ -0,0 +1,37 @@
+//! Chunk manifest writer
+//!
+//! Hash a model file into the chunk manifest workers use to update an older
+//! version by delta. Publish the manifest and set it as the model's
+//! `chunk_manifest_url` in the catalog.
+//!
+//! Usage: cargo run --example chunk_manifest -- <model.gguf> [chunk_size_mb]
+
+use anyhow::{anyhow, Result};
+use gpuf_c::util::model_delta::{ChunkManifest, DEFAULT_CHUNK_SIZE};
+use std::path::PathBuf;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let model = PathBuf::from(
+        args.next()
+            .ok_or_else(|| anyhow!("Usage: chunk_manifest <model.gguf> [chunk_size_mb]"))?,
+    );
+    let chunk_size = match args.next() {
+        Some(mb) => mb.parse::<u64>()? * 1024 * 1024,
+        None => DEFAULT_CHUNK_SIZE,
+    };
+
+    let manifest = ChunkManifest::build(&model, chunk_size).await?;
+    let mut output = model.clone().into_os_string();
+    output.push(".chunks.json");
+    tokio::fs::write(&output, serde_json::to_string(&manifest)?).await?;
+
+    println!(
+        "Wrote {} chunks of {} bytes to {}",
+        manifest.chunks.len(),
+        chunk_size,
+        PathBuf::from(output).display()
+    );
+    Ok(())
+}
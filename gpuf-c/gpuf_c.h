@@ -17,6 +17,69 @@ typedef enum ProjectorType {
   Pixtral = 5,
 } ProjectorType;
 
+/**
+ * Result of a C API call. Calls that return a count or length use the
+ * negative codes for failures.
+ */
+typedef enum GpufErrorCode {
+  GpufErrorCode_Ok = 0,
+  GpufErrorCode_InvalidArgument = -1,
+  GpufErrorCode_NotInitialized = -2,
+  GpufErrorCode_Network = -3,
+  GpufErrorCode_ModelLoad = -4,
+  GpufErrorCode_OutOfMemory = -5,
+  GpufErrorCode_Timeout = -6,
+  GpufErrorCode_Cancelled = -7,
+  /**
+   * Another generation or worker holds the resource
+   */
+  GpufErrorCode_Busy = -8,
+  GpufErrorCode_Internal = -9,
+} GpufErrorCode;
+
+/**
+ * State of a `gpuf_download_t`
+ */
+typedef enum GpufDownloadState {
+  GpufDownloadState_Running,
+  /**
+   * Paused with `gpuf_download_pause`
+   */
+  GpufDownloadState_Paused,
+  GpufDownloadState_Completed,
+  GpufDownloadState_Failed,
+  GpufDownloadState_Cancelled,
+} GpufDownloadState;
+
+/**
+ * Details of the last failed call on the calling thread
+ */
+typedef struct GpufErrorInfo {
+  enum GpufErrorCode code;
+  /**
+   * Owned by the library, valid until the next failing call on this thread
+   */
+  const char *message;
+  bool retryable;
+} GpufErrorInfo;
+
+/**
+ * Progress of a `gpuf_download_t`
+ */
+typedef struct GpufDownloadProgress {
+  enum GpufDownloadState state;
+  uint64_t downloaded_bytes;
+  /**
+   * 0 while unknown
+   */
+  uint64_t total_bytes;
+  uint64_t speed_bps;
+  /**
+   * -1 while unknown
+   */
+  int64_t eta_seconds;
+} GpufDownloadProgress;
+
 typedef struct llama_model {
   uint8_t _private[0];
 } llama_model;
@@ -509,10 +572,42 @@ const char *gpuf_system_info(void);
 
 const char *gpuf_version(void);
 
+int gpuf_client_get_metrics(char *output, int max_length);
+
 int gpuf_init(void);
 
 int gpuf_cleanup(void);
 
+/**
+ * Stop the remote worker and tear down the global Tokio runtime (C API)
+ *
+ * Call once when the app is done with the library, e.g. before unloading
+ * it. Functions called afterwards start a new runtime.
+ *
+ * # Returns
+ * - `0`: Success
+ */
+int gpuf_shutdown(void);
+
+/**
+ * Message of the last failed call on this thread, or NULL (C API)
+ *
+ * The string is owned by the library and valid until the next failing call
+ * on this thread.
+ */
+const char *gpuf_get_last_error(void);
+
+/**
+ * Code, message and retryability of the last failed call on this thread
+ * (C API)
+ *
+ * # Returns
+ * - `GpufErrorCode::Ok`: `info` was filled; its code is `Ok` when nothing
+ *   failed yet
+ * - `GpufErrorCode::InvalidArgument`: `info` is null
+ */
+enum GpufErrorCode gpuf_get_last_error_info(struct GpufErrorInfo *info);
+
 /**
  * Stop ongoing generation
  */
@@ -560,10 +655,10 @@ int start_remote_worker(const char *server_addr,
  *
  * # Returns
  * - `0`: Success (model loaded and context created)
- * - `-1`: Backend initialization failed
- * - `-2`: Path conversion failed
- * - `-3`: Model loading failed
- * - `-4`: Context creation failed
+ * - `GpufErrorCode::NotInitialized`: Backend initialization failed
+ * - `GpufErrorCode::InvalidArgument`: Path conversion failed
+ * - `GpufErrorCode::ModelLoad`: Model loading failed
+ * - `GpufErrorCode::OutOfMemory`: Context creation failed
  *
  * # Safety
  * Caller must ensure `model_path` is a valid null-terminated C string
@@ -599,13 +694,444 @@ int stop_remote_worker(void);
  *
  * # Returns
  * - `0`: Success (status written to buffer)
- * - `-1`: Error (buffer too small or other error)
+ * - `GpufErrorCode::InvalidArgument`: Buffer is null or too small
  *
  * # Safety
  * Caller must ensure `buffer` is valid and can hold `buffer_size` bytes
  */
 int get_remote_worker_status(char *buffer, size_t buffer_size);
 
+/**
+ * Report battery and thermal state of the device (C API)
+ *
+ * Android apps read these from `BatteryManager` and
+ * `PowerManager.getCurrentThermalStatus()`. Once reported, the worker stops
+ * reading sysfs, so keep calling this on changes.
+ *
+ * # Returns
+ * - `0`: Success
+ * - `GpufErrorCode::InvalidArgument`: A value is out of range
+ */
+int set_remote_worker_power_status(int battery_pct,
+                                   bool charging,
+                                   int temperature_c,
+                                   int thermal_status);
+
+/**
+ * Set when the worker pauses inference (C API)
+ *
+ * Inference is paused below `min_battery_pct` unless charging, at
+ * `max_temperature_c` or above, and at `max_thermal_status` or above.
+ * Defaults are 20%, 45°C and THERMAL_STATUS_SEVERE (3).
+ *
+ * # Returns
+ * - `0`: Success
+ * - `GpufErrorCode::InvalidArgument`: A value is out of range
+ */
+int set_remote_worker_power_policy(int min_battery_pct,
+                                   int max_temperature_c,
+                                   int max_thermal_status);
+
+/**
+ * Limit background model downloads (C API)
+ *
+ * `max_bytes_per_sec` of 0 removes the bandwidth cap. Downloads run only
+ * from `window_start_hour` up to `window_end_hour` local time, wrapping past
+ * midnight, or at any hour when both are -1. With `unmetered_only`, they
+ * wait while `set_remote_worker_network_metered` reports a metered network.
+ * Downloads already running keep their bandwidth cap.
+ *
+ * # Returns
+ * - `0`: Success
+ * - `GpufErrorCode::InvalidArgument`: The window is out of range
+ */
+int set_remote_worker_download_policy(uint64_t max_bytes_per_sec,
+                                      int window_start_hour,
+                                      int window_end_hour,
+                                      bool unmetered_only);
+
+/**
+ * Report whether the active network is metered (C API)
+ *
+ * Android apps read it from `ConnectivityManager.isActiveNetworkMetered()`
+ * and call this on network changes. Downloads held back by it resume within
+ * a minute of the network becoming unmetered.
+ *
+ * # Returns
+ * - `0`: Success
+ */
+int set_remote_worker_network_metered(bool metered);
+
+/**
+ * App moved to the background (C API)
+ *
+ * Pauses the remote worker: the server stops sending tasks, new tasks are
+ * rejected and heartbeats slow down to every 10 minutes. With
+ * `release_model`, waits for the running generation to finish and frees the
+ * model and context; `remote_worker_on_foregrounded` loads them again.
+ *
+ * # Returns
+ * - `0`: Success
+ */
+int remote_worker_on_backgrounded(bool release_model);
+
+/**
+ * App returned to the foreground (C API)
+ *
+ * Reloads a model released by `remote_worker_on_backgrounded` and resumes
+ * the remote worker.
+ *
+ * # Returns
+ * - `0`: Success
+ * - Negative: Reloading the model failed, see `set_remote_worker_model`
+ */
+int remote_worker_on_foregrounded(void);
+
+/**
+ * Set the idle unload timeout (C API)
+ *
+ * Frees the model and context after `secs` seconds without a task and
+ * reloads them when the next task arrives. 0 keeps the model loaded.
+ *
+ * # Returns
+ * - `0`: Success
+ */
+int set_remote_worker_idle_unload(uint32_t secs);
+
+/**
+ * Set the request limits (C API)
+ *
+ * Tasks with a prompt, or sum of chat message contents, above
+ * `max_prompt_bytes`, or a `max_tokens` above `max_tokens`, fail right away
+ * with a `request_too_large` or `limit_exceeded` error instead of reaching
+ * the model. 0 turns a limit off. Prompts are limited to 1 MiB by default.
+ *
+ * # Returns
+ * - `0`: Success
+ */
+int set_remote_worker_request_limits(uint64_t max_prompt_bytes, uint32_t max_tokens);
+
+/**
+ * Open the model cache at `models_dir` (C API)
+ *
+ * Keeps the GGUF models in `models_dir` under `quota_bytes`, deleting the
+ * least recently used ones first, except pinned models and the loaded one.
+ * A quota of 0 is unlimited. Calling it again changes the quota.
+ *
+ * # Returns
+ * - Number of models deleted to fit the quota
+ * - `GpufErrorCode::InvalidArgument`: The directory is invalid, or another
+ *   one is already open
+ */
+int gpuf_model_cache_init(const char *models_dir, uint64_t quota_bytes);
+
+/**
+ * List cached models as JSON (C API)
+ *
+ * Writes an array, most recently used first, of
+ * `{"name", "size_bytes", "last_used", "pinned", "loaded"}` objects.
+ * `last_used` is in Unix seconds.
+ *
+ * # Returns
+ * - Length of the JSON written, without the NUL
+ * - `GpufErrorCode::NotInitialized`: `gpuf_model_cache_init` was not called
+ * - `GpufErrorCode::InvalidArgument`: Buffer is null or too small
+ *
+ * # Safety
+ * Caller must ensure `buffer` is valid and can hold `buffer_size` bytes
+ */
+int gpuf_model_cache_list(char *buffer, size_t buffer_size);
+
+/**
+ * Pin or unpin a cached model (C API)
+ *
+ * Pinned models are never deleted to fit the quota.
+ *
+ * # Returns
+ * - `0`: Success
+ * - `GpufErrorCode::NotInitialized`: `gpuf_model_cache_init` was not called
+ * - `GpufErrorCode::InvalidArgument`: The model is not in the cache
+ */
+int gpuf_model_cache_set_pinned(const char *name, bool pinned);
+
+/**
+ * A loaded model and its context (opaque to C)
+ */
+typedef struct gpuf_llm_t gpuf_llm_t;
+
+/**
+ * Remote worker configuration (opaque to C)
+ *
+ * Not a worker of its own: a started client drives the process-wide worker,
+ * so an app may keep several configurations but connect one at a time.
+ */
+typedef struct gpuf_client_t gpuf_client_t;
+
+/**
+ * A model download running in the background (opaque to C)
+ */
+typedef struct gpuf_download_t gpuf_download_t;
+
+/**
+ * Receives each piece of generated text, or each worker event
+ */
+typedef void (*gpuf_token_callback)(const char *token, void *user_data);
+
+/**
+ * Called once when an `_async` call finishes. `result` is the generated text
+ * on success and the error message otherwise; it is only valid during the
+ * call.
+ */
+typedef void (*gpuf_completion_callback)(enum GpufErrorCode code,
+                                         const char *result,
+                                         void *user_data);
+
+/**
+ * Load a model into a new instance (C API)
+ *
+ * # Returns
+ * - The instance, to be released with `gpuf_llm_free`
+ * - NULL if the backend, the model or the context failed to initialize; see
+ *   `gpuf_get_last_error_info`
+ *
+ * # Safety
+ * Caller must ensure `model_path` is a valid null-terminated C string
+ */
+gpuf_llm_t *gpuf_llm_create(const char *model_path);
+
+/**
+ * Generate text into `output` (C API)
+ *
+ * Waits for generations running on other instances.
+ *
+ * # Returns
+ * - Length of the generated text written to `output`
+ * - Negative `GpufErrorCode`: Invalid arguments or generation failed
+ */
+int gpuf_llm_generate(gpuf_llm_t *llm,
+                      const char *prompt,
+                      int max_tokens,
+                      float temperature,
+                      int top_k,
+                      float top_p,
+                      float repeat_penalty,
+                      char *output,
+                      int output_len);
+
+/**
+ * Generate text and pass each piece to `on_token` as it is produced (C API)
+ *
+ * Blocks until generation ends or `gpuf_llm_stop` is called, and waits for
+ * generations running on other instances.
+ *
+ * # Returns
+ * - Number of generated tokens
+ * - Negative `GpufErrorCode`: Invalid arguments or generation failed
+ */
+int gpuf_llm_generate_stream(gpuf_llm_t *llm,
+                             const char *prompt,
+                             int max_tokens,
+                             float temperature,
+                             int top_k,
+                             float top_p,
+                             float repeat_penalty,
+                             gpuf_token_callback on_token,
+                             void *user_data);
+
+/**
+ * Stop a running `gpuf_llm_generate_stream` on this instance (C API)
+ *
+ * # Returns
+ * - `GpufErrorCode::Ok`: Stop requested
+ * - `GpufErrorCode::InvalidArgument`: The instance is not generating
+ */
+enum GpufErrorCode gpuf_llm_stop(gpuf_llm_t *llm);
+
+/**
+ * Non-blocking `gpuf_llm_generate_stream` (C API)
+ *
+ * Returns once generation is queued. `on_token` (may be NULL) gets each
+ * piece and `on_complete` the whole text, both on a library thread. `llm`
+ * and `user_data` must stay valid until `on_complete` runs.
+ *
+ * # Returns
+ * - `GpufErrorCode::Ok`: Generation started; `on_complete` will be called
+ * - Other codes: Nothing started, `on_complete` is not called
+ */
+enum GpufErrorCode gpuf_llm_generate_async(gpuf_llm_t *llm,
+                                           const char *prompt,
+                                           int max_tokens,
+                                           float temperature,
+                                           int top_k,
+                                           float top_p,
+                                           float repeat_penalty,
+                                           gpuf_token_callback on_token,
+                                           gpuf_completion_callback on_complete,
+                                           void *user_data);
+
+/**
+ * Free an instance and its model (C API)
+ *
+ * Waits for the running generation to finish. `llm` must not be used
+ * afterwards.
+ */
+void gpuf_llm_free(gpuf_llm_t *llm);
+
+/**
+ * Create a remote worker configuration (C API)
+ *
+ * Nothing is connected until `gpuf_client_start`. Arguments are the same as
+ * for `start_remote_worker`.
+ *
+ * # Returns
+ * - The client, to be released with `gpuf_client_free`
+ * - NULL if a string is null or a port is out of range
+ *   (`GpufErrorCode::InvalidArgument`)
+ */
+gpuf_client_t *gpuf_client_create(const char *server_addr,
+                                  int control_port,
+                                  int proxy_port,
+                                  const char *worker_type,
+                                  const char *client_id);
+
+/**
+ * Connect the client, log in and start serving tasks (C API)
+ *
+ * `callback` receives worker events as with
+ * `start_remote_worker_tasks_with_callback_ptr` and may be NULL. Stop the
+ * connected client before starting another one.
+ *
+ * # Returns
+ * - `GpufErrorCode::Ok`: Success
+ * - `GpufErrorCode::Busy`: Another client, or the legacy worker, is already
+ *   connected
+ * - Other codes: Login or starting the tasks failed
+ */
+enum GpufErrorCode gpuf_client_start(gpuf_client_t *client, gpuf_token_callback callback);
+
+/**
+ * Non-blocking `gpuf_client_start` (C API)
+ *
+ * Returns right away and logs in on a library thread, then calls
+ * `on_complete` with the result of `gpuf_client_start`. `client` and
+ * `user_data` must stay valid until then.
+ *
+ * # Returns
+ * - `GpufErrorCode::Ok`: Connecting; `on_complete` will be called
+ * - Other codes: Nothing started, `on_complete` is not called
+ */
+enum GpufErrorCode gpuf_client_connect_async(gpuf_client_t *client,
+                                             gpuf_token_callback callback,
+                                             gpuf_completion_callback on_complete,
+                                             void *user_data);
+
+/**
+ * Load the model the connected client serves, hot swapping the current one
+ * (C API)
+ *
+ * # Returns
+ * - `GpufErrorCode::Ok`: Success
+ * - `GpufErrorCode::NotInitialized`: The client is not connected
+ * - Other codes: see `set_remote_worker_model`
+ */
+enum GpufErrorCode gpuf_client_set_model(gpuf_client_t *client, const char *model_path);
+
+/**
+ * Status of the client's worker, written like `get_remote_worker_status`
+ * (C API)
+ *
+ * # Returns
+ * - `GpufErrorCode::Ok`: Success
+ * - `GpufErrorCode::NotInitialized`: The client is not connected
+ * - `GpufErrorCode::InvalidArgument`: `buffer` is null or too small
+ */
+enum GpufErrorCode gpuf_client_get_status(gpuf_client_t *client, char *buffer, size_t buffer_size);
+
+/**
+ * Disconnect the client (C API)
+ *
+ * # Returns
+ * - `GpufErrorCode::Ok`: Success
+ * - `GpufErrorCode::NotInitialized`: The client is not connected
+ */
+enum GpufErrorCode gpuf_client_stop(gpuf_client_t *client);
+
+/**
+ * Disconnect the client if needed and free it (C API)
+ *
+ * `client` must not be used afterwards.
+ */
+void gpuf_client_free(gpuf_client_t *client);
+
+/**
+ * Download `url` to `output_path` in the background (C API)
+ *
+ * Continues a partial file left at `output_path` and follows the policy set
+ * with `set_remote_worker_download_policy`. `on_complete` (may be NULL)
+ * gets the output path, or the error message, on a library thread;
+ * `user_data` must stay valid until then.
+ *
+ * # Returns
+ * - The download, to be released with `gpuf_download_free`
+ * - NULL if a string is null (`GpufErrorCode::InvalidArgument`) or the
+ *   download thread failed to start
+ */
+gpuf_download_t *gpuf_download_start(const char *url,
+                                     const char *output_path,
+                                     gpuf_completion_callback on_complete,
+                                     void *user_data);
+
+/**
+ * Stop transferring, keeping what was downloaded (C API)
+ *
+ * Takes effect within about a second.
+ *
+ * # Returns
+ * - `GpufErrorCode::Ok`: Success
+ * - `GpufErrorCode::InvalidArgument`: `download` is null
+ */
+enum GpufErrorCode gpuf_download_pause(gpuf_download_t *download);
+
+/**
+ * Continue a paused download (C API)
+ *
+ * # Returns
+ * - `GpufErrorCode::Ok`: Success
+ * - `GpufErrorCode::InvalidArgument`: `download` is null
+ */
+enum GpufErrorCode gpuf_download_resume(gpuf_download_t *download);
+
+/**
+ * Stop the download and delete the partial file (C API)
+ *
+ * `on_complete` of `gpuf_download_start` then reports
+ * `GpufErrorCode::Cancelled`. A finished download is left alone.
+ *
+ * # Returns
+ * - `GpufErrorCode::Ok`: Success
+ * - `GpufErrorCode::InvalidArgument`: `download` is null
+ */
+enum GpufErrorCode gpuf_download_cancel(gpuf_download_t *download);
+
+/**
+ * State and progress of a download (C API)
+ *
+ * Progress is updated about once a second while data arrives.
+ *
+ * # Returns
+ * - `GpufErrorCode::Ok`: `progress` was filled
+ * - `GpufErrorCode::InvalidArgument`: `download` or `progress` is null
+ */
+enum GpufErrorCode gpuf_download_get_progress(gpuf_download_t *download,
+                                              struct GpufDownloadProgress *progress);
+
+/**
+ * Free a download, cancelling it if it is still running (C API)
+ *
+ * `download` must not be used afterwards.
+ */
+void gpuf_download_free(gpuf_download_t *download);
+
 extern const struct llama_model *llama_get_model(const struct llama_context *ctx);
 
 extern const struct llama_vocab *llama_model_get_vocab(const struct llama_model *model);
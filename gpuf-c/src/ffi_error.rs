@@ -0,0 +1,131 @@
+//! Error codes of the C API. Failing calls record the code and a message for
+//! the calling thread, read back with `gpuf_get_last_error_info`.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CString};
+
+/// Result of a C API call. Calls that return a count or length use the
+/// negative codes for failures.
+///
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpufErrorCode {
+    Ok = 0,
+    InvalidArgument = -1,
+    NotInitialized = -2,
+    Network = -3,
+    ModelLoad = -4,
+    OutOfMemory = -5,
+    Timeout = -6,
+    Cancelled = -7,
+    /// Another generation or worker holds the resource
+    Busy = -8,
+    Internal = -9,
+}
+
+impl GpufErrorCode {
+    /// Whether the same call may succeed when retried unchanged
+    pub fn is_retryable(self) -> bool {
+        matches!(self, Self::Network | Self::Timeout | Self::Busy)
+    }
+}
+
+impl From<GpufErrorCode> for c_int {
+    fn from(code: GpufErrorCode) -> Self {
+        code as c_int
+    }
+}
+
+/// Details of the last failed call on the calling thread
+#[repr(C)]
+pub struct GpufErrorInfo {
+    pub code: GpufErrorCode,
+    /// Owned by the library, valid until the next failing call on this thread
+    pub message: *const c_char,
+    pub retryable: bool,
+}
+
+struct LastError {
+    code: GpufErrorCode,
+    message: CString,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<LastError>> = const { RefCell::new(None) };
+}
+
+/// Record a failure for the calling thread and return its code
+pub fn set_last_error(code: GpufErrorCode, message: impl Into<String>) -> c_int {
+    let message = message.into();
+    eprintln!("❌ C API: {}", message);
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(LastError { code, message }));
+    code.into()
+}
+
+pub fn last_error_code() -> GpufErrorCode {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(GpufErrorCode::Ok, |e| e.code))
+}
+
+/// Message of the last failed call on this thread, or NULL (C API)
+///
+/// The string is owned by the library and valid until the next failing call
+/// on this thread.
+#[no_mangle]
+pub extern "C" fn gpuf_get_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |e| e.message.as_ptr())
+    })
+}
+
+/// Code, message and retryability of the last failed call on this thread
+/// (C API)
+///
+/// # Returns
+/// - `GpufErrorCode::Ok`: `info` was filled; its code is `Ok` when nothing
+///   failed yet
+/// - `GpufErrorCode::InvalidArgument`: `info` is null
+#[no_mangle]
+pub extern "C" fn gpuf_get_last_error_info(info: *mut GpufErrorInfo) -> GpufErrorCode {
+    if info.is_null() {
+        return GpufErrorCode::InvalidArgument;
+    }
+    let code = last_error_code();
+    unsafe {
+        *info = GpufErrorInfo {
+            code,
+            message: gpuf_get_last_error(),
+            retryable: code.is_retryable(),
+        };
+    }
+    GpufErrorCode::Ok
+}
+
+#[test]
+fn test_last_error() {
+    assert_eq!(last_error_code(), GpufErrorCode::Ok);
+    assert!(gpuf_get_last_error().is_null());
+
+    assert_eq!(
+        set_last_error(GpufErrorCode::Network, "connection refused"),
+        -3
+    );
+    let mut info = GpufErrorInfo {
+        code: GpufErrorCode::Ok,
+        message: std::ptr::null(),
+        retryable: false,
+    };
+    assert_eq!(gpuf_get_last_error_info(&mut info), GpufErrorCode::Ok);
+    assert_eq!(info.code, GpufErrorCode::Network);
+    assert!(info.retryable);
+    let message = unsafe { std::ffi::CStr::from_ptr(info.message) };
+    assert_eq!(message.to_str().unwrap(), "connection refused");
+
+    set_last_error(GpufErrorCode::ModelLoad, "bad\0file");
+    assert!(!GpufErrorCode::ModelLoad.is_retryable());
+    let message = unsafe { std::ffi::CStr::from_ptr(gpuf_get_last_error()) };
+    assert_eq!(message.to_str().unwrap(), "bad file");
+}
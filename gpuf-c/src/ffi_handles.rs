@@ -0,0 +1,809 @@
+//! Handle-based C API. Each `gpuf_llm_t` owns its own model and context, so
+//! an app can keep several models loaded; `gpuf_client_t` carries one worker
+//! configuration. The legacy functions (`set_remote_worker_model`,
+//! `start_remote_worker`, ...) keep driving the process-wide default model
+//! and worker, and the client handle functions are built on them.
+//!
+//! Generation shares scratch buffers across the process, so generations on
+//! different `gpuf_llm_t` run one at a time. The worker keeps a single server
+//! connection, so only one `gpuf_client_t` can be connected at a time.
+//!
+//! The `_async` variants return right away and report the result to a
+//! completion callback from a library thread. So does `gpuf_download_start`,
+//! whose `gpuf_download_t` pauses, resumes or cancels the download meanwhile.
+
+use crate::ffi_error::{gpuf_get_last_error, last_error_code, set_last_error, GpufErrorCode};
+use crate::util::model_downloader::{DownloadConfig, DownloadHandle, ModelDownloader};
+use crate::{
+    ensure_backend_initialized, get_global_runtime, get_remote_worker_status, gpuf_create_context,
+    gpuf_load_model, gpuf_start_generation_async, gpuf_stop_generation, llama_context, llama_free,
+    llama_model, llama_model_free, manual_llama_completion, set_remote_worker_model,
+    start_remote_worker, start_remote_worker_tasks_with_callback_ptr, stop_remote_worker,
+    GLOBAL_INFERENCE_MUTEX,
+};
+use libc::size_t;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A loaded model and its context (opaque to C)
+#[allow(non_camel_case_types)]
+pub struct gpuf_llm_t {
+    model: *mut llama_model,
+    context: *mut llama_context,
+    generating: AtomicBool,
+}
+
+// The pointers are only used under GLOBAL_INFERENCE_MUTEX
+unsafe impl Send for gpuf_llm_t {}
+unsafe impl Sync for gpuf_llm_t {}
+
+/// Remote worker configuration (opaque to C)
+///
+/// Not a worker of its own: a started client drives the process-wide worker,
+/// so an app may keep several configurations but connect one at a time.
+#[allow(non_camel_case_types)]
+pub struct gpuf_client_t {
+    server_addr: CString,
+    control_port: c_int,
+    proxy_port: c_int,
+    worker_type: CString,
+    client_id: CString,
+}
+
+/// A model download running in the background (opaque to C)
+#[allow(non_camel_case_types)]
+pub struct gpuf_download_t {
+    handle: DownloadHandle,
+    /// Set once the download has ended
+    result: Arc<Mutex<Option<GpufErrorCode>>>,
+}
+
+/// State of a `gpuf_download_t`
+///
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpufDownloadState {
+    Running,
+    /// Paused with `gpuf_download_pause`
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Progress of a `gpuf_download_t`
+#[repr(C)]
+pub struct GpufDownloadProgress {
+    pub state: GpufDownloadState,
+    pub downloaded_bytes: u64,
+    /// 0 while unknown
+    pub total_bytes: u64,
+    pub speed_bps: u64,
+    /// -1 while unknown
+    pub eta_seconds: i64,
+}
+
+/// Called once when an `_async` call finishes. `result` is the generated text
+/// on success and the error message otherwise; it is only valid during the
+/// call.
+#[allow(non_camel_case_types)]
+pub type gpuf_completion_callback =
+    extern "C" fn(code: GpufErrorCode, result: *const c_char, user_data: *mut c_void);
+
+/// Receives each piece of generated text, or each worker event
+#[allow(non_camel_case_types)]
+pub type gpuf_token_callback = extern "C" fn(token: *const c_char, user_data: *mut c_void);
+
+/// Caller pointers handed to the thread that runs an `_async` call. The
+/// caller keeps them valid until the completion callback.
+struct AsyncCall<T> {
+    target: *mut T,
+    on_complete: gpuf_completion_callback,
+    user_data: *mut c_void,
+}
+
+unsafe impl<T> Send for AsyncCall<T> {}
+
+impl<T> AsyncCall<T> {
+    fn complete(&self, code: GpufErrorCode, result: &CStr) {
+        (self.on_complete)(code, result.as_ptr(), self.user_data);
+    }
+
+    /// Complete with the error recorded on this thread
+    fn fail(&self, code: GpufErrorCode) {
+        let message = gpuf_get_last_error();
+        if message.is_null() {
+            self.complete(code, &CString::default());
+        } else {
+            self.complete(code, unsafe { CStr::from_ptr(message) });
+        }
+    }
+}
+
+/// Run `job` on a new library thread
+fn spawn_async(name: &str, job: impl FnOnce() + Send + 'static) -> GpufErrorCode {
+    match std::thread::Builder::new()
+        .name(name.to_string())
+        .spawn(job)
+    {
+        Ok(_) => GpufErrorCode::Ok,
+        Err(e) => {
+            set_last_error(
+                GpufErrorCode::Internal,
+                format!("Failed to start {} thread: {}", name, e),
+            );
+            GpufErrorCode::Internal
+        }
+    }
+}
+
+/// Address of the client handle holding the worker connection, 0 when none
+static CONNECTED_CLIENT: Mutex<usize> = Mutex::new(0);
+
+/// Drop the connected client without stopping it, for `gpuf_shutdown`
+pub(crate) fn forget_connected_client() {
+    *CONNECTED_CLIENT.lock().unwrap() = 0;
+}
+
+/// Whether `client` holds the worker connection, recording an error if not
+fn is_connected(client: *mut gpuf_client_t) -> bool {
+    let connected = !client.is_null() && *CONNECTED_CLIENT.lock().unwrap() == client as usize;
+    if !connected {
+        set_last_error(GpufErrorCode::NotInitialized, "Client is not connected");
+    }
+    connected
+}
+
+fn to_cstring(s: *const c_char) -> Option<CString> {
+    if s.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(s) }.to_owned())
+}
+
+/// Load a model into a new instance (C API)
+///
+/// # Returns
+/// - The instance, to be released with `gpuf_llm_free`
+/// - NULL if the backend, the model or the context failed to initialize; see
+///   `gpuf_get_last_error_info`
+///
+/// # Safety
+/// Caller must ensure `model_path` is a valid null-terminated C string
+#[no_mangle]
+pub extern "C" fn gpuf_llm_create(model_path: *const c_char) -> *mut gpuf_llm_t {
+    if model_path.is_null() {
+        set_last_error(GpufErrorCode::InvalidArgument, "Model path is null");
+        return std::ptr::null_mut();
+    }
+    if ensure_backend_initialized() != 0 {
+        set_last_error(
+            GpufErrorCode::NotInitialized,
+            "Backend initialization failed",
+        );
+        return std::ptr::null_mut();
+    }
+
+    let model = gpuf_load_model(model_path);
+    if model.is_null() {
+        set_last_error(
+            GpufErrorCode::ModelLoad,
+            "Failed to load model for LLM instance",
+        );
+        return std::ptr::null_mut();
+    }
+    let context = gpuf_create_context(model);
+    if context.is_null() {
+        unsafe { llama_model_free(model) };
+        set_last_error(
+            GpufErrorCode::OutOfMemory,
+            "Failed to create context for LLM instance",
+        );
+        return std::ptr::null_mut();
+    }
+
+    Box::into_raw(Box::new(gpuf_llm_t {
+        model,
+        context,
+        generating: AtomicBool::new(false),
+    }))
+}
+
+/// Generate text into `output` (C API)
+///
+/// Waits for generations running on other instances.
+///
+/// # Returns
+/// - Length of the generated text written to `output`
+/// - Negative `GpufErrorCode`: Invalid arguments or generation failed
+#[no_mangle]
+pub extern "C" fn gpuf_llm_generate(
+    llm: *mut gpuf_llm_t,
+    prompt: *const c_char,
+    max_tokens: c_int,
+    temperature: f32,
+    top_k: c_int,
+    top_p: f32,
+    repeat_penalty: f32,
+    output: *mut c_char,
+    output_len: c_int,
+) -> c_int {
+    if llm.is_null() || prompt.is_null() || output.is_null() || output_len <= 0 {
+        return set_last_error(
+            GpufErrorCode::InvalidArgument,
+            "Invalid generation arguments",
+        );
+    }
+    let llm = unsafe { &*llm };
+
+    let _inference_lock = GLOBAL_INFERENCE_MUTEX.lock().unwrap();
+    llm.generating.store(true, Ordering::SeqCst);
+    let result = manual_llama_completion(
+        llm.model,
+        llm.context,
+        prompt,
+        max_tokens,
+        temperature,
+        top_k,
+        top_p,
+        repeat_penalty,
+        output,
+        output_len,
+    );
+    llm.generating.store(false, Ordering::SeqCst);
+    if result < 0 {
+        return set_last_error(
+            GpufErrorCode::Internal,
+            format!("Generation failed: {}", result),
+        );
+    }
+    result
+}
+
+/// Generate text and pass each piece to `on_token` as it is produced (C API)
+///
+/// Blocks until generation ends or `gpuf_llm_stop` is called, and waits for
+/// generations running on other instances.
+///
+/// # Returns
+/// - Number of generated tokens
+/// - Negative `GpufErrorCode`: Invalid arguments or generation failed
+#[no_mangle]
+pub extern "C" fn gpuf_llm_generate_stream(
+    llm: *mut gpuf_llm_t,
+    prompt: *const c_char,
+    max_tokens: c_int,
+    temperature: f32,
+    top_k: c_int,
+    top_p: f32,
+    repeat_penalty: f32,
+    on_token: Option<gpuf_token_callback>,
+    user_data: *mut c_void,
+) -> c_int {
+    if llm.is_null() || prompt.is_null() {
+        return set_last_error(
+            GpufErrorCode::InvalidArgument,
+            "Invalid generation arguments",
+        );
+    }
+    let llm = unsafe { &*llm };
+
+    let _inference_lock = GLOBAL_INFERENCE_MUTEX.lock().unwrap();
+    llm.generating.store(true, Ordering::SeqCst);
+    let result = gpuf_start_generation_async(
+        llm.context,
+        prompt,
+        max_tokens,
+        temperature,
+        top_k,
+        top_p,
+        repeat_penalty,
+        on_token,
+        user_data,
+    );
+    llm.generating.store(false, Ordering::SeqCst);
+    if result < 0 {
+        return set_last_error(
+            GpufErrorCode::Internal,
+            format!("Generation failed: {}", result),
+        );
+    }
+    result
+}
+
+/// Stop a running `gpuf_llm_generate_stream` on this instance (C API)
+///
+/// # Returns
+/// - `GpufErrorCode::Ok`: Stop requested
+/// - `GpufErrorCode::InvalidArgument`: The instance is not generating
+#[no_mangle]
+pub extern "C" fn gpuf_llm_stop(llm: *mut gpuf_llm_t) -> GpufErrorCode {
+    if llm.is_null() {
+        set_last_error(GpufErrorCode::InvalidArgument, "LLM instance is null");
+        return GpufErrorCode::InvalidArgument;
+    }
+    let llm = unsafe { &*llm };
+    if !llm.generating.load(Ordering::SeqCst) {
+        set_last_error(
+            GpufErrorCode::InvalidArgument,
+            "LLM instance is not generating",
+        );
+        return GpufErrorCode::InvalidArgument;
+    }
+    gpuf_stop_generation(llm.context);
+    GpufErrorCode::Ok
+}
+
+/// Text collected by `gpuf_llm_generate_async` while forwarding each piece
+struct StreamState {
+    text: String,
+    on_token: Option<gpuf_token_callback>,
+    user_data: *mut c_void,
+}
+
+extern "C" fn collect_token(token: *const c_char, state: *mut c_void) {
+    let state = unsafe { &mut *(state as *mut StreamState) };
+    state
+        .text
+        .push_str(&unsafe { CStr::from_ptr(token) }.to_string_lossy());
+    if let Some(on_token) = state.on_token {
+        on_token(token, state.user_data);
+    }
+}
+
+/// Non-blocking `gpuf_llm_generate_stream` (C API)
+///
+/// Returns once generation is queued. `on_token` (may be NULL) gets each
+/// piece and `on_complete` the whole text, both on a library thread. `llm`
+/// and `user_data` must stay valid until `on_complete` runs.
+///
+/// # Returns
+/// - `GpufErrorCode::Ok`: Generation started; `on_complete` will be called
+/// - Other codes: Nothing started, `on_complete` is not called
+#[no_mangle]
+pub extern "C" fn gpuf_llm_generate_async(
+    llm: *mut gpuf_llm_t,
+    prompt: *const c_char,
+    max_tokens: c_int,
+    temperature: f32,
+    top_k: c_int,
+    top_p: f32,
+    repeat_penalty: f32,
+    on_token: Option<gpuf_token_callback>,
+    on_complete: gpuf_completion_callback,
+    user_data: *mut c_void,
+) -> GpufErrorCode {
+    let Some(prompt) = to_cstring(prompt).filter(|_| !llm.is_null()) else {
+        set_last_error(
+            GpufErrorCode::InvalidArgument,
+            "Invalid generation arguments",
+        );
+        return GpufErrorCode::InvalidArgument;
+    };
+    let call = AsyncCall {
+        target: llm,
+        on_complete,
+        user_data,
+    };
+
+    spawn_async("gpuf-generate", move || {
+        // Move the whole call in, its pointer fields alone are not Send
+        let call = call;
+        let mut state = StreamState {
+            text: String::new(),
+            on_token,
+            user_data: call.user_data,
+        };
+        let result = gpuf_llm_generate_stream(
+            call.target,
+            prompt.as_ptr(),
+            max_tokens,
+            temperature,
+            top_k,
+            top_p,
+            repeat_penalty,
+            Some(collect_token),
+            &mut state as *mut StreamState as *mut c_void,
+        );
+        if result < 0 {
+            call.fail(last_error_code());
+        } else {
+            let text = CString::new(state.text.replace('\0', "")).unwrap_or_default();
+            call.complete(GpufErrorCode::Ok, &text);
+        }
+    })
+}
+
+/// Free an instance and its model (C API)
+///
+/// Waits for the running generation to finish. `llm` must not be used
+/// afterwards.
+#[no_mangle]
+pub extern "C" fn gpuf_llm_free(llm: *mut gpuf_llm_t) {
+    if llm.is_null() {
+        return;
+    }
+    let _inference_lock = GLOBAL_INFERENCE_MUTEX.lock().unwrap();
+    let llm = unsafe { Box::from_raw(llm) };
+    unsafe {
+        llama_free(llm.context);
+        llama_model_free(llm.model);
+    }
+}
+
+/// Create a remote worker configuration (C API)
+///
+/// Nothing is connected until `gpuf_client_start`. Arguments are the same as
+/// for `start_remote_worker`.
+///
+/// # Returns
+/// - The client, to be released with `gpuf_client_free`
+/// - NULL if a string is null or a port is out of range
+///   (`GpufErrorCode::InvalidArgument`)
+#[no_mangle]
+pub extern "C" fn gpuf_client_create(
+    server_addr: *const c_char,
+    control_port: c_int,
+    proxy_port: c_int,
+    worker_type: *const c_char,
+    client_id: *const c_char,
+) -> *mut gpuf_client_t {
+    let (Some(server_addr), Some(worker_type), Some(client_id)) = (
+        to_cstring(server_addr),
+        to_cstring(worker_type),
+        to_cstring(client_id),
+    ) else {
+        set_last_error(
+            GpufErrorCode::InvalidArgument,
+            "Client configuration has a null string",
+        );
+        return std::ptr::null_mut();
+    };
+    let ports = 1..=u16::MAX as c_int;
+    if !ports.contains(&control_port) || !ports.contains(&proxy_port) {
+        set_last_error(
+            GpufErrorCode::InvalidArgument,
+            "Client configuration has an invalid port",
+        );
+        return std::ptr::null_mut();
+    }
+
+    Box::into_raw(Box::new(gpuf_client_t {
+        server_addr,
+        control_port,
+        proxy_port,
+        worker_type,
+        client_id,
+    }))
+}
+
+/// Connect the client, log in and start serving tasks (C API)
+///
+/// `callback` receives worker events as with
+/// `start_remote_worker_tasks_with_callback_ptr` and may be NULL. Stop the
+/// connected client before starting another one.
+///
+/// # Returns
+/// - `GpufErrorCode::Ok`: Success
+/// - `GpufErrorCode::Busy`: Another client, or the legacy worker, is already
+///   connected
+/// - Other codes: Login or starting the tasks failed
+#[no_mangle]
+pub extern "C" fn gpuf_client_start(
+    client: *mut gpuf_client_t,
+    callback: Option<gpuf_token_callback>,
+) -> GpufErrorCode {
+    if client.is_null() {
+        set_last_error(GpufErrorCode::InvalidArgument, "Client is null");
+        return GpufErrorCode::InvalidArgument;
+    }
+    let mut connected = CONNECTED_CLIENT.lock().unwrap();
+    if *connected != 0 || crate::handle::android_sdk::get_android_tcp_stream().is_some() {
+        set_last_error(GpufErrorCode::Busy, "A remote worker is already connected");
+        return GpufErrorCode::Busy;
+    }
+
+    let config = unsafe { &*client };
+    if start_remote_worker(
+        config.server_addr.as_ptr(),
+        config.control_port,
+        config.proxy_port,
+        config.worker_type.as_ptr(),
+        config.client_id.as_ptr(),
+    ) != 0
+    {
+        return last_error_code();
+    }
+    if start_remote_worker_tasks_with_callback_ptr(callback) != 0 {
+        let code = last_error_code();
+        stop_remote_worker();
+        return code;
+    }
+
+    *connected = client as usize;
+    GpufErrorCode::Ok
+}
+
+/// Non-blocking `gpuf_client_start` (C API)
+///
+/// Returns right away and logs in on a library thread, then calls
+/// `on_complete` with the result of `gpuf_client_start`. `client` and
+/// `user_data` must stay valid until then.
+///
+/// # Returns
+/// - `GpufErrorCode::Ok`: Connecting; `on_complete` will be called
+/// - Other codes: Nothing started, `on_complete` is not called
+#[no_mangle]
+pub extern "C" fn gpuf_client_connect_async(
+    client: *mut gpuf_client_t,
+    callback: Option<gpuf_token_callback>,
+    on_complete: gpuf_completion_callback,
+    user_data: *mut c_void,
+) -> GpufErrorCode {
+    if client.is_null() {
+        set_last_error(GpufErrorCode::InvalidArgument, "Client is null");
+        return GpufErrorCode::InvalidArgument;
+    }
+    let call = AsyncCall {
+        target: client,
+        on_complete,
+        user_data,
+    };
+
+    spawn_async("gpuf-connect", move || {
+        // Move the whole call in, its pointer fields alone are not Send
+        let call = call;
+        match gpuf_client_start(call.target, callback) {
+            GpufErrorCode::Ok => call.complete(GpufErrorCode::Ok, &CString::default()),
+            code => call.fail(code),
+        }
+    })
+}
+
+/// Load the model the connected client serves, hot swapping the current one
+/// (C API)
+///
+/// # Returns
+/// - `GpufErrorCode::Ok`: Success
+/// - `GpufErrorCode::NotInitialized`: The client is not connected
+/// - Other codes: see `set_remote_worker_model`
+#[no_mangle]
+pub extern "C" fn gpuf_client_set_model(
+    client: *mut gpuf_client_t,
+    model_path: *const c_char,
+) -> GpufErrorCode {
+    if !is_connected(client) {
+        return GpufErrorCode::NotInitialized;
+    }
+    if set_remote_worker_model(model_path) != 0 {
+        return last_error_code();
+    }
+    GpufErrorCode::Ok
+}
+
+/// Status of the client's worker, written like `get_remote_worker_status`
+/// (C API)
+///
+/// # Returns
+/// - `GpufErrorCode::Ok`: Success
+/// - `GpufErrorCode::NotInitialized`: The client is not connected
+/// - `GpufErrorCode::InvalidArgument`: `buffer` is null or too small
+#[no_mangle]
+pub extern "C" fn gpuf_client_get_status(
+    client: *mut gpuf_client_t,
+    buffer: *mut c_char,
+    buffer_size: size_t,
+) -> GpufErrorCode {
+    if !is_connected(client) {
+        return GpufErrorCode::NotInitialized;
+    }
+    if get_remote_worker_status(buffer, buffer_size) != 0 {
+        return last_error_code();
+    }
+    GpufErrorCode::Ok
+}
+
+/// Disconnect the client (C API)
+///
+/// # Returns
+/// - `GpufErrorCode::Ok`: Success
+/// - `GpufErrorCode::NotInitialized`: The client is not connected
+#[no_mangle]
+pub extern "C" fn gpuf_client_stop(client: *mut gpuf_client_t) -> GpufErrorCode {
+    if !is_connected(client) {
+        return GpufErrorCode::NotInitialized;
+    }
+    *CONNECTED_CLIENT.lock().unwrap() = 0;
+    stop_remote_worker();
+    GpufErrorCode::Ok
+}
+
+/// Disconnect the client if needed and free it (C API)
+///
+/// `client` must not be used afterwards.
+#[no_mangle]
+pub extern "C" fn gpuf_client_free(client: *mut gpuf_client_t) {
+    if client.is_null() {
+        return;
+    }
+    gpuf_client_stop(client);
+    drop(unsafe { Box::from_raw(client) });
+}
+
+/// Download `url` to `output_path` in the background (C API)
+///
+/// Continues a partial file left at `output_path` and follows the policy set
+/// with `set_remote_worker_download_policy`. `on_complete` (may be NULL)
+/// gets the output path, or the error message, on a library thread;
+/// `user_data` must stay valid until then.
+///
+/// # Returns
+/// - The download, to be released with `gpuf_download_free`
+/// - NULL if a string is null (`GpufErrorCode::InvalidArgument`) or the
+///   download thread failed to start
+#[no_mangle]
+pub extern "C" fn gpuf_download_start(
+    url: *const c_char,
+    output_path: *const c_char,
+    on_complete: Option<gpuf_completion_callback>,
+    user_data: *mut c_void,
+) -> *mut gpuf_download_t {
+    let (Some(url), Some(output_path)) = (to_cstring(url), to_cstring(output_path)) else {
+        set_last_error(GpufErrorCode::InvalidArgument, "URL or output path is null");
+        return std::ptr::null_mut();
+    };
+    let downloader = ModelDownloader::new(DownloadConfig {
+        url: url.to_string_lossy().into_owned(),
+        output_path: PathBuf::from(output_path.to_string_lossy().into_owned()),
+        ..Default::default()
+    });
+    let download = Box::into_raw(Box::new(gpuf_download_t {
+        handle: downloader.handle(),
+        result: Arc::new(Mutex::new(None)),
+    }));
+    let result = unsafe { (*download).result.clone() };
+    let call = on_complete.map(|on_complete| AsyncCall {
+        target: download,
+        on_complete,
+        user_data,
+    });
+
+    let started = spawn_async("gpuf-download", move || {
+        // Move the whole call in, its pointer fields alone are not Send
+        let call = call;
+        let code = match get_global_runtime().block_on(downloader.download()) {
+            Ok(()) => GpufErrorCode::Ok,
+            Err(e) if downloader.handle().is_cancelled() => {
+                set_last_error(GpufErrorCode::Cancelled, e.to_string());
+                GpufErrorCode::Cancelled
+            }
+            Err(e) => {
+                set_last_error(GpufErrorCode::Network, format!("Download failed: {}", e));
+                GpufErrorCode::Network
+            }
+        };
+        *result.lock().unwrap() = Some(code);
+        if let Some(call) = call {
+            if code == GpufErrorCode::Ok {
+                call.complete(code, &output_path);
+            } else {
+                call.fail(code);
+            }
+        }
+    });
+    if started != GpufErrorCode::Ok {
+        drop(unsafe { Box::from_raw(download) });
+        return std::ptr::null_mut();
+    }
+    download
+}
+
+/// Stop transferring, keeping what was downloaded (C API)
+///
+/// Takes effect within about a second.
+///
+/// # Returns
+/// - `GpufErrorCode::Ok`: Success
+/// - `GpufErrorCode::InvalidArgument`: `download` is null
+#[no_mangle]
+pub extern "C" fn gpuf_download_pause(download: *mut gpuf_download_t) -> GpufErrorCode {
+    let Some(download) = (unsafe { download.as_ref() }) else {
+        set_last_error(GpufErrorCode::InvalidArgument, "Download is null");
+        return GpufErrorCode::InvalidArgument;
+    };
+    download.handle.pause();
+    GpufErrorCode::Ok
+}
+
+/// Continue a paused download (C API)
+///
+/// # Returns
+/// - `GpufErrorCode::Ok`: Success
+/// - `GpufErrorCode::InvalidArgument`: `download` is null
+#[no_mangle]
+pub extern "C" fn gpuf_download_resume(download: *mut gpuf_download_t) -> GpufErrorCode {
+    let Some(download) = (unsafe { download.as_ref() }) else {
+        set_last_error(GpufErrorCode::InvalidArgument, "Download is null");
+        return GpufErrorCode::InvalidArgument;
+    };
+    download.handle.resume();
+    GpufErrorCode::Ok
+}
+
+/// Stop the download and delete the partial file (C API)
+///
+/// `on_complete` of `gpuf_download_start` then reports
+/// `GpufErrorCode::Cancelled`. A finished download is left alone.
+///
+/// # Returns
+/// - `GpufErrorCode::Ok`: Success
+/// - `GpufErrorCode::InvalidArgument`: `download` is null
+#[no_mangle]
+pub extern "C" fn gpuf_download_cancel(download: *mut gpuf_download_t) -> GpufErrorCode {
+    let Some(download) = (unsafe { download.as_ref() }) else {
+        set_last_error(GpufErrorCode::InvalidArgument, "Download is null");
+        return GpufErrorCode::InvalidArgument;
+    };
+    download.handle.cancel();
+    GpufErrorCode::Ok
+}
+
+/// State and progress of a download (C API)
+///
+/// Progress is updated about once a second while data arrives.
+///
+/// # Returns
+/// - `GpufErrorCode::Ok`: `progress` was filled
+/// - `GpufErrorCode::InvalidArgument`: `download` or `progress` is null
+#[no_mangle]
+pub extern "C" fn gpuf_download_get_progress(
+    download: *mut gpuf_download_t,
+    progress: *mut GpufDownloadProgress,
+) -> GpufErrorCode {
+    let Some(download) = (unsafe { download.as_ref() }).filter(|_| !progress.is_null()) else {
+        set_last_error(
+            GpufErrorCode::InvalidArgument,
+            "Download or progress is null",
+        );
+        return GpufErrorCode::InvalidArgument;
+    };
+    let state = match *download.result.lock().unwrap() {
+        Some(GpufErrorCode::Ok) => GpufDownloadState::Completed,
+        Some(GpufErrorCode::Cancelled) => GpufDownloadState::Cancelled,
+        Some(_) => GpufDownloadState::Failed,
+        None if download.handle.is_cancelled() => GpufDownloadState::Cancelled,
+        None if download.handle.is_paused() => GpufDownloadState::Paused,
+        None => GpufDownloadState::Running,
+    };
+    let report = download.handle.progress();
+    unsafe {
+        *progress = GpufDownloadProgress {
+            state,
+            downloaded_bytes: report.as_ref().map_or(0, |r| r.downloaded_bytes),
+            total_bytes: report.as_ref().map_or(0, |r| r.total_bytes),
+            speed_bps: report.as_ref().map_or(0, |r| r.speed_bps),
+            eta_seconds: report
+                .as_ref()
+                .and_then(|r| r.eta_seconds)
+                .map_or(-1, |eta| eta as i64),
+        };
+    }
+    GpufErrorCode::Ok
+}
+
+/// Free a download, cancelling it if it is still running (C API)
+///
+/// `download` must not be used afterwards.
+#[no_mangle]
+pub extern "C" fn gpuf_download_free(download: *mut gpuf_download_t) {
+    if download.is_null() {
+        return;
+    }
+    let download = unsafe { Box::from_raw(download) };
+    if download.result.lock().unwrap().is_none() {
+        download.handle.cancel();
+    }
+}
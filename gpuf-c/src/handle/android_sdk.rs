@@ -8,7 +8,9 @@
 use anyhow::{anyhow, Result};
 
 #[cfg(target_os = "android")]
-use common::{ChatMessage, Command, CommandV1, Model, OsType, OutputPhase, SystemInfo};
+use common::{
+    ChatMessage, Command, CommandV1, Model, OsType, OutputPhase, SystemInfo, DEADLINE_EXCEEDED,
+};
 
 #[cfg(target_os = "android")]
 use std::ffi::{CStr, CString};
@@ -31,6 +33,11 @@ use tracing::info;
 #[cfg(target_os = "android")]
 use std::time::Duration;
 
+#[cfg(target_os = "android")]
+use crate::util::power;
+#[cfg(target_os = "android")]
+use crate::util::runtime_config::RuntimeConfig;
+
 #[cfg(target_os = "android")]
 fn build_chat_prompt(messages: &[ChatMessage]) -> String {
     let mut out = String::new();
@@ -662,6 +669,301 @@ static GLOBAL_WORKER_HANDLES: OnceLock<
 #[cfg(target_os = "android")]
 static GLOBAL_STOP_SIGNAL: OnceLock<Arc<AtomicBool>> = OnceLock::new();
 
+/// How often battery and temperature are checked
+#[cfg(target_os = "android")]
+const POWER_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+#[cfg(target_os = "android")]
+const HEARTBEAT_INTERVAL_SECS: u64 = 120;
+/// Heartbeats are sent less often while the app is in the background
+#[cfg(target_os = "android")]
+const BACKGROUND_HEARTBEAT_INTERVAL_SECS: u64 = 600;
+
+/// How often the idle monitor looks for an unused model
+#[cfg(target_os = "android")]
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Set while the host app is in the background
+#[cfg(target_os = "android")]
+static APP_BACKGROUNDED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(target_os = "android")]
+fn heartbeat_interval_secs() -> u64 {
+    if APP_BACKGROUNDED.load(Ordering::Relaxed) {
+        BACKGROUND_HEARTBEAT_INTERVAL_SECS
+    } else {
+        HEARTBEAT_INTERVAL_SECS
+    }
+}
+
+/// Tells the server whether the worker takes inference
+#[cfg(target_os = "android")]
+fn send_busy_status(
+    stream: &Mutex<std::net::TcpStream>,
+    busy: bool,
+    reason: Option<String>,
+) -> Result<()> {
+    let client_id = ANDROID_CLIENT_ID
+        .get()
+        .and_then(|m| m.lock().ok().and_then(|g| *g))
+        .unwrap_or([0u8; 16]);
+    let busy_cmd = CommandV1::WorkerBusy {
+        client_id,
+        busy,
+        reason,
+    };
+    let mut stream = stream
+        .lock()
+        .map_err(|_| anyhow!("TCP stream lock poisoned"))?;
+    common::write_command_sync(&mut *stream, &Command::V1(busy_cmd))
+}
+
+/// The host app went to the background: stop taking inference and slow down
+/// heartbeats so Android does not flag the app for battery use
+#[cfg(target_os = "android")]
+pub fn on_app_backgrounded() {
+    if APP_BACKGROUNDED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    println!("🌙 Android: App backgrounded, pausing inference");
+    // A worker paused for power is already busy on the server
+    if power::is_paused() {
+        return;
+    }
+    if let Some(stream) = get_android_tcp_stream() {
+        if let Err(e) = send_busy_status(&stream, true, Some("app in background".to_string())) {
+            eprintln!("❌ Android: Failed to send busy status: {}", e);
+        }
+    }
+}
+
+/// The host app is back in the foreground: take inference again
+#[cfg(target_os = "android")]
+pub fn on_app_foregrounded() {
+    if !APP_BACKGROUNDED.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    println!("☀️ Android: App foregrounded, resuming inference");
+    if power::is_paused() {
+        return;
+    }
+    if let Some(stream) = get_android_tcp_stream() {
+        if let Err(e) = send_busy_status(&stream, false, None) {
+            eprintln!("❌ Android: Failed to send busy status: {}", e);
+        }
+    }
+}
+
+/// Watches battery and temperature and tells the server when inference is
+/// paused or resumed, so it stops routing tasks to a hot or drained phone
+#[cfg(target_os = "android")]
+fn spawn_power_monitor(stream: Arc<Mutex<std::net::TcpStream>>, stop_signal: Arc<AtomicBool>) {
+    std::thread::spawn(move || {
+        while !stop_signal.load(Ordering::Relaxed) {
+            if let Some((busy, reason)) = power::pending_change() {
+                println!(
+                    "🔋 Android: {} inference ({})",
+                    if busy { "Pausing" } else { "Resuming" },
+                    reason.as_deref().unwrap_or("power ok")
+                );
+                // Only switch once the server knows, a failed send is retried next check.
+                // A backgrounded app is already busy on the server.
+                let sent = if APP_BACKGROUNDED.load(Ordering::Relaxed) {
+                    Ok(())
+                } else {
+                    send_busy_status(&stream, busy, reason)
+                };
+                match sent {
+                    Ok(()) => power::set_paused(busy),
+                    Err(e) => eprintln!("❌ Android: Failed to send busy status: {}", e),
+                }
+            }
+            std::thread::sleep(POWER_CHECK_INTERVAL);
+        }
+    });
+}
+
+/// Tells the server whether the model is loaded and ready for a task
+#[cfg(target_os = "android")]
+fn send_model_warmth(stream: &mut std::net::TcpStream, warm: bool) -> Result<()> {
+    let client_id = ANDROID_CLIENT_ID
+        .get()
+        .and_then(|m| m.lock().ok().and_then(|g| *g))
+        .unwrap_or([0u8; 16]);
+    let warmth = CommandV1::ModelWarmth { client_id, warm };
+    common::write_command_sync(stream, &Command::V1(warmth))
+}
+
+/// Answers a liveness challenge. The work takes a fraction of the deadline,
+/// so it holds up the next command only briefly.
+#[cfg(target_os = "android")]
+fn answer_challenge(
+    stream: &mut std::net::TcpStream,
+    challenge_id: [u8; 16],
+    task: &common::ChallengeTask,
+) -> Result<()> {
+    let client_id = ANDROID_CLIENT_ID
+        .get()
+        .and_then(|m| m.lock().ok().and_then(|g| *g))
+        .unwrap_or([0u8; 16]);
+    let response = CommandV1::ChallengeResponse {
+        client_id,
+        challenge_id,
+        answer: task.solve(),
+    };
+    common::write_command_sync(stream, &Command::V1(response))
+}
+
+/// Frees the model once no task ran for the `idle_unload_secs` of the
+/// runtime config, so the phone gets its RAM back while nobody asks
+/// anything. The next task loads the model again.
+#[cfg(target_os = "android")]
+fn spawn_idle_monitor(stream: Arc<Mutex<std::net::TcpStream>>, stop_signal: Arc<AtomicBool>) {
+    std::thread::spawn(move || {
+        let mut idle_since = std::time::Instant::now();
+        while !stop_signal.load(Ordering::Relaxed) {
+            std::thread::sleep(IDLE_CHECK_INTERVAL);
+            let busy = ANDROID_ACTIVE_TASK_ID
+                .get()
+                .is_some_and(|active| active.lock().map_or(true, |task| task.is_some()));
+            let loaded = !crate::GLOBAL_CONTEXT_PTR.load(Ordering::SeqCst).is_null();
+            if busy || !loaded {
+                idle_since = std::time::Instant::now();
+                continue;
+            }
+            let idle_unload_secs = RuntimeConfig::global().settings().idle_unload_secs;
+            if idle_unload_secs == 0
+                || idle_since.elapsed() < Duration::from_secs(idle_unload_secs as u64)
+            {
+                continue;
+            }
+            if crate::release_idle_model() {
+                println!(
+                    "💤 Android: Model unloaded after {} seconds without tasks",
+                    idle_unload_secs
+                );
+                let sent = stream
+                    .lock()
+                    .map_err(|_| anyhow!("TCP stream lock poisoned"))
+                    .and_then(|mut stream| send_model_warmth(&mut stream, false));
+                if let Err(e) = sent {
+                    eprintln!("❌ Android: Failed to send model warmth: {}", e);
+                }
+            }
+        }
+    });
+}
+
+/// Loads the model again before a task when it was freed for sitting idle
+#[cfg(target_os = "android")]
+fn reload_idle_model(stream: &mut std::net::TcpStream) {
+    let Some(result) = crate::reload_idle_model() else {
+        return;
+    };
+    if result != 0 {
+        eprintln!("❌ Android: Failed to reload idle model: {}", result);
+        return;
+    }
+    if let Err(e) = send_model_warmth(stream, true) {
+        eprintln!("❌ Android: Failed to send model warmth: {}", e);
+    }
+}
+
+/// Fails a task right away while inference is paused for heat, battery or
+/// because the app is in the background
+#[cfg(target_os = "android")]
+fn reject_task_if_paused(stream: &mut std::net::TcpStream, task_id: &str) -> bool {
+    if !power::is_paused() && !APP_BACKGROUNDED.load(Ordering::Relaxed) {
+        return false;
+    }
+    let result_command = CommandV1::InferenceResultChunk {
+        task_id: task_id.to_string(),
+        seq: 0,
+        delta: String::new(),
+        phase: OutputPhase::Unknown,
+        done: true,
+        error: Some(
+            "Worker is paused: device too hot, battery low or app in background".to_string(),
+        ),
+        prompt_tokens: 0,
+        completion_tokens: 0,
+        analysis_tokens: 0,
+        final_tokens: 0,
+    };
+    let _ = common::write_command_sync(stream, &Command::V1(result_command));
+    true
+}
+
+/// Fails a task right away when its prompt or max_tokens is over the limits
+/// set with `set_remote_worker_request_limits`
+#[cfg(target_os = "android")]
+fn reject_task_over_limits(
+    stream: &mut std::net::TcpStream,
+    task_id: &str,
+    prompt_bytes: usize,
+    max_tokens: u32,
+) -> bool {
+    let limits = crate::util::request_limits::limits();
+    let Err(e) = limits
+        .check_max_tokens(max_tokens)
+        .and_then(|_| limits.check_prompt_bytes(prompt_bytes))
+    else {
+        return false;
+    };
+    println!("⚠️ Android: Rejected task {}: {}", task_id, e);
+    let result_command = CommandV1::InferenceResultChunk {
+        task_id: task_id.to_string(),
+        seq: 0,
+        delta: String::new(),
+        phase: OutputPhase::Unknown,
+        done: true,
+        error: Some(e.to_string()),
+        prompt_tokens: 0,
+        completion_tokens: 0,
+        analysis_tokens: 0,
+        final_tokens: 0,
+    };
+    let _ = common::write_command_sync(stream, &Command::V1(result_command));
+    true
+}
+
+/// When the server stops waiting for a task it sent with `deadline_ms`
+#[cfg(target_os = "android")]
+fn task_deadline(deadline_ms: Option<u64>) -> Option<std::time::Instant> {
+    deadline_ms.map(|ms| std::time::Instant::now() + Duration::from_millis(ms))
+}
+
+/// Fails a task that waited for the model until after its deadline
+#[cfg(target_os = "android")]
+fn reject_task_past_deadline(
+    stream: &std::net::TcpStream,
+    task_id: &str,
+    deadline: Option<std::time::Instant>,
+) -> bool {
+    if !deadline.is_some_and(|deadline| deadline <= std::time::Instant::now()) {
+        return false;
+    }
+    println!("⚠️ Android: Task {} waited past its deadline", task_id);
+    let result_command = CommandV1::InferenceResultChunk {
+        task_id: task_id.to_string(),
+        seq: 0,
+        delta: String::new(),
+        phase: OutputPhase::Unknown,
+        done: true,
+        error: Some(format!(
+            "{}: task waited past its deadline for the model",
+            DEADLINE_EXCEEDED
+        )),
+        prompt_tokens: 0,
+        completion_tokens: 0,
+        analysis_tokens: 0,
+        final_tokens: 0,
+    };
+    let mut stream = stream;
+    let _ = common::write_command_sync(&mut stream, &Command::V1(result_command));
+    true
+}
+
 /// Perform Android-native login using blocking TCP and bincode protocol
 ///
 /// This function replicates the functionality of TCPWorker::login() but
@@ -708,7 +1010,6 @@ pub async fn perform_android_login(
         network_tx: 0,
     };
     // Create Login command (same structure as TCPWorker::login())
-    const CURRENT_VERSION: u32 = 1;
 
     // Calculate device metrics from actual device info
     let device_memtotal_gb = devices_info.memsize_gb.try_into().unwrap_or(0);
@@ -725,8 +1026,8 @@ pub async fn perform_android_login(
         fixed_devices_info.device_id = 0x1000; // Generic ARM device ID
     }
 
-    let login_cmd = CommandV1::Login {
-        version: CURRENT_VERSION,
+    let login_cmd = CommandV1::LoginV2 {
+        version: common::PROTOCOL_VERSION,
         auto_models,
         os_type: OsType::ANDROID,
         client_id: hex::decode(client_id)
@@ -737,6 +1038,9 @@ pub async fn perform_android_login(
         device_memtotal_gb,
         device_total_tflops,
         devices_info: vec![fixed_devices_info],
+        benchmark: Some(crate::util::benchmark::run()),
+        enrollment_token: None,
+        region: None,
     };
 
     // Send login command using common library function
@@ -830,6 +1134,9 @@ pub async fn start_worker_tasks() -> Result<()> {
         .set(stop_signal.clone())
         .map_err(|_| anyhow!("Failed to set stop signal"))?;
 
+    spawn_power_monitor(tcp_stream.clone(), stop_signal.clone());
+    spawn_idle_monitor(tcp_stream.clone(), stop_signal.clone());
+
     // Spawn heartbeat task using native thread with full heartbeat logic
     let heartbeat_stream = tcp_stream.clone();
     let heartbeat_stop_signal = stop_signal.clone();
@@ -842,7 +1149,10 @@ pub async fn start_worker_tasks() -> Result<()> {
                 println!("🔧 Android: Heartbeat thread received stop signal");
                 break;
             }
-            println!("🔧 Android: Heartbeat loop - sleeping for 120 seconds...");
+            println!(
+                "🔧 Android: Heartbeat loop - sleeping for {} seconds...",
+                heartbeat_interval_secs()
+            );
 
             println!("💓 Android: Woke up - collecting system info for heartbeat...");
 
@@ -907,7 +1217,7 @@ pub async fn start_worker_tasks() -> Result<()> {
                 .get()
                 .and_then(|m| m.lock().ok().and_then(|g| *g))
                 .unwrap_or([0u8; 16]);
-            let heartbeat_cmd = CommandV1::Heartbeat {
+            let heartbeat_cmd = CommandV1::HeartbeatV2 {
                 client_id,
                 system_info: SystemInfo {
                     cpu_usage: cpu_usage as u8,
@@ -920,6 +1230,10 @@ pub async fn start_worker_tasks() -> Result<()> {
                 device_total_tflops: device_info.total_tflops.into(),
                 device_count: device_info.num as u16,
                 devices_info: vec![device_info],
+                gpu_metrics: Vec::new(), // NVML is not available on Android
+                network_quality: None,
+                power: power::current_status(),
+                version: Some(crate::util::version_info::worker_version(None)),
             };
 
             // Send heartbeat using common library function
@@ -936,10 +1250,12 @@ pub async fn start_worker_tasks() -> Result<()> {
             drop(heartbeat_stream);
             println!("🔧 Android: Heartbeat connection closed, starting next iteration...");
 
-            // Sleep with periodic stop signal checks
-            for _ in 0..120 {
-                // 120 seconds / 1 second intervals
+            // Sleep with periodic stop signal checks. The interval is re-read every
+            // second so coming back to the foreground cuts a background wait short.
+            let mut slept_secs = 0;
+            while slept_secs < heartbeat_interval_secs() {
                 thread::sleep(Duration::from_secs(1));
+                slept_secs += 1;
                 if heartbeat_stop_signal.load(Ordering::Relaxed) {
                     println!("🔧 Android: Heartbeat thread received stop signal during sleep");
                     break;
@@ -982,7 +1298,7 @@ pub async fn start_worker_tasks() -> Result<()> {
                     // Handle different command types
                     match command {
                         Command::V1(cmd_v1) => match cmd_v1 {
-                            CommandV1::LoginResult {
+                            CommandV1::LoginResultV2 {
                                 success,
                                 pods_model,
                                 error,
@@ -1031,7 +1347,7 @@ pub async fn start_worker_tasks() -> Result<()> {
                                     break;
                                 }
                             }
-                            CommandV1::PullModelResult { pods_model, error } => {
+                            CommandV1::PullModelResultV2 { pods_model, error } => {
                                 if let Some(err) = error {
                                     eprintln!("❌ Android: Pull model failed: {}", err);
                                 } else {
@@ -1049,8 +1365,10 @@ pub async fn start_worker_tasks() -> Result<()> {
                                     }
                                 }
                             }
-                            CommandV1::InferenceTask {
+                            CommandV1::InferenceTaskV2 {
                                 task_id,
+                                request_id: _,
+                                traceparent: _,
                                 prompt,
                                 max_tokens,
                                 temperature,
@@ -1059,8 +1377,22 @@ pub async fn start_worker_tasks() -> Result<()> {
                                 repeat_penalty,
                                 repeat_last_n: _,
                                 min_keep: _,
+                                overflow: _,
+                                deadline_ms,
                             } => {
                                 println!("🔧 Android: Received inference task: {}", task_id);
+                                if reject_task_if_paused(&mut stream, &task_id) {
+                                    continue;
+                                }
+                                if reject_task_over_limits(
+                                    &mut stream,
+                                    &task_id,
+                                    prompt.len(),
+                                    max_tokens,
+                                ) {
+                                    continue;
+                                }
+                                reload_idle_model(&mut stream);
                                 println!("📝 Android: Prompt: {}", prompt);
                                 println!("⚙️ Android: Parameters: max_tokens={}, temp={}, top_k={}, top_p={}", 
                                                              max_tokens, temperature, top_k, top_p);
@@ -1129,6 +1461,7 @@ pub async fn start_worker_tasks() -> Result<()> {
                                     }
                                 };
 
+                                let deadline = task_deadline(deadline_ms);
                                 let task_id_for_thread = task_id.clone();
                                 let prompt_for_thread = prompt.clone();
                                 let context_ptr_usize = context_ptr as usize;
@@ -1256,6 +1589,13 @@ pub async fn start_worker_tasks() -> Result<()> {
                                     }
 
                                     let _lock = GLOBAL_INFERENCE_MUTEX.lock().unwrap();
+                                    if reject_task_past_deadline(
+                                        &writer_stream,
+                                        &task_id_for_thread,
+                                        deadline,
+                                    ) {
+                                        return;
+                                    }
                                     let start_time = std::time::Instant::now();
                                     let prompt_cstr = match CString::new(prompt_for_thread) {
                                         Ok(s) => s,
@@ -1375,8 +1715,10 @@ pub async fn start_worker_tasks() -> Result<()> {
                                     );
                                 });
                             }
-                            CommandV1::ChatInferenceTask {
+                            CommandV1::ChatInferenceTaskV2 {
                                 task_id,
+                                request_id: _,
+                                traceparent: _,
                                 model: _,
                                 messages,
                                 max_tokens,
@@ -1386,8 +1728,24 @@ pub async fn start_worker_tasks() -> Result<()> {
                                 repeat_penalty,
                                 repeat_last_n: _,
                                 min_keep: _,
+                                prefix: _,
+                                overflow: _,
+                                deadline_ms,
                             } => {
                                 println!("🔧 Android: Received chat inference task: {}", task_id);
+                                if reject_task_if_paused(&mut stream, &task_id) {
+                                    continue;
+                                }
+                                let prompt_bytes = messages.iter().map(|m| m.content.len()).sum();
+                                if reject_task_over_limits(
+                                    &mut stream,
+                                    &task_id,
+                                    prompt_bytes,
+                                    max_tokens,
+                                ) {
+                                    continue;
+                                }
+                                reload_idle_model(&mut stream);
 
                                 use crate::llama_context;
                                 use crate::{
@@ -1458,6 +1816,7 @@ pub async fn start_worker_tasks() -> Result<()> {
                                     }
                                 };
 
+                                let deadline = task_deadline(deadline_ms);
                                 let task_id_for_thread = task_id.clone();
                                 let prompt_for_thread = prompt.clone();
                                 let context_ptr_usize = context_ptr as usize;
@@ -1537,6 +1896,13 @@ pub async fn start_worker_tasks() -> Result<()> {
                                     }
 
                                     let _lock = GLOBAL_INFERENCE_MUTEX.lock().unwrap();
+                                    if reject_task_past_deadline(
+                                        &writer_stream,
+                                        &task_id_for_thread,
+                                        deadline,
+                                    ) {
+                                        return;
+                                    }
                                     let prompt_cstr = match CString::new(prompt_for_thread) {
                                         Ok(s) => s,
                                         Err(e) => {
@@ -1663,6 +2029,14 @@ pub async fn start_worker_tasks() -> Result<()> {
                                     }
                                 }
                             }
+                            CommandV1::Challenge {
+                                challenge_id, task, ..
+                            } => {
+                                if let Err(e) = answer_challenge(&mut *stream, challenge_id, &task)
+                                {
+                                    eprintln!("❌ Android: Failed to answer challenge: {}", e);
+                                }
+                            }
                             _ => {
                                 println!("⚠️ Android: Received unhandled command type");
                             }
@@ -1768,6 +2142,9 @@ pub async fn start_worker_tasks_with_callback_ptr(
     let device_info_for_heartbeat = devices_info.clone();
     let device_info_for_handler = devices_info.clone();
 
+    spawn_power_monitor(tcp_stream.clone(), stop_signal.clone());
+    spawn_idle_monitor(tcp_stream.clone(), stop_signal.clone());
+
     // Spawn heartbeat task using native thread with full heartbeat logic
     let heartbeat_stream = tcp_stream.clone();
     let heartbeat_callback = callback;
@@ -1782,7 +2159,10 @@ pub async fn start_worker_tasks_with_callback_ptr(
                 break;
             }
 
-            println!("🔧 Android: Heartbeat loop - sleeping for 120 seconds...");
+            println!(
+                "🔧 Android: Heartbeat loop - sleeping for {} seconds...",
+                heartbeat_interval_secs()
+            );
 
             println!("💓 Android: Woke up - collecting system info for heartbeat...");
 
@@ -1861,7 +2241,7 @@ pub async fn start_worker_tasks_with_callback_ptr(
                 .get()
                 .and_then(|m| m.lock().ok().and_then(|g| *g))
                 .unwrap_or([0u8; 16]);
-            let heartbeat_cmd = CommandV1::Heartbeat {
+            let heartbeat_cmd = CommandV1::HeartbeatV2 {
                 client_id,
                 system_info: SystemInfo {
                     cpu_usage: cpu_usage as u8,
@@ -1874,6 +2254,10 @@ pub async fn start_worker_tasks_with_callback_ptr(
                 device_total_tflops: device_info.total_tflops.into(),
                 device_count: device_info.num as u16,
                 devices_info: vec![device_info],
+                gpu_metrics: Vec::new(), // NVML is not available on Android
+                network_quality: None,
+                power: power::current_status(),
+                version: Some(crate::util::version_info::worker_version(None)),
             };
 
             // Send heartbeat using common library function
@@ -1934,10 +2318,12 @@ pub async fn start_worker_tasks_with_callback_ptr(
             drop(stream);
             println!("🔧 Android: Heartbeat connection closed, starting next iteration...");
 
-            // Sleep with periodic stop signal checks
-            for _ in 0..120 {
-                // 120 seconds / 1 second intervals
+            // Sleep with periodic stop signal checks. The interval is re-read every
+            // second so coming back to the foreground cuts a background wait short.
+            let mut slept_secs = 0;
+            while slept_secs < heartbeat_interval_secs() {
                 thread::sleep(Duration::from_secs(1));
+                slept_secs += 1;
                 if heartbeat_stop_signal.load(Ordering::Relaxed) {
                     println!("🔧 Android: Heartbeat thread received stop signal during sleep");
                     break;
@@ -2024,7 +2410,7 @@ pub async fn start_worker_tasks_with_callback_ptr(
                     match command {
                         Command::V1(cmd_v1) => {
                             match cmd_v1 {
-                                CommandV1::LoginResult {
+                                CommandV1::LoginResultV2 {
                                     success,
                                     pods_model,
                                     error,
@@ -2101,7 +2487,7 @@ pub async fn start_worker_tasks_with_callback_ptr(
                                         break;
                                     }
                                 }
-                                CommandV1::PullModelResult { pods_model, error } => {
+                                CommandV1::PullModelResultV2 { pods_model, error } => {
                                     if let Some(err) = error {
                                         eprintln!("❌ Android: Pull model failed: {}", err);
                                         if let Some(callback_fn) = handler_callback {
@@ -2146,8 +2532,10 @@ pub async fn start_worker_tasks_with_callback_ptr(
                                         }
                                     }
                                 }
-                                CommandV1::InferenceTask {
+                                CommandV1::InferenceTaskV2 {
                                     task_id,
+                                    request_id: _,
+                                    traceparent: _,
                                     prompt,
                                     max_tokens,
                                     temperature,
@@ -2156,8 +2544,22 @@ pub async fn start_worker_tasks_with_callback_ptr(
                                     repeat_penalty,
                                     repeat_last_n: _,
                                     min_keep: _,
+                                    overflow: _,
+                                    deadline_ms,
                                 } => {
                                     println!("🔧 Android: Received inference task: {}", task_id);
+                                    if reject_task_if_paused(&mut stream, &task_id) {
+                                        continue;
+                                    }
+                                    if reject_task_over_limits(
+                                        &mut stream,
+                                        &task_id,
+                                        prompt.len(),
+                                        max_tokens,
+                                    ) {
+                                        continue;
+                                    }
+                                    reload_idle_model(&mut stream);
                                     println!("📝 Android: Prompt: {}", prompt);
                                     println!("⚙️ Android: Parameters: max_tokens={}, temp={}, top_k={}, top_p={}", 
                                                              max_tokens, temperature, top_k, top_p);
@@ -2240,6 +2642,7 @@ pub async fn start_worker_tasks_with_callback_ptr(
                                         }
                                     };
 
+                                    let deadline = task_deadline(deadline_ms);
                                     let task_id_for_thread = task_id.clone();
                                     let prompt_for_thread = prompt.clone();
                                     let context_ptr_usize = context_ptr as usize;
@@ -2369,6 +2772,13 @@ pub async fn start_worker_tasks_with_callback_ptr(
                                             );
                                         }
  let _lock = GLOBAL_INFERENCE_MUTEX.lock().unwrap();
+                                        if reject_task_past_deadline(
+                                            &writer_stream,
+                                            &task_id_for_thread,
+                                            deadline,
+                                        ) {
+                                            return;
+                                        }
                                         let start_time = std::time::Instant::now();
                                         let prompt_cstr = match CString::new(prompt_for_thread) {
                                             Ok(s) => s,
@@ -2508,8 +2918,10 @@ pub async fn start_worker_tasks_with_callback_ptr(
                                     });
                                 }
 
-                                CommandV1::ChatInferenceTask {
+                                CommandV1::ChatInferenceTaskV2 {
                                     task_id,
+                                    request_id: _,
+                                    traceparent: _,
                                     model: _,
                                     messages,
                                     max_tokens,
@@ -2519,11 +2931,28 @@ pub async fn start_worker_tasks_with_callback_ptr(
                                     repeat_penalty,
                                     repeat_last_n: _,
                                     min_keep: _,
+                                    prefix: _,
+                                    overflow: _,
+                                    deadline_ms,
                                 } => {
                                     println!(
                                         "🔧 Android: Received chat inference task: {}",
                                         task_id
                                     );
+                                    if reject_task_if_paused(&mut stream, &task_id) {
+                                        continue;
+                                    }
+                                    let prompt_bytes =
+                                        messages.iter().map(|m| m.content.len()).sum();
+                                    if reject_task_over_limits(
+                                        &mut stream,
+                                        &task_id,
+                                        prompt_bytes,
+                                        max_tokens,
+                                    ) {
+                                        continue;
+                                    }
+                                    reload_idle_model(&mut stream);
 
                                     invoke_callback(
                                         "INFERENCE_START",
@@ -2608,6 +3037,7 @@ pub async fn start_worker_tasks_with_callback_ptr(
                                         }
                                     };
 
+                                    let deadline = task_deadline(deadline_ms);
                                     let task_id_for_thread = task_id.clone();
                                     let prompt_for_thread = prompt.clone();
                                     let context_ptr_usize = context_ptr as usize;
@@ -2739,6 +3169,13 @@ pub async fn start_worker_tasks_with_callback_ptr(
                                         }
 
                                         let _lock = GLOBAL_INFERENCE_MUTEX.lock().unwrap();
+                                        if reject_task_past_deadline(
+                                            &writer_stream,
+                                            &task_id_for_thread,
+                                            deadline,
+                                        ) {
+                                            return;
+                                        }
                                         let start_time = std::time::Instant::now();
                                         let prompt_cstr = match CString::new(prompt_for_thread) {
                                             Ok(s) => s,
@@ -2889,6 +3326,16 @@ pub async fn start_worker_tasks_with_callback_ptr(
                                         }
                                     }
                                 }
+                                CommandV1::Challenge {
+                                    challenge_id, task, ..
+                                } => {
+                                    if let Err(e) =
+                                        answer_challenge(&mut *stream, challenge_id, &task)
+                                    {
+                                        eprintln!("❌ Android: Failed to answer challenge: {}", e);
+                                        invoke_callback("ERROR", "Failed to answer challenge");
+                                    }
+                                }
                                 _ => {
                                     println!("⚠️ Android: Received unhandled command type");
                                     invoke_callback("WARNING", "Received unhandled command type");
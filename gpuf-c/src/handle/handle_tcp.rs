@@ -2,16 +2,37 @@ use super::*;
 // LLM engine is not available in lightweight Android version
 #[cfg(not(target_os = "android"))]
 use crate::llm_engine::{self, llama_engine::LlamaEngine};
+use crate::llm_engine::image_engine::{self, ImageBackend, ImageService};
+use crate::llm_engine::{
+    ollama_engine::PullProgress, ExternalEngine, InferenceInput, InferenceRequest, OllamaEngine,
+};
 use crate::util::system_info::{
-    collect_device_info, collect_system_info, get_engine_models, pull_ollama_model,
+    collect_device_info, collect_gpu_metrics, collect_system_info, get_engine_models,
 };
+use super::jobs::JobRegistry;
+use crate::util::benchmark;
+use crate::util::download_manager::{DownloadEvent, DownloadJob, DownloadManager};
+use crate::util::download_policy;
+use crate::util::model_cache::{self, ModelCache};
+use crate::util::model_delta;
+use crate::util::model_downloader::{DownloadConfig, ModelDownloader};
+use crate::util::model_fit;
+use crate::util::net;
+use crate::util::network_info;
+use crate::util::proxy;
 use crate::util::log_icon;
+use crate::util::runtime_config::RuntimeConfig;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+use crate::util::updater;
+use crate::util::version_info;
 use anyhow::{anyhow, Result};
+use common::trace::{self, TraceParent};
 use common::{
     format_bytes, format_duration, join_streams, read_command, write_command, Command, CommandV1,
-    CommandV2, DownloadStatus, EngineType as ClientEngineType, Model, OsType, OutputPhase,
-    P2PCandidate, P2PCandidateType, P2PConnectionType, P2PTransport, PodModel, SystemInfo,
-    MAX_MESSAGE_SIZE,
+    CommandV2, DownloadStatus, EngineType as ClientEngineType, JobKind, JobOutput, JobSpec,
+    JobStatus, Model, ModelBenchmark, NetworkQuality, OsType, OutputPhase, P2PCandidate,
+    P2PCandidateType, P2PConnectionType, P2PTransport, PodModelV2, SystemInfo, DEADLINE_EXCEEDED,
+    MAX_MESSAGE_SIZE, PROTOCOL_VERSION,
 };
 use tokio::io::AsyncWriteExt;
 
@@ -22,24 +43,49 @@ use std::collections::HashMap;
 use std::collections::{HashSet, VecDeque};
 use std::fs::File;
 use std::io::BufReader;
-use std::net::ToSocketAddrs;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use tokio::net::TcpStream;
 use tokio::net::UdpSocket;
-use tokio::time::interval;
 use tokio::time::timeout;
 
 // Global flag to track if HTTP server is already running
 #[cfg(not(target_os = "android"))]
 static HTTP_SERVER_STARTED: AtomicBool = AtomicBool::new(false);
 
+// Session whose engine supervisor runs; older sessions' supervisors stop
+#[cfg(not(target_os = "android"))]
+static SUPERVISOR_SESSION: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
 // Global engine cache - initialized once on startup, reused on reconnection
 #[cfg(not(target_os = "android"))]
 use std::sync::OnceLock;
 #[cfg(not(target_os = "android"))]
 static GLOBAL_ENGINE: OnceLock<Arc<Mutex<Option<AnyEngine>>>> = OnceLock::new();
+// Copy of the llama.cpp engine serving the local HTTP API
+#[cfg(not(target_os = "android"))]
+static LLAMA_SERVER_ENGINE: OnceLock<Arc<tokio::sync::RwLock<LlamaEngine>>> = OnceLock::new();
+
+// Session whose idle unload task runs; older sessions' tasks stop
+#[cfg(not(target_os = "android"))]
+static IDLE_UNLOAD_SESSION: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+/// Set while the model is unloaded for sitting idle, until a request loads it again
+#[cfg(not(target_os = "android"))]
+static IDLE_UNLOADED: AtomicBool = AtomicBool::new(false);
+/// How often the idle unload task looks for an unused model
+#[cfg(not(target_os = "android"))]
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+// Model download queue - outlives reconnections like the engine cache
+static DOWNLOADS: std::sync::OnceLock<DownloadManager> = std::sync::OnceLock::new();
+/// How often a running model download reports its progress to the server
+const DOWNLOAD_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+/// Offered models that did not fit into free memory, so the server skips them
+static UNFIT_MODELS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+/// Models being pulled into Ollama, so a repeated offer does not pull twice
+static OLLAMA_PULLS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
 
 #[cfg(not(target_os = "android"))]
 use tokio_rustls::{
@@ -49,10 +95,52 @@ use tokio_rustls::{
     },
     TlsConnector,
 };
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, warn, Instrument};
 use url::Url;
 
 const DEFAULT_TURNS_PORT: u16 = 5349;
+/// How often heartbeats carry a fresh network probe
+const NETWORK_PROBE_INTERVAL: Duration = Duration::from_secs(600);
+const NETWORK_PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Span of one inference task, joined to the trace started by gpuf-s
+fn task_span(
+    task_id: &str,
+    request_id: Option<&str>,
+    traceparent: Option<&str>,
+) -> tracing::Span {
+    let parent = TraceParent::from_header_or_root(traceparent);
+    let span = tracing::info_span!(
+        "inference_task",
+        task_id = %task_id,
+        request_id = request_id.unwrap_or_default(),
+        trace_id = %parent.trace_id_hex(),
+    );
+    trace::set_span_parent(&span, &parent);
+    span
+}
+
+/// Delay of a migration within its window, picked by client id so the
+/// fleet reaches the new server spread over the window rather than at once
+fn migration_delay(client_id: [u8; 16], window_ms: u32) -> Duration {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&client_id[..8]);
+    Duration::from_millis(u64::from_le_bytes(bytes) % (window_ms as u64 + 1))
+}
+
+/// When the server stops waiting for a task it sent with `deadline_ms`
+fn task_deadline(deadline_ms: Option<u64>) -> Option<tokio::time::Instant> {
+    deadline_ms.map(|ms| tokio::time::Instant::now() + Duration::from_millis(ms))
+}
+
+/// Resolves at `deadline`, never without one
+#[cfg(not(target_os = "android"))]
+async fn until_deadline(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
 
 // Filter internal GGUF control tokens from streaming output
 fn filter_control_tokens(text: &str) -> String {
@@ -307,41 +395,15 @@ impl PhaseSplitter {
     }
 }
 
-fn derive_model_id_from_path(model_path: &str) -> String {
-    let lower = model_path.to_ascii_lowercase();
-    if lower.contains("llama-3") || lower.contains("llama3") {
-        return "llama3".to_string();
-    }
-
-    let file_name = std::path::Path::new(model_path)
-        .file_name()
-        .and_then(|s| s.to_str())
-        .unwrap_or(model_path);
-
-    let base = file_name
-        .trim_end_matches(".gguf")
-        .trim_end_matches(".bin");
-
-    // Rule B: keep file name, but strip shard suffix like "-00001-of-00002".
-    // This avoids exposing split file indices as the model id.
-    if let Some(of_pos) = base.rfind("-of-") {
-        let right = &base[of_pos + 4..];
-        if !right.is_empty() && right.chars().all(|c| c.is_ascii_digit()) {
-            let left_all = &base[..of_pos];
-            if let Some(left_dash) = left_all.rfind('-') {
-                let left_digits = &left_all[left_dash + 1..];
-                if !left_digits.is_empty() && left_digits.chars().all(|c| c.is_ascii_digit()) {
-                    return left_all[..left_dash].to_string();
-                }
-            }
-        }
-    }
-
-    base.to_string()
+/// What an inference task needs of its worker to run next to the read loop
+struct InferenceTaskContext {
+    #[cfg(not(target_os = "android"))]
+    engine: Arc<Mutex<Option<AnyEngine>>>,
+    writer: Arc<Mutex<WriteHalf<TcpStream>>>,
+    cancel_state: Arc<CancelState>,
+    stream_chunk_bytes: usize,
 }
 
-const CURRENT_VERSION: u32 = 1;
-
 impl ClientWorker {
     /// Execute inference task using local LLM engine (Android specific)
 
@@ -440,71 +502,86 @@ impl ClientWorker {
         }
     }
 
-    async fn stream_inference_task_to_server(
+    /// Run `request` as task `task_id` without holding up the read loop, so
+    /// engines with parallel slots decode several tasks at once. Tasks past
+    /// `max_concurrent_requests` wait for a running one to finish, and fail
+    /// when their deadline passes first.
+    fn spawn_inference_task(
         &self,
         task_id: String,
-        prompt: String,
-        max_tokens: u32,
-        temperature: f32,
-        top_k: u32,
-        top_p: f32,
-        repeat_penalty: f32,
-        repeat_last_n: i32,
-        min_keep: u32,
+        span: tracing::Span,
+        request: InferenceRequest,
+        deadline: Option<tokio::time::Instant>,
+    ) {
+        let ctx = InferenceTaskContext {
+            #[cfg(not(target_os = "android"))]
+            engine: Arc::clone(&self.engine),
+            writer: Arc::clone(&self.writer),
+            cancel_state: Arc::clone(&self.cancel_state),
+            stream_chunk_bytes: self.args.stream_chunk_bytes,
+        };
+        tokio::spawn(async move {
+            // Held until the task is finished
+            let _slot = RuntimeConfig::global().acquire_request_slot().await;
+            let result =
+                Self::stream_inference_task_to_server(&ctx, task_id.clone(), request, deadline)
+                    .instrument(span)
+                    .await;
+            if let Err(e) = result {
+                let chunk = CommandV1::InferenceResultChunk {
+                    task_id: task_id.clone(),
+                    seq: 0,
+                    delta: String::new(),
+                    phase: OutputPhase::Unknown,
+                    done: true,
+                    completion_tokens: 0,
+                    prompt_tokens: 0,
+                    error: Some(e.to_string()),
+                    analysis_tokens: 0,
+                    final_tokens: 0,
+                };
+                if let Err(e) = Self::send_command_on_writer(Arc::clone(&ctx.writer), chunk).await {
+                    error!("Failed to report inference task {}: {}", task_id, e);
+                }
+            }
+        });
+    }
+
+    /// Stream the output of `request` from whichever engine this worker
+    /// runs to the server, as inference result chunks of `task_id`. Stops
+    /// generating once `deadline` passes.
+    async fn stream_inference_task_to_server(
+        ctx: &InferenceTaskContext,
+        task_id: String,
+        request: InferenceRequest,
+        deadline: Option<tokio::time::Instant>,
     ) -> Result<()> {
         #[cfg(not(target_os = "android"))]
         {
-            let engine_guard = self.engine.lock().await;
-            let engine = engine_guard
-                .as_ref()
-                .ok_or_else(|| anyhow!("Engine not initialized"))?;
+            use crate::llm_engine::InferenceEvent;
 
-            let AnyEngine::Llama(llama) = engine else {
+            if deadline.is_some_and(|deadline| deadline <= tokio::time::Instant::now()) {
                 return Err(anyhow!(
-                    "stream_inference_task_to_server is only supported for LLAMA engine"
+                    "{}: task waited past its deadline for a free slot",
+                    DEADLINE_EXCEEDED
                 ));
-            };
-
-            let sampling = crate::llm_engine::llama_engine::SamplingParams {
-                temperature,
-                top_k: top_k as i32,
-                top_p,
-                repeat_penalty,
-                repeat_last_n,
-                seed: 0,
-                min_keep: min_keep as usize,
-            };
-
-            let prompt_tokens: u32 = {
-                let prompt = prompt.clone();
-                let cached_model = llama
-                    .cached_model
-                    .as_ref()
-                    .ok_or_else(|| anyhow!("Model not loaded - call load_model() first"))?
-                    .clone();
-
-                tokio::task::spawn_blocking(move || {
-                    use llama_cpp_2::model::AddBos;
-
-                    let model_guard = cached_model
-                        .lock()
-                        .map_err(|e| anyhow!("Failed to lock model: {:?}", e))?;
-
-                    let tokens = model_guard
-                        .str_to_token(&prompt, AddBos::Always)
-                        .map_err(|e| anyhow!("Failed to tokenize prompt: {:?}", e))?;
-                    Ok::<u32, anyhow::Error>(tokens.len().min(u32::MAX as usize) as u32)
-                })
-                .await??
-            };
-
-            let stream = llama
-                .stream_with_cached_model_sampling(&prompt, max_tokens as usize, &sampling)
-                .await?;
+            }
+            // Rejected before an idle model is loaded for it
+            crate::util::request_limits::limits().check_request(&request)?;
+            reload_idle_model(&ctx.engine).await;
+            // Cloned out so the engine is not locked while the task streams
+            let engine = ctx
+                .engine
+                .lock()
+                .await
+                .clone()
+                .ok_or_else(|| anyhow!("Engine not initialized"))?;
 
-            let mut stream = Box::pin(stream);
+            let mut stream = engine.infer(request).await?;
+            let mut prompt_tokens: u32 = 0;
+            let mut reported_completion_tokens: u32 = 0;
 
-            let max_bytes: usize = self.args.stream_chunk_bytes.max(1);
+            let max_bytes: usize = ctx.stream_chunk_bytes.max(1);
             let mut seq: u32 = 0;
             let mut buf = String::new();
             let mut buf_phase: OutputPhase = OutputPhase::Unknown;
@@ -516,7 +593,7 @@ impl ClientWorker {
             let mut cancelled_early = false;
             loop {
                 {
-                    let cancelled = self.cancel_state.cancelled.lock().await;
+                    let cancelled = ctx.cancel_state.cancelled.lock().await;
                     if cancelled.contains(&task_id) {
                         cancelled_early = true;
                         debug!(task_id = %task_id, "Cancellation observed in stream loop");
@@ -525,19 +602,49 @@ impl ClientWorker {
                 }
 
                 tokio::select! {
-                    _ = self.cancel_state.notify.notified() => {
-                        let cancelled = self.cancel_state.cancelled.lock().await;
+                    _ = ctx.cancel_state.notify.notified() => {
+                        let cancelled = ctx.cancel_state.cancelled.lock().await;
                         if cancelled.contains(&task_id) {
                             cancelled_early = true;
                             debug!(task_id = %task_id, "Cancellation notified during streaming");
                             break;
                         }
                     }
-                    piece_res = stream.next() => {
-                        let Some(piece_res) = piece_res else {
+                    _ = until_deadline(deadline) => {
+                        // Dropping the stream stops the engine
+                        return Err(anyhow!(
+                            "{}: deadline passed after {} tokens",
+                            DEADLINE_EXCEEDED,
+                            completion_tokens
+                        ));
+                    }
+                    event = stream.next() => {
+                        let Some(event) = event else {
                             break;
                         };
-                        let piece = piece_res?;
+                        let piece = match event? {
+                            InferenceEvent::Token(piece) => piece,
+                            InferenceEvent::Usage {
+                                prompt_tokens: prompt,
+                                completion_tokens: completion,
+                            } => {
+                                prompt_tokens = prompt;
+                                reported_completion_tokens = completion;
+                                continue;
+                            }
+                            InferenceEvent::ContextOverflow {
+                                strategy,
+                                dropped_tokens,
+                            } => {
+                                let applied = CommandV1::ContextOverflowApplied {
+                                    task_id: task_id.clone(),
+                                    strategy,
+                                    dropped_tokens,
+                                };
+                                Self::send_command_on_writer(Arc::clone(&ctx.writer), applied).await?;
+                                continue;
+                            }
+                        };
                         let filtered = filter_control_tokens(&piece);
                         // Each streamed `piece` corresponds to (at most) one generated token.
                         // Never count bytes/chars here, otherwise completion_tokens can greatly exceed max_tokens.
@@ -574,7 +681,7 @@ impl ClientWorker {
                                     analysis_tokens,
                                     final_tokens,
                                 };
-                                self.send_command(chunk).await?;
+                                Self::send_command_on_writer(Arc::clone(&ctx.writer), chunk).await?;
                                 seq = seq.wrapping_add(1);
                                 buf_phase = phase;
                             }
@@ -594,7 +701,7 @@ impl ClientWorker {
                                     analysis_tokens,
                                     final_tokens,
                                 };
-                                self.send_command(chunk).await?;
+                                Self::send_command_on_writer(Arc::clone(&ctx.writer), chunk).await?;
                                 seq = seq.wrapping_add(1);
                             }
                         }
@@ -602,6 +709,11 @@ impl ClientWorker {
                 }
             }
 
+            // Engines that report usage count tokens better than pieces do
+            if reported_completion_tokens > 0 {
+                completion_tokens = reported_completion_tokens;
+            }
+
             if !buf.is_empty() {
                 let chunk = CommandV1::InferenceResultChunk {
                     task_id: task_id.clone(),
@@ -615,7 +727,7 @@ impl ClientWorker {
                     analysis_tokens,
                     final_tokens,
                 };
-                self.send_command(chunk).await?;
+                Self::send_command_on_writer(Arc::clone(&ctx.writer), chunk).await?;
                 seq = seq.wrapping_add(1);
             }
 
@@ -631,14 +743,14 @@ impl ClientWorker {
                 analysis_tokens,
                 final_tokens,
             };
-            self.send_command(done_chunk).await?;
+            Self::send_command_on_writer(Arc::clone(&ctx.writer), done_chunk).await?;
 
             if cancelled_early {
                 debug!(task_id = %task_id, "Sent done chunk after cancellation");
             }
 
             {
-                let mut cancelled = self.cancel_state.cancelled.lock().await;
+                let mut cancelled = ctx.cancel_state.cancelled.lock().await;
                 cancelled.remove(&task_id);
             }
             return Ok(());
@@ -646,67 +758,57 @@ impl ClientWorker {
 
         #[cfg(target_os = "android")]
         {
-            let _ = (
-                task_id,
-                prompt,
-                max_tokens,
-                temperature,
-                top_k,
-                top_p,
-                repeat_penalty,
-                repeat_last_n,
-                min_keep,
-            );
+            let _ = (ctx, task_id, request, deadline);
             Err(anyhow!("Android streaming is not implemented"))
         }
     }
 
-    fn build_chat_prompt_fallback(&self, messages: &[common::ChatMessage]) -> String {
-        let template = std::env::var("CHAT_TEMPLATE").unwrap_or_else(|_| "simple".to_string());
-        match template.to_ascii_lowercase().as_str() {
-            "chatml" => {
-                let mut prompt = String::new();
-                for msg in messages {
-                    prompt.push_str(&format!("{}\n{}\n", msg.role, msg.content));
-                }
-                prompt.push_str("\nassistant\n");
-                prompt
-            }
-            "llama3" => {
-                let mut prompt = String::from("<|begin_of_text|>");
-                for msg in messages {
-                    prompt.push_str(&format!(
-                        "<|start_header_id|>{}\n\n{}\n<|eot_id|>",
-                        msg.role, msg.content
+    /// Send command to server
+    async fn send_command(&self, command: CommandV1) -> Result<()> {
+        use common::{write_command, Command};
+
+        let command = Command::V1(command);
+        let mut writer = self.writer.lock().await;
+        write_command(&mut *writer, &command).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Log in and send `benchmark` from `gpuf-c bench`, which the server
+    /// keeps with this client
+    pub async fn upload_benchmark(&self, benchmark: ModelBenchmark) -> Result<()> {
+        self.login().await?;
+        let mut buf = BytesMut::with_capacity(MAX_MESSAGE_SIZE);
+        loop {
+            match read_command(&mut *self.reader.lock().await, &mut buf).await? {
+                Command::V1(CommandV1::LoginResultV2 { success: true, .. }) => break,
+                Command::V1(CommandV1::LoginResultV2 { error, .. }) => {
+                    return Err(anyhow!(
+                        "Login failed: {}",
+                        error.as_deref().unwrap_or("unknown error")
                     ));
                 }
-                prompt.push_str("<|start_header_id|>assistant\n\n");
-                prompt
-            }
-            _ => {
-                let mut prompt = String::new();
-                for msg in messages {
-                    let role = match msg.role.as_str() {
-                        "user" => "Human",
-                        "assistant" => "Assistant",
-                        _ => "System",
-                    };
-                    prompt.push_str(&format!("{}: {}\n\n", role, msg.content));
-                }
-                prompt.push_str("Assistant: ");
-                prompt
+                // Sent along with the login, e.g. a release to update to
+                _ => continue,
             }
         }
+        self.send_command(CommandV1::ModelBenchmarkReport {
+            client_id: self.client_id,
+            benchmark,
+        })
+        .await
     }
 
-    /// Send command to server
-    async fn send_command(&self, command: CommandV1) -> Result<()> {
+    async fn send_command_on_writer(
+        writer: Arc<Mutex<WriteHalf<TcpStream>>>,
+        command: CommandV1,
+    ) -> Result<()> {
         use common::{write_command, Command};
 
         let command = Command::V1(command);
-        let mut writer = self.writer.lock().await;
-        write_command(&mut *writer, &command).await?;
-        writer.flush().await?;
+        let mut w = writer.lock().await;
+        write_command(&mut *w, &command).await?;
+        w.flush().await?;
         Ok(())
     }
 
@@ -1238,7 +1340,7 @@ impl ClientWorker {
             }
         }
     }
-    pub async fn new(args: Args) -> Result<ClientWorker> {
+    pub async fn new(mut args: Args) -> Result<ClientWorker> {
         let (device_info, device_memtotal_mb) = match collect_device_info(args.engine_type.to_common()).await {
             Ok(info) => info,
             Err(e) => {
@@ -1253,6 +1355,7 @@ impl ClientWorker {
         }
 
         info!("Debug: Engine type from args: {:?}", args.engine_type);
+        RuntimeConfig::global().set_default_idle_unload_secs(args.idle_unload_secs);
 
         let os_type = if cfg!(target_os = "macos") {
             OsType::MACOS
@@ -1271,24 +1374,106 @@ impl ClientWorker {
             EngineType::VLLM => ClientEngineType::Vllm,
             EngineType::OLLAMA => ClientEngineType::Ollama,
             EngineType::LLAMA => ClientEngineType::Llama,
+            EngineType::EXTERNAL => ClientEngineType::External,
+            EngineType::TRTLLM => ClientEngineType::TensorRT,
+            EngineType::SD => ClientEngineType::StableDiffusion,
         };
+        #[cfg(target_os = "macos")]
+        if args.engine_type == EngineType::LLAMA {
+            if !cfg!(feature = "metal") && args.n_gpu_layers > 0 {
+                warn!("Built without the `metal` feature, n_gpu_layers is ignored and layers run on the CPU");
+            } else {
+                info!(
+                    "Offloading up to {} layers to Metal, {} GB GPU memory",
+                    args.n_gpu_layers, device_info.memtotal_gb
+                );
+            }
+        }
         #[cfg(not(target_os = "android"))]
         let mut engine: Option<AnyEngine> = None;
         #[cfg(target_os = "android")]
         let mut engine: Option<()> = None;
+        // The external engine's server takes the place of the local service
+        // proxied connections are forwarded to
+        if args.engine_type == EngineType::EXTERNAL {
+            let url = args
+                .external_url
+                .as_deref()
+                .ok_or_else(|| anyhow!("--external-url is required for the external engine"))?;
+            let external = ExternalEngine::new(url)?;
+            (args.local_addr, args.local_port) = external.proxy_target();
+            // The provider may start its server after the worker
+            match external.list_models().await {
+                Ok(models) => info!(
+                    "Proxying to external engine at {}:{}, serving {} models",
+                    args.local_addr,
+                    args.local_port,
+                    models.len()
+                ),
+                Err(e) => warn!("External engine at {} is not reachable yet: {}", url, e),
+            }
+            #[cfg(not(target_os = "android"))]
+            {
+                engine = Some(AnyEngine::External(external));
+            }
+        }
+        // Images are generated behind a local server proxied connections
+        // are forwarded to, as with the llama engine
+        if args.engine_type == EngineType::SD {
+            let backend = ImageBackend::from_args(
+                &args.sd_binary,
+                args.sd_model.as_deref(),
+                args.sd_server_url.as_deref(),
+            )?;
+            let client_id = args
+                .client_id
+                .ok_or_else(|| anyhow!("client_id is required"))?;
+            let service = Arc::new(ImageService::new(
+                backend,
+                args.sd_model_name.clone(),
+                client_id,
+            ));
+            let local_addr = args.local_addr.clone();
+            let local_port = args.local_port;
+            if !HTTP_SERVER_STARTED.swap(true, Ordering::SeqCst) {
+                tokio::spawn(async move {
+                    if let Err(e) =
+                        image_engine::start_server(service, &local_addr, local_port).await
+                    {
+                        error!("Image generation server error: {}", e);
+                        HTTP_SERVER_STARTED.store(false, Ordering::SeqCst);
+                    }
+                });
+            } else {
+                info!(
+                    "Image generation server already running on {}:{}",
+                    local_addr, local_port
+                );
+            }
+        }
         #[cfg(all(not(target_os = "macos"), not(target_os = "android")))]
         {
             if args.engine_type == EngineType::VLLM {
-                let mut llvm_worker = llm_engine::create_engine(
-                    args.engine_type.clone(),
-                    args.hugging_face_hub_token.clone(),
-                    args.chat_template_path.clone(),
-                );
+                let placement = llm_engine::vllm_engine::VllmPlacement::plan(
+                    &device_info,
+                    args.vllm_gpus.as_deref(),
+                    args.vllm_tensor_parallel_size,
+                    args.vllm_max_model_len,
+                )?;
+                info!("VLLM placement: {:?}", placement);
+                let mut llvm_worker = llm_engine::create_engine(&args, placement)?;
                 match llvm_worker.init().await {
                     Ok(_) => info!("VLLM init success"),
                     Err(e) => error!("VLLM init failed: {}", e),
                 }
                 engine = Some(llvm_worker);
+            } else if args.engine_type == EngineType::TRTLLM {
+                let mut trtllm_worker = llm_engine::create_engine(&args, Default::default())?;
+                match trtllm_worker.init().await {
+                    Ok(_) => info!("TensorRT-LLM init success"),
+                    Err(e) => error!("TensorRT-LLM init failed: {}", e),
+                }
+                engine = Some(trtllm_worker);
             } else if args.engine_type == EngineType::LLAMA {
                 // Check if engine is already initialized in global cache
                 let global_engine_cache = GLOBAL_ENGINE.get_or_init(|| {
@@ -1318,27 +1503,45 @@ impl ClientWorker {
                     // First time initialization - create and init engine
                     info!("First time initialization - creating and loading LLAMA engine");
                     
-                    let mut llama_worker = if let Some(model_path) = &args.llama_model_path {
+                    // The model loaded last is likely the one the server
+                    // assigns again, and is warm by the time it does
+                    let startup_model = args.llama_model_path.clone().or_else(|| {
+                        if !args.preload_model {
+                            return None;
+                        }
+                        let path = open_model_cache(&args).last_loaded()?;
+                        info!("Preloading {}, the model loaded last", path.display());
+                        Some(path.to_string_lossy().to_string())
+                    });
+                    let mut llama_worker = if let Some(model_path) = &startup_model {
                         // Use provided model path
                         info!("Creating LLAMA engine with model: {}", model_path);
-                        llm_engine::AnyEngine::Llama(LlamaEngine::with_config(
-                            model_path.clone(),
-                            args.n_ctx,
-                            args.n_gpu_layers,
-                            args.llama_split_mode.clone(),
-                            args.llama_main_gpu,
-                            args.llama_devices.clone(),
-                        ))
+                        llm_engine::AnyEngine::Llama(
+                            LlamaEngine::with_config(
+                                model_path.clone(),
+                                args.n_ctx,
+                                args.n_gpu_layers,
+                                args.llama_split_mode.clone(),
+                                args.llama_main_gpu,
+                                args.llama_devices.clone(),
+                            )
+                            .with_draft_model(args.draft_model_path.clone(), args.draft_tokens)
+                            .with_parallel_slots(args.parallel_slots),
+                        )
                     } else {
                         // Create engine without model (will be set later)
                         info!("Creating LLAMA engine without model (will be set later)");
-                        llm_engine::AnyEngine::Llama(LlamaEngine::with_runtime_config(
-                            args.n_ctx,
-                            args.n_gpu_layers,
-                            args.llama_split_mode.clone(),
-                            args.llama_main_gpu,
-                            args.llama_devices.clone(),
-                        ))
+                        llm_engine::AnyEngine::Llama(
+                            LlamaEngine::with_runtime_config(
+                                args.n_ctx,
+                                args.n_gpu_layers,
+                                args.llama_split_mode.clone(),
+                                args.llama_main_gpu,
+                                args.llama_devices.clone(),
+                            )
+                            .with_draft_model(args.draft_model_path.clone(), args.draft_tokens)
+                            .with_parallel_slots(args.parallel_slots),
+                        )
                     };
 
                     // Initialize the engine (only on first startup)
@@ -1347,7 +1550,7 @@ impl ClientWorker {
                             info!("LLAMA engine init success - model loaded into memory");
 
                             // Update MODEL_STATUS with the local model path
-                            if let Some(ref model_path) = args.llama_model_path {
+                            if let Some(ref model_path) = startup_model {
                                 if let Ok(mut status) = crate::MODEL_STATUS.lock() {
                                     status.current_model = Some(model_path.clone());
                                     status.is_loaded = true;
@@ -1397,6 +1600,7 @@ impl ClientWorker {
                     
                     // Wrap the shared engine in Arc<RwLock> for HTTP server
                     let engine_arc = Arc::new(RwLock::new(server_engine));
+                    let _ = LLAMA_SERVER_ENGINE.set(Arc::clone(&engine_arc));
 
                     // Spawn server in background
                     tokio::spawn(async move {
@@ -1422,7 +1626,7 @@ impl ClientWorker {
         #[cfg(target_os = "macos")]
         {
             if args.engine_type == EngineType::OLLAMA {
-                if let Err(e) = check_and_restart_ollama().await {
+                if let Err(e) = check_and_restart_ollama(&args.ollama_keep_alive).await {
                     error!("Failed to manage Ollama process: {}", e);
                     // Decide whether to return error or continue without Ollama
                 }
@@ -1455,27 +1659,45 @@ impl ClientWorker {
                     // First time initialization - create and init engine
                     info!("First time initialization - creating and loading LLAMA engine");
                     
-                    let mut llama_worker = if let Some(model_path) = &args.llama_model_path {
+                    // The model loaded last is likely the one the server
+                    // assigns again, and is warm by the time it does
+                    let startup_model = args.llama_model_path.clone().or_else(|| {
+                        if !args.preload_model {
+                            return None;
+                        }
+                        let path = open_model_cache(&args).last_loaded()?;
+                        info!("Preloading {}, the model loaded last", path.display());
+                        Some(path.to_string_lossy().to_string())
+                    });
+                    let mut llama_worker = if let Some(model_path) = &startup_model {
                         // Use provided model path
                         info!("Creating LLAMA engine with model: {}", model_path);
-                        llm_engine::AnyEngine::Llama(LlamaEngine::with_config(
-                            model_path.clone(),
-                            args.n_ctx,
-                            args.n_gpu_layers,
-                            args.llama_split_mode.clone(),
-                            args.llama_main_gpu,
-                            args.llama_devices.clone(),
-                        ))
+                        llm_engine::AnyEngine::Llama(
+                            LlamaEngine::with_config(
+                                model_path.clone(),
+                                args.n_ctx,
+                                args.n_gpu_layers,
+                                args.llama_split_mode.clone(),
+                                args.llama_main_gpu,
+                                args.llama_devices.clone(),
+                            )
+                            .with_draft_model(args.draft_model_path.clone(), args.draft_tokens)
+                            .with_parallel_slots(args.parallel_slots),
+                        )
                     } else {
                         // Create engine without model (will be set later)
                         info!("Creating LLAMA engine without model (will be set later)");
-                        llm_engine::AnyEngine::Llama(LlamaEngine::with_runtime_config(
-                            args.n_ctx,
-                            args.n_gpu_layers,
-                            args.llama_split_mode.clone(),
-                            args.llama_main_gpu,
-                            args.llama_devices.clone(),
-                        ))
+                        llm_engine::AnyEngine::Llama(
+                            LlamaEngine::with_runtime_config(
+                                args.n_ctx,
+                                args.n_gpu_layers,
+                                args.llama_split_mode.clone(),
+                                args.llama_main_gpu,
+                                args.llama_devices.clone(),
+                            )
+                            .with_draft_model(args.draft_model_path.clone(), args.draft_tokens)
+                            .with_parallel_slots(args.parallel_slots),
+                        )
                     };
 
                     // Initialize the engine (only on first startup)
@@ -1484,7 +1706,7 @@ impl ClientWorker {
                             info!("LLAMA engine init success - model loaded into memory");
 
                             // Update MODEL_STATUS with the local model path
-                            if let Some(ref model_path) = args.llama_model_path {
+                            if let Some(ref model_path) = startup_model {
                                 if let Ok(mut status) = crate::MODEL_STATUS.lock() {
                                     status.current_model = Some(model_path.clone());
                                     status.is_loaded = true;
@@ -1510,16 +1732,24 @@ impl ClientWorker {
         }
         let device_memtotal_gb = device_memtotal_mb as u32;
         let device_total_tflops = device_info.total_tflops as u32;
-
-        let addr_str = format!("{}:{}", args.server_addr, args.control_port);
-        let addr = addr_str.to_socket_addrs()?.next().ok_or_else(|| {
-            std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid server address or port",
-            )
-        })?;
-        let ip_addr = addr.ip();
-        let control_stream = TcpStream::connect(addr).await?;
+        let benchmark = tokio::task::spawn_blocking(benchmark::run).await.ok();
+
+        let server_proxy = proxy::server_proxy(&args);
+        let control_stream = proxy::connect(
+            server_proxy,
+            &args.server_addr,
+            args.control_port,
+            args.ip_version,
+        )
+        .await?;
+        // Proxy connections go to the address that answered, so they use the
+        // family that works. Through a proxy it is only the local resolution.
+        let ip_addr = match server_proxy {
+            None => control_stream.peer_addr()?.ip(),
+            Some(_) => {
+                net::resolve(&args.server_addr, args.control_port, args.ip_version).await?[0].ip()
+            }
+        };
 
         info!("Connected to control port.");
 
@@ -1532,10 +1762,18 @@ impl ClientWorker {
         let (cpu_useage, mem_useage, disk_useage, _computer_name) = collect_system_info().await?;
 
         let stats = network_monitor.lock().await.refresh().unwrap_or((0, 0));
+        #[cfg(not(target_os = "android"))]
+        let engine = Arc::new(Mutex::new(engine));
+        let jobs = job_registry(
+            #[cfg(not(target_os = "android"))]
+            Arc::clone(&engine),
+            engine_type,
+            &args,
+        );
         let worker = ClientWorker {
             addr: ip_addr,
             #[cfg(not(target_os = "android"))]
-            engine: Arc::new(Mutex::new(engine)),
+            engine,
             #[cfg(target_os = "android")]
             _engine: PhantomData,
             //TODO: only one device
@@ -1552,6 +1790,7 @@ impl ClientWorker {
             client_id: args.client_id.expect("client_id is required"),
             device_memtotal_gb,
             device_total_tflops,
+            benchmark,
             os_type,
             engine_type,
             args,
@@ -1560,6 +1799,7 @@ impl ClientWorker {
                 cancelled: Mutex::new(HashSet::new()),
                 notify: tokio::sync::Notify::new(),
             }),
+            jobs,
         };
         Ok(worker)
     }
@@ -1580,9 +1820,7 @@ impl ClientWorker {
 
     pub async fn deal_with_model(&self, model_name: &str) -> Result<()> {
         match self.engine_type {
-            common::EngineType::Ollama => {
-                pull_ollama_model(&model_name, self.args.local_port).await?
-            }
+            common::EngineType::Ollama => self.ollama_pull_task(model_name),
             common::EngineType::Vllm => {
                 #[cfg(all(not(target_os = "macos"), not(target_os = "android")))]
                 if let Some(_engine) = self.engine.lock().await.as_mut() {
@@ -1597,8 +1835,100 @@ impl ClientWorker {
         Ok(())
     }
 
-    /// Download and manage model for Llama engine with progress reporting
-    pub async fn deal_with_pod_model(&self, pod_model: &PodModel) -> Result<()> {
+    /// Pull a model into Ollama in the background, reporting its progress
+    /// like a model download, then load it so it stays resident
+    fn ollama_pull_task(&self, model_name: &str) {
+        {
+            let mut pulls = OLLAMA_PULLS.lock().unwrap();
+            if pulls.iter().any(|name| name == model_name) {
+                info!("Ollama pull of {} is already running", model_name);
+                return;
+            }
+            pulls.push(model_name.to_string());
+        }
+        let ollama = OllamaEngine::new()
+            .with_port(self.args.local_port)
+            .with_keep_alive(self.args.ollama_keep_alive.clone());
+        let writer = Arc::clone(&self.writer);
+        let client_id = self.client_id;
+        let model_name = model_name.to_string();
+        tokio::spawn(async move {
+            let (progress_tx, mut progress_rx) =
+                tokio::sync::watch::channel(PullProgress::default());
+            let reporter = {
+                let writer = Arc::clone(&writer);
+                let model_name = model_name.clone();
+                tokio::spawn(async move {
+                    let mut last = (std::time::Instant::now(), 0u64);
+                    while progress_rx.changed().await.is_ok() {
+                        let progress = progress_rx.borrow_and_update().clone();
+                        let elapsed = last.0.elapsed().as_secs_f64().max(1.0);
+                        let speed_bps = (progress.completed_bytes.saturating_sub(last.1) as f64
+                            / elapsed) as u64;
+                        last = (std::time::Instant::now(), progress.completed_bytes);
+                        let cmd = CommandV1::ModelDownloadProgress {
+                            client_id,
+                            model_name: model_name.clone(),
+                            downloaded_bytes: progress.completed_bytes,
+                            total_bytes: progress.total_bytes,
+                            percentage: progress.percentage(),
+                            speed_bps,
+                            status: DownloadStatus::Downloading,
+                            error: None,
+                        };
+                        if let Err(e) = Self::send_command_on_writer(Arc::clone(&writer), cmd).await
+                        {
+                            debug!("Ollama pull reporting stopped: {}", e);
+                            break;
+                        }
+                        tokio::time::sleep(DOWNLOAD_REPORT_INTERVAL).await;
+                    }
+                })
+            };
+
+            let result = ollama
+                .pull_model_with_progress(&model_name, |progress| {
+                    progress_tx.send_replace(progress.clone());
+                })
+                .await;
+            reporter.abort();
+            let result = match result {
+                Ok(()) => ollama.keep_resident(&model_name).await,
+                Err(e) => Err(e),
+            };
+            OLLAMA_PULLS
+                .lock()
+                .unwrap()
+                .retain(|name| name != &model_name);
+
+            let total_bytes = progress_tx.borrow().total_bytes;
+            let (status, error) = match result {
+                Ok(()) => (DownloadStatus::Completed, None),
+                Err(e) => {
+                    error!("Failed to pull model {} into Ollama: {}", model_name, e);
+                    (DownloadStatus::Failed, Some(e.to_string()))
+                }
+            };
+            let completed = status == DownloadStatus::Completed;
+            let cmd = CommandV1::ModelDownloadProgress {
+                client_id,
+                model_name,
+                downloaded_bytes: if completed { total_bytes } else { 0 },
+                total_bytes,
+                percentage: if completed { 100.0 } else { 0.0 },
+                speed_bps: 0,
+                status,
+                error,
+            };
+            if let Err(e) = Self::send_command_on_writer(writer, cmd).await {
+                debug!("Failed to report Ollama pull result: {}", e);
+            }
+        });
+    }
+
+    /// Load the model for Llama engine, or queue its download. Progress and
+    /// completion of queued downloads are reported by `download_task`.
+    pub async fn deal_with_pod_model(&self, pod_model: &PodModelV2) -> Result<()> {
         let model_name = match &pod_model.model_name {
             Some(name) => name.clone(),
             None => return Ok(()),
@@ -1613,30 +1943,47 @@ impl ClientWorker {
         };
 
         // Get models directory (same level as executable)
-        let models_dir = std::env::current_exe()
-            .ok()
-            .and_then(|p| p.parent().map(|p| p.to_path_buf()))
-            .unwrap_or_else(|| std::path::PathBuf::from("."))
-            .join("models");
+        let models_dir = models_dir();
 
         // Create models directory if it doesn't exist
         tokio::fs::create_dir_all(&models_dir).await?;
 
+        #[cfg(not(target_os = "android"))]
+        self.sync_draft_model(pod_model, &models_dir);
+
         let model_path = models_dir.join(&model_name);
         let model_path_str = model_path.to_string_lossy().to_string();
 
+        // Another catalog version on disk is outdated. It keeps serving until
+        // its update is downloaded, and the update reuses its unchanged chunks.
+        let installed_version = model_delta::installed_version(&model_path).await;
+        let outdated = match (&pod_model.version, &installed_version) {
+            (Some(wanted), Some(installed)) => wanted != installed,
+            _ => false,
+        };
+
         // Check if model is already loaded
         if let Ok(status) = crate::MODEL_STATUS.lock() {
             if let Some(current_model) = &status.current_model {
-                if current_model == &model_path_str && status.is_loaded {
+                if current_model == &model_path_str && status.is_loaded && !outdated {
                     info!("Model {} is already loaded, skipping", model_name);
                     return Ok(());
                 }
             }
         }
 
+        // A model too large for the memory free right now is traded for a
+        // smaller one before it is downloaded
+        if !self.args.skip_model_fit_check
+            && !self
+                .model_fits(pod_model, &model_name, &model_path, &download_url)
+                .await?
+        {
+            return Ok(());
+        }
+
         // Check if model file already exists and is complete
-        let model_exists_and_complete = if model_path.exists() {
+        let model_exists_and_complete = if model_path.exists() && !outdated {
             if let Some(expected_size) = pod_model.expected_size {
                 let metadata = tokio::fs::metadata(&model_path).await?;
                 metadata.len() == expected_size
@@ -1647,45 +1994,58 @@ impl ClientWorker {
             false
         };
 
+        // A file from before publisher keys were pinned, or changed on disk,
+        // is downloaded again
+        let model_exists_and_complete = model_exists_and_complete && {
+            let downloader = ModelDownloader::new(DownloadConfig {
+                url: download_url.clone(),
+                output_path: model_path.clone(),
+                signature: pod_model.signature.clone(),
+                ..Default::default()
+            });
+            match downloader.verify_signature().await {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!(
+                        "Local model {} failed verification, downloading it again: {}",
+                        model_name, e
+                    );
+                    let _ = tokio::fs::remove_file(&model_path).await;
+                    false
+                }
+            }
+        };
+
         // If model exists and is complete, load it directly without downloading
         if model_exists_and_complete {
             info!("Model {} already exists locally, loading directly", model_name);
-            
-            // Update MODEL_STATUS
-            if let Ok(mut status) = crate::MODEL_STATUS.lock() {
-                status.current_model = Some(model_path_str.clone());
-                status.loading_status = "Loading into engine".to_string();
-                status.is_loaded = false;
-                status.error_message = None;
-            }
-            
+            mark_model_current(&model_path_str);
+
             // Load model into engine (only on non-Android platforms)
             #[cfg(not(target_os = "android"))]
-            {
-                let mut engine_guard = self.engine.lock().await;
-                if let Some(engine) = engine_guard.as_mut() {
-                    match engine.set_models(vec![model_path_str.clone()]).await {
-                        Ok(_) => {
-                            info!("Model {} loaded into engine successfully", model_name);
-                            if let Ok(mut status) = crate::MODEL_STATUS.lock() {
-                                status.loading_status = "Loaded".to_string();
-                                status.is_loaded = true;
-                            }
-                        }
-                        Err(e) => {
-                            error!("Failed to load model {} into engine: {}", model_name, e);
-                            if let Ok(mut status) = crate::MODEL_STATUS.lock() {
-                                status.loading_status = format!("Load failed: {}", e);
-                                status.error_message = Some(e.to_string());
-                            }
-                        }
-                    }
-                }
-            }
+            load_model_into_engine(
+                &self.engine,
+                &self.writer,
+                self.client_id,
+                &model_name,
+                &model_path_str,
+            )
+            .await;
             return Ok(());
         }
 
-        info!("Starting download for model: {} from {}", model_name, download_url);
+        // Moved aside so it is not taken for a partial download
+        let base_path = model_delta::base_path(&model_path);
+        if outdated && model_path.exists() {
+            info!(
+                "Model {} {} is outdated, updating to {}",
+                model_name,
+                installed_version.as_deref().unwrap_or_default(),
+                pod_model.version.as_deref().unwrap_or_default()
+            );
+            tokio::fs::rename(&model_path, &base_path).await?;
+            model_delta::remove_version(&model_path).await;
+        }
 
         // Check existing bytes for resume.
         // Note: parallel downloads store partial progress in a "<model>.parts" directory.
@@ -1715,6 +2075,35 @@ impl ClientWorker {
             }
         }
 
+        // Models downloading are kept, as their files may not be complete yet
+        let mut downloading = downloading_models(self.downloads());
+        downloading.push(model_name.clone());
+        self.model_cache().make_room(
+            pod_model
+                .expected_size
+                .unwrap_or(0)
+                .saturating_sub(already_downloaded),
+            &downloading,
+        );
+
+        let queued = self.downloads().enqueue(DownloadJob {
+            id: model_name.clone(),
+            url: download_url.clone(),
+            mirrors: Vec::new(),
+            output_path: model_path.clone(),
+            expected_size: pod_model.expected_size,
+            checksum: pod_model.checksum.clone(),
+            signature: pod_model.signature.clone(),
+            version: pod_model.version.clone(),
+            base_path: base_path.exists().then_some(base_path),
+            chunk_manifest_url: pod_model.chunk_manifest_url.clone(),
+        });
+        if !queued {
+            info!("Download for model {} is already queued", model_name);
+            return Ok(());
+        }
+        info!("Starting download for model: {} from {}", model_name, download_url);
+
         // Only send an initial progress event when we have non-zero progress to report.
         // This avoids a noisy "0B Pending" event on reconnect, while still reporting resume state.
         if already_downloaded > 0 {
@@ -1734,186 +2123,254 @@ impl ClientWorker {
             .await?;
         }
 
-        // Create download config
-        let config = crate::util::model_downloader::DownloadConfig {
-            url: download_url.clone(),
-            output_path: model_path.clone(),
-            parallel_chunks: 4,
-            chunk_size: 8 * 1024 * 1024,
-            expected_size: pod_model.expected_size,
-            checksum: pod_model.checksum.clone(),
-            resume: true,
-        };
-
-        // Setup progress reporting with 10 second interval
-        let client_id = self.client_id;
-        let writer = self.writer.clone();
-        let model_name_clone = model_name.clone();
-        let last_report_time = Arc::new(std::sync::Mutex::new(std::time::Instant::now()));
-
-        let mut downloader = crate::util::model_downloader::ModelDownloader::new(config);
-        downloader.set_progress_callback({
-            let last_report_time = last_report_time.clone();
-            let model_name = model_name_clone.clone();
-            let writer = writer.clone();
-
-            move |progress| {
-                let elapsed = {
-                    let last_time = last_report_time.lock().unwrap();
-                    last_time.elapsed()
-                };
-
-                // Report every 10 seconds
-                if elapsed.as_secs() >= 10 {
-                    let mut last_time = last_report_time.lock().unwrap();
-                    *last_time = std::time::Instant::now();
-
-                    let cmd = Command::V1(CommandV1::ModelDownloadProgress {
-                        client_id,
-                        model_name: model_name.clone(),
-                        downloaded_bytes: progress.downloaded_bytes,
-                        total_bytes: progress.total_bytes,
-                        percentage: (progress.percentage as f32) * 100.0,
-                        speed_bps: progress.speed_bps,
-                        status: DownloadStatus::Downloading,
-                        error: None,
-                    });
+        Ok(())
+    }
 
-                    // Use blocking send for callback
-                    let writer_clone = writer.clone();
-                    tokio::task::spawn(async move {
-                        let mut writer_guard = writer_clone.lock().await;
-                        let _ = write_command(&mut *writer_guard, &cmd).await;
-                    });
-                }
-            }
+    /// Download the catalog's draft model of `pod_model` into `models_dir`
+    /// and hand it to the llama.cpp engine, or drop the draft of the previous
+    /// model. A `--draft-model-path` given on the command line wins.
+    #[cfg(not(target_os = "android"))]
+    fn sync_draft_model(&self, pod_model: &PodModelV2, models_dir: &std::path::Path) {
+        if self.args.draft_model_path.is_some() || self.args.draft_tokens == 0 {
+            return;
+        }
+        let draft_path = pod_model.draft_download_url.as_ref().map(|url| {
+            let file_name = url
+                .rsplit('/')
+                .next()
+                .and_then(|name| name.split(['?', '#']).next())
+                .filter(|name| !name.is_empty())
+                .unwrap_or("draft.gguf");
+            (url.clone(), models_dir.join(file_name))
         });
-
-        // Execute download with retry logic
-        const MAX_RETRIES: u32 = 10;
-        const RETRY_DELAY_SECS: u64 = 10;
-        
-        let mut last_error = None;
-        for attempt in 1..=MAX_RETRIES {
-            match downloader.download().await {
-                Ok(_) => {
-                    info!("Model {} downloaded successfully to {:?}", model_name, model_path);
-                    self.send_download_progress(
-                        &model_name,
-                        pod_model.expected_size.unwrap_or(0),
-                        pod_model.expected_size.unwrap_or(0),
-                        100.0,
-                        0,
-                        DownloadStatus::Completed,
-                        None,
-                    ).await?;
-                    
-                    // Update MODEL_STATUS and load model into engine
-                    let model_path_str = model_path.to_string_lossy().to_string();
-                    if let Ok(mut status) = crate::MODEL_STATUS.lock() {
-                        status.current_model = Some(model_path_str.clone());
-                        status.loading_status = "Loading into engine".to_string();
-                        status.is_loaded = false;
-                        status.error_message = None;
-                    }
-                    
-                    // Load model into engine (only on non-Android platforms)
-                    #[cfg(not(target_os = "android"))]
-                    {
-                        info!("Loading model {} into engine", model_name);
-                        let mut engine_guard = self.engine.lock().await;
-                        if let Some(engine) = engine_guard.as_mut() {
-                            match engine.set_models(vec![model_path_str.clone()]).await {
-                                Ok(_) => {
-                                    info!("Model {} loaded into engine successfully", model_name);
-                                    if let Ok(mut status) = crate::MODEL_STATUS.lock() {
-                                        status.loading_status = "Loaded".to_string();
-                                        status.is_loaded = true;
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("Failed to load model {} into engine: {}", model_name, e);
-                                    if let Ok(mut status) = crate::MODEL_STATUS.lock() {
-                                        status.loading_status = format!("Load failed: {}", e);
-                                        status.error_message = Some(e.to_string());
-                                    }
-                                }
-                            }
+        let checksum = pod_model.draft_checksum.clone();
+        let engine = self.engine.clone();
+
+        tokio::spawn(async move {
+            let draft_path = match draft_path {
+                Some((url, path)) => {
+                    if !path.exists() {
+                        info!("Downloading draft model from {}", url);
+                        let downloader = ModelDownloader::new(DownloadConfig {
+                            url,
+                            output_path: path.clone(),
+                            checksum,
+                            ..Default::default()
+                        });
+                        if let Err(e) = downloader.download().await {
+                            warn!("Failed to download draft model: {}", e);
+                            return;
                         }
                     }
-                    return Ok(());
+                    Some(path.to_string_lossy().to_string())
                 }
-                Err(e) => {
-                    warn!("Download attempt {}/{} failed for model {}: {}", attempt, MAX_RETRIES, model_name, e);
-                    last_error = Some(e);
-                    
-                    if attempt < MAX_RETRIES {
-                        info!("Retrying download in {} seconds (attempt {}/{})...", RETRY_DELAY_SECS, attempt + 1, MAX_RETRIES);
-                        tokio::time::sleep(std::time::Duration::from_secs(RETRY_DELAY_SECS)).await;
-                        
-                        // Recreate downloader for retry (it will resume from where it left off)
-                        let config = crate::util::model_downloader::DownloadConfig {
-                            url: download_url.clone(),
-                            output_path: model_path.clone(),
-                            parallel_chunks: 4,
-                            chunk_size: 8 * 1024 * 1024,
-                            expected_size: pod_model.expected_size,
-                            checksum: pod_model.checksum.clone(),
-                            resume: true,
-                        };
-                        downloader = crate::util::model_downloader::ModelDownloader::new(config);
-                        downloader.set_progress_callback({
-                            let last_report_time = last_report_time.clone();
-                            let model_name = model_name_clone.clone();
-                            let writer = writer.clone();
-
-                            move |progress| {
-                                let elapsed = {
-                                    let last_time = last_report_time.lock().unwrap();
-                                    last_time.elapsed()
-                                };
+                None => None,
+            };
 
-                                if elapsed.as_secs() >= 10 {
-                                    let mut last_time = last_report_time.lock().unwrap();
-                                    *last_time = std::time::Instant::now();
+            let mut engine_guard = engine.lock().await;
+            if let Some(AnyEngine::Llama(llama)) = engine_guard.as_mut() {
+                if llama.draft_model_path == draft_path {
+                    return;
+                }
+                if let Err(e) = llama.set_draft_model(draft_path).await {
+                    warn!("Speculative decoding disabled: {}", e);
+                }
+            }
+        });
+    }
 
-                                    let cmd = Command::V1(CommandV1::ModelDownloadProgress {
-                                        client_id,
-                                        model_name: model_name.clone(),
-                                        downloaded_bytes: progress.downloaded_bytes,
-                                        total_bytes: progress.total_bytes,
-                                        percentage: (progress.percentage as f32) * 100.0,
-                                        speed_bps: progress.speed_bps,
-                                        status: DownloadStatus::Downloading,
-                                        error: None,
-                                    });
+    /// Check the model against free RAM and VRAM, counting the model it
+    /// replaces as free, and ask the server for a smaller one if it does not
+    /// fit. Models of unknown size pass.
+    async fn model_fits(
+        &self,
+        pod_model: &PodModelV2,
+        model_name: &str,
+        model_path: &std::path::Path,
+        download_url: &str,
+    ) -> Result<bool> {
+        let local_size = tokio::fs::metadata(model_path).await.map(|m| m.len()).ok();
+        let Some(model_bytes) = pod_model.expected_size.or(local_size).filter(|&s| s > 0) else {
+            return Ok(true);
+        };
 
-                                    let writer_clone = writer.clone();
-                                    tokio::task::spawn(async move {
-                                        let mut writer_guard = writer_clone.lock().await;
-                                        let _ = write_command(&mut *writer_guard, &cmd).await;
-                                    });
-                                }
+        let header = match local_size {
+            Some(_) => model_fit::read_header(model_path).await,
+            None => model_fit::fetch_header(download_url).await,
+        };
+        let header = match header {
+            Ok(header) => Some(header),
+            Err(e) => {
+                debug!(
+                    "No GGUF header of {} to size its KV cache: {}",
+                    model_name, e
+                );
+                None
+            }
+        };
+
+        let loaded = crate::MODEL_STATUS
+            .lock()
+            .ok()
+            .and_then(|status| status.current_model.clone().filter(|_| status.is_loaded));
+        let reclaimable_bytes = match loaded {
+            Some(path) => tokio::fs::metadata(path)
+                .await
+                .map(|m| m.len())
+                .unwrap_or(0),
+            None => 0,
+        };
+
+        let fit = model_fit::check(
+            model_bytes,
+            header.as_deref(),
+            self.args.n_ctx as u64,
+            reclaimable_bytes,
+        );
+        let rejected_models = {
+            let mut unfit = UNFIT_MODELS.lock().unwrap();
+            if fit.fits() {
+                unfit.retain(|name| name != model_name);
+                return Ok(true);
+            }
+            if !unfit.iter().any(|name| name == model_name) {
+                unfit.push(model_name.to_string());
+            }
+            unfit.clone()
+        };
+
+        warn!(
+            "Model {} needs {} with its KV cache but only {} is free, requesting a smaller model",
+            model_name,
+            format_bytes!(fit.needed_bytes()),
+            format_bytes!(fit.available_bytes)
+        );
+        self.send_command(CommandV1::RequestSmallerModel {
+            client_id: self.client_id,
+            pod_id: pod_model.pod_id,
+            rejected_models,
+            max_model_bytes: fit.max_model_bytes(),
+        })
+        .await?;
+        Ok(false)
+    }
+
+    /// Process-wide download queue, saved next to the models
+    fn downloads(&self) -> &'static DownloadManager {
+        DOWNLOADS.get_or_init(|| {
+            DownloadManager::open(
+                models_dir().join("downloads.json"),
+                self.args.max_concurrent_downloads,
+                download_policy::policy(),
+            )
+        })
+    }
+
+    /// Process-wide model cache over `models_dir`, unless the app opened one
+    fn model_cache(&self) -> &'static ModelCache {
+        open_model_cache(&self.args)
+    }
+
+    /// Report queued model downloads to the server and load each model once
+    /// it is downloaded. Started after every login, which also resumes the
+    /// downloads left over from the last run.
+    fn download_task(&self) {
+        let downloads = self.downloads();
+        let mut events = downloads.subscribe();
+        let writer = Arc::clone(&self.writer);
+        let cache = self.model_cache();
+        let client_id = self.client_id;
+        #[cfg(not(target_os = "android"))]
+        let engine = Arc::clone(&self.engine);
+        tokio::spawn(async move {
+            let mut last_report: HashMap<String, std::time::Instant> = HashMap::new();
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Download reporting skipped {} events", missed);
+                        continue;
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                };
+                let (downloaded_bytes, total_bytes, percentage, speed_bps, status, error) =
+                    match &event {
+                        DownloadEvent::Progress { id, progress } => {
+                            let now = std::time::Instant::now();
+                            if last_report.get(id).is_some_and(|last| {
+                                now.duration_since(*last) < DOWNLOAD_REPORT_INTERVAL
+                            }) {
+                                continue;
                             }
-                        });
+                            last_report.insert(id.clone(), now);
+                            (
+                                progress.downloaded_bytes,
+                                progress.total_bytes,
+                                (progress.percentage as f32) * 100.0,
+                                progress.speed_bps,
+                                DownloadStatus::Downloading,
+                                None,
+                            )
+                        }
+                        DownloadEvent::Completed { output_path, .. } => {
+                            let size = tokio::fs::metadata(output_path)
+                                .await
+                                .map(|m| m.len())
+                                .unwrap_or(0);
+                            (size, size, 100.0, 0, DownloadStatus::Completed, None)
+                        }
+                        DownloadEvent::Failed { error, .. } => {
+                            (0, 0, 0.0, 0, DownloadStatus::Failed, Some(error.clone()))
+                        }
+                        DownloadEvent::Cancelled { .. } => (
+                            0,
+                            0,
+                            0.0,
+                            0,
+                            DownloadStatus::Failed,
+                            Some("Download cancelled".to_string()),
+                        ),
+                    };
+                if !matches!(event, DownloadEvent::Progress { .. }) {
+                    last_report.remove(event.id());
+                }
+
+                let cmd = Command::V1(CommandV1::ModelDownloadProgress {
+                    client_id,
+                    model_name: event.id().to_string(),
+                    downloaded_bytes,
+                    total_bytes,
+                    percentage,
+                    speed_bps,
+                    status,
+                    error,
+                });
+                let sent = {
+                    let mut writer = writer.lock().await;
+                    match write_command(&mut *writer, &cmd).await {
+                        Ok(()) => writer.flush().await.map_err(anyhow::Error::from),
+                        Err(e) => Err(e),
                     }
+                };
+                // A dead connection ends this task; the next login starts a new one
+                if let Err(e) = sent {
+                    debug!("Download reporting stopped: {}", e);
+                    break;
+                }
+
+                if let DownloadEvent::Completed { id, output_path } = event {
+                    info!("Model {} downloaded successfully to {:?}", id, output_path);
+                    let model_path = output_path.to_string_lossy().to_string();
+                    mark_model_current(&model_path);
+                    // The size may not have been known when the download
+                    // was queued
+                    cache.make_room(0, &downloading_models(downloads));
+                    #[cfg(not(target_os = "android"))]
+                    load_model_into_engine(&engine, &writer, client_id, &id, &model_path).await;
                 }
             }
-        }
-        
-        // All retries failed
-        let final_error = last_error.unwrap_or_else(|| anyhow::anyhow!("Download failed after {} retries", MAX_RETRIES));
-        error!("Failed to download model {} after {} attempts: {}", model_name, MAX_RETRIES, final_error);
-        self.send_download_progress(
-            &model_name,
-            0,
-            pod_model.expected_size.unwrap_or(0),
-            0.0,
-            0,
-            DownloadStatus::Failed,
-            Some(final_error.to_string()),
-        ).await?;
-        Err(final_error)
+        });
+        downloads.resume_pending();
     }
 
     async fn send_download_progress(
@@ -1940,11 +2397,318 @@ impl ClientWorker {
     }
 }
 
+/// Handlers of the job kinds this worker runs. Embeddings come from the
+/// engine's OpenAI-compatible server, so llama.cpp workers, whose server
+/// lacks the route, fail them.
+fn job_registry(
+    #[cfg(not(target_os = "android"))] engine: Arc<Mutex<Option<AnyEngine>>>,
+    engine_type: ClientEngineType,
+    args: &Args,
+) -> JobRegistry {
+    let mut jobs = JobRegistry::new();
+
+    #[cfg(not(target_os = "android"))]
+    {
+        let inference_engine = Arc::clone(&engine);
+        jobs.register(JobKind::Inference, move |spec| {
+            let engine = Arc::clone(&inference_engine);
+            async move {
+                use crate::llm_engine::InferenceEvent;
+
+                let JobSpec::Inference {
+                    model,
+                    prompt,
+                    max_tokens,
+                    inputs,
+                } = spec
+                else {
+                    return Err(anyhow!("Not an inference job"));
+                };
+                let mut context = String::new();
+                for input in &inputs {
+                    context.push_str(&fetch_job_input(input).await?);
+                    context.push_str("\n\n");
+                }
+                let prompt = context + &prompt;
+                reload_idle_model(&engine).await;
+                let engine = engine
+                    .lock()
+                    .await
+                    .clone()
+                    .ok_or_else(|| anyhow!("Engine not initialized"))?;
+                let request = InferenceRequest {
+                    model,
+                    input: InferenceInput::Prompt(prompt),
+                    max_tokens,
+                    temperature: 0.8,
+                    top_k: 40,
+                    top_p: 0.95,
+                    repeat_penalty: 1.1,
+                    repeat_last_n: 64,
+                    min_keep: 1,
+                    prefix: None,
+                    overflow: common::ContextOverflow::Error,
+                };
+                let mut stream = engine.infer(request).await?;
+                let mut text = String::new();
+                let mut pieces: u32 = 0;
+                let mut reported: u32 = 0;
+                while let Some(event) = stream.next().await {
+                    match event? {
+                        InferenceEvent::Token(piece) => {
+                            text.push_str(&piece);
+                            pieces += 1;
+                        }
+                        InferenceEvent::Usage {
+                            completion_tokens, ..
+                        } => reported = completion_tokens,
+                        InferenceEvent::ContextOverflow { .. } => {}
+                    }
+                }
+                Ok(JobOutput::Text {
+                    text,
+                    completion_tokens: if reported > 0 { reported } else { pieces },
+                })
+            }
+        });
+
+        let probe_engine = engine;
+        let probe =
+            crate::llm_engine::supervisor::HealthProbe::for_engine(engine_type, args.local_port);
+        if let Some(probe) = probe {
+            jobs.register(JobKind::HealthProbe, move |_| {
+                let engine = Arc::clone(&probe_engine);
+                let probe = probe.clone();
+                async move {
+                    let start = std::time::Instant::now();
+                    probe.check(&reqwest::Client::new(), &engine).await?;
+                    Ok(JobOutput::Healthy {
+                        latency_ms: start.elapsed().as_millis().min(u32::MAX as u128) as u32,
+                    })
+                }
+            });
+        }
+    }
+    #[cfg(target_os = "android")]
+    let _ = engine_type;
+
+    let engine_url = format!("http://{}:{}", args.local_addr, args.local_port);
+    jobs.register(JobKind::Embedding, move |spec| {
+        let engine_url = engine_url.clone();
+        async move {
+            let JobSpec::Embedding { model, input } = spec else {
+                return Err(anyhow!("Not an embedding job"));
+            };
+            let embeddings = crate::llm_engine::openai_api::embed(
+                &reqwest::Client::new(),
+                &engine_url,
+                model,
+                input,
+            )
+            .await?;
+            Ok(JobOutput::Embeddings(embeddings))
+        }
+    });
+
+    jobs.register(JobKind::Benchmark, |_| async move {
+        let result = tokio::task::spawn_blocking(benchmark::run).await?;
+        Ok(JobOutput::Benchmark(result))
+    });
+
+    // Downloaded directly rather than queued: the queue loads what it
+    // finishes into the engine
+    jobs.register(JobKind::Prefetch, |spec| async move {
+        let JobSpec::Prefetch { url, file_name } = spec else {
+            return Err(anyhow!("Not a prefetch job"));
+        };
+        let mut components = std::path::Path::new(&file_name).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(std::path::Component::Normal(_)), None)
+        ) {
+            return Err(anyhow!("Invalid file name {}", file_name));
+        }
+        let path = models_dir().join(&file_name);
+        if !path.exists() {
+            tokio::fs::create_dir_all(models_dir()).await?;
+            crate::util::model_downloader::download_model(&url, &path).await?;
+        }
+        let bytes = tokio::fs::metadata(&path).await?.len();
+        Ok(JobOutput::Prefetched {
+            path: path.display().to_string(),
+            bytes,
+        })
+    });
+
+    jobs
+}
+
+/// Text of an uploaded job input, checked against its size and hash
+#[cfg(not(target_os = "android"))]
+async fn fetch_job_input(input: &common::JobInput) -> Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let content = proxy::http_client()
+        .build()?
+        .get(&input.url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    if content.len() as u64 != input.size || hex::encode(Sha256::digest(&content)) != input.sha256 {
+        return Err(anyhow!("Upload {} arrived damaged", input.upload_id));
+    }
+    String::from_utf8(content.to_vec())
+        .map_err(|_| anyhow!("Upload {} is not UTF-8 text", input.upload_id))
+}
+
+/// Directory models are downloaded to, next to the executable
+fn models_dir() -> std::path::PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(|p| p.to_path_buf()))
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+        .join("models")
+}
+
+/// Process-wide model cache over `models_dir`, unless the app opened one
+fn open_model_cache(args: &Args) -> &'static ModelCache {
+    let quota_bytes = args
+        .model_cache_quota_gb
+        .map(|gb| gb.saturating_mul(1024 * 1024 * 1024));
+    model_cache::get_or_init(models_dir(), quota_bytes)
+}
+
+/// File names of the models in the download queue
+fn downloading_models(downloads: &DownloadManager) -> Vec<String> {
+    downloads
+        .pending()
+        .iter()
+        .filter_map(|job| job.output_path.file_name()?.to_str().map(String::from))
+        .collect()
+}
+
+/// Record `model_path` as the model being loaded
+fn mark_model_current(model_path: &str) {
+    if let Ok(mut status) = crate::MODEL_STATUS.lock() {
+        status.current_model = Some(model_path.to_string());
+        status.loading_status = "Loading into engine".to_string();
+        status.is_loaded = false;
+        status.error_message = None;
+    }
+    if let Some(cache) = model_cache::global() {
+        cache.touch_path(std::path::Path::new(model_path));
+    }
+}
+
+/// Whether the current model is loaded and warmed up, for the server to
+/// route interactive requests to warm workers first
+fn model_warmth(client_id: [u8; 16]) -> CommandV1 {
+    let warm = crate::MODEL_STATUS
+        .lock()
+        .map_or(false, |status| status.is_loaded && status.is_warm);
+    CommandV1::ModelWarmth { client_id, warm }
+}
+
+/// Load `model_path` into the engine, reporting the worker cold while it
+/// loads and warms up
+#[cfg(not(target_os = "android"))]
+async fn load_model_into_engine(
+    engine: &Mutex<Option<AnyEngine>>,
+    writer: &Arc<Mutex<WriteHalf<TcpStream>>>,
+    client_id: [u8; 16],
+    model_name: &str,
+    model_path: &str,
+) {
+    info!("Loading model {} into engine", model_name);
+    let cold = CommandV1::ModelWarmth {
+        client_id,
+        warm: false,
+    };
+    if let Err(e) = ClientWorker::send_command_on_writer(Arc::clone(writer), cold).await {
+        warn!("Failed to report model warmth: {}", e);
+    }
+    let mut engine_guard = engine.lock().await;
+    if let Some(engine) = engine_guard.as_mut() {
+        match engine.set_models(vec![model_path.to_string()]).await {
+            Ok(_) => {
+                info!("Model {} loaded into engine successfully", model_name);
+                if let Ok(mut status) = crate::MODEL_STATUS.lock() {
+                    status.loading_status = "Loaded".to_string();
+                    status.is_loaded = true;
+                }
+            }
+            Err(e) => {
+                error!("Failed to load model {} into engine: {}", model_name, e);
+                if let Ok(mut status) = crate::MODEL_STATUS.lock() {
+                    status.loading_status = format!("Load failed: {}", e);
+                    status.error_message = Some(e.to_string());
+                }
+            }
+        }
+    }
+    drop(engine_guard);
+    let warmth = model_warmth(client_id);
+    if let Err(e) = ClientWorker::send_command_on_writer(Arc::clone(writer), warmth).await {
+        warn!("Failed to report model warmth: {}", e);
+    }
+}
+
+/// Hand the worker's llama.cpp engine to the copies kept for reconnections
+/// and by the local HTTP API, so a model unloaded or loaded again is freed or
+/// served by all of them
+#[cfg(not(target_os = "android"))]
+async fn share_llama_engine(llama: &LlamaEngine) {
+    if let Some(cache) = GLOBAL_ENGINE.get() {
+        *cache.lock().await = Some(AnyEngine::Llama(llama.clone()));
+    }
+    if let Some(server_engine) = LLAMA_SERVER_ENGINE.get() {
+        *server_engine.write().await = llama.clone();
+    }
+}
+
+/// Load the model the idle unload task freed again before a request needs
+/// it. Also counts requests that take no request slot as activity.
+#[cfg(not(target_os = "android"))]
+async fn reload_idle_model(engine: &Mutex<Option<AnyEngine>>) {
+    RuntimeConfig::global().mark_active();
+    if !IDLE_UNLOADED.load(Ordering::SeqCst) {
+        return;
+    }
+    let mut engine_guard = engine.lock().await;
+    // Reloaded by another request while this one waited for the engine
+    if !IDLE_UNLOADED.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let Some(AnyEngine::Llama(llama)) = engine_guard.as_mut() else {
+        return;
+    };
+    let Some(model_path) = llama.model_path.clone() else {
+        return;
+    };
+    info!("Reloading model {} unloaded while idle", model_path);
+    match llama.set_models(vec![model_path.clone()]).await {
+        Ok(_) => {
+            if let Ok(mut status) = crate::MODEL_STATUS.lock() {
+                status.set_loaded(&model_path);
+            }
+            share_llama_engine(llama).await;
+        }
+        Err(e) => {
+            error!("Failed to reload model {}: {}", model_path, e);
+            if let Ok(mut status) = crate::MODEL_STATUS.lock() {
+                status.set_error(&e.to_string());
+            }
+        }
+    }
+}
+
 type TCPWorker = ClientWorker;
 
 #[cfg(target_os = "macos")]
 #[allow(dead_code)]
-async fn check_and_restart_ollama() -> Result<()> {
+async fn check_and_restart_ollama(keep_alive: &str) -> Result<()> {
     use std::process::Stdio;
     use tokio::process::Command;
 
@@ -1981,6 +2745,7 @@ async fn check_and_restart_ollama() -> Result<()> {
     let mut cmd = Command::new("ollama");
     cmd.arg("serve")
         .env("OLLAMA_HOST", "0.0.0.0")
+        .env("OLLAMA_KEEP_ALIVE", keep_alive)
         .stdout(Stdio::null())
         .stderr(Stdio::null());
 
@@ -2031,8 +2796,8 @@ impl WorkerHandle for ClientWorker {
     fn login(&self) -> impl Future<Output = Result<()>> + Send {
         async move {
             info!("{} Starting login process...", log_icon("🔧", "[LOGIN]"));
-            let login_cmd = CommandV1::Login {
-                version: CURRENT_VERSION,
+            let login_cmd = CommandV1::LoginV2 {
+                version: PROTOCOL_VERSION,
                 auto_models: self.args.llama_model_path.is_none(),
                 os_type: self.os_type.clone(),
                 client_id: self.client_id.clone(),
@@ -2040,6 +2805,9 @@ impl WorkerHandle for ClientWorker {
                 device_memtotal_gb: self.device_memtotal_gb,
                 device_total_tflops: self.device_total_tflops,
                 devices_info: self.devices_info.as_ref().clone(),
+                benchmark: self.benchmark,
+                enrollment_token: self.args.enrollment_token.clone(),
+                region: self.args.region.clone(),
             };
             info!(
                 "{} About to write login command to server...",
@@ -2074,11 +2842,20 @@ impl WorkerHandle for ClientWorker {
             let engine_type = self.engine_type.clone();
             info!("{} Model task started", log_icon("✅", "[OK]"));
             let local_port = self.args.local_port;
+            let external = match engine_type {
+                common::EngineType::External => self
+                    .args
+                    .external_url
+                    .as_deref()
+                    .and_then(|url| ExternalEngine::new(url).ok()),
+                _ => None,
+            };
             let devices_info = self.devices_info.clone();
             tokio::spawn(async move {
-                let mut interval = interval(Duration::from_secs(300)); // Send heartbeat every 10 seconds
+                // Report model status every monitor interval (300 seconds by default)
+                let mut ticker = RuntimeConfig::global().ticker(|s| s.monitor_interval_secs);
                 loop {
-                    interval.tick().await;
+                    ticker.tick().await;
 
                     let models: Vec<common::Model> = match engine_type {
                         common::EngineType::Ollama => match get_engine_models(local_port).await {
@@ -2102,7 +2879,8 @@ impl WorkerHandle for ClientWorker {
                             debug!("current_model_path {:?}", current_model_path);
                             match current_model_path {
                                 Some(model_path) => {
-                                    let model_id = derive_model_id_from_path(&model_path);
+                                    let model_id =
+                                        crate::llm_engine::model_id_from_path(&model_path);
                                     vec![Model {
                                         id: model_id,
                                         object: "model".to_string(),
@@ -2113,6 +2891,32 @@ impl WorkerHandle for ClientWorker {
                                 None => Vec::new(),
                             }
                         }
+                        common::EngineType::TensorRT => match get_engine_models(local_port).await {
+                            Ok(models) => models,
+                            Err(e) => {
+                                warn!("Could not fetch models from TensorRT-LLM: {}", e);
+                                Vec::new()
+                            }
+                        },
+                        common::EngineType::StableDiffusion => {
+                            match get_engine_models(local_port).await {
+                                Ok(models) => models,
+                                Err(e) => {
+                                    warn!("Could not fetch models from image server: {}", e);
+                                    Vec::new()
+                                }
+                            }
+                        }
+                        common::EngineType::External => match &external {
+                            Some(external) => match external.list_models().await {
+                                Ok(models) => models,
+                                Err(e) => {
+                                    warn!("Could not fetch models from external engine: {}", e);
+                                    Vec::new()
+                                }
+                            },
+                            None => Vec::new(),
+                        },
                         _ => Vec::new(),
                     };
                     debug!("Successfully fetched {:?} models from engine.", models);
@@ -2151,12 +2955,33 @@ impl WorkerHandle for ClientWorker {
             let client_id = Arc::new(self.client_id.clone());
             let network_monitor = Arc::clone(&self.network_monitor);
             let engine_type = self.engine_type; // Clone engine_type for use in spawn
+            let args = self.args.clone();
+            let addr = self.addr;
             // network_monitor.lock().await.update();
             tokio::spawn(async move {
-                let mut interval = interval(Duration::from_secs(120)); // Send heartbeat every 120 seconds
+                // Send heartbeat every heartbeat interval (120 seconds by default)
+                let mut ticker = RuntimeConfig::global().ticker(|s| s.heartbeat_interval_secs);
+                let mut network_quality = None;
+                let mut last_probe: Option<std::time::Instant> = None;
+                let http = reqwest::Client::new();
 
                 loop {
-                    interval.tick().await;
+                    ticker.tick().await;
+
+                    if last_probe.map_or(true, |at| at.elapsed() >= NETWORK_PROBE_INTERVAL) {
+                        last_probe = Some(std::time::Instant::now());
+                        match timeout(NETWORK_PROBE_TIMEOUT, probe_network(&args, addr)).await {
+                            Ok(Ok(quality)) => {
+                                info!(
+                                    "network_probe: rtt {}ms up {:.1} Mbps down {:.1} Mbps",
+                                    quality.rtt_ms, quality.upload_mbps, quality.download_mbps
+                                );
+                                network_quality = Some(quality);
+                            }
+                            Ok(Err(e)) => warn!("Network probe failed: {}", e),
+                            Err(_) => warn!("Network probe timed out"),
+                        }
+                    }
 
                     let (cpu_usage, memory_usage, disk_usage, _computer_name) =
                         match collect_system_info().await {
@@ -2176,6 +3001,13 @@ impl WorkerHandle for ClientWorker {
                         }
                     };
 
+                    let engine_version = crate::llm_engine::supervisor::engine_version(
+                        &http,
+                        engine_type,
+                        args.local_port,
+                    )
+                    .await;
+
                     // TODO: device_info is remote device info
                     info!("heartbeat: cpu_usage {}% memory_usage {}% disk_usage {}% device_memtotal {}mb", cpu_usage, memory_usage, disk_usage, device_memtotal_mb);
 
@@ -2197,7 +3029,7 @@ impl WorkerHandle for ClientWorker {
                     let mut writer = writer_clone.lock().await;
                     if let Err(e) = write_command(
                         &mut *writer,
-                        &Command::V1(CommandV1::Heartbeat {
+                        &Command::V1(CommandV1::HeartbeatV2 {
                             client_id: *client_id,
                             system_info: SystemInfo {
                                 cpu_usage: cpu_usage,
@@ -2211,6 +3043,10 @@ impl WorkerHandle for ClientWorker {
                             device_total_tflops: device_info.total_tflops as u32,
                             device_count: device_info.num as u16,
                             devices_info: vec![device_info],
+                            gpu_metrics: collect_gpu_metrics(),
+                            network_quality,
+                            power: None,
+                            version: Some(version_info::worker_version(engine_version)),
                         }),
                     )
                     .await
@@ -2224,12 +3060,152 @@ impl WorkerHandle for ClientWorker {
         }
     }
 
+    /// Probe the engine, restart it when it keeps failing and report each
+    /// change of its health. Replaces the supervisor of an earlier session.
+    #[cfg(not(target_os = "android"))]
+    fn engine_supervisor_task(&self) {
+        use crate::llm_engine::supervisor::{self, HealthProbe, Supervisor};
+
+        let Some(probe) = HealthProbe::for_engine(self.engine_type, self.args.local_port) else {
+            return;
+        };
+        let session = SUPERVISOR_SESSION.fetch_add(1, Ordering::SeqCst) + 1;
+        let engine = Arc::clone(&self.engine);
+        let writer = Arc::clone(&self.writer);
+        let client_id = self.client_id;
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            let mut supervisor = Supervisor::new();
+            let mut reported = supervisor.state();
+            let mut ticker = tokio::time::interval(supervisor::PROBE_INTERVAL);
+            loop {
+                ticker.tick().await;
+                if SUPERVISOR_SESSION.load(Ordering::SeqCst) != session {
+                    break;
+                }
+
+                let error = probe
+                    .check(&client, &engine)
+                    .await
+                    .err()
+                    .map(|e| e.to_string());
+                let restart = supervisor.on_probe(error.is_none(), std::time::Instant::now());
+                if supervisor.state() != reported {
+                    reported = supervisor.state();
+                    let status = CommandV1::EngineStatus {
+                        client_id,
+                        state: reported,
+                        restarts: supervisor.restarts(),
+                        error: error.clone(),
+                    };
+                    if let Err(e) =
+                        write_command(&mut *writer.lock().await, &Command::V1(status)).await
+                    {
+                        error!("Failed to send engine status: {}", e);
+                        break;
+                    }
+                }
+                if restart {
+                    warn!(
+                        "Restarting engine ({:?}, restart {}): {}",
+                        reported,
+                        supervisor.restarts(),
+                        error.as_deref().unwrap_or_default()
+                    );
+                    if let Err(e) = supervisor::restart(&engine).await {
+                        error!("Engine restart failed: {}", e);
+                    }
+                    ticker.reset();
+                }
+            }
+        });
+    }
+
+    /// Unload the llama.cpp model once no request was in flight for the
+    /// `idle_unload_secs` of the runtime config, reporting the worker cold
+    /// until a request loads it again
+    #[cfg(not(target_os = "android"))]
+    fn idle_unload_task(&self) {
+        let session = IDLE_UNLOAD_SESSION.fetch_add(1, Ordering::SeqCst) + 1;
+        let engine = Arc::clone(&self.engine);
+        let writer = Arc::clone(&self.writer);
+        let client_id = self.client_id;
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(IDLE_CHECK_INTERVAL);
+            // Whether this task reported the worker cold after unloading
+            let mut reported_cold = IDLE_UNLOADED.load(Ordering::SeqCst);
+            loop {
+                ticker.tick().await;
+                if IDLE_UNLOAD_SESSION.load(Ordering::SeqCst) != session {
+                    break;
+                }
+
+                if reported_cold && !IDLE_UNLOADED.load(Ordering::SeqCst) {
+                    let warmth = model_warmth(client_id);
+                    if let Err(e) = Self::send_command_on_writer(Arc::clone(&writer), warmth).await
+                    {
+                        warn!("Failed to report model warmth: {}", e);
+                        continue;
+                    }
+                    reported_cold = false;
+                }
+
+                let config = RuntimeConfig::global();
+                let idle_unload_secs = config.settings().idle_unload_secs;
+                let idle_for = config.idle_for();
+                if idle_unload_secs == 0
+                    || idle_for.map_or(true, |idle| {
+                        idle < Duration::from_secs(idle_unload_secs as u64)
+                    })
+                {
+                    continue;
+                }
+
+                let mut engine_guard = engine.lock().await;
+                let Some(AnyEngine::Llama(llama)) = engine_guard.as_mut() else {
+                    continue;
+                };
+                if !llama.is_initialized {
+                    continue;
+                }
+                llama.clear_cache();
+                IDLE_UNLOADED.store(true, Ordering::SeqCst);
+                share_llama_engine(llama).await;
+                drop(engine_guard);
+                if let Ok(mut status) = crate::MODEL_STATUS.lock() {
+                    status.set_unloaded("Unloaded while idle");
+                }
+                info!(
+                    "Model unloaded after {} seconds without requests",
+                    idle_unload_secs
+                );
+
+                let cold = CommandV1::ModelWarmth {
+                    client_id,
+                    warm: false,
+                };
+                match Self::send_command_on_writer(Arc::clone(&writer), cold).await {
+                    Ok(_) => reported_cold = true,
+                    Err(e) => warn!("Failed to report model warmth: {}", e),
+                }
+            }
+        });
+    }
+
     fn handler(&self) -> impl Future<Output = Result<()>> + Send {
         async move {
             let mut buf = BytesMut::with_capacity(MAX_MESSAGE_SIZE);
             let mut p2p_turn_config: HashMap<[u8; 16], (Vec<String>, String, String, String)> =
                 HashMap::new();
             // (turn_urls, username, password, peer_id as hex) - peer_id used only for debugging/selection
+            // Data-plane socket of each connection, and the peer address once a punch arrives
+            let mut p2p_udp_sockets: HashMap<
+                [u8; 16],
+                (
+                    Arc<UdpSocket>,
+                    tokio::sync::watch::Receiver<Option<SocketAddr>>,
+                ),
+            > = HashMap::new();
             loop {
                 let cmd_result = read_command(&mut *self.reader.lock().await, &mut buf).await;
                 
@@ -2262,7 +3238,60 @@ impl WorkerHandle for ClientWorker {
                                 }
                                 self.cancel_state.notify.notify_waiters();
                             }
-                            CommandV1::LoginResult {
+                            CommandV1::ServerRestarting {
+                                reconnect_to,
+                                delay_ms,
+                            } => {
+                                return Err(ServerRestart {
+                                    reconnect_to,
+                                    control_port: None,
+                                    proxy_port: None,
+                                    delay: Duration::from_millis(delay_ms as u64),
+                                }
+                                .into());
+                            }
+                            CommandV1::MigrateServer {
+                                server_addr,
+                                control_port,
+                                proxy_port,
+                                delay_window_ms,
+                            } => {
+                                return Err(ServerRestart {
+                                    reconnect_to: Some(server_addr),
+                                    control_port: Some(control_port),
+                                    proxy_port: Some(proxy_port),
+                                    delay: migration_delay(self.client_id, delay_window_ms),
+                                }
+                                .into());
+                            }
+                            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                            CommandV1::WorkerUpdate { release } => {
+                                let as_service = self.args.run_as_service;
+                                tokio::spawn(async move {
+                                    if let Err(e) = updater::apply(release, as_service).await {
+                                        warn!("Self-update failed: {:#}", e);
+                                    }
+                                });
+                            }
+                            CommandV1::UpdateConfig { version, config } => {
+                                info!("Received config version {} from server", version);
+                                let (success, error) =
+                                    match RuntimeConfig::global().apply(version, &config) {
+                                        Ok(_) => (true, None),
+                                        Err(e) => {
+                                            warn!("Rejected config version {}: {}", version, e);
+                                            (false, Some(e.to_string()))
+                                        }
+                                    };
+                                self.send_command(CommandV1::UpdateConfigAck {
+                                    client_id: self.client_id,
+                                    version,
+                                    success,
+                                    error,
+                                })
+                                .await?;
+                            }
+                            CommandV1::LoginResultV2 {
                                 success,
                                 pods_model,
                                 error,
@@ -2280,6 +3309,11 @@ impl WorkerHandle for ClientWorker {
                                             ));
                                         }
                                     }
+                                    self.download_task();
+                                    // A model preloaded before login is warm already
+                                    if self.engine_type == common::EngineType::Llama {
+                                        self.send_command(model_warmth(self.client_id)).await?;
+                                    }
                                     // If server assigned models, ensure they are pulled/ready.
                                     // Skip if user specified a local model path OR auto_models is disabled
                                     if self.args.llama_model_path.is_some() {
@@ -2305,6 +3339,12 @@ impl WorkerHandle for ClientWorker {
                                                                         // Start model reporting task immediately after login (choice A).
                                     self.model_task().await?;
                                     self.heartbeat_task().await?;
+                                    #[cfg(not(target_os = "android"))]
+                                    self.engine_supervisor_task();
+                                    #[cfg(not(target_os = "android"))]
+                                    if self.engine_type == common::EngineType::Llama {
+                                        self.idle_unload_task();
+                                    }
                                     debug!("Successfully logged in.");
                                     continue;
                                 } else {
@@ -2312,7 +3352,7 @@ impl WorkerHandle for ClientWorker {
                                     return Err(anyhow!("Login failed"));
                                 }
                             }
-                            CommandV1::PullModelResult { pods_model, error } => {
+                            CommandV1::PullModelResultV2 { pods_model, error } => {
                                 if error.is_some() {
                                     error!("Pull model failed: {}", error.unwrap_or_default());
                                     return Err(anyhow!("Pull model failed"));
@@ -2354,7 +3394,14 @@ impl WorkerHandle for ClientWorker {
                                 let args_clone = self.args.clone();
                                 let cert_chain_path_clone = self.args.cert_chain_path.clone();
                                 let addr_clone = self.addr;
+                                let runtime_config = RuntimeConfig::global();
+                                #[cfg(not(target_os = "android"))]
+                                let engine = Arc::clone(&self.engine);
                                 tokio::spawn(async move {
+                                    // Held until the proxied request is finished
+                                    let _slot = runtime_config.acquire_request_slot().await;
+                                    #[cfg(not(target_os = "android"))]
+                                    reload_idle_model(&engine).await;
                                     if let Err(e) = create_proxy_connection(
                                         args_clone,
                                         addr_clone,
@@ -2367,9 +3414,11 @@ impl WorkerHandle for ClientWorker {
                                     }
                                 });
                             }
-                            CommandV1::ChatInferenceTask {
+                            CommandV1::ChatInferenceTaskV2 {
                                 task_id,
-                                model: _model,
+                                request_id,
+                                traceparent,
+                                model,
                                 messages,
                                 max_tokens,
                                 temperature,
@@ -2378,6 +3427,9 @@ impl WorkerHandle for ClientWorker {
                                 repeat_penalty,
                                 repeat_last_n,
                                 min_keep,
+                                prefix,
+                                overflow,
+                                deadline_ms,
                             } => {
                                 info!(
                                     "Received chat inference task: {} messages: {} max_tokens: {}",
@@ -2385,116 +3437,39 @@ impl WorkerHandle for ClientWorker {
                                     messages.len(),
                                     max_tokens
                                 );
-                                let prompt = {
-                                    #[cfg(target_os = "android")]
-                                    {
-                                        self.build_chat_prompt_fallback(&messages)
-                                    }
-
-                                    #[cfg(not(target_os = "android"))]
-                                    {
-                                        let cached_model = {
-                                            let engine_guard = self.engine.lock().await;
-                                            let engine = engine_guard
-                                                .as_ref()
-                                                .ok_or_else(|| anyhow!("Engine not initialized"))?;
-
-                                            let AnyEngine::Llama(llama) = engine else {
-                                                return Err(anyhow!(
-                                                    "ChatInferenceTask is only supported for LLAMA engine"
-                                                ));
-                                            };
-
-                                            llama.cached_model
-                                                .as_ref()
-                                                .ok_or_else(|| {
-                                                    anyhow!("Model not loaded - call load_model() first")
-                                                })?
-                                                .clone()
-                                        };
-
-                                        let messages_for_fallback = messages.clone();
-                                        match tokio::task::spawn_blocking(
-                                            move || -> anyhow::Result<String> {
-                                                use llama_cpp_2::model::LlamaChatMessage;
-
-                                                let model_guard =
-                                                    cached_model.lock().map_err(|e| {
-                                                        anyhow!("Failed to lock model: {:?}", e)
-                                                    })?;
-
-                                                let tmpl = model_guard
-                                                    .chat_template(None)
-                                                    .map_err(|e| {
-                                                        anyhow!(
-                                                            "Failed to get chat template: {:?}",
-                                                            e
-                                                        )
-                                                    })?;
-
-                                                let mut chat =
-                                                    Vec::with_capacity(messages_for_fallback.len());
-                                                for m in messages_for_fallback {
-                                                    let msg =
-                                                        LlamaChatMessage::new(m.role, m.content)
-                                                            .map_err(|e| {
-                                                                anyhow!(
-                                                            "Failed to build chat message: {:?}",
-                                                            e
-                                                        )
-                                                            })?;
-                                                    chat.push(msg);
-                                                }
-
-                                                model_guard
-                                                    .apply_chat_template(&tmpl, &chat, true)
-                                                    .map_err(|e| {
-                                                        anyhow!(
-                                                            "Failed to apply chat template: {:?}",
-                                                            e
-                                                        )
-                                                    })
-                                            },
-                                        )
-                                        .await
-                                        {
-                                            Ok(Ok(p)) => p,
-                                            _ => self.build_chat_prompt_fallback(&messages),
-                                        }
-                                    }
-                                };
-                                let result = self
-                                    .stream_inference_task_to_server(
-                                        task_id.clone(),
-                                        prompt,
-                                        max_tokens,
-                                        temperature,
-                                        top_k,
-                                        top_p,
-                                        repeat_penalty,
-                                        repeat_last_n,
-                                        min_keep,
+                                // Engines apply the chat template of their model
+                                let input = if cfg!(target_os = "android") {
+                                    InferenceInput::Prompt(
+                                        crate::llm_engine::build_chat_prompt_fallback(&messages),
                                     )
-                                    .await;
-
-                                if let Err(e) = result {
-                                    let chunk = CommandV1::InferenceResultChunk {
-                                        task_id,
-                                        seq: 0,
-                                        delta: String::new(),
-                                        phase: OutputPhase::Unknown,
-                                        done: true,
-                                        completion_tokens: 0,
-                                        prompt_tokens: 0,
-                                        error: Some(e.to_string()),
-                                        analysis_tokens: 0,
-                                        final_tokens: 0,
-                                    };
-                                    self.send_command(chunk).await?;
-                                }
+                                } else {
+                                    InferenceInput::Chat(messages)
+                                };
+                                let request = InferenceRequest {
+                                    model: Some(model).filter(|model| !model.is_empty()),
+                                    input,
+                                    max_tokens,
+                                    temperature,
+                                    top_k,
+                                    top_p,
+                                    repeat_penalty,
+                                    repeat_last_n,
+                                    min_keep,
+                                    prefix,
+                                    overflow,
+                                };
+                                let span = task_span(
+                                    &task_id,
+                                    request_id.as_deref(),
+                                    traceparent.as_deref(),
+                                );
+                                let deadline = task_deadline(deadline_ms);
+                                self.spawn_inference_task(task_id, span, request, deadline);
                             }
-                            CommandV1::InferenceTask {
+                            CommandV1::InferenceTaskV2 {
                                 task_id,
+                                request_id,
+                                traceparent,
                                 prompt,
                                 max_tokens,
                                 temperature,
@@ -2503,50 +3478,42 @@ impl WorkerHandle for ClientWorker {
                                 repeat_penalty,
                                 repeat_last_n,
                                 min_keep,
+                                overflow,
+                                deadline_ms,
                             } => {
                                 info!(
                                     "Received inference task: {} max_tokens: {}",
                                     task_id, max_tokens
                                 );
 
-                                let start_time = std::time::Instant::now();
-
                                 #[cfg(not(target_os = "android"))]
                                 {
-                                    let result = self
-                                        .stream_inference_task_to_server(
-                                            task_id.clone(),
-                                            prompt.clone(),
-                                            max_tokens,
-                                            temperature,
-                                            top_k,
-                                            top_p,
-                                            repeat_penalty,
-                                            repeat_last_n,
-                                            min_keep,
-                                        )
-                                        .await;
-
-                                    let _execution_time = start_time.elapsed().as_millis() as u64;
-                                    if let Err(e) = result {
-                                        let chunk = CommandV1::InferenceResultChunk {
-                                            task_id,
-                                            seq: 0,
-                                            delta: String::new(),
-                                            phase: OutputPhase::Unknown,
-                                            done: true,
-                                            completion_tokens: 0,
-                                            prompt_tokens: 0,
-                                            error: Some(e.to_string()),
-                                            analysis_tokens: 0,
-                                            final_tokens: 0,
-                                        };
-                                        self.send_command(chunk).await?;
-                                    }
+                                    let request = InferenceRequest {
+                                        model: None,
+                                        input: InferenceInput::Prompt(prompt.clone()),
+                                        max_tokens,
+                                        temperature,
+                                        top_k,
+                                        top_p,
+                                        repeat_penalty,
+                                        repeat_last_n,
+                                        min_keep,
+                                        prefix: None,
+                                        overflow,
+                                    };
+                                    let span = task_span(
+                                        &task_id,
+                                        request_id.as_deref(),
+                                        traceparent.as_deref(),
+                                    );
+                                    let deadline = task_deadline(deadline_ms);
+                                    self.spawn_inference_task(task_id, span, request, deadline);
                                 }
 
                                 #[cfg(target_os = "android")]
                                 {
+                                    let _ = (overflow, deadline_ms);
+                                    let start_time = std::time::Instant::now();
                                     let result = self
                                         .execute_inference_task(
                                             &prompt,
@@ -2558,6 +3525,11 @@ impl WorkerHandle for ClientWorker {
                                             repeat_last_n,
                                             min_keep,
                                         )
+                                        .instrument(task_span(
+                                            &task_id,
+                                            request_id.as_deref(),
+                                            traceparent.as_deref(),
+                                        ))
                                         .await;
 
                                     let _execution_time = start_time.elapsed().as_millis() as u64;
@@ -2633,6 +3605,103 @@ impl WorkerHandle for ClientWorker {
                                     }
                                 }
                             }
+                            CommandV1::Job {
+                                job_id,
+                                spec,
+                                timeout_secs,
+                            } => {
+                                let kind = spec.kind();
+                                let timeout = (timeout_secs > 0)
+                                    .then(|| Duration::from_secs(timeout_secs as u64));
+                                let job = match self.jobs.accept(&job_id, spec, timeout) {
+                                    Ok(job) => job,
+                                    Err(e) => {
+                                        warn!("Rejected {:?} job {}: {}", kind, job_id, e);
+                                        self.send_command(CommandV1::JobUpdate {
+                                            client_id: self.client_id,
+                                            job_id,
+                                            status: JobStatus::Rejected,
+                                            output: None,
+                                            error: Some(e.to_string()),
+                                        })
+                                        .await?;
+                                        continue;
+                                    }
+                                };
+                                info!(
+                                    "Running {:?} job {} ({} jobs running)",
+                                    kind,
+                                    job_id,
+                                    self.jobs.running().len()
+                                );
+                                self.send_command(CommandV1::JobUpdate {
+                                    client_id: self.client_id,
+                                    job_id: job_id.clone(),
+                                    status: JobStatus::Running,
+                                    output: None,
+                                    error: None,
+                                })
+                                .await?;
+
+                                let writer = Arc::clone(&self.writer);
+                                let client_id = self.client_id;
+                                tokio::spawn(async move {
+                                    let (status, output, error) = match job.await {
+                                        Ok(output) => (JobStatus::Succeeded, Some(output), None),
+                                        Err(e) => {
+                                            warn!("{:?} job {} failed: {}", kind, job_id, e);
+                                            (JobStatus::Failed, None, Some(e.to_string()))
+                                        }
+                                    };
+                                    let update = CommandV1::JobUpdate {
+                                        client_id,
+                                        job_id: job_id.clone(),
+                                        status,
+                                        output,
+                                        error,
+                                    };
+                                    if let Err(e) =
+                                        Self::send_command_on_writer(writer, update).await
+                                    {
+                                        error!("Failed to report job {}: {}", job_id, e);
+                                    }
+                                });
+                            }
+                            CommandV1::Challenge {
+                                challenge_id,
+                                task,
+                                deadline_ms,
+                            } => {
+                                debug!(
+                                    "Answering challenge {} within {}ms",
+                                    hex::encode(challenge_id),
+                                    deadline_ms
+                                );
+                                let writer = Arc::clone(&self.writer);
+                                let client_id = self.client_id;
+                                tokio::spawn(async move {
+                                    let answer =
+                                        match tokio::task::spawn_blocking(move || task.solve())
+                                            .await
+                                        {
+                                            Ok(answer) => answer,
+                                            Err(e) => {
+                                                error!("Challenge task panicked: {}", e);
+                                                return;
+                                            }
+                                        };
+                                    let response = CommandV1::ChallengeResponse {
+                                        client_id,
+                                        challenge_id,
+                                        answer,
+                                    };
+                                    if let Err(e) =
+                                        Self::send_command_on_writer(writer, response).await
+                                    {
+                                        error!("Failed to answer challenge: {}", e);
+                                    }
+                                });
+                            }
                             _ => {
                                 warn!("Received unexpected CommandV1: {:?}", cmd_v1);
                             }
@@ -2673,6 +3742,39 @@ impl WorkerHandle for ClientWorker {
                                 let local_port = socket.local_addr()?.port();
                                 let advertise_ip = self.get_advertise_ip().await?;
 
+                                // Ask STUN before the data-plane loop takes over the socket
+                                let mut candidates = Vec::<P2PCandidate>::new();
+                                candidates.push(P2PCandidate {
+                                    candidate_type: P2PCandidateType::Host,
+                                    transport: P2PTransport::Udp,
+                                    addr: format!("{}:{}", advertise_ip, local_port),
+                                    priority: 200,
+                                });
+                                if let Some(stun_url) = stun_urls.first() {
+                                    match Self::stun_binding_srflx_on_socket(&socket, stun_url)
+                                        .await
+                                    {
+                                        Ok(addr) => {
+                                            candidates.push(P2PCandidate {
+                                                candidate_type: P2PCandidateType::Srflx,
+                                                transport: P2PTransport::Udp,
+                                                addr: addr.to_string(),
+                                                priority: 100,
+                                            });
+                                        }
+                                        Err(e) => {
+                                            warn!("STUN binding failed: {}", e);
+                                        }
+                                    }
+                                }
+
+                                let (punched_tx, punched_rx) = tokio::sync::watch::channel(None);
+                                p2p_udp_sockets
+                                    .insert(connection_id, (Arc::clone(&socket), punched_rx));
+                                // No data-plane loop answers punches
+                                #[cfg(target_os = "android")]
+                                drop(punched_tx);
+
                                 #[cfg(not(target_os = "android"))]
                                 {
                                     let engine = Arc::clone(&self.engine);
@@ -2699,6 +3801,21 @@ impl WorkerHandle for ClientWorker {
                                                 continue;
                                             };
 
+                                            if (flags & Self::P2P_UDP_FLAG_PUNCH) != 0 {
+                                                if Self::p2p_udp_handle_punch(
+                                                    &socket,
+                                                    from,
+                                                    flags,
+                                                    &buf[..n],
+                                                    &connection_id,
+                                                )
+                                                .await
+                                                {
+                                                    let _ = punched_tx.send(Some(from));
+                                                }
+                                                continue;
+                                            }
+
                                             if (flags & Self::P2P_UDP_FLAG_ACK) != 0 {
                                                 // acks are consumed by sender path
                                                 continue;
@@ -2759,6 +3876,7 @@ impl WorkerHandle for ClientWorker {
                                                     min_keep: min_keep as usize,
                                                 };
 
+                                            reload_idle_model(&engine).await;
                                             let token_stream_res = {
                                                 let engine_guard = engine.lock().await;
                                                 let engine_ref = match engine_guard.as_ref() {
@@ -2970,31 +4088,6 @@ impl WorkerHandle for ClientWorker {
                                     });
                                 }
 
-                                let mut candidates = Vec::<P2PCandidate>::new();
-                                candidates.push(P2PCandidate {
-                                    candidate_type: P2PCandidateType::Host,
-                                    transport: P2PTransport::Udp,
-                                    addr: format!("{}:{}", advertise_ip, local_port),
-                                    priority: 200,
-                                });
-                                if let Some(stun_url) = stun_urls.first() {
-                                    match Self::stun_binding_srflx_on_socket(&socket, stun_url)
-                                        .await
-                                    {
-                                        Ok(addr) => {
-                                            candidates.push(P2PCandidate {
-                                                candidate_type: P2PCandidateType::Srflx,
-                                                transport: P2PTransport::Udp,
-                                                addr: addr.to_string(),
-                                                priority: 100,
-                                            });
-                                        }
-                                        Err(e) => {
-                                            warn!("STUN binding failed: {}", e);
-                                        }
-                                    }
-                                }
-
                                 #[cfg(not(target_os = "android"))]
                                 if let Some(turn_url) = turn_urls.first() {
                                     let writer = Arc::clone(&self.writer);
@@ -3175,6 +4268,7 @@ impl WorkerHandle for ClientWorker {
                                                             min_keep: min_keep as usize,
                                                         };
 
+                                                        reload_idle_model(&engine).await;
                                                         let token_stream_res = {
                                                             let engine_guard = engine.lock().await;
                                                             let engine_ref = match engine_guard
@@ -3439,6 +4533,44 @@ impl WorkerHandle for ClientWorker {
                                     continue;
                                 }
 
+                                // Punch through both NATs over UDP first, then try
+                                // TCP and TURN; gpuf-s keeps relaying if all fail
+                                if let Some((socket, punched)) =
+                                    p2p_udp_sockets.get(&connection_id).cloned()
+                                {
+                                    let peers: Vec<SocketAddr> = candidates
+                                        .iter()
+                                        .filter(|c| {
+                                            c.transport == P2PTransport::Udp
+                                                && matches!(
+                                                    c.candidate_type,
+                                                    P2PCandidateType::Host
+                                                        | P2PCandidateType::Srflx
+                                                )
+                                        })
+                                        .filter_map(|c| c.addr.parse().ok())
+                                        .collect();
+                                    if !peers.is_empty() {
+                                        if let Some(peer) = Self::p2p_udp_punch(
+                                            &socket,
+                                            punched,
+                                            &peers,
+                                            &connection_id,
+                                        )
+                                        .await
+                                        {
+                                            info!("P2P UDP path to {} open", peer);
+                                            let established = CommandV2::P2PConnectionEstablished {
+                                                peer_id: source_client_id,
+                                                connection_id,
+                                                connection_type: P2PConnectionType::Direct,
+                                            };
+                                            self.send_command_v2(established).await?;
+                                            continue;
+                                        }
+                                    }
+                                }
+
                                 // Try direct TCP connect to host/srflx candidates.
                                 let mut last_err: Option<anyhow::Error> = None;
                                 for c in &candidates {
@@ -3619,23 +4751,30 @@ fn load_root_cert(path: &str) -> anyhow::Result<Vec<u8>> {
     Ok(certs.into_iter().flatten().collect())
 }
 
+/// TLS connection to the proxy port of the server at `addr`
 #[cfg(not(target_os = "android"))]
-pub async fn create_proxy_connection(
-    args: Args,
+async fn connect_proxy_tls(
+    args: &Args,
     addr: std::net::IpAddr,
-    proxy_conn_id: [u8; 16],
-    cert_chain_path: String,
-) -> Result<()> {
+    cert_chain_path: &str,
+) -> Result<tokio_rustls::client::TlsStream<TcpStream>> {
     // DONE: addr is sent to server addr
-    let addr_str = format!("{}:{}", addr.to_string(), args.proxy_port);
-    let addr = addr_str.to_socket_addrs()?.next().ok_or_else(|| {
-        std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Invalid server address or port",
-        )
-    })?;
-
-    let tcp_stream = match TcpStream::connect(addr).await {
+    let addr = SocketAddr::new(addr, args.proxy_port);
+
+    let tcp_stream = match proxy::server_proxy(args) {
+        // Through a proxy the server is reached by name, which the proxy resolves
+        Some(server_proxy) => {
+            proxy::connect(
+                Some(server_proxy),
+                &args.server_addr,
+                args.proxy_port,
+                args.ip_version,
+            )
+            .await
+        }
+        None => TcpStream::connect(addr).await,
+    };
+    let tcp_stream = match tcp_stream {
         Ok(stream) => stream,
         Err(e) => {
             error!(" create proxy connection failed {}: {}", addr, e);
@@ -3643,61 +4782,59 @@ pub async fn create_proxy_connection(
         }
     };
 
-    match tcp_stream.set_nodelay(true) {
-        Ok(_) => info!("Set nodelay for proxy connection {:?}", proxy_conn_id),
-        Err(e) => error!(
-            "Failed to set nodelay for proxy connection {:?}: {}",
-            proxy_conn_id, e
-        ),
-    };
+    if let Err(e) = tcp_stream.set_nodelay(true) {
+        error!(
+            "Failed to set nodelay for proxy connection to {}: {}",
+            addr, e
+        );
+    }
 
-    let cert = match load_root_cert(cert_chain_path.as_str()) {
-        Ok(cert) => cert,
+    let config = match crate::util::tls::client_config(
+        cert_chain_path,
+        args.ca_bundle.as_deref(),
+        &args.server_cert_pin,
+    ) {
+        Ok(config) => config,
         Err(e) => {
-            error!("Failed to load root cert: {}", e);
-            return Err(e.into());
+            error!("Failed to load trusted certificates: {:#}", e);
+            return Err(e);
         }
     };
 
-    let mut root_store = RootCertStore::empty();
-    match root_store.add(cert[0].clone()) {
-        Ok(_) => info!("Add root cert for proxy connection {:?}", proxy_conn_id),
-        Err(e) => error!(
-            "Failed to add root cert for proxy connection {:?}: {}",
-            proxy_conn_id, e
-        ),
-    };
-
-    info!(
-        " proxy_conn_id {:?} Connected to proxy port.",
-        proxy_conn_id
-    );
-
-    let config = ClientConfig::builder()
-        .with_root_certificates(root_store)
-        .with_no_client_auth();
-
     let connector = TlsConnector::from(Arc::new(config));
 
     let server_addr_clone = args.server_addr.clone();
-    let server_addr_clone2 = args.server_addr.clone();
     let server_name = if let Ok(ip) = server_addr_clone.parse::<std::net::IpAddr>() {
         // For IP address
         ServerName::try_from(ip.to_string())
             .map_err(|_| anyhow::anyhow!("Invalid server name: {}", server_addr_clone))?
     } else {
         // For domain name
-        ServerName::try_from(args.server_addr)
-            .map_err(|_| anyhow::anyhow!("Invalid server name: {}", server_addr_clone2))?
+        ServerName::try_from(args.server_addr.clone())
+            .map_err(|_| anyhow::anyhow!("Invalid server name: {}", server_addr_clone))?
     };
 
-    let mut tls_proxy_stream = match connector.connect(server_name, tcp_stream).await {
-        Ok(stream) => stream,
+    match connector.connect(server_name, tcp_stream).await {
+        Ok(stream) => Ok(stream),
         Err(e) => {
             error!("rustls: {}", e);
-            return Err(anyhow!("Failed to connect to proxy port: {}", e));
+            Err(anyhow!("Failed to connect to proxy port: {}", e))
         }
-    };
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+pub async fn create_proxy_connection(
+    args: Args,
+    addr: std::net::IpAddr,
+    proxy_conn_id: [u8; 16],
+    cert_chain_path: String,
+) -> Result<()> {
+    let mut tls_proxy_stream = connect_proxy_tls(&args, addr, &cert_chain_path).await?;
+    info!(
+        " proxy_conn_id {:?} Connected to proxy port.",
+        proxy_conn_id
+    );
 
     let notify_cmd = Command::V1(CommandV1::NewProxyConn {
         proxy_conn_id: proxy_conn_id.clone(),
@@ -3752,6 +4889,30 @@ pub async fn create_proxy_connection(
     }
 }
 
+/// Round trip to the control port and bandwidth over a probe connection to
+/// the proxy port
+#[cfg(not(target_os = "android"))]
+pub async fn probe_network(args: &Args, addr: std::net::IpAddr) -> Result<NetworkQuality> {
+    let host = match proxy::server_proxy(args) {
+        Some(_) => args.server_addr.clone(),
+        None => addr.to_string(),
+    };
+    let rtt = network_info::measure_rtt(
+        proxy::server_proxy(args),
+        &host,
+        args.control_port,
+        args.ip_version,
+    )
+    .await?;
+    let mut stream = connect_proxy_tls(args, addr, &args.cert_chain_path).await?;
+    network_info::probe_bandwidth(&mut stream, rtt, network_info::PROBE_BYTES).await
+}
+
+#[cfg(target_os = "android")]
+pub async fn probe_network(_args: &Args, _addr: std::net::IpAddr) -> Result<NetworkQuality> {
+    Err(anyhow!("Network probing needs TLS proxy connections"))
+}
+
 #[cfg(target_os = "android")]
 pub async fn create_proxy_connection(
     args: Args,
@@ -3763,13 +4924,7 @@ pub async fn create_proxy_connection(
     warn!("Android TLS proxy connections are simplified - full TLS support requires additional configuration");
 
     // For now, just establish TCP connection without TLS
-    let addr_str = format!("{}:{}", addr.to_string(), args.proxy_port);
-    let addr = addr_str.to_socket_addrs()?.next().ok_or_else(|| {
-        std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "Invalid server address or port",
-        )
-    })?;
+    let addr = SocketAddr::new(addr, args.proxy_port);
 
     let mut tcp_stream = match TcpStream::connect(addr).await {
         Ok(stream) => stream,
@@ -19,6 +19,10 @@ impl ClientWorker {
     pub(super) const P2P_UDP_MAGIC: [u8; 4] = *b"P2PU";
     pub(super) const P2P_UDP_VERSION: u8 = 1;
     pub(super) const P2P_UDP_FLAG_ACK: u8 = 0x01;
+    /// Hole punching probe, the payload is the connection id
+    pub(super) const P2P_UDP_FLAG_PUNCH: u8 = 0x02;
+    pub(super) const P2P_UDP_PUNCH_INTERVAL: Duration = Duration::from_millis(100);
+    pub(super) const P2P_UDP_PUNCH_TIMEOUT: Duration = Duration::from_secs(3);
     pub(super) const P2P_UDP_HEADER_LEN: usize = 4 + 1 + 1 + 4 + 2 + 2; // magic + version + flags + msg_id + frag_idx + frag_cnt
     pub(super) const P2P_UDP_MTU_PAYLOAD: usize = 1200;
 
@@ -60,6 +64,75 @@ impl ClientWorker {
         let _ = socket.send_to(&hdr, to).await;
     }
 
+    pub(super) async fn p2p_udp_send_punch(
+        socket: &UdpSocket,
+        to: std::net::SocketAddr,
+        connection_id: &[u8; 16],
+        ack: bool,
+    ) {
+        let flags = if ack {
+            Self::P2P_UDP_FLAG_PUNCH | Self::P2P_UDP_FLAG_ACK
+        } else {
+            Self::P2P_UDP_FLAG_PUNCH
+        };
+        let mut pkt = Self::p2p_udp_make_header(flags, 0, 0, 0).to_vec();
+        pkt.extend_from_slice(connection_id);
+        let _ = socket.send_to(&pkt, to).await;
+    }
+
+    /// Answer a punch probe received by the data-plane loop; true when it
+    /// belongs to `connection_id` and the path from `from` is open
+    pub(super) async fn p2p_udp_handle_punch(
+        socket: &UdpSocket,
+        from: std::net::SocketAddr,
+        flags: u8,
+        datagram: &[u8],
+        connection_id: &[u8; 16],
+    ) -> bool {
+        if datagram.get(Self::P2P_UDP_HEADER_LEN..) != Some(&connection_id[..]) {
+            return false;
+        }
+        if (flags & Self::P2P_UDP_FLAG_ACK) == 0 {
+            Self::p2p_udp_send_punch(socket, from, connection_id, true).await;
+        }
+        true
+    }
+
+    /// Probe the UDP candidates of the peer, which probes ours at the same
+    /// time, until the data-plane loop reports a probe of the peer through
+    /// `punched`. Both NATs then have a mapping for the pair of addresses.
+    pub(super) async fn p2p_udp_punch(
+        socket: &UdpSocket,
+        mut punched: tokio::sync::watch::Receiver<Option<std::net::SocketAddr>>,
+        peers: &[std::net::SocketAddr],
+        connection_id: &[u8; 16],
+    ) -> Option<std::net::SocketAddr> {
+        let punch = async {
+            let mut interval = tokio::time::interval(Self::P2P_UDP_PUNCH_INTERVAL);
+            loop {
+                let peer = *punched.borrow_and_update();
+                if peer.is_some() {
+                    return peer;
+                }
+                tokio::select! {
+                    _ = interval.tick() => {
+                        for peer in peers {
+                            Self::p2p_udp_send_punch(socket, *peer, connection_id, false).await;
+                        }
+                    }
+                    changed = punched.changed() => {
+                        // The data-plane loop is gone
+                        changed.ok()?;
+                    }
+                }
+            }
+        };
+        timeout(Self::P2P_UDP_PUNCH_TIMEOUT, punch)
+            .await
+            .ok()
+            .flatten()
+    }
+
     pub(super) async fn p2p_udp_send_reliable(
         socket: &UdpSocket,
         to: std::net::SocketAddr,
@@ -240,9 +313,11 @@ impl ClientWorker {
             return Err(anyhow!("Invalid STUN url: {stun_url}"));
         };
 
+        // An address the socket can reach, of the same family
+        let ipv4 = socket.local_addr()?.is_ipv4();
         let server = (host.as_str(), port)
             .to_socket_addrs()?
-            .next()
+            .find(|addr| addr.is_ipv4() == ipv4)
             .ok_or_else(|| anyhow!("STUN host resolve failed"))?;
 
         let txid = Self::stun_new_txid();
@@ -606,6 +681,7 @@ impl ClientWorker {
         let rest = rest.strip_prefix("//").unwrap_or(rest);
         let (host, port) = rest.rsplit_once(':')?;
         let port: u16 = port.parse().ok()?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
         Some((host.to_string(), port))
     }
 
@@ -1,12 +1,13 @@
 use crate::handle::{WSWorker, WorkerHandle};
 use crate::util::cmd::Args;
+use crate::util::proxy;
 
 use anyhow::Result;
 use futures_util::StreamExt;
 use std::future::Future;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tokio_tungstenite::connect_async;
+use tokio_tungstenite::{client_async, connect_async, MaybeTlsStream};
 
 //use futures_util::SinkExt;
 //use tokio_tungstenite::{WebSocketStream,tungstenite::protocol::Message};
@@ -14,7 +15,17 @@ use tokio_tungstenite::connect_async;
 impl WSWorker {
     pub async fn new(args: Args) -> Result<Self> {
         let url = "ws://example.com/ws";
-        let (ws_stream, _) = connect_async(url).await?;
+        let (ws_stream, _) = match proxy::server_proxy(&args) {
+            Some(server_proxy) => {
+                let parsed = url::Url::parse(url)?;
+                let host = parsed.host_str().unwrap_or_default();
+                let port = parsed.port_or_known_default().unwrap_or(80);
+                let stream =
+                    proxy::connect(Some(server_proxy), host, port, args.ip_version).await?;
+                client_async(url, MaybeTlsStream::Plain(stream)).await?
+            }
+            None => connect_async(url).await?,
+        };
         let (write, read) = ws_stream.split();
         Ok(Self {
             reader: Arc::new(Mutex::new(read)),
@@ -0,0 +1,155 @@
+//! Typed jobs the server dispatches besides proxied HTTP and inference
+//! tasks. Each kind runs through a handler registered when the worker
+//! starts; the registry rejects kinds without one, tracks the jobs running
+//! and bounds each by its timeout.
+
+use anyhow::{anyhow, Result};
+use common::{JobKind, JobOutput, JobSpec};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub type JobFuture = Pin<Box<dyn Future<Output = Result<JobOutput>> + Send>>;
+type JobHandler = Arc<dyn Fn(JobSpec) -> JobFuture + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct JobRegistry {
+    handlers: HashMap<JobKind, JobHandler>,
+    /// Kind and start of each running job, by id
+    running: Arc<Mutex<HashMap<String, (JobKind, Instant)>>>,
+}
+
+/// Removes a job from the running ones however its future ends
+struct RunningJob {
+    job_id: String,
+    running: Arc<Mutex<HashMap<String, (JobKind, Instant)>>>,
+}
+
+impl Drop for RunningJob {
+    fn drop(&mut self) {
+        if let Ok(mut running) = self.running.lock() {
+            running.remove(&self.job_id);
+        }
+    }
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run jobs of `kind` with `handler`, replacing an earlier one
+    pub fn register<F, Fut>(&mut self, kind: JobKind, handler: F)
+    where
+        F: Fn(JobSpec) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<JobOutput>> + Send + 'static,
+    {
+        self.handlers
+            .insert(kind, Arc::new(move |spec| Box::pin(handler(spec))));
+    }
+
+    /// Start `spec` as job `job_id`, failing when no handler runs its kind or
+    /// a job with the same id still runs. The returned future yields the
+    /// job's output, or an error once `timeout` passes.
+    pub fn accept(
+        &self,
+        job_id: &str,
+        spec: JobSpec,
+        timeout: Option<Duration>,
+    ) -> Result<impl Future<Output = Result<JobOutput>> + Send> {
+        let kind = spec.kind();
+        let handler = self
+            .handlers
+            .get(&kind)
+            .ok_or_else(|| anyhow!("No handler for {:?} jobs on this worker", kind))?;
+        {
+            let mut running = self
+                .running
+                .lock()
+                .map_err(|_| anyhow!("Job registry poisoned"))?;
+            if running.contains_key(job_id) {
+                return Err(anyhow!("Job {} is already running", job_id));
+            }
+            running.insert(job_id.to_string(), (kind, Instant::now()));
+        }
+
+        let guard = RunningJob {
+            job_id: job_id.to_string(),
+            running: Arc::clone(&self.running),
+        };
+        let job = handler(spec);
+        Ok(async move {
+            let _guard = guard;
+            match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, job)
+                    .await
+                    .map_err(|_| anyhow!("Job timed out after {:?}", timeout))?,
+                None => job.await,
+            }
+        })
+    }
+
+    /// Running jobs with their kind and how long they have run
+    pub fn running(&self) -> Vec<(String, JobKind, Duration)> {
+        self.running
+            .lock()
+            .map(|running| {
+                running
+                    .iter()
+                    .map(|(id, (kind, started))| (id.clone(), *kind, started.elapsed()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[tokio::test]
+async fn test_job_registry() {
+    let mut jobs = JobRegistry::new();
+    jobs.register(JobKind::Inference, |spec| async move {
+        match spec {
+            JobSpec::Inference { prompt, .. } => Ok(JobOutput::Text {
+                completion_tokens: prompt.split_whitespace().count() as u32,
+                text: prompt,
+            }),
+            _ => Err(anyhow!("Not an inference job")),
+        }
+    });
+    jobs.register(JobKind::HealthProbe, |_| async move {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Err(anyhow!("Engine did not answer"))
+    });
+    let inference = |prompt: &str| JobSpec::Inference {
+        model: None,
+        prompt: prompt.to_string(),
+        max_tokens: 16,
+        inputs: Vec::new(),
+    };
+
+    let job = jobs.accept("a", inference("hello there"), None).unwrap();
+    assert_eq!(jobs.running().len(), 1);
+    // Ids stay taken until the job ends
+    assert!(jobs.accept("a", inference("again"), None).is_err());
+    assert_eq!(
+        job.await.unwrap(),
+        JobOutput::Text {
+            text: "hello there".to_string(),
+            completion_tokens: 2
+        }
+    );
+    assert!(jobs.running().is_empty());
+    // A dropped job frees its id too
+    assert!(jobs.accept("a", inference("again"), None).is_ok());
+    assert!(jobs.running().is_empty());
+
+    assert!(jobs.accept("b", JobSpec::Benchmark, None).is_err());
+
+    let probe = jobs
+        .accept("c", JobSpec::HealthProbe, Some(Duration::from_millis(10)))
+        .unwrap();
+    assert_eq!(jobs.running()[0].1, JobKind::HealthProbe);
+    assert!(probe.await.unwrap_err().to_string().contains("timed out"));
+    assert!(jobs.running().is_empty());
+}
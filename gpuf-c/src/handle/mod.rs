@@ -3,13 +3,14 @@ pub mod worker_sdk;
 pub mod handle_tcp;
 pub mod handle_udp;
 pub mod handle_ws;
+pub mod jobs;
 use crate::util::cmd::{Args, EngineType, WorkerType};
 use crate::util::log_icon;
 use crate::util::network_info::SessionNetworkMonitor;
 // LLM engine is not available in lightweight Android version
 #[cfg(not(target_os = "android"))]
 use crate::llm_engine::Engine;
-use common::{DevicesInfo, EngineType as ClientEngineType, OsType, SystemInfo};
+use common::{BenchmarkResult, DevicesInfo, EngineType as ClientEngineType, OsType, SystemInfo};
 use tracing::{error, info};
 
 use anyhow::Result;
@@ -45,12 +46,14 @@ pub struct ClientWorker {
     devices_info: Arc<Vec<DevicesInfo>>,
     device_memtotal_gb: u32,
     device_total_tflops: u32,
+    benchmark: Option<BenchmarkResult>,
     network_monitor: Arc<Mutex<SessionNetworkMonitor>>,
     client_id: [u8; 16],
     os_type: OsType,
     engine_type: ClientEngineType,
     args: Args,
     cancel_state: Arc<CancelState>,
+    jobs: jobs::JobRegistry,
     #[cfg(not(target_os = "android"))]
     engine: Arc<Mutex<Option<AnyEngine>>>,
     #[cfg(target_os = "android")]
@@ -65,6 +68,46 @@ pub struct CancelState {
     pub notify: Notify,
 }
 
+/// Returned by a handler when the server announced it is shutting down or
+/// moved the worker to another server, so the caller reconnects after
+/// `delay`, to `reconnect_to` and the given ports when set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerRestart {
+    pub reconnect_to: Option<String>,
+    pub control_port: Option<u16>,
+    pub proxy_port: Option<u16>,
+    pub delay: std::time::Duration,
+}
+
+impl ServerRestart {
+    /// Point `args` at the server to reconnect to
+    pub fn apply(&self, args: &mut Args) {
+        if let Some(host) = &self.reconnect_to {
+            args.server_addr = host.clone();
+        }
+        if let Some(port) = self.control_port {
+            args.control_port = port;
+        }
+        if let Some(port) = self.proxy_port {
+            args.proxy_port = port;
+        }
+    }
+}
+
+impl std::fmt::Display for ServerRestart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.reconnect_to, self.control_port) {
+            (Some(host), Some(port)) => {
+                write!(f, "moved to server {}:{}", host, port)
+            }
+            (Some(host), None) => write!(f, "server restarting, reconnect to {}", host),
+            (None, _) => write!(f, "server restarting"),
+        }
+    }
+}
+
+impl std::error::Error for ServerRestart {}
+
 // WS worker
 #[allow(dead_code)]
 
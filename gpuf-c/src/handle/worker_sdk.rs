@@ -5,8 +5,6 @@ use std::io::Write;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, OnceLock};
 
-const CURRENT_VERSION: u32 = 1;
-
 fn client_id_to_hex(client_id: [u8; 16]) -> String {
     hex::encode(client_id)
 }
@@ -111,8 +109,8 @@ pub async fn perform_login(
         .try_into()
         .map_err(|_| anyhow!("Invalid client_id length (expected 16 bytes / 32 hex chars)"))?;
 
-    let login_cmd = CommandV1::Login {
-        version: CURRENT_VERSION,
+    let login_cmd = CommandV1::LoginV2 {
+        version: common::PROTOCOL_VERSION,
         auto_models,
         os_type: os_type(),
         client_id,
@@ -120,6 +118,9 @@ pub async fn perform_login(
         device_memtotal_gb: 0,
         device_total_tflops: 0,
         devices_info: vec![fixed_devices_info],
+        benchmark: Some(crate::util::benchmark::run()),
+        enrollment_token: None,
+        region: None,
     };
 
     common::write_command_sync(&mut stream, &Command::V1(login_cmd))
@@ -188,13 +189,17 @@ pub async fn start_worker_tasks_with_callback_ptr(
             fixed_devices_info.vendor_id = 0x41;
             fixed_devices_info.device_id = 0x1000;
 
-            let hb = CommandV1::Heartbeat {
+            let hb = CommandV1::HeartbeatV2 {
                 client_id,
                 system_info,
                 device_count: 1,
                 device_memtotal_gb: 0,
                 device_total_tflops: 0,
                 devices_info: vec![fixed_devices_info],
+                gpu_metrics: crate::util::system_info::collect_gpu_metrics(),
+                network_quality: None,
+                power: None,
+                version: Some(crate::util::version_info::worker_version(None)),
             };
 
             let send_result = (|| {
@@ -323,7 +328,7 @@ pub async fn start_worker_tasks_with_callback_ptr(
             };
 
             match v1 {
-                CommandV1::LoginResult {
+                CommandV1::LoginResultV2 {
                     success,
                     pods_model: _,
                     error,
@@ -364,7 +369,7 @@ pub async fn start_worker_tasks_with_callback_ptr(
                     let _ = common::write_command_sync(&mut stream, &Command::V1(model_status));
                     emit_callback(handler_callback, "MODEL_STATUS_SENT");
                 }
-                CommandV1::InferenceTask {
+                CommandV1::InferenceTaskV2 {
                     task_id,
                     prompt,
                     max_tokens,
@@ -404,7 +409,7 @@ pub async fn start_worker_tasks_with_callback_ptr(
                         emit_callback(handler_callback, &format!("INFERENCE_DONE - {task_id}"));
                     }
                 }
-                CommandV1::ChatInferenceTask {
+                CommandV1::ChatInferenceTaskV2 {
                     task_id,
                     model: _,
                     messages,
@@ -447,6 +452,20 @@ pub async fn start_worker_tasks_with_callback_ptr(
                         emit_callback(handler_callback, &format!("INFERENCE_DONE - {task_id}"));
                     }
                 }
+                CommandV1::Challenge {
+                    challenge_id, task, ..
+                } => {
+                    let client_id = WORKER_CLIENT_ID
+                        .get()
+                        .and_then(|m| m.lock().ok().and_then(|g| *g))
+                        .unwrap_or([0u8; 16]);
+                    let response = CommandV1::ChallengeResponse {
+                        client_id,
+                        challenge_id,
+                        answer: task.solve(),
+                    };
+                    let _ = common::write_command_sync(&mut stream, &Command::V1(response));
+                }
                 _ => {}
             }
             
@@ -9,7 +9,7 @@
 // ============================================================================
 
 #[cfg(target_os = "android")]
-use jni::objects::{JClass, JObject, JString};
+use jni::objects::{GlobalRef, JClass, JObject, JString, JValue};
 #[cfg(target_os = "android")]
 use jni::sys::{jboolean, jbyteArray, jfloat, jint, jlong, jstring};
 #[cfg(target_os = "android")]
@@ -25,7 +25,7 @@ use crate::{
     gpuf_load_multimodal_model, gpuf_multimodal_model, gpuf_multimodal_supports_vision,
     gpuf_start_generation_async, gpuf_stop_generation, gpuf_system_info, gpuf_version,
     llama_context, llama_model, manual_llama_completion, should_stop_generation,
-    GLOBAL_CONTEXT_PTR, GLOBAL_MODEL_PTR, MODEL_STATUS,
+    GLOBAL_CONTEXT_PTR, GLOBAL_INFERENCE_MUTEX, GLOBAL_MODEL_PTR, MODEL_STATUS,
 };
 
 // ============================================================================
@@ -273,7 +273,10 @@ pub extern "C" fn Java_com_gpuf_c_GPUEngine_startInferenceService(
     if model_ptr.is_null() {
         eprintln!("🔥 GPUFabric JNI: Failed to load model");
         let mut status = MODEL_STATUS.lock().unwrap();
-        status.set_error("Failed to load model");
+        // Keep the reason, e.g. a model too large for the free memory
+        if status.error_message.is_none() {
+            status.set_error("Failed to load model");
+        }
         return -3;
     }
 
@@ -379,7 +382,10 @@ pub extern "C" fn Java_com_gpuf_c_GPUEngine_startInferenceServiceAsync(
     if model_ptr.is_null() {
         eprintln!("🔥 GPUFabric JNI: Failed to load model");
         let mut status = MODEL_STATUS.lock().unwrap();
-        status.set_error("Failed to load model");
+        // Keep the reason, e.g. a model too large for the free memory
+        if status.error_message.is_none() {
+            status.set_error("Failed to load model");
+        }
         return -3;
     }
 
@@ -480,7 +486,10 @@ pub extern "C" fn Java_com_gpuf_c_GPUEngine_loadModelNew(
     if model_ptr.is_null() {
         eprintln!("🔥 GPUFabric JNI: Failed to load model");
         let mut status = MODEL_STATUS.lock().unwrap();
-        status.set_error("Failed to load model");
+        // Keep the reason, e.g. a model too large for the free memory
+        if status.error_message.is_none() {
+            status.set_error("Failed to load model");
+        }
         return -3;
     }
 
@@ -952,6 +961,145 @@ pub extern "C" fn Java_com_gpuf_c_GPUEngine_getGenerationStatus(
     }
 }
 
+/// Java callback of `generateTextStream`, handed to the generation loop as
+/// `user_data`
+#[cfg(target_os = "android")]
+struct JavaTokenSink<'a, 'local> {
+    env: &'a mut JNIEnv<'local>,
+    callback: &'a GlobalRef,
+}
+
+#[cfg(target_os = "android")]
+extern "C" fn java_token_callback(token: *const c_char, user_data: *mut c_void) {
+    if token.is_null() || user_data.is_null() {
+        return;
+    }
+    let sink = unsafe { &mut *(user_data as *mut JavaTokenSink) };
+    let token = unsafe { CStr::from_ptr(token) }.to_string_lossy();
+
+    let jtoken = match sink.env.new_string(token.as_ref()) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("❌ JNI: Failed to create token string: {:?}", e);
+            return;
+        }
+    };
+    if let Err(e) = sink.env.call_method(
+        sink.callback.as_obj(),
+        "onToken",
+        "(Ljava/lang/String;)V",
+        &[JValue::Object(&jtoken)],
+    ) {
+        // An exception in onToken must not stay pending across further JNI calls
+        let _ = sink.env.exception_clear();
+        eprintln!("❌ JNI: onToken failed, stopping generation: {:?}", e);
+        gpuf_stop_generation(std::ptr::null_mut());
+    }
+    // The attached thread lives for the whole generation, free refs as we go
+    let _ = sink.env.delete_local_ref(jtoken);
+}
+
+/// Generate text with the global model and stream it to a Java callback
+///
+/// Returns right away; generation runs on its own thread and calls
+/// `callback.onToken(String)` for every piece of text, then
+/// `callback.onComplete()`. `stopGeneration` ends it early.
+///
+/// Java signature:
+/// public static native int generateTextStream(String prompt, int maxTokens, float temperature, int topK, float topP, float repeatPenalty, Object callback);
+///
+/// @return 0 when generation started, -1 if the model is not loaded or the
+/// arguments are invalid
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_GPUEngine_generateTextStream(
+    mut env: JNIEnv,
+    _class: JClass,
+    prompt: JString,
+    max_tokens: jint,
+    temperature: jfloat,
+    top_k: jint,
+    top_p: jfloat,
+    repeat_penalty: jfloat,
+    callback: JObject,
+) -> jint {
+    println!("🚀 JNI: Starting streaming generation with Java callback");
+
+    if callback.is_null() {
+        eprintln!("❌ JNI: Callback is null");
+        return -1;
+    }
+    if GLOBAL_CONTEXT_PTR.load(Ordering::SeqCst).is_null() {
+        eprintln!("❌ JNI: Model or context not initialized");
+        return -1;
+    }
+
+    let prompt_cstr = match env
+        .get_string(&prompt)
+        .map(|s| CString::new(s.to_string_lossy().to_string()))
+    {
+        Ok(Ok(s)) => s,
+        _ => {
+            eprintln!("❌ JNI: Invalid prompt");
+            return -1;
+        }
+    };
+    let (jvm, callback) = match (env.get_java_vm(), env.new_global_ref(callback)) {
+        (Ok(jvm), Ok(callback)) => (jvm, callback),
+        _ => {
+            eprintln!("❌ JNI: Failed to keep callback for the generation thread");
+            return -1;
+        }
+    };
+
+    std::thread::spawn(move || {
+        let mut env = match jvm.attach_current_thread() {
+            Ok(env) => env,
+            Err(e) => {
+                eprintln!("❌ JNI: Failed to attach generation thread: {:?}", e);
+                return;
+            }
+        };
+
+        let generated = {
+            // Waits for other generations and keeps the model from being swapped out
+            let _inference_lock = GLOBAL_INFERENCE_MUTEX.lock().unwrap();
+            let ctx = GLOBAL_CONTEXT_PTR.load(Ordering::SeqCst);
+            if ctx.is_null() {
+                -1
+            } else {
+                let mut sink = JavaTokenSink {
+                    env: &mut env,
+                    callback: &callback,
+                };
+                gpuf_start_generation_async(
+                    ctx,
+                    prompt_cstr.as_ptr(),
+                    max_tokens,
+                    temperature,
+                    top_k,
+                    top_p,
+                    repeat_penalty,
+                    Some(java_token_callback),
+                    &mut sink as *mut JavaTokenSink as *mut c_void,
+                )
+            }
+        };
+        if generated < 0 {
+            eprintln!("❌ JNI: Streaming generation failed: {}", generated);
+        } else {
+            println!("✅ JNI: Streamed {} tokens", generated);
+        }
+
+        if let Err(e) = env.call_method(callback.as_obj(), "onComplete", "()V", &[]) {
+            let _ = env.exception_clear();
+            eprintln!("❌ JNI: onComplete failed: {:?}", e);
+        }
+    });
+
+    0
+}
+
 // ============================================================================
 // Multimodal API (Vision + Text)
 // ============================================================================
@@ -19,7 +19,11 @@ use std::sync::Mutex;
 use std::sync::OnceLock;
 
 use crate::{
-    get_remote_worker_status, set_remote_worker_model, start_remote_worker,
+    get_remote_worker_status, gpuf_model_cache_init, gpuf_model_cache_set_pinned,
+    remote_worker_on_backgrounded, remote_worker_on_foregrounded,
+    set_remote_worker_download_policy, set_remote_worker_idle_unload, set_remote_worker_model,
+    set_remote_worker_network_metered, set_remote_worker_power_policy,
+    set_remote_worker_power_status, set_remote_worker_request_limits, start_remote_worker,
     start_remote_worker_tasks_with_callback_ptr, stop_remote_worker,
 };
 
@@ -462,3 +466,315 @@ pub extern "C" fn Java_com_gpuf_c_RemoteWorker_stopRemoteWorker(
 
     result
 }
+
+// ============================================================================
+// JNI Function: Report Power Status
+// ============================================================================
+/// Reports battery and thermal state so the worker can pause when the device
+/// is hot or low on battery
+///
+/// Java signature:
+/// public static native int reportPowerStatus(
+///     int batteryPct,
+///     boolean charging,
+///     int temperatureC,
+///     int thermalStatus
+/// );
+///
+/// @param batteryPct Battery level, 0-100
+/// @param charging Whether the device is plugged in
+/// @param temperatureC Battery or skin temperature in °C
+/// @param thermalStatus PowerManager.getCurrentThermalStatus(), 0-6
+/// @return 0 on success, -1 on invalid values
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_RemoteWorker_reportPowerStatus(
+    _env: JNIEnv,
+    _class: JClass,
+    battery_pct: jint,
+    charging: jboolean,
+    temperature_c: jint,
+    thermal_status: jint,
+) -> jint {
+    set_remote_worker_power_status(battery_pct, charging != 0, temperature_c, thermal_status)
+}
+
+// ============================================================================
+// JNI Function: Set Power Policy
+// ============================================================================
+/// Sets the limits beyond which the worker stops accepting inference
+///
+/// Java signature:
+/// public static native int setPowerPolicy(
+///     int minBatteryPct,
+///     int maxTemperatureC,
+///     int maxThermalStatus
+/// );
+///
+/// @param minBatteryPct Pause below this level unless charging (default 20)
+/// @param maxTemperatureC Pause at or above this temperature (default 45)
+/// @param maxThermalStatus Pause at or above this thermal status (default 3, SEVERE)
+/// @return 0 on success, -1 on invalid values
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_RemoteWorker_setPowerPolicy(
+    _env: JNIEnv,
+    _class: JClass,
+    min_battery_pct: jint,
+    max_temperature_c: jint,
+    max_thermal_status: jint,
+) -> jint {
+    set_remote_worker_power_policy(min_battery_pct, max_temperature_c, max_thermal_status)
+}
+
+// ============================================================================
+// JNI Function: Set Download Policy
+// ============================================================================
+/// Limits model downloads to a bandwidth cap and a local time window
+///
+/// Java signature:
+/// public static native int setDownloadPolicy(
+///     long maxBytesPerSec,
+///     int windowStartHour,
+///     int windowEndHour,
+///     boolean unmeteredOnly
+/// );
+///
+/// @param maxBytesPerSec Bandwidth cap, 0 for unlimited
+/// @param windowStartHour First hour downloads may run, -1 for any hour
+/// @param windowEndHour Hour downloads stop, may wrap past midnight, -1 for any hour
+/// @param unmeteredOnly Wait while the network is metered
+/// @return 0 on success, -1 on an invalid window
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_RemoteWorker_setDownloadPolicy(
+    _env: JNIEnv,
+    _class: JClass,
+    max_bytes_per_sec: jlong,
+    window_start_hour: jint,
+    window_end_hour: jint,
+    unmetered_only: jboolean,
+) -> jint {
+    set_remote_worker_download_policy(
+        max_bytes_per_sec.max(0) as u64,
+        window_start_hour,
+        window_end_hour,
+        unmetered_only != 0,
+    )
+}
+
+// ============================================================================
+// JNI Function: Set Network Metered
+// ============================================================================
+/// Reports whether the active network is metered, from
+/// `ConnectivityManager.isActiveNetworkMetered()`
+///
+/// Java signature:
+/// public static native int setNetworkMetered(boolean metered);
+///
+/// @param metered Whether the active network is metered
+/// @return 0 on success
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_RemoteWorker_setNetworkMetered(
+    _env: JNIEnv,
+    _class: JClass,
+    metered: jboolean,
+) -> jint {
+    set_remote_worker_network_metered(metered != 0)
+}
+
+// ============================================================================
+// JNI Function: App Backgrounded
+// ============================================================================
+/// Call from `onStop` of the activity or a lifecycle observer. Pauses the
+/// worker and slows heartbeats so Android does not kill the process for
+/// battery use.
+///
+/// Java signature:
+/// public static native int onAppBackgrounded(boolean releaseModel);
+///
+/// @param releaseModel Free the model until the app is foregrounded again
+/// @return 0 on success
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_RemoteWorker_onAppBackgrounded(
+    _env: JNIEnv,
+    _class: JClass,
+    release_model: jboolean,
+) -> jint {
+    remote_worker_on_backgrounded(release_model != 0)
+}
+
+// ============================================================================
+// JNI Function: App Foregrounded
+// ============================================================================
+/// Call from `onStart`. Reloads a released model and resumes the worker.
+///
+/// Java signature:
+/// public static native int onAppForegrounded();
+///
+/// @return 0 on success, negative if reloading the model failed
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_RemoteWorker_onAppForegrounded(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jint {
+    let result = remote_worker_on_foregrounded();
+    if result != 0 {
+        eprintln!("❌ JNI: Failed to reload model (error: {})", result);
+    }
+    result
+}
+
+// ============================================================================
+// JNI Function: Set Idle Unload
+// ============================================================================
+/// Frees the model after a number of seconds without a task. 0 keeps it
+/// loaded.
+///
+/// Java signature:
+/// public static native int setIdleUnload(int secs);
+///
+/// @param secs Idle timeout in seconds, 0 to disable
+/// @return 0 on success, -1 if negative
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_RemoteWorker_setIdleUnload(
+    _env: JNIEnv,
+    _class: JClass,
+    secs: jint,
+) -> jint {
+    if secs < 0 {
+        return -1;
+    }
+    set_remote_worker_idle_unload(secs as u32)
+}
+
+// ============================================================================
+// JNI Function: Set Request Limits
+// ============================================================================
+/// Rejects tasks with a larger prompt or max_tokens before they reach the
+/// model. 0 turns a limit off.
+///
+/// Java signature:
+/// public static native int setRequestLimits(long maxPromptBytes, int maxTokens);
+///
+/// @param maxPromptBytes Largest prompt in bytes
+/// @param maxTokens Highest max_tokens a task may ask for
+/// @return 0 on success, -1 if negative
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_RemoteWorker_setRequestLimits(
+    _env: JNIEnv,
+    _class: JClass,
+    max_prompt_bytes: jlong,
+    max_tokens: jint,
+) -> jint {
+    if max_prompt_bytes < 0 || max_tokens < 0 {
+        return -1;
+    }
+    set_remote_worker_request_limits(max_prompt_bytes as u64, max_tokens as u32)
+}
+
+// ============================================================================
+// JNI Function: Open Model Cache
+// ============================================================================
+/// Keeps the models in a directory under a disk quota, deleting the least
+/// recently used ones first
+///
+/// Java signature:
+/// public static native int modelCacheInit(String modelsDir, long quotaBytes);
+///
+/// @param modelsDir Directory the app stores GGUF models in
+/// @param quotaBytes Disk quota, 0 for unlimited
+/// @return Number of models deleted to fit the quota, negative on failure
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_RemoteWorker_modelCacheInit(
+    mut env: JNIEnv,
+    _class: JClass,
+    models_dir: JString,
+    quota_bytes: jlong,
+) -> jint {
+    let models_dir: String = match env.get_string(&models_dir) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            eprintln!("❌ JNI: Failed to get models directory string: {}", e);
+            return -1;
+        }
+    };
+    let models_dir = match std::ffi::CString::new(models_dir) {
+        Ok(s) => s,
+        Err(_) => return -1,
+    };
+    let result = gpuf_model_cache_init(models_dir.as_ptr(), quota_bytes.max(0) as u64);
+    if result < 0 {
+        eprintln!("❌ JNI: Failed to open model cache (error: {})", result);
+    }
+    result
+}
+
+// ============================================================================
+// JNI Function: List Cached Models
+// ============================================================================
+/// Lists cached models, most recently used first
+///
+/// Java signature:
+/// public static native String listCachedModels();
+///
+/// @return JSON array of {"name", "size_bytes", "last_used", "pinned", "loaded"},
+///         or null when the cache is not open
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_RemoteWorker_listCachedModels(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    let Some(cache) = crate::util::model_cache::global() else {
+        eprintln!("❌ JNI: Model cache is not open (did you call modelCacheInit?)");
+        return std::ptr::null_mut();
+    };
+    let json = serde_json::to_string(&cache.list()).unwrap_or_else(|_| "[]".to_string());
+    match env.new_string(json) {
+        Ok(jstr) => jstr.into_raw(),
+        Err(e) => {
+            eprintln!("❌ JNI: Failed to create JString: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+// ============================================================================
+// JNI Function: Pin Cached Model
+// ============================================================================
+/// Pins a cached model so it is never deleted to fit the quota, or unpins it
+///
+/// Java signature:
+/// public static native int setModelPinned(String name, boolean pinned);
+///
+/// @param name File name of the model in the cache directory
+/// @param pinned Whether to keep the model
+/// @return 0 on success, negative if the model is not cached
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn Java_com_gpuf_c_RemoteWorker_setModelPinned(
+    mut env: JNIEnv,
+    _class: JClass,
+    name: JString,
+    pinned: jboolean,
+) -> jint {
+    let name: String = match env.get_string(&name) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            eprintln!("❌ JNI: Failed to get model name string: {}", e);
+            return -1;
+        }
+    };
+    let name = match std::ffi::CString::new(name) {
+        Ok(s) => s,
+        Err(_) => return -1,
+    };
+    gpuf_model_cache_set_pinned(name.as_ptr(), pinned != 0)
+}
@@ -10,6 +10,8 @@
 
 #![allow(dead_code)] // Ignore IDE warnings for sampler functions
 
+#[cfg(any(target_os = "android", target_os = "ios"))]
+use crate::ffi_error::{set_last_error, GpufErrorCode};
 #[cfg(target_os = "android")]
 use jni::objects::{JClass, JObject, JString};
 #[cfg(target_os = "android")]
@@ -30,6 +32,9 @@ use std::sync::atomic::{AtomicPtr, Ordering};
 use std::sync::{Arc, Mutex};
 
 const DEFAULT_LLAMA_THREADS: i32 = 4;
+/// Context size of SDK models, smaller when the memory plan shrinks it
+const SDK_N_CTX: u32 = 4096;
+const SDK_N_BATCH: u32 = 128;
 const DEFAULT_MTMD_THREADS: i32 = 4;
 struct Utf8EmitBuffer {
     buf: Vec<u8>,
@@ -81,17 +86,27 @@ impl Utf8EmitBuffer {
     }
 }
 
-// Global Tokio Runtime for async operations
-#[cfg(target_os = "android")]
-static TOKIO_RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
-    println!("🔧 Initializing Android-compatible single-threaded tokio runtime...");
-    let runtime = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .expect("Failed to create tokio runtime");
-    println!("✅ Tokio runtime initialized successfully");
-    runtime
-});
+// Global Tokio Runtime shared by the C API entry points, torn down by `gpuf_shutdown`
+#[cfg(any(target_os = "android", target_os = "ios"))]
+static TOKIO_RUNTIME: Mutex<Option<Arc<tokio::runtime::Runtime>>> = Mutex::new(None);
+
+/// The global runtime, created on first use or after `gpuf_shutdown`
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn get_global_runtime() -> Arc<tokio::runtime::Runtime> {
+    TOKIO_RUNTIME
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| {
+            println!("🔧 Initializing single-threaded tokio runtime...");
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to create tokio runtime");
+            println!("✅ Tokio runtime initialized successfully");
+            Arc::new(runtime)
+        })
+        .clone()
+}
 
 // Export modules
 #[cfg(not(target_os = "ios"))]
@@ -106,6 +121,11 @@ pub mod util;
 #[path = "handle/worker_sdk.rs"]
 pub mod worker_sdk;
 
+// Handle-based C API
+pub mod ffi_error;
+#[cfg(target_os = "android")]
+pub mod ffi_handles;
+
 // JNI wrapper modules
 #[cfg(target_os = "android")]
 pub mod jni_llama;
@@ -380,6 +400,10 @@ pub struct ModelStatusInfo {
     pub loading_status: String,
     pub is_loaded: bool,
     pub error_message: Option<String>,
+    /// Configuration and memory the current model was planned with
+    pub memory_plan: Option<util::memory_plan::MemoryPlan>,
+    /// The current model ran a warmup request since it was loaded
+    pub is_warm: bool,
 }
 
 impl ModelStatusInfo {
@@ -389,6 +413,8 @@ impl ModelStatusInfo {
             loading_status: "Not initialized".to_string(),
             is_loaded: false,
             error_message: None,
+            memory_plan: None,
+            is_warm: false,
         }
     }
 
@@ -404,19 +430,32 @@ impl ModelStatusInfo {
         self.loading_status = "Loaded".to_string();
         self.is_loaded = true;
         self.error_message = None;
+        if let Some(cache) = crate::util::model_cache::global() {
+            cache.touch_path(std::path::Path::new(model_path));
+        }
     }
 
     pub fn set_error(&mut self, error: &str) {
         self.loading_status = "Error".to_string();
         self.is_loaded = false;
+        self.is_warm = false;
         self.error_message = Some(error.to_string());
     }
 
+    /// The current model was freed for `reason` and is loaded again on demand
+    pub fn set_unloaded(&mut self, reason: &str) {
+        self.loading_status = reason.to_string();
+        self.is_loaded = false;
+        self.is_warm = false;
+    }
+
     pub fn clear(&mut self) {
         self.current_model = None;
         self.loading_status = "Not initialized".to_string();
         self.is_loaded = false;
+        self.is_warm = false;
         self.error_message = None;
+        self.memory_plan = None;
     }
 }
 
@@ -1446,9 +1485,15 @@ pub extern "C" fn gpuf_create_context(model: *mut llama_model) -> *mut llama_con
 
     println!("🔧 Creating context with correct llama.cpp parameters...");
 
+    let n_ctx = MODEL_STATUS
+        .lock()
+        .ok()
+        .and_then(|status| status.memory_plan)
+        .map_or(SDK_N_CTX, |plan| plan.config.n_ctx);
+
     let mut params = unsafe { llama_context_default_params() };
-    params.n_ctx = 4096;
-    params.n_batch = 128;
+    params.n_ctx = n_ctx;
+    params.n_batch = SDK_N_BATCH;
     params.n_threads = DEFAULT_LLAMA_THREADS;
     params.n_threads_batch = DEFAULT_LLAMA_THREADS;
     params.embeddings = false;
@@ -1846,6 +1891,34 @@ pub extern "C" fn gpuf_load_model(path: *const c_char) -> *mut llama_model {
         return std::ptr::null_mut();
     }
 
+    // Loading a model larger than the free memory gets the app killed
+    let path_str = unsafe { CStr::from_ptr(path) }
+        .to_string_lossy()
+        .to_string();
+    let plan = util::memory_plan::plan_for_file(
+        std::path::Path::new(&path_str),
+        util::memory_plan::MemoryConfig {
+            n_ctx: SDK_N_CTX,
+            n_gpu_layers: 0,
+            n_batch: SDK_N_BATCH,
+            n_seq: 1,
+        },
+    );
+    let plan = match plan {
+        Ok(plan) => plan,
+        Err(e) => {
+            set_last_error(GpufErrorCode::OutOfMemory, e.to_string());
+            if let Ok(mut status) = MODEL_STATUS.lock() {
+                status.set_error(&e.to_string());
+            }
+            return std::ptr::null_mut();
+        }
+    };
+    println!("📊 Memory plan: {}", plan);
+    if let Ok(mut status) = MODEL_STATUS.lock() {
+        status.memory_plan = Some(plan);
+    }
+
     println!("🔧 Loading model with safe parameters...");
 
     // Use safer parameter settings
@@ -3357,6 +3430,30 @@ pub extern "C" fn gpuf_version() -> *const c_char {
     version.into_raw()
 }
 
+// Per-GPU metrics as a JSON array, written to `output` with a trailing NUL.
+// Returns the JSON length, or -1 if `output` is null or too small.
+#[no_mangle]
+pub extern "C" fn gpuf_client_get_metrics(output: *mut c_char, max_length: c_int) -> c_int {
+    if output.is_null() || max_length <= 0 {
+        return -1;
+    }
+
+    let metrics = crate::util::system_info::collect_gpu_metrics();
+    let json = serde_json::to_string(&metrics).unwrap_or_else(|_| "[]".to_string());
+    let Ok(json_cstr) = CString::new(json) else {
+        return -1;
+    };
+
+    let bytes = json_cstr.to_bytes_with_nul();
+    if bytes.len() > max_length as usize {
+        return -1;
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(json_cstr.as_ptr(), output, bytes.len());
+    }
+    (bytes.len() - 1) as c_int
+}
+
 #[no_mangle]
 pub extern "C" fn gpuf_init() -> c_int {
     println!("🔥 GPUFabric Android LLaMA.cpp solution initialized");
@@ -3443,6 +3540,35 @@ pub extern "C" fn gpuf_cleanup() -> c_int {
     0
 }
 
+/// Stop the remote worker and tear down the global Tokio runtime (C API)
+///
+/// Call once when the app is done with the library, e.g. before unloading
+/// it. Functions called afterwards start a new runtime.
+///
+/// # Returns
+/// - `0`: Success
+#[cfg(any(target_os = "android", target_os = "ios"))]
+#[no_mangle]
+pub extern "C" fn gpuf_shutdown() -> c_int {
+    println!("🧹 GPUFabric C API: Shutting down");
+
+    #[cfg(target_os = "android")]
+    crate::ffi_handles::forget_connected_client();
+    stop_remote_worker();
+
+    let runtime = TOKIO_RUNTIME.lock().unwrap().take();
+    if let Some(runtime) = runtime {
+        match Arc::try_unwrap(runtime) {
+            Ok(runtime) => runtime.shutdown_timeout(std::time::Duration::from_secs(5)),
+            // Another thread is still in block_on; the runtime goes when it returns
+            Err(_) => println!("⚠️ C API: Tokio runtime still in use, dropping it later"),
+        }
+    }
+
+    println!("✅ C API: Shutdown complete");
+    0
+}
+
 // ============================================================================
 // Static buffers for Android memory safety
 // ============================================================================
@@ -3978,40 +4104,43 @@ pub extern "C" fn start_remote_worker(
 
     // Convert C strings to Rust strings
     let server_addr_str = if server_addr.is_null() {
-        eprintln!("❌ Error: server_addr is null");
-        return -1;
+        return set_last_error(GpufErrorCode::InvalidArgument, "server_addr is null");
     } else {
         match unsafe { std::ffi::CStr::from_ptr(server_addr).to_str() } {
             Ok(s) => s,
             Err(e) => {
-                eprintln!("❌ Error: Invalid server_addr UTF-8: {}", e);
-                return -1;
+                return set_last_error(
+                    GpufErrorCode::InvalidArgument,
+                    format!("Invalid server_addr UTF-8: {}", e),
+                );
             }
         }
     };
 
     let worker_type_str = if worker_type.is_null() {
-        eprintln!("❌ Error: worker_type is null");
-        return -1;
+        return set_last_error(GpufErrorCode::InvalidArgument, "worker_type is null");
     } else {
         match unsafe { std::ffi::CStr::from_ptr(worker_type).to_str() } {
             Ok(s) => s,
             Err(e) => {
-                eprintln!("❌ Error: Invalid worker_type UTF-8: {}", e);
-                return -1;
+                return set_last_error(
+                    GpufErrorCode::InvalidArgument,
+                    format!("Invalid worker_type UTF-8: {}", e),
+                );
             }
         }
     };
 
     let client_id_str = if client_id.is_null() {
-        eprintln!("❌ Error: client_id is null");
-        return -1;
+        return set_last_error(GpufErrorCode::InvalidArgument, "client_id is null");
     } else {
         match unsafe { std::ffi::CStr::from_ptr(client_id).to_str() } {
             Ok(s) => s,
             Err(e) => {
-                eprintln!("❌ Error: Invalid client_id UTF-8: {}", e);
-                return -1;
+                return set_last_error(
+                    GpufErrorCode::InvalidArgument,
+                    format!("Invalid client_id UTF-8: {}", e),
+                );
             }
         }
     };
@@ -4026,8 +4155,10 @@ pub extern "C" fn start_remote_worker(
         "TCP" => WorkerType::TCP,
         "WS" => WorkerType::WS,
         _ => {
-            eprintln!("❌ Error: Unknown worker type: {}", worker_type_str);
-            return -1;
+            return set_last_error(
+                GpufErrorCode::InvalidArgument,
+                format!("Unknown worker type: {}", worker_type_str),
+            );
         }
     };
 
@@ -4050,8 +4181,15 @@ pub extern "C" fn start_remote_worker(
         p2p_advertise_ip: None,
         p2p_udp_port: 40000,
         cert_chain_path: "".to_string(),
+        ca_bundle: None,
+        server_cert_pin: Vec::new(),
+        proxy_url: None,
+        server_proxy_url: None,
+        download_proxy_url: None,
+        ip_version: Default::default(),
         auto_models: false,
         hugging_face_hub_token: None,
+        hugging_face_hub_token_file: None,
         chat_template_path: None,
         standalone_llama: false,
         llama_model_path: None,
@@ -4060,7 +4198,46 @@ pub extern "C" fn start_remote_worker(
         llama_split_mode: LlamaSplitModeArg::Layer,
         llama_main_gpu: 0,
         llama_devices: None,
+        draft_model_path: None,
+        draft_tokens: 8,
+        parallel_slots: 0,
         stream_chunk_bytes: 256,
+        log_format: Default::default(),
+        download_max_rate: None,
+        download_window: None,
+        download_unmetered_only: false,
+        max_concurrent_downloads: 2,
+        model_publisher_keys: Vec::new(),
+        update_keys: Vec::new(),
+        model_cache_quota_gb: None,
+        skip_model_fit_check: false,
+        preload_model: false,
+        idle_unload_secs: 0,
+        max_prompt_bytes: 1024 * 1024,
+        max_prompt_tokens: 0,
+        max_tokens_limit: 0,
+        vllm_mode: Default::default(),
+        vllm_python: None,
+        vllm_gpus: None,
+        vllm_tensor_parallel_size: None,
+        vllm_max_model_len: None,
+        ollama_keep_alive: "-1".to_string(),
+        external_url: None,
+        trtllm_model_repository: None,
+        trtllm_tokenizer: None,
+        trtllm_image: None,
+        sd_model: None,
+        sd_binary: "sd".to_string(),
+        sd_server_url: None,
+        sd_model_name: None,
+        enrollment_token: None,
+        enrollment_token_file: None,
+        secrets_dir: None,
+        region: None,
+        install_service: false,
+        uninstall_service: false,
+        run_as_service: false,
+        command: None,
     };
 
 
@@ -4070,12 +4247,9 @@ pub extern "C" fn start_remote_worker(
         println!("🚀 C API: Initializing global worker with Android-native login...");
         std::io::stdout().flush().unwrap();
 
-        let local_runtime = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("Failed to create local tokio runtime");
+        let runtime = get_global_runtime();
 
-        match local_runtime.block_on(async {
+        match runtime.block_on(async {
             crate::handle::android_sdk::perform_android_login(
                 server_addr_str,
                 control_port as u16,
@@ -4085,22 +4259,19 @@ pub extern "C" fn start_remote_worker(
             .await
         }) {
             Ok(_) => 0,
-            Err(e) => {
-                eprintln!("❌ C API: Failed to start and login Android worker: {}", e);
-                -1
-            }
+            Err(e) => set_last_error(
+                GpufErrorCode::Network,
+                format!("Failed to start and login Android worker: {}", e),
+            ),
         }
     }
 
     #[cfg(target_os = "ios")]
     {
         // iOS: login via cross-platform worker_sdk.
-        let local_runtime = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("Failed to create local tokio runtime");
+        let runtime = get_global_runtime();
 
-        match local_runtime.block_on(async {
+        match runtime.block_on(async {
             crate::worker_sdk::perform_login(
                 server_addr_str,
                 control_port as u16,
@@ -4110,10 +4281,10 @@ pub extern "C" fn start_remote_worker(
             .await
         }) {
             Ok(_) => 0,
-            Err(e) => {
-                eprintln!("❌ C API: Failed to login iOS worker: {}", e);
-                -1
-            }
+            Err(e) => set_last_error(
+                GpufErrorCode::Network,
+                format!("Failed to login iOS worker: {}", e),
+            ),
         }
     }
 }
@@ -4166,10 +4337,10 @@ fn ensure_backend_initialized() -> c_int {
 ///
 /// # Returns
 /// - `0`: Success (model loaded and context created)
-/// - `-1`: Backend initialization failed
-/// - `-2`: Path conversion failed
-/// - `-3`: Model loading failed
-/// - `-4`: Context creation failed
+/// - `GpufErrorCode::NotInitialized`: Backend initialization failed
+/// - `GpufErrorCode::InvalidArgument`: Path conversion failed
+/// - `GpufErrorCode::ModelLoad`: Model loading failed
+/// - `GpufErrorCode::OutOfMemory`: Context creation failed
 ///
 /// # Safety
 /// Caller must ensure `model_path` is a valid null-terminated C string
@@ -4187,22 +4358,25 @@ pub extern "C" fn set_remote_worker_model(model_path: *const c_char) -> c_int {
 
     // 1. Ensure backend is initialized (only once per process)
     if ensure_backend_initialized() != 0 {
-        eprintln!("❌ C API: Backend initialization failed");
-        return -1;
+        return set_last_error(
+            GpufErrorCode::NotInitialized,
+            "Backend initialization failed",
+        );
     }
     println!("✅ C API: Backend ready");
 
     // 2. Convert C string to Rust string
     let path_str = if model_path.is_null() {
-        eprintln!("❌ C API: Model path is null");
-        return -2;
+        return set_last_error(GpufErrorCode::InvalidArgument, "Model path is null");
     } else {
         unsafe {
             match std::ffi::CStr::from_ptr(model_path).to_str() {
                 Ok(s) => s,
                 Err(e) => {
-                    eprintln!("❌ C API: Failed to convert model path: {}", e);
-                    return -2;
+                    return set_last_error(
+                        GpufErrorCode::InvalidArgument,
+                        format!("Failed to convert model path: {}", e),
+                    );
                 }
             }
         }
@@ -4217,20 +4391,25 @@ pub extern "C" fn set_remote_worker_model(model_path: *const c_char) -> c_int {
     // 4. Load new model and context
     let model_ptr = gpuf_load_model(model_path);
     if model_ptr.is_null() {
-        eprintln!("❌ C API: Failed to load model");
         let mut status = MODEL_STATUS.lock().unwrap();
+        // Refused by the memory plan, which set the reason
+        if let Some(reason) = status.error_message.clone() {
+            return set_last_error(GpufErrorCode::OutOfMemory, reason);
+        }
         status.set_error("Failed to load model");
-        return -3;
+        return set_last_error(
+            GpufErrorCode::ModelLoad,
+            format!("Failed to load model: {}", path_str),
+        );
     }
     println!("✅ C API: Model loaded: {}", path_str);
 
     let context_ptr = gpuf_create_context(model_ptr);
     if context_ptr.is_null() {
-        eprintln!("❌ C API: Failed to create context");
         let mut status = MODEL_STATUS.lock().unwrap();
         status.set_error("Failed to create context");
         unsafe { llama_model_free(model_ptr) }; // Clean up loaded model
-        return -4;
+        return set_last_error(GpufErrorCode::OutOfMemory, "Failed to create context");
     }
     println!("✅ C API: Context created");
 
@@ -4292,32 +4471,29 @@ pub extern "C" fn start_remote_worker_tasks() -> c_int {
 
     #[cfg(target_os = "android")]
     {
-        match TOKIO_RUNTIME
+        match get_global_runtime()
             .block_on(async { crate::handle::android_sdk::start_worker_tasks().await })
         {
             Ok(_) => 0 as c_int,
-            Err(e) => {
-                eprintln!("❌ C API: Failed to start background tasks: {}", e);
-                -1 as c_int
-            }
+            Err(e) => set_last_error(
+                GpufErrorCode::NotInitialized,
+                format!("Failed to start background tasks: {}", e),
+            ),
         }
     }
 
     #[cfg(target_os = "ios")]
     {
-        let local_runtime = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("Failed to create local tokio runtime");
+        let runtime = get_global_runtime();
 
-        match local_runtime
+        match runtime
             .block_on(async { crate::worker_sdk::start_worker_tasks_with_callback_ptr(None).await })
         {
             Ok(_) => 0 as c_int,
-            Err(e) => {
-                eprintln!("❌ C API: Failed to start background tasks: {}", e);
-                -1 as c_int
-            }
+            Err(e) => set_last_error(
+                GpufErrorCode::NotInitialized,
+                format!("Failed to start background tasks: {}", e),
+            ),
         }
     }
 }
@@ -4338,32 +4514,29 @@ pub extern "C" fn start_remote_worker_tasks_with_callback_ptr(
 
     #[cfg(target_os = "android")]
     {
-        match TOKIO_RUNTIME.block_on(async {
+        match get_global_runtime().block_on(async {
             crate::handle::android_sdk::start_worker_tasks_with_callback_ptr(callback).await
         }) {
             Ok(_) => 0 as c_int,
-            Err(e) => {
-                eprintln!("❌ C API: Failed to start background tasks with callback: {}", e);
-                -1 as c_int
-            }
+            Err(e) => set_last_error(
+                GpufErrorCode::NotInitialized,
+                format!("Failed to start background tasks with callback: {}", e),
+            ),
         }
     }
 
     #[cfg(target_os = "ios")]
     {
-        let local_runtime = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("Failed to create local tokio runtime");
+        let runtime = get_global_runtime();
 
-        match local_runtime
+        match runtime
             .block_on(async { crate::worker_sdk::start_worker_tasks_with_callback_ptr(callback).await })
         {
             Ok(_) => 0 as c_int,
-            Err(e) => {
-                eprintln!("❌ C API: Failed to start background tasks with callback: {}", e);
-                -1 as c_int
-            }
+            Err(e) => set_last_error(
+                GpufErrorCode::NotInitialized,
+                format!("Failed to start background tasks with callback: {}", e),
+            ),
         }
     }
 }
@@ -4384,17 +4557,15 @@ pub extern "C" fn stop_remote_worker() -> c_int {
 
     #[cfg(target_os = "android")]
     {
-        TOKIO_RUNTIME.block_on(async { crate::handle::android_sdk::stop_global_worker().await });
+        get_global_runtime()
+            .block_on(async { crate::handle::android_sdk::stop_global_worker().await });
         0
     }
 
     #[cfg(target_os = "ios")]
     {
-        let local_runtime = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("Failed to create local tokio runtime");
-        local_runtime.block_on(async { crate::worker_sdk::stop_global_worker().await });
+        let runtime = get_global_runtime();
+        runtime.block_on(async { crate::worker_sdk::stop_global_worker().await });
         0
     }
 }
@@ -4413,7 +4584,7 @@ pub extern "C" fn stop_remote_worker() -> c_int {
 ///
 /// # Returns
 /// - `0`: Success (status written to buffer)
-/// - `-1`: Error (buffer too small or other error)
+/// - `GpufErrorCode::InvalidArgument`: Buffer is null or too small
 ///
 /// # Safety
 /// Caller must ensure `buffer` is valid and can hold `buffer_size` bytes
@@ -4423,20 +4594,18 @@ pub extern "C" fn get_remote_worker_status(buffer: *mut c_char, buffer_size: siz
     println!("🔥 GPUFabric C API: Getting remote worker status");
 
     if buffer.is_null() {
-        eprintln!("❌ C API: Buffer is null");
-        return -1;
+        return set_last_error(GpufErrorCode::InvalidArgument, "Buffer is null");
     }
 
     if buffer_size == 0 {
-        eprintln!("❌ C API: Buffer size is zero");
-        return -1;
+        return set_last_error(GpufErrorCode::InvalidArgument, "Buffer size is zero");
     }
 
     // Get status from async function
     let status: String = {
         #[cfg(target_os = "android")]
         {
-            TOKIO_RUNTIME.block_on(async {
+            get_global_runtime().block_on(async {
                 crate::handle::android_sdk::get_worker_status()
                     .await
                     .unwrap_or_else(|_| "Error".to_string())
@@ -4445,11 +4614,8 @@ pub extern "C" fn get_remote_worker_status(buffer: *mut c_char, buffer_size: siz
 
         #[cfg(target_os = "ios")]
         {
-            let local_runtime = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .expect("Failed to create local tokio runtime");
-            local_runtime.block_on(async {
+            let runtime = get_global_runtime();
+            runtime.block_on(async {
                 crate::worker_sdk::get_worker_status()
                     .await
                     .unwrap_or_else(|_| "Error".to_string())
@@ -4463,20 +4629,24 @@ pub extern "C" fn get_remote_worker_status(buffer: *mut c_char, buffer_size: siz
     let status_c = match std::ffi::CString::new(status) {
         Ok(s) => s,
         Err(e) => {
-            eprintln!("❌ C API: Failed to convert status to C string: {}", e);
-            return -1;
+            return set_last_error(
+                GpufErrorCode::Internal,
+                format!("Failed to convert status to C string: {}", e),
+            );
         }
     };
 
     let status_bytes = status_c.as_bytes_with_nul();
 
     if status_bytes.len() > buffer_size {
-        eprintln!(
-            "❌ C API: Buffer too small (need {}, have {})",
-            status_bytes.len(),
-            buffer_size
+        return set_last_error(
+            GpufErrorCode::InvalidArgument,
+            format!(
+                "Buffer too small (need {}, have {})",
+                status_bytes.len(),
+                buffer_size
+            ),
         );
-        return -1;
     }
 
     unsafe {
@@ -4499,3 +4669,362 @@ pub extern "C" fn get_remote_worker_status(buffer: *mut c_char, buffer_size: siz
     }
     -1
 }
+
+/// Report battery and thermal state of the device (C API)
+///
+/// Android apps read these from `BatteryManager` and
+/// `PowerManager.getCurrentThermalStatus()`. Once reported, the worker stops
+/// reading sysfs, so keep calling this on changes.
+///
+/// # Returns
+/// - `0`: Success
+/// - `GpufErrorCode::InvalidArgument`: A value is out of range
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn set_remote_worker_power_status(
+    battery_pct: c_int,
+    charging: bool,
+    temperature_c: c_int,
+    thermal_status: c_int,
+) -> c_int {
+    if !(0..=100).contains(&battery_pct) || !(0..=6).contains(&thermal_status) {
+        return set_last_error(GpufErrorCode::InvalidArgument, "Invalid power status");
+    }
+    crate::util::power::report_status(common::PowerStatus {
+        battery_pct: battery_pct as u8,
+        charging,
+        temperature_c: temperature_c.clamp(0, u8::MAX as c_int) as u8,
+        thermal_status: thermal_status as u8,
+    });
+    0
+}
+
+/// Set when the worker pauses inference (C API)
+///
+/// Inference is paused below `min_battery_pct` unless charging, at
+/// `max_temperature_c` or above, and at `max_thermal_status` or above.
+/// Defaults are 20%, 45°C and THERMAL_STATUS_SEVERE (3).
+///
+/// # Returns
+/// - `0`: Success
+/// - `GpufErrorCode::InvalidArgument`: A value is out of range
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn set_remote_worker_power_policy(
+    min_battery_pct: c_int,
+    max_temperature_c: c_int,
+    max_thermal_status: c_int,
+) -> c_int {
+    if !(0..=100).contains(&min_battery_pct)
+        || !(1..=u8::MAX as c_int).contains(&max_temperature_c)
+        || !(1..=6).contains(&max_thermal_status)
+    {
+        return set_last_error(GpufErrorCode::InvalidArgument, "Invalid power policy");
+    }
+    crate::util::power::set_policy(crate::util::power::PowerPolicy {
+        min_battery_pct: min_battery_pct as u8,
+        max_temperature_c: max_temperature_c as u8,
+        max_thermal_status: max_thermal_status as u8,
+    });
+    0
+}
+
+/// Limit background model downloads (C API)
+///
+/// `max_bytes_per_sec` of 0 removes the bandwidth cap. Downloads run only
+/// from `window_start_hour` up to `window_end_hour` local time, wrapping past
+/// midnight, or at any hour when both are -1. With `unmetered_only`, they
+/// wait while `set_remote_worker_network_metered` reports a metered network.
+/// Downloads already running keep their bandwidth cap.
+///
+/// # Returns
+/// - `0`: Success
+/// - `GpufErrorCode::InvalidArgument`: The window is out of range
+#[cfg(any(target_os = "android", target_os = "ios"))]
+#[no_mangle]
+pub extern "C" fn set_remote_worker_download_policy(
+    max_bytes_per_sec: u64,
+    window_start_hour: c_int,
+    window_end_hour: c_int,
+    unmetered_only: bool,
+) -> c_int {
+    use crate::util::download_policy::{DownloadPolicy, DownloadWindow};
+
+    let window = match (window_start_hour, window_end_hour) {
+        (-1, -1) => None,
+        (start @ 0..=23, end @ 0..=24) => match DownloadWindow::new(start as u8, end as u8) {
+            Ok(window) => Some(window),
+            Err(e) => return set_last_error(GpufErrorCode::InvalidArgument, e),
+        },
+        _ => return set_last_error(GpufErrorCode::InvalidArgument, "Invalid download window"),
+    };
+    crate::util::download_policy::set_policy(DownloadPolicy {
+        max_bytes_per_sec: (max_bytes_per_sec > 0).then_some(max_bytes_per_sec),
+        unmetered_only,
+        window,
+    });
+    0
+}
+
+/// Report whether the active network is metered (C API)
+///
+/// Android apps read it from `ConnectivityManager.isActiveNetworkMetered()`
+/// and call this on network changes. Downloads held back by it resume within
+/// a minute of the network becoming unmetered.
+///
+/// # Returns
+/// - `0`: Success
+#[cfg(any(target_os = "android", target_os = "ios"))]
+#[no_mangle]
+pub extern "C" fn set_remote_worker_network_metered(metered: bool) -> c_int {
+    crate::util::download_policy::set_network_metered(metered);
+    0
+}
+
+/// Path of the model freed when the app went to the background
+#[cfg(target_os = "android")]
+static BACKGROUND_RELEASED_MODEL: Mutex<Option<String>> = Mutex::new(None);
+
+/// Path of the model freed after sitting idle
+#[cfg(target_os = "android")]
+static IDLE_RELEASED_MODEL: Mutex<Option<String>> = Mutex::new(None);
+
+/// Wait for the running generation to finish and free the model and
+/// context. Returns the path of the freed model, None when none was loaded.
+#[cfg(target_os = "android")]
+fn release_remote_worker_model(reason: &str) -> Option<String> {
+    let _swap_lock = MODEL_SWAP_LOCK.lock().unwrap();
+    // Blocks until the running generation is done
+    let _inference_lock = GLOBAL_INFERENCE_MUTEX.lock().unwrap();
+
+    let model = GLOBAL_MODEL_PTR.swap(std::ptr::null_mut(), Ordering::SeqCst);
+    let context = GLOBAL_CONTEXT_PTR.swap(std::ptr::null_mut(), Ordering::SeqCst);
+    if !context.is_null() {
+        unsafe { llama_free(context) };
+    }
+    if model.is_null() {
+        return None;
+    }
+    unsafe { llama_model_free(model) };
+    let mut status = MODEL_STATUS.lock().unwrap();
+    status.set_unloaded(reason);
+    println!("🧹 C API: Model released: {}", reason);
+    status.current_model.clone()
+}
+
+/// Free the model of the remote worker after no task used it for the idle
+/// timeout. Returns whether a model was loaded.
+#[cfg(target_os = "android")]
+pub(crate) fn release_idle_model() -> bool {
+    match release_remote_worker_model("Unloaded while idle") {
+        Some(path) => {
+            *IDLE_RELEASED_MODEL.lock().unwrap() = Some(path);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Load the model freed by `release_idle_model` again. None when no model
+/// was freed, else the result of `set_remote_worker_model`.
+#[cfg(target_os = "android")]
+pub(crate) fn reload_idle_model() -> Option<c_int> {
+    let path = IDLE_RELEASED_MODEL.lock().unwrap().take()?;
+    println!("🔄 C API: Reloading model released while idle: {}", path);
+    Some(match CString::new(path) {
+        Ok(path) => set_remote_worker_model(path.as_ptr()),
+        Err(_) => set_last_error(GpufErrorCode::InvalidArgument, "Model path contains NUL"),
+    })
+}
+
+/// App moved to the background (C API)
+///
+/// Pauses the remote worker: the server stops sending tasks, new tasks are
+/// rejected and heartbeats slow down to every 10 minutes. With
+/// `release_model`, waits for the running generation to finish and frees the
+/// model and context; `remote_worker_on_foregrounded` loads them again.
+///
+/// # Returns
+/// - `0`: Success
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn remote_worker_on_backgrounded(release_model: bool) -> c_int {
+    crate::handle::android_sdk::on_app_backgrounded();
+
+    if release_model {
+        if let Some(path) = release_remote_worker_model("Released while in background") {
+            *BACKGROUND_RELEASED_MODEL.lock().unwrap() = Some(path);
+        }
+    }
+    0
+}
+
+/// App returned to the foreground (C API)
+///
+/// Reloads a model released by `remote_worker_on_backgrounded` and resumes
+/// the remote worker.
+///
+/// # Returns
+/// - `0`: Success
+/// - Negative: Reloading the model failed, see `set_remote_worker_model`
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn remote_worker_on_foregrounded() -> c_int {
+    let mut result = 0;
+    if let Some(path) = BACKGROUND_RELEASED_MODEL.lock().unwrap().take() {
+        println!("🔄 C API: Reloading model released in background: {}", path);
+        result = match CString::new(path) {
+            Ok(path) => set_remote_worker_model(path.as_ptr()),
+            Err(_) => set_last_error(GpufErrorCode::InvalidArgument, "Model path contains NUL"),
+        };
+    }
+
+    crate::handle::android_sdk::on_app_foregrounded();
+    result
+}
+
+/// Set the idle unload timeout (C API)
+///
+/// Frees the model and context after `secs` seconds without a task and
+/// reloads them when the next task arrives. 0 keeps the model loaded.
+///
+/// # Returns
+/// - `0`: Success
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn set_remote_worker_idle_unload(secs: u32) -> c_int {
+    crate::util::runtime_config::RuntimeConfig::global().set_default_idle_unload_secs(secs);
+    0
+}
+
+/// Set the request limits (C API)
+///
+/// Tasks with a prompt, or sum of chat message contents, above
+/// `max_prompt_bytes`, or a `max_tokens` above `max_tokens`, fail right away
+/// with a `request_too_large` or `limit_exceeded` error instead of reaching
+/// the model. 0 turns a limit off. Prompts are limited to 1 MiB by default.
+///
+/// # Returns
+/// - `0`: Success
+#[cfg(target_os = "android")]
+#[no_mangle]
+pub extern "C" fn set_remote_worker_request_limits(
+    max_prompt_bytes: u64,
+    max_tokens: u32,
+) -> c_int {
+    let limits = crate::util::request_limits::RequestLimits {
+        max_prompt_bytes: usize::try_from(max_prompt_bytes).unwrap_or(usize::MAX),
+        max_prompt_tokens: 0,
+        max_tokens,
+    };
+    crate::util::request_limits::set_limits(limits);
+    0
+}
+
+/// Read a C string argument, or record why it is invalid
+#[cfg(any(target_os = "android", target_os = "ios"))]
+fn model_cache_arg<'a>(value: *const c_char, name: &str) -> Result<&'a str, c_int> {
+    if value.is_null() {
+        return Err(set_last_error(
+            GpufErrorCode::InvalidArgument,
+            format!("{} is null", name),
+        ));
+    }
+    unsafe { CStr::from_ptr(value) }.to_str().map_err(|e| {
+        set_last_error(
+            GpufErrorCode::InvalidArgument,
+            format!("Invalid UTF-8 in {}: {}", name, e),
+        )
+    })
+}
+
+/// Open the model cache at `models_dir` (C API)
+///
+/// Keeps the GGUF models in `models_dir` under `quota_bytes`, deleting the
+/// least recently used ones first, except pinned models and the loaded one.
+/// A quota of 0 is unlimited. Calling it again changes the quota.
+///
+/// # Returns
+/// - Number of models deleted to fit the quota
+/// - `GpufErrorCode::InvalidArgument`: The directory is invalid, or another
+///   one is already open
+#[cfg(any(target_os = "android", target_os = "ios"))]
+#[no_mangle]
+pub extern "C" fn gpuf_model_cache_init(models_dir: *const c_char, quota_bytes: u64) -> c_int {
+    let models_dir = match model_cache_arg(models_dir, "Models directory") {
+        Ok(dir) => dir,
+        Err(code) => return code,
+    };
+    match crate::util::model_cache::init(models_dir, Some(quota_bytes)) {
+        Ok(cache) => cache.make_room(0, &[]).len() as c_int,
+        Err(e) => set_last_error(GpufErrorCode::InvalidArgument, e.to_string()),
+    }
+}
+
+/// List cached models as JSON (C API)
+///
+/// Writes an array, most recently used first, of
+/// `{"name", "size_bytes", "last_used", "pinned", "loaded"}` objects.
+/// `last_used` is in Unix seconds.
+///
+/// # Returns
+/// - Length of the JSON written, without the NUL
+/// - `GpufErrorCode::NotInitialized`: `gpuf_model_cache_init` was not called
+/// - `GpufErrorCode::InvalidArgument`: Buffer is null or too small
+///
+/// # Safety
+/// Caller must ensure `buffer` is valid and can hold `buffer_size` bytes
+#[cfg(any(target_os = "android", target_os = "ios"))]
+#[no_mangle]
+pub extern "C" fn gpuf_model_cache_list(buffer: *mut c_char, buffer_size: size_t) -> c_int {
+    if buffer.is_null() || buffer_size == 0 {
+        return set_last_error(GpufErrorCode::InvalidArgument, "Buffer is null or empty");
+    }
+    let Some(cache) = crate::util::model_cache::global() else {
+        return set_last_error(GpufErrorCode::NotInitialized, "Model cache is not open");
+    };
+    let json = serde_json::to_string(&cache.list()).unwrap_or_else(|_| "[]".to_string());
+    let json = match CString::new(json) {
+        Ok(json) => json,
+        Err(e) => return set_last_error(GpufErrorCode::Internal, e.to_string()),
+    };
+    let bytes = json.as_bytes_with_nul();
+    if bytes.len() > buffer_size {
+        return set_last_error(
+            GpufErrorCode::InvalidArgument,
+            format!(
+                "Buffer too small (need {}, have {})",
+                bytes.len(),
+                buffer_size
+            ),
+        );
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer as *mut u8, bytes.len());
+    }
+    (bytes.len() - 1) as c_int
+}
+
+/// Pin or unpin a cached model (C API)
+///
+/// Pinned models are never deleted to fit the quota.
+///
+/// # Returns
+/// - `0`: Success
+/// - `GpufErrorCode::NotInitialized`: `gpuf_model_cache_init` was not called
+/// - `GpufErrorCode::InvalidArgument`: The model is not in the cache
+#[cfg(any(target_os = "android", target_os = "ios"))]
+#[no_mangle]
+pub extern "C" fn gpuf_model_cache_set_pinned(name: *const c_char, pinned: bool) -> c_int {
+    let name = match model_cache_arg(name, "Model name") {
+        Ok(name) => name,
+        Err(code) => return code,
+    };
+    let Some(cache) = crate::util::model_cache::global() else {
+        return set_last_error(GpufErrorCode::NotInitialized, "Model cache is not open");
+    };
+    match cache.set_pinned(name, pinned) {
+        Ok(()) => 0,
+        Err(e) => set_last_error(GpufErrorCode::InvalidArgument, e.to_string()),
+    }
+}
@@ -0,0 +1,239 @@
+//! `gpuf-c bench`: prompt processing (pp) and text generation (tg) speed of a
+//! GGUF model at several context and batch sizes, measured the way
+//! llama-bench does so numbers compare across workers. Prompts are tokens
+//! spread over the vocabulary, as the speed does not depend on the text.
+
+use super::chat::model_path;
+use super::{Engine, LlamaEngine};
+use crate::handle::TCPWorker;
+use crate::util::cmd::{Args, BenchArgs};
+use crate::util::memory_plan::{self, MemoryConfig};
+use anyhow::{anyhow, Result};
+use common::{format_bytes, ModelBenchmark, ModelBenchmarkRun};
+use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::context::LlamaContext;
+use llama_cpp_2::llama_backend::LlamaBackend;
+use llama_cpp_2::llama_batch::LlamaBatch;
+use llama_cpp_2::model::LlamaModel;
+use llama_cpp_2::token::LlamaToken;
+use std::num::NonZeroU32;
+use std::time::Instant;
+
+/// One measurement: a fresh context of `n_ctx` tokens evaluating a prompt in
+/// batches of `n_batch`, then generating from an empty context
+#[derive(Debug, Clone, Copy)]
+struct BenchCase {
+    n_ctx: u32,
+    n_batch: u32,
+    n_prompt: u32,
+    n_gen: u32,
+    repetitions: u32,
+}
+
+/// Load `bench.model` with the engine settings of `args`, measure every
+/// context and batch size and print a table, then upload the results when
+/// asked to
+pub async fn run(args: &Args, bench: &BenchArgs) -> Result<()> {
+    let path = model_path(&bench.model)?;
+    if !path.exists() {
+        return Err(anyhow!("Model file not found: {}", path.display()));
+    }
+    if bench.upload && args.client_id.is_none() {
+        return Err(anyhow!(
+            "--upload needs the client id of this worker, from --client-id or --config"
+        ));
+    }
+    if bench.ctx_sizes.is_empty() || bench.batch_sizes.is_empty() {
+        return Err(anyhow!("No context or batch size to measure"));
+    }
+
+    eprintln!("Loading {} ...", path.display());
+    // Offloaded layers are planned for the largest context
+    let mut engine = LlamaEngine::with_config(
+        path.to_string_lossy().to_string(),
+        bench.ctx_sizes.iter().copied().max().unwrap_or(args.n_ctx),
+        args.n_gpu_layers,
+        args.llama_split_mode.clone(),
+        args.llama_main_gpu,
+        args.llama_devices.clone(),
+    );
+    engine.init().await?;
+    if let Some(error) = engine.load_error().await {
+        return Err(anyhow!("Failed to load {}: {}", path.display(), error));
+    }
+    let (Some(backend), Some(model)) = (engine.cached_backend.clone(), engine.cached_model.clone())
+    else {
+        return Err(anyhow!("Model not loaded"));
+    };
+    let n_gpu_layers = engine
+        .memory_plan
+        .map_or(args.n_gpu_layers, |plan| plan.config.n_gpu_layers);
+
+    let mut benchmark = ModelBenchmark {
+        model: path.file_name().map_or_else(
+            || bench.model.clone(),
+            |name| name.to_string_lossy().into_owned(),
+        ),
+        model_bytes: std::fs::metadata(&path)?.len(),
+        n_gpu_layers,
+        runs: Vec::new(),
+    };
+    println!(
+        "{} ({}), {} GPU layers, {} repetitions",
+        benchmark.model,
+        format_bytes!(benchmark.model_bytes),
+        n_gpu_layers,
+        bench.repetitions
+    );
+    println!(
+        "| {:>6} | {:>7} | {:>12} | {:>12} | {:>10} | {:>10} |",
+        "n_ctx",
+        "n_batch",
+        format!("pp{} t/s", bench.n_prompt),
+        format!("tg{} t/s", bench.n_gen),
+        "VRAM",
+        "RAM"
+    );
+    for &n_ctx in &bench.ctx_sizes {
+        for &n_batch in &bench.batch_sizes {
+            let case = BenchCase {
+                n_ctx,
+                n_batch: n_batch.max(1),
+                n_prompt: bench.n_prompt.min(n_ctx),
+                n_gen: bench.n_gen.min(n_ctx),
+                repetitions: bench.repetitions.max(1),
+            };
+            let (vram_bytes, ram_bytes) = memory_plan::estimate_for_file(
+                &path,
+                MemoryConfig {
+                    n_ctx,
+                    n_gpu_layers,
+                    n_batch: case.n_batch,
+                    n_seq: 1,
+                },
+            )?;
+            let (backend, model) = (backend.clone(), model.clone());
+            let measured = tokio::task::spawn_blocking(move || {
+                let model = model
+                    .read()
+                    .map_err(|e| anyhow!("Failed to lock model: {:?}", e))?;
+                measure(&backend, &model, case)
+            })
+            .await?;
+            let (prompt_tokens_per_sec, gen_tokens_per_sec) = match measured {
+                Ok(speeds) => speeds,
+                Err(e) => {
+                    eprintln!("n_ctx {} with n_batch {} failed: {:#}", n_ctx, n_batch, e);
+                    continue;
+                }
+            };
+            let run = ModelBenchmarkRun {
+                n_ctx,
+                n_batch: case.n_batch,
+                n_prompt: case.n_prompt,
+                n_gen: case.n_gen,
+                prompt_tokens_per_sec,
+                gen_tokens_per_sec,
+                vram_bytes,
+                ram_bytes,
+            };
+            println!(
+                "| {:>6} | {:>7} | {:>12.1} | {:>12.1} | {:>10} | {:>10} |",
+                run.n_ctx,
+                run.n_batch,
+                run.prompt_tokens_per_sec,
+                run.gen_tokens_per_sec,
+                format_bytes!(run.vram_bytes),
+                format_bytes!(run.ram_bytes)
+            );
+            benchmark.runs.push(run);
+        }
+    }
+    // The worker connecting below loads its own engine
+    drop((engine, backend, model));
+
+    if bench.upload {
+        if benchmark.runs.is_empty() {
+            return Err(anyhow!("No results to upload"));
+        }
+        let worker = TCPWorker::new(args.clone()).await?;
+        worker.upload_benchmark(benchmark).await?;
+        eprintln!("Uploaded the results to {}", args.server_addr);
+    }
+    Ok(())
+}
+
+/// Average prompt processing and generation tokens per second of `case`
+fn measure(backend: &LlamaBackend, model: &LlamaModel, case: BenchCase) -> Result<(f32, f32)> {
+    let params = LlamaContextParams::default()
+        .with_n_ctx(NonZeroU32::new(case.n_ctx))
+        .with_n_batch(case.n_batch);
+    let mut context = model
+        .new_context(backend, params)
+        .map_err(|e| anyhow!("Failed to create context: {:?}", e))?;
+    let tokens = bench_tokens(model, case.n_prompt.max(case.n_gen));
+
+    // Compute buffers of this batch size are allocated on first use
+    decode(&mut context, &tokens[..1], 0, case.n_batch)?;
+    let (mut prompt_secs, mut gen_secs) = (0.0, 0.0);
+    for _ in 0..case.repetitions {
+        context.clear_kv_cache();
+        let started = Instant::now();
+        decode(
+            &mut context,
+            &tokens[..case.n_prompt as usize],
+            0,
+            case.n_batch,
+        )?;
+        prompt_secs += started.elapsed().as_secs_f64();
+
+        context.clear_kv_cache();
+        let started = Instant::now();
+        for (pos, token) in tokens[..case.n_gen as usize].iter().enumerate() {
+            decode(&mut context, std::slice::from_ref(token), pos, 1)?;
+        }
+        gen_secs += started.elapsed().as_secs_f64();
+    }
+
+    let rate = |tokens: u32, secs: f64| {
+        if secs > 0.0 {
+            (f64::from(tokens) * f64::from(case.repetitions) / secs) as f32
+        } else {
+            0.0
+        }
+    };
+    Ok((rate(case.n_prompt, prompt_secs), rate(case.n_gen, gen_secs)))
+}
+
+/// Evaluate `tokens` from position `start` in batches of `n_batch`
+fn decode(
+    context: &mut LlamaContext,
+    tokens: &[LlamaToken],
+    start: usize,
+    n_batch: u32,
+) -> Result<()> {
+    let mut batch = LlamaBatch::new(n_batch as usize, 1);
+    for (chunk_index, chunk) in tokens.chunks(n_batch as usize).enumerate() {
+        batch.clear();
+        for (i, token) in chunk.iter().enumerate() {
+            let pos = start + chunk_index * n_batch as usize + i;
+            batch
+                .add(*token, pos as i32, &[0], i == chunk.len() - 1)
+                .map_err(|e| anyhow!("Failed to add token to batch: {:?}", e))?;
+        }
+        context
+            .decode(&mut batch)
+            .map_err(|e| anyhow!("Failed to decode batch: {:?}", e))?;
+        // GPU backends decode asynchronously; reading logits waits for them
+        let _ = context.get_logits_ith(batch.n_tokens() - 1);
+    }
+    Ok(())
+}
+
+/// BOS and `n - 1` tokens spread over the vocabulary
+fn bench_tokens(model: &LlamaModel, n: u32) -> Vec<LlamaToken> {
+    let n_vocab = model.n_vocab().max(1) as u64;
+    std::iter::once(model.token_bos())
+        .chain((1..u64::from(n)).map(|i| LlamaToken::new((i * 7919 % n_vocab) as i32)))
+        .collect()
+}
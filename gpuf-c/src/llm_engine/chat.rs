@@ -0,0 +1,193 @@
+//! `gpuf-c chat`: an interactive terminal chat with a local GGUF model on the
+//! llama engine, to try a model and the GPU settings before joining the
+//! fabric. Answers stream as they are generated; Ctrl-C stops an answer.
+
+use super::{Engine, InferenceEvent, InferenceInput, InferenceRequest, LlamaEngine};
+use crate::util::cmd::{Args, ChatArgs};
+use anyhow::{anyhow, Result};
+use common::{ChatMessage, ContextOverflow};
+use futures_util::StreamExt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+const HELP: &str = "Commands: /reset clears the chat, /exit quits. Ctrl-C stops an answer.";
+
+/// Path of `model`, looked up in ~/.llama/models when it is a bare file name
+/// that does not exist in the working directory
+pub(crate) fn model_path(model: &str) -> Result<PathBuf> {
+    let path = Path::new(model);
+    if path.exists() || path.components().count() > 1 {
+        return Ok(path.to_path_buf());
+    }
+    let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not determine home directory"))?;
+    Ok(home.join(".llama").join("models").join(model))
+}
+
+fn first_messages(chat: &ChatArgs) -> Vec<ChatMessage> {
+    chat.system
+        .iter()
+        .map(|system| ChatMessage {
+            role: "system".to_string(),
+            content: system.clone(),
+        })
+        .collect()
+}
+
+/// Load `chat.model` with the engine settings of `args` and chat with it on
+/// stdin and stdout until /exit, end of input or Ctrl-C at the prompt
+pub async fn run(args: &Args, chat: &ChatArgs) -> Result<()> {
+    let path = model_path(&chat.model)?;
+    if !path.exists() {
+        return Err(anyhow!("Model file not found: {}", path.display()));
+    }
+
+    eprintln!("Loading {} ...", path.display());
+    let started = Instant::now();
+    let mut engine = LlamaEngine::with_config(
+        path.to_string_lossy().to_string(),
+        args.n_ctx,
+        args.n_gpu_layers,
+        args.llama_split_mode.clone(),
+        args.llama_main_gpu,
+        args.llama_devices.clone(),
+    );
+    engine.init().await?;
+    if let Some(error) = engine.load_error().await {
+        return Err(anyhow!("Failed to load {}: {}", path.display(), error));
+    }
+    eprintln!(
+        "Loaded in {:.1}s with a {} token context and {} GPU layers. {}",
+        started.elapsed().as_secs_f64(),
+        args.n_ctx,
+        args.n_gpu_layers,
+        HELP
+    );
+
+    let mut messages = first_messages(chat);
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+        // Ctrl-C no longer ends the process once an answer waited for it
+        let line = tokio::select! {
+            line = lines.next_line() => line?,
+            _ = tokio::signal::ctrl_c() => None,
+        };
+        let Some(line) = line else {
+            println!();
+            break;
+        };
+
+        match line.trim() {
+            "" => continue,
+            "/exit" | "/quit" => break,
+            "/reset" => {
+                messages = first_messages(chat);
+                eprintln!("Chat cleared");
+                continue;
+            }
+            "/help" => {
+                eprintln!("{}", HELP);
+                continue;
+            }
+            prompt => messages.push(ChatMessage {
+                role: "user".to_string(),
+                content: prompt.to_string(),
+            }),
+        }
+
+        match answer(&engine, chat, messages.clone()).await {
+            Ok(reply) => messages.push(ChatMessage {
+                role: "assistant".to_string(),
+                content: reply,
+            }),
+            Err(e) => {
+                messages.pop();
+                eprintln!("Error: {:#}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stream the reply to `messages` to stdout and return it, with what was
+/// generated until Ctrl-C when it stopped early
+async fn answer(
+    engine: &LlamaEngine,
+    chat: &ChatArgs,
+    messages: Vec<ChatMessage>,
+) -> Result<String> {
+    let request = InferenceRequest {
+        model: None,
+        input: InferenceInput::Chat(messages),
+        max_tokens: chat.max_tokens,
+        temperature: chat.temperature,
+        top_k: chat.top_k,
+        top_p: chat.top_p,
+        repeat_penalty: chat.repeat_penalty,
+        repeat_last_n: chat.repeat_last_n,
+        min_keep: 1,
+        prefix: None,
+        overflow: ContextOverflow::TruncateLeft,
+    };
+
+    let started = Instant::now();
+    let mut stream = engine.infer(request).await?;
+    let mut stdout = std::io::stdout();
+    let mut reply = String::new();
+    let mut first_token = None;
+    let mut tokens = 0u32;
+    let mut prompt_tokens = 0u32;
+    let mut stopped = false;
+    loop {
+        let event = tokio::select! {
+            event = stream.next() => event,
+            _ = tokio::signal::ctrl_c() => {
+                stopped = true;
+                break;
+            }
+        };
+        let Some(event) = event else {
+            break;
+        };
+        match event? {
+            InferenceEvent::Token(piece) => {
+                first_token.get_or_insert_with(Instant::now);
+                tokens += 1;
+                print!("{}", piece);
+                stdout.flush()?;
+                reply.push_str(&piece);
+            }
+            InferenceEvent::Usage {
+                prompt_tokens: prompt,
+                ..
+            } => prompt_tokens = prompt,
+            InferenceEvent::ContextOverflow { dropped_tokens, .. } => {
+                eprintln!(
+                    "[dropped the oldest {} tokens to fit the context]",
+                    dropped_tokens
+                )
+            }
+        }
+    }
+    println!();
+
+    let first_token_ms = first_token.map_or(0, |at| at.duration_since(started).as_millis());
+    let generating = first_token.map_or(0.0, |at| at.elapsed().as_secs_f64());
+    let tokens_per_sec = if generating > 0.0 {
+        tokens.saturating_sub(1) as f64 / generating
+    } else {
+        0.0
+    };
+    eprintln!(
+        "[{}{} prompt tokens, {} tokens, first after {} ms, {:.1} tokens/s]",
+        if stopped { "stopped, " } else { "" },
+        prompt_tokens,
+        tokens,
+        first_token_ms,
+        tokens_per_sec
+    );
+    Ok(reply)
+}
@@ -0,0 +1,116 @@
+//! External engine: an OpenAI-compatible server the provider already runs,
+//! such as llama-server, TGI or LM Studio. The worker starts and stops
+//! nothing. It forwards proxied connections to the server and reports the
+//! models the server lists under `/v1/models`.
+
+use super::{openai_api, Engine, InferenceRequest, InferenceStream};
+use anyhow::{anyhow, Result};
+use common::Model;
+use reqwest::Client;
+use std::time::Duration;
+use tracing::info;
+use url::Url;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct ExternalEngine {
+    base_url: Url,
+    client: Client,
+}
+
+impl ExternalEngine {
+    /// Engine served at `base_url`, with or without the `/v1` suffix
+    pub fn new(base_url: &str) -> Result<Self> {
+        let base_url = Url::parse(base_url)
+            .map_err(|e| anyhow!("Invalid external engine URL {}: {}", base_url, e))?;
+        // Connections are forwarded as raw bytes, so the server must speak plain HTTP
+        if base_url.scheme() != "http" {
+            return Err(anyhow!(
+                "External engine URL must use http, not {}",
+                base_url.scheme()
+            ));
+        }
+        if base_url.host_str().is_none() {
+            return Err(anyhow!("External engine URL {} has no host", base_url));
+        }
+        let client = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        Ok(Self { base_url, client })
+    }
+
+    /// Host and port proxied connections are forwarded to
+    pub fn proxy_target(&self) -> (String, u16) {
+        let host = self.base_url.host_str().unwrap_or_default().to_string();
+        (host, self.base_url.port_or_known_default().unwrap_or(80))
+    }
+
+    /// Base URL without the `/v1` suffix the OpenAI routes are under
+    fn api_base(&self) -> &str {
+        let base = self.base_url.as_str().trim_end_matches('/');
+        base.strip_suffix("/v1").unwrap_or(base)
+    }
+
+    pub fn models_url(&self) -> String {
+        format!("{}/v1/models", self.api_base())
+    }
+
+    /// Models the server has loaded
+    pub async fn list_models(&self) -> Result<Vec<Model>> {
+        openai_api::list_models(&self.client, self.api_base(), "external").await
+    }
+}
+
+impl Engine for ExternalEngine {
+    fn init(&mut self) -> impl std::future::Future<Output = Result<()>> + Send {
+        async move {
+            let models = self.list_models().await?;
+            info!(
+                "External engine at {} serves {} models",
+                self.base_url,
+                models.len()
+            );
+            Ok(())
+        }
+    }
+
+    fn set_models(
+        &mut self,
+        _models: Vec<String>,
+    ) -> impl std::future::Future<Output = Result<()>> + Send {
+        // The provider decides what the server loads
+        async move { Ok(()) }
+    }
+
+    fn start_worker(&mut self) -> impl std::future::Future<Output = Result<()>> + Send {
+        async move { Ok(()) }
+    }
+
+    fn stop_worker(&mut self) -> impl std::future::Future<Output = Result<()>> + Send {
+        async move { Ok(()) }
+    }
+
+    fn infer(
+        &self,
+        request: InferenceRequest,
+    ) -> impl std::future::Future<Output = Result<InferenceStream>> + Send {
+        openai_api::infer(&self.client, self.api_base(), request)
+    }
+
+    fn list_models(&self) -> impl std::future::Future<Output = Result<Vec<Model>>> + Send {
+        ExternalEngine::list_models(self)
+    }
+}
+
+#[test]
+fn test_external_engine() {
+    let engine = ExternalEngine::new("http://10.0.0.5:1234/v1/").unwrap();
+    assert_eq!(engine.models_url(), "http://10.0.0.5:1234/v1/models");
+    assert_eq!(engine.proxy_target(), ("10.0.0.5".to_string(), 1234));
+
+    let engine = ExternalEngine::new("http://localhost").unwrap();
+    assert_eq!(engine.models_url(), "http://localhost/v1/models");
+    assert_eq!(engine.proxy_target(), ("localhost".to_string(), 80));
+
+    assert!(ExternalEngine::new("https://localhost:8080").is_err());
+    assert!(ExternalEngine::new("localhost:8080").is_err());
+}
@@ -0,0 +1,542 @@
+//! Image generation engine: Stable Diffusion through the stable-diffusion.cpp
+//! `sd` command line, or through a server the provider already runs with the
+//! AUTOMATIC1111 API. The worker serves it to the proxy as an
+//! OpenAI-compatible `/v1/images/generations` endpoint, backed by jobs a
+//! client can also submit, poll and fetch the PNGs of later.
+
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::process::Command;
+use tokio::sync::{Mutex, Semaphore};
+use tracing::{error, info};
+use url::Url;
+
+/// Images a single request may ask for
+const MAX_IMAGES: u32 = 4;
+const MAX_SIDE: u32 = 2048;
+const DEFAULT_SIZE: &str = "512x512";
+const DEFAULT_STEPS: u32 = 20;
+/// How long finished jobs and their images are kept for fetching
+const JOB_TTL: Duration = Duration::from_secs(30 * 60);
+/// A large model on a slow card takes minutes for a batch
+const SERVER_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Where images are generated
+#[derive(Debug, Clone)]
+pub enum ImageBackend {
+    /// The stable-diffusion.cpp `sd` binary, run once per image
+    Cli { binary: String, model: PathBuf },
+    /// A server with the AUTOMATIC1111 `/sdapi/v1/txt2img` API
+    Server { url: Url },
+}
+
+impl ImageBackend {
+    /// Backend of the `sd` engine: the server when `server_url` is set,
+    /// else `binary` with the local `model`
+    pub fn from_args(binary: &str, model: Option<&str>, server_url: Option<&str>) -> Result<Self> {
+        if let Some(url) = server_url {
+            let url = Url::parse(url)
+                .map_err(|e| anyhow!("Invalid Stable Diffusion server URL {}: {}", url, e))?;
+            return Ok(Self::Server { url });
+        }
+        let model = model.ok_or_else(|| {
+            anyhow!("--sd-model or --sd-server-url is required for the sd engine")
+        })?;
+        Ok(Self::Cli {
+            binary: binary.to_string(),
+            model: PathBuf::from(model),
+        })
+    }
+
+    /// Model id reported to the server and matched against requests
+    pub fn default_model_id(&self) -> String {
+        match self {
+            Self::Cli { model, .. } => model
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_else(|| "stable-diffusion".to_string()),
+            Self::Server { .. } => "stable-diffusion".to_string(),
+        }
+    }
+
+    async fn generate(&self, client: &Client, params: &ImageParams) -> Result<Vec<Vec<u8>>> {
+        match self {
+            Self::Cli { binary, model } => {
+                let mut images = Vec::with_capacity(params.n as usize);
+                for index in 0..params.n {
+                    let output =
+                        std::env::temp_dir().join(format!("gpuf-sd-{}.png", uuid::Uuid::new_v4()));
+                    let mut command = Command::new(binary);
+                    command
+                        .arg("-m")
+                        .arg(model)
+                        .arg("-p")
+                        .arg(&params.prompt)
+                        .arg("-o")
+                        .arg(&output)
+                        .arg("-W")
+                        .arg(params.width.to_string())
+                        .arg("-H")
+                        .arg(params.height.to_string())
+                        .arg("--steps")
+                        .arg(params.steps.to_string())
+                        .arg("-s")
+                        .arg((params.seed + i64::from(index)).to_string());
+                    if let Some(negative_prompt) = &params.negative_prompt {
+                        command.args(["-n", negative_prompt.as_str()]);
+                    }
+                    if let Some(cfg_scale) = params.cfg_scale {
+                        command.arg("--cfg-scale").arg(cfg_scale.to_string());
+                    }
+
+                    let result = command.output().await?;
+                    if !result.status.success() {
+                        let _ = std::fs::remove_file(&output);
+                        return Err(anyhow!(
+                            "{} failed: {}",
+                            binary,
+                            String::from_utf8_lossy(&result.stderr).trim()
+                        ));
+                    }
+                    let image = std::fs::read(&output)
+                        .map_err(|e| anyhow!("{} wrote no image: {}", binary, e));
+                    let _ = std::fs::remove_file(&output);
+                    images.push(image?);
+                }
+                Ok(images)
+            }
+            Self::Server { url } => {
+                let endpoint = url.join("/sdapi/v1/txt2img")?;
+                let body = serde_json::json!({
+                    "prompt": params.prompt,
+                    "negative_prompt": params.negative_prompt.as_deref().unwrap_or_default(),
+                    "width": params.width,
+                    "height": params.height,
+                    "steps": params.steps,
+                    "seed": params.seed,
+                    "cfg_scale": params.cfg_scale.unwrap_or(7.0),
+                    "batch_size": params.n,
+                });
+                let response = client
+                    .post(endpoint)
+                    .json(&body)
+                    .timeout(SERVER_TIMEOUT)
+                    .send()
+                    .await?;
+                if !response.status().is_success() {
+                    return Err(anyhow!(
+                        "Stable Diffusion server returned {}",
+                        response.status()
+                    ));
+                }
+                let generated: Txt2ImgResponse = response.json().await?;
+                generated
+                    .images
+                    .iter()
+                    .map(|image| {
+                        BASE64.decode(image).map_err(|e| {
+                            anyhow!("Invalid image from Stable Diffusion server: {}", e)
+                        })
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Txt2ImgResponse {
+    images: Vec<String>,
+}
+
+/// OpenAI image generation request, with the Stable Diffusion knobs it lacks
+#[derive(Debug, Deserialize)]
+pub struct ImageRequest {
+    pub prompt: String,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub n: Option<u32>,
+    #[serde(default)]
+    pub size: Option<String>,
+    #[serde(default)]
+    pub response_format: Option<String>,
+    #[serde(default)]
+    pub negative_prompt: Option<String>,
+    #[serde(default)]
+    pub steps: Option<u32>,
+    #[serde(default)]
+    pub seed: Option<i64>,
+    #[serde(default)]
+    pub cfg_scale: Option<f32>,
+}
+
+/// A validated request
+#[derive(Debug, Clone, PartialEq)]
+struct ImageParams {
+    prompt: String,
+    negative_prompt: Option<String>,
+    n: u32,
+    width: u32,
+    height: u32,
+    steps: u32,
+    seed: i64,
+    cfg_scale: Option<f32>,
+}
+
+impl ImageRequest {
+    fn params(&self) -> Result<ImageParams> {
+        if self.prompt.trim().is_empty() {
+            return Err(anyhow!("prompt cannot be empty"));
+        }
+        let n = self.n.unwrap_or(1);
+        if n == 0 || n > MAX_IMAGES {
+            return Err(anyhow!("n must be between 1 and {}", MAX_IMAGES));
+        }
+        let (width, height) = parse_size(self.size.as_deref().unwrap_or(DEFAULT_SIZE))?;
+        Ok(ImageParams {
+            prompt: self.prompt.clone(),
+            negative_prompt: self.negative_prompt.clone(),
+            n,
+            width,
+            height,
+            steps: self.steps.unwrap_or(DEFAULT_STEPS).clamp(1, 150),
+            seed: self
+                .seed
+                .filter(|seed| *seed >= 0)
+                .unwrap_or_else(rand_seed),
+            cfg_scale: self.cfg_scale,
+        })
+    }
+
+    fn wants_urls(&self) -> bool {
+        self.response_format.as_deref() == Some("url")
+    }
+}
+
+/// `WIDTHxHEIGHT`, each a multiple of 64 as Stable Diffusion's latents need
+fn parse_size(size: &str) -> Result<(u32, u32)> {
+    let invalid = || anyhow!("Invalid size {}, expected e.g. {}", size, DEFAULT_SIZE);
+    let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+    let width: u32 = width.trim().parse().map_err(|_| invalid())?;
+    let height: u32 = height.trim().parse().map_err(|_| invalid())?;
+    for side in [width, height] {
+        if side == 0 || side > MAX_SIDE || side % 64 != 0 {
+            return Err(anyhow!(
+                "Image sides must be multiples of 64 up to {}, got {}",
+                MAX_SIDE,
+                size
+            ));
+        }
+    }
+    Ok((width, height))
+}
+
+fn rand_seed() -> i64 {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    i64::from_le_bytes(bytes[..8].try_into().unwrap()) & i64::from(u32::MAX)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+struct ImageJob {
+    status: JobStatus,
+    created: u64,
+    images: Vec<Vec<u8>>,
+    error: Option<String>,
+    finished: Option<Instant>,
+}
+
+#[derive(Debug, Serialize)]
+struct ImageData {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    b64_json: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+}
+
+/// Job as clients see it. `worker` goes into the `gpuf-worker` header of
+/// polls so the server routes them back to this worker.
+#[derive(Debug, Serialize)]
+struct JobView {
+    id: String,
+    object: &'static str,
+    status: JobStatus,
+    created: u64,
+    worker: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    data: Vec<ImageData>,
+}
+
+pub struct ImageService {
+    backend: ImageBackend,
+    model_id: String,
+    /// Hex client id of this worker
+    worker: String,
+    client: Client,
+    jobs: Mutex<HashMap<String, ImageJob>>,
+    /// One generation at a time, the model fills the GPU
+    gpu: Semaphore,
+}
+
+impl ImageService {
+    pub fn new(backend: ImageBackend, model_id: Option<String>, client_id: [u8; 16]) -> Self {
+        Self {
+            model_id: model_id.unwrap_or_else(|| backend.default_model_id()),
+            backend,
+            worker: hex::encode(client_id),
+            client: Client::new(),
+            jobs: Mutex::new(HashMap::new()),
+            gpu: Semaphore::new(1),
+        }
+    }
+
+    async fn submit(&self) -> String {
+        let id = format!("imgjob-{}", uuid::Uuid::new_v4().simple());
+        let mut jobs = self.jobs.lock().await;
+        jobs.retain(|_, job| !job.finished.is_some_and(|at| at.elapsed() >= JOB_TTL));
+        jobs.insert(
+            id.clone(),
+            ImageJob {
+                status: JobStatus::Queued,
+                created: unix_now(),
+                images: Vec::new(),
+                error: None,
+                finished: None,
+            },
+        );
+        id
+    }
+
+    async fn set_status(&self, id: &str, status: JobStatus) {
+        if let Some(job) = self.jobs.lock().await.get_mut(id) {
+            job.status = status;
+        }
+    }
+
+    async fn run(&self, id: &str, params: ImageParams) {
+        let _permit = self.gpu.acquire().await;
+        self.set_status(id, JobStatus::Running).await;
+        info!(
+            "Generating {} {}x{} image(s) for job {}",
+            params.n, params.width, params.height, id
+        );
+        let result = self.backend.generate(&self.client, &params).await;
+
+        let mut jobs = self.jobs.lock().await;
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        job.finished = Some(Instant::now());
+        match result {
+            Ok(images) => {
+                job.status = JobStatus::Succeeded;
+                job.images = images;
+            }
+            Err(e) => {
+                error!("Image job {} failed: {}", id, e);
+                job.status = JobStatus::Failed;
+                job.error = Some(e.to_string());
+            }
+        }
+    }
+
+    async fn view(&self, id: &str, inline: bool) -> Option<JobView> {
+        let jobs = self.jobs.lock().await;
+        let job = jobs.get(id)?;
+        let data = (0..job.images.len())
+            .map(|index| ImageData {
+                b64_json: inline.then(|| BASE64.encode(&job.images[index])),
+                url: (!inline).then(|| format!("/v1/images/jobs/{}/{}", id, index)),
+            })
+            .collect();
+        Some(JobView {
+            id: id.to_string(),
+            object: "image.job",
+            status: job.status,
+            created: job.created,
+            worker: self.worker.clone(),
+            error: job.error.clone(),
+            data,
+        })
+    }
+}
+
+fn error_response(status: StatusCode, message: impl ToString) -> Response {
+    let body = serde_json::json!({
+        "error": { "message": message.to_string(), "type": "invalid_request_error" }
+    });
+    (status, Json(body)).into_response()
+}
+
+/// `POST /v1/images/generations`. Answers with the images once generated,
+/// or at once with the queued job under `Prefer: respond-async`.
+async fn generate_images(
+    State(service): State<Arc<ImageService>>,
+    headers: HeaderMap,
+    Json(request): Json<ImageRequest>,
+) -> Response {
+    if let Some(model) = &request.model {
+        if *model != service.model_id {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                format!("Model {} is not served by this worker", model),
+            );
+        }
+    }
+    let params = match request.params() {
+        Ok(params) => params,
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, e),
+    };
+    let respond_async = headers
+        .get("prefer")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("respond-async"));
+
+    let id = service.submit().await;
+    if respond_async {
+        let background = Arc::clone(&service);
+        let job_id = id.clone();
+        tokio::spawn(async move { background.run(&job_id, params).await });
+        return match service.view(&id, false).await {
+            Some(view) => (StatusCode::ACCEPTED, Json(view)).into_response(),
+            None => error_response(StatusCode::INTERNAL_SERVER_ERROR, "Job vanished"),
+        };
+    }
+
+    service.run(&id, params).await;
+    match service.view(&id, !request.wants_urls()).await {
+        Some(view) if view.status == JobStatus::Succeeded => {
+            Json(serde_json::json!({ "created": view.created, "data": view.data })).into_response()
+        }
+        Some(view) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            view.error.unwrap_or_default(),
+        ),
+        None => error_response(StatusCode::INTERNAL_SERVER_ERROR, "Job vanished"),
+    }
+}
+
+/// `GET /v1/images/jobs/:id`
+async fn get_job(State(service): State<Arc<ImageService>>, Path(id): Path<String>) -> Response {
+    match service.view(&id, false).await {
+        Some(view) => Json(view).into_response(),
+        None => error_response(StatusCode::NOT_FOUND, format!("No image job {}", id)),
+    }
+}
+
+/// `GET /v1/images/jobs/:id/:index`, the PNG itself
+async fn get_job_image(
+    State(service): State<Arc<ImageService>>,
+    Path((id, index)): Path<(String, usize)>,
+) -> Response {
+    let jobs = service.jobs.lock().await;
+    match jobs.get(&id).and_then(|job| job.images.get(index)) {
+        Some(image) => ([(header::CONTENT_TYPE, "image/png")], image.clone()).into_response(),
+        None => error_response(
+            StatusCode::NOT_FOUND,
+            format!("No image {} in job {}", index, id),
+        ),
+    }
+}
+
+async fn list_models(State(service): State<Arc<ImageService>>) -> Response {
+    Json(serde_json::json!({
+        "object": "list",
+        "data": [{
+            "id": service.model_id,
+            "object": "model",
+            "created": 0,
+            "owned_by": "gpuf-c",
+        }]
+    }))
+    .into_response()
+}
+
+pub fn create_router(service: Arc<ImageService>) -> Router {
+    Router::new()
+        .route("/v1/images/generations", post(generate_images))
+        .route("/v1/images/jobs/:id", get(get_job))
+        .route("/v1/images/jobs/:id/:index", get(get_job_image))
+        .route("/v1/models", get(list_models))
+        .with_state(service)
+}
+
+/// Serve image generation on `host:port`, where the worker forwards
+/// proxied connections
+pub async fn start_server(service: Arc<ImageService>, host: &str, port: u16) -> Result<()> {
+    let addr = format!("{}:{}", host, port);
+    info!(
+        "Starting image generation server for {} on {}",
+        service.model_id, addr
+    );
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    axum::serve(listener, create_router(service)).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_image_jobs() {
+    assert_eq!(parse_size("768x512").unwrap(), (768, 512));
+    assert!(parse_size("500x500").is_err());
+    assert!(parse_size("4096x512").is_err());
+    assert!(parse_size("512").is_err());
+
+    let request: ImageRequest =
+        serde_json::from_str(r#"{"prompt":"a red fox","n":2,"seed":42}"#).unwrap();
+    let params = request.params().unwrap();
+    assert_eq!((params.width, params.height), (512, 512));
+    assert_eq!(
+        (params.n, params.steps, params.seed),
+        (2, DEFAULT_STEPS, 42)
+    );
+    assert!(!request.wants_urls());
+    let request: ImageRequest = serde_json::from_str(r#"{"prompt":"a red fox","n":9}"#).unwrap();
+    assert!(request.params().is_err());
+
+    // A backend that cannot run fails the job instead of the worker
+    let backend =
+        ImageBackend::from_args("/nonexistent/sd", Some("/models/sd-v1-5.safetensors"), None)
+            .unwrap();
+    assert_eq!(backend.default_model_id(), "sd-v1-5");
+    let service = ImageService::new(backend, None, [0xab; 16]);
+    let id = service.submit().await;
+    assert_eq!(
+        service.view(&id, false).await.unwrap().status,
+        JobStatus::Queued
+    );
+    service.run(&id, params).await;
+    let view = service.view(&id, false).await.unwrap();
+    assert_eq!(view.status, JobStatus::Failed);
+    assert_eq!(view.worker, "ab".repeat(16));
+    assert!(view.data.is_empty());
+    assert!(service.view("imgjob-missing", false).await.is_none());
+}
@@ -5,18 +5,30 @@
 use anyhow::{anyhow, Result};
 use axum::{
     extract::State,
+    http::header,
+    response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv6Addr};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{debug, error, info};
+use std::time::Duration;
+use tokio::sync::{RwLock, Semaphore};
+use tracing::{debug, error, info, warn};
+
+use crate::util::net;
+use crate::util::system_info::collect_device_info;
 
 /// Inference service configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InferenceServiceConfig {
+    /// Service listening address; `::` takes IPv4 and IPv6 clients
+    #[serde(default = "default_bind_addr")]
+    pub bind_addr: IpAddr,
     /// Service listening port
     pub port: u16,
     /// Model path
@@ -32,6 +44,7 @@ pub struct InferenceServiceConfig {
 impl Default for InferenceServiceConfig {
     fn default() -> Self {
         Self {
+            bind_addr: default_bind_addr(),
             port: 8082, // Distinguish from gpuf-c's 8081
             model_path: String::new(),
             n_ctx: 4096,
@@ -41,6 +54,10 @@ impl Default for InferenceServiceConfig {
     }
 }
 
+fn default_bind_addr() -> IpAddr {
+    Ipv6Addr::UNSPECIFIED.into()
+}
+
 /// Inference request
 #[derive(Debug, Deserialize)]
 pub struct InferenceRequest {
@@ -72,6 +89,133 @@ pub struct InferenceResponse {
 pub struct InferenceServiceState {
     pub config: InferenceServiceConfig,
     pub request_count: Arc<RwLock<u64>>,
+    pub metrics: Arc<InferenceMetrics>,
+    /// Limits generation to `max_concurrent_requests`, the rest wait in queue
+    pub slots: Arc<Semaphore>,
+}
+
+/// Counters exposed on `/metrics` in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct InferenceMetrics {
+    requests_ok: AtomicU64,
+    requests_failed: AtomicU64,
+    prompt_tokens: AtomicU64,
+    completion_tokens: AtomicU64,
+    prompt_eval_us: AtomicU64,
+    generation_us: AtomicU64,
+    /// Tokens per second of the last finished request, stored as f64 bits
+    last_tokens_per_second: AtomicU64,
+    queued: AtomicU64,
+    in_flight: AtomicU64,
+}
+
+impl InferenceMetrics {
+    pub fn record_success(
+        &self,
+        prompt_tokens: usize,
+        completion_tokens: usize,
+        prompt_eval: Duration,
+        generation: Duration,
+    ) {
+        self.requests_ok.fetch_add(1, Ordering::Relaxed);
+        self.prompt_tokens
+            .fetch_add(prompt_tokens as u64, Ordering::Relaxed);
+        self.completion_tokens
+            .fetch_add(completion_tokens as u64, Ordering::Relaxed);
+        self.prompt_eval_us
+            .fetch_add(prompt_eval.as_micros() as u64, Ordering::Relaxed);
+        self.generation_us
+            .fetch_add(generation.as_micros() as u64, Ordering::Relaxed);
+
+        let secs = generation.as_secs_f64();
+        if secs > 0.0 {
+            let tps = completion_tokens as f64 / secs;
+            self.last_tokens_per_second
+                .store(tps.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_failure(&self) {
+        self.requests_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render all metrics; `gpu_memory_used_bytes` is sampled by the caller
+    pub fn render(&self, gpu_memory_used_bytes: Option<u64>) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, String)]| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+        let load = |v: &AtomicU64| v.load(Ordering::Relaxed).to_string();
+        let secs = |v: &AtomicU64| (v.load(Ordering::Relaxed) as f64 / 1_000_000.0).to_string();
+
+        metric(
+            "gpuf_inference_requests_total",
+            "counter",
+            "Inference requests handled, by result",
+            &[
+                ("{status=\"ok\"}", load(&self.requests_ok)),
+                ("{status=\"error\"}", load(&self.requests_failed)),
+            ],
+        );
+        metric(
+            "gpuf_inference_prompt_tokens_total",
+            "counter",
+            "Prompt tokens evaluated",
+            &[("", load(&self.prompt_tokens))],
+        );
+        metric(
+            "gpuf_inference_completion_tokens_total",
+            "counter",
+            "Tokens generated",
+            &[("", load(&self.completion_tokens))],
+        );
+        metric(
+            "gpuf_inference_prompt_eval_seconds_total",
+            "counter",
+            "Time spent evaluating prompts",
+            &[("", secs(&self.prompt_eval_us))],
+        );
+        metric(
+            "gpuf_inference_generation_seconds_total",
+            "counter",
+            "Time spent generating tokens",
+            &[("", secs(&self.generation_us))],
+        );
+        metric(
+            "gpuf_inference_tokens_per_second",
+            "gauge",
+            "Generation speed of the last finished request",
+            &[(
+                "",
+                f64::from_bits(self.last_tokens_per_second.load(Ordering::Relaxed)).to_string(),
+            )],
+        );
+        metric(
+            "gpuf_inference_queue_depth",
+            "gauge",
+            "Requests waiting for a free generation slot",
+            &[("", load(&self.queued))],
+        );
+        metric(
+            "gpuf_inference_requests_in_flight",
+            "gauge",
+            "Requests currently generating",
+            &[("", load(&self.in_flight))],
+        );
+        if let Some(bytes) = gpu_memory_used_bytes {
+            metric(
+                "gpuf_gpu_memory_used_bytes",
+                "gauge",
+                "GPU memory in use on this worker",
+                &[("", bytes.to_string())],
+            );
+        }
+        out
+    }
 }
 
 /// Standalone inference service
@@ -93,6 +237,8 @@ impl InferenceService {
         let state = InferenceServiceState {
             config: config.clone(),
             request_count: Arc::new(RwLock::new(0)),
+            metrics: Arc::new(InferenceMetrics::default()),
+            slots: Arc::new(Semaphore::new(config.max_concurrent_requests.max(1))),
         };
 
         Ok(Self { config, state })
@@ -109,13 +255,13 @@ impl InferenceService {
         let app = self.create_router();
 
         // 3. Start server
-        let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", self.config.port))
+        let listener = net::bind(self.config.bind_addr, self.config.port)
             .await
             .map_err(|e| anyhow!("Failed to bind to port {}: {}", self.config.port, e))?;
 
         info!(
-            "Inference service started successfully on http://0.0.0.0:{}",
-            self.config.port
+            "Inference service started successfully on http://{}",
+            listener.local_addr()?
         );
 
         axum::serve(listener, app)
@@ -149,6 +295,7 @@ impl InferenceService {
             .route("/v1/chat/completions", post(chat_completions))
             .route("/v1/models", get(list_models))
             .route("/stats", get(get_stats))
+            .route("/metrics", get(get_metrics))
             .with_state(self.state.clone())
     }
 
@@ -184,11 +331,23 @@ async fn completions(
     // Simulate engine initialization check
     if state.config.model_path.is_empty() {
         error!("LLM engine not initialized");
+        state.metrics.record_failure();
         return Err(axum::http::StatusCode::SERVICE_UNAVAILABLE);
     }
 
+    state.metrics.queued.fetch_add(1, Ordering::Relaxed);
+    let permit = state.slots.acquire().await;
+    state.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+    let Ok(_permit) = permit else {
+        state.metrics.record_failure();
+        return Err(axum::http::StatusCode::SERVICE_UNAVAILABLE);
+    };
+    state.metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+
     let start_time = std::time::Instant::now();
     let max_tokens = request.max_tokens.unwrap_or(1024);
+    let prompt_tokens = estimate_tokens(&request.prompt);
+    let prompt_eval_time = start_time.elapsed();
 
     // Simulate text generation
     let text = format!(
@@ -202,6 +361,13 @@ async fn completions(
 
     // Update request count
     *state.request_count.write().await += 1;
+    state.metrics.record_success(
+        prompt_tokens,
+        tokens_used,
+        prompt_eval_time,
+        start_time.elapsed() - prompt_eval_time,
+    );
+    state.metrics.in_flight.fetch_sub(1, Ordering::Relaxed);
 
     let response = InferenceResponse {
         text,
@@ -294,6 +460,25 @@ async fn get_stats(State(state): State<InferenceServiceState>) -> Json<serde_jso
     }))
 }
 
+/// Prometheus scrape endpoint
+async fn get_metrics(State(state): State<InferenceServiceState>) -> impl IntoResponse {
+    let gpu_memory_used_bytes = match collect_device_info(common::EngineType::Llama).await {
+        Ok((info, _)) if info.memsize_gb > 0 => {
+            Some((info.memsize_gb as u64 * info.mem_usage / 100) << 30)
+        }
+        Ok(_) => None,
+        Err(e) => {
+            warn!("Failed to sample GPU memory for metrics: {}", e);
+            None
+        }
+    };
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(gpu_memory_used_bytes),
+    )
+}
+
 // Helper functions
 
 /// Estimate token count (simplified implementation)
@@ -351,4 +536,20 @@ mod tests {
         let result = InferenceService::new(config);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_metrics_render() {
+        let metrics = InferenceMetrics::default();
+        metrics.record_success(12, 50, Duration::from_millis(100), Duration::from_secs(2));
+        metrics.record_failure();
+
+        let text = metrics.render(Some(1 << 30));
+        assert!(text.contains("gpuf_inference_requests_total{status=\"ok\"} 1"));
+        assert!(text.contains("gpuf_inference_requests_total{status=\"error\"} 1"));
+        assert!(text.contains("gpuf_inference_completion_tokens_total 50"));
+        assert!(text.contains("gpuf_inference_prompt_eval_seconds_total 0.1"));
+        assert!(text.contains("gpuf_inference_tokens_per_second 25"));
+        assert!(text.contains("gpuf_gpu_memory_used_bytes 1073741824"));
+        assert!(!metrics.render(None).contains("gpuf_gpu_memory_used_bytes"));
+    }
 }
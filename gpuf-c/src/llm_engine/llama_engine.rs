@@ -1,4 +1,4 @@
-use super::Engine;
+use super::{Engine, InferenceEvent, InferenceInput, InferenceRequest, InferenceStream};
 use anyhow::{anyhow, Result};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
@@ -11,9 +11,20 @@ use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 
 use crate::util::cmd::LlamaSplitModeArg;
+#[cfg(not(target_os = "android"))]
+use crate::util::memory_plan::{self, MemoryConfig, MemoryPlan};
+#[cfg(not(target_os = "android"))]
+use crate::util::request_limits;
+use common::{ChatMessage, ContextOverflow, Model, CONTEXT_LENGTH_EXCEEDED};
 
 // llama-cpp-2 imports (only for non-Android platforms)
 #[cfg(not(target_os = "android"))]
+use super::prefix_cache::{PrefixCache, PrefixState};
+#[cfg(not(target_os = "android"))]
+use super::slots::SlotPool;
+#[cfg(not(target_os = "android"))]
+use llama_cpp_2::token::LlamaToken;
+#[cfg(not(target_os = "android"))]
 use llama_cpp_2::{context::params::LlamaContextParams, model::params::LlamaModelParams};
 #[cfg(not(target_os = "android"))]
 use llama_cpp_2::{context::LlamaContext, llama_backend::LlamaBackend, model::LlamaModel};
@@ -26,6 +37,11 @@ use std::sync::OnceLock;
 #[cfg(not(target_os = "android"))]
 static LLAMA_BACKEND: OnceLock<Arc<LlamaBackend>> = OnceLock::new();
 
+/// The loaded model; parallel slots read it for as long as they run, so
+/// generating in a context of its own takes it for writing
+#[cfg(not(target_os = "android"))]
+pub type SharedModel = Arc<std::sync::RwLock<LlamaModel>>;
+
 #[allow(dead_code)] // LLM engine implementation for llama.cpp (embedded mode)
 #[derive(Clone)] // Enable cloning for shared instance usage
 pub struct LlamaEngine {
@@ -37,6 +53,11 @@ pub struct LlamaEngine {
     pub llama_split_mode: LlamaSplitModeArg,
     pub llama_main_gpu: i32,
     pub llama_devices: Option<String>,
+    // Small model drafting tokens for the loaded one to verify
+    pub draft_model_path: Option<String>,
+    pub draft_tokens: u32,
+    // Sequences decoded in parallel on the loaded model, 0 for as many as fit
+    pub parallel_slots: u32,
     pub is_initialized: bool,
     pub models_dir: PathBuf,
     // Added: model loading status tracking
@@ -47,9 +68,73 @@ pub struct LlamaEngine {
     #[cfg(not(target_os = "android"))]
     pub cached_backend: Option<Arc<LlamaBackend>>,
     #[cfg(not(target_os = "android"))]
-    pub cached_model: Option<Arc<Mutex<LlamaModel>>>,
+    pub cached_model: Option<SharedModel>,
     #[cfg(not(target_os = "android"))]
     pub cached_model_path: Option<String>, // Track which model is currently cached
+    // KV states of shared chat prefixes of the cached model
+    #[cfg(not(target_os = "android"))]
+    pub prefix_cache: Arc<Mutex<PrefixCache>>,
+    #[cfg(not(target_os = "android"))]
+    pub cached_draft_model: Option<Arc<Mutex<LlamaModel>>>,
+    // Context size and GPU layers the cached model was loaded with
+    #[cfg(not(target_os = "android"))]
+    pub memory_plan: Option<MemoryPlan>,
+    // Slots of the cached model when it decodes more than one sequence
+    #[cfg(not(target_os = "android"))]
+    pub slot_pool: Option<Arc<SlotPool>>,
+}
+
+/// How a prompt too long for the context is fit into it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContextFit {
+    /// Prompt tokens dropped after the first, the BOS token
+    pub dropped_tokens: usize,
+    /// Shift the oldest generated tokens out whenever the context fills up
+    pub sliding: bool,
+}
+
+/// How `prompt_tokens` and up to `max_tokens` generated ones fit a context
+/// of `n_ctx` tokens with `strategy`. None when they fit as they are.
+pub fn fit_context(
+    prompt_tokens: usize,
+    max_tokens: usize,
+    n_ctx: usize,
+    strategy: ContextOverflow,
+) -> Result<Option<ContextFit>> {
+    if prompt_tokens + max_tokens <= n_ctx {
+        return Ok(None);
+    }
+    let exceeded = || {
+        anyhow!(
+            "{}: {} prompt tokens and max_tokens {} exceed the context of {} tokens",
+            CONTEXT_LENGTH_EXCEEDED,
+            prompt_tokens,
+            max_tokens,
+            n_ctx
+        )
+    };
+    let (keep, sliding) = match strategy {
+        ContextOverflow::Error => return Err(exceeded()),
+        ContextOverflow::TruncateLeft => (n_ctx.saturating_sub(max_tokens), false),
+        // A quarter of the context is left for generation before the first shift
+        ContextOverflow::SlidingWindow => (n_ctx - n_ctx / 4, true),
+    };
+    // The BOS token and at least one of the prompt
+    if keep < 2 {
+        return Err(exceeded());
+    }
+    Ok(Some(ContextFit {
+        dropped_tokens: prompt_tokens.saturating_sub(keep),
+        sliding,
+    }))
+}
+
+/// Leading part of a prompt whose KV state is kept under `hash`
+#[derive(Clone, Debug)]
+pub struct SharedPrefix {
+    pub hash: String,
+    /// Length of the part in bytes
+    pub len: usize,
 }
 
 #[derive(Clone, Debug)]
@@ -137,7 +222,17 @@ impl LlamaEngine {
                     self.clear_cache();
                 }
             }
-            let n_gpu_layers = self.n_gpu_layers;
+            let requested = MemoryConfig {
+                n_ctx: self.n_ctx,
+                n_gpu_layers: self.n_gpu_layers,
+                n_batch: memory_plan::DEFAULT_N_BATCH,
+                // Speculative decoding drafts for a single sequence
+                n_seq: if self.draft_model_path.is_some() && self.parallel_slots == 0 {
+                    1
+                } else {
+                    self.parallel_slots
+                },
+            };
             let llama_split_mode = self.llama_split_mode.clone();
             let llama_main_gpu = self.llama_main_gpu;
             let llama_devices = self.llama_devices.clone();
@@ -148,9 +243,27 @@ impl LlamaEngine {
                 "Loading and caching llama-cpp-2 model: {}",
                 model_path_for_closure
             );
+            if let Ok(mut status) = crate::MODEL_STATUS.lock() {
+                status.is_warm = false;
+            }
 
             // Run model loading in blocking thread
-            let (backend, model) = tokio::task::spawn_blocking(move || {
+            let (backend, model, plan) = tokio::task::spawn_blocking(move || {
+                // A configuration running out of memory takes the process down
+                let plan = memory_plan::plan_for_file(
+                    std::path::Path::new(&model_path_for_closure),
+                    requested,
+                )?;
+                if plan.shrunk {
+                    warn!(
+                        "Model does not fit with n_ctx {} and {} GPU layers, loading with {}",
+                        requested.n_ctx, requested.n_gpu_layers, plan
+                    );
+                } else {
+                    info!("Memory plan: {}", plan);
+                }
+                let n_gpu_layers = plan.config.n_gpu_layers;
+
                 // Use global backend singleton - initialize only once
                 let backend = LLAMA_BACKEND.get_or_init(|| {
                     info!("Initializing Llama backend (first time only)");
@@ -213,20 +326,48 @@ impl LlamaEngine {
                     LlamaModel::load_from_file(&*backend, &model_path_for_closure, &model_params)
                         .map_err(|e| anyhow!("Failed to load model: {:?}", e))?;
 
-                Ok::<(Arc<LlamaBackend>, LlamaModel), anyhow::Error>((backend, model))
+                Ok::<(Arc<LlamaBackend>, LlamaModel, MemoryPlan), anyhow::Error>((
+                    backend, model, plan,
+                ))
             })
             .await??;
 
             // Cache the components and store the model path
             self.cached_backend = Some(backend);
-            self.cached_model = Some(Arc::new(Mutex::new(model)));
+            self.cached_model = Some(Arc::new(std::sync::RwLock::new(model)));
             self.cached_model_path = Some(model_path_for_cache.clone());
+            self.memory_plan = Some(plan);
+            if let Ok(mut status) = crate::MODEL_STATUS.lock() {
+                status.memory_plan = Some(plan);
+            }
+            // States of the previous model do not apply to this one
+            if let Ok(mut prefix_cache) = self.prefix_cache.lock() {
+                prefix_cache.clear();
+            }
             self.is_initialized = true;
 
             info!(
                 "Model successfully loaded and cached: {}",
                 model_path_for_cache
             );
+            // Requests queue for the one context without slots
+            if let Err(e) = self.start_slot_pool().await {
+                warn!("Parallel slots disabled: {}", e);
+            }
+            // Generation works without the draft model, only slower
+            if let Err(e) = self.load_draft_model().await {
+                warn!("Speculative decoding disabled: {}", e);
+            }
+            match self.warmup().await {
+                Ok(elapsed) => {
+                    info!("Model warmed up in {} ms", elapsed.as_millis());
+                    if let Ok(mut status) = crate::MODEL_STATUS.lock() {
+                        status.is_warm = true;
+                    }
+                }
+                // The first request pays for what the warmup would have
+                Err(e) => warn!("Model warmup failed: {}", e),
+            }
             Ok(())
         }
     }
@@ -239,11 +380,130 @@ impl LlamaEngine {
             self.cached_model = None;
             self.cached_backend = None;
             self.cached_model_path = None;
+            self.cached_draft_model = None;
+            self.memory_plan = None;
+            self.slot_pool = None;
+            if let Ok(mut prefix_cache) = self.prefix_cache.lock() {
+                prefix_cache.clear();
+            }
             self.is_initialized = false;
             info!("Model cache cleared");
         }
     }
 
+    /// Context size of the cached model, smaller than `n_ctx` when that did
+    /// not fit
+    #[cfg(not(target_os = "android"))]
+    fn context_size(&self) -> u32 {
+        self.memory_plan
+            .map_or(self.n_ctx, |plan| plan.config.n_ctx)
+    }
+
+    /// Prefill a few tokens and decode one, so the first request does not
+    /// pay for paging in the weights, compiling GPU kernels and allocating
+    /// compute buffers
+    #[cfg(not(target_os = "android"))]
+    pub async fn warmup(&self) -> Result<std::time::Duration> {
+        let started = std::time::Instant::now();
+        let greedy = SamplingParams {
+            temperature: 0.0,
+            ..Default::default()
+        };
+        self.generate_with_cached_model_sampling("Hello", 1, &greedy)
+            .await?;
+        Ok(started.elapsed())
+    }
+
+    /// Start the slots of the cached model when its memory plan has more
+    /// than one
+    #[cfg(not(target_os = "android"))]
+    async fn start_slot_pool(&mut self) -> Result<()> {
+        self.slot_pool = None;
+        let Some(plan) = self.memory_plan else {
+            return Ok(());
+        };
+        if plan.config.n_seq <= 1 {
+            return Ok(());
+        }
+        if plan.config.n_ctx == 0 {
+            return Err(anyhow!("Context size of the model unknown"));
+        }
+        let (Some(backend), Some(model)) = (self.cached_backend.clone(), self.cached_model.clone())
+        else {
+            return Err(anyhow!("Model not loaded"));
+        };
+        let pool = tokio::task::spawn_blocking(move || {
+            SlotPool::start(backend, model, plan.config.n_seq, plan.config.n_ctx)
+        })
+        .await??;
+        self.slot_pool = Some(Arc::new(pool));
+        Ok(())
+    }
+
+    /// Use the draft model at `path`, or none, loading it now when the main
+    /// model is loaded
+    #[cfg(not(target_os = "android"))]
+    pub async fn set_draft_model(&mut self, path: Option<String>) -> Result<()> {
+        self.draft_model_path = path;
+        self.cached_draft_model = None;
+        if self.cached_model.is_some() {
+            self.load_draft_model().await?;
+        }
+        Ok(())
+    }
+
+    /// Load the draft model next to the cached main model, on the same GPU
+    #[cfg(not(target_os = "android"))]
+    async fn load_draft_model(&mut self) -> Result<()> {
+        self.cached_draft_model = None;
+        let Some(draft_model_path) = self.draft_model_path.clone() else {
+            return Ok(());
+        };
+        if self.draft_tokens == 0 {
+            return Ok(());
+        }
+        if self.slot_pool.is_some() {
+            return Err(anyhow!("Draft model needs a single slot"));
+        }
+        let (Some(backend), Some(model)) = (self.cached_backend.clone(), self.cached_model.clone())
+        else {
+            return Err(anyhow!("Main model not loaded"));
+        };
+        let resolved = self.resolve_model_path(&draft_model_path)?;
+        let n_gpu_layers = self.n_gpu_layers;
+        let llama_main_gpu = self.llama_main_gpu;
+
+        info!("Loading draft model: {}", resolved.display());
+        let draft = tokio::task::spawn_blocking(move || {
+            let model_params = LlamaModelParams::default()
+                .with_n_gpu_layers(n_gpu_layers)
+                .with_main_gpu(llama_main_gpu);
+            let draft = LlamaModel::load_from_file(&*backend, &resolved, &model_params)
+                .map_err(|e| anyhow!("Failed to load draft model: {:?}", e))?;
+            let n_vocab = model
+                .read()
+                .map_err(|e| anyhow!("Failed to lock model: {:?}", e))?
+                .n_vocab();
+            // Drafted token ids are only meaningful in the same vocabulary
+            if draft.n_vocab() != n_vocab {
+                return Err(anyhow!(
+                    "Draft model vocabulary ({} tokens) differs from the model's ({})",
+                    draft.n_vocab(),
+                    n_vocab
+                ));
+            }
+            Ok::<LlamaModel, anyhow::Error>(draft)
+        })
+        .await??;
+
+        self.cached_draft_model = Some(Arc::new(Mutex::new(draft)));
+        info!(
+            "Speculative decoding enabled, drafting {} tokens per step",
+            self.draft_tokens
+        );
+        Ok(())
+    }
+
     /// Generate text using cached model (inference only)
     /// Returns (generated_text, prompt_tokens, completion_tokens)
     pub async fn generate_with_cached_model(
@@ -280,6 +540,18 @@ impl LlamaEngine {
 
         #[cfg(not(target_os = "android"))]
         {
+            self.check_request_limits(prompt, max_tokens).await?;
+            if let Some(pool) = &self.slot_pool {
+                let prompt_tokens = self.count_tokens(prompt).await? as usize;
+                let mut rx = pool.submit(prompt, None, max_tokens, sampling)?;
+                let (mut text, mut completion_tokens) = (String::new(), 0);
+                while let Some(piece) = rx.recv().await {
+                    text.push_str(&piece?);
+                    completion_tokens += 1;
+                }
+                return Ok((text, prompt_tokens, completion_tokens));
+            }
+
             // Client: Real inference using cached model
             info!("Client: Executing inference with cached model");
 
@@ -295,21 +567,20 @@ impl LlamaEngine {
                 .clone();
 
             let prompt = prompt.to_string();
-            let n_ctx = self.n_ctx;
+            let n_ctx = self.context_size();
             let sampling = sampling.clone();
 
             // Run inference in blocking thread
             tokio::task::spawn_blocking(move || {
                 use llama_cpp_2::llama_batch::LlamaBatch;
                 use llama_cpp_2::model::AddBos;
-                use llama_cpp_2::sampling::LlamaSampler;
 
                 let context_params =
                     LlamaContextParams::default().with_n_ctx(NonZeroU32::new(n_ctx));
 
                 // Lock model and create context with proper lifetime
                 let model_guard = model
-                    .lock()
+                    .write()
                     .map_err(|e| anyhow!("Failed to lock model: {:?}", e))?;
 
                 let mut context = model_guard
@@ -340,30 +611,7 @@ impl LlamaEngine {
                 let mut output_text = String::new();
                 let mut n_cur = tokens.len(); // Current position in sequence
 
-                let mut samplers = Vec::new();
-
-                if sampling.repeat_penalty != 1.0 {
-                    samplers.push(LlamaSampler::penalties(
-                        sampling.repeat_last_n,
-                        sampling.repeat_penalty,
-                        0.0,
-                        0.0,
-                    ));
-                }
-                if sampling.top_k > 0 {
-                    samplers.push(LlamaSampler::top_k(sampling.top_k));
-                }
-                if sampling.top_p > 0.0 && sampling.top_p < 1.0 {
-                    samplers.push(LlamaSampler::top_p(sampling.top_p, sampling.min_keep));
-                }
-                samplers.push(LlamaSampler::temp(sampling.temperature));
-                if sampling.temperature <= 0.0 {
-                    samplers.push(LlamaSampler::greedy());
-                } else {
-                    samplers.push(LlamaSampler::dist(sampling.seed));
-                }
-
-                let mut sampler = LlamaSampler::chain_simple(samplers);
+                let mut sampler = build_sampler(&sampling);
                 sampler.accept_many(tokens.iter());
 
                 for i in 0..max_tokens {
@@ -430,6 +678,20 @@ impl LlamaEngine {
         prompt: &str,
         max_tokens: usize,
         sampling: &SamplingParams,
+    ) -> Result<impl Stream<Item = Result<String>> + Send + 'static> {
+        self.stream_with_prefix(prompt, None, None, max_tokens, sampling)
+            .await
+    }
+
+    /// Stream a completion of `prompt`, reusing or keeping the KV state of
+    /// its shared `prefix`, cut to `fit` when it is too long for the context
+    pub async fn stream_with_prefix(
+        &self,
+        prompt: &str,
+        prefix: Option<SharedPrefix>,
+        fit: Option<ContextFit>,
+        max_tokens: usize,
+        sampling: &SamplingParams,
     ) -> Result<impl Stream<Item = Result<String>> + Send + 'static> {
         if !self.is_initialized {
             return Err(anyhow!("Engine not initialized - call load_model() first"));
@@ -439,7 +701,7 @@ impl LlamaEngine {
         {
             use futures_util::StreamExt;
 
-            let _ = (prompt, max_tokens, sampling);
+            let _ = (prompt, prefix, fit, max_tokens, sampling);
             let s = futures_util::stream::once(async {
                 Err(anyhow!("Android streaming is not implemented"))
             })
@@ -450,6 +712,13 @@ impl LlamaEngine {
 
         #[cfg(not(target_os = "android"))]
         {
+            self.check_request_limits(prompt, max_tokens).await?;
+            // Slots keep no prefix states and draft nothing
+            if let Some(pool) = &self.slot_pool {
+                let rx = pool.submit(prompt, fit, max_tokens, sampling)?;
+                return Ok(ReceiverStream::new(rx));
+            }
+
             let backend = self
                 .cached_backend
                 .as_ref()
@@ -462,99 +731,136 @@ impl LlamaEngine {
                 .clone();
 
             let prompt = prompt.to_string();
-            let n_ctx = self.n_ctx;
+            let n_ctx = self.context_size();
             let sampling = sampling.clone();
+            let prefix_cache = self.prefix_cache.clone();
+            let draft_model = self.cached_draft_model.clone();
+            let draft_tokens = self.draft_tokens as usize;
+            let dropped_tokens = fit.map_or(0, |fit| fit.dropped_tokens);
+            let sliding = fit.is_some_and(|fit| fit.sliding);
+            // A cut prompt no longer starts with its prefix
+            let prefix = prefix.filter(|_| dropped_tokens == 0);
 
             let (tx, rx) = mpsc::channel::<Result<String>>(64);
 
+            // Child of the caller's span, e.g. the inference task forwarded by gpuf-s
+            let span = tracing::info_span!(
+                "llama_generate",
+                max_tokens,
+                prompt_tokens = tracing::field::Empty,
+                prefix_tokens = tracing::field::Empty,
+                completion_tokens = tracing::field::Empty,
+            );
+
             tokio::task::spawn_blocking(move || {
                 use llama_cpp_2::llama_batch::LlamaBatch;
-                use llama_cpp_2::model::{AddBos, Special};
-                use llama_cpp_2::sampling::LlamaSampler;
+                use llama_cpp_2::model::AddBos;
 
+                let _entered = span.enter();
                 let context_params =
                     LlamaContextParams::default().with_n_ctx(NonZeroU32::new(n_ctx));
 
                 let model_guard = model
-                    .lock()
+                    .write()
                     .map_err(|e| anyhow!("Failed to lock model: {:?}", e))?;
+                let draft_guard = match &draft_model {
+                    Some(draft_model) => Some(
+                        draft_model
+                            .lock()
+                            .map_err(|e| anyhow!("Failed to lock draft model: {:?}", e))?,
+                    ),
+                    None => None,
+                };
                 let mut context = model_guard
                     .new_context(&*backend, context_params)
                     .map_err(|e| anyhow!("Failed to create context: {:?}", e))?;
 
-                let tokens = model_guard
+                let mut tokens = model_guard
                     .str_to_token(&prompt, AddBos::Always)
                     .map_err(|e| anyhow!("Failed to tokenize prompt: {:?}", e))?;
-
-                let mut batch = LlamaBatch::new(tokens.len(), 1);
-                for (i, token) in tokens.iter().enumerate() {
-                    let is_last = i == tokens.len() - 1;
-                    batch
-                        .add(*token, i as i32, &[0], is_last)
-                        .map_err(|e| anyhow!("Failed to add token to batch: {:?}", e))?;
-                }
-
-                context
-                    .decode(&mut batch)
-                    .map_err(|e| anyhow!("Failed to decode batch: {:?}", e))?;
-
-                let mut samplers = Vec::new();
-                if sampling.repeat_penalty != 1.0 {
-                    samplers.push(LlamaSampler::penalties(
-                        sampling.repeat_last_n,
-                        sampling.repeat_penalty,
-                        0.0,
-                        0.0,
-                    ));
-                }
-                if sampling.top_k > 0 {
-                    samplers.push(LlamaSampler::top_k(sampling.top_k));
-                }
-                if sampling.top_p > 0.0 && sampling.top_p < 1.0 {
-                    samplers.push(LlamaSampler::top_p(sampling.top_p, sampling.min_keep));
-                }
-                samplers.push(LlamaSampler::temp(sampling.temperature));
-                if sampling.temperature <= 0.0 {
-                    samplers.push(LlamaSampler::greedy());
-                } else {
-                    samplers.push(LlamaSampler::dist(sampling.seed));
+                if dropped_tokens > 0 && tokens.len() > 1 {
+                    let dropped = dropped_tokens.min(tokens.len() - 2);
+                    tokens.drain(1..1 + dropped);
                 }
+                span.record("prompt_tokens", tokens.len());
+
+                tracing::info_span!("llama_prompt_eval").in_scope(|| {
+                    // Tokens of the shared prefix already in the context
+                    let start = match &prefix {
+                        Some(prefix) => evaluate_prefix(
+                            &model_guard,
+                            &mut context,
+                            &prefix_cache,
+                            prefix,
+                            &prompt,
+                            &tokens,
+                        )?,
+                        None => 0,
+                    };
+                    span.record("prefix_tokens", start);
+
+                    let rest = &tokens[start..];
+                    let mut batch = LlamaBatch::new(rest.len(), 1);
+                    for (i, token) in rest.iter().enumerate() {
+                        let is_last = i == rest.len() - 1;
+                        batch
+                            .add(*token, (start + i) as i32, &[0], is_last)
+                            .map_err(|e| anyhow!("Failed to add token to batch: {:?}", e))?;
+                    }
+                    context
+                        .decode(&mut batch)
+                        .map_err(|e| anyhow!("Failed to decode batch: {:?}", e))
+                })?;
 
-                let mut sampler = LlamaSampler::chain_simple(samplers);
+                let mut sampler = build_sampler(&sampling);
                 sampler.accept_many(tokens.iter());
 
-                let mut n_cur = tokens.len();
-                for _i in 0..max_tokens {
-                    let new_token = sampler.sample(&context, -1);
-                    sampler.accept(new_token);
-
-                    if new_token == model_guard.token_eos() {
-                        break;
-                    }
+                let mut emit = |token: LlamaToken| {
+                    send_piece(&model_guard, token, |piece| {
+                        tx.blocking_send(Ok(piece)).is_ok()
+                    })
+                };
 
-                    if let Ok(piece) = model_guard.token_to_str(new_token, Special::Tokenize) {
-                        if piece.contains("<|im_end|>")
-                            || piece.contains("<|eot_id|>")
-                            || piece.contains("<|end_of_text|>")
-                            || piece.contains("</s>")
-                        {
-                            break;
-                        }
+                let completion_tokens = match &draft_guard {
+                    // Drafts are not shifted along with the context
+                    Some(draft_model) if draft_tokens > 0 && !sliding => generate_speculative(
+                        &mut context,
+                        &mut sampler,
+                        draft_model,
+                        &backend,
+                        n_ctx,
+                        &tokens,
+                        max_tokens,
+                        draft_tokens,
+                        &mut emit,
+                    )?,
+                    _ => {
+                        let mut n_cur = tokens.len();
+                        let mut generated = 0;
+                        for _i in 0..max_tokens {
+                            let new_token = sampler.sample(&context, -1);
+                            sampler.accept(new_token);
+                            if !emit(new_token) {
+                                break;
+                            }
+                            generated += 1;
+                            if sliding && n_cur >= n_ctx as usize {
+                                n_cur = shift_context(&mut context, 0, n_cur)?;
+                            }
 
-                        if tx.blocking_send(Ok(piece)).is_err() {
-                            break;
+                            let mut next_batch = LlamaBatch::new(1, 1);
+                            next_batch
+                                .add(new_token, n_cur as i32, &[0], true)
+                                .map_err(|e| anyhow!("Failed to add token: {:?}", e))?;
+                            context
+                                .decode(&mut next_batch)
+                                .map_err(|e| anyhow!("Failed to decode token: {:?}", e))?;
+                            n_cur += 1;
                         }
+                        generated
                     }
-
-                    let mut next_batch = LlamaBatch::new(1, 1);
-                    next_batch
-                        .add(new_token, n_cur as i32, &[0], true)
-                        .map_err(|e| anyhow!("Failed to add token: {:?}", e))?;
-                    context
-                        .decode(&mut next_batch)
-                        .map_err(|e| anyhow!("Failed to decode token: {:?}", e))?;
-                    n_cur += 1;
-                }
+                };
+                span.record("completion_tokens", completion_tokens);
 
                 Ok::<(), anyhow::Error>(())
             });
@@ -578,6 +884,9 @@ impl LlamaEngine {
             llama_split_mode: LlamaSplitModeArg::Layer,
             llama_main_gpu: 0,
             llama_devices: None,
+            draft_model_path: None,
+            draft_tokens: 0,
+            parallel_slots: 1,
             is_initialized: false,
             models_dir,
             loading_status: Arc::new(RwLock::new("not_loaded".to_string())),
@@ -589,6 +898,14 @@ impl LlamaEngine {
             cached_model: None,
             #[cfg(not(target_os = "android"))]
             cached_model_path: None,
+            #[cfg(not(target_os = "android"))]
+            prefix_cache: Arc::new(Mutex::new(PrefixCache::default())),
+            #[cfg(not(target_os = "android"))]
+            cached_draft_model: None,
+            #[cfg(not(target_os = "android"))]
+            memory_plan: None,
+            #[cfg(not(target_os = "android"))]
+            slot_pool: None,
         }
     }
 
@@ -613,6 +930,9 @@ impl LlamaEngine {
             llama_split_mode,
             llama_main_gpu,
             llama_devices,
+            draft_model_path: None,
+            draft_tokens: 0,
+            parallel_slots: 1,
             is_initialized: false,
             models_dir,
             loading_status: Arc::new(RwLock::new("not_loaded".to_string())),
@@ -624,6 +944,14 @@ impl LlamaEngine {
             cached_model: None,
             #[cfg(not(target_os = "android"))]
             cached_model_path: None,
+            #[cfg(not(target_os = "android"))]
+            prefix_cache: Arc::new(Mutex::new(PrefixCache::default())),
+            #[cfg(not(target_os = "android"))]
+            cached_draft_model: None,
+            #[cfg(not(target_os = "android"))]
+            memory_plan: None,
+            #[cfg(not(target_os = "android"))]
+            slot_pool: None,
         }
     }
 
@@ -649,6 +977,9 @@ impl LlamaEngine {
             llama_split_mode,
             llama_main_gpu,
             llama_devices,
+            draft_model_path: None,
+            draft_tokens: 0,
+            parallel_slots: 1,
             is_initialized: false,
             models_dir,
             loading_status: Arc::new(RwLock::new("not_loaded".to_string())),
@@ -660,9 +991,34 @@ impl LlamaEngine {
             cached_model: None,
             #[cfg(not(target_os = "android"))]
             cached_model_path: None,
+            #[cfg(not(target_os = "android"))]
+            prefix_cache: Arc::new(Mutex::new(PrefixCache::default())),
+            #[cfg(not(target_os = "android"))]
+            cached_draft_model: None,
+            #[cfg(not(target_os = "android"))]
+            memory_plan: None,
+            #[cfg(not(target_os = "android"))]
+            slot_pool: None,
         }
     }
 
+    /// Draft `draft_tokens` tokens per step with the model at `draft_model_path`
+    /// and verify them with the main model in one batch. The draft model
+    /// must share the main model's vocabulary; 0 tokens turns drafting off.
+    pub fn with_draft_model(mut self, draft_model_path: Option<String>, draft_tokens: u32) -> Self {
+        self.draft_model_path = draft_model_path;
+        self.draft_tokens = draft_tokens;
+        self
+    }
+
+    /// Decode up to `parallel_slots` requests at once on the loaded model,
+    /// each in a sequence of `n_ctx` tokens; 0 for as many as its memory
+    /// fits. Shared prefix reuse and speculative decoding need a single slot.
+    pub fn with_parallel_slots(mut self, parallel_slots: u32) -> Self {
+        self.parallel_slots = parallel_slots;
+        self
+    }
+
     async fn ensure_initialized(&mut self) -> Result<()> {
         #[cfg(target_os = "android")]
         {
@@ -813,6 +1169,444 @@ impl LlamaEngine {
     }
 }
 
+#[cfg(not(target_os = "android"))]
+impl LlamaEngine {
+    /// `messages` in the loaded model's chat template, or in the fallback
+    /// format when the model has none
+    async fn chat_prompt(&self, messages: Vec<ChatMessage>) -> Result<String> {
+        let messages_for_fallback = messages.clone();
+        Ok(self
+            .apply_chat_template(messages, true)
+            .await?
+            .unwrap_or_else(|| super::build_chat_prompt_fallback(&messages_for_fallback)))
+    }
+
+    /// `messages` in the loaded model's chat template, None when the model
+    /// has none or it fails
+    async fn apply_chat_template(
+        &self,
+        messages: Vec<ChatMessage>,
+        add_assistant: bool,
+    ) -> Result<Option<String>> {
+        let cached_model = self
+            .cached_model
+            .as_ref()
+            .ok_or_else(|| anyhow!("Model not loaded - call load_model() first"))?
+            .clone();
+
+        let templated = tokio::task::spawn_blocking(move || -> Result<String> {
+            use llama_cpp_2::model::LlamaChatMessage;
+
+            let model_guard = cached_model
+                .read()
+                .map_err(|e| anyhow!("Failed to lock model: {:?}", e))?;
+
+            let tmpl = model_guard
+                .chat_template(None)
+                .map_err(|e| anyhow!("Failed to get chat template: {:?}", e))?;
+
+            let mut chat = Vec::with_capacity(messages.len());
+            for m in messages {
+                let msg = LlamaChatMessage::new(m.role, m.content)
+                    .map_err(|e| anyhow!("Failed to build chat message: {:?}", e))?;
+                chat.push(msg);
+            }
+
+            model_guard
+                .apply_chat_template(&tmpl, &chat, add_assistant)
+                .map_err(|e| anyhow!("Failed to apply chat template: {:?}", e))
+        })
+        .await;
+        Ok(match templated {
+            Ok(Ok(prompt)) => Some(prompt),
+            _ => None,
+        })
+    }
+
+    /// Part of `prompt`, the templated `messages`, made of the leading
+    /// messages `prefix` covers. None when the template renders them
+    /// differently alone, e.g. a model without a chat template.
+    async fn shared_prefix(
+        &self,
+        messages: &[ChatMessage],
+        prompt: &str,
+        prefix: &common::PromptPrefix,
+    ) -> Option<SharedPrefix> {
+        let count = prefix.messages as usize;
+        if count == 0 || count >= messages.len() {
+            return None;
+        }
+        let text = self
+            .apply_chat_template(messages[..count].to_vec(), false)
+            .await
+            .ok()??;
+        (!text.is_empty() && text.len() < prompt.len() && prompt.starts_with(&text)).then(|| {
+            SharedPrefix {
+                hash: prefix.hash.clone(),
+                len: text.len(),
+            }
+        })
+    }
+
+    /// Tokens in `prompt` for the loaded model
+    async fn count_tokens(&self, prompt: &str) -> Result<u32> {
+        let prompt = prompt.to_string();
+        let cached_model = self
+            .cached_model
+            .as_ref()
+            .ok_or_else(|| anyhow!("Model not loaded - call load_model() first"))?
+            .clone();
+
+        tokio::task::spawn_blocking(move || {
+            use llama_cpp_2::model::AddBos;
+
+            let model_guard = cached_model
+                .read()
+                .map_err(|e| anyhow!("Failed to lock model: {:?}", e))?;
+
+            let tokens = model_guard
+                .str_to_token(&prompt, AddBos::Always)
+                .map_err(|e| anyhow!("Failed to tokenize prompt: {:?}", e))?;
+            Ok::<u32, anyhow::Error>(tokens.len().min(u32::MAX as usize) as u32)
+        })
+        .await?
+    }
+
+    /// Fail a request over the worker's limits before it takes the model
+    async fn check_request_limits(&self, prompt: &str, max_tokens: usize) -> Result<()> {
+        let limits = request_limits::limits();
+        limits.check_max_tokens(u32::try_from(max_tokens).unwrap_or(u32::MAX))?;
+        limits.check_prompt_bytes(prompt.len())?;
+        if limits.max_prompt_tokens > 0 {
+            limits.check_prompt_tokens(self.count_tokens(prompt).await? as usize)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bring the context to the end of `prefix`, from its cached state or by
+/// evaluating and caching it. Returns the number of leading `tokens` in the
+/// context, 0 when the prefix does not tokenize to a start of `tokens`.
+#[cfg(not(target_os = "android"))]
+fn evaluate_prefix(
+    model: &LlamaModel,
+    context: &mut LlamaContext,
+    prefix_cache: &Mutex<PrefixCache>,
+    prefix: &SharedPrefix,
+    prompt: &str,
+    tokens: &[LlamaToken],
+) -> Result<usize> {
+    use llama_cpp_2::llama_batch::LlamaBatch;
+    use llama_cpp_2::model::AddBos;
+
+    let cached = prefix_cache
+        .lock()
+        .ok()
+        .and_then(|mut cache| cache.get(&prefix.hash));
+    if let Some(cached) = cached {
+        let matches = cached.tokens.len() < tokens.len()
+            && cached.tokens.iter().zip(tokens).all(|(c, t)| *c == t.0);
+        // SAFETY: the state was copied from a context of the same model
+        if matches && unsafe { context.set_state_data(&cached.state) } > 0 {
+            debug!(
+                "Reused KV state of prefix {} ({} tokens)",
+                prefix.hash,
+                cached.tokens.len()
+            );
+            return Ok(cached.tokens.len());
+        }
+    }
+
+    let prefix_tokens = model
+        .str_to_token(&prompt[..prefix.len], AddBos::Always)
+        .map_err(|e| anyhow!("Failed to tokenize prefix: {:?}", e))?;
+    if prefix_tokens.is_empty()
+        || prefix_tokens.len() >= tokens.len()
+        || !tokens.starts_with(&prefix_tokens)
+    {
+        return Ok(0);
+    }
+    let mut batch = LlamaBatch::new(prefix_tokens.len(), 1);
+    for (i, token) in prefix_tokens.iter().enumerate() {
+        batch
+            .add(*token, i as i32, &[0], false)
+            .map_err(|e| anyhow!("Failed to add token to batch: {:?}", e))?;
+    }
+    context
+        .decode(&mut batch)
+        .map_err(|e| anyhow!("Failed to decode prefix: {:?}", e))?;
+
+    let mut state = vec![0u8; context.get_state_size()];
+    // SAFETY: `state` holds get_state_size() bytes
+    let written = unsafe { context.copy_state_data(state.as_mut_ptr()) };
+    state.truncate(written);
+    debug!(
+        "Cached KV state of prefix {} ({} tokens, {} bytes)",
+        prefix.hash,
+        prefix_tokens.len(),
+        state.len()
+    );
+    if let Ok(mut cache) = prefix_cache.lock() {
+        cache.insert(
+            prefix.hash.clone(),
+            PrefixState {
+                tokens: prefix_tokens.iter().map(|t| t.0).collect(),
+                state,
+            },
+        );
+    }
+    Ok(prefix_tokens.len())
+}
+
+/// Sampler chain of `sampling`
+#[cfg(not(target_os = "android"))]
+pub(crate) fn build_sampler(sampling: &SamplingParams) -> llama_cpp_2::sampling::LlamaSampler {
+    use llama_cpp_2::sampling::LlamaSampler;
+
+    let mut samplers = Vec::new();
+    if sampling.repeat_penalty != 1.0 {
+        samplers.push(LlamaSampler::penalties(
+            sampling.repeat_last_n,
+            sampling.repeat_penalty,
+            0.0,
+            0.0,
+        ));
+    }
+    if sampling.top_k > 0 {
+        samplers.push(LlamaSampler::top_k(sampling.top_k));
+    }
+    if sampling.top_p > 0.0 && sampling.top_p < 1.0 {
+        samplers.push(LlamaSampler::top_p(sampling.top_p, sampling.min_keep));
+    }
+    samplers.push(LlamaSampler::temp(sampling.temperature));
+    if sampling.temperature <= 0.0 {
+        samplers.push(LlamaSampler::greedy());
+    } else {
+        samplers.push(LlamaSampler::dist(sampling.seed));
+    }
+    LlamaSampler::chain_simple(samplers)
+}
+
+/// Hand the piece of a generated `token` to `send`. False once generation
+/// ends, at an end of generation token or when `send` returns false.
+#[cfg(not(target_os = "android"))]
+pub(crate) fn send_piece(
+    model: &LlamaModel,
+    token: LlamaToken,
+    send: impl FnOnce(String) -> bool,
+) -> bool {
+    use llama_cpp_2::model::Special;
+
+    if token == model.token_eos() {
+        return false;
+    }
+    if let Ok(piece) = model.token_to_str(token, Special::Tokenize) {
+        // Stop sequences of ChatML, Llama 3, etc.
+        if piece.contains("<|im_end|>")
+            || piece.contains("<|eot_id|>")
+            || piece.contains("<|end_of_text|>")
+            || piece.contains("</s>")
+        {
+            return false;
+        }
+        if !send(piece) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Make room in the full sequence `seq` of `context`, `n_past` tokens long,
+/// by discarding the older half of those after the first and moving the
+/// newer half down. Returns the position of the next token.
+#[cfg(not(target_os = "android"))]
+pub(crate) fn shift_context(context: &mut LlamaContext, seq: i32, n_past: usize) -> Result<usize> {
+    let n_keep = 1;
+    let n_discard = (n_past - n_keep) / 2;
+    context.clear_kv_cache_seq(
+        Some(seq as u32),
+        Some(n_keep as u32),
+        Some((n_keep + n_discard) as u32),
+    )?;
+    context.kv_cache_seq_add(
+        seq,
+        Some((n_keep + n_discard) as u32),
+        Some(n_past as u32),
+        -(n_discard as i32),
+    )?;
+    debug!("Shifted {} tokens out of the context", n_discard);
+    Ok(n_past - n_discard)
+}
+
+/// Drafts to verify after `history`, whose last token is verified along with
+/// them: at most `draft_tokens` and `remaining`, and no more than the context
+/// of `n_ctx` tokens holds. None once not even the last token fits.
+#[cfg(not(target_os = "android"))]
+fn draft_len(draft_tokens: usize, remaining: usize, n_ctx: usize, history: usize) -> Option<usize> {
+    let room = n_ctx.checked_sub(history)?;
+    Some(draft_tokens.min(remaining).min(room))
+}
+
+/// Tokens the main model keeps of `drafts`, with `sample(i)` its token after
+/// the `i`th verified position: the drafts it samples itself and its own token
+/// at the first mismatch, or after the last draft, at most `budget` of them
+#[cfg(not(target_os = "android"))]
+fn verify_drafts(
+    drafts: &[LlamaToken],
+    budget: usize,
+    mut sample: impl FnMut(usize) -> LlamaToken,
+) -> Vec<LlamaToken> {
+    let mut kept = Vec::with_capacity(drafts.len() + 1);
+    for i in 0..=drafts.len() {
+        if kept.len() >= budget {
+            break;
+        }
+        let token = sample(i);
+        kept.push(token);
+        if drafts.get(i) != Some(&token) {
+            break;
+        }
+    }
+    kept
+}
+
+/// Leading tokens `a` and `b` have in common
+#[cfg(not(target_os = "android"))]
+fn common_prefix(a: &[LlamaToken], b: &[LlamaToken]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// Generate up to `max_tokens` after `tokens`, already decoded in `context`,
+/// drafting `draft_tokens` at a time with `draft_model` and keeping those the
+/// main model samples itself. The output matches plain sampling with
+/// `sampler`, and generation stops once the context of `n_ctx` tokens is
+/// full. Returns the number of generated tokens.
+#[cfg(not(target_os = "android"))]
+#[allow(clippy::too_many_arguments)]
+fn generate_speculative(
+    context: &mut LlamaContext,
+    sampler: &mut llama_cpp_2::sampling::LlamaSampler,
+    draft_model: &LlamaModel,
+    backend: &LlamaBackend,
+    n_ctx: u32,
+    tokens: &[LlamaToken],
+    max_tokens: usize,
+    draft_tokens: usize,
+    emit: &mut impl FnMut(LlamaToken) -> bool,
+) -> Result<usize> {
+    use llama_cpp_2::llama_batch::LlamaBatch;
+    use llama_cpp_2::sampling::LlamaSampler;
+
+    let context_params = LlamaContextParams::default().with_n_ctx(NonZeroU32::new(n_ctx));
+    let mut draft_context = draft_model
+        .new_context(backend, context_params)
+        .map_err(|e| anyhow!("Failed to create draft context: {:?}", e))?;
+    let mut draft_sampler = LlamaSampler::greedy();
+
+    // Prompt and accepted tokens; the main context holds all of them but the
+    // last, which is decoded together with the next drafts
+    let mut history = tokens.to_vec();
+    // Tokens in the draft context
+    let mut draft_kv: Vec<LlamaToken> = Vec::new();
+    let mut generated = 0;
+    let (mut drafted, mut accepted) = (0usize, 0usize);
+
+    let first = sampler.sample(context, -1);
+    sampler.accept(first);
+    if !emit(first) {
+        return Ok(0);
+    }
+    history.push(first);
+    generated += 1;
+
+    'generate: while generated < max_tokens {
+        // Draft greedily, never past the remaining budget or the context
+        let Some(n_draft) = draft_len(
+            draft_tokens,
+            max_tokens - generated,
+            n_ctx as usize,
+            history.len(),
+        ) else {
+            debug!(
+                "Speculative decoding filled the context of {} tokens",
+                n_ctx
+            );
+            break;
+        };
+
+        // Bring the draft context to the history, dropping rejected drafts
+        let keep = common_prefix(&draft_kv, &history);
+        draft_context.clear_kv_cache_seq(Some(0), Some(keep as u32), None)?;
+        draft_kv.truncate(keep);
+        let missing = &history[keep..];
+        let mut batch = LlamaBatch::new(missing.len(), 1);
+        for (i, token) in missing.iter().enumerate() {
+            batch
+                .add(*token, (keep + i) as i32, &[0], i == missing.len() - 1)
+                .map_err(|e| anyhow!("Failed to add token to draft batch: {:?}", e))?;
+        }
+        draft_context
+            .decode(&mut batch)
+            .map_err(|e| anyhow!("Failed to decode draft tokens: {:?}", e))?;
+        draft_kv.extend_from_slice(missing);
+
+        let mut drafts = Vec::with_capacity(n_draft);
+        for _ in 0..n_draft {
+            let token = draft_sampler.sample(&draft_context, -1);
+            drafts.push(token);
+            if drafts.len() == n_draft || token == draft_model.token_eos() {
+                break;
+            }
+            let mut batch = LlamaBatch::new(1, 1);
+            batch
+                .add(token, draft_kv.len() as i32, &[0], true)
+                .map_err(|e| anyhow!("Failed to add token to draft batch: {:?}", e))?;
+            draft_context
+                .decode(&mut batch)
+                .map_err(|e| anyhow!("Failed to decode draft token: {:?}", e))?;
+            draft_kv.push(token);
+        }
+        drafted += drafts.len();
+
+        // Verify the last accepted token and the drafts in one batch
+        let start = history.len() - 1;
+        let mut batch = LlamaBatch::new(drafts.len() + 1, 1);
+        for (i, token) in std::iter::once(&history[start]).chain(&drafts).enumerate() {
+            batch
+                .add(*token, (start + i) as i32, &[0], true)
+                .map_err(|e| anyhow!("Failed to add token to batch: {:?}", e))?;
+        }
+        context
+            .decode(&mut batch)
+            .map_err(|e| anyhow!("Failed to decode drafted tokens: {:?}", e))?;
+
+        // Sample after each position; a draft stands while the main model
+        // samples it, the first mismatch is replaced by the main model's token
+        let kept = verify_drafts(&drafts, max_tokens - generated, |i| {
+            let token = sampler.sample(context, i as i32);
+            sampler.accept(token);
+            token
+        });
+        accepted += common_prefix(&kept, &drafts);
+        for token in kept {
+            if !emit(token) {
+                break 'generate;
+            }
+            history.push(token);
+            generated += 1;
+        }
+
+        // Drop what the main context holds beyond the accepted tokens
+        context.clear_kv_cache_seq(Some(0), Some((history.len() - 1) as u32), None)?;
+    }
+
+    debug!(
+        "Speculative decoding accepted {}/{} drafted tokens",
+        accepted, drafted
+    );
+    Ok(generated)
+}
+
 impl Engine for LlamaEngine {
     fn init(&mut self) -> impl std::future::Future<Output = Result<()>> + Send {
         async move {
@@ -968,6 +1762,7 @@ impl Engine for LlamaEngine {
                         // Clear cached model and backend to free memory
                         self.cached_model = None;
                         self.cached_backend = None;
+                        self.slot_pool = None;
                         info!("Previous model cache cleared");
 
                         self.is_initialized = false;
@@ -1046,12 +1841,101 @@ impl Engine for LlamaEngine {
             Ok(())
         }
     }
+
+    fn infer(
+        &self,
+        request: InferenceRequest,
+    ) -> impl std::future::Future<Output = Result<InferenceStream>> + Send {
+        async move {
+            #[cfg(target_os = "android")]
+            {
+                let _ = request;
+                Err(anyhow!("Android streaming is not implemented"))
+            }
+
+            #[cfg(not(target_os = "android"))]
+            {
+                use futures_util::StreamExt;
+
+                let (prompt, prefix) = match request.input {
+                    InferenceInput::Prompt(prompt) => (prompt, None),
+                    InferenceInput::Chat(messages) => {
+                        let prompt = self.chat_prompt(messages.clone()).await?;
+                        let prefix = match &request.prefix {
+                            Some(prefix) => self.shared_prefix(&messages, &prompt, prefix).await,
+                            None => None,
+                        };
+                        (prompt, prefix)
+                    }
+                };
+                let prompt_tokens = self.count_tokens(&prompt).await?;
+                let fit = fit_context(
+                    prompt_tokens as usize,
+                    request.max_tokens as usize,
+                    self.context_size() as usize,
+                    request.overflow,
+                )?;
+                let sampling = SamplingParams {
+                    temperature: request.temperature,
+                    top_k: request.top_k as i32,
+                    top_p: request.top_p,
+                    repeat_penalty: request.repeat_penalty,
+                    repeat_last_n: request.repeat_last_n,
+                    seed: 0,
+                    min_keep: request.min_keep as usize,
+                };
+                let pieces = self
+                    .stream_with_prefix(
+                        &prompt,
+                        prefix,
+                        fit,
+                        request.max_tokens as usize,
+                        &sampling,
+                    )
+                    .await?;
+
+                let dropped_tokens = fit.map_or(0, |fit| fit.dropped_tokens as u32);
+                // Each piece is one token, counted by the caller
+                let mut head = vec![Ok(InferenceEvent::Usage {
+                    prompt_tokens: prompt_tokens - dropped_tokens,
+                    completion_tokens: 0,
+                })];
+                if fit.is_some() {
+                    head.insert(
+                        0,
+                        Ok(InferenceEvent::ContextOverflow {
+                            strategy: request.overflow,
+                            dropped_tokens,
+                        }),
+                    );
+                }
+                let events = futures_util::stream::iter(head)
+                    .chain(pieces.map(|piece| piece.map(InferenceEvent::Token)));
+                Ok(Box::pin(events) as InferenceStream)
+            }
+        }
+    }
+
+    fn list_models(&self) -> impl std::future::Future<Output = Result<Vec<Model>>> + Send {
+        async move {
+            let model_path = self.get_current_model().await;
+            if model_path.is_empty() {
+                return Ok(Vec::new());
+            }
+            Ok(vec![Model {
+                id: super::model_id_from_path(&model_path),
+                object: "model".to_string(),
+                created: 0,
+                owned_by: "gpuf-c".to_string(),
+            }])
+        }
+    }
 }
 
 impl Drop for LlamaEngine {
     fn drop(&mut self) {
         // Note: We do NOT clear cached_model here because:
-        // 1. LlamaEngine is Clone, so multiple instances share the same Arc<Mutex<LlamaModel>>
+        // 1. LlamaEngine is Clone, so multiple instances share the same SharedModel
         // 2. The global GLOBAL_ENGINE cache holds a reference to the engine
         // 3. Arc automatically manages reference counting and will free memory when last reference is dropped
         // 4. Clearing here would break the global cache and cause model to be freed prematurely
@@ -1177,6 +2061,12 @@ impl LlamaEngine {
         status.as_str() == "loaded"
     }
 
+    /// Error of the last model load, None while loading or once loaded
+    pub async fn load_error(&self) -> Option<String> {
+        let status = self.loading_status.read().await;
+        status.strip_prefix("error: ").map(String::from)
+    }
+
     /// Get detailed loading status
     pub async fn get_loading_status(&self) -> String {
         let status = self.loading_status.read().await;
@@ -1333,3 +2223,81 @@ impl LlamaEngine {
         Ok(metadata.len())
     }
 }
+
+#[test]
+fn test_fit_context() {
+    assert_eq!(
+        fit_context(100, 100, 512, ContextOverflow::Error).unwrap(),
+        None
+    );
+    let err = fit_context(400, 200, 512, ContextOverflow::Error).unwrap_err();
+    assert!(err.to_string().starts_with(CONTEXT_LENGTH_EXCEEDED));
+
+    let fit = fit_context(400, 200, 512, ContextOverflow::TruncateLeft).unwrap();
+    assert_eq!(
+        fit,
+        Some(ContextFit {
+            dropped_tokens: 88,
+            sliding: false,
+        })
+    );
+    // No room left for the prompt
+    assert!(fit_context(400, 511, 512, ContextOverflow::TruncateLeft).is_err());
+
+    // The prompt fits, generation shifts the context
+    let fit = fit_context(300, 400, 512, ContextOverflow::SlidingWindow).unwrap();
+    assert_eq!(
+        fit,
+        Some(ContextFit {
+            dropped_tokens: 0,
+            sliding: true,
+        })
+    );
+    let fit = fit_context(500, 100, 512, ContextOverflow::SlidingWindow).unwrap();
+    assert_eq!(fit.map(|fit| fit.dropped_tokens), Some(116));
+}
+
+#[cfg(not(target_os = "android"))]
+#[test]
+fn test_speculative_bookkeeping() {
+    let tokens = |ids: &[i32]| {
+        ids.iter()
+            .map(|&id| LlamaToken::new(id))
+            .collect::<Vec<_>>()
+    };
+    let drafts = tokens(&[10, 11, 12]);
+
+    // The main model takes two drafts and replaces the third
+    let main = tokens(&[10, 11, 99, 50]);
+    assert_eq!(
+        verify_drafts(&drafts, 8, |i| main[i]),
+        tokens(&[10, 11, 99])
+    );
+    // Every draft taken adds the main model's next token
+    let main = tokens(&[10, 11, 12, 7]);
+    assert_eq!(verify_drafts(&drafts, 8, |i| main[i]), main);
+    assert_eq!(verify_drafts(&drafts, 2, |i| main[i]), tokens(&[10, 11]));
+    assert_eq!(verify_drafts(&[], 8, |_| main[3]), tokens(&[7]));
+    assert_eq!(verify_drafts(&drafts, 8, |_| main[3]), tokens(&[7]));
+
+    // The prompt ends in 3, verified along with the drafts at positions 2..6.
+    // The draft context holds the drafts it decoded, all but the last.
+    let mut history = tokens(&[1, 2, 3]);
+    let draft_kv = tokens(&[1, 2, 3, 10, 11]);
+    history.extend(verify_drafts(&drafts, 8, |i| tokens(&[10, 11, 99])[i]));
+    // The main context keeps all of the history but its last token, so the
+    // rejected draft 12 is cleared from position 5
+    assert_eq!(history[..history.len() - 1], tokens(&[1, 2, 3, 10, 11]));
+    // and the draft context only needs the main model's token
+    assert_eq!(common_prefix(&draft_kv, &history), 5);
+    // A rejected first draft leaves the draft context with the prompt
+    let history = tokens(&[1, 2, 3, 99]);
+    assert_eq!(common_prefix(&draft_kv, &history), 3);
+
+    // Drafts stop at the budget and the end of the context
+    assert_eq!(draft_len(8, 100, 16, 6), Some(8));
+    assert_eq!(draft_len(8, 3, 16, 6), Some(3));
+    assert_eq!(draft_len(8, 100, 16, 12), Some(4));
+    assert_eq!(draft_len(8, 100, 16, 16), Some(0));
+    assert_eq!(draft_len(8, 100, 16, 17), None);
+}
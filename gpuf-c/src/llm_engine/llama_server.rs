@@ -8,6 +8,7 @@ use axum::{
     routing::{get, post},
     Json, Router,
 };
+use common::{LIMIT_EXCEEDED, REQUEST_TOO_LARGE};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -401,14 +402,23 @@ impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         error!("API error: {}", self.0);
 
+        // Requests over the worker's limits are the caller's to fix
+        let message = self.0.to_string();
+        let (status, kind) = if message.starts_with(REQUEST_TOO_LARGE) {
+            (StatusCode::PAYLOAD_TOO_LARGE, "invalid_request_error")
+        } else if message.starts_with(LIMIT_EXCEEDED) {
+            (StatusCode::UNPROCESSABLE_ENTITY, "invalid_request_error")
+        } else {
+            (StatusCode::INTERNAL_SERVER_ERROR, "internal_error")
+        };
         let error_response = ErrorResponse {
             error: ErrorDetail {
-                message: self.0.to_string(),
-                r#type: "internal_error".to_string(),
+                message,
+                r#type: kind.to_string(),
             },
         };
 
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+        (status, Json(error_response)).into_response()
     }
 }
 
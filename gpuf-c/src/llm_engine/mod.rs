@@ -1,16 +1,34 @@
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod bench;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod chat;
+pub mod external_engine;
+pub mod image_engine;
 pub mod inference_service;
 #[cfg(not(target_os = "ios"))]
 pub mod llama_engine;
 pub mod llama_server;
 pub mod ollama_engine;
+pub mod openai_api;
+pub mod prefix_cache;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod slots;
+#[cfg(not(target_os = "ios"))]
+pub mod supervisor;
+pub mod trtllm_engine;
 pub mod vllm_engine;
 
 // Re-export commonly used types
-use crate::util::cmd::EngineType;
+use crate::util::cmd::{Args, EngineType, VllmMode};
 use anyhow::{anyhow, Result};
+use common::{ChatMessage, ContextOverflow, Model, PromptPrefix};
+use futures_util::Stream;
+use std::pin::Pin;
 
+pub use external_engine::ExternalEngine;
 #[cfg(not(target_os = "ios"))]
 pub use llama_engine::LlamaEngine;
+pub use trtllm_engine::TrtLlmEngine;
 
 #[cfg(target_os = "ios")]
 #[derive(Clone, Default)]
@@ -43,6 +61,17 @@ impl Engine for LlamaEngine {
     fn stop_worker(&mut self) -> impl std::future::Future<Output = Result<()>> + Send {
         async move { Ok(()) }
     }
+
+    fn infer(
+        &self,
+        _request: InferenceRequest,
+    ) -> impl std::future::Future<Output = Result<InferenceStream>> + Send {
+        async move { Err(anyhow!("LlamaEngine is not available on iOS in this build")) }
+    }
+
+    fn list_models(&self) -> impl std::future::Future<Output = Result<Vec<Model>>> + Send {
+        async move { Ok(Vec::new()) }
+    }
 }
 use reqwest::Client;
 
@@ -53,6 +82,9 @@ const VLLM_DEFAULT_PORT: u16 = 8000;
 const VLLM_CONTAINER_NAME: &str = "vllm_engine_container";
 const VLLM_CONTAINER_PATH: &str = "/app/default_template.jinja";
 
+const TRTLLM_DEFAULT_PORT: u16 = 9000;
+const TRTLLM_CONTAINER_NAME: &str = "trtllm_engine_container";
+
 const DEFAULT_CHAT_TEMPLATE: &str = r#"
 {% if not add_generation_prompt is defined %}
   {% set add_generation_prompt = false %}
@@ -69,6 +101,55 @@ const DEFAULT_CHAT_TEMPLATE: &str = r#"
 {% endif %}
 "#;
 
+/// What an inference request generates from
+#[derive(Debug, Clone)]
+pub enum InferenceInput {
+    /// Prompt sent as is
+    Prompt(String),
+    /// Chat the engine applies its model's chat template to
+    Chat(Vec<ChatMessage>),
+}
+
+/// Inference request any engine serves
+#[derive(Debug, Clone)]
+pub struct InferenceRequest {
+    /// Model to run, or the engine's first
+    pub model: Option<String>,
+    pub input: InferenceInput,
+    pub max_tokens: u32,
+    pub temperature: f32,
+    /// Sampling knobs past temperature and top_p only llama.cpp applies
+    pub top_k: u32,
+    pub top_p: f32,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: i32,
+    pub min_keep: u32,
+    /// Leading chat messages shared with other requests, whose KV state
+    /// llama.cpp keeps for reuse
+    pub prefix: Option<PromptPrefix>,
+    /// What llama.cpp does when the prompt and max_tokens exceed its context
+    pub overflow: ContextOverflow,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InferenceEvent {
+    /// Generated text, about one token
+    Token(String),
+    /// Token counts reported by the engine. A completion count of 0 leaves
+    /// counting the tokens to the caller.
+    Usage {
+        prompt_tokens: u32,
+        completion_tokens: u32,
+    },
+    /// The prompt was too long for the context and fit with `strategy`
+    ContextOverflow {
+        strategy: ContextOverflow,
+        dropped_tokens: u32,
+    },
+}
+
+pub type InferenceStream = Pin<Box<dyn Stream<Item = Result<InferenceEvent>> + Send>>;
+
 pub trait Engine {
     fn init(&mut self) -> impl std::future::Future<Output = Result<()>> + Send;
     #[allow(dead_code)]
@@ -80,6 +161,86 @@ pub trait Engine {
     fn start_worker(&mut self) -> impl std::future::Future<Output = Result<()>> + Send;
     #[allow(dead_code)]
     fn stop_worker(&mut self) -> impl std::future::Future<Output = Result<()>> + Send;
+    /// Stream the output of `request`
+    fn infer(
+        &self,
+        request: InferenceRequest,
+    ) -> impl std::future::Future<Output = Result<InferenceStream>> + Send;
+    /// Models the engine serves now
+    fn list_models(&self) -> impl std::future::Future<Output = Result<Vec<Model>>> + Send;
+}
+
+/// Model id reported for a model file: the file name without its extension
+/// and shard suffix
+pub fn model_id_from_path(model_path: &str) -> String {
+    let lower = model_path.to_ascii_lowercase();
+    if lower.contains("llama-3") || lower.contains("llama3") {
+        return "llama3".to_string();
+    }
+
+    let file_name = std::path::Path::new(model_path)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or(model_path);
+
+    let base = file_name.trim_end_matches(".gguf").trim_end_matches(".bin");
+
+    // Rule B: keep file name, but strip shard suffix like "-00001-of-00002".
+    // This avoids exposing split file indices as the model id.
+    if let Some(of_pos) = base.rfind("-of-") {
+        let right = &base[of_pos + 4..];
+        if !right.is_empty() && right.chars().all(|c| c.is_ascii_digit()) {
+            let left_all = &base[..of_pos];
+            if let Some(left_dash) = left_all.rfind('-') {
+                let left_digits = &left_all[left_dash + 1..];
+                if !left_digits.is_empty() && left_digits.chars().all(|c| c.is_ascii_digit()) {
+                    return left_all[..left_dash].to_string();
+                }
+            }
+        }
+    }
+
+    base.to_string()
+}
+
+/// Prompt for a chat when the model has no usable chat template, in the
+/// format `CHAT_TEMPLATE` names
+pub fn build_chat_prompt_fallback(messages: &[ChatMessage]) -> String {
+    let template = std::env::var("CHAT_TEMPLATE").unwrap_or_else(|_| "simple".to_string());
+    match template.to_ascii_lowercase().as_str() {
+        "chatml" => {
+            let mut prompt = String::new();
+            for msg in messages {
+                prompt.push_str(&format!("{}\n{}\n", msg.role, msg.content));
+            }
+            prompt.push_str("\nassistant\n");
+            prompt
+        }
+        "llama3" => {
+            let mut prompt = String::from("<|begin_of_text|>");
+            for msg in messages {
+                prompt.push_str(&format!(
+                    "<|start_header_id|>{}\n\n{}\n<|eot_id|>",
+                    msg.role, msg.content
+                ));
+            }
+            prompt.push_str("<|start_header_id|>assistant\n\n");
+            prompt
+        }
+        _ => {
+            let mut prompt = String::new();
+            for msg in messages {
+                let role = match msg.role.as_str() {
+                    "user" => "Human",
+                    "assistant" => "Assistant",
+                    _ => "System",
+                };
+                prompt.push_str(&format!("{}: {}\n\n", role, msg.content));
+            }
+            prompt.push_str("Assistant: ");
+            prompt
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -101,7 +262,6 @@ pub struct VLLMEngine {
     worker_handler: Option<tokio::task::JoinHandle<()>>,
     #[allow(dead_code)]
     show_worker_log: bool,
-    #[allow(dead_code)]
     base_url: String,
     #[allow(dead_code)]
     gpu_count: u32,
@@ -109,6 +269,11 @@ pub struct VLLMEngine {
     //HUGGING_FACE_HUB_TOKEN
     hugging_face_hub_token: Option<String>,
     chat_template_path: Option<String>,
+    mode: VllmMode,
+    /// Interpreter to run vLLM with in native mode, else `vllm` from PATH
+    python: Option<String>,
+    process: Option<Arc<vllm_engine::VllmProcess>>,
+    placement: vllm_engine::VllmPlacement,
 }
 
 impl Default for VLLMEngine {
@@ -129,6 +294,10 @@ impl Clone for VLLMEngine {
             container_id: self.container_id.clone(),
             hugging_face_hub_token: self.hugging_face_hub_token.clone(),
             chat_template_path: self.chat_template_path.clone(),
+            mode: self.mode,
+            python: self.python.clone(),
+            process: self.process.clone(),
+            placement: self.placement.clone(),
         }
     }
 }
@@ -143,6 +312,8 @@ pub struct OllamaEngine {
     base_url: String,
     container_id: Option<String>,
     gpu_count: u32,
+    /// How long Ollama keeps a model loaded after its last request
+    keep_alive: String,
 }
 
 impl Default for OllamaEngine {
@@ -156,6 +327,8 @@ pub enum AnyEngine {
     VLLM(VLLMEngine),
     Ollama(OllamaEngine),
     Llama(LlamaEngine),
+    External(ExternalEngine),
+    TrtLlm(TrtLlmEngine),
 }
 
 impl Engine for AnyEngine {
@@ -165,6 +338,8 @@ impl Engine for AnyEngine {
                 AnyEngine::VLLM(engine) => engine.init().await,
                 AnyEngine::Ollama(engine) => engine.init().await,
                 AnyEngine::Llama(engine) => engine.init().await,
+                AnyEngine::External(engine) => engine.init().await,
+                AnyEngine::TrtLlm(engine) => engine.init().await,
             }
         }
     }
@@ -178,6 +353,8 @@ impl Engine for AnyEngine {
                 AnyEngine::VLLM(engine) => engine.set_models(models).await,
                 AnyEngine::Ollama(engine) => engine.set_models(models).await,
                 AnyEngine::Llama(engine) => engine.set_models(models).await,
+                AnyEngine::External(engine) => engine.set_models(models).await,
+                AnyEngine::TrtLlm(engine) => engine.set_models(models).await,
             }
         }
     }
@@ -188,6 +365,8 @@ impl Engine for AnyEngine {
                 AnyEngine::VLLM(engine) => engine.start_worker().await,
                 AnyEngine::Ollama(engine) => engine.start_worker().await,
                 AnyEngine::Llama(engine) => engine.start_worker().await,
+                AnyEngine::External(engine) => engine.start_worker().await,
+                AnyEngine::TrtLlm(engine) => engine.start_worker().await,
             }
         }
     }
@@ -198,22 +377,74 @@ impl Engine for AnyEngine {
                 AnyEngine::VLLM(engine) => engine.stop_worker().await,
                 AnyEngine::Ollama(engine) => engine.stop_worker().await,
                 AnyEngine::Llama(engine) => engine.stop_worker().await,
+                AnyEngine::External(engine) => engine.stop_worker().await,
+                AnyEngine::TrtLlm(engine) => engine.stop_worker().await,
+            }
+        }
+    }
+
+    fn infer(
+        &self,
+        request: InferenceRequest,
+    ) -> impl std::future::Future<Output = Result<InferenceStream>> + Send {
+        async move {
+            match self {
+                AnyEngine::VLLM(engine) => engine.infer(request).await,
+                AnyEngine::Ollama(engine) => engine.infer(request).await,
+                AnyEngine::Llama(engine) => engine.infer(request).await,
+                AnyEngine::External(engine) => engine.infer(request).await,
+                AnyEngine::TrtLlm(engine) => engine.infer(request).await,
+            }
+        }
+    }
+
+    fn list_models(&self) -> impl std::future::Future<Output = Result<Vec<Model>>> + Send {
+        async move {
+            match self {
+                AnyEngine::VLLM(engine) => Engine::list_models(engine).await,
+                AnyEngine::Ollama(engine) => Engine::list_models(engine).await,
+                AnyEngine::Llama(engine) => Engine::list_models(engine).await,
+                AnyEngine::External(engine) => Engine::list_models(engine).await,
+                AnyEngine::TrtLlm(engine) => Engine::list_models(engine).await,
             }
         }
     }
 }
 
+/// Engine of `args.engine_type`, configured from `args`
 #[allow(dead_code)]
-pub fn create_engine(
-    engine_type: EngineType,
-    hugging_face_hub_token: Option<String>,
-    chat_template_path: Option<String>,
-) -> AnyEngine {
-    match engine_type {
+pub fn create_engine(args: &Args, vllm_placement: vllm_engine::VllmPlacement) -> Result<AnyEngine> {
+    Ok(match args.engine_type {
         EngineType::VLLM => {
-            AnyEngine::VLLM(VLLMEngine::new(hugging_face_hub_token, chat_template_path))
+            let engine = VLLMEngine::new(
+                args.hugging_face_hub_token.clone(),
+                args.chat_template_path.clone(),
+            )
+            .with_placement(vllm_placement);
+            AnyEngine::VLLM(match args.vllm_mode {
+                VllmMode::Docker => engine,
+                VllmMode::Native => engine.with_native_process(args.vllm_python.clone()),
+            })
         }
         EngineType::OLLAMA => AnyEngine::Ollama(OllamaEngine::new()),
         EngineType::LLAMA => AnyEngine::Llama(LlamaEngine::new()),
-    }
+        EngineType::EXTERNAL => {
+            let url = args
+                .external_url
+                .as_deref()
+                .ok_or_else(|| anyhow!("--external-url is required for the external engine"))?;
+            AnyEngine::External(ExternalEngine::new(url)?)
+        }
+        EngineType::TRTLLM => AnyEngine::TrtLlm(TrtLlmEngine::new(
+            args.trtllm_image.clone(),
+            args.trtllm_model_repository.clone().map(Into::into),
+            args.trtllm_tokenizer.clone(),
+            args.hugging_face_hub_token.clone(),
+        )),
+        EngineType::SD => {
+            return Err(anyhow!(
+                "The sd engine generates images through the image service, not an inference engine"
+            ))
+        }
+    })
 }
@@ -1,16 +1,72 @@
-use super::{Engine, OllamaEngine, OLLAMA_CONTAINER_NAME, OLLAMA_DEFAULT_PORT};
+use super::{
+    openai_api, Engine, InferenceRequest, InferenceStream, OllamaEngine, OLLAMA_CONTAINER_NAME,
+    OLLAMA_DEFAULT_PORT,
+};
 #[cfg(not(target_os = "macos"))]
 use crate::util::system_info::get_gpu_count;
 
 use anyhow::{anyhow, Result};
+use futures_util::StreamExt;
 use reqwest::Client;
+use serde::Deserialize;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::process::Stdio;
 use std::time::Duration;
 use tokio::process::Command;
 use tokio::time::sleep;
 use tracing::{debug, error, info};
 
+/// Default of `--ollama-keep-alive`: models stay loaded until Ollama stops
+pub const DEFAULT_KEEP_ALIVE: &str = "-1";
+
+/// One line of the `/api/pull` stream
+#[derive(Debug, Deserialize)]
+struct PullStatus {
+    #[serde(default)]
+    status: String,
+    digest: Option<String>,
+    total: Option<u64>,
+    completed: Option<u64>,
+    error: Option<String>,
+}
+
+/// Progress of an `ollama pull`, summed over the layers seen so far
+#[derive(Debug, Clone, Default)]
+pub struct PullProgress {
+    pub status: String,
+    pub completed_bytes: u64,
+    pub total_bytes: u64,
+    layers: HashMap<String, (u64, u64)>,
+}
+
+impl PullProgress {
+    /// Apply one line of the pull stream. Returns true once the pull succeeded.
+    fn update(&mut self, line: &[u8]) -> Result<bool> {
+        let status: PullStatus = serde_json::from_slice(line)
+            .map_err(|e| anyhow!("Failed to parse JSON from Ollama: {}", e))?;
+        if let Some(error) = status.error {
+            return Err(anyhow!("Ollama pull failed: {}", error));
+        }
+        if let (Some(digest), Some(total)) = (status.digest, status.total) {
+            self.layers
+                .insert(digest, (status.completed.unwrap_or(0), total));
+            self.completed_bytes = self.layers.values().map(|(completed, _)| completed).sum();
+            self.total_bytes = self.layers.values().map(|(_, total)| total).sum();
+        }
+        let done = status.status == "success";
+        self.status = status.status;
+        Ok(done)
+    }
+
+    pub fn percentage(&self) -> f32 {
+        if self.total_bytes == 0 {
+            return 0.0;
+        }
+        self.completed_bytes as f32 / self.total_bytes as f32 * 100.0
+    }
+}
+
 impl OllamaEngine {
     pub fn new() -> Self {
         OllamaEngine {
@@ -23,6 +79,29 @@ impl OllamaEngine {
             gpu_count: get_gpu_count().unwrap_or(0) as u32,
             #[cfg(target_os = "macos")]
             gpu_count: 1,
+            keep_alive: DEFAULT_KEEP_ALIVE.to_string(),
+        }
+    }
+
+    /// Talk to an Ollama server already listening on `port`
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.base_url = format!("http://localhost:{}", port);
+        self
+    }
+
+    /// Keep models loaded for `keep_alive`: a duration such as "30m", a
+    /// number of seconds, or a negative number for as long as Ollama runs
+    pub fn with_keep_alive(mut self, keep_alive: String) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// `keep_alive` as the Ollama API takes it: a number of seconds, or a
+    /// duration string
+    fn keep_alive_value(&self) -> Value {
+        match self.keep_alive.parse::<i64>() {
+            Ok(seconds) => Value::from(seconds),
+            Err(_) => Value::from(self.keep_alive.as_str()),
         }
     }
 
@@ -93,6 +172,7 @@ impl OllamaEngine {
             error!("Failed to create model directory {}: {}", model_dir, e);
         }
         let volume_flag = format!("-v {}:/root/.ollama", model_dir);
+        let keep_alive_env = format!("OLLAMA_KEEP_ALIVE={}", self.keep_alive);
 
         // TODO: auto set gpu number
         let mut args = vec![
@@ -106,6 +186,8 @@ impl OllamaEngine {
             "OLLAMA_HOST=0.0.0.0",
             "-e",
             "OLLAMA_GPU_LAYERS=all",
+            "-e",
+            &keep_alive_env,
         ];
 
         if cfg!(target_os = "macos") {
@@ -223,15 +305,46 @@ impl OllamaEngine {
         Ok(models)
     }
 
+    /// Remove a local model from Ollama
+    #[allow(dead_code)]
+    pub async fn delete_model(&self, model: &str) -> Result<()> {
+        let response = self
+            .client
+            .delete(format!("{}/api/delete", self.base_url))
+            .json(&serde_json::json!({ "model": model }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to delete model {}: {}",
+                model,
+                response.text().await?
+            ));
+        }
+
+        info!("Deleted model: {}", model);
+        Ok(())
+    }
+
     #[allow(dead_code)]
     pub async fn pull_model(&self, model: &str) -> Result<()> {
+        self.pull_model_with_progress(model, |_| {}).await
+    }
+
+    /// Pull `model`, calling `on_progress` after each update Ollama streams
+    pub async fn pull_model_with_progress(
+        &self,
+        model: &str,
+        mut on_progress: impl FnMut(&PullProgress),
+    ) -> Result<()> {
         info!("Pulling model: {}", model);
         let response = self
             .client
-            .post(&format!("{}/api/pull", self.base_url))
+            .post(format!("{}/api/pull", self.base_url))
             .json(&serde_json::json!({
-                "name": model,
-                "stream": false
+                "model": model,
+                "stream": true
             }))
             .send()
             .await?;
@@ -244,7 +357,55 @@ impl OllamaEngine {
             ));
         }
 
-        info!("Successfully pulled model: {}", model);
+        let mut progress = PullProgress::default();
+        let mut stream = response.bytes_stream();
+        let mut buffer = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            buffer.extend_from_slice(&chunk?);
+            // Chunks do not follow line boundaries
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let line = line.trim_ascii();
+                if line.is_empty() {
+                    continue;
+                }
+                let done = progress.update(line)?;
+                on_progress(&progress);
+                if done {
+                    info!("Successfully pulled model: {}", model);
+                    return Ok(());
+                }
+            }
+        }
+
+        Err(anyhow!(
+            "Ollama pull of {} ended before it succeeded",
+            model
+        ))
+    }
+
+    /// Load `model` into memory and keep it there for the keep-alive time,
+    /// so the first request does not wait for it to load
+    pub async fn keep_resident(&self, model: &str) -> Result<()> {
+        let response = self
+            .client
+            .post(format!("{}/api/generate", self.base_url))
+            .json(&serde_json::json!({
+                "model": model,
+                "keep_alive": self.keep_alive_value()
+            }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to load model {}: {}",
+                model,
+                response.text().await?
+            ));
+        }
+
+        info!("Model {} loaded, keep alive {}", model, self.keep_alive);
         Ok(())
     }
 }
@@ -303,6 +464,18 @@ impl Engine for OllamaEngine {
             Ok(())
         }
     }
+
+    /// Through Ollama's OpenAI-compatible API
+    fn infer(
+        &self,
+        request: InferenceRequest,
+    ) -> impl std::future::Future<Output = Result<InferenceStream>> + Send {
+        openai_api::infer(&self.client, &self.base_url, request)
+    }
+
+    fn list_models(&self) -> impl std::future::Future<Output = Result<Vec<common::Model>>> + Send {
+        openai_api::list_models(&self.client, &self.base_url, "ollama")
+    }
 }
 
 #[cfg(test)]
@@ -328,4 +501,36 @@ mod tests {
             .await
             .expect("start_container error");
     }
+
+    #[test]
+    fn test_pull_progress() {
+        let mut progress = PullProgress::default();
+        let lines: [&[u8]; 6] = [
+            br#"{"status":"pulling manifest"}"#,
+            br#"{"status":"pulling aaa","digest":"sha256:aaa","total":100,"completed":50}"#,
+            br#"{"status":"pulling bbb","digest":"sha256:bbb","total":300}"#,
+            br#"{"status":"pulling aaa","digest":"sha256:aaa","total":100,"completed":100}"#,
+            br#"{"status":"verifying sha256 digest"}"#,
+            br#"{"status":"success"}"#,
+        ];
+        let mut done = Vec::new();
+        for line in lines {
+            done.push(progress.update(line).unwrap());
+        }
+        assert_eq!(done, vec![false, false, false, false, false, true]);
+        assert_eq!(progress.completed_bytes, 100);
+        assert_eq!(progress.total_bytes, 400);
+        assert_eq!(progress.percentage(), 25.0);
+
+        let mut progress = PullProgress::default();
+        assert!(progress
+            .update(br#"{"error":"pull model manifest: file does not exist"}"#)
+            .is_err());
+        assert!(progress.update(b"not json").is_err());
+
+        let engine = OllamaEngine::new().with_keep_alive("30m".to_string());
+        assert_eq!(engine.keep_alive_value(), Value::from("30m"));
+        let engine = engine.with_keep_alive(DEFAULT_KEEP_ALIVE.to_string());
+        assert_eq!(engine.keep_alive_value(), Value::from(-1));
+    }
 }
@@ -0,0 +1,305 @@
+//! Client of the OpenAI-compatible API that vLLM, Ollama, TensorRT-LLM and
+//! external servers expose, so the server engines list models, stream
+//! inference tasks and embed texts the same way.
+
+use super::{InferenceEvent, InferenceInput, InferenceRequest, InferenceStream};
+use anyhow::{anyhow, Result};
+use common::Model;
+use futures_util::StreamExt;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::debug;
+
+/// Longest a streamed completion may take
+const INFERENCE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// One entry of `/v1/models`. Servers disagree on all fields but `id`.
+#[derive(Debug, Deserialize)]
+struct ListedModel {
+    id: String,
+    #[serde(default)]
+    object: Option<String>,
+    #[serde(default)]
+    created: Option<u64>,
+    #[serde(default)]
+    owned_by: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    data: Vec<ListedModel>,
+}
+
+fn parse_models(body: &[u8], owned_by: &str) -> Result<Vec<Model>> {
+    let list: ModelList =
+        serde_json::from_slice(body).map_err(|e| anyhow!("Failed to parse model list: {}", e))?;
+    Ok(list
+        .data
+        .into_iter()
+        .map(|model| Model {
+            id: model.id,
+            object: model.object.unwrap_or_else(|| "model".to_string()),
+            created: model.created.unwrap_or(0),
+            owned_by: model.owned_by.unwrap_or_else(|| owned_by.to_string()),
+        })
+        .collect())
+}
+
+/// Models listed at `{base_url}/v1/models`, owned by `owned_by` unless the
+/// server says otherwise
+pub async fn list_models(client: &Client, base_url: &str, owned_by: &str) -> Result<Vec<Model>> {
+    let response = client.get(format!("{}/v1/models", base_url)).send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "{} returned {} for its model list",
+            base_url,
+            response.status()
+        ));
+    }
+    parse_models(&response.bytes().await?, owned_by)
+}
+
+/// Stream `request` from the server at `base_url`, through
+/// `/v1/chat/completions` for chats and `/v1/completions` for prompts.
+/// Without a model in the request, the first the server lists is used.
+pub async fn infer(
+    client: &Client,
+    base_url: &str,
+    request: InferenceRequest,
+) -> Result<InferenceStream> {
+    let model = match request.model {
+        Some(model) => model,
+        None => list_models(client, base_url, "")
+            .await?
+            .into_iter()
+            .next()
+            .map(|model| model.id)
+            .ok_or_else(|| anyhow!("{} serves no model", base_url))?,
+    };
+
+    let mut body = serde_json::json!({
+        "model": model,
+        "max_tokens": request.max_tokens,
+        "temperature": request.temperature,
+        "top_p": request.top_p,
+        "stream": true,
+        "stream_options": { "include_usage": true },
+    });
+    let endpoint = match request.input {
+        InferenceInput::Prompt(prompt) => {
+            body["prompt"] = Value::from(prompt);
+            format!("{}/v1/completions", base_url)
+        }
+        InferenceInput::Chat(messages) => {
+            body["messages"] = serde_json::to_value(messages)?;
+            format!("{}/v1/chat/completions", base_url)
+        }
+    };
+
+    let response = client
+        .post(&endpoint)
+        .json(&body)
+        .timeout(INFERENCE_TIMEOUT)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        return Err(anyhow!("{} returned {}: {}", endpoint, status, text));
+    }
+
+    let (tx, rx) = mpsc::channel::<Result<InferenceEvent>>(64);
+    tokio::spawn(async move {
+        let mut bytes = response.bytes_stream();
+        let mut buffer = Vec::new();
+        while let Some(chunk) = bytes.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    let _ = tx.send(Err(e.into())).await;
+                    return;
+                }
+            };
+            buffer.extend_from_slice(&chunk);
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let events = match parse_event_line(&line) {
+                    Ok(Some(events)) => events,
+                    Ok(None) => continue,
+                    Err(e) => vec![Err(e)],
+                };
+                for event in events {
+                    // The receiver is gone once the task is cancelled,
+                    // dropping the response closes the connection
+                    if tx.send(event).await.is_err() {
+                        debug!("Inference stream from {} dropped", endpoint);
+                        return;
+                    }
+                }
+            }
+        }
+    });
+    Ok(Box::pin(ReceiverStream::new(rx)))
+}
+
+/// Embeddings of `input` from `{base_url}/v1/embeddings`, in input order
+pub async fn embed(
+    client: &Client,
+    base_url: &str,
+    model: Option<String>,
+    input: Vec<String>,
+) -> Result<Vec<Vec<f32>>> {
+    let model = match model {
+        Some(model) => model,
+        None => list_models(client, base_url, "")
+            .await?
+            .into_iter()
+            .next()
+            .map(|model| model.id)
+            .ok_or_else(|| anyhow!("{} serves no model", base_url))?,
+    };
+    let endpoint = format!("{}/v1/embeddings", base_url);
+    let response = client
+        .post(&endpoint)
+        .json(&serde_json::json!({ "model": model, "input": input }))
+        .timeout(INFERENCE_TIMEOUT)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        return Err(anyhow!("{} returned {}: {}", endpoint, status, text));
+    }
+    parse_embeddings(&response.bytes().await?)
+}
+
+#[derive(Debug, Deserialize)]
+struct Embedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingList {
+    data: Vec<Embedding>,
+}
+
+fn parse_embeddings(body: &[u8]) -> Result<Vec<Vec<f32>>> {
+    let mut list: EmbeddingList =
+        serde_json::from_slice(body).map_err(|e| anyhow!("Failed to parse embeddings: {}", e))?;
+    list.data.sort_by_key(|embedding| embedding.index);
+    Ok(list
+        .data
+        .into_iter()
+        .map(|embedding| embedding.embedding)
+        .collect())
+}
+
+/// Events in one line of a server-sent event stream, None for lines that
+/// carry none
+fn parse_event_line(line: &[u8]) -> Result<Option<Vec<Result<InferenceEvent>>>> {
+    let line = std::str::from_utf8(line)?.trim();
+    let Some(data) = line.strip_prefix("data:") else {
+        return Ok(None);
+    };
+    let data = data.trim();
+    if data.is_empty() || data == "[DONE]" {
+        return Ok(None);
+    }
+    let chunk: Value = serde_json::from_str(data)
+        .map_err(|e| anyhow!("Invalid event in inference stream: {}", e))?;
+    if let Some(error) = chunk.get("error") {
+        let message = error
+            .get("message")
+            .and_then(Value::as_str)
+            .map(String::from)
+            .unwrap_or_else(|| error.to_string());
+        return Ok(Some(vec![Err(anyhow!(message))]));
+    }
+
+    let mut events = Vec::new();
+    if let Some(choice) = chunk["choices"].get(0) {
+        let text = choice["delta"]["content"]
+            .as_str()
+            .or_else(|| choice["text"].as_str())
+            .unwrap_or_default();
+        if !text.is_empty() {
+            events.push(Ok(InferenceEvent::Token(text.to_string())));
+        }
+    }
+    if let Some(usage) = chunk.get("usage").filter(|usage| !usage.is_null()) {
+        let count = |field: &str| usage[field].as_u64().unwrap_or(0).min(u32::MAX as u64) as u32;
+        events.push(Ok(InferenceEvent::Usage {
+            prompt_tokens: count("prompt_tokens"),
+            completion_tokens: count("completion_tokens"),
+        }));
+    }
+    Ok(Some(events))
+}
+
+#[test]
+fn test_openai_stream_events() {
+    let events = |line: &str| {
+        parse_event_line(line.as_bytes())
+            .unwrap()
+            .map(|events| events.into_iter().map(Result::unwrap).collect::<Vec<_>>())
+    };
+
+    assert_eq!(
+        events(r#"data: {"choices":[{"index":0,"delta":{"content":"Hel"}}]}"#),
+        Some(vec![InferenceEvent::Token("Hel".to_string())])
+    );
+    assert_eq!(
+        events(r#"data: {"choices":[{"index":0,"text":"lo"}],"usage":null}"#),
+        Some(vec![InferenceEvent::Token("lo".to_string())])
+    );
+    // vLLM reports usage in a last chunk without choices
+    assert_eq!(
+        events(r#"data: {"choices":[],"usage":{"prompt_tokens":12,"completion_tokens":2}}"#),
+        Some(vec![InferenceEvent::Usage {
+            prompt_tokens: 12,
+            completion_tokens: 2
+        }])
+    );
+    assert_eq!(events("data: [DONE]"), None);
+    assert_eq!(events(": keep-alive"), None);
+    assert_eq!(events(""), None);
+
+    let error = parse_event_line(br#"data: {"error":{"message":"model not found"}}"#)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        error[0].as_ref().unwrap_err().to_string(),
+        "model not found"
+    );
+    assert!(parse_event_line(b"data: {").is_err());
+
+    // Lenient model lists: llama-server and TGI list full entries, others only ids
+    let models = parse_models(
+        br#"{"object":"list","data":[
+            {"id":"qwen2.5-7b","object":"model","created":1700000000,"owned_by":"llamacpp"},
+            {"id":"mistral-7b"}
+        ]}"#,
+        "external",
+    )
+    .unwrap();
+    assert_eq!(models.len(), 2);
+    assert_eq!(models[0].owned_by, "llamacpp");
+    assert_eq!(models[1].id, "mistral-7b");
+    assert_eq!(models[1].object, "model");
+    assert_eq!(models[1].owned_by, "external");
+    assert!(parse_models(b"{}", "external").is_err());
+
+    let embeddings = parse_embeddings(
+        br#"{"object":"list","data":[
+            {"object":"embedding","index":1,"embedding":[0.5,-1.0]},
+            {"object":"embedding","index":0,"embedding":[0.25,0.0]}
+        ]}"#,
+    )
+    .unwrap();
+    assert_eq!(embeddings, vec![vec![0.25, 0.0], vec![0.5, -1.0]]);
+}
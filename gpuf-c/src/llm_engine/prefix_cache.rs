@@ -0,0 +1,112 @@
+//! KV states of chat prefixes gpuf-s marked as shared between requests, such
+//! as system prompts and RAG headers. A prompt starting with a cached prefix
+//! restores its state instead of evaluating those tokens again.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Bytes of KV state kept across all prefixes
+pub const DEFAULT_MAX_BYTES: usize = 512 * 1024 * 1024;
+
+pub struct PrefixState {
+    /// Tokens of the prefix, the leading tokens of the prompts reusing it
+    pub tokens: Vec<i32>,
+    /// Context state after evaluating `tokens`
+    pub state: Vec<u8>,
+}
+
+/// Prefix states by hash, evicting the least recently used past `max_bytes`
+pub struct PrefixCache {
+    /// Least recently used first
+    entries: VecDeque<(String, Arc<PrefixState>)>,
+    bytes: usize,
+    max_bytes: usize,
+}
+
+impl PrefixCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            bytes: 0,
+            max_bytes,
+        }
+    }
+
+    pub fn get(&mut self, hash: &str) -> Option<Arc<PrefixState>> {
+        let index = self.entries.iter().position(|(h, _)| h == hash)?;
+        let entry = self.entries.remove(index)?;
+        let state = entry.1.clone();
+        self.entries.push_back(entry);
+        Some(state)
+    }
+
+    /// Keep `state` under `hash`. States larger than the whole cache are
+    /// dropped.
+    pub fn insert(&mut self, hash: String, state: PrefixState) {
+        self.remove(&hash);
+        let size = state.state.len();
+        if size > self.max_bytes {
+            return;
+        }
+        while self.bytes + size > self.max_bytes {
+            let Some((_, evicted)) = self.entries.pop_front() else {
+                break;
+            };
+            self.bytes -= evicted.state.len();
+        }
+        self.bytes += size;
+        self.entries.push_back((hash, Arc::new(state)));
+    }
+
+    pub fn remove(&mut self, hash: &str) {
+        if let Some(index) = self.entries.iter().position(|(h, _)| h == hash) {
+            if let Some((_, removed)) = self.entries.remove(index) {
+                self.bytes -= removed.state.len();
+            }
+        }
+    }
+
+    /// Drop every state, e.g. when another model is loaded
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl Default for PrefixCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_BYTES)
+    }
+}
+
+#[test]
+fn test_prefix_cache() {
+    let state = |size: usize| PrefixState {
+        tokens: vec![1, 2, 3],
+        state: vec![0; size],
+    };
+    let mut cache = PrefixCache::new(100);
+    cache.insert("a".to_string(), state(40));
+    cache.insert("b".to_string(), state(40));
+    assert!(cache.get("a").is_some());
+    // "b" is the least recently used
+    cache.insert("c".to_string(), state(40));
+    assert!(cache.get("b").is_none());
+    assert!(cache.get("a").is_some());
+    assert_eq!(cache.bytes(), 80);
+
+    // Replacing a state frees the old one
+    cache.insert("a".to_string(), state(10));
+    assert_eq!(cache.bytes(), 50);
+    cache.insert("huge".to_string(), state(101));
+    assert!(cache.get("huge").is_none());
+    assert_eq!(cache.bytes(), 50);
+
+    cache.clear();
+    assert!(cache.get("a").is_none());
+    assert_eq!(cache.bytes(), 0);
+}
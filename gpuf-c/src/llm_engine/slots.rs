@@ -0,0 +1,391 @@
+//! Parallel sequence slots: one llama.cpp context holding a sequence of its
+//! KV cache per request, so the loaded model decodes several requests in one
+//! batch instead of one after another. Requests wait for a free slot in the
+//! order they came. The decode thread never waits for a reader: pieces a
+//! request's channel cannot take yet queue in its outbox, and a request whose
+//! reader falls too far behind is stopped so its slot goes to the next one.
+
+use super::llama_engine::{
+    build_sampler, send_piece, shift_context, ContextFit, SamplingParams, SharedModel,
+};
+use crate::util::memory_plan::DEFAULT_N_BATCH;
+use anyhow::{anyhow, Result};
+use common::CONTEXT_LENGTH_EXCEEDED;
+use llama_cpp_2::context::params::LlamaContextParams;
+use llama_cpp_2::context::LlamaContext;
+use llama_cpp_2::llama_backend::LlamaBackend;
+use llama_cpp_2::llama_batch::LlamaBatch;
+use llama_cpp_2::model::{AddBos, LlamaModel};
+use llama_cpp_2::sampling::LlamaSampler;
+use llama_cpp_2::token::LlamaToken;
+use std::collections::VecDeque;
+use std::num::NonZeroU32;
+use std::sync::{mpsc as std_mpsc, Arc};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{debug, info, warn};
+
+/// Pieces a request may lag behind its slot before it is stopped
+const MAX_BACKLOG: usize = 1024;
+/// How often outboxes of finished requests are flushed while no slot runs
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+struct SlotRequest {
+    prompt: String,
+    fit: Option<ContextFit>,
+    max_tokens: usize,
+    sampling: SamplingParams,
+    tx: mpsc::Sender<Result<String>>,
+}
+
+/// Slots of the loaded model. Dropping the last clone of the engine holding
+/// it stops the decode thread once its running requests are done.
+pub struct SlotPool {
+    requests: std_mpsc::Sender<SlotRequest>,
+}
+
+impl SlotPool {
+    /// Create a context of `slots` sequences of `n_ctx` tokens each and start
+    /// the thread decoding them. Blocks until the context exists.
+    pub fn start(
+        backend: Arc<LlamaBackend>,
+        model: SharedModel,
+        slots: u32,
+        n_ctx: u32,
+    ) -> Result<Self> {
+        let (requests, rx) = std_mpsc::channel();
+        let (ready_tx, ready_rx) = std_mpsc::sync_channel(1);
+        std::thread::Builder::new()
+            .name("llama-slots".to_string())
+            .spawn(move || {
+                let model = match model.read() {
+                    Ok(model) => model,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(anyhow!("Failed to lock model: {:?}", e)));
+                        return;
+                    }
+                };
+                let context_params = LlamaContextParams::default()
+                    .with_n_ctx(NonZeroU32::new(n_ctx * slots))
+                    .with_n_batch(DEFAULT_N_BATCH)
+                    .with_n_seq_max(slots);
+                let context = match model.new_context(&backend, context_params) {
+                    Ok(context) => context,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(anyhow!(
+                            "Failed to create context of {} slots: {:?}",
+                            slots,
+                            e
+                        )));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok(()));
+                run(&model, context, slots, n_ctx, rx);
+            })?;
+        ready_rx
+            .recv()
+            .map_err(|_| anyhow!("Slot thread exited before creating its context"))??;
+        info!("Decoding {} slots of {} tokens", slots, n_ctx);
+        Ok(Self { requests })
+    }
+
+    /// Queue a completion of `prompt`, cut to `fit` when it is too long for
+    /// a slot. Its pieces arrive on the returned channel once a slot is free.
+    pub fn submit(
+        &self,
+        prompt: &str,
+        fit: Option<ContextFit>,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+    ) -> Result<mpsc::Receiver<Result<String>>> {
+        let (tx, rx) = mpsc::channel(64);
+        self.requests
+            .send(SlotRequest {
+                prompt: prompt.to_string(),
+                fit,
+                max_tokens,
+                sampling: sampling.clone(),
+                tx,
+            })
+            .map_err(|_| anyhow!("Slot thread is not running"))?;
+        Ok(rx)
+    }
+}
+
+/// Results on their way to a request's receiver, in order
+struct Outbox {
+    tx: mpsc::Sender<Result<String>>,
+    pending: VecDeque<Result<String>>,
+}
+
+impl Outbox {
+    fn new(tx: mpsc::Sender<Result<String>>) -> Self {
+        Self {
+            tx,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Queue `item` and flush. False once the receiver is gone.
+    fn push(&mut self, item: Result<String>) -> bool {
+        self.pending.push_back(item);
+        self.flush()
+    }
+
+    /// Send what the channel has room for. False once the receiver is gone.
+    fn flush(&mut self) -> bool {
+        while let Some(item) = self.pending.pop_front() {
+            match self.tx.try_send(item) {
+                Ok(()) => {}
+                Err(TrySendError::Full(item)) => {
+                    self.pending.push_front(item);
+                    break;
+                }
+                Err(TrySendError::Closed(_)) => return false,
+            }
+        }
+        true
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    fn lagging(&self) -> bool {
+        self.pending.len() > MAX_BACKLOG
+    }
+}
+
+/// A request in a slot, owning sequence `seq` of the context
+struct Slot {
+    seq: i32,
+    tokens: Vec<LlamaToken>,
+    /// Leading `tokens` in the context
+    n_eval: usize,
+    /// Position of the next token in the sequence
+    n_past: usize,
+    /// Sampled token not in the context yet
+    pending: Option<LlamaToken>,
+    generated: usize,
+    max_tokens: usize,
+    sliding: bool,
+    sampler: LlamaSampler,
+    outbox: Outbox,
+    /// Batch index of the logits to sample from after this step
+    logits: Option<i32>,
+}
+
+impl Slot {
+    fn new(model: &LlamaModel, seq: i32, n_ctx: u32, request: SlotRequest) -> Result<Self> {
+        let mut tokens = model
+            .str_to_token(&request.prompt, AddBos::Always)
+            .map_err(|e| anyhow!("Failed to tokenize prompt: {:?}", e))?;
+        let dropped_tokens = request.fit.map_or(0, |fit| fit.dropped_tokens);
+        if dropped_tokens > 0 && tokens.len() > 1 {
+            let dropped = dropped_tokens.min(tokens.len() - 2);
+            tokens.drain(1..1 + dropped);
+        }
+        if tokens.is_empty() || tokens.len() >= n_ctx as usize {
+            return Err(anyhow!(
+                "{}: {} prompt tokens exceed the slot context of {} tokens",
+                CONTEXT_LENGTH_EXCEEDED,
+                tokens.len(),
+                n_ctx
+            ));
+        }
+        let mut sampler = build_sampler(&request.sampling);
+        sampler.accept_many(tokens.iter());
+        Ok(Self {
+            seq,
+            tokens,
+            n_eval: 0,
+            n_past: 0,
+            pending: None,
+            generated: 0,
+            max_tokens: request.max_tokens,
+            sliding: request.fit.is_some_and(|fit| fit.sliding),
+            sampler,
+            outbox: Outbox::new(request.tx),
+            logits: None,
+        })
+    }
+}
+
+/// Decode the slots until every sender of requests is gone and the last
+/// request is done
+fn run(
+    model: &LlamaModel,
+    mut context: LlamaContext,
+    slots: u32,
+    n_ctx: u32,
+    requests: std_mpsc::Receiver<SlotRequest>,
+) {
+    let mut active: Vec<Option<Slot>> = (0..slots).map(|_| None).collect();
+    // Outboxes of requests that left their slot before their reader caught up
+    let mut draining: Vec<Outbox> = Vec::new();
+    let mut batch = LlamaBatch::new(DEFAULT_N_BATCH as usize, 1);
+    loop {
+        draining.retain_mut(|outbox| outbox.flush() && !outbox.is_empty());
+
+        // Fill free slots, waiting for a request while none runs
+        for seq in 0..active.len() {
+            if active[seq].is_some() {
+                continue;
+            }
+            let idle = active.iter().all(Option::is_none);
+            let request = if idle && draining.is_empty() {
+                match requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => {
+                        debug!("Slot thread stopped");
+                        return;
+                    }
+                }
+            } else if idle {
+                match requests.recv_timeout(DRAIN_INTERVAL) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        // Keep flushing at the same pace once requests stopped
+                        if e == std_mpsc::RecvTimeoutError::Disconnected {
+                            std::thread::sleep(DRAIN_INTERVAL);
+                        }
+                        break;
+                    }
+                }
+            } else {
+                match requests.try_recv() {
+                    Ok(request) => request,
+                    Err(_) => break,
+                }
+            };
+            let tx = request.tx.clone();
+            let slot = Slot::new(model, seq as i32, n_ctx, request).and_then(|slot| {
+                context.clear_kv_cache_seq(Some(seq as u32), None, None)?;
+                Ok(slot)
+            });
+            match slot {
+                Ok(slot) => active[seq] = Some(slot),
+                Err(e) => {
+                    // A new channel has room for it
+                    let _ = tx.try_send(Err(e));
+                }
+            }
+        }
+
+        // Every request taken was rejected
+        if active.iter().all(Option::is_none) {
+            continue;
+        }
+
+        // A token of each generating slot, then as much prompt as fits
+        batch.clear();
+        let mut step = || -> Result<()> {
+            for slot in active.iter_mut().flatten() {
+                slot.logits = None;
+                let Some(token) = slot.pending.take() else {
+                    continue;
+                };
+                if slot.sliding && slot.n_past >= n_ctx as usize {
+                    slot.n_past = shift_context(&mut context, slot.seq, slot.n_past)?;
+                }
+                batch
+                    .add(token, slot.n_past as i32, &[slot.seq], true)
+                    .map_err(|e| anyhow!("Failed to add token to batch: {:?}", e))?;
+                slot.n_past += 1;
+                slot.logits = Some(batch.n_tokens() - 1);
+            }
+            for slot in active.iter_mut().flatten() {
+                let room = (DEFAULT_N_BATCH as usize).saturating_sub(batch.n_tokens() as usize);
+                if slot.n_eval == slot.tokens.len() || room == 0 {
+                    continue;
+                }
+                let end = slot.tokens.len().min(slot.n_eval + room);
+                for (i, token) in slot.tokens[slot.n_eval..end].iter().enumerate() {
+                    let is_last = slot.n_eval + i == slot.tokens.len() - 1;
+                    batch
+                        .add(*token, slot.n_past as i32, &[slot.seq], is_last)
+                        .map_err(|e| anyhow!("Failed to add token to batch: {:?}", e))?;
+                    slot.n_past += 1;
+                }
+                slot.n_eval = end;
+                if end == slot.tokens.len() {
+                    slot.logits = Some(batch.n_tokens() - 1);
+                }
+            }
+            context
+                .decode(&mut batch)
+                .map_err(|e| anyhow!("Failed to decode batch: {:?}", e))
+        };
+        if let Err(e) = step() {
+            warn!("Slot batch failed: {}", e);
+            for entry in active.iter_mut() {
+                if let Some(mut slot) = entry.take() {
+                    slot.outbox.push(Err(anyhow!("{}", e)));
+                    draining.push(slot.outbox);
+                }
+            }
+            context.clear_kv_cache();
+            continue;
+        }
+
+        for entry in active.iter_mut() {
+            let Some(slot) = entry else {
+                continue;
+            };
+            let mut done = !slot.outbox.flush();
+            if let (false, Some(index)) = (done, slot.logits) {
+                let token = slot.sampler.sample(&context, index);
+                slot.sampler.accept(token);
+                let more = send_piece(model, token, |piece| slot.outbox.push(Ok(piece)));
+                slot.generated += 1;
+                // Without a sliding window a slot ends where its context does
+                let full = !slot.sliding && slot.n_past >= n_ctx as usize;
+                done = !more || slot.generated >= slot.max_tokens || full;
+                slot.pending = Some(token);
+            }
+            if !done && slot.outbox.lagging() {
+                warn!(
+                    "Reader of slot {} fell {} pieces behind, stopping its request",
+                    slot.seq, MAX_BACKLOG
+                );
+                slot.outbox.pending.push_back(Err(anyhow!(
+                    "Generation stopped, the reader fell {} pieces behind",
+                    MAX_BACKLOG
+                )));
+                done = true;
+            }
+            if done {
+                let _ = context.clear_kv_cache_seq(Some(slot.seq as u32), None, None);
+                if let Some(slot) = entry.take() {
+                    if !slot.outbox.is_empty() {
+                        draining.push(slot.outbox);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_outbox() {
+    let (tx, mut rx) = mpsc::channel(2);
+    let mut outbox = Outbox::new(tx);
+    for piece in ["a", "b", "c"] {
+        assert!(outbox.push(Ok(piece.to_string())));
+    }
+    // The full channel leaves the last piece queued instead of blocking
+    assert_eq!(outbox.pending.len(), 1);
+    assert_eq!(rx.try_recv().unwrap().unwrap(), "a");
+    assert!(outbox.flush());
+    assert!(outbox.is_empty());
+    assert_eq!(rx.try_recv().unwrap().unwrap(), "b");
+    assert_eq!(rx.try_recv().unwrap().unwrap(), "c");
+
+    for _ in 0..MAX_BACKLOG + 3 {
+        outbox.push(Ok(String::new()));
+    }
+    assert!(outbox.lagging());
+    drop(rx);
+    assert!(!outbox.flush());
+}
@@ -0,0 +1,278 @@
+//! Engine supervisor. Probes the inference engine, restarts it once it keeps
+//! failing, backing off exponentially between restarts, and tells a crash
+//! loop apart from a one-off failure.
+
+use super::{AnyEngine, Engine, TRTLLM_DEFAULT_PORT, VLLM_DEFAULT_PORT};
+use anyhow::{anyhow, Result};
+use common::{EngineState, EngineType, EngineVersion};
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(15);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Failed probes in a row before the engine is restarted
+const FAILURES_BEFORE_RESTART: u32 = 3;
+const BACKOFF_BASE: Duration = Duration::from_secs(5);
+const BACKOFF_MAX: Duration = Duration::from_secs(300);
+/// Restarts within the window that make a crash loop
+const CRASH_LOOP_RESTARTS: usize = 5;
+const CRASH_LOOP_WINDOW: Duration = Duration::from_secs(600);
+
+/// How to tell whether an engine is healthy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthProbe {
+    /// Engine serving HTTP, healthy when this URL answers with success
+    Http(String),
+    /// llama.cpp in this process, healthy unless its model failed to load
+    InProcess,
+    /// Server the provider runs, healthy while it lists its models
+    External,
+}
+
+impl HealthProbe {
+    /// Probe of `engine_type`, with Ollama listening on `ollama_port`
+    pub fn for_engine(engine_type: EngineType, ollama_port: u16) -> Option<Self> {
+        match engine_type {
+            EngineType::Vllm => Some(Self::Http(format!(
+                "http://localhost:{}/health",
+                VLLM_DEFAULT_PORT
+            ))),
+            EngineType::Ollama => Some(Self::Http(format!(
+                "http://localhost:{}/api/tags",
+                ollama_port
+            ))),
+            EngineType::TensorRT => Some(Self::Http(format!(
+                "http://localhost:{}/health/ready",
+                TRTLLM_DEFAULT_PORT
+            ))),
+            EngineType::Llama => Some(Self::InProcess),
+            EngineType::External => Some(Self::External),
+            _ => None,
+        }
+    }
+
+    pub async fn check(&self, client: &Client, engine: &Mutex<Option<AnyEngine>>) -> Result<()> {
+        match self {
+            Self::Http(url) => {
+                let response = client.get(url).timeout(PROBE_TIMEOUT).send().await?;
+                if !response.status().is_success() {
+                    return Err(anyhow!("Health check returned {}", response.status()));
+                }
+                Ok(())
+            }
+            Self::InProcess => match engine.lock().await.as_ref() {
+                Some(AnyEngine::Llama(llama)) => match llama.load_error().await {
+                    Some(error) => Err(anyhow!("Model failed to load: {}", error)),
+                    None => Ok(()),
+                },
+                _ => Ok(()),
+            },
+            Self::External => {
+                let external = match engine.lock().await.as_ref() {
+                    Some(AnyEngine::External(external)) => external.clone(),
+                    _ => return Ok(()),
+                };
+                external.list_models().await.map(|_| ())
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct VersionResponse {
+    version: String,
+}
+
+/// Version the server engine of `engine_type` reports, with Ollama listening
+/// on `ollama_port`. None for in-process and external engines and when the
+/// engine does not answer.
+pub async fn engine_version(
+    client: &Client,
+    engine_type: EngineType,
+    ollama_port: u16,
+) -> Option<EngineVersion> {
+    let (engine, url) = match engine_type {
+        EngineType::Vllm => (
+            "vllm",
+            format!("http://localhost:{}/version", VLLM_DEFAULT_PORT),
+        ),
+        EngineType::Ollama => (
+            "ollama",
+            format!("http://localhost:{}/api/version", ollama_port),
+        ),
+        // Triton serving TensorRT-LLM reports its own version
+        EngineType::TensorRT => (
+            "triton",
+            format!("http://localhost:{}/v2", TRTLLM_DEFAULT_PORT),
+        ),
+        _ => return None,
+    };
+    let response = client.get(&url).timeout(PROBE_TIMEOUT).send().await.ok()?;
+    let body: VersionResponse = response.error_for_status().ok()?.json().await.ok()?;
+    Some(EngineVersion {
+        engine: engine.to_string(),
+        version: body.version,
+    })
+}
+
+/// Restart the engine: llama.cpp reloads its model, server engines are
+/// stopped and started again, and an external server is only probed again
+pub async fn restart(engine: &Mutex<Option<AnyEngine>>) -> Result<()> {
+    let mut engine = engine.lock().await;
+    match engine.as_mut() {
+        Some(AnyEngine::Llama(llama)) => {
+            let model_path = llama.get_current_model().await;
+            if model_path.is_empty() {
+                llama.init().await
+            } else {
+                llama.load_model(&model_path).await
+            }
+        }
+        Some(engine) => {
+            if let Err(e) = engine.stop_worker().await {
+                warn!("Failed to stop engine before restart: {}", e);
+            }
+            engine.init().await
+        }
+        None => Err(anyhow!("No engine managed by this worker to restart")),
+    }
+}
+
+/// Health and restart bookkeeping, fed one probe result at a time
+#[derive(Debug)]
+pub struct Supervisor {
+    state: EngineState,
+    failures: u32,
+    restarts: u32,
+    recent_restarts: VecDeque<Instant>,
+    next_restart: Option<Instant>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self {
+            state: EngineState::Healthy,
+            failures: 0,
+            restarts: 0,
+            recent_restarts: VecDeque::new(),
+            next_restart: None,
+        }
+    }
+
+    pub fn state(&self) -> EngineState {
+        self.state
+    }
+
+    /// Restarts since the supervisor started
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    /// Record a probe taken at `now`. Returns true when the engine should be
+    /// restarted now.
+    pub fn on_probe(&mut self, healthy: bool, now: Instant) -> bool {
+        while let Some(&at) = self.recent_restarts.front() {
+            if now.duration_since(at) < CRASH_LOOP_WINDOW {
+                break;
+            }
+            self.recent_restarts.pop_front();
+        }
+        let crash_loop = self.recent_restarts.len() >= CRASH_LOOP_RESTARTS;
+
+        if healthy {
+            self.state = EngineState::Healthy;
+            self.failures = 0;
+            self.next_restart = None;
+            return false;
+        }
+
+        self.failures += 1;
+        self.state = if crash_loop {
+            EngineState::CrashLoop
+        } else {
+            EngineState::Unhealthy
+        };
+        if self.failures < FAILURES_BEFORE_RESTART {
+            return false;
+        }
+
+        let backoff = self.backoff();
+        let next_restart = *self.next_restart.get_or_insert(now + backoff);
+        if now < next_restart {
+            return false;
+        }
+
+        self.failures = 0;
+        self.next_restart = None;
+        self.restarts += 1;
+        self.recent_restarts.push_back(now);
+        self.state = if self.recent_restarts.len() >= CRASH_LOOP_RESTARTS {
+            EngineState::CrashLoop
+        } else {
+            EngineState::Restarting
+        };
+        true
+    }
+
+    /// Wait before the next restart: none for the first, then doubling with
+    /// each recent restart, and the longest in a crash loop
+    fn backoff(&self) -> Duration {
+        match self.recent_restarts.len() {
+            0 => Duration::ZERO,
+            n if n >= CRASH_LOOP_RESTARTS => BACKOFF_MAX,
+            n => BACKOFF_BASE.saturating_mul(1 << (n - 1)).min(BACKOFF_MAX),
+        }
+    }
+}
+
+#[test]
+fn test_supervisor_backoff() {
+    let mut supervisor = Supervisor::new();
+    let mut now = Instant::now();
+    let mut probe = |supervisor: &mut Supervisor, healthy: bool| {
+        now += PROBE_INTERVAL;
+        supervisor.on_probe(healthy, now)
+    };
+
+    assert!(!probe(&mut supervisor, true));
+    assert_eq!(supervisor.state(), EngineState::Healthy);
+
+    // Restarted on the third failure in a row
+    assert!(!probe(&mut supervisor, false));
+    assert!(!probe(&mut supervisor, false));
+    assert_eq!(supervisor.state(), EngineState::Unhealthy);
+    assert!(probe(&mut supervisor, false));
+    assert_eq!(supervisor.state(), EngineState::Restarting);
+    assert!(!probe(&mut supervisor, true));
+    assert_eq!(supervisor.state(), EngineState::Healthy);
+
+    // Restarts keep failing: each waits longer than the one before
+    let mut probes_between = Vec::new();
+    for _ in 0..4 {
+        let mut probes = 1;
+        while !probe(&mut supervisor, false) {
+            probes += 1;
+        }
+        probes_between.push(probes);
+    }
+    // 3 failures, then 5s, 10s, 20s and 40s of backoff at 15s per probe
+    assert_eq!(probes_between, vec![4, 4, 5, 6]);
+    assert_eq!(supervisor.restarts(), 5);
+    assert_eq!(supervisor.state(), EngineState::CrashLoop);
+
+    // Still a crash loop until the engine recovers
+    assert!(!probe(&mut supervisor, false));
+    assert_eq!(supervisor.state(), EngineState::CrashLoop);
+    assert!(!probe(&mut supervisor, true));
+    assert_eq!(supervisor.state(), EngineState::Healthy);
+}
@@ -0,0 +1,328 @@
+//! TensorRT-LLM engine: Triton Inference Server with its OpenAI-compatible
+//! frontend, in the NVIDIA TensorRT-LLM container. Triton serves every
+//! model in the mounted repository, each a directory of engines built with
+//! TensorRT-LLM ahead of time.
+
+use super::{
+    openai_api, Engine, InferenceRequest, InferenceStream, TRTLLM_CONTAINER_NAME,
+    TRTLLM_DEFAULT_PORT,
+};
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio::time::{sleep, Duration};
+use tracing::{debug, error, info};
+
+pub const TRTLLM_DEFAULT_IMAGE: &str = "nvcr.io/nvidia/tritonserver:24.12-trtllm-python-py3";
+/// Where the model repository is mounted in the container
+const TRTLLM_REPOSITORY_PATH: &str = "/models";
+/// Loading TensorRT engines of a large model onto every GPU takes a while
+const TRTLLM_READY_TIMEOUT: Duration = Duration::from_secs(900);
+/// Lines of container output logged when Triton fails to start
+const TRTLLM_LOG_TAIL_LINES: &str = "50";
+
+/// Default model repository, `~/.triton/models`
+pub fn default_model_repository() -> PathBuf {
+    let home = if cfg!(target_os = "windows") {
+        std::env::var("USERPROFILE").unwrap_or_else(|_| "C:\\".to_string())
+    } else {
+        std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string())
+    };
+    PathBuf::from(home).join(".triton").join("models")
+}
+
+#[derive(Clone)]
+pub struct TrtLlmEngine {
+    image: String,
+    model_repository: PathBuf,
+    /// Hugging Face tokenizer the frontend applies chat templates with
+    tokenizer: Option<String>,
+    hugging_face_hub_token: Option<String>,
+    container_id: Option<String>,
+    models_name: Vec<String>,
+}
+
+impl TrtLlmEngine {
+    pub fn new(
+        image: Option<String>,
+        model_repository: Option<PathBuf>,
+        tokenizer: Option<String>,
+        hugging_face_hub_token: Option<String>,
+    ) -> Self {
+        TrtLlmEngine {
+            image: image.unwrap_or_else(|| TRTLLM_DEFAULT_IMAGE.to_string()),
+            model_repository: model_repository.unwrap_or_else(default_model_repository),
+            tokenizer,
+            hugging_face_hub_token,
+            container_id: None,
+            models_name: Vec::new(),
+        }
+    }
+
+    async fn is_container_running(&self) -> bool {
+        let Some(container_id) = &self.container_id else {
+            return false;
+        };
+        match Command::new("docker")
+            .args(["inspect", "-f", "{{.State.Running}}", container_id])
+            .output()
+            .await
+        {
+            Ok(output) if output.status.success() => {
+                String::from_utf8_lossy(&output.stdout).trim() == "true"
+            }
+            _ => false,
+        }
+    }
+
+    async fn container_log_tail(&self) -> String {
+        let Some(container_id) = &self.container_id else {
+            return String::new();
+        };
+        match Command::new("docker")
+            .args(["logs", "--tail", TRTLLM_LOG_TAIL_LINES, container_id])
+            .output()
+            .await
+        {
+            Ok(output) => format!(
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            Err(e) => format!("no logs: {}", e),
+        }
+    }
+
+    async fn start_container(&mut self) -> Result<()> {
+        if self.is_container_running().await {
+            info!("TensorRT-LLM container is already running");
+            return Ok(());
+        }
+
+        let _ = Command::new("docker")
+            .args(["rm", "-f", TRTLLM_CONTAINER_NAME])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await?;
+
+        let image_check = Command::new("docker")
+            .args(["inspect", "--type=image", self.image.as_str()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await;
+        if !image_check.is_ok_and(|status| status.success()) {
+            info!("Pulling TensorRT-LLM image {}...", self.image);
+            let pull_output = Command::new("docker")
+                .args(["pull", self.image.as_str()])
+                .output()
+                .await?;
+            if !pull_output.status.success() {
+                let stderr = String::from_utf8_lossy(&pull_output.stderr);
+                return Err(anyhow!("Failed to pull TensorRT-LLM image: {}", stderr));
+            }
+        }
+
+        std::fs::create_dir_all(&self.model_repository)?;
+        let name_flag = format!("--name={}", TRTLLM_CONTAINER_NAME);
+        let port = TRTLLM_DEFAULT_PORT.to_string();
+        let port_flag = format!("-p{}:{}", port, port);
+        let volume_flag = format!(
+            "{}:{}",
+            self.model_repository.display(),
+            TRTLLM_REPOSITORY_PATH
+        );
+
+        let mut command = Command::new("docker");
+        command.args([
+            "run",
+            "-d",
+            "--rm",
+            name_flag.as_str(),
+            port_flag.as_str(),
+            "-v",
+            volume_flag.as_str(),
+            "--gpus",
+            "all",
+            "--shm-size",
+            "2g",
+            "--ulimit",
+            "memlock=-1",
+            "--ulimit",
+            "stack=67108864",
+        ]);
+        // Passed by name so the token stays out of the process list
+        if let Some(token) = &self.hugging_face_hub_token {
+            command
+                .args(["-e", "HUGGING_FACE_HUB_TOKEN"])
+                .env("HUGGING_FACE_HUB_TOKEN", token);
+        }
+        command.args([
+            self.image.as_str(),
+            "python3",
+            "/opt/tritonserver/python/openai/openai_frontend/main.py",
+            "--model-repository",
+            TRTLLM_REPOSITORY_PATH,
+            "--backend",
+            "tensorrtllm",
+            "--openai-port",
+            port.as_str(),
+        ]);
+        if let Some(tokenizer) = &self.tokenizer {
+            command.args(["--tokenizer", tokenizer.as_str()]);
+        }
+
+        debug!("TensorRT-LLM command: {:?}", command);
+        let output = command.output().await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            error!("Failed to start TensorRT-LLM container: {}", stderr);
+            return Err(anyhow!(
+                "Failed to start TensorRT-LLM container: {}",
+                stderr
+            ));
+        }
+        self.container_id = Some(String::from_utf8_lossy(&output.stdout).trim().to_string());
+
+        self.wait_until_ready(TRTLLM_READY_TIMEOUT).await?;
+        info!("TensorRT-LLM container started successfully");
+        Ok(())
+    }
+
+    async fn stop_container(&self) -> Result<()> {
+        if let Some(container_id) = &self.container_id {
+            info!("Stopping TensorRT-LLM container...");
+            let _ = Command::new("docker")
+                .args(["stop", container_id])
+                .output()
+                .await?;
+            info!("TensorRT-LLM container stopped");
+        }
+        Ok(())
+    }
+
+    /// Wait until Triton has loaded the repository and its frontend reports
+    /// ready, failing early if the container exits
+    async fn wait_until_ready(&self, timeout: Duration) -> Result<()> {
+        let start = std::time::Instant::now();
+        let client = reqwest::Client::new();
+        let endpoint = format!("http://localhost:{}/health/ready", TRTLLM_DEFAULT_PORT);
+
+        info!("Waiting for TensorRT-LLM to be ready at {}...", endpoint);
+        while start.elapsed() < timeout {
+            if !self.is_container_running().await {
+                error!("TensorRT-LLM output:\n{}", self.container_log_tail().await);
+                return Err(anyhow!("TensorRT-LLM container exited before it was ready"));
+            }
+            match client
+                .get(&endpoint)
+                .timeout(Duration::from_secs(2))
+                .send()
+                .await
+            {
+                Ok(response) if response.status().is_success() => {
+                    info!("TensorRT-LLM is ready! Took {:?}", start.elapsed());
+                    return Ok(());
+                }
+                Ok(response) => debug!("TensorRT-LLM not ready: {}", response.status()),
+                Err(e) => debug!("Waiting for TensorRT-LLM service: {}", e),
+            }
+            sleep(Duration::from_secs(5)).await;
+        }
+
+        error!("TensorRT-LLM output:\n{}", self.container_log_tail().await);
+        Err(anyhow!(
+            "TensorRT-LLM did not become ready within {:?}",
+            timeout
+        ))
+    }
+}
+
+impl Engine for TrtLlmEngine {
+    fn init(&mut self) -> impl std::future::Future<Output = Result<()>> + Send {
+        async move {
+            info!("Initializing TensorRT-LLM engine...");
+            self.start_container().await
+        }
+    }
+
+    fn set_models(
+        &mut self,
+        models: Vec<String>,
+    ) -> impl std::future::Future<Output = Result<()>> + Send {
+        async move {
+            if models.is_empty() {
+                return Err(anyhow!("Model list cannot be empty"));
+            }
+            // Triton serves what the repository holds, engines cannot be
+            // built on the fly
+            for model in &models {
+                if !self.model_repository.join(model).is_dir() {
+                    return Err(anyhow!(
+                        "Model {} not found in the Triton model repository {}",
+                        model,
+                        self.model_repository.display()
+                    ));
+                }
+            }
+            info!("Setting TensorRT-LLM models: {:?}", models);
+            self.models_name = models;
+            Ok(())
+        }
+    }
+
+    fn start_worker(&mut self) -> impl std::future::Future<Output = Result<()>> + Send {
+        async move {
+            if !self.is_container_running().await {
+                return Err(anyhow!("TensorRT-LLM container is not running"));
+            }
+            info!("TensorRT-LLM worker started successfully");
+            Ok(())
+        }
+    }
+
+    fn stop_worker(&mut self) -> impl std::future::Future<Output = Result<()>> + Send {
+        async move {
+            info!("Stopping TensorRT-LLM worker...");
+            self.stop_container().await
+        }
+    }
+
+    fn infer(
+        &self,
+        request: InferenceRequest,
+    ) -> impl std::future::Future<Output = Result<InferenceStream>> + Send {
+        async move {
+            let base_url = format!("http://localhost:{}", TRTLLM_DEFAULT_PORT);
+            openai_api::infer(&reqwest::Client::new(), &base_url, request).await
+        }
+    }
+
+    fn list_models(&self) -> impl std::future::Future<Output = Result<Vec<common::Model>>> + Send {
+        async move {
+            let base_url = format!("http://localhost:{}", TRTLLM_DEFAULT_PORT);
+            openai_api::list_models(&reqwest::Client::new(), &base_url, "triton").await
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_trtllm_set_models() {
+    let repository = tempfile::tempdir().unwrap();
+    std::fs::create_dir(repository.path().join("llama-3.1-8b")).unwrap();
+    let mut engine = TrtLlmEngine::new(None, Some(repository.path().to_path_buf()), None, None);
+    assert_eq!(engine.image, TRTLLM_DEFAULT_IMAGE);
+
+    assert!(engine
+        .set_models(vec!["llama-3.1-8b".to_string()])
+        .await
+        .is_ok());
+    assert_eq!(engine.models_name, vec!["llama-3.1-8b".to_string()]);
+    assert!(engine
+        .set_models(vec!["mistral-7b".to_string()])
+        .await
+        .is_err());
+    assert!(engine.set_models(Vec::new()).await.is_err());
+}
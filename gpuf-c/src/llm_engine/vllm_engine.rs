@@ -1,38 +1,207 @@
+use crate::util::cmd::VllmMode;
 use crate::util::system_info;
 use anyhow::{anyhow, Result};
-use std::collections::HashMap;
-use std::process::Stdio;
+use common::{get_u16_from_u128, DevicesInfo};
+use std::collections::{HashMap, VecDeque};
+use std::process::{ExitStatus, Stdio};
 use std::sync::Arc;
-use tokio::process::Command;
-use tokio::sync::RwLock;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::{Mutex, RwLock};
 use tokio::time::sleep;
 use tokio::time::Duration;
 use tracing::{debug, error, info, warn};
 
 use super::{
-    Engine, VLLMEngine, DEFAULT_CHAT_TEMPLATE, VLLM_CONTAINER_NAME, VLLM_CONTAINER_PATH,
-    VLLM_DEFAULT_PORT,
+    openai_api, Engine, InferenceRequest, InferenceStream, VLLMEngine, DEFAULT_CHAT_TEMPLATE,
+    VLLM_CONTAINER_NAME, VLLM_CONTAINER_PATH, VLLM_DEFAULT_PORT,
 };
 
+/// Lines of vLLM output kept to show when it fails to start
+const VLLM_LOG_TAIL_LINES: usize = 50;
+/// Time vLLM gets to shut down its workers before it is killed
+const VLLM_STOP_GRACE: Duration = Duration::from_secs(15);
+
+/// `vllm serve` running as a child process in native mode
+pub(super) struct VllmProcess {
+    child: Mutex<Child>,
+    log_tail: Arc<std::sync::Mutex<VecDeque<String>>>,
+}
+
+impl VllmProcess {
+    /// Exit status once the process has exited
+    async fn exited(&self) -> Option<ExitStatus> {
+        self.child.lock().await.try_wait().ok().flatten()
+    }
+
+    fn log_tail(&self) -> String {
+        let tail = self.log_tail.lock().unwrap();
+        tail.iter().cloned().collect::<Vec<_>>().join("\n")
+    }
+}
+
+/// Log each line vLLM prints and keep the last ones
+fn capture_log(
+    output: impl AsyncRead + Unpin + Send + 'static,
+    tail: Arc<std::sync::Mutex<VecDeque<String>>>,
+) {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            debug!("vllm: {}", line);
+            let mut tail = tail.lock().unwrap();
+            if tail.len() == VLLM_LOG_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+    });
+}
+
+/// Hugging Face cache shared by the container and the native process
+fn vllm_model_dir() -> String {
+    let model_dir = if cfg!(target_os = "windows") {
+        // Windows: %USERPROFILE%\.vllm\models
+        let home = std::env::var("USERPROFILE").unwrap_or_else(|_| "C:\\".to_string());
+        format!("{}\\.vllm\\models", home.replace('\\', "\\\\"))
+    } else {
+        // Unix-like: ~/.vllm/models
+        let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
+        format!("{}/.vllm/models", home)
+    };
+    if let Err(e) = std::fs::create_dir_all(&model_dir) {
+        warn!("Failed to create model directory {}: {}", model_dir, e);
+    }
+    info!("Model directory: {}", model_dir);
+    model_dir
+}
+
+/// GPUs vLLM runs on and how it splits the model across them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VllmPlacement {
+    /// GPU indices exposed through `CUDA_VISIBLE_DEVICES`, all when empty
+    pub gpus: Vec<u32>,
+    /// 0 keeps the engine's own GPU count
+    pub tensor_parallel_size: u32,
+    pub max_model_len: Option<u32>,
+}
+
+impl VllmPlacement {
+    /// Place vLLM on the GPUs in `devices`: the `gpus` listed, else all of
+    /// them, split across the largest power of two of them unless
+    /// `tensor_parallel_size` is given, and with a context length sized to
+    /// the memory of the smallest card unless `max_model_len` is given
+    pub fn plan(
+        devices: &DevicesInfo,
+        gpus: Option<&str>,
+        tensor_parallel_size: Option<u32>,
+        max_model_len: Option<u32>,
+    ) -> Result<Self> {
+        // The inventory packs per-GPU fields for up to 8 devices
+        let num = (devices.num as u32).min(8);
+        let gpus = match gpus {
+            Some(gpus) => {
+                let mut selected = Vec::new();
+                for index in gpus.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                    let index: u32 = index
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid GPU index '{}' in --vllm-gpus", index))?;
+                    if index >= num {
+                        return Err(anyhow!(
+                            "GPU {} in --vllm-gpus not found, this host has {} GPUs",
+                            index,
+                            num
+                        ));
+                    }
+                    if !selected.contains(&index) {
+                        selected.push(index);
+                    }
+                }
+                if selected.is_empty() {
+                    return Err(anyhow!("--vllm-gpus lists no GPUs"));
+                }
+                selected
+            }
+            None => (0..num).collect(),
+        };
+
+        let tensor_parallel_size = match tensor_parallel_size {
+            Some(0) => return Err(anyhow!("Tensor parallel size must be positive")),
+            Some(size) if !gpus.is_empty() && size as usize > gpus.len() => {
+                return Err(anyhow!(
+                    "Tensor parallel size {} exceeds the {} GPUs selected for vLLM",
+                    size,
+                    gpus.len()
+                ));
+            }
+            Some(size) => size,
+            // vLLM splits attention heads evenly, which model configs size in powers of two
+            None if gpus.is_empty() => 0,
+            None => 1 << (usize::BITS - 1 - gpus.len().leading_zeros()),
+        };
+
+        let max_model_len = match max_model_len {
+            Some(0) => return Err(anyhow!("Max model length must be positive")),
+            Some(len) => Some(len),
+            None => {
+                let smallest_gb = gpus
+                    .iter()
+                    .map(|&index| get_u16_from_u128(devices.memsize_gb, index as usize) as u32)
+                    .min()
+                    .unwrap_or(0);
+                default_max_model_len(smallest_gb * tensor_parallel_size.max(1))
+            }
+        };
+
+        Ok(Self {
+            gpus,
+            tensor_parallel_size,
+            max_model_len,
+        })
+    }
+
+    /// Value of `CUDA_VISIBLE_DEVICES`, unset to expose every GPU
+    fn visible_devices(&self) -> Option<String> {
+        if self.gpus.is_empty() {
+            return None;
+        }
+        let gpus: Vec<String> = self.gpus.iter().map(u32::to_string).collect();
+        Some(gpus.join(","))
+    }
+}
+
+/// Context length whose KV cache leaves room for the weights in `vram_gb`
+/// of GPU memory, or the model's own when there is plenty or it is unknown
+fn default_max_model_len(vram_gb: u32) -> Option<u32> {
+    match vram_gb {
+        0 => None,
+        1..=15 => Some(4096),
+        16..=31 => Some(8192),
+        32..=63 => Some(16384),
+        _ => None,
+    }
+}
+
 macro_rules! setup_tensor_parallel {
-    ($args:expr, $gpu_count:expr) => {{
+    ($args:expr, $gpus:expr, $tensor_parallel:expr) => {{
         #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
         {
             //TODO: vllm not support mps
             // $args.extend(["--device", "mps"]);
+            let _ = $gpus;
             system_info::get_apple_gpu_cores().unwrap_or(1).to_string()
         }
         #[cfg(not(target_os = "macos"))]
         {
             $args.extend([
                 "--gpus",
-                "all",
+                $gpus,
                 "-e",
                 "VLLM_DEVICE_TYPE=cuda",
                 "-e",
                 "TORCH_CUDA_ARCH_LIST=7.0 7.5 8.0 8.6 8.9 9.0+PTX",
             ]);
-            $gpu_count.to_string()
+            $tensor_parallel.to_string()
         }
     }};
 }
@@ -52,7 +221,56 @@ impl VLLMEngine {
             gpu_count: 1,
             hugging_face_hub_token,
             chat_template_path,
+            mode: VllmMode::Docker,
+            python: None,
+            process: None,
+            placement: VllmPlacement::default(),
+        }
+    }
+
+    /// Run on the GPUs and with the parallelism and context length of
+    /// `placement`
+    pub fn with_placement(mut self, placement: VllmPlacement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    fn tensor_parallel_size(&self) -> u32 {
+        match self.placement.tensor_parallel_size {
+            0 => self.gpu_count.max(1),
+            size => size,
+        }
+    }
+
+    /// Run vLLM as a child process instead of a container, with `python`
+    /// when given and the `vllm` command otherwise
+    pub fn with_native_process(mut self, python: Option<String>) -> Self {
+        self.mode = VllmMode::Native;
+        self.python = python;
+        self
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        match self.mode {
+            VllmMode::Docker => self.start_container().await,
+            VllmMode::Native => self.start_process().await,
+        }
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        match self.mode {
+            VllmMode::Docker => self.stop_container().await,
+            VllmMode::Native => self.stop_process().await,
+        }
+    }
+
+    /// First model to serve, a small default when none is set
+    fn serving_model(&mut self) -> String {
+        if self.models_name.is_empty() {
+            warn!("No model specified, using default model");
+            self.models_name.push("facebook/opt-125m".to_string());
         }
+        self.models_name[0].clone()
     }
 
     async fn is_container_running(&self) -> bool {
@@ -113,19 +331,8 @@ impl VLLMEngine {
                 return Err(anyhow!("Failed to pull VLLM image: {}", stderr));
             }
         }
-        let model_dir = if cfg!(target_os = "windows") {
-            // Windows: %USERPROFILE%\.vllm\models
-            let home = std::env::var("USERPROFILE").unwrap_or_else(|_| "C:\\".to_string());
-            format!("{}\\.vllm\\models", home.replace('\\', "\\\\"))
-        } else {
-            // Unix-like: ~/.vllm/models
-            let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
-            format!("{}/.vllm/models", home)
-        };
-        if let Err(e) = std::fs::create_dir_all(&model_dir) {
-            warn!("Failed to create model directory {}: {}", model_dir, e);
-        }
-        info!("Model directory: {}", model_dir);
+        let model_dir = vllm_model_dir();
+        let model = self.serving_model();
         let name_flag = format!("--name={}", VLLM_CONTAINER_NAME);
 
         let port = VLLM_DEFAULT_PORT.to_string();
@@ -160,7 +367,13 @@ impl VLLMEngine {
         args.push("-v");
         args.push(template_path.as_str());
 
-        let tensor_parallel = setup_tensor_parallel!(args, self.gpu_count);
+        // Docker parses the device list as CSV, so it is quoted to keep the commas
+        let gpus = match self.placement.visible_devices() {
+            Some(devices) => format!("\"device={}\"", devices),
+            None => "all".to_string(),
+        };
+        let tensor_parallel =
+            setup_tensor_parallel!(args, gpus.as_str(), self.tensor_parallel_size());
         if let Some(hugging_face_hub_token) = &self.hugging_face_hub_token {
             args.push("-e");
             args.push("HUGGING_FACE_HUB_TOKEN");
@@ -178,15 +391,13 @@ impl VLLMEngine {
         // args.push("dummy");
         args.push("--tensor-parallel-size");
         args.push(&tensor_parallel);
-        // Add model if specified
-        if self.models_name.is_empty() {
-            warn!("No model specified, using default model");
-            self.models_name.push("facebook/opt-125m".to_string());
-        }
-        if let Some(model) = self.models_name.first() {
-            args.push("--model");
-            args.push(model);
+        let max_model_len = self.placement.max_model_len.map(|len| len.to_string());
+        if let Some(max_model_len) = &max_model_len {
+            args.push("--max-model-len");
+            args.push(max_model_len);
         }
+        args.push("--model");
+        args.push(&model);
 
         debug!("VLLM args: {:?}", args);
         let output = Command::new("docker")
@@ -225,6 +436,127 @@ impl VLLMEngine {
         Ok(())
     }
 
+    async fn is_process_running(&self) -> bool {
+        match &self.process {
+            Some(process) => process.exited().await.is_none(),
+            None => false,
+        }
+    }
+
+    /// Spawn `vllm serve` on the host, sharing the container's model cache
+    async fn start_process(&mut self) -> Result<()> {
+        if self.is_process_running().await {
+            info!("VLLM process is already running");
+            return Ok(());
+        }
+        self.process = None;
+
+        let model = self.serving_model();
+        let template_path = match &self.chat_template_path {
+            Some(path) => path.clone(),
+            None => {
+                let temp_dir = std::env::var("TEMP_DIR").unwrap_or_else(|_| "/tmp".to_string());
+                let template_path =
+                    std::path::Path::new(&temp_dir).join("vllm_default_template.jinja");
+                std::fs::write(&template_path, DEFAULT_CHAT_TEMPLATE)?;
+                template_path.display().to_string()
+            }
+        };
+
+        let port = VLLM_DEFAULT_PORT.to_string();
+        let tensor_parallel = self.tensor_parallel_size().to_string();
+        let mut command = match &self.python {
+            Some(python) => {
+                let mut command = Command::new(python);
+                command.args([
+                    "-m",
+                    "vllm.entrypoints.openai.api_server",
+                    "--model",
+                    model.as_str(),
+                ]);
+                command
+            }
+            None => {
+                let mut command = Command::new("vllm");
+                command.args(["serve", model.as_str()]);
+                command
+            }
+        };
+        command
+            .args(["--host", "0.0.0.0", "--port", port.as_str()])
+            .args(["--chat-template", template_path.as_str()])
+            .args(["--tensor-parallel-size", tensor_parallel.as_str()])
+            .env("HF_HUB_CACHE", vllm_model_dir())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(max_model_len) = self.placement.max_model_len {
+            command.args(["--max-model-len", max_model_len.to_string().as_str()]);
+        }
+        if let Some(devices) = self.placement.visible_devices() {
+            command.env("CUDA_VISIBLE_DEVICES", devices);
+        }
+        if let Some(token) = &self.hugging_face_hub_token {
+            command.env("HUGGING_FACE_HUB_TOKEN", token);
+        }
+        // Own process group, so stopping it also stops the tensor parallel workers
+        #[cfg(unix)]
+        command.process_group(0);
+
+        debug!("VLLM command: {:?}", command);
+        let mut child = command.spawn().map_err(|e| {
+            error!("Failed to start VLLM process: {}", e);
+            anyhow!("Failed to start VLLM process: {}", e)
+        })?;
+
+        let log_tail = Arc::new(std::sync::Mutex::new(VecDeque::new()));
+        if let Some(stdout) = child.stdout.take() {
+            capture_log(stdout, log_tail.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            capture_log(stderr, log_tail.clone());
+        }
+        self.process = Some(Arc::new(VllmProcess {
+            child: Mutex::new(child),
+            log_tail,
+        }));
+
+        if let Err(e) = self.wait_until_ready(Duration::from_secs(600)).await {
+            let _ = self.stop_process().await;
+            return Err(e);
+        }
+        info!("VLLM process started successfully");
+        Ok(())
+    }
+
+    /// Ask vLLM to shut down, and kill it if it has not after a grace period
+    async fn stop_process(&mut self) -> Result<()> {
+        let Some(process) = self.process.take() else {
+            return Ok(());
+        };
+        info!("Stopping VLLM process...");
+        let mut child = process.child.lock().await;
+        #[cfg(unix)]
+        if let Some(pid) = child.id() {
+            // SAFETY: signals the process group spawned by start_process
+            unsafe {
+                libc::kill(-(pid as libc::pid_t), libc::SIGTERM);
+            }
+            if tokio::time::timeout(VLLM_STOP_GRACE, child.wait())
+                .await
+                .is_ok()
+            {
+                info!("VLLM process stopped");
+                return Ok(());
+            }
+            warn!("VLLM did not exit within {:?}, killing it", VLLM_STOP_GRACE);
+        }
+        child.kill().await?;
+        info!("VLLM process stopped");
+        Ok(())
+    }
+
     async fn wait_until_ready(&self, timeout: Duration) -> Result<()> {
         let start = std::time::Instant::now();
         let client = reqwest::Client::new();
@@ -233,6 +565,12 @@ impl VLLMEngine {
         info!("Waiting for VLLM to be ready at {}...", endpoint);
         let mut attempt = 0;
         while start.elapsed() < timeout {
+            if let Some(process) = &self.process {
+                if let Some(status) = process.exited().await {
+                    error!("VLLM output:\n{}", process.log_tail());
+                    return Err(anyhow!("VLLM exited before it was ready: {}", status));
+                }
+            }
             match client
                 .get(&endpoint)
                 .timeout(Duration::from_secs(2)) // Add timeout
@@ -279,6 +617,9 @@ impl VLLMEngine {
                 error!("Container logs:\n{}", logs);
             }
         }
+        if let Some(process) = &self.process {
+            error!("VLLM output:\n{}", process.log_tail());
+        }
         Err(anyhow::anyhow!("Timed out waiting for VLLM to be ready"))
     }
 
@@ -309,7 +650,7 @@ impl Engine for VLLMEngine {
     fn init(&mut self) -> impl std::future::Future<Output = Result<()>> + Send {
         async move {
             info!("Initializing VLLM engine...");
-            self.start_container().await?;
+            self.start().await?;
             info!("VLLM engine initialized successfully");
             Ok(())
         }
@@ -330,10 +671,10 @@ impl Engine for VLLMEngine {
             info!("Setting models: {:?}", models);
             self.models_name = models.clone();
             if models.first().is_some() {
-                // Stop existing container if running
-                self.stop_container().await?;
-                // Start new container with the specified model
-                self.start_container().await?;
+                // Stop the running server
+                self.stop().await?;
+                // Start it again with the specified model
+                self.start().await?;
             }
             Ok(())
         }
@@ -345,7 +686,7 @@ impl Engine for VLLMEngine {
             if self.models_name.is_empty() {
                 return Err(anyhow!("No models loaded, cannot start worker"));
             }
-            self.start_container().await?;
+            self.start().await?;
             info!("VLLM worker started successfully");
             Ok(())
         }
@@ -354,11 +695,22 @@ impl Engine for VLLMEngine {
     fn stop_worker(&mut self) -> impl std::future::Future<Output = Result<()>> + Send {
         async move {
             info!("Stopping VLLM worker...");
-            self.stop_container().await?;
+            self.stop().await?;
             info!("VLLM worker stopped successfully");
             Ok(())
         }
     }
+
+    fn infer(
+        &self,
+        request: InferenceRequest,
+    ) -> impl std::future::Future<Output = Result<InferenceStream>> + Send {
+        async move { openai_api::infer(&reqwest::Client::new(), &self.base_url, request).await }
+    }
+
+    fn list_models(&self) -> impl std::future::Future<Output = Result<Vec<common::Model>>> + Send {
+        async move { openai_api::list_models(&reqwest::Client::new(), &self.base_url, "vllm").await }
+    }
 }
 
 #[tokio::test]
@@ -388,3 +740,42 @@ async fn test_pull_model_success() {
         }
     }
 }
+
+#[test]
+fn test_vllm_placement() {
+    use common::set_u16_to_u128;
+
+    let mut devices = DevicesInfo {
+        num: 3,
+        ..Default::default()
+    };
+    for (index, gb) in [24u16, 24, 80].into_iter().enumerate() {
+        set_u16_to_u128(&mut devices.memsize_gb, index, gb);
+    }
+
+    // All cards, split across the largest power of two of them
+    let placement = VllmPlacement::plan(&devices, None, None, None).unwrap();
+    assert_eq!(placement.gpus, vec![0, 1, 2]);
+    assert_eq!(placement.tensor_parallel_size, 2);
+    assert_eq!(placement.max_model_len, Some(16384));
+    assert_eq!(placement.visible_devices().as_deref(), Some("0,1,2"));
+
+    let placement = VllmPlacement::plan(&devices, Some("2"), None, None).unwrap();
+    assert_eq!(placement.gpus, vec![2]);
+    assert_eq!(placement.tensor_parallel_size, 1);
+    assert_eq!(placement.max_model_len, None);
+
+    let placement = VllmPlacement::plan(&devices, Some("1, 0"), Some(1), Some(2048)).unwrap();
+    assert_eq!(placement.gpus, vec![1, 0]);
+    assert_eq!(placement.tensor_parallel_size, 1);
+    assert_eq!(placement.max_model_len, Some(2048));
+
+    assert!(VllmPlacement::plan(&devices, Some("3"), None, None).is_err());
+    assert!(VllmPlacement::plan(&devices, Some("a"), None, None).is_err());
+    assert!(VllmPlacement::plan(&devices, Some("0,1"), Some(4), None).is_err());
+    assert!(VllmPlacement::plan(&devices, None, Some(0), None).is_err());
+
+    // No inventory: leave the GPUs and parallelism to the engine
+    let placement = VllmPlacement::plan(&DevicesInfo::default(), None, None, None).unwrap();
+    assert_eq!(placement, VllmPlacement::default());
+}
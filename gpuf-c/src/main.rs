@@ -1,13 +1,26 @@
 use anyhow::{anyhow, Result};
 use clap::Parser;
 use gpuf_c::{
-    handle::{new_worker, WorkerHandle},
+    handle::{new_worker, ServerRestart, WorkerHandle},
     util::cmd::Args,
-    util::init_logging,
+    util::download_policy::{self, DownloadPolicy},
+    util::hf_hub,
+    util::init_logging_with_format,
+    util::model_signature,
+    util::proxy,
+    util::request_limits::{self, RequestLimits},
+    util::service,
 };
 
 #[cfg(not(target_os = "android"))]
 use gpuf_c::llm_engine::{llama_engine::LlamaEngine, llama_server::start_server, Engine};
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+use gpuf_c::util::updater;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+use gpuf_c::{
+    llm_engine::{bench, chat},
+    util::cmd::WorkerCommand,
+};
 #[cfg(not(target_os = "android"))]
 use std::sync::Arc;
 #[cfg(not(target_os = "android"))]
@@ -15,13 +28,42 @@ use tokio::sync::RwLock;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    init_logging();
+    let args = Args::parse();
+    init_logging_with_format(args.log_format);
 
     std::panic::set_hook(Box::new(|info| {
         eprintln!("gpuf-c panic: {info}");
     }));
 
-    let args = Args::parse().load_config()?;
+    if args.uninstall_service {
+        return service::uninstall();
+    }
+
+    let args = args.load_config()?;
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    match &args.command {
+        Some(WorkerCommand::Chat(chat)) => return chat::run(&args, chat).await,
+        Some(WorkerCommand::Bench(bench)) => return bench::run(&args, bench).await,
+        None => {}
+    }
+    // Installed with the arguments as given, after checking they load
+    if args.install_service {
+        return service::install(&service::service_args(std::env::args()));
+    }
+
+    download_policy::set_policy(DownloadPolicy::from_args(&args));
+    hf_hub::set_token(args.hugging_face_hub_token.clone());
+    proxy::set_http_proxy(&args);
+    request_limits::set_limits(RequestLimits::from_args(&args));
+    model_signature::set_publisher_keys(&args.model_publisher_keys)?;
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    updater::set_update_keys(&args.update_keys)?;
+
+    // Up once the settings are loaded; the status tells whether it is connected
+    service::notify_ready();
+    if let Some(interval) = service::watchdog_interval() {
+        tokio::spawn(service::run_watchdog(interval));
+    }
 
     // Check if running in standalone LLAMA mode
     #[cfg(not(target_os = "android"))]
@@ -30,17 +72,39 @@ async fn main() -> Result<()> {
     }
 
     // Normal GPUFabric worker mode
+    #[cfg(target_os = "windows")]
+    if args.run_as_service {
+        return tokio::task::block_in_place(|| service::run_windows_service(run_worker(args)));
+    }
+    run_worker(args).await
+}
+
+async fn run_worker(mut args: Args) -> Result<()> {
     loop {
         let worker = new_worker(args.clone()).await;
 
         if let Err(e) = worker.login().await {
             tracing::error!(error = %e, "gpuf-c login failed");
+            service::notify_status(&format!("Login failed: {}", e));
             drop(worker); // Explicitly drop worker to free resources
             tokio::time::sleep(std::time::Duration::from_secs(5)).await;
             continue;
         }
+        service::notify_status(&format!(
+            "Connected to {}:{}",
+            args.server_addr, args.control_port
+        ));
 
         if let Err(e) = worker.handler().await {
+            // A restarting server says where and when to reconnect
+            if let Some(restart) = e.downcast_ref::<ServerRestart>() {
+                tracing::info!(delay_ms = restart.delay.as_millis() as u64, "{}", restart);
+                restart.apply(&mut args);
+                let delay = restart.delay;
+                drop(worker);
+                tokio::time::sleep(delay).await;
+                continue;
+            }
             tracing::error!(error = %e, "gpuf-c handler exited");
             drop(worker); // Explicitly drop worker to free resources
             tracing::info!("Waiting for resources to be freed before reconnecting...");
@@ -92,7 +156,7 @@ async fn run_standalone_llama(mut args: Args) -> Result<()> {
             info!("Downloading {} (~600MB)...", default_model_name);
             let url = "https://huggingface.co/TheBloke/TinyLlama-1.1B-Chat-v1.0-GGUF/resolve/main/tinyllama-1.1b-chat-v1.0.Q4_K_M.gguf";
 
-            let response = reqwest::get(url).await?;
+            let response = proxy::http_client().build()?.get(url).send().await?;
             let bytes = response.bytes().await?;
             std::fs::write(&model_path_buf, bytes)?;
 
@@ -122,7 +186,9 @@ async fn run_standalone_llama(mut args: Args) -> Result<()> {
         args.llama_split_mode.clone(),
         args.llama_main_gpu,
         args.llama_devices.clone(),
-    );
+    )
+    .with_draft_model(args.draft_model_path.clone(), args.draft_tokens)
+    .with_parallel_slots(args.parallel_slots);
 
     engine.init().await?;
     engine.start_worker().await?;
@@ -0,0 +1,76 @@
+//! Short standardized workload run before login, so the server can rank workers
+//! by measured rather than claimed capability. Takes well under a second.
+
+use common::BenchmarkResult;
+use std::hint::black_box;
+use std::time::Instant;
+use tracing::info;
+
+const MATMUL_N: usize = 256;
+const BANDWIDTH_BYTES: usize = 32 << 20;
+const BANDWIDTH_PASSES: usize = 4;
+/// Weights read per generated token by a 7B Q4_0 model
+const REFERENCE_MODEL_BYTES: f64 = 3.8e9;
+
+pub fn run() -> BenchmarkResult {
+    let started = Instant::now();
+    let gflops = matmul_gflops(MATMUL_N);
+    let memory_bandwidth_gbps = memory_bandwidth_gbps(BANDWIDTH_BYTES, BANDWIDTH_PASSES);
+    let result = BenchmarkResult {
+        gflops,
+        memory_bandwidth_gbps,
+        // Decoding is memory bound: every token streams all weights once
+        tokens_per_sec: (memory_bandwidth_gbps as f64 * 1e9 / REFERENCE_MODEL_BYTES) as f32,
+        duration_ms: started.elapsed().as_millis() as u32,
+    };
+    info!(
+        "Benchmark: {:.1} GFLOPS, {:.1} GB/s memory bandwidth, ~{:.1} tokens/s (7B Q4) in {} ms",
+        result.gflops, result.memory_bandwidth_gbps, result.tokens_per_sec, result.duration_ms
+    );
+    result
+}
+
+/// Single-precision `n`x`n` matrix multiply
+fn matmul_gflops(n: usize) -> f32 {
+    let a: Vec<f32> = (0..n * n).map(|i| (i % 7) as f32 * 0.5).collect();
+    let b: Vec<f32> = (0..n * n).map(|i| (i % 5) as f32 * 0.25).collect();
+    let mut c = vec![0f32; n * n];
+
+    let start = Instant::now();
+    for i in 0..n {
+        let out = &mut c[i * n..(i + 1) * n];
+        for k in 0..n {
+            let aik = a[i * n + k];
+            for (o, &bkj) in out.iter_mut().zip(&b[k * n..(k + 1) * n]) {
+                *o += aik * bkj;
+            }
+        }
+    }
+    black_box(&c);
+    let secs = start.elapsed().as_secs_f64().max(1e-9);
+
+    (2.0 * (n * n * n) as f64 / secs / 1e9) as f32
+}
+
+/// Copy `bytes` between two buffers `passes` times, counting reads and writes
+fn memory_bandwidth_gbps(bytes: usize, passes: usize) -> f32 {
+    let src = vec![1u8; bytes];
+    let mut dst = vec![0u8; bytes];
+    // Fault the pages in before timing
+    dst.copy_from_slice(&src);
+
+    let start = Instant::now();
+    for _ in 0..passes {
+        dst.copy_from_slice(black_box(&src));
+        black_box(&mut dst);
+    }
+    let secs = start.elapsed().as_secs_f64().max(1e-9);
+
+    ((2 * bytes * passes) as f64 / secs / 1e9) as f32
+}
+
+#[test]
+fn test_benchmark_measures() {
+    assert!(matmul_gflops(32) > 0.0);
+    assert!(memory_bandwidth_gbps(1 << 20, 2) > 0.0);
+}
@@ -1,7 +1,12 @@
 use anyhow::{Context, Result};
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
+use common::secrets::load_secret;
 
 use crate::util::config::Config;
+use crate::util::download_policy::DownloadWindow;
+use crate::util::net::IpVersion;
+use crate::util::proxy::OutboundProxy;
+use crate::util::tls::ServerPin;
 use tracing::info;
 
 #[derive(ValueEnum, Debug, Clone, PartialEq, Eq, serde::Serialize)]
@@ -31,13 +36,13 @@ impl std::str::FromStr for LlamaSplitModeArg {
 }
 
 #[derive(Parser, Debug, Clone)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct Args {
     #[arg(short('f'), long)]
     pub config: Option<String>,
 
     /// Unique ID for this client instance. If not provided, uses machine ID.
-    #[arg(short('i'), long, value_parser = parse_client_id, required_unless_present_any = ["config", "standalone_llama"])]
+    #[arg(short('i'), long, value_parser = parse_client_id, required_unless_present_any = ["config", "standalone_llama", "uninstall_service"])]
     pub client_id: Option<[u8; 16]>,
 
     /// Address of the gpuf-s server.
@@ -73,6 +78,35 @@ pub struct Args {
     #[arg(long, default_value = "ca-cert.pem")]
     pub cert_chain_path: String,
 
+    /// PEM bundle of CA certificates also trusted for the server, e.g. the
+    /// internal CA of a corporate network
+    #[arg(long, env = "GPUF_CA_BUNDLE")]
+    pub ca_bundle: Option<String>,
+
+    /// Accepted server certificates, comma-separated: `sha256/<base64>` of
+    /// the public key, or the hex SHA-256 fingerprint of the certificate
+    #[arg(long, env = "GPUF_SERVER_CERT_PIN", value_delimiter = ',')]
+    pub server_cert_pin: Vec<ServerPin>,
+
+    /// Proxy of outbound connections, `http://`, `socks5://` or `socks5h://`
+    /// with optional `user:password@`
+    #[arg(long, env = "GPUF_PROXY_URL")]
+    pub proxy_url: Option<OutboundProxy>,
+
+    /// Proxy of the connections to gpuf-s instead of `--proxy-url`, or `direct`
+    #[arg(long, env = "GPUF_SERVER_PROXY_URL")]
+    pub server_proxy_url: Option<OutboundProxy>,
+
+    /// Proxy of model downloads and other HTTP requests instead of
+    /// `--proxy-url`, or `direct`
+    #[arg(long, env = "GPUF_DOWNLOAD_PROXY_URL")]
+    pub download_proxy_url: Option<OutboundProxy>,
+
+    /// Address family of the connections to gpuf-s: `auto` races IPv6 and
+    /// IPv4, `4` or `6` sticks to one
+    #[arg(long, env = "GPUF_IP_VERSION", default_value = "auto")]
+    pub ip_version: IpVersion,
+
     #[arg(
         long,
         default_value = "tcp",
@@ -83,7 +117,7 @@ pub struct Args {
     #[arg(
         long,
         default_value = "ollama",
-        help = "type of engine to use (vllm or ollama)"
+        help = "type of engine to use (vllm, ollama, external, trtllm or sd)"
     )]
     pub engine_type: EngineType,
 
@@ -93,6 +127,10 @@ pub struct Args {
     #[arg(long, default_value = None, help = "hugging face hub token" )]
     pub hugging_face_hub_token: Option<String>,
 
+    /// File holding the hugging face hub token, instead of `--hugging-face-hub-token`
+    #[arg(long)]
+    pub hugging_face_hub_token_file: Option<String>,
+
     #[arg(long, default_value = None, help = "chat template path" )]
     pub chat_template_path: Option<String>,
 
@@ -107,17 +145,24 @@ pub struct Args {
     /// Number of GPU layers to offload (default: 99 for large models)
     #[arg(
         long,
+        global = true,
         default_value_t = 99,
         help = "Number of model layers to offload to GPU"
     )]
     pub n_gpu_layers: u32,
 
     /// Context size for model inference (default: 8192)
-    #[arg(long, default_value_t = 8192, help = "Context window size in tokens")]
+    #[arg(
+        long,
+        global = true,
+        default_value_t = 8192,
+        help = "Context window size in tokens"
+    )]
     pub n_ctx: u32,
 
     #[arg(
         long,
+        global = true,
         default_value = "layer",
         help = "Llama multi-GPU split mode: none, layer, row"
     )]
@@ -125,6 +170,7 @@ pub struct Args {
 
     #[arg(
         long,
+        global = true,
         default_value_t = 0,
         help = "Main GPU index for llama.cpp (scratch/small tensors)"
     )]
@@ -132,21 +178,372 @@ pub struct Args {
 
     #[arg(
         long,
+        global = true,
         default_value = None,
         help = "Comma-separated ggml backend device indices to use (e.g. '0,1'); empty uses default"
     )]
     pub llama_devices: Option<String>,
 
+    /// Small GGUF model of the same vocabulary drafting tokens for
+    /// speculative decoding, instead of the draft the catalog names
+    #[arg(
+        long,
+        help = "Draft GGUF model for speculative decoding with the llama engine"
+    )]
+    pub draft_model_path: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 8,
+        help = "Tokens the draft model proposes per verification step"
+    )]
+    pub draft_tokens: u32,
+
+    /// Requests the llama engine decodes at once on the loaded model, each
+    /// in a context of `n_ctx`. 0 picks as many as its memory fits, or one
+    /// with a draft model.
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Parallel sequence slots of the llama engine (0 = as many as fit)"
+    )]
+    pub parallel_slots: u32,
+
     #[arg(
         long,
         default_value_t = 1,
         help = "Max bytes per streamed delta chunk sent to server"
     )]
     pub stream_chunk_bytes: usize,
+
+    /// Log output format: compact for humans, json for log collectors
+    #[arg(long, env = "GPUF_LOG_FORMAT", default_value = "compact")]
+    pub log_format: LogFormat,
+
+    /// Cap on model download bandwidth in bytes per second
+    #[arg(
+        long,
+        help = "Max model download rate in bytes per second (unlimited if unset)"
+    )]
+    pub download_max_rate: Option<u64>,
+
+    /// Local hours in which models may be downloaded, e.g. "1-6" or "23-7"
+    #[arg(
+        long,
+        help = "Only download models between these local hours, e.g. 1-6"
+    )]
+    pub download_window: Option<DownloadWindow>,
+
+    /// Hold model downloads while the network is reported as metered
+    #[arg(long, help = "Only download models on unmetered networks")]
+    pub download_unmetered_only: bool,
+
+    /// Model downloads running at once, sharing the download rate cap
+    #[arg(
+        long,
+        default_value_t = 2,
+        help = "Max model downloads running at once"
+    )]
+    pub max_concurrent_downloads: usize,
+
+    /// Once set, models are only loaded with a valid signature by one of
+    /// these publishers
+    #[arg(
+        long = "model-publisher-key",
+        help = "Base64 Ed25519 public key whose model signatures to trust (repeatable)"
+    )]
+    pub model_publisher_keys: Vec<String>,
+
+    /// Once set, the worker updates itself to releases the server offers
+    /// whose builds carry a valid signature by one of these keys
+    #[arg(
+        long = "update-key",
+        help = "Base64 Ed25519 public key whose worker builds to trust (repeatable)"
+    )]
+    pub update_keys: Vec<String>,
+
+    /// Disk space downloaded models may take; least recently used ones are
+    /// deleted to stay under it
+    #[arg(long, help = "Disk quota of cached models in GB, unlimited when unset")]
+    pub model_cache_quota_gb: Option<u64>,
+
+    /// Load offered models without checking that they fit into free RAM and
+    /// VRAM next to their KV cache
+    #[arg(long, help = "Skip the free memory check before loading a model")]
+    pub skip_model_fit_check: bool,
+
+    /// Load and warm up the most recently used cached model before logging
+    /// in, so the worker takes requests warm instead of loading the model
+    /// the server assigns after login
+    #[arg(long, help = "Preload the last used model before logging in")]
+    pub preload_model: bool,
+
+    /// Free the loaded model once no request used it for this long and load
+    /// it again on the next one. The server can change it with a config push.
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Unload the model after this many idle seconds, 0 to keep it loaded"
+    )]
+    pub idle_unload_secs: u32,
+
+    /// Inference requests with a longer prompt are rejected before they
+    /// reach the engine, so one oversized request cannot tie up the worker
+    #[arg(
+        long,
+        default_value_t = 1024 * 1024,
+        help = "Largest prompt in bytes, 0 for no limit"
+    )]
+    pub max_prompt_bytes: usize,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Largest prompt in tokens, 0 to only limit it by the context"
+    )]
+    pub max_prompt_tokens: usize,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Highest max_tokens a request may ask for, 0 for no ceiling"
+    )]
+    pub max_tokens_limit: u32,
+
+    /// How the vLLM engine runs: a Docker container, or `vllm serve` as a
+    /// child process for bare-metal and rootless installs
+    #[arg(
+        long,
+        default_value = "docker",
+        help = "Run the vllm engine in docker or as a native process"
+    )]
+    pub vllm_mode: VllmMode,
+
+    /// Python environment with vLLM installed, for native mode when the
+    /// `vllm` command is not on PATH
+    #[arg(long, help = "Python interpreter to run vllm with in native mode")]
+    pub vllm_python: Option<String>,
+
+    /// GPUs vLLM may use, as indices into the device inventory; all of them
+    /// when unset
+    #[arg(
+        long,
+        help = "Comma-separated GPU indices for vllm (CUDA_VISIBLE_DEVICES)"
+    )]
+    pub vllm_gpus: Option<String>,
+
+    #[arg(
+        long,
+        help = "Tensor parallel size for vllm [default: largest power of two of the GPUs]"
+    )]
+    pub vllm_tensor_parallel_size: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Maximum context length for vllm [default: derived from GPU memory]"
+    )]
+    pub vllm_max_model_len: Option<u32>,
+
+    /// How long Ollama keeps a pulled model loaded, passed to it as
+    /// `keep_alive`; negative keeps it loaded until Ollama stops
+    #[arg(
+        long,
+        default_value = "-1",
+        allow_hyphen_values = true,
+        help = "How long ollama keeps models loaded (e.g. 30m, -1 for always)"
+    )]
+    pub ollama_keep_alive: String,
+
+    /// Base URL of the OpenAI-compatible server the external engine proxies
+    /// to, e.g. a llama-server, TGI or LM Studio the provider runs
+    #[arg(long, help = "Base URL of the server for the external engine")]
+    pub external_url: Option<String>,
+
+    /// Triton model repository of TensorRT-LLM engines, mounted into the
+    /// container; `~/.triton/models` when unset
+    #[arg(long, help = "Triton model repository for the trtllm engine")]
+    pub trtllm_model_repository: Option<String>,
+
+    #[arg(
+        long,
+        help = "Hugging Face tokenizer for the trtllm engine's chat templates"
+    )]
+    pub trtllm_tokenizer: Option<String>,
+
+    /// Triton TensorRT-LLM container image, matching the TensorRT-LLM
+    /// version the engines were built with
+    #[arg(long, help = "Triton TensorRT-LLM image for the trtllm engine")]
+    pub trtllm_image: Option<String>,
+
+    /// Stable Diffusion checkpoint the sd engine generates images with
+    /// through stable-diffusion.cpp
+    #[arg(long, help = "Stable Diffusion model file for the sd engine")]
+    pub sd_model: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "sd",
+        help = "stable-diffusion.cpp binary for the sd engine"
+    )]
+    pub sd_binary: String,
+
+    /// Server with the AUTOMATIC1111 API the sd engine generates images with
+    /// instead of stable-diffusion.cpp
+    #[arg(long, help = "Stable Diffusion server URL for the sd engine")]
+    pub sd_server_url: Option<String>,
+
+    #[arg(
+        long,
+        help = "Model id the sd engine reports, the model file name by default"
+    )]
+    pub sd_model_name: Option<String>,
+
+    /// Enrollment token that gets this worker approved on its first login
+    #[arg(long, env = "GPUF_ENROLLMENT_TOKEN")]
+    pub enrollment_token: Option<String>,
+
+    /// File holding the enrollment token, instead of `--enrollment-token`
+    #[arg(long, env = "GPUF_ENROLLMENT_TOKEN_FILE")]
+    pub enrollment_token_file: Option<String>,
+
+    /// Directory of secret files named like the settings, e.g. `/run/secrets`
+    /// with `enrollment_token`, used for secrets that are not set otherwise
+    #[arg(long, env = "GPUF_SECRETS_DIR")]
+    pub secrets_dir: Option<String>,
+
+    /// Region this worker serves, e.g. `eu-west`; the server looks up the
+    /// country of its address when unset
+    #[arg(long, env = "GPUF_REGION")]
+    pub region: Option<String>,
+
+    /// Register gpuf-c with systemd or as a Windows service that starts at
+    /// boot and restarts when it exits, running with the other arguments
+    /// given here
+    #[arg(
+        long,
+        help = "Install gpuf-c as a service with these arguments and exit"
+    )]
+    pub install_service: bool,
+
+    #[arg(long, help = "Remove the gpuf-c service and exit")]
+    pub uninstall_service: bool,
+
+    /// Passed by the Windows service to run under the service control manager
+    #[arg(long, hide = true)]
+    pub run_as_service: bool,
+
+    #[command(subcommand)]
+    pub command: Option<WorkerCommand>,
+}
+
+/// Runs locally instead of joining the fabric
+#[derive(Subcommand, Debug, Clone)]
+pub enum WorkerCommand {
+    /// Chat with a GGUF model in the terminal, to check the hardware and
+    /// model before joining the fabric
+    Chat(ChatArgs),
+    /// Measure prompt processing and generation speed of a GGUF model at
+    /// several context and batch sizes
+    Bench(BenchArgs),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ChatArgs {
+    /// GGUF model file, or the name of one in ~/.llama/models
+    #[arg(short, long)]
+    pub model: String,
+
+    #[arg(long, help = "System prompt the chat starts with")]
+    pub system: Option<String>,
+
+    #[arg(long, default_value_t = 512, help = "Most tokens generated per answer")]
+    pub max_tokens: u32,
+
+    #[arg(long, default_value_t = 0.8)]
+    pub temperature: f32,
+
+    #[arg(long, default_value_t = 40)]
+    pub top_k: u32,
+
+    #[arg(long, default_value_t = 0.95)]
+    pub top_p: f32,
+
+    #[arg(long, default_value_t = 1.1)]
+    pub repeat_penalty: f32,
+
+    #[arg(
+        long,
+        default_value_t = 64,
+        help = "Tokens the repeat penalty looks back on"
+    )]
+    pub repeat_last_n: i32,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct BenchArgs {
+    /// GGUF model file, or the name of one in ~/.llama/models
+    #[arg(short, long)]
+    pub model: String,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "2048,8192",
+        help = "Comma-separated context sizes to measure"
+    )]
+    pub ctx_sizes: Vec<u32>,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "512",
+        help = "Comma-separated batch sizes the prompt is evaluated in"
+    )]
+    pub batch_sizes: Vec<u32>,
+
+    #[arg(long, default_value_t = 512, help = "Prompt tokens evaluated per run")]
+    pub n_prompt: u32,
+
+    #[arg(long, default_value_t = 128, help = "Tokens generated per run")]
+    pub n_gen: u32,
+
+    #[arg(long, default_value_t = 3, help = "Runs averaged per measurement")]
+    pub repetitions: u32,
+
+    /// Send the results to the server, which keeps them with this client
+    #[arg(long)]
+    pub upload: bool,
 }
 
 impl Args {
     pub fn load_config(&self) -> Result<Args> {
+        let mut args = self.merge_config()?;
+        args.load_secrets()?;
+        Ok(args)
+    }
+
+    /// Replace secrets with the content of their `--*-file`, or of the file
+    /// named like them in `--secrets-dir`, and resolve `env:NAME` values
+    fn load_secrets(&mut self) -> Result<()> {
+        let dir = self.secrets_dir.clone();
+        let secret = |name: &str, value: Option<&str>, file: Option<&str>| {
+            load_secret(name, value, value.is_none(), file, dir.as_deref())
+                .with_context(|| format!("Failed to load {}", name))
+        };
+        self.hugging_face_hub_token = secret(
+            "hugging_face_hub_token",
+            self.hugging_face_hub_token.as_deref(),
+            self.hugging_face_hub_token_file.as_deref(),
+        )?;
+        self.enrollment_token = secret(
+            "enrollment_token",
+            self.enrollment_token.as_deref(),
+            self.enrollment_token_file.as_deref(),
+        )?;
+        Ok(())
+    }
+
+    fn merge_config(&self) -> Result<Args> {
         if let Some(config_path) = &self.config {
             // Try to load from config file
             let config_data = Config::from_file(config_path)
@@ -167,9 +564,12 @@ impl Args {
                 "vllm" => EngineType::VLLM,
                 "ollama" => EngineType::OLLAMA,
                 "llama" => EngineType::LLAMA,
+                "external" => EngineType::EXTERNAL,
+                "trtllm" => EngineType::TRTLLM,
+                "sd" => EngineType::SD,
                 _ => {
                     return Err(anyhow::anyhow!(
-                        "Invalid engine_type in config. Must be 'vllm' or 'ollama'"
+                        "Invalid engine_type in config. Must be 'vllm', 'ollama', 'llama', 'external', 'trtllm' or 'sd'"
                     ))
                 }
             };
@@ -180,6 +580,15 @@ impl Args {
 
             info!("client_id: {:?}", client_id);
 
+            let server_cert_pin = match config_data.client.server_cert_pin {
+                Some(pins) => pins
+                    .iter()
+                    .map(|pin| pin.parse())
+                    .collect::<Result<Vec<ServerPin>, _>>()
+                    .map_err(|e| anyhow::anyhow!("Invalid server_cert_pin in config: {}", e))?,
+                None => self.server_cert_pin.clone(),
+            };
+
             let llama_split_mode = match config_data
                 .client
                 .llama_split_mode
@@ -202,10 +611,23 @@ impl Args {
                 p2p_advertise_ip: self.p2p_advertise_ip.clone(),
                 p2p_udp_port: self.p2p_udp_port,
                 cert_chain_path: config_data.client.cert_chain_path,
+                ca_bundle: config_data
+                    .client
+                    .ca_bundle
+                    .or_else(|| self.ca_bundle.clone()),
+                server_cert_pin,
+                proxy_url: self.proxy_url.clone(),
+                server_proxy_url: self.server_proxy_url.clone(),
+                download_proxy_url: self.download_proxy_url.clone(),
+                ip_version: self.ip_version,
                 worker_type: worker_type,
                 engine_type: engine_type,
                 auto_models: config_data.client.auto_models,
-                hugging_face_hub_token: config_data.client.hugging_face_hub_token,
+                hugging_face_hub_token: config_data
+                    .client
+                    .hugging_face_hub_token
+                    .or_else(|| self.hugging_face_hub_token.clone()),
+                hugging_face_hub_token_file: self.hugging_face_hub_token_file.clone(),
                 chat_template_path: config_data.client.chat_template_path,
                 standalone_llama: false, // Config file doesn't support standalone mode
                 llama_model_path: None,
@@ -218,7 +640,46 @@ impl Args {
                     .llama_devices
                     .clone()
                     .or_else(|| self.llama_devices.clone()),
+                draft_model_path: self.draft_model_path.clone(),
+                draft_tokens: self.draft_tokens,
+                parallel_slots: self.parallel_slots,
                 stream_chunk_bytes: self.stream_chunk_bytes,
+                log_format: self.log_format.clone(),
+                download_max_rate: self.download_max_rate,
+                download_window: self.download_window,
+                download_unmetered_only: self.download_unmetered_only,
+                max_concurrent_downloads: self.max_concurrent_downloads,
+                model_publisher_keys: self.model_publisher_keys.clone(),
+                update_keys: self.update_keys.clone(),
+                model_cache_quota_gb: self.model_cache_quota_gb,
+                skip_model_fit_check: self.skip_model_fit_check,
+                preload_model: self.preload_model,
+                idle_unload_secs: self.idle_unload_secs,
+                max_prompt_bytes: self.max_prompt_bytes,
+                max_prompt_tokens: self.max_prompt_tokens,
+                max_tokens_limit: self.max_tokens_limit,
+                vllm_mode: self.vllm_mode,
+                vllm_python: self.vllm_python.clone(),
+                vllm_gpus: self.vllm_gpus.clone(),
+                vllm_tensor_parallel_size: self.vllm_tensor_parallel_size,
+                vllm_max_model_len: self.vllm_max_model_len,
+                ollama_keep_alive: self.ollama_keep_alive.clone(),
+                external_url: self.external_url.clone(),
+                trtllm_model_repository: self.trtllm_model_repository.clone(),
+                trtllm_tokenizer: self.trtllm_tokenizer.clone(),
+                trtllm_image: self.trtllm_image.clone(),
+                sd_model: self.sd_model.clone(),
+                sd_binary: self.sd_binary.clone(),
+                sd_server_url: self.sd_server_url.clone(),
+                sd_model_name: self.sd_model_name.clone(),
+                enrollment_token: self.enrollment_token.clone(),
+                enrollment_token_file: self.enrollment_token_file.clone(),
+                secrets_dir: self.secrets_dir.clone(),
+                region: self.region.clone(),
+                install_service: self.install_service,
+                uninstall_service: self.uninstall_service,
+                run_as_service: self.run_as_service,
+                command: self.command.clone(),
             })
         } else {
             // In standalone_llama mode, client_id is optional
@@ -241,6 +702,24 @@ fn parse_client_id(s: &str) -> Result<[u8; 16], String> {
         .map_err(|_| format!("Invalid client ID length"))?)
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize)]
+pub enum LogFormat {
+    #[default]
+    #[clap(name = "compact")]
+    Compact,
+    #[clap(name = "json")]
+    Json,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize)]
+pub enum VllmMode {
+    #[default]
+    #[clap(name = "docker")]
+    Docker,
+    #[clap(name = "native")]
+    Native,
+}
+
 #[derive(ValueEnum, Debug, Clone, serde::Serialize)]
 pub enum WorkerType {
     #[clap(name = "tcp")]
@@ -257,6 +736,12 @@ pub enum EngineType {
     OLLAMA,
     #[clap(name = "llama")]
     LLAMA,
+    #[clap(name = "external")]
+    EXTERNAL,
+    #[clap(name = "trtllm")]
+    TRTLLM,
+    #[clap(name = "sd")]
+    SD,
 }
 
 impl EngineType {
@@ -265,6 +750,9 @@ impl EngineType {
             EngineType::VLLM => common::EngineType::Vllm,
             EngineType::OLLAMA => common::EngineType::Ollama,
             EngineType::LLAMA => common::EngineType::Llama,
+            EngineType::EXTERNAL => common::EngineType::External,
+            EngineType::TRTLLM => common::EngineType::TensorRT,
+            EngineType::SD => common::EngineType::StableDiffusion,
         }
     }
 }
@@ -33,6 +33,10 @@ pub struct ClientConfig {
     pub engine_type: String,
     #[serde(rename = "cert_chain_path")]
     pub cert_chain_path: String,
+    #[serde(rename = "ca_bundle")]
+    pub ca_bundle: Option<String>,
+    #[serde(rename = "server_cert_pin")]
+    pub server_cert_pin: Option<Vec<String>>,
     #[serde(rename = "local_addr")]
     pub local_addr: String,
     #[serde(rename = "local_port")]
@@ -124,10 +128,13 @@ impl DockerConfig {
             EngineType::VLLM => "vllm",
             EngineType::OLLAMA => "ollama",
             EngineType::LLAMA => "llama",
+            EngineType::EXTERNAL => "external",
+            EngineType::TRTLLM => "trtllm",
+            EngineType::SD => "sd",
         };
 
         let service = match engine_type {
-            EngineType::VLLM => Service {
+            EngineType::VLLM => Some(Service {
                 image: "vllm/vllm-openai:latest".to_string(),
                 container_name: "vllm_engine_container".to_string(),
                 ports: vec!["8000:8000".to_string()],
@@ -142,8 +149,8 @@ impl DockerConfig {
                 shm_size: Some("2g".to_string()),
                 runtime: None,
                 devices: None,
-            },
-            EngineType::LLAMA => Service {
+            }),
+            EngineType::LLAMA => Some(Service {
                 image: "ghcr.io/ggerganov/llama.cpp:server".to_string(),
                 container_name: "llama_engine_container".to_string(),
                 ports: vec!["8080:8080".to_string()],
@@ -159,9 +166,9 @@ impl DockerConfig {
                 shm_size: Some("2g".to_string()),
                 runtime: Some("nvidia".to_string()),
                 devices: None,
-            },
+            }),
 
-            EngineType::OLLAMA => Service {
+            EngineType::OLLAMA => Some(Service {
                 image: "ollama/ollama:latest".to_string(),
                 container_name: "ollama_engine_container".to_string(),
                 ports: vec!["11434:11434".to_string()],
@@ -173,10 +180,26 @@ impl DockerConfig {
                 shm_size: Some("2g".to_string()),
                 runtime: Some("nvidia".to_string()),
                 devices: Some(vec!["/dev/kfd".to_string(), "/dev/dri".to_string()]),
-            },
+            }),
+            EngineType::TRTLLM => Some(Service {
+                image: "nvcr.io/nvidia/tritonserver:24.12-trtllm-python-py3".to_string(),
+                container_name: "trtllm_engine_container".to_string(),
+                ports: vec!["9000:9000".to_string()],
+                volumes: vec!["~/.triton/models:/models".to_string()],
+                environment: None,
+                shm_size: Some("2g".to_string()),
+                runtime: Some("nvidia".to_string()),
+                devices: None,
+            }),
+            // The provider runs the server, there is no container to compose
+            EngineType::EXTERNAL => None,
+            // stable-diffusion.cpp runs as a local binary
+            EngineType::SD => None,
         };
 
-        services.insert(service_name.to_string(), service);
+        if let Some(service) = service {
+            services.insert(service_name.to_string(), service);
+        }
 
         let mut volumes = HashMap::new();
         volumes.insert(
@@ -82,3 +82,240 @@ fn test_read_power_metrics() {
     println!("metrics: {:#?}", &metrics);
     assert!(metrics.is_some());
 }
+
+// AMD and Intel GPUs on Linux. The DRM sysfs nodes give PCI ids, VRAM and hwmon
+// readings for both vendors; with the `rocm` feature AMD load/power/temperature
+// come from ROCm SMI instead.
+
+#[cfg(all(target_os = "linux", any(feature = "rocm", feature = "intel")))]
+use std::path::Path;
+
+#[cfg(all(target_os = "linux", feature = "rocm"))]
+const AMD_VENDOR_ID: u16 = 0x1002;
+#[cfg(all(target_os = "linux", feature = "intel"))]
+const INTEL_VENDOR_ID: u16 = 0x8086;
+
+/// One GPU in `DevicesInfo` units
+#[cfg(all(target_os = "linux", any(feature = "rocm", feature = "intel")))]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GpuReading {
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub usage: u8,
+    pub mem_usage: u8,
+    pub power_w: u8,
+    pub temp_c: u8,
+    pub memsize_gb: u16,
+    pub powerlimit_w: u16,
+}
+
+#[cfg(all(target_os = "linux", any(feature = "rocm", feature = "intel")))]
+fn read_sysfs_u64(path: &Path) -> Option<u64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(all(target_os = "linux", any(feature = "rocm", feature = "intel")))]
+fn read_sysfs_hex(path: &Path) -> Option<u16> {
+    let raw = std::fs::read_to_string(path).ok()?;
+    let raw = raw.trim();
+    u16::from_str_radix(raw.strip_prefix("0x").unwrap_or(raw), 16).ok()
+}
+
+/// First value found among `names` in the device's hwmon directory
+#[cfg(all(target_os = "linux", any(feature = "rocm", feature = "intel")))]
+fn read_hwmon(device_dir: &Path, names: &[&str]) -> Option<u64> {
+    let entries = std::fs::read_dir(device_dir.join("hwmon")).ok()?;
+    entries.flatten().find_map(|hwmon| {
+        names
+            .iter()
+            .find_map(|name| read_sysfs_u64(&hwmon.path().join(name)))
+    })
+}
+
+/// GPUs of `vendor_id` under `drm_root` (normally `/sys/class/drm`), in card order
+#[cfg(all(target_os = "linux", any(feature = "rocm", feature = "intel")))]
+pub fn read_drm_gpus(drm_root: &Path, vendor_id: u16) -> Vec<GpuReading> {
+    let Ok(entries) = std::fs::read_dir(drm_root) else {
+        return Vec::new();
+    };
+    let mut cards: Vec<_> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("card") && !n.contains('-'))
+        })
+        .collect();
+    cards.sort_by_key(|path| {
+        path.file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n["card".len()..].parse::<u32>().ok())
+            .unwrap_or(u32::MAX)
+    });
+
+    cards
+        .iter()
+        .filter_map(|card| {
+            let device_dir = card.join("device");
+            if read_sysfs_hex(&device_dir.join("vendor"))? != vendor_id {
+                return None;
+            }
+
+            // amdgpu: mem_info_vram_*, i915 discrete: lmem_*_bytes on the card
+            let vram_total = read_sysfs_u64(&device_dir.join("mem_info_vram_total"))
+                .or_else(|| read_sysfs_u64(&card.join("lmem_total_bytes")))
+                .unwrap_or(0);
+            let vram_used = read_sysfs_u64(&device_dir.join("mem_info_vram_used"))
+                .or_else(|| {
+                    read_sysfs_u64(&card.join("lmem_avail_bytes"))
+                        .map(|avail| vram_total.saturating_sub(avail))
+                })
+                .unwrap_or(0);
+
+            // hwmon reports millidegrees and microwatts
+            Some(GpuReading {
+                vendor_id,
+                device_id: read_sysfs_hex(&device_dir.join("device")).unwrap_or(0),
+                // i915/xe expose no busy percentage in sysfs
+                usage: read_sysfs_u64(&device_dir.join("gpu_busy_percent"))
+                    .unwrap_or(0)
+                    .min(100) as u8,
+                mem_usage: (vram_used * 100 / vram_total.max(1)) as u8,
+                power_w: (read_hwmon(&device_dir, &["power1_average", "power1_input"]).unwrap_or(0)
+                    / 1_000_000)
+                    .min(u8::MAX as u64) as u8,
+                temp_c: (read_hwmon(&device_dir, &["temp1_input", "temp2_input"]).unwrap_or(0)
+                    / 1000)
+                    .min(u8::MAX as u64) as u8,
+                memsize_gb: (vram_total >> 30) as u16,
+                powerlimit_w: (read_hwmon(&device_dir, &["power1_cap", "power1_max"]).unwrap_or(0)
+                    / 1_000_000) as u16,
+            })
+        })
+        .collect()
+}
+
+/// AMD GPUs with ROCm SMI readings, matched to DRM cards by index
+#[cfg(all(target_os = "linux", feature = "rocm"))]
+fn read_rocm_gpus() -> Vec<GpuReading> {
+    use rocm_smi_lib::*;
+
+    let mut gpus = read_drm_gpus(Path::new("/sys/class/drm"), AMD_VENDOR_ID);
+    let rocm = match RocmSmi::new() {
+        Ok(rocm) => rocm,
+        Err(e) => {
+            tracing::debug!("ROCm SMI unavailable, using sysfs readings: {}", e);
+            return gpus;
+        }
+    };
+
+    for (gpu, device) in gpus.iter_mut().zip(rocm.devices()) {
+        if let Ok(utilization) = device.get_utilization_rate() {
+            gpu.usage = (utilization as u64).min(100) as u8;
+        }
+        if let Ok(memory) = device.get_memory_info() {
+            let (used, total) = (memory.used as u64, memory.total as u64);
+            gpu.mem_usage = (used * 100 / total.max(1)) as u8;
+            gpu.memsize_gb = (total >> 30) as u16;
+        }
+        if let Ok(power) = device.get_power_usage() {
+            gpu.power_w = (power as u64 / 1_000_000).min(u8::MAX as u64) as u8;
+        }
+        if let Ok(temp) = device.get_temperature(TemperatureSensor::Gpu) {
+            gpu.temp_c = (temp as u64).min(u8::MAX as u64) as u8;
+        }
+    }
+    gpus
+}
+
+/// Pack readings into `DevicesInfo`, at most 8 devices fit
+#[cfg(all(target_os = "linux", any(feature = "rocm", feature = "intel")))]
+pub fn to_devices_info(
+    gpus: &[GpuReading],
+    engine_type: common::EngineType,
+) -> (common::DevicesInfo, u32) {
+    use common::{set_u16_to_u128, set_u8_to_u64, to_tflops};
+
+    let mut info = common::DevicesInfo {
+        engine_type,
+        os_type: common::OsType::LINUX,
+        ..Default::default()
+    };
+    let mut total_memory: u32 = 0;
+    let mut total_tflops: f32 = 0.0;
+    for (index, gpu) in gpus.iter().take(8).enumerate() {
+        set_u8_to_u64(&mut info.usage, index, gpu.usage);
+        set_u8_to_u64(&mut info.mem_usage, index, gpu.mem_usage);
+        set_u8_to_u64(&mut info.power_usage, index, gpu.power_w);
+        set_u8_to_u64(&mut info.temp, index, gpu.temp_c);
+        set_u16_to_u128(&mut info.vendor_id, index, gpu.vendor_id);
+        set_u16_to_u128(&mut info.device_id, index, gpu.device_id);
+        set_u16_to_u128(&mut info.memsize_gb, index, gpu.memsize_gb);
+        set_u16_to_u128(&mut info.powerlimit_w, index, gpu.powerlimit_w);
+        total_memory += gpu.memsize_gb as u32;
+        total_tflops += to_tflops(gpu.device_id).unwrap_or(0.0);
+    }
+    info.num = gpus.len().min(8) as u16;
+    info.memtotal_gb = total_memory as u16;
+    info.total_tflops = total_tflops as u16;
+    (info, total_memory)
+}
+
+/// AMD (`rocm` feature) and Intel (`intel` feature) GPUs, `None` if none found
+#[cfg(all(target_os = "linux", any(feature = "rocm", feature = "intel")))]
+pub fn collect_vendor_gpus(engine_type: common::EngineType) -> Option<(common::DevicesInfo, u32)> {
+    #[allow(unused_mut)]
+    let mut gpus: Vec<GpuReading> = Vec::new();
+    #[cfg(feature = "rocm")]
+    gpus.extend(read_rocm_gpus());
+    #[cfg(feature = "intel")]
+    gpus.extend(read_drm_gpus(Path::new("/sys/class/drm"), INTEL_VENDOR_ID));
+
+    if gpus.is_empty() {
+        return None;
+    }
+    tracing::debug!("Found {} AMD/Intel GPU(s): {:?}", gpus.len(), gpus);
+    Some(to_devices_info(&gpus, engine_type))
+}
+
+#[cfg(all(target_os = "linux", any(feature = "rocm", feature = "intel")))]
+#[test]
+fn test_read_drm_gpus() {
+    let root = tempfile::tempdir().unwrap();
+    let write = |path: &str, value: &str| {
+        let path = root.path().join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, value).unwrap();
+    };
+    write("card1/device/vendor", "0x1002\n");
+    write("card1/device/device", "0x744c\n");
+    write("card1/device/gpu_busy_percent", "42\n");
+    write("card1/device/mem_info_vram_total", "25753026560\n");
+    write("card1/device/mem_info_vram_used", "6438256640\n");
+    write("card1/device/hwmon/hwmon3/power1_average", "87000000\n");
+    write("card1/device/hwmon/hwmon3/power1_cap", "303000000\n");
+    write("card1/device/hwmon/hwmon3/temp1_input", "61000\n");
+    write("card1-DP-1/device/vendor", "0x1002\n");
+    write("card0/device/vendor", "0x8086\n");
+
+    let gpus = read_drm_gpus(root.path(), 0x1002);
+    assert_eq!(
+        gpus,
+        vec![GpuReading {
+            vendor_id: 0x1002,
+            device_id: 0x744c,
+            usage: 42,
+            mem_usage: 25,
+            power_w: 87,
+            temp_c: 61,
+            memsize_gb: 23,
+            powerlimit_w: 303,
+        }]
+    );
+
+    let (info, total_memory) = to_devices_info(&gpus, common::EngineType::Llama);
+    assert_eq!(info.num, 1);
+    assert_eq!(total_memory, 23);
+    assert_eq!(common::get_u16_from_u128(info.vendor_id, 0), 0x1002);
+}
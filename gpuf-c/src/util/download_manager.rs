@@ -0,0 +1,357 @@
+//! Queue of model downloads shared by the process. Jobs run a few at a time
+//! under one bandwidth budget and report progress as events. The queue is
+//! saved to disk, so jobs still pending when the process exits continue
+//! after a restart.
+
+use crate::util::download_policy::{DownloadPolicy, RateLimiter};
+use crate::util::model_downloader::{
+    DownloadConfig, DownloadHandle, DownloadProgress, ModelDownloader,
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, Semaphore};
+use tokio::time::Duration;
+use tracing::{error, info, warn};
+
+/// Attempts per job before it is dropped from the queue
+const MAX_ATTEMPTS: u32 = 10;
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+/// Events a subscriber may fall behind by before it misses some
+const EVENT_CAPACITY: usize = 256;
+
+/// A queued download, saved with the queue
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadJob {
+    /// Unique within the queue, e.g. the model name
+    pub id: String,
+    pub url: String,
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    pub output_path: PathBuf,
+    pub expected_size: Option<u64>,
+    pub checksum: Option<String>,
+    #[serde(default)]
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Earlier version to update by delta, see `model_delta`
+    #[serde(default)]
+    pub base_path: Option<PathBuf>,
+    #[serde(default)]
+    pub chunk_manifest_url: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum DownloadEvent {
+    Progress {
+        id: String,
+        progress: DownloadProgress,
+    },
+    Completed {
+        id: String,
+        output_path: PathBuf,
+    },
+    /// The job gave up after `MAX_ATTEMPTS` and left the queue
+    Failed {
+        id: String,
+        error: String,
+    },
+    Cancelled {
+        id: String,
+    },
+}
+
+impl DownloadEvent {
+    pub fn id(&self) -> &str {
+        match self {
+            Self::Progress { id, .. }
+            | Self::Completed { id, .. }
+            | Self::Failed { id, .. }
+            | Self::Cancelled { id } => id,
+        }
+    }
+}
+
+struct QueuedJob {
+    job: DownloadJob,
+    /// Set once the job's task is started
+    handle: Option<DownloadHandle>,
+}
+
+struct Shared {
+    state_path: PathBuf,
+    policy: DownloadPolicy,
+    slots: Semaphore,
+    bandwidth: Arc<RateLimiter>,
+    jobs: Mutex<Vec<QueuedJob>>,
+    events: broadcast::Sender<DownloadEvent>,
+}
+
+impl Shared {
+    /// Write the queue to `state_path`. A queue that fails to save still
+    /// runs, it just does not survive a restart.
+    fn save(&self) {
+        let jobs: Vec<DownloadJob> = self
+            .jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|queued| queued.job.clone())
+            .collect();
+        let result = serde_json::to_string_pretty(&jobs)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| {
+                if let Some(parent) = self.state_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                // Write a sibling file first so a crash never leaves half a queue
+                let tmp = self.state_path.with_extension("json.tmp");
+                std::fs::write(&tmp, json)?;
+                std::fs::rename(&tmp, &self.state_path)?;
+                Ok(())
+            });
+        if let Err(e) = result {
+            warn!(
+                "Failed to save download queue to {:?}: {}",
+                self.state_path, e
+            );
+        }
+    }
+
+    fn finish(&self, id: &str, event: DownloadEvent) {
+        self.jobs
+            .lock()
+            .unwrap()
+            .retain(|queued| queued.job.id != id);
+        self.save();
+        let _ = self.events.send(event);
+    }
+}
+
+/// Runs queued model downloads; clones share the same queue
+#[derive(Clone)]
+pub struct DownloadManager {
+    shared: Arc<Shared>,
+}
+
+impl DownloadManager {
+    /// Load the queue saved at `state_path`. Nothing runs until
+    /// `resume_pending` or `enqueue`. At most `max_concurrent` jobs download
+    /// at once and all of them share `policy.max_bytes_per_sec`.
+    pub fn open(
+        state_path: impl Into<PathBuf>,
+        max_concurrent: usize,
+        policy: DownloadPolicy,
+    ) -> Self {
+        let state_path = state_path.into();
+        let jobs = match std::fs::read_to_string(&state_path) {
+            Ok(json) => serde_json::from_str::<Vec<DownloadJob>>(&json).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable download queue {:?}: {}", state_path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        if !jobs.is_empty() {
+            info!(
+                "Loaded {} pending downloads from {:?}",
+                jobs.len(),
+                state_path
+            );
+        }
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            shared: Arc::new(Shared {
+                state_path,
+                policy,
+                slots: Semaphore::new(max_concurrent.max(1)),
+                bandwidth: Arc::new(RateLimiter::new(policy.max_bytes_per_sec)),
+                jobs: Mutex::new(
+                    jobs.into_iter()
+                        .map(|job| QueuedJob { job, handle: None })
+                        .collect(),
+                ),
+                events,
+            }),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DownloadEvent> {
+        self.shared.events.subscribe()
+    }
+
+    /// Jobs not finished yet, in queue order
+    pub fn pending(&self) -> Vec<DownloadJob> {
+        self.shared
+            .jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|queued| queued.job.clone())
+            .collect()
+    }
+
+    /// Queue `job` and start it once a slot is free. Returns false when a job
+    /// with the same id is already queued.
+    pub fn enqueue(&self, job: DownloadJob) -> bool {
+        {
+            let mut jobs = self.shared.jobs.lock().unwrap();
+            if jobs.iter().any(|queued| queued.job.id == job.id) {
+                return false;
+            }
+            info!("Queued download {} from {}", job.id, job.url);
+            jobs.push(QueuedJob { job, handle: None });
+        }
+        self.shared.save();
+        self.resume_pending();
+        true
+    }
+
+    /// Start the queued jobs that are not running yet, e.g. those loaded by
+    /// `open`. Must be called within a Tokio runtime.
+    pub fn resume_pending(&self) {
+        let mut jobs = self.shared.jobs.lock().unwrap();
+        for queued in jobs.iter_mut().filter(|queued| queued.handle.is_none()) {
+            let job = &queued.job;
+            let mut downloader = ModelDownloader::with_rate_limiter(
+                DownloadConfig {
+                    url: job.url.clone(),
+                    mirrors: job.mirrors.clone(),
+                    output_path: job.output_path.clone(),
+                    expected_size: job.expected_size,
+                    checksum: job.checksum.clone(),
+                    signature: job.signature.clone(),
+                    version: job.version.clone(),
+                    base_path: job.base_path.clone(),
+                    chunk_manifest_url: job.chunk_manifest_url.clone(),
+                    policy: self.shared.policy,
+                    ..Default::default()
+                },
+                self.shared.bandwidth.clone(),
+            );
+            downloader.set_progress_callback({
+                let events = self.shared.events.clone();
+                let id = job.id.clone();
+                move |progress| {
+                    let _ = events.send(DownloadEvent::Progress {
+                        id: id.clone(),
+                        progress,
+                    });
+                }
+            });
+            queued.handle = Some(downloader.handle());
+            tokio::spawn(run_job(self.shared.clone(), job.clone(), downloader));
+        }
+    }
+
+    /// Cancel a job and delete its partial download. Returns false when no
+    /// job with this id is queued.
+    pub fn cancel(&self, id: &str) -> bool {
+        let handle = {
+            let jobs = self.shared.jobs.lock().unwrap();
+            match jobs.iter().find(|queued| queued.job.id == id) {
+                Some(queued) => queued.handle.clone(),
+                None => return false,
+            }
+        };
+        match handle {
+            Some(handle) => handle.cancel(),
+            // Not started yet, so no task reports it
+            None => self
+                .shared
+                .finish(id, DownloadEvent::Cancelled { id: id.to_string() }),
+        }
+        true
+    }
+}
+
+async fn run_job(shared: Arc<Shared>, job: DownloadJob, downloader: ModelDownloader) {
+    let handle = downloader.handle();
+    // A job cancelled while it waits goes straight through and fails below
+    let _slot = tokio::select! {
+        slot = shared.slots.acquire() => slot.ok(),
+        _ = handle.cancelled() => None,
+    };
+
+    let mut attempt = 1;
+    let event = loop {
+        match downloader.download().await {
+            Ok(()) => {
+                info!("Download {} completed", job.id);
+                break DownloadEvent::Completed {
+                    id: job.id.clone(),
+                    output_path: job.output_path.clone(),
+                };
+            }
+            Err(_) if handle.is_cancelled() => {
+                info!("Download {} cancelled", job.id);
+                break DownloadEvent::Cancelled { id: job.id.clone() };
+            }
+            Err(e) if attempt >= MAX_ATTEMPTS => {
+                error!(
+                    "Download {} failed after {} attempts: {}",
+                    job.id, MAX_ATTEMPTS, e
+                );
+                break DownloadEvent::Failed {
+                    id: job.id.clone(),
+                    error: e.to_string(),
+                };
+            }
+            Err(e) => {
+                warn!(
+                    "Download {} attempt {}/{} failed: {}",
+                    job.id, attempt, MAX_ATTEMPTS, e
+                );
+                attempt += 1;
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_DELAY) => {}
+                    _ = handle.cancelled() => {}
+                }
+            }
+        }
+    };
+    shared.finish(&job.id, event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_queue_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("downloads.json");
+        let job = DownloadJob {
+            id: "tiny.gguf".to_string(),
+            url: "http://127.0.0.1:9/tiny.gguf".to_string(),
+            mirrors: Vec::new(),
+            output_path: dir.path().join("tiny.gguf"),
+            expected_size: Some(1024),
+            checksum: None,
+            signature: None,
+            version: None,
+            base_path: None,
+            chunk_manifest_url: None,
+        };
+
+        let manager = DownloadManager::open(&state_path, 2, DownloadPolicy::default());
+        let mut events = manager.subscribe();
+        assert!(manager.enqueue(job.clone()));
+        assert!(!manager.enqueue(job.clone()));
+
+        let reopened = DownloadManager::open(&state_path, 2, DownloadPolicy::default());
+        assert_eq!(reopened.pending(), vec![job.clone()]);
+
+        // Cancelled before its task first runs, so nothing is fetched
+        assert!(manager.cancel(&job.id));
+        assert!(!manager.cancel("missing"));
+        match events.recv().await.unwrap() {
+            DownloadEvent::Cancelled { id } => assert_eq!(id, job.id),
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(manager.pending().is_empty());
+
+        let reopened = DownloadManager::open(&state_path, 2, DownloadPolicy::default());
+        assert!(reopened.pending().is_empty());
+    }
+}
@@ -0,0 +1,332 @@
+//! Limits on background model downloads: a bandwidth cap and when downloads
+//! may run at all. The worker takes them from `Args`; mobile apps set them
+//! through `set_remote_worker_download_policy` and report metered networks
+//! through `set_remote_worker_network_metered`. A `DownloadGate` applies them
+//! to one download, together with pauses and cancellation asked for by the
+//! user.
+
+use crate::util::cmd::Args;
+use anyhow::{anyhow, Result};
+use chrono::Timelike;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::Notify;
+use tokio::time::{Duration, Instant};
+use tracing::info;
+
+/// How often a paused download checks whether it may continue
+const SCHEDULE_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Local hours in which downloads may run, `end_hour` exclusive. Wraps past
+/// midnight when `end_hour` is before `start_hour`, e.g. `23-6`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadWindow {
+    pub start_hour: u8,
+    pub end_hour: u8,
+}
+
+impl DownloadWindow {
+    pub fn new(start_hour: u8, end_hour: u8) -> Result<Self, String> {
+        if start_hour > 23 || end_hour > 24 || start_hour == end_hour {
+            return Err(format!(
+                "Invalid download window {}-{}. Hours must be 0-24 and differ",
+                start_hour, end_hour
+            ));
+        }
+        Ok(Self {
+            start_hour,
+            end_hour,
+        })
+    }
+
+    pub fn contains(&self, hour: u8) -> bool {
+        if self.start_hour < self.end_hour {
+            (self.start_hour..self.end_hour).contains(&hour)
+        } else {
+            hour >= self.start_hour || hour < self.end_hour
+        }
+    }
+}
+
+impl FromStr for DownloadWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_hour = |h: &str| {
+            h.trim()
+                .parse::<u8>()
+                .map_err(|_| format!("Invalid download window '{}'. Expected e.g. '1-6'", s))
+        };
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid download window '{}'. Expected e.g. '1-6'", s))?;
+        Self::new(parse_hour(start)?, parse_hour(end)?)
+    }
+}
+
+/// Bandwidth cap and schedule of model downloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DownloadPolicy {
+    /// Unlimited when `None`
+    pub max_bytes_per_sec: Option<u64>,
+    /// Wait while the network is reported as metered
+    pub unmetered_only: bool,
+    /// Any hour when `None`
+    pub window: Option<DownloadWindow>,
+}
+
+impl DownloadPolicy {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            max_bytes_per_sec: args.download_max_rate.filter(|&rate| rate > 0),
+            unmetered_only: args.download_unmetered_only,
+            window: args.download_window,
+        }
+    }
+
+    /// Why a download has to wait at local `hour`, `None` when it may run
+    pub fn blocked_reason(&self, hour: u8, metered: bool) -> Option<String> {
+        if self.unmetered_only && metered {
+            return Some("network is metered".to_string());
+        }
+        match self.window {
+            Some(window) if !window.contains(hour) => Some(format!(
+                "outside download window {}:00-{}:00",
+                window.start_hour, window.end_hour
+            )),
+            _ => None,
+        }
+    }
+}
+
+static POLICY: OnceLock<Mutex<DownloadPolicy>> = OnceLock::new();
+static NETWORK_METERED: AtomicBool = AtomicBool::new(false);
+
+/// Policy of downloads started from now on
+pub fn set_policy(policy: DownloadPolicy) {
+    *POLICY
+        .get_or_init(|| Mutex::new(DownloadPolicy::default()))
+        .lock()
+        .unwrap() = policy;
+}
+
+pub fn policy() -> DownloadPolicy {
+    POLICY.get().map(|p| *p.lock().unwrap()).unwrap_or_default()
+}
+
+/// Reported by the host app; desktop workers are never metered
+pub fn set_network_metered(metered: bool) {
+    NETWORK_METERED.store(metered, Ordering::Relaxed);
+}
+
+pub fn is_network_metered() -> bool {
+    NETWORK_METERED.load(Ordering::Relaxed)
+}
+
+/// Bandwidth cap, shared by everything drawing from the same budget
+pub struct RateLimiter {
+    /// Unlimited when `None`
+    bytes_per_sec: Option<u64>,
+    /// When the bytes received so far are paid off at the capped rate
+    next_free: tokio::sync::Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.filter(|&rate| rate > 0),
+            next_free: tokio::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Account for `bytes` just received, sleeping as long as the cap needs.
+    /// Not reading the socket meanwhile slows the sender down as well.
+    pub async fn throttle(&self, bytes: usize) {
+        let Some(rate) = self.bytes_per_sec else {
+            return;
+        };
+        let until = {
+            let mut next_free = self.next_free.lock().await;
+            let cost = Duration::from_secs_f64(bytes as f64 / rate as f64);
+            *next_free = (*next_free).max(Instant::now()) + cost;
+            *next_free
+        };
+        tokio::time::sleep_until(until).await;
+    }
+}
+
+/// Applies a policy to one download. The bandwidth cap is shared by all of
+/// its parallel chunks, or by several downloads with `with_rate_limiter`.
+pub struct DownloadGate {
+    policy: DownloadPolicy,
+    limiter: Arc<RateLimiter>,
+    paused: AtomicBool,
+    cancelled: AtomicBool,
+    /// Wakes `wait_until_open` on pause, resume and cancel
+    changed: Notify,
+}
+
+impl DownloadGate {
+    pub fn new(policy: DownloadPolicy) -> Self {
+        Self::with_rate_limiter(policy, Arc::new(RateLimiter::new(policy.max_bytes_per_sec)))
+    }
+
+    /// Gate whose bandwidth comes out of `limiter` instead of
+    /// `policy.max_bytes_per_sec`
+    pub fn with_rate_limiter(policy: DownloadPolicy, limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            policy,
+            limiter,
+            paused: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            changed: Notify::new(),
+        }
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+        self.changed.notify_waiters();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.changed.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Resolves once the download is cancelled
+    pub async fn cancelled(&self) {
+        loop {
+            let changed = self.changed.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    /// Why the download has to stop right now, `None` when it may run
+    pub fn blocked_reason(&self) -> Option<String> {
+        if self.is_cancelled() {
+            return Some("cancelled".to_string());
+        }
+        if self.is_paused() {
+            return Some("paused by user".to_string());
+        }
+        let hour = chrono::Local::now().hour() as u8;
+        self.policy.blocked_reason(hour, is_network_metered())
+    }
+
+    /// Wait until the download may run. Fails once it is cancelled.
+    pub async fn wait_until_open(&self) -> Result<()> {
+        let mut logged = false;
+        loop {
+            let changed = self.changed.notified();
+            let Some(reason) = self.blocked_reason() else {
+                break;
+            };
+            if self.is_cancelled() {
+                return Err(anyhow!("Download cancelled"));
+            }
+            if !logged {
+                info!("Model download paused: {}", reason);
+                logged = true;
+            }
+            if self.is_paused() {
+                changed.await;
+            } else {
+                let _ = tokio::time::timeout(SCHEDULE_POLL_INTERVAL, changed).await;
+            }
+        }
+        if logged {
+            info!("Model download resumed");
+        }
+        Ok(())
+    }
+
+    pub async fn throttle(&self, bytes: usize) {
+        self.limiter.throttle(bytes).await;
+    }
+}
+
+#[test]
+fn test_download_window() {
+    let night: DownloadWindow = "23-6".parse().unwrap();
+    assert!(night.contains(23));
+    assert!(night.contains(0));
+    assert!(night.contains(5));
+    assert!(!night.contains(6));
+    assert!(!night.contains(12));
+
+    let day: DownloadWindow = "9-24".parse().unwrap();
+    assert!(day.contains(9));
+    assert!(day.contains(23));
+    assert!(!day.contains(8));
+
+    assert!("6-6".parse::<DownloadWindow>().is_err());
+    assert!("25-3".parse::<DownloadWindow>().is_err());
+    assert!("night".parse::<DownloadWindow>().is_err());
+}
+
+#[test]
+fn test_blocked_reason() {
+    let policy = DownloadPolicy {
+        max_bytes_per_sec: None,
+        unmetered_only: true,
+        window: Some(DownloadWindow::new(1, 6).unwrap()),
+    };
+    assert_eq!(policy.blocked_reason(3, false), None);
+    assert!(policy.blocked_reason(3, true).is_some());
+    assert!(policy.blocked_reason(12, false).is_some());
+    assert_eq!(DownloadPolicy::default().blocked_reason(12, true), None);
+}
+
+#[tokio::test]
+async fn test_throttle() {
+    let gate = DownloadGate::new(DownloadPolicy {
+        max_bytes_per_sec: Some(1_000_000),
+        ..Default::default()
+    });
+    let start = Instant::now();
+    gate.throttle(100_000).await;
+    gate.throttle(100_000).await;
+    assert!(start.elapsed() >= Duration::from_millis(190));
+
+    let unlimited = DownloadGate::new(DownloadPolicy::default());
+    let start = Instant::now();
+    unlimited.throttle(100_000_000).await;
+    assert!(start.elapsed() < Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn test_pause_and_cancel() {
+    let gate = std::sync::Arc::new(DownloadGate::new(DownloadPolicy::default()));
+    assert!(gate.wait_until_open().await.is_ok());
+
+    gate.set_paused(true);
+    assert!(gate.blocked_reason().is_some());
+    let waiter = tokio::spawn({
+        let gate = gate.clone();
+        async move { gate.wait_until_open().await }
+    });
+    tokio::task::yield_now().await;
+    gate.set_paused(false);
+    assert!(waiter.await.unwrap().is_ok());
+
+    gate.set_paused(true);
+    let waiter = tokio::spawn({
+        let gate = gate.clone();
+        async move { gate.wait_until_open().await }
+    });
+    tokio::task::yield_now().await;
+    gate.cancel();
+    assert!(waiter.await.unwrap().is_err());
+}
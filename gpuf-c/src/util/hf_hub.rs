@@ -0,0 +1,246 @@
+//! Model files on the Hugging Face Hub, given to the model downloader as
+//! `hf://<owner>/<repo>/<filename>[@<revision>]` instead of a resolved URL.
+//! Gated repos need a token, set with `set_token` or `HF_TOKEN`. A split
+//! GGUF model (`-00001-of-00003.gguf`) is named by any of its shards and is
+//! downloaded whole.
+
+use anyhow::{anyhow, Result};
+use reqwest::{header, StatusCode};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use tokio::time::Duration;
+
+use crate::util::proxy;
+
+pub const SCHEME: &str = "hf://";
+const DEFAULT_ENDPOINT: &str = "https://huggingface.co";
+const DEFAULT_REVISION: &str = "main";
+
+/// A file in a Hub repo at a given revision
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HfFile {
+    /// `owner/repo`
+    pub repo_id: String,
+    /// Path within the repo
+    pub filename: String,
+    /// Branch, tag or commit hash
+    pub revision: String,
+}
+
+impl HfFile {
+    /// Download URL on `endpoint`, e.g. `https://huggingface.co`
+    pub fn url(&self, endpoint: &str) -> String {
+        format!(
+            "{}/{}/resolve/{}/{}",
+            endpoint.trim_end_matches('/'),
+            self.repo_id,
+            self.revision.replace('/', "%2F"),
+            self.filename
+        )
+    }
+
+    /// All shards of a split file in order, or just this file
+    pub fn shards(&self) -> Vec<HfFile> {
+        split_shards(&self.filename)
+            .into_iter()
+            .map(|filename| HfFile {
+                filename,
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+impl FromStr for HfFile {
+    type Err = anyhow::Error;
+
+    /// Accepts `owner/repo/filename@revision`, with or without `hf://`
+    fn from_str(s: &str) -> Result<Self> {
+        let spec = s.strip_prefix(SCHEME).unwrap_or(s);
+        let (path, revision) = match spec.split_once('@') {
+            Some((path, revision)) => (path, revision),
+            None => (spec, DEFAULT_REVISION),
+        };
+        let mut parts = path.splitn(3, '/');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(owner), Some(repo), Some(filename))
+                if !owner.is_empty()
+                    && !repo.is_empty()
+                    && !filename.is_empty()
+                    && !revision.is_empty() =>
+            {
+                Ok(Self {
+                    repo_id: format!("{}/{}", owner, repo),
+                    filename: filename.to_string(),
+                    revision: revision.to_string(),
+                })
+            }
+            _ => Err(anyhow!(
+                "Invalid Hugging Face file '{}'. Expected owner/repo/filename[@revision]",
+                s
+            )),
+        }
+    }
+}
+
+/// Hub to download from, `HF_ENDPOINT` when set
+pub fn endpoint() -> String {
+    std::env::var("HF_ENDPOINT")
+        .ok()
+        .filter(|e| !e.is_empty())
+        .unwrap_or_else(|| DEFAULT_ENDPOINT.to_string())
+}
+
+static TOKEN: OnceLock<Mutex<Option<String>>> = OnceLock::new();
+
+/// Token of downloads started from now on
+pub fn set_token(token: Option<String>) {
+    *TOKEN.get_or_init(|| Mutex::new(None)).lock().unwrap() =
+        token.filter(|token| !token.is_empty());
+}
+
+/// Token set by `set_token`, else `HF_TOKEN` from the environment
+pub fn token() -> Option<String> {
+    TOKEN
+        .get()
+        .and_then(|token| token.lock().unwrap().clone())
+        .or_else(|| std::env::var("HF_TOKEN").ok().filter(|t| !t.is_empty()))
+}
+
+/// Every shard of the split GGUF file `name` in order, given any of them,
+/// or just `name` when it is not split
+pub fn split_shards(name: &str) -> Vec<String> {
+    let split = name.strip_suffix(".gguf").and_then(|stem| {
+        let (rest, count) = stem.rsplit_once("-of-")?;
+        let (prefix, index) = rest.rsplit_once('-')?;
+        if index.len() != 5 || count.len() != 5 {
+            return None;
+        }
+        let index: u32 = index.parse().ok()?;
+        let total: u32 = count.parse().ok()?;
+        (1..=total)
+            .contains(&index)
+            .then(|| (prefix.to_string(), count.to_string(), total))
+    });
+    match split {
+        Some((prefix, count, total)) => (1..=total)
+            .map(|i| format!("{}-{:05}-of-{}.gguf", prefix, i, count))
+            .collect(),
+        None => vec![name.to_string()],
+    }
+}
+
+/// What the Hub reports about a file without downloading it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HfFileInfo {
+    /// Changes whenever the file content does
+    pub etag: Option<String>,
+    pub size: Option<u64>,
+}
+
+/// Look up a file at its resolve `url`
+pub async fn file_info(url: &str, token: Option<&str>) -> Result<HfFileInfo> {
+    // Files stored in LFS redirect to a CDN; the Hub's own answer carries the
+    // content hash as X-Linked-Etag, which the CDN's ETag does not match
+    let client = proxy::http_client()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(Duration::from_secs(30))
+        .build()?;
+    let mut request = client.head(url);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await?;
+    match response.status() {
+        status if status.is_success() || status.is_redirection() => {}
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            return Err(anyhow!(
+                "Access to {} denied ({}). Gated models need a Hugging Face token with access, see --hugging-face-hub-token",
+                url,
+                response.status()
+            ));
+        }
+        StatusCode::NOT_FOUND => return Err(anyhow!("{} not found on the Hub", url)),
+        status => return Err(anyhow!("Hub lookup of {} failed: {}", url, status)),
+    }
+
+    let headers = response.headers();
+    let text = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    let etag = text("x-linked-etag")
+        .or_else(|| text(header::ETAG.as_str()))
+        .map(|etag| etag.trim_start_matches("W/").trim_matches('"').to_string());
+    let size = text("x-linked-size")
+        .or_else(|| text(header::CONTENT_LENGTH.as_str()))
+        .and_then(|size| size.parse().ok())
+        .filter(|&size| size > 0);
+    Ok(HfFileInfo { etag, size })
+}
+
+/// Sidecar file holding the ETag `path` was downloaded at
+fn etag_path(path: &Path) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(".etag");
+    PathBuf::from(p)
+}
+
+pub async fn cached_etag(path: &Path) -> Option<String> {
+    tokio::fs::read_to_string(etag_path(path))
+        .await
+        .ok()
+        .map(|etag| etag.trim().to_string())
+}
+
+pub async fn save_etag(path: &Path, etag: &str) -> Result<()> {
+    tokio::fs::write(etag_path(path), etag).await?;
+    Ok(())
+}
+
+pub async fn remove_etag(path: &Path) {
+    let _ = tokio::fs::remove_file(etag_path(path)).await;
+}
+
+#[test]
+fn test_parse_hf_file() {
+    let file: HfFile = "hf://TheBloke/TinyLlama-GGUF/q4/tiny.gguf@v1.0"
+        .parse()
+        .unwrap();
+    assert_eq!(file.repo_id, "TheBloke/TinyLlama-GGUF");
+    assert_eq!(file.filename, "q4/tiny.gguf");
+    assert_eq!(
+        file.url("https://huggingface.co/"),
+        "https://huggingface.co/TheBloke/TinyLlama-GGUF/resolve/v1.0/q4/tiny.gguf"
+    );
+
+    let file: HfFile = "org/repo/model.gguf".parse().unwrap();
+    assert_eq!(file.revision, "main");
+    let file: HfFile = "org/repo/model.gguf@refs/pr/1".parse().unwrap();
+    assert!(file
+        .url(DEFAULT_ENDPOINT)
+        .contains("/resolve/refs%2Fpr%2F1/"));
+
+    assert!("org/model.gguf".parse::<HfFile>().is_err());
+    assert!("org/repo/model.gguf@".parse::<HfFile>().is_err());
+}
+
+#[test]
+fn test_split_shards() {
+    assert_eq!(
+        split_shards("q4/llama-70b-00002-of-00003.gguf"),
+        vec![
+            "q4/llama-70b-00001-of-00003.gguf",
+            "q4/llama-70b-00002-of-00003.gguf",
+            "q4/llama-70b-00003-of-00003.gguf",
+        ]
+    );
+    assert_eq!(split_shards("llama-70b.gguf"), vec!["llama-70b.gguf"]);
+    assert_eq!(
+        split_shards("llama-00004-of-00003.gguf"),
+        vec!["llama-00004-of-00003.gguf"]
+    );
+}
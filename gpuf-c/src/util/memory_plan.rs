@@ -0,0 +1,343 @@
+//! Memory a llama.cpp model takes with a configuration, and the configuration
+//! it is loaded with. Weights and KV cache of offloaded layers go to VRAM with
+//! the compute buffers, the rest stays in RAM. A configuration that does not
+//! fit is shrunk before loading, as running out of memory kills the process,
+//! and on phones the app with it.
+
+use crate::util::model_fit::{self, KvShape};
+use crate::util::system_info;
+use anyhow::{anyhow, Result};
+use common::format_bytes;
+use std::io::Read;
+use std::path::Path;
+use sysinfo::System;
+
+/// Tokens llama.cpp evaluates at once by default, which sizes its compute
+/// buffers
+pub const DEFAULT_N_BATCH: u32 = 512;
+/// Smallest context a configuration is shrunk to
+const MIN_N_CTX: u32 = 512;
+/// Most parallel slots picked when the configuration leaves it to the plan
+pub const MAX_AUTO_SEQ: u32 = 4;
+/// Runtime, scratch space and what else the estimate misses
+const RAM_OVERHEAD_BYTES: u64 = 256 * 1024 * 1024;
+/// GPU context and allocator slack
+const VRAM_OVERHEAD_BYTES: u64 = 256 * 1024 * 1024;
+/// Share of free memory left to the rest of the system
+const HEADROOM_PERCENT: u64 = 10;
+/// Compute buffers hold f32
+const COMPUTE_ELEMENT_BYTES: u64 = 4;
+
+/// Settings deciding how much memory a model takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryConfig {
+    /// 0 for the model's trained context
+    pub n_ctx: u32,
+    pub n_gpu_layers: u32,
+    pub n_batch: u32,
+    /// Parallel slots, each a sequence with a context of `n_ctx`. 0 asks for
+    /// as many as fit, up to [`MAX_AUTO_SEQ`].
+    pub n_seq: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreeMemory {
+    pub ram_bytes: u64,
+    /// None when no GPU reports it, offloaded layers are not checked then
+    pub vram_bytes: Option<u64>,
+    /// GPU and CPU share the RAM, as on Apple silicon and phones
+    pub unified: bool,
+}
+
+impl FreeMemory {
+    pub fn current() -> Self {
+        let mut sys = System::new();
+        sys.refresh_memory();
+        let unified = cfg!(any(
+            target_os = "macos",
+            target_os = "android",
+            target_os = "ios"
+        ));
+        let vram_bytes = system_info::free_vram_bytes();
+        Self {
+            ram_bytes: sys.available_memory(),
+            vram_bytes: (!unified && vram_bytes > 0).then_some(vram_bytes),
+            unified,
+        }
+    }
+
+    fn fits(&self, vram_bytes: u64, ram_bytes: u64) -> bool {
+        let usable = |free: u64| free - free / 100 * HEADROOM_PERCENT;
+        if self.unified {
+            return vram_bytes.saturating_add(ram_bytes) <= usable(self.ram_bytes);
+        }
+        ram_bytes <= usable(self.ram_bytes)
+            && self
+                .vram_bytes
+                .map_or(true, |free| vram_bytes <= usable(free))
+    }
+}
+
+/// Configuration a model is loaded with and the memory it is expected to take
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryPlan {
+    pub config: MemoryConfig,
+    pub vram_bytes: u64,
+    pub ram_bytes: u64,
+    pub free: FreeMemory,
+    /// The requested configuration did not fit
+    pub shrunk: bool,
+}
+
+impl std::fmt::Display for MemoryPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "n_ctx {}, {} slots, {} GPU layers: {} VRAM and {} RAM of {} and {} free",
+            self.config.n_ctx,
+            self.config.n_seq,
+            self.config.n_gpu_layers,
+            format_bytes!(self.vram_bytes),
+            format_bytes!(self.ram_bytes),
+            self.free
+                .vram_bytes
+                .map_or_else(|| "unknown".to_string(), |free| format_bytes!(free)),
+            format_bytes!(self.free.ram_bytes)
+        )
+    }
+}
+
+/// VRAM and RAM a model of `model_bytes` with attention `shape` takes with
+/// `config`. Without the shape the layer count is unknown, and offloading
+/// any layer counts as offloading all.
+pub fn estimate(model_bytes: u64, shape: Option<&KvShape>, config: MemoryConfig) -> (u64, u64) {
+    let n_layer = shape.map_or(1, |shape| shape.n_layer.max(1));
+    let offloaded = u64::from(config.n_gpu_layers).min(n_layer);
+    // Slots share one context holding all their sequences
+    let n_ctx = u64::from(config.n_ctx) * u64::from(config.n_seq.max(1));
+    let kv_cache_bytes = shape.map_or(0, |shape| shape.kv_cache_bytes(n_ctx));
+    // Attention scores of a batch against the whole context, and activations
+    let compute_bytes = shape.map_or(0, |shape| {
+        let n_ctx = if n_ctx == 0 {
+            shape.context_length * u64::from(config.n_seq.max(1))
+        } else {
+            n_ctx
+        };
+        u64::from(config.n_batch)
+            .saturating_mul(
+                n_ctx
+                    .saturating_mul(shape.n_head)
+                    .saturating_add(4 * shape.n_embd),
+            )
+            .saturating_mul(COMPUTE_ELEMENT_BYTES)
+    });
+    let offloaded_share = |bytes: u64| (bytes as u128 * offloaded as u128 / n_layer as u128) as u64;
+
+    let gpu_bytes = offloaded_share(model_bytes) + offloaded_share(kv_cache_bytes);
+    let cpu_bytes = model_bytes + kv_cache_bytes - gpu_bytes + RAM_OVERHEAD_BYTES;
+    if offloaded > 0 {
+        (gpu_bytes + compute_bytes + VRAM_OVERHEAD_BYTES, cpu_bytes)
+    } else {
+        (0, cpu_bytes + compute_bytes)
+    }
+}
+
+/// `requested` when it fits into `free`, otherwise the same with fewer slots,
+/// then fewer offloaded layers, and failing that with the context halved
+/// until one fits. Slots and layers go first as they only cost throughput
+/// and speed, while a shorter context rejects long prompts. Fails when the
+/// smallest context does not fit.
+pub fn plan(
+    model_bytes: u64,
+    shape: Option<&KvShape>,
+    requested: MemoryConfig,
+    free: FreeMemory,
+) -> Result<MemoryPlan> {
+    let n_layer = shape.map_or(1, |shape| shape.n_layer.max(1));
+    let mut n_ctx = match (requested.n_ctx, shape) {
+        (0, Some(shape)) => u32::try_from(shape.context_length).unwrap_or(u32::MAX),
+        (n_ctx, _) => n_ctx,
+    };
+    let auto_seq = requested.n_seq == 0;
+    loop {
+        let mut n_gpu_layers = requested.n_gpu_layers;
+        loop {
+            let mut n_seq = if auto_seq {
+                MAX_AUTO_SEQ
+            } else {
+                requested.n_seq
+            };
+            loop {
+                let config = MemoryConfig {
+                    n_ctx,
+                    n_gpu_layers,
+                    n_batch: requested.n_batch,
+                    n_seq,
+                };
+                let (vram_bytes, ram_bytes) = estimate(model_bytes, shape, config);
+                if free.fits(vram_bytes, ram_bytes) {
+                    return Ok(MemoryPlan {
+                        config,
+                        vram_bytes,
+                        ram_bytes,
+                        free,
+                        shrunk: n_ctx != requested.n_ctx
+                            || n_gpu_layers != requested.n_gpu_layers
+                            || (!auto_seq && n_seq != requested.n_seq),
+                    });
+                }
+                if n_seq <= 1 {
+                    break;
+                }
+                n_seq -= 1;
+            }
+            // Layers on the CPU free no memory the GPU shares
+            if n_gpu_layers == 0 || free.unified {
+                break;
+            }
+            n_gpu_layers = (u64::from(n_gpu_layers).min(n_layer) - 1) as u32;
+        }
+        if n_ctx <= MIN_N_CTX {
+            break;
+        }
+        n_ctx = (n_ctx / 2).max(MIN_N_CTX);
+    }
+
+    let (vram_bytes, ram_bytes) = estimate(
+        model_bytes,
+        shape,
+        MemoryConfig {
+            n_ctx,
+            n_gpu_layers: 0,
+            n_seq: 1,
+            ..requested
+        },
+    );
+    Err(anyhow!(
+        "Model needs {} with n_ctx {}, but only {} of RAM is free",
+        format_bytes!(vram_bytes + ram_bytes),
+        n_ctx,
+        format_bytes!(free.ram_bytes)
+    ))
+}
+
+/// Plan the model file at `path` against the memory free right now
+pub fn plan_for_file(path: &Path, requested: MemoryConfig) -> Result<MemoryPlan> {
+    let (model_bytes, shape) = read_model(path)?;
+    plan(
+        model_bytes,
+        shape.as_ref(),
+        requested,
+        FreeMemory::current(),
+    )
+}
+
+/// [`estimate`] for the GGUF model at `path`
+pub fn estimate_for_file(path: &Path, config: MemoryConfig) -> Result<(u64, u64)> {
+    let (model_bytes, shape) = read_model(path)?;
+    Ok(estimate(model_bytes, shape.as_ref(), config))
+}
+
+/// Size of the model at `path` and the KV cache shape in its header
+fn read_model(path: &Path) -> Result<(u64, Option<KvShape>)> {
+    let model_bytes = std::fs::metadata(path)?.len();
+    let mut header = Vec::new();
+    std::fs::File::open(path)?
+        .take(model_fit::HEADER_BYTES as u64)
+        .read_to_end(&mut header)?;
+    Ok((model_bytes, model_fit::parse_kv_shape(&header).ok()))
+}
+
+#[test]
+fn test_memory_plan() {
+    let gib = 1024 * 1024 * 1024;
+    // 32 layers, 1 GiB of KV cache at 8192 tokens
+    let shape = KvShape {
+        n_layer: 32,
+        n_embd: 4096,
+        n_head: 32,
+        n_head_kv: 8,
+        context_length: 131072,
+        key_length: None,
+        value_length: None,
+    };
+    let requested = MemoryConfig {
+        n_ctx: 8192,
+        n_gpu_layers: 99,
+        n_batch: DEFAULT_N_BATCH,
+        n_seq: 1,
+    };
+
+    let (vram, ram) = estimate(8 * gib, Some(&shape), requested);
+    assert_eq!(ram, RAM_OVERHEAD_BYTES);
+    assert!(vram > 9 * gib && vram < 10 * gib);
+    let (vram, ram) = estimate(
+        8 * gib,
+        Some(&shape),
+        MemoryConfig {
+            n_gpu_layers: 16,
+            ..requested
+        },
+    );
+    assert!(vram > 4 * gib + gib / 2 && vram < 5 * gib + gib / 2);
+    assert_eq!(ram, 4 * gib + gib / 2 + RAM_OVERHEAD_BYTES);
+
+    let roomy = FreeMemory {
+        ram_bytes: 64 * gib,
+        vram_bytes: Some(24 * gib),
+        unified: false,
+    };
+    let plan_of = |free| plan(8 * gib, Some(&shape), requested, free);
+    let fits = plan_of(roomy).unwrap();
+    assert_eq!(fits.config, requested);
+    assert!(!fits.shrunk);
+
+    // Layers move to the CPU before the context shrinks
+    let small_gpu = plan_of(FreeMemory {
+        vram_bytes: Some(6 * gib),
+        ..roomy
+    })
+    .unwrap();
+    assert!(small_gpu.shrunk);
+    assert_eq!(small_gpu.config.n_ctx, 8192);
+    assert!(small_gpu.config.n_gpu_layers > 0 && small_gpu.config.n_gpu_layers < 32);
+
+    // A phone shares its RAM, the context shrinks
+    let phone = plan_of(FreeMemory {
+        ram_bytes: 11 * gib,
+        vram_bytes: None,
+        unified: true,
+    })
+    .unwrap();
+    assert_eq!(phone.config.n_ctx, 4096);
+    assert_eq!(phone.config.n_gpu_layers, 99);
+
+    assert!(plan_of(FreeMemory {
+        ram_bytes: 4 * gib,
+        vram_bytes: None,
+        unified: true,
+    })
+    .is_err());
+
+    // Each slot adds a context's KV cache, as many as fit are picked
+    let auto = |free| {
+        plan(
+            8 * gib,
+            Some(&shape),
+            MemoryConfig {
+                n_seq: 0,
+                ..requested
+            },
+            free,
+        )
+    };
+    assert_eq!(auto(roomy).unwrap().config.n_seq, MAX_AUTO_SEQ);
+    let slots = auto(FreeMemory {
+        vram_bytes: Some(13 * gib),
+        ..roomy
+    })
+    .unwrap();
+    assert_eq!(slots.config.n_seq, 2);
+    assert_eq!(slots.config.n_gpu_layers, 99);
+    assert!(!slots.shrunk);
+}
@@ -1,20 +1,49 @@
 pub mod asm;
+pub mod benchmark;
 pub mod cmd;
 pub mod config;
 pub mod device_info;
+pub mod download_manager;
+pub mod download_policy;
+pub mod hf_hub;
+pub mod memory_plan;
+pub mod model_cache;
+pub mod model_delta;
 pub mod model_downloader;
 #[cfg(not(target_os = "ios"))]
 pub mod model_downloader_example;
+pub mod model_fit;
+pub mod model_signature;
+pub mod net;
 pub mod network_info;
 pub mod nvswitch_check;
+pub mod object_store;
+pub mod power;
+pub mod proxy;
+pub mod request_limits;
+pub mod runtime_config;
+pub mod service;
 pub mod system_info;
 pub mod system_info_vulkan;
+pub mod tls;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod updater;
+pub mod version_info;
 
+use std::str::FromStr;
 use std::sync::OnceLock;
-use tracing::{debug, Level};
+use cmd::LogFormat;
+use tracing::debug;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Layer};
 
 static LOG_ICONS_UTF8: OnceLock<bool> = OnceLock::new();
 
+type LogLevelReloader = Box<dyn Fn(LevelFilter) -> anyhow::Result<()> + Send + Sync>;
+static LOG_LEVEL_RELOADER: OnceLock<LogLevelReloader> = OnceLock::new();
+
 fn detect_utf8_locale() -> bool {
     for key in ["LC_ALL", "LC_CTYPE", "LANG"] {
         if let Ok(v) = std::env::var(key) {
@@ -40,35 +69,66 @@ pub fn log_icon(unicode: &'static str, ascii: &'static str) -> &'static str {
 }
 
 pub fn init_logging() {
-    // Use DEBUG level for debug builds, INFO for release builds
+    init_logging_with_format(LogFormat::Compact);
+}
 
+/// Install the global subscriber. `json` writes one object per line with the
+/// fields of the current span (request_id, client_id, model, ...) merged in.
+pub fn init_logging_with_format(format: LogFormat) {
     let _ = LOG_ICONS_UTF8.get_or_init(detect_utf8_locale);
 
-    #[cfg(not(debug_assertions))]
-    tracing_subscriber::fmt()
-        .with_max_level(Level::INFO)
-        .with_ansi(!cfg!(windows))
-        .with_target(false)
-        // Debug builds: show thread info, file, and line number
-        .with_thread_ids(false)
-        .with_thread_names(false)
-        .with_file(false)
-        .with_line_number(false)
-        .compact()
-        .init();
+    // Use DEBUG level for debug builds, INFO for release builds
+    let level = if cfg!(debug_assertions) {
+        LevelFilter::DEBUG
+    } else {
+        LevelFilter::INFO
+    };
+    let (filter, handle) = reload::Layer::new(level);
+
+    // Debug builds: show thread info, file, and line number
+    let verbose = cfg!(debug_assertions);
+    let fmt_layer = match format {
+        LogFormat::Compact => fmt::layer()
+            .with_ansi(!cfg!(windows))
+            .with_target(false)
+            .with_thread_ids(verbose)
+            .with_thread_names(verbose)
+            .with_file(verbose)
+            .with_line_number(verbose)
+            .compact()
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_target(false)
+            .with_file(verbose)
+            .with_line_number(verbose)
+            .boxed(),
+    };
 
-    #[cfg(debug_assertions)]
-    tracing_subscriber::fmt()
-        .with_max_level(Level::DEBUG)
-        .with_ansi(!cfg!(windows))
-        .with_target(false)
-        // Debug builds: show thread info, file, and line number
-        .with_thread_ids(true)
-        .with_thread_names(true)
-        .with_file(true)
-        .with_line_number(true)
-        .compact()
-        .init();
+    let subscriber = tracing_subscriber::registry().with(filter).with(fmt_layer);
+    // Spans are exported over OTLP when built with `otel` and OTEL_EXPORTER_OTLP_ENDPOINT is set
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(common::trace::otlp_layer("gpuf-c"));
+    if subscriber.try_init().is_err() {
+        debug!("Logging already initialized");
+        return;
+    }
+    let _ = LOG_LEVEL_RELOADER.get_or_init(|| {
+        Box::new(move |level: LevelFilter| handle.reload(level).map_err(Into::into))
+    });
 
     debug!("Logging initialized");
 }
+
+/// Change the log level at runtime, e.g. `debug` or `warn`
+pub fn set_log_level(level: &str) -> anyhow::Result<()> {
+    let level = LevelFilter::from_str(level)
+        .map_err(|_| anyhow::anyhow!("Invalid log level: {}", level))?;
+    match LOG_LEVEL_RELOADER.get() {
+        Some(reload) => reload(level),
+        None => Err(anyhow::anyhow!("Logging is not initialized")),
+    }
+}
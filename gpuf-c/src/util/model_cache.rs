@@ -0,0 +1,350 @@
+//! Models kept on disk between runs. The cache remembers when each model in
+//! its directory was last used and keeps the directory under a disk quota by
+//! deleting the least recently used models first. Pinned models and the
+//! loaded model are never deleted. Desktop workers cache in `models/` next
+//! to the executable; apps open their own directory with
+//! `gpuf_model_cache_init`.
+
+use crate::util::hf_hub;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Usage and pins, saved in the cache directory
+const STATE_FILE: &str = "cache.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct EntryState {
+    last_used: u64,
+    #[serde(default)]
+    pinned: bool,
+}
+
+/// A model in the cache
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CachedModel {
+    /// File name in the cache directory, the first shard of a split model
+    pub name: String,
+    /// All shards together
+    pub size_bytes: u64,
+    /// Unix seconds of the last load, else of the download
+    pub last_used: u64,
+    pub pinned: bool,
+    pub loaded: bool,
+}
+
+pub struct ModelCache {
+    dir: PathBuf,
+    /// Unlimited when `None`
+    quota_bytes: Mutex<Option<u64>>,
+    state: Mutex<HashMap<String, EntryState>>,
+}
+
+static CACHE: OnceLock<ModelCache> = OnceLock::new();
+
+/// The process-wide cache, opened at `dir` unless it already is open
+pub fn get_or_init(dir: impl Into<PathBuf>, quota_bytes: Option<u64>) -> &'static ModelCache {
+    CACHE.get_or_init(|| ModelCache::open(dir, quota_bytes))
+}
+
+/// Open the process-wide cache at `dir`, or change the quota of the one
+/// already open. Fails when it is already open at another directory.
+pub fn init(dir: impl Into<PathBuf>, quota_bytes: Option<u64>) -> Result<&'static ModelCache> {
+    let dir = dir.into();
+    let cache = get_or_init(dir.clone(), quota_bytes);
+    if cache.dir != dir {
+        return Err(anyhow!("Model cache is already open at {:?}", cache.dir));
+    }
+    cache.set_quota(quota_bytes);
+    Ok(cache)
+}
+
+pub fn global() -> Option<&'static ModelCache> {
+    CACHE.get()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Files of one cached model
+struct Group {
+    name: String,
+    size_bytes: u64,
+    modified: u64,
+}
+
+impl ModelCache {
+    /// Cache of the models in `dir`, keeping them under `quota_bytes`
+    pub fn open(dir: impl Into<PathBuf>, quota_bytes: Option<u64>) -> Self {
+        let dir = dir.into();
+        let state = std::fs::read_to_string(dir.join(STATE_FILE))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self {
+            dir,
+            quota_bytes: Mutex::new(quota_bytes.filter(|&quota| quota > 0)),
+            state: Mutex::new(state),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn quota_bytes(&self) -> Option<u64> {
+        *self.quota_bytes.lock().unwrap()
+    }
+
+    /// Takes effect at the next `make_room`
+    pub fn set_quota(&self, quota_bytes: Option<u64>) {
+        *self.quota_bytes.lock().unwrap() = quota_bytes.filter(|&quota| quota > 0);
+    }
+
+    /// Best effort; a cache that fails to save forgets usage on restart
+    fn save(&self) {
+        let json = match serde_json::to_string_pretty(&*self.state.lock().unwrap()) {
+            Ok(json) => json,
+            Err(e) => return warn!("Failed to encode model cache state: {}", e),
+        };
+        if let Err(e) = std::fs::write(self.dir.join(STATE_FILE), json) {
+            warn!("Failed to save model cache state in {:?}: {}", self.dir, e);
+        }
+    }
+
+    /// GGUF files in the directory, shards of a split model grouped
+    fn scan(&self) -> Vec<Group> {
+        let mut groups: Vec<Group> = Vec::new();
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return groups;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if !meta.is_file() || !file_name.ends_with(".gguf") {
+                continue;
+            }
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let name = hf_hub::split_shards(&file_name).swap_remove(0);
+            match groups.iter_mut().find(|group| group.name == name) {
+                Some(group) => {
+                    group.size_bytes += meta.len();
+                    group.modified = group.modified.max(modified);
+                }
+                None => groups.push(Group {
+                    name,
+                    size_bytes: meta.len(),
+                    modified,
+                }),
+            }
+        }
+        groups
+    }
+
+    /// Cache name of the model currently loaded, if it lives in the cache
+    fn loaded_name(&self) -> Option<String> {
+        let current = crate::MODEL_STATUS.lock().ok()?.current_model.clone()?;
+        let path = Path::new(&current);
+        if path.parent()? != self.dir {
+            return None;
+        }
+        let file_name = path.file_name()?.to_str()?;
+        Some(hf_hub::split_shards(file_name).swap_remove(0))
+    }
+
+    /// Cached models, most recently used first
+    pub fn list(&self) -> Vec<CachedModel> {
+        let loaded = self.loaded_name();
+        let state = self.state.lock().unwrap();
+        let mut models: Vec<CachedModel> = self
+            .scan()
+            .into_iter()
+            .map(|group| {
+                let entry = state.get(&group.name).cloned().unwrap_or_default();
+                CachedModel {
+                    loaded: loaded.as_deref() == Some(group.name.as_str()),
+                    last_used: match entry.last_used {
+                        0 => group.modified,
+                        last_used => last_used,
+                    },
+                    pinned: entry.pinned,
+                    size_bytes: group.size_bytes,
+                    name: group.name,
+                }
+            })
+            .collect();
+        models.sort_by(|a, b| b.last_used.cmp(&a.last_used));
+        models
+    }
+
+    /// Record that the model `name` was just loaded
+    pub fn touch(&self, name: &str) {
+        let name = hf_hub::split_shards(name).swap_remove(0);
+        self.state
+            .lock()
+            .unwrap()
+            .entry(name)
+            .or_default()
+            .last_used = now_secs();
+        self.save();
+    }
+
+    /// `touch` for a model file loaded from `path`, ignored outside the cache
+    pub fn touch_path(&self, path: &Path) {
+        if path.parent() != Some(self.dir.as_path()) {
+            return;
+        }
+        if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            self.touch(name);
+        }
+    }
+
+    /// First file of the model loaded last, None when no cached model was
+    /// loaded yet or it is gone
+    pub fn last_loaded(&self) -> Option<PathBuf> {
+        let state = self.state.lock().unwrap();
+        let (name, _) = state
+            .iter()
+            .filter(|(_, entry)| entry.last_used > 0)
+            .max_by_key(|(_, entry)| entry.last_used)?;
+        let path = self.dir.join(name);
+        path.is_file().then_some(path)
+    }
+
+    /// Keep the model `name` from being evicted, or allow it again
+    pub fn set_pinned(&self, name: &str, pinned: bool) -> Result<()> {
+        let name = hf_hub::split_shards(name).swap_remove(0);
+        if !self.scan().iter().any(|group| group.name == name) {
+            return Err(anyhow!("Model {} is not cached", name));
+        }
+        self.state.lock().unwrap().entry(name).or_default().pinned = pinned;
+        self.save();
+        Ok(())
+    }
+
+    /// Delete least recently used models until `incoming` more bytes fit in
+    /// the quota. Pinned models, the loaded model and those in `keep`, e.g.
+    /// still downloading, stay. Returns the names deleted.
+    pub fn make_room(&self, incoming: u64, keep: &[String]) -> Vec<String> {
+        let Some(quota) = self.quota_bytes() else {
+            return Vec::new();
+        };
+        let mut models = self.list();
+        let mut used: u64 = models.iter().map(|model| model.size_bytes).sum();
+        let mut evicted = Vec::new();
+        // Oldest first
+        models.reverse();
+        for model in models {
+            if used.saturating_add(incoming) <= quota {
+                break;
+            }
+            if model.pinned || model.loaded || keep.contains(&model.name) {
+                continue;
+            }
+            info!(
+                "Evicting cached model {} ({} bytes) to stay under the {} byte quota",
+                model.name, model.size_bytes, quota
+            );
+            for shard in hf_hub::split_shards(&model.name) {
+                let path = self.dir.join(shard);
+                let _ = std::fs::remove_file(&path);
+                // Sidecars of `hf_hub` and `model_delta`
+                for suffix in [".etag", ".version", ".base"] {
+                    let mut sidecar = path.clone().into_os_string();
+                    sidecar.push(suffix);
+                    let _ = std::fs::remove_file(sidecar);
+                }
+            }
+            used = used.saturating_sub(model.size_bytes);
+            self.state.lock().unwrap().remove(&model.name);
+            evicted.push(model.name);
+        }
+        if !evicted.is_empty() {
+            self.save();
+        }
+        if used.saturating_add(incoming) > quota {
+            warn!(
+                "Model cache needs {} bytes over its {} byte quota; the rest is pinned or in use",
+                used.saturating_add(incoming) - quota,
+                quota
+            );
+        }
+        evicted
+    }
+}
+
+#[test]
+fn test_model_cache_eviction() {
+    let dir = tempfile::tempdir().unwrap();
+    let write = |name: &str, len: usize| std::fs::write(dir.path().join(name), vec![0u8; len]);
+    write("old.gguf", 400).unwrap();
+    write("pinned.gguf", 300).unwrap();
+    write("split-00001-of-00002.gguf", 100).unwrap();
+    write("split-00002-of-00002.gguf", 100).unwrap();
+    write("downloads.json", 50).unwrap();
+
+    let cache = ModelCache::open(dir.path(), Some(1000));
+    let models = cache.list();
+    assert_eq!(models.len(), 3);
+    let split = models
+        .iter()
+        .find(|m| m.name == "split-00001-of-00002.gguf")
+        .unwrap();
+    assert_eq!(split.size_bytes, 200);
+
+    cache.set_pinned("pinned.gguf", true).unwrap();
+    assert!(cache.set_pinned("missing.gguf", true).is_err());
+    {
+        let mut state = cache.state.lock().unwrap();
+        state.get_mut("pinned.gguf").unwrap().last_used = 1;
+    }
+    cache.state.lock().unwrap().insert(
+        "old.gguf".to_string(),
+        EntryState {
+            last_used: 2,
+            pinned: false,
+        },
+    );
+    cache.touch("split-00002-of-00002.gguf");
+    assert_eq!(
+        cache.last_loaded(),
+        Some(dir.path().join("split-00001-of-00002.gguf"))
+    );
+
+    // 900 used, so 100 more fit as they are
+    assert!(cache.make_room(100, &[]).is_empty());
+    // The pinned model is older, but only old.gguf may go
+    assert_eq!(cache.make_room(400, &[]), vec!["old.gguf".to_string()]);
+    assert!(!dir.path().join("old.gguf").exists());
+    assert!(dir.path().join("pinned.gguf").exists());
+
+    let kept = vec!["split-00001-of-00002.gguf".to_string()];
+    assert!(cache.make_room(900, &kept).is_empty());
+    assert_eq!(
+        cache.make_room(900, &[]),
+        vec!["split-00001-of-00002.gguf".to_string()]
+    );
+    assert!(!dir.path().join("split-00002-of-00002.gguf").exists());
+
+    let reopened = ModelCache::open(dir.path(), None);
+    assert!(reopened.list()[0].pinned);
+    assert_eq!(reopened.last_loaded(), Some(dir.path().join("pinned.gguf")));
+    assert!(reopened.make_room(u64::MAX, &[]).is_empty());
+}
@@ -0,0 +1,189 @@
+//! Delta updates of models. A publisher hashes each model version in
+//! fixed-size chunks into a manifest, written by `examples/chunk_manifest.rs`,
+//! and the catalog hands out its URL next to the download URL. A worker that
+//! holds an older version hashes its file the same way, copies every chunk
+//! the manifest still lists and downloads only the others by range. New
+//! fine-tunes and requantized layers of a model keep most tensors in place,
+//! so most chunks of a multi-GB GGUF carry over.
+//!
+//! Workers record the catalog version of each downloaded model in a
+//! `<model>.version` file. An outdated model waits as `<model>.base` while
+//! its update downloads.
+
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncReadExt;
+
+pub const DEFAULT_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// SHA-256 of each `chunk_size` slice of a model file, the last one shorter
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkManifest {
+    pub chunk_size: u64,
+    /// Size of the whole file
+    pub size: u64,
+    /// Lowercase hex digests in file order
+    pub chunks: Vec<String>,
+}
+
+impl ChunkManifest {
+    /// Hash the file at `path`
+    pub async fn build(path: &Path, chunk_size: u64) -> Result<Self> {
+        let size = tokio::fs::metadata(path).await?.len();
+        let chunks = hash_chunks(path, chunk_size).await?;
+        Ok(Self {
+            chunk_size,
+            size,
+            chunks,
+        })
+    }
+
+    pub async fn fetch(client: &Client, url: &str) -> Result<Self> {
+        let response = client.get(url).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Chunk manifest request failed: {}",
+                response.status()
+            ));
+        }
+        let manifest: Self = response.json().await?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.chunk_size == 0 || self.size == 0 {
+            return Err(anyhow!("Chunk manifest has no chunks"));
+        }
+        if self.chunks.len() as u64 != self.size.div_ceil(self.chunk_size) {
+            return Err(anyhow!(
+                "Chunk manifest lists {} chunks for {} bytes in {} byte chunks",
+                self.chunks.len(),
+                self.size,
+                self.chunk_size
+            ));
+        }
+        Ok(())
+    }
+
+    /// First and last byte of chunk `index`
+    pub fn chunk_range(&self, index: usize) -> (u64, u64) {
+        let start = index as u64 * self.chunk_size;
+        (start, (start + self.chunk_size).min(self.size) - 1)
+    }
+}
+
+/// Hex SHA-256 of each `chunk_size` slice of the file at `path`
+pub async fn hash_chunks(path: &Path, chunk_size: u64) -> Result<Vec<String>> {
+    if chunk_size == 0 {
+        return Err(anyhow!("Chunk size must be positive"));
+    }
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buffer = vec![0u8; 1024 * 1024];
+    let mut chunks = Vec::new();
+    loop {
+        let mut hasher = Sha256::new();
+        let mut filled = 0u64;
+        while filled < chunk_size {
+            let want = (chunk_size - filled).min(buffer.len() as u64) as usize;
+            let bytes_read = file.read(&mut buffer[..want]).await?;
+            if bytes_read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..bytes_read]);
+            filled += bytes_read as u64;
+        }
+        if filled == 0 {
+            return Ok(chunks);
+        }
+        chunks.push(format!("{:x}", hasher.finalize()));
+        if filled < chunk_size {
+            return Ok(chunks);
+        }
+    }
+}
+
+/// Offset in the file at `path` of each chunk by its hash
+pub async fn index_chunks(path: &Path, chunk_size: u64) -> Result<HashMap<String, u64>> {
+    let mut index = HashMap::new();
+    for (i, hash) in hash_chunks(path, chunk_size).await?.into_iter().enumerate() {
+        index.entry(hash).or_insert(i as u64 * chunk_size);
+    }
+    Ok(index)
+}
+
+fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut p = path.as_os_str().to_owned();
+    p.push(suffix);
+    PathBuf::from(p)
+}
+
+/// Where the outdated version of the model at `path` waits for its update
+pub fn base_path(path: &Path) -> PathBuf {
+    sidecar(path, ".base")
+}
+
+/// Part files of a delta update to `path`, kept apart from those of a full
+/// download since their chunks differ
+pub fn parts_dir(path: &Path) -> PathBuf {
+    sidecar(path, ".delta")
+}
+
+/// Catalog version of the model downloaded to `path`, if recorded
+pub async fn installed_version(path: &Path) -> Option<String> {
+    tokio::fs::read_to_string(sidecar(path, ".version"))
+        .await
+        .ok()
+        .map(|version| version.trim().to_string())
+        .filter(|version| !version.is_empty())
+}
+
+pub async fn save_version(path: &Path, version: &str) -> Result<()> {
+    tokio::fs::write(sidecar(path, ".version"), version).await?;
+    Ok(())
+}
+
+pub async fn remove_version(path: &Path) {
+    let _ = tokio::fs::remove_file(sidecar(path, ".version")).await;
+}
+
+#[tokio::test]
+async fn test_chunk_manifest() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = dir.path().join("old.gguf");
+    let new = dir.path().join("new.gguf");
+    // Chunks of 4: "aaaa" "bbbb" "cc" becomes "bbbb" "dddd" "aaaa" "e"
+    tokio::fs::write(&old, b"aaaabbbbcc").await.unwrap();
+    tokio::fs::write(&new, b"bbbbddddaaaae").await.unwrap();
+
+    let manifest = ChunkManifest::build(&new, 4).await.unwrap();
+    assert_eq!(manifest.size, 13);
+    assert_eq!(manifest.chunks.len(), 4);
+    assert!(manifest.validate().is_ok());
+    assert_eq!(manifest.chunk_range(1), (4, 7));
+    assert_eq!(manifest.chunk_range(3), (12, 12));
+
+    let have = index_chunks(&old, 4).await.unwrap();
+    let reused: Vec<Option<u64>> = manifest
+        .chunks
+        .iter()
+        .map(|hash| have.get(hash).copied())
+        .collect();
+    assert_eq!(reused, vec![Some(4), None, Some(0), None]);
+
+    let truncated = ChunkManifest {
+        chunks: manifest.chunks[..3].to_vec(),
+        ..manifest
+    };
+    assert!(truncated.validate().is_err());
+
+    assert_eq!(installed_version(&new).await, None);
+    save_version(&new, "v2\n").await.unwrap();
+    assert_eq!(installed_version(&new).await.as_deref(), Some("v2"));
+    remove_version(&new).await;
+    assert_eq!(installed_version(&new).await, None);
+}
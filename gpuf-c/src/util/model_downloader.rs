@@ -5,23 +5,42 @@
 //! - Resume capability for interrupted downloads
 //! - Progress tracking and reporting
 //! - Integrity verification with checksums
-
+//! - Failover to mirror URLs when a host keeps failing
+//! - Bandwidth cap and download schedule, see `download_policy`
+//! - Pause, resume and cancel through a `DownloadHandle`
+//! - Hugging Face Hub files by `hf://` URL, see `hf_hub`
+//! - Private buckets by `s3://`, `gs://` or `az://` URL, see `object_store`
+//! - Publisher signatures checked against pinned keys, see `model_signature`
+//! - Delta updates from an older version of the model, see `model_delta`
+
+use crate::util::download_policy::{self, DownloadGate, DownloadPolicy, RateLimiter};
+use crate::util::hf_hub::{self, HfFile};
+use crate::util::model_delta::{self, ChunkManifest};
+use crate::util::{model_signature, object_store, proxy};
 use anyhow::{anyhow, Result};
 use futures_util::StreamExt;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
 use reqwest::Client;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio::sync::{Mutex, Semaphore};
 use tokio::task::JoinSet;
 use tokio::time::{timeout, Duration};
 use tracing::{debug, error, info, warn};
 
+/// Attempts against one source before falling back to the next mirror
+const ATTEMPTS_PER_SOURCE: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
 /// Configuration for model downloading
 #[derive(Debug, Clone)]
 pub struct DownloadConfig {
-    /// URL of the model file to download
+    /// URL of the model file to download, an `hf://` Hub file or a bucket
+    /// object
     pub url: String,
+    /// Mirrors of `url`, tried in order when it keeps failing
+    pub mirrors: Vec<String>,
     /// Local path where the model should be saved
     pub output_path: PathBuf,
     /// Number of parallel download chunks (default: 4)
@@ -34,18 +53,38 @@ pub struct DownloadConfig {
     pub checksum: Option<String>,
     /// Whether to resume interrupted downloads
     pub resume: bool,
+    /// Bandwidth cap and schedule (default: the process-wide policy)
+    pub policy: DownloadPolicy,
+    /// Token of gated `hf://` files (default: `hf_hub::token()`)
+    pub hf_token: Option<String>,
+    /// Publisher's Ed25519 signature, required once publisher keys are pinned
+    pub signature: Option<String>,
+    /// Catalog version, recorded next to the file once downloaded
+    pub version: Option<String>,
+    /// Earlier version of the file to copy unchanged chunks from, deleted
+    /// once the download completes
+    pub base_path: Option<PathBuf>,
+    /// Chunk manifest of the file, needed to update `base_path` by delta
+    pub chunk_manifest_url: Option<String>,
 }
 
 impl Default for DownloadConfig {
     fn default() -> Self {
         Self {
             url: String::new(),
+            mirrors: Vec::new(),
             output_path: PathBuf::new(),
             parallel_chunks: 4,
             chunk_size: 8 * 1024 * 1024, // 8MB
             expected_size: None,
             checksum: None,
             resume: true,
+            policy: download_policy::policy(),
+            hf_token: hf_hub::token(),
+            signature: None,
+            version: None,
+            base_path: None,
+            chunk_manifest_url: None,
         }
     }
 }
@@ -68,42 +107,188 @@ pub struct DownloadProgress {
 /// Progress callback type
 pub type ProgressCallback = Box<dyn Fn(DownloadProgress) + Send + Sync>;
 
+/// Controls a running download from another task or thread
+#[derive(Clone)]
+pub struct DownloadHandle {
+    gate: Arc<DownloadGate>,
+    progress: Arc<std::sync::Mutex<Option<DownloadProgress>>>,
+}
+
+impl DownloadHandle {
+    /// Stop transferring within about a second, keeping what was downloaded
+    pub fn pause(&self) {
+        self.gate.set_paused(true);
+    }
+
+    pub fn resume(&self) {
+        self.gate.set_paused(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.gate.is_paused()
+    }
+
+    /// Stop the download and delete what it downloaded so far
+    pub fn cancel(&self) {
+        self.gate.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.gate.is_cancelled()
+    }
+
+    /// Resolves once the download is cancelled
+    pub async fn cancelled(&self) {
+        self.gate.cancelled().await
+    }
+
+    /// Latest progress report, `None` before the first one
+    pub fn progress(&self) -> Option<DownloadProgress> {
+        self.progress.lock().unwrap().clone()
+    }
+}
+
 /// Model downloader with parallel and resume capabilities
 pub struct ModelDownloader {
     client: Client,
     config: DownloadConfig,
     progress_callback: Option<Arc<ProgressCallback>>,
+    gate: Arc<DownloadGate>,
+    progress: Arc<std::sync::Mutex<Option<DownloadProgress>>>,
 }
 
 impl ModelDownloader {
     /// Create a new model downloader with the given configuration
     pub fn new(config: DownloadConfig) -> Self {
-        let client = Client::builder()
-            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36")
-            .timeout(std::time::Duration::from_secs(300)) // 5 minute timeout
-            .build()
-            .expect("Failed to create HTTP client");
+        let limiter = Arc::new(RateLimiter::new(config.policy.max_bytes_per_sec));
+        Self::with_rate_limiter(config, limiter)
+    }
 
+    /// Downloader drawing bandwidth from `limiter`, shared with other
+    /// downloads, instead of `config.policy.max_bytes_per_sec`
+    pub fn with_rate_limiter(config: DownloadConfig, limiter: Arc<RateLimiter>) -> Self {
+        let client = Self::http_client(None);
+        let progress = Arc::new(std::sync::Mutex::new(None));
         Self {
+            gate: Arc::new(DownloadGate::with_rate_limiter(config.policy, limiter)),
+            progress_callback: Some(Self::recording_callback(progress.clone(), None)),
+            progress,
             config,
             client,
-            progress_callback: None,
         }
     }
 
+    /// Client sending `token` as bearer auth. Redirects to another host, like
+    /// the Hub's CDN, drop it.
+    fn http_client(token: Option<&str>) -> Client {
+        let mut builder = proxy::http_client()
+            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36")
+            .timeout(std::time::Duration::from_secs(300)); // 5 minute timeout
+        if let Some(token) = token {
+            if let Ok(mut value) = HeaderValue::from_str(&format!("Bearer {}", token)) {
+                value.set_sensitive(true);
+                builder = builder.default_headers(HeaderMap::from_iter([(AUTHORIZATION, value)]));
+            }
+        }
+        builder.build().expect("Failed to create HTTP client")
+    }
+
     /// Set progress callback for download updates
     pub fn set_progress_callback<F>(&mut self, callback: F)
     where
         F: Fn(DownloadProgress) + Send + Sync + 'static,
     {
-        self.progress_callback = Some(Arc::new(Box::new(callback)));
+        self.progress_callback = Some(Self::recording_callback(
+            self.progress.clone(),
+            Some(Box::new(callback)),
+        ));
+    }
+
+    /// Handle to pause, resume or cancel the download and to read its progress
+    pub fn handle(&self) -> DownloadHandle {
+        DownloadHandle {
+            gate: self.gate.clone(),
+            progress: self.progress.clone(),
+        }
+    }
+
+    /// Keep the latest report for `DownloadHandle::progress` before passing
+    /// it on to `callback`
+    fn recording_callback(
+        progress: Arc<std::sync::Mutex<Option<DownloadProgress>>>,
+        callback: Option<ProgressCallback>,
+    ) -> Arc<ProgressCallback> {
+        Arc::new(Box::new(move |report: DownloadProgress| {
+            *progress.lock().unwrap() = Some(report.clone());
+            if let Some(callback) = &callback {
+                callback(report);
+            }
+        }))
     }
 
     /// Start the download with parallel chunks and resume support
     pub async fn download(&self) -> Result<()> {
+        self.fetch().await?;
+
+        if let Err(e) = self.verify_signature().await {
+            // Whoever served it cannot be trusted with a retry from the same bytes
+            error!("Removing {:?}: {}", self.config.output_path, e);
+            for path in self
+                .output_paths()
+                .unwrap_or_else(|_| vec![self.config.output_path.clone()])
+            {
+                let _ = tokio::fs::remove_file(&path).await;
+                hf_hub::remove_etag(&path).await;
+            }
+            return Err(e);
+        }
+
+        if let Some(version) = &self.config.version {
+            if let Err(e) = model_delta::save_version(&self.config.output_path, version).await {
+                warn!("Failed to record model version {}: {}", version, e);
+            }
+        }
+        if let Some(base_path) = &self.config.base_path {
+            let _ = tokio::fs::remove_file(base_path).await;
+        }
+        Ok(())
+    }
+
+    /// Download without checking the publisher signature, for files signed
+    /// with other keys than models
+    pub async fn fetch(&self) -> Result<()> {
+        let result = self.run().await;
+        if result.is_err() && self.gate.is_cancelled() {
+            info!("Download cancelled, removing {:?}", self.config.output_path);
+            let paths = self
+                .output_paths()
+                .unwrap_or_else(|_| vec![self.config.output_path.clone()]);
+            for path in paths {
+                let _ = tokio::fs::remove_file(&path).await;
+                let _ = tokio::fs::remove_dir_all(Self::parts_dir_of(&path)).await;
+                let _ = tokio::fs::remove_dir_all(model_delta::parts_dir(&path)).await;
+                hf_hub::remove_etag(&path).await;
+            }
+            return Err(anyhow!("Download cancelled"));
+        }
+        result
+    }
+
+    /// Check the downloaded files against `signature` and the pinned
+    /// publisher keys. Passes when no keys are pinned.
+    pub async fn verify_signature(&self) -> Result<()> {
+        model_signature::verify_files(&self.output_paths()?, self.config.signature.as_deref()).await
+    }
+
+    async fn run(&self) -> Result<()> {
+        if self.config.url.starts_with(hf_hub::SCHEME) {
+            return self.run_hf().await;
+        }
         info!("Starting download: {}", self.config.url);
         info!("Output path: {:?}", self.config.output_path);
 
+        self.gate.wait_until_open().await?;
+
         // Get file info from server
         let file_size = self.get_file_size().await?;
         info!("File size: {} bytes", file_size);
@@ -173,6 +358,35 @@ impl ModelDownloader {
             }
         }
 
+        if downloaded_size == 0 {
+            if let (Some(base_path), Some(manifest_url)) =
+                (&self.config.base_path, &self.config.chunk_manifest_url)
+            {
+                if base_path.exists() {
+                    match self
+                        .delta_download(base_path, manifest_url, file_size)
+                        .await
+                    {
+                        Ok(()) => {
+                            if let Some(checksum) = &self.config.checksum {
+                                self.verify_checksum(checksum).await?;
+                            }
+                            info!("Delta update completed successfully!");
+                            return Ok(());
+                        }
+                        Err(e) if self.gate.is_cancelled() => return Err(e),
+                        Err(e) => {
+                            warn!("Delta update failed, downloading the whole file: {}", e);
+                            let _ = tokio::fs::remove_dir_all(model_delta::parts_dir(
+                                &self.config.output_path,
+                            ))
+                            .await;
+                        }
+                    }
+                }
+            }
+        }
+
         if downloaded_size > 0 {
             info!(
                 "Resume detected ({} bytes already present). Using sequential ranged download to avoid file corruption.",
@@ -214,10 +428,228 @@ impl ModelDownloader {
         Ok(())
     }
 
-    /// Get file size from server headers
+    /// Files the download writes: one per shard of a split `hf://` file,
+    /// named after `output_path`
+    fn output_paths(&self) -> Result<Vec<PathBuf>> {
+        if !self.config.url.starts_with(hf_hub::SCHEME) {
+            return Ok(vec![self.config.output_path.clone()]);
+        }
+        let file: HfFile = self.config.url.parse()?;
+        let count = file.shards().len();
+        if count == 1 {
+            return Ok(vec![self.config.output_path.clone()]);
+        }
+        let name = self
+            .config
+            .output_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let names = hf_hub::split_shards(name);
+        if names.len() != count {
+            return Err(anyhow!(
+                "{} is split into {} shards, so {:?} must be named like one of them, e.g. model-00001-of-{:05}.gguf",
+                file.filename,
+                count,
+                self.config.output_path,
+                count
+            ));
+        }
+        Ok(names
+            .into_iter()
+            .map(|name| self.config.output_path.with_file_name(name))
+            .collect())
+    }
+
+    /// Download an `hf://` file, every shard of it when it is split. Shards
+    /// whose ETag still matches the Hub are not downloaded again.
+    async fn run_hf(&self) -> Result<()> {
+        let file: HfFile = self.config.url.parse()?;
+        let shards = file.shards();
+        let paths = self.output_paths()?;
+        let endpoint = hf_hub::endpoint();
+        let token = self.config.hf_token.as_deref();
+        info!(
+            "Starting download: {} from {}@{} ({} files)",
+            file.filename,
+            file.repo_id,
+            file.revision,
+            shards.len()
+        );
+
+        self.gate.wait_until_open().await?;
+
+        let mut infos = Vec::with_capacity(shards.len());
+        for shard in &shards {
+            let url = shard.url(&endpoint);
+            let lookup = Self::with_failover(&[url], "Hub lookup", RETRY_DELAY, |url| async move {
+                hf_hub::file_info(&url, token).await
+            })
+            .await;
+            match lookup {
+                Ok(info) => infos.push(info),
+                Err(e) => {
+                    // Offline with a complete earlier download: keep using it
+                    let mut cached = true;
+                    for path in &paths {
+                        cached &= path.exists() && hf_hub::cached_etag(path).await.is_some();
+                    }
+                    if cached {
+                        warn!("Hub unreachable ({}), using cached {:?}", e, paths[0]);
+                        return Ok(());
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let total: Option<u64> = infos.iter().map(|info| info.size).sum();
+        let mut offset = 0;
+        for ((shard, path), info) in shards.iter().zip(&paths).zip(&infos) {
+            let downloader = self.for_shard(
+                shard.url(&endpoint),
+                path.clone(),
+                shards.len() == 1,
+                offset,
+                total,
+            );
+            if let (Some(cached), Some(etag)) = (hf_hub::cached_etag(path).await, &info.etag) {
+                if &cached == etag && path.exists() {
+                    info!("{:?} is up to date with {}", path, file.revision);
+                    offset += tokio::fs::metadata(path).await?.len();
+                    continue;
+                }
+                info!("{:?} changed on the Hub, downloading it again", path);
+                let _ = tokio::fs::remove_file(path).await;
+                let _ = tokio::fs::remove_dir_all(downloader.parts_dir()).await;
+                hf_hub::remove_etag(path).await;
+            }
+
+            downloader.run().await?;
+            if let Some(etag) = &info.etag {
+                hf_hub::save_etag(path, etag).await?;
+            }
+            offset += tokio::fs::metadata(path).await?.len();
+        }
+
+        info!("Download completed successfully!");
+        Ok(())
+    }
+
+    /// Downloader of one shard at its resolved `url`. It shares this
+    /// download's gate and reports progress as part of the whole, `offset`
+    /// bytes in.
+    fn for_shard(
+        &self,
+        url: String,
+        output_path: PathBuf,
+        only_shard: bool,
+        offset: u64,
+        total: Option<u64>,
+    ) -> Self {
+        let parent = self.progress_callback.clone();
+        let callback: ProgressCallback = Box::new(move |progress: DownloadProgress| {
+            let Some(callback) = &parent else {
+                return;
+            };
+            let downloaded_bytes = offset + progress.downloaded_bytes;
+            let total_bytes = total.unwrap_or(offset + progress.total_bytes);
+            callback(DownloadProgress {
+                downloaded_bytes,
+                total_bytes,
+                percentage: if total_bytes > 0 {
+                    downloaded_bytes as f64 / total_bytes as f64
+                } else {
+                    0.0
+                },
+                speed_bps: progress.speed_bps,
+                eta_seconds: (progress.speed_bps > 0)
+                    .then(|| total_bytes.saturating_sub(downloaded_bytes) / progress.speed_bps),
+            });
+        });
+
+        Self {
+            client: Self::http_client(self.config.hf_token.as_deref()),
+            config: DownloadConfig {
+                url,
+                mirrors: Vec::new(),
+                output_path,
+                // Both describe the whole file, so only hold for a single shard
+                expected_size: self.config.expected_size.filter(|_| only_shard),
+                checksum: self.config.checksum.clone().filter(|_| only_shard),
+                ..self.config.clone()
+            },
+            progress_callback: Some(Arc::new(callback)),
+            gate: self.gate.clone(),
+            progress: self.progress.clone(),
+        }
+    }
+
+    /// `url` followed by the mirrors, without blanks and duplicates
+    fn sources(&self) -> Vec<String> {
+        let mut sources: Vec<String> = Vec::new();
+        for url in std::iter::once(&self.config.url).chain(&self.config.mirrors) {
+            if !url.is_empty() && !sources.contains(url) {
+                sources.push(url.clone());
+            }
+        }
+        sources
+    }
+
+    /// Run `attempt` against each source in turn, retrying each a few times.
+    /// Downloads resume from what earlier attempts left on disk. Bucket URLs
+    /// are presigned afresh for every attempt, so long downloads never run
+    /// into an expired signature.
+    async fn with_failover<T, F, Fut>(
+        sources: &[String],
+        what: &str,
+        retry_delay: Duration,
+        mut attempt: F,
+    ) -> Result<T>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut last_error = None;
+        for (i, url) in sources.iter().enumerate() {
+            if i > 0 {
+                warn!("Falling back to mirror {} for {}", url, what);
+            }
+            for n in 1..=ATTEMPTS_PER_SOURCE {
+                let result = match object_store::resolve(url) {
+                    Ok(resolved) => attempt(resolved).await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(value) => return Ok(value),
+                    Err(e) => {
+                        warn!(
+                            "{} failed from {} (attempt {}/{}): {}",
+                            what, url, n, ATTEMPTS_PER_SOURCE, e
+                        );
+                        last_error = Some(e);
+                        if n < ATTEMPTS_PER_SOURCE {
+                            tokio::time::sleep(retry_delay).await;
+                        }
+                    }
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("No download URL configured")))
+    }
+
+    /// Get file size from the first source that answers
     async fn get_file_size(&self) -> Result<u64> {
+        Self::with_failover(&self.sources(), "Size probe", RETRY_DELAY, move |url| {
+            self.get_file_size_from(url)
+        })
+        .await
+    }
+
+    /// Get file size from server headers
+    async fn get_file_size_from(&self, url: String) -> Result<u64> {
         // Try HEAD request first
-        match self.client.head(&self.config.url).send().await {
+        match self.client.head(&url).send().await {
             Ok(response) => {
                 if response.status().is_success() {
                     if let Some(size) = response.content_length() {
@@ -237,7 +669,7 @@ impl ModelDownloader {
         // Response will be: Content-Range: bytes 0-0/TOTAL_SIZE
         let response = self
             .client
-            .get(&self.config.url)
+            .get(&url)
             .header("Range", "bytes=0-0")
             .send()
             .await?;
@@ -326,7 +758,27 @@ impl ModelDownloader {
         initial_downloaded: u64,
     ) -> Result<()> {
         let parts_dir = self.parts_dir();
-        tokio::fs::create_dir_all(&parts_dir).await?;
+        let total_chunks = chunks.len();
+        self.download_parts(&parts_dir, chunks, total_size, initial_downloaded)
+            .await?;
+
+        Self::assemble_parts(&parts_dir, &self.config.output_path, total_chunks).await?;
+
+        let _ = tokio::fs::remove_dir_all(&parts_dir).await;
+
+        Ok(())
+    }
+
+    /// Download chunks in parallel into part files of `parts_dir`, numbered
+    /// by chunk index. Parts already there are resumed.
+    async fn download_parts(
+        &self,
+        parts_dir: &Path,
+        chunks: Vec<DownloadChunk>,
+        total_size: u64,
+        initial_downloaded: u64,
+    ) -> Result<()> {
+        tokio::fs::create_dir_all(parts_dir).await?;
 
         let mut existing = 0u64;
         for chunk in chunks.iter() {
@@ -343,6 +795,7 @@ impl ModelDownloader {
         }
 
         let semaphore = Arc::new(Semaphore::new(self.config.parallel_chunks));
+        let sources = Arc::new(self.sources());
         let baseline_downloaded = initial_downloaded + existing;
         let downloaded_bytes = Arc::new(Mutex::new(baseline_downloaded));
         let start_time = std::time::Instant::now();
@@ -353,28 +806,43 @@ impl ModelDownloader {
         for chunk in chunks {
             let semaphore = semaphore.clone();
             let client = self.client.clone();
-            let url = self.config.url.clone();
+            let sources = sources.clone();
             let output_path = self.config.output_path.clone();
-            let parts_dir = parts_dir.clone();
+            let parts_dir = parts_dir.to_path_buf();
             let downloaded_bytes = downloaded_bytes.clone();
             let progress_callback = self.progress_callback.clone();
+            let gate = self.gate.clone();
             let baseline_downloaded = baseline_downloaded;
 
             set.spawn(async move {
                 let _permit = semaphore.acquire().await?;
 
-                let result = Self::download_chunk_to_part(
-                    client,
-                    &url,
-                    &output_path,
-                    &parts_dir,
-                    chunk,
-                    downloaded_bytes.clone(),
-                    total_size,
-                    progress_callback,
-                    start_time,
-                    baseline_downloaded,
-                )
+                let what = format!("Chunk {}", chunk.index);
+                // A chunk stopped by the schedule continues from its part file
+                let result: Result<()> = async {
+                    loop {
+                        gate.wait_until_open().await?;
+                        let finished = Self::with_failover(&sources, &what, RETRY_DELAY, |url| {
+                            Self::download_chunk_to_part(
+                                client.clone(),
+                                url,
+                                &output_path,
+                                &parts_dir,
+                                chunk,
+                                downloaded_bytes.clone(),
+                                total_size,
+                                progress_callback.clone(),
+                                start_time,
+                                baseline_downloaded,
+                                gate.clone(),
+                            )
+                        })
+                        .await?;
+                        if finished {
+                            return Ok(());
+                        }
+                    }
+                }
                 .await;
 
                 // Return the chunk index for error reporting
@@ -400,23 +868,100 @@ impl ModelDownloader {
                     );
                 }
                 Ok(Err(e)) => {
+                    // Stop the other chunks before the caller touches the parts
+                    set.shutdown().await;
                     return Err(anyhow!("Chunk download failed: {}", e));
                 }
                 Err(e) => {
+                    set.shutdown().await;
                     return Err(anyhow!("Task join error: {}", e));
                 }
             }
         }
 
-        Self::assemble_parts(&parts_dir, &self.config.output_path, total_chunks).await?;
+        Ok(())
+    }
 
-        let _ = tokio::fs::remove_dir_all(&parts_dir).await;
+    /// Rebuild the file from the chunks of `base_path` that its manifest
+    /// still lists, downloading only the others
+    async fn delta_download(
+        &self,
+        base_path: &Path,
+        manifest_url: &str,
+        file_size: u64,
+    ) -> Result<()> {
+        let manifest = Self::with_failover(
+            &[manifest_url.to_string()],
+            "Chunk manifest",
+            RETRY_DELAY,
+            |url| async move { ChunkManifest::fetch(&self.client, &url).await },
+        )
+        .await?;
+        if manifest.size != file_size {
+            return Err(anyhow!(
+                "Chunk manifest describes {} bytes, the server has {}",
+                manifest.size,
+                file_size
+            ));
+        }
 
+        let parts_dir = model_delta::parts_dir(&self.config.output_path);
+        tokio::fs::create_dir_all(&parts_dir).await?;
+        let have = model_delta::index_chunks(base_path, manifest.chunk_size).await?;
+        let mut base = tokio::fs::File::open(base_path).await?;
+        let mut missing = Vec::new();
+        let mut reused = 0u64;
+        for (index, hash) in manifest.chunks.iter().enumerate() {
+            let (start, end) = manifest.chunk_range(index);
+            let len = end - start + 1;
+            let Some(&offset) = have.get(hash) else {
+                missing.push(DownloadChunk { start, end, index });
+                continue;
+            };
+            let part_path = Self::part_path(&parts_dir, index);
+            let copied = tokio::fs::metadata(&part_path)
+                .await
+                .map(|meta| meta.len() == len)
+                .unwrap_or(false);
+            if !copied {
+                base.seek(std::io::SeekFrom::Start(offset)).await?;
+                let mut part = tokio::fs::File::create(&part_path).await?;
+                tokio::io::copy(&mut (&mut base).take(len), &mut part).await?;
+                part.flush().await?;
+            }
+            reused += len;
+        }
+        info!(
+            "Delta update of {:?}: reusing {} of {} bytes, downloading {} of {} chunks",
+            self.config.output_path,
+            reused,
+            file_size,
+            missing.len(),
+            manifest.chunks.len()
+        );
+
+        self.download_parts(&parts_dir, missing.clone(), file_size, reused)
+            .await?;
+        for chunk in &missing {
+            let part_path = Self::part_path(&parts_dir, chunk.index);
+            let hash = model_delta::hash_chunks(&part_path, manifest.chunk_size).await?;
+            if hash.first() != Some(&manifest.chunks[chunk.index]) {
+                let _ = tokio::fs::remove_file(&part_path).await;
+                return Err(anyhow!("Chunk {} does not match the manifest", chunk.index));
+            }
+        }
+
+        Self::assemble_parts(&parts_dir, &self.config.output_path, manifest.chunks.len()).await?;
+        let _ = tokio::fs::remove_dir_all(&parts_dir).await;
         Ok(())
     }
 
     fn parts_dir(&self) -> PathBuf {
-        let mut p = self.config.output_path.to_string_lossy().to_string();
+        Self::parts_dir_of(&self.config.output_path)
+    }
+
+    fn parts_dir_of(output_path: &Path) -> PathBuf {
+        let mut p = output_path.to_string_lossy().to_string();
         p.push_str(".parts");
         PathBuf::from(p)
     }
@@ -438,7 +983,7 @@ impl ModelDownloader {
 
     async fn download_chunk_to_part(
         client: Client,
-        url: &str,
+        url: String,
         output_path: &Path,
         parts_dir: &Path,
         chunk: DownloadChunk,
@@ -447,7 +992,8 @@ impl ModelDownloader {
         progress_callback: Option<Arc<ProgressCallback>>,
         start_time: std::time::Instant,
         baseline_downloaded: u64,
-    ) -> Result<()> {
+        gate: Arc<DownloadGate>,
+    ) -> Result<bool> {
         let _ = output_path;
         let part_path = Self::part_path(parts_dir, chunk.index);
 
@@ -460,11 +1006,14 @@ impl ModelDownloader {
         let existing_len = existing_len.min(max_len);
         let start = chunk.start + existing_len;
         if start > chunk.end {
-            return Ok(());
+            return Ok(true);
+        }
+        if gate.blocked_reason().is_some() {
+            return Ok(false);
         }
 
         let range_header = format!("bytes={}-{}", start, chunk.end);
-        let response = client.get(url).header("Range", range_header).send().await?;
+        let response = client.get(&url).header("Range", range_header).send().await?;
 
         if response.status() != 206 {
             return Err(anyhow!(
@@ -483,6 +1032,7 @@ impl ModelDownloader {
 
         let mut stream = response.bytes_stream();
         let mut last_report = std::time::Instant::now();
+        let mut last_schedule_check = std::time::Instant::now();
 
         loop {
             let next = timeout(Duration::from_secs(30), stream.next()).await;
@@ -490,6 +1040,15 @@ impl ModelDownloader {
                 Ok(Some(item)) => {
                     let bytes = item?;
                     file.write_all(&bytes).await?;
+                    gate.throttle(bytes.len()).await;
+
+                    if last_schedule_check.elapsed().as_secs() >= 1 {
+                        last_schedule_check = std::time::Instant::now();
+                        if gate.blocked_reason().is_some() {
+                            file.flush().await?;
+                            return Ok(false);
+                        }
+                    }
 
                     let mut downloaded = downloaded_bytes.lock().await;
                     *downloaded += bytes.len() as u64;
@@ -530,7 +1089,7 @@ impl ModelDownloader {
 
         file.flush().await?;
 
-        Ok(())
+        Ok(true)
     }
 
     /// Verify file integrity using SHA256 checksum
@@ -567,6 +1126,23 @@ impl ModelDownloader {
 
     /// Simple download for servers that don't provide Content-Length
     async fn simple_download(&self) -> Result<()> {
+        loop {
+            self.gate.wait_until_open().await?;
+            let finished = Self::with_failover(&self.sources(), "Download", RETRY_DELAY, |url| {
+                self.simple_download_from(url)
+            })
+            .await?;
+            if finished {
+                return Ok(());
+            }
+        }
+    }
+
+    /// `Ok(false)` when the schedule or a pause stopped the download midway
+    async fn simple_download_from(&self, url: String) -> Result<bool> {
+        if self.gate.blocked_reason().is_some() {
+            return Ok(false);
+        }
         info!("Starting simple download (no Content-Length)");
 
         // Check if we can resume from existing file
@@ -582,12 +1158,12 @@ impl ModelDownloader {
         // Send request with Range header if resuming
         let response = if resume_from > 0 {
             self.client
-                .get(&self.config.url)
+                .get(&url)
                 .header("Range", format!("bytes={}-", resume_from))
                 .send()
                 .await?
         } else {
-            self.client.get(&self.config.url).send().await?
+            self.client.get(&url).send().await?
         };
 
         if response.status() == 416 && resume_from > 0 {
@@ -595,7 +1171,7 @@ impl ModelDownloader {
                 self.verify_checksum(checksum).await?;
             }
             info!("Range resume not satisfiable; treating existing file as complete");
-            return Ok(());
+            return Ok(true);
         }
 
         if !response.status().is_success() && response.status() != 206 {
@@ -643,10 +1219,21 @@ impl ModelDownloader {
         let mut stream = response.bytes_stream();
         use futures_util::StreamExt;
 
+        let mut last_schedule_check = std::time::Instant::now();
+
         while let Some(chunk_result) = stream.next().await {
             let chunk = chunk_result?;
             file.write_all(&chunk).await?;
             downloaded_bytes += chunk.len() as u64;
+            self.gate.throttle(chunk.len()).await;
+
+            if last_schedule_check.elapsed().as_secs() >= 1 {
+                last_schedule_check = std::time::Instant::now();
+                if self.gate.blocked_reason().is_some() {
+                    file.flush().await?;
+                    return Ok(false);
+                }
+            }
 
             // Update progress
             if let Some(callback) = &self.progress_callback {
@@ -691,7 +1278,7 @@ impl ModelDownloader {
             self.verify_checksum(checksum).await?;
         }
 
-        Ok(())
+        Ok(true)
     }
 }
 
@@ -761,4 +1348,99 @@ mod tests {
         let chunks = downloader.calculate_chunks(0, 5000, 5000);
         assert_eq!(chunks.len(), 4);
     }
+
+    #[tokio::test]
+    async fn test_cancel_removes_partial_download() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_path = dir.path().join("model.gguf");
+        let downloader = ModelDownloader::new(DownloadConfig {
+            url: "https://a.example/model.gguf".to_string(),
+            output_path: output_path.clone(),
+            ..Default::default()
+        });
+        tokio::fs::write(&output_path, b"partial").await.unwrap();
+        tokio::fs::create_dir_all(downloader.parts_dir())
+            .await
+            .unwrap();
+
+        let handle = downloader.handle();
+        handle.pause();
+        assert!(handle.is_paused());
+        handle.cancel();
+        assert!(downloader.download().await.is_err());
+        assert!(!output_path.exists());
+        assert!(!downloader.parts_dir().exists());
+        assert!(handle.progress().is_none());
+    }
+
+    #[test]
+    fn test_hf_shard_paths() {
+        let downloader = ModelDownloader::new(DownloadConfig {
+            url: "hf://org/repo/q4/llama-00001-of-00002.gguf@v2".to_string(),
+            output_path: PathBuf::from("models/llama-00001-of-00002.gguf"),
+            ..Default::default()
+        });
+        assert_eq!(
+            downloader.output_paths().unwrap(),
+            vec![
+                PathBuf::from("models/llama-00001-of-00002.gguf"),
+                PathBuf::from("models/llama-00002-of-00002.gguf"),
+            ]
+        );
+
+        let downloader = ModelDownloader::new(DownloadConfig {
+            url: "hf://org/repo/q4/llama-00001-of-00002.gguf".to_string(),
+            output_path: PathBuf::from("models/llama.gguf"),
+            ..Default::default()
+        });
+        assert!(downloader.output_paths().is_err());
+
+        let downloader = ModelDownloader::new(DownloadConfig {
+            url: "hf://org/repo/llama.gguf".to_string(),
+            output_path: PathBuf::from("models/llama.gguf"),
+            ..Default::default()
+        });
+        assert_eq!(
+            downloader.output_paths().unwrap(),
+            vec![PathBuf::from("models/llama.gguf")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mirror_failover() {
+        let downloader = ModelDownloader::new(DownloadConfig {
+            url: "https://a.example/model.gguf".to_string(),
+            mirrors: vec![
+                String::new(),
+                "https://a.example/model.gguf".to_string(),
+                "https://b.example/model.gguf".to_string(),
+            ],
+            ..Default::default()
+        });
+        let sources = downloader.sources();
+        assert_eq!(sources.len(), 2);
+
+        let mut tried = Vec::new();
+        let result = ModelDownloader::with_failover(&sources, "Test", Duration::ZERO, |url| {
+            tried.push(url.clone());
+            async move {
+                if url.contains("b.example") {
+                    Ok(url)
+                } else {
+                    Err(anyhow!("rate limited"))
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(result, "https://b.example/model.gguf");
+        assert_eq!(tried.len() as u32, ATTEMPTS_PER_SOURCE + 1);
+
+        let result: Result<()> =
+            ModelDownloader::with_failover(&sources, "Test", Duration::ZERO, |_| async {
+                Err(anyhow!("down"))
+            })
+            .await;
+        assert!(result.is_err());
+    }
 }
@@ -1,6 +1,7 @@
 //! Example usage of the model downloader with parallel downloading and resume support
 
 use crate::llm_engine::Engine;
+use crate::util::download_policy::DownloadPolicy;
 use crate::util::model_downloader::{DownloadConfig, DownloadProgress, ModelDownloader};
 use anyhow::Result;
 use std::path::PathBuf;
@@ -11,6 +12,8 @@ pub async fn download_llama_model() -> Result<()> {
 
     let config = DownloadConfig {
         url: model_url.to_string(),
+        // Tried in order when huggingface.co keeps failing
+        mirrors: vec![model_url.replace("huggingface.co", "hf-mirror.com")],
         output_path: dirs::home_dir()
             .unwrap_or_default()
             .join(".llama")
@@ -23,6 +26,16 @@ pub async fn download_llama_model() -> Result<()> {
             "7e5a3a8a9c8f5b2d4e6a1b3c7f9e8d5a2b4c6d8e7f9a1b3c5d7e8f9a2b4c6d8".to_string(),
         ), // Example checksum
         resume: true,
+        // Leave bandwidth for the rest of the machine
+        policy: DownloadPolicy {
+            max_bytes_per_sec: Some(10 * 1024 * 1024),
+            ..Default::default()
+        },
+        hf_token: None,
+        signature: None,
+        version: None,
+        base_path: None,
+        chunk_manifest_url: None,
     };
 
     let mut downloader = ModelDownloader::new(config);
@@ -67,12 +80,19 @@ pub async fn simple_download() -> Result<()> {
 pub async fn download_for_slow_network() -> Result<()> {
     let config = DownloadConfig {
         url: "https://example.com/large-model.gguf".to_string(),
+        mirrors: Vec::new(),
         output_path: PathBuf::from("/path/to/large-model.gguf"),
         parallel_chunks: 2,          // Fewer chunks for slow networks
         chunk_size: 4 * 1024 * 1024, // Smaller chunks (4MB)
         expected_size: None,
         checksum: None,
         resume: true,
+        policy: DownloadPolicy::default(),
+        hf_token: None,
+        signature: None,
+        version: None,
+        base_path: None,
+        chunk_manifest_url: None,
     };
 
     let downloader = ModelDownloader::new(config);
@@ -95,6 +115,7 @@ pub async fn download_multiple_models() -> Result<()> {
 
         let config = DownloadConfig {
             url: url.to_string(),
+            mirrors: Vec::new(),
             output_path: dirs::home_dir()
                 .unwrap_or_default()
                 .join(".llama")
@@ -105,6 +126,12 @@ pub async fn download_multiple_models() -> Result<()> {
             expected_size: None,
             checksum: None,
             resume: true,
+            policy: DownloadPolicy::default(),
+            hf_token: None,
+            signature: None,
+            version: None,
+            base_path: None,
+            chunk_manifest_url: None,
         };
 
         let downloader = ModelDownloader::new(config);
@@ -132,12 +159,19 @@ pub async fn download_and_initialize_llama() -> Result<()> {
 
     let config = DownloadConfig {
         url: model_url.to_string(),
+        mirrors: Vec::new(),
         output_path: model_path.clone(),
         parallel_chunks: 4,
         chunk_size: 8 * 1024 * 1024,
         expected_size: Some(668_066_816),
         checksum: None,
         resume: true,
+        policy: DownloadPolicy::default(),
+        hf_token: None,
+        signature: None,
+        version: None,
+        base_path: None,
+        chunk_manifest_url: None,
     };
 
     let mut downloader = ModelDownloader::new(config);
@@ -179,12 +213,19 @@ mod tests {
     async fn test_download_config_creation() {
         let config = DownloadConfig {
             url: "https://example.com/test.bin".to_string(),
+            mirrors: Vec::new(),
             output_path: PathBuf::from("/tmp/test.bin"),
             parallel_chunks: 2,
             chunk_size: 1024,
             expected_size: Some(2048),
             checksum: Some("abc123".to_string()),
             resume: true,
+            policy: DownloadPolicy::default(),
+            hf_token: None,
+            signature: None,
+            version: None,
+            base_path: None,
+            chunk_manifest_url: None,
         };
 
         assert_eq!(config.url, "https://example.com/test.bin");
@@ -0,0 +1,362 @@
+//! Whether a model fits into the memory a worker has free right now. The
+//! catalog matches models on the total memory of a device, but other
+//! processes, a loaded model and the KV cache of the configured context all
+//! take their share. The KV cache is sized from the attention shape in the
+//! GGUF header, read from the local file or by range from the download URL.
+
+use crate::util::hf_hub::{self, HfFile};
+use crate::util::{object_store, proxy, system_info};
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use sysinfo::System;
+use tokio::io::AsyncReadExt;
+
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+/// Bytes read from the start of a model for its metadata. Architecture keys
+/// come before the tokenizer's, so a cut-off vocabulary does not matter.
+pub(crate) const HEADER_BYTES: usize = 8 * 1024 * 1024;
+/// Compute buffers, scratch space and the runtime itself
+const OVERHEAD_BYTES: u64 = 512 * 1024 * 1024;
+/// Bytes per KV cache element, llama.cpp keeps it in f16 by default
+const KV_ELEMENT_BYTES: u64 = 2;
+
+/// Attention shape of a model, enough to size its KV cache
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KvShape {
+    pub n_layer: u64,
+    pub n_embd: u64,
+    pub n_head: u64,
+    pub n_head_kv: u64,
+    /// Context the model was trained for
+    pub context_length: u64,
+    pub key_length: Option<u64>,
+    pub value_length: Option<u64>,
+}
+
+impl KvShape {
+    /// Size of the KV cache for `n_ctx` tokens, or the trained context when 0
+    pub fn kv_cache_bytes(&self, n_ctx: u64) -> u64 {
+        let n_ctx = if n_ctx == 0 {
+            self.context_length
+        } else {
+            n_ctx
+        };
+        let head_dim = self.n_embd.checked_div(self.n_head).unwrap_or(0);
+        let key = self.key_length.unwrap_or(head_dim);
+        let value = self.value_length.unwrap_or(head_dim);
+        self.n_layer
+            .saturating_mul(n_ctx)
+            .saturating_mul(self.n_head_kv.saturating_mul(key + value))
+            .saturating_mul(KV_ELEMENT_BYTES)
+    }
+}
+
+/// Read the attention shape from the start of a GGUF file. `data` may end
+/// anywhere after the architecture keys.
+pub fn parse_kv_shape(data: &[u8]) -> Result<KvShape> {
+    let mut reader = Reader { data, pos: 0 };
+    if reader.take(4)? != GGUF_MAGIC {
+        return Err(anyhow!("Not a GGUF file"));
+    }
+    let version = reader.u32()?;
+    if version < 2 {
+        return Err(anyhow!("Unsupported GGUF version {}", version));
+    }
+    let _tensor_count = reader.u64()?;
+    let kv_count = reader.u64()?;
+
+    let mut architecture = None;
+    let mut numbers: HashMap<String, u64> = HashMap::new();
+    for _ in 0..kv_count {
+        let Ok(entry) = reader.entry() else {
+            break;
+        };
+        match entry {
+            (key, Value::String(value)) if key == "general.architecture" => {
+                architecture = Some(value);
+            }
+            (key, Value::Number(value)) => {
+                numbers.insert(key, value);
+            }
+            _ => {}
+        }
+    }
+
+    let architecture = architecture.ok_or_else(|| anyhow!("GGUF has no architecture"))?;
+    let get = |name: &str| numbers.get(&format!("{}.{}", architecture, name)).copied();
+    let n_head = get("attention.head_count").unwrap_or(0);
+    let shape = KvShape {
+        n_layer: get("block_count").unwrap_or(0),
+        n_embd: get("embedding_length").unwrap_or(0),
+        n_head,
+        n_head_kv: get("attention.head_count_kv").unwrap_or(n_head),
+        context_length: get("context_length").unwrap_or(0),
+        key_length: get("attention.key_length"),
+        value_length: get("attention.value_length"),
+    };
+    if shape.n_layer == 0 || shape.n_head == 0 {
+        return Err(anyhow!("GGUF of {} has no attention shape", architecture));
+    }
+    Ok(shape)
+}
+
+enum Value {
+    /// Integers, and the largest element of integer arrays like per-layer head counts
+    Number(u64),
+    String(String),
+    Other,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8]> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| anyhow!("GGUF header is truncated"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = usize::try_from(self.u64()?)?;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn entry(&mut self) -> Result<(String, Value)> {
+        let key = self.string()?;
+        let value_type = self.u32()?;
+        Ok((key, self.value(value_type)?))
+    }
+
+    fn value(&mut self, value_type: u32) -> Result<Value> {
+        let int = |bytes: &[u8], signed: bool| {
+            let mut buf = [0u8; 8];
+            buf[..bytes.len()].copy_from_slice(bytes);
+            let value = u64::from_le_bytes(buf);
+            let negative = signed && bytes.last().is_some_and(|b| b & 0x80 != 0);
+            Value::Number(if negative { 0 } else { value })
+        };
+        Ok(match value_type {
+            0 | 7 => int(self.take(1)?, false),
+            1 => int(self.take(1)?, true),
+            2 => int(self.take(2)?, false),
+            3 => int(self.take(2)?, true),
+            4 => int(self.take(4)?, false),
+            5 => int(self.take(4)?, true),
+            10 => int(self.take(8)?, false),
+            11 => int(self.take(8)?, true),
+            6 => {
+                self.take(4)?;
+                Value::Other
+            }
+            12 => {
+                self.take(8)?;
+                Value::Other
+            }
+            8 => Value::String(self.string()?),
+            9 => {
+                let item_type = self.u32()?;
+                let len = self.u64()?;
+                let mut max = None;
+                for _ in 0..len {
+                    if let Value::Number(n) = self.value(item_type)? {
+                        max = max.max(Some(n));
+                    }
+                }
+                max.map_or(Value::Other, Value::Number)
+            }
+            other => return Err(anyhow!("Unknown GGUF value type {}", other)),
+        })
+    }
+}
+
+/// Start of the model file at `path`
+pub async fn read_header(path: &Path) -> Result<Vec<u8>> {
+    let file = tokio::fs::File::open(path).await?;
+    let mut header = Vec::new();
+    file.take(HEADER_BYTES as u64)
+        .read_to_end(&mut header)
+        .await?;
+    Ok(header)
+}
+
+/// Start of the model at `url` by a range request. A split model's first
+/// shard holds the metadata.
+pub async fn fetch_header(url: &str) -> Result<Vec<u8>> {
+    let client = proxy::http_client()
+        .timeout(Duration::from_secs(60))
+        .build()?;
+    let request = if url.starts_with(hf_hub::SCHEME) {
+        let file: HfFile = url.parse()?;
+        let first = file.shards().swap_remove(0);
+        let request = client.get(first.url(&hf_hub::endpoint()));
+        match hf_hub::token() {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    } else {
+        client.get(object_store::resolve(url)?)
+    };
+    let mut response = request
+        .header("Range", format!("bytes=0-{}", HEADER_BYTES - 1))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Header request failed: {}", response.status()));
+    }
+    // Servers that ignore the range send the whole file, stop early
+    let mut header = Vec::new();
+    while header.len() < HEADER_BYTES {
+        match response.chunk().await? {
+            Some(chunk) => header.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    header.truncate(HEADER_BYTES);
+    Ok(header)
+}
+
+/// Free RAM plus free VRAM of discrete GPUs
+pub fn free_memory_bytes() -> u64 {
+    let mut sys = System::new();
+    sys.refresh_memory();
+    sys.available_memory()
+        .saturating_add(system_info::free_vram_bytes())
+}
+
+/// Memory a model needs against what is free
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FitCheck {
+    pub model_bytes: u64,
+    /// 0 when the model's attention shape is unknown
+    pub kv_cache_bytes: u64,
+    pub available_bytes: u64,
+}
+
+impl FitCheck {
+    pub fn needed_bytes(&self) -> u64 {
+        self.model_bytes
+            .saturating_add(self.kv_cache_bytes)
+            .saturating_add(OVERHEAD_BYTES)
+    }
+
+    pub fn fits(&self) -> bool {
+        self.needed_bytes() <= self.available_bytes
+    }
+
+    /// Largest model file that would fit next to a KV cache of the same size
+    pub fn max_model_bytes(&self) -> u64 {
+        self.available_bytes
+            .saturating_sub(self.kv_cache_bytes)
+            .saturating_sub(OVERHEAD_BYTES)
+    }
+}
+
+/// Check a model of `model_bytes` against free memory for a context of
+/// `n_ctx` tokens. `header` is the start of the model's file when known, and
+/// `reclaimable_bytes` memory freed by unloading the current model.
+pub fn check(
+    model_bytes: u64,
+    header: Option<&[u8]>,
+    n_ctx: u64,
+    reclaimable_bytes: u64,
+) -> FitCheck {
+    let kv_cache_bytes = header
+        .and_then(|header| parse_kv_shape(header).ok())
+        .map(|shape| shape.kv_cache_bytes(n_ctx))
+        .unwrap_or(0);
+    FitCheck {
+        model_bytes,
+        kv_cache_bytes,
+        available_bytes: free_memory_bytes().saturating_add(reclaimable_bytes),
+    }
+}
+
+#[test]
+fn test_model_fit() {
+    fn string(out: &mut Vec<u8>, s: &str) {
+        out.extend_from_slice(&(s.len() as u64).to_le_bytes());
+        out.extend_from_slice(s.as_bytes());
+    }
+    fn kv_u32(out: &mut Vec<u8>, key: &str, value: u32) {
+        string(out, key);
+        out.extend_from_slice(&4u32.to_le_bytes());
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let mut gguf = Vec::new();
+    gguf.extend_from_slice(GGUF_MAGIC);
+    gguf.extend_from_slice(&3u32.to_le_bytes());
+    gguf.extend_from_slice(&0u64.to_le_bytes());
+    gguf.extend_from_slice(&7u64.to_le_bytes());
+    string(&mut gguf, "general.architecture");
+    gguf.extend_from_slice(&8u32.to_le_bytes());
+    string(&mut gguf, "llama");
+    string(&mut gguf, "llama.rope.scaling.factor");
+    gguf.extend_from_slice(&6u32.to_le_bytes());
+    gguf.extend_from_slice(&1.5f32.to_le_bytes());
+    kv_u32(&mut gguf, "llama.block_count", 32);
+    kv_u32(&mut gguf, "llama.embedding_length", 4096);
+    kv_u32(&mut gguf, "llama.context_length", 131072);
+    // Per-layer head counts, the largest is used
+    string(&mut gguf, "llama.attention.head_count");
+    gguf.extend_from_slice(&9u32.to_le_bytes());
+    gguf.extend_from_slice(&4u32.to_le_bytes());
+    gguf.extend_from_slice(&2u64.to_le_bytes());
+    gguf.extend_from_slice(&16u32.to_le_bytes());
+    gguf.extend_from_slice(&32u32.to_le_bytes());
+    kv_u32(&mut gguf, "llama.attention.head_count_kv", 8);
+
+    let shape = parse_kv_shape(&gguf).unwrap();
+    assert_eq!(
+        shape,
+        KvShape {
+            n_layer: 32,
+            n_embd: 4096,
+            n_head: 32,
+            n_head_kv: 8,
+            context_length: 131072,
+            key_length: None,
+            value_length: None,
+        }
+    );
+    // 32 layers * 8192 tokens * 8 heads * (128 + 128) * 2 bytes = 1 GiB
+    assert_eq!(shape.kv_cache_bytes(8192), 1024 * 1024 * 1024);
+    assert_eq!(shape.kv_cache_bytes(0), 16 * 1024 * 1024 * 1024);
+
+    // The head counts are cut off, so the shape is incomplete
+    assert!(parse_kv_shape(&gguf[..gguf.len() - 60]).is_err());
+    assert!(parse_kv_shape(b"GGML").is_err());
+
+    let gib = 1024 * 1024 * 1024;
+    let fit = FitCheck {
+        model_bytes: 4 * gib,
+        kv_cache_bytes: gib,
+        available_bytes: 6 * gib,
+    };
+    assert!(fit.fits());
+    assert_eq!(fit.max_model_bytes(), 4 * gib + gib / 2);
+    let tight = FitCheck {
+        available_bytes: 5 * gib,
+        ..fit
+    };
+    assert!(!tight.fits());
+    assert_eq!(tight.max_model_bytes(), 3 * gib + gib / 2);
+}
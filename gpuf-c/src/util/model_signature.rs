@@ -0,0 +1,134 @@
+//! Ed25519 signatures of model files. A publisher signs the SHA-256 digest
+//! of the file, or of all shards of a split model read in order, and the
+//! catalog hands the base64 signature to workers with the download URL.
+//! Workers pin the keys they trust with `--model-publisher-key`; once a key
+//! is pinned, a model is only loaded with a valid signature from one of
+//! them, so a compromised CDN or mirror cannot serve a tampered GGUF.
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use tokio::io::AsyncReadExt;
+
+const ED25519_KEY_LEN: usize = 32;
+
+static PUBLISHER_KEYS: OnceLock<Mutex<Vec<Vec<u8>>>> = OnceLock::new();
+
+/// Decode base64 Ed25519 public keys, naming them `kind` in errors
+pub fn decode_public_keys(keys: &[String], kind: &str) -> Result<Vec<Vec<u8>>> {
+    keys.iter()
+        .map(|key| match BASE64.decode(key.trim()) {
+            Ok(bytes) if bytes.len() == ED25519_KEY_LEN => Ok(bytes),
+            _ => Err(anyhow!(
+                "Invalid {} '{}'. Expected a base64 Ed25519 public key",
+                kind,
+                key
+            )),
+        })
+        .collect()
+}
+
+/// Pin the base64 Ed25519 public keys models must be signed with. No keys
+/// turns verification off.
+pub fn set_publisher_keys(keys: &[String]) -> Result<()> {
+    let keys = decode_public_keys(keys, "model publisher key")?;
+    *PUBLISHER_KEYS
+        .get_or_init(|| Mutex::new(Vec::new()))
+        .lock()
+        .unwrap() = keys;
+    Ok(())
+}
+
+pub fn publisher_keys() -> Vec<Vec<u8>> {
+    PUBLISHER_KEYS
+        .get()
+        .map(|keys| keys.lock().unwrap().clone())
+        .unwrap_or_default()
+}
+
+/// SHA-256 of the files read one after another
+pub async fn digest_files(paths: &[PathBuf]) -> Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    for path in paths {
+        let mut file = tokio::fs::File::open(path).await?;
+        loop {
+            let bytes_read = file.read(&mut buffer).await?;
+            if bytes_read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..bytes_read]);
+        }
+    }
+    Ok(hasher.finalize().into())
+}
+
+/// Check the base64 `signature` of `message` against any of `keys`
+pub fn verify_signature(message: &[u8], signature: &str, keys: &[Vec<u8>]) -> Result<()> {
+    let signature = BASE64
+        .decode(signature.trim())
+        .map_err(|e| anyhow!("Invalid signature: {}", e))?;
+    if keys.iter().any(|key| {
+        UnparsedPublicKey::new(&ED25519, key)
+            .verify(message, &signature)
+            .is_ok()
+    }) {
+        Ok(())
+    } else {
+        Err(anyhow!("Signature does not match any pinned key"))
+    }
+}
+
+/// Check `signature` of `digest` against any of `keys`
+pub fn verify_digest(digest: &[u8; 32], signature: &str, keys: &[Vec<u8>]) -> Result<()> {
+    verify_signature(digest, signature, keys).context("Invalid model signature")
+}
+
+/// Verify a downloaded model, made of `paths` in order, against the pinned
+/// publisher keys. Passes when none are pinned.
+pub async fn verify_files(paths: &[PathBuf], signature: Option<&str>) -> Result<()> {
+    let keys = publisher_keys();
+    if keys.is_empty() {
+        return Ok(());
+    }
+    let signature =
+        signature.ok_or_else(|| anyhow!("Model is not signed, but publisher keys are pinned"))?;
+    verify_digest(&digest_files(paths).await?, signature, &keys)
+}
+
+#[tokio::test]
+async fn test_verify_signature() {
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    let publisher = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+    let public_key = BASE64.encode(publisher.public_key().as_ref());
+
+    let dir = tempfile::tempdir().unwrap();
+    let shards = vec![dir.path().join("a.gguf"), dir.path().join("b.gguf")];
+    tokio::fs::write(&shards[0], b"GGUF first half")
+        .await
+        .unwrap();
+    tokio::fs::write(&shards[1], b" and second half")
+        .await
+        .unwrap();
+    let digest = digest_files(&shards).await.unwrap();
+    let signature = BASE64.encode(publisher.sign(&digest).as_ref());
+    let keys = vec![BASE64.decode(&public_key).unwrap()];
+
+    assert!(verify_digest(&digest, &signature, &keys).is_ok());
+    assert!(verify_digest(&digest, &signature, &[vec![7u8; 32]]).is_err());
+    assert!(verify_digest(&digest, "not base64!", &keys).is_err());
+
+    tokio::fs::write(&shards[1], b" and tampered half")
+        .await
+        .unwrap();
+    let tampered = digest_files(&shards).await.unwrap();
+    assert!(verify_digest(&tampered, &signature, &keys).is_err());
+
+    assert!(set_publisher_keys(&["c2hvcnQ=".to_string()]).is_err());
+}
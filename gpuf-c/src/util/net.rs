@@ -0,0 +1,140 @@
+//! IPv4 and IPv6 connectivity. Connections to gpuf-s race the addresses of
+//! the server name Happy Eyeballs style (RFC 8305), so a broken IPv6 route
+//! costs a quarter second instead of a connect timeout.
+
+use clap::ValueEnum;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tracing::{debug, warn};
+
+/// Head start of each connection attempt over the next one
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Address family of connections to gpuf-s
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize)]
+pub enum IpVersion {
+    /// Both, IPv6 first when the resolver lists it first
+    #[default]
+    #[clap(name = "auto")]
+    Auto,
+    #[clap(name = "4")]
+    V4,
+    #[clap(name = "6")]
+    V6,
+}
+
+impl IpVersion {
+    fn allows(self, addr: &SocketAddr) -> bool {
+        match self {
+            IpVersion::Auto => true,
+            IpVersion::V4 => addr.is_ipv4(),
+            IpVersion::V6 => addr.is_ipv6(),
+        }
+    }
+}
+
+/// Addresses of `host:port` of `ip_version`, alternating between the
+/// families starting with the first the resolver returned
+pub async fn resolve(host: &str, port: u16, ip_version: IpVersion) -> io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = lookup_host((host, port))
+        .await?
+        .filter(|addr| ip_version.allows(addr))
+        .collect();
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No {:?} address found for {}", ip_version, host),
+        ));
+    }
+    Ok(interleave(addrs))
+}
+
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addrs[0].is_ipv6();
+    let (mut first, mut second): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_v6);
+    let mut interleaved = Vec::with_capacity(first.len() + second.len());
+    first.reverse();
+    second.reverse();
+    loop {
+        match (first.pop(), second.pop()) {
+            (None, None) => return interleaved,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// TCP connection to `host:port`. Attempts start one address after the
+/// other, each `CONNECTION_ATTEMPT_DELAY` or one failure after the previous,
+/// and the first to connect wins.
+pub async fn connect(host: &str, port: u16, ip_version: IpVersion) -> io::Result<TcpStream> {
+    let mut addrs = resolve(host, port, ip_version).await?.into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+    loop {
+        if let Some(addr) = addrs.next() {
+            attempts.spawn(async move { (addr, TcpStream::connect(addr).await) });
+        }
+        let delay = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY);
+        tokio::select! {
+            Some(attempt) = attempts.join_next() => match attempt {
+                // Dropping the set aborts the attempts still running
+                Ok((addr, Ok(stream))) => {
+                    debug!("Connected to {} at {}", host, addr);
+                    return Ok(stream);
+                }
+                Ok((addr, Err(e))) => {
+                    debug!("Connecting to {} at {} failed: {}", host, addr, e);
+                    last_error = Some(e);
+                }
+                Err(e) => last_error = Some(io::Error::other(e)),
+            },
+            _ = delay, if !addrs.as_slice().is_empty() => {}
+            else => break,
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::from(io::ErrorKind::NotConnected)))
+}
+
+/// Listener on `ip:port`. The unspecified IPv6 address `::` also takes IPv4
+/// clients where the system allows it, and falls back to `0.0.0.0` on hosts
+/// without IPv6.
+pub async fn bind(ip: IpAddr, port: u16) -> io::Result<TcpListener> {
+    match TcpListener::bind((ip, port)).await {
+        Err(e) if ip.is_unspecified() && ip.is_ipv6() => {
+            warn!("IPv6 is unavailable ({}), listening on IPv4 only", e);
+            TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).await
+        }
+        result => result,
+    }
+}
+
+#[test]
+fn test_interleave() {
+    let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+    let addrs = vec![
+        addr("[2001:db8::1]:17000"),
+        addr("[2001:db8::2]:17000"),
+        addr("[2001:db8::3]:17000"),
+        addr("192.0.2.1:17000"),
+    ];
+    assert_eq!(
+        interleave(addrs),
+        vec![
+            addr("[2001:db8::1]:17000"),
+            addr("192.0.2.1:17000"),
+            addr("[2001:db8::2]:17000"),
+            addr("[2001:db8::3]:17000"),
+        ]
+    );
+    let addrs = vec![addr("192.0.2.1:17000"), addr("[2001:db8::1]:17000")];
+    assert_eq!(interleave(addrs.clone()), addrs);
+
+    assert!(IpVersion::V4.allows(&addr("192.0.2.1:17000")));
+    assert!(!IpVersion::V4.allows(&addr("[2001:db8::1]:17000")));
+    assert!(IpVersion::Auto.allows(&addr("[2001:db8::1]:17000")));
+}
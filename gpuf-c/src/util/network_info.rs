@@ -1,4 +1,10 @@
+use crate::util::net::IpVersion;
+use crate::util::proxy::{self, OutboundProxy};
+use anyhow::{anyhow, Result};
+use bytes::BytesMut;
+use common::{read_command, write_command, Command, CommandV1, NetworkQuality};
 use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
 
 #[cfg(not(target_os = "android"))]
 use std::net::UdpSocket;
@@ -209,6 +215,74 @@ impl SessionNetworkMonitor {
     }
 }
 
+/// Payload size of each bandwidth probe. Small enough to run next to live
+/// traffic, large enough that TCP slow start does not dominate on fast links.
+pub const PROBE_BYTES: usize = 1 << 20;
+
+/// Time to open a TCP connection to `host:port`, about one network round
+/// trip, or the time to open the tunnel through `proxy`
+pub async fn measure_rtt(
+    proxy: Option<&OutboundProxy>,
+    host: &str,
+    port: u16,
+    ip_version: IpVersion,
+) -> Result<Duration> {
+    let start = Instant::now();
+    let _stream = proxy::connect(proxy, host, port, ip_version).await?;
+    Ok(start.elapsed())
+}
+
+/// Times one upload and one download of `bytes` over a proxy connection to the
+/// server, after an empty exchange that measures the per-request overhead
+pub async fn probe_bandwidth<S>(
+    stream: &mut S,
+    rtt: Duration,
+    bytes: usize,
+) -> Result<NetworkQuality>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = BytesMut::with_capacity(bytes + 64);
+    let overhead = probe_exchange(stream, &mut buf, Vec::new(), 0).await?;
+    let upload = probe_exchange(stream, &mut buf, vec![0u8; bytes], 0).await?;
+    let download = probe_exchange(stream, &mut buf, Vec::new(), bytes as u32).await?;
+
+    Ok(NetworkQuality {
+        rtt_ms: rtt.as_millis() as u32,
+        upload_mbps: mbps(bytes, upload.saturating_sub(overhead)),
+        download_mbps: mbps(bytes, download.saturating_sub(overhead)),
+    })
+}
+
+async fn probe_exchange<S>(
+    stream: &mut S,
+    buf: &mut BytesMut,
+    payload: Vec<u8>,
+    reply_bytes: u32,
+) -> Result<Duration>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let start = Instant::now();
+    write_command(
+        stream,
+        &Command::V1(CommandV1::NetworkProbe {
+            payload,
+            reply_bytes,
+        }),
+    )
+    .await?;
+    match read_command(stream, buf).await? {
+        Command::V1(CommandV1::NetworkProbe { .. }) => Ok(start.elapsed()),
+        _ => Err(anyhow!("Unexpected reply to network probe")),
+    }
+}
+
+fn mbps(bytes: usize, elapsed: Duration) -> f32 {
+    let secs = elapsed.as_secs_f64().max(1e-3);
+    (bytes as f64 * 8.0 / secs / 1_000_000.0) as f32
+}
+
 #[cfg(not(target_os = "android"))]
 #[test]
 fn test_detect_default_interface() {
@@ -216,3 +290,29 @@ fn test_detect_default_interface() {
     println!("interface: {:?}", interface);
     assert!(interface.is_some());
 }
+
+#[tokio::test]
+async fn test_probe_bandwidth() {
+    let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let mut buf = BytesMut::new();
+        while let Ok(Command::V1(CommandV1::NetworkProbe { reply_bytes, .. })) =
+            read_command(&mut server, &mut buf).await
+        {
+            let reply = Command::V1(CommandV1::NetworkProbe {
+                payload: vec![0u8; reply_bytes as usize],
+                reply_bytes: 0,
+            });
+            if write_command(&mut server, &reply).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let quality = probe_bandwidth(&mut client, Duration::from_millis(12), 256 * 1024)
+        .await
+        .unwrap();
+    assert_eq!(quality.rtt_ms, 12);
+    assert!(quality.upload_mbps > 0.0);
+    assert!(quality.download_mbps > 0.0);
+}
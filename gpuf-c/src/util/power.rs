@@ -0,0 +1,208 @@
+//! Battery and thermal state of mobile workers. Host apps report it through
+//! `set_remote_worker_power_status`; without a report the worker reads sysfs,
+//! which newer Android versions may hide from apps.
+
+use common::PowerStatus;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// Android PowerManager.THERMAL_STATUS_SEVERE
+pub const THERMAL_STATUS_SEVERE: u8 = 3;
+/// A paused worker resumes once it is this much cooler than the limit
+const RESUME_TEMPERATURE_MARGIN_C: u8 = 3;
+/// A paused worker resumes once the battery is this much above the limit
+const RESUME_BATTERY_MARGIN_PCT: u8 = 5;
+
+/// Limits beyond which the worker stops taking inference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerPolicy {
+    /// Ignored while charging
+    pub min_battery_pct: u8,
+    pub max_temperature_c: u8,
+    pub max_thermal_status: u8,
+}
+
+impl Default for PowerPolicy {
+    fn default() -> Self {
+        Self {
+            min_battery_pct: 20,
+            max_temperature_c: 45,
+            max_thermal_status: THERMAL_STATUS_SEVERE,
+        }
+    }
+}
+
+static REPORTED_STATUS: OnceLock<Mutex<Option<PowerStatus>>> = OnceLock::new();
+static POLICY: OnceLock<Mutex<PowerPolicy>> = OnceLock::new();
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Status pushed by the host app, preferred over sysfs from now on
+pub fn report_status(status: PowerStatus) {
+    *REPORTED_STATUS
+        .get_or_init(|| Mutex::new(None))
+        .lock()
+        .unwrap() = Some(status);
+}
+
+pub fn set_policy(policy: PowerPolicy) {
+    *POLICY
+        .get_or_init(|| Mutex::new(PowerPolicy::default()))
+        .lock()
+        .unwrap() = policy;
+}
+
+pub fn policy() -> PowerPolicy {
+    POLICY.get().map(|p| *p.lock().unwrap()).unwrap_or_default()
+}
+
+/// Latest reported status, or a sysfs reading when the app reports nothing
+pub fn current_status() -> Option<PowerStatus> {
+    REPORTED_STATUS
+        .get()
+        .and_then(|s| *s.lock().unwrap())
+        .or_else(|| read_sysfs(Path::new("/sys/class")))
+}
+
+/// Whether inference is paused for heat or battery
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+pub fn set_paused(paused: bool) {
+    PAUSED.store(paused, Ordering::Relaxed);
+}
+
+/// New pause state and its reason when the current status calls for a change.
+/// Callers apply it with `set_paused` once the server knows.
+pub fn pending_change() -> Option<(bool, Option<String>)> {
+    let status = current_status()?;
+    let was_paused = is_paused();
+    let reason = pause_reason(&status, &policy(), was_paused);
+    let paused = reason.is_some();
+    (paused != was_paused).then_some((paused, reason))
+}
+
+/// Why the worker should not take inference. Once paused, the limits are
+/// tightened by a margin so the worker does not flap around them.
+pub fn pause_reason(status: &PowerStatus, policy: &PowerPolicy, paused: bool) -> Option<String> {
+    let (temperature_margin, battery_margin) = if paused {
+        (RESUME_TEMPERATURE_MARGIN_C, RESUME_BATTERY_MARGIN_PCT)
+    } else {
+        (0, 0)
+    };
+
+    if status.thermal_status >= policy.max_thermal_status {
+        return Some(format!("thermal status {}", status.thermal_status));
+    }
+    if status.temperature_c.saturating_add(temperature_margin) >= policy.max_temperature_c {
+        return Some(format!("temperature {}°C", status.temperature_c));
+    }
+    if !status.charging
+        && status.battery_pct < policy.min_battery_pct.saturating_add(battery_margin)
+    {
+        return Some(format!("battery {}%", status.battery_pct));
+    }
+    None
+}
+
+/// Battery from `power_supply/battery` and the hottest `thermal/thermal_zone*`
+/// under `root`, usually `/sys/class`
+pub fn read_sysfs(root: &Path) -> Option<PowerStatus> {
+    let battery = root.join("power_supply/battery");
+    let battery_pct = std::fs::read_to_string(battery.join("capacity"))
+        .ok()?
+        .trim()
+        .parse::<u8>()
+        .ok()?;
+    let charging = std::fs::read_to_string(battery.join("status"))
+        .map(|s| matches!(s.trim(), "Charging" | "Full"))
+        .unwrap_or(false);
+
+    let temperature_c = std::fs::read_dir(root.join("thermal"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with("thermal_zone")
+        })
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join("temp")).ok())
+        .filter_map(|raw| raw.trim().parse::<i64>().ok())
+        // Most zones report millidegrees, some whole degrees
+        .map(|t| if t > 1000 { t / 1000 } else { t })
+        .max()
+        .unwrap_or(0)
+        .clamp(0, u8::MAX as i64) as u8;
+
+    Some(PowerStatus {
+        battery_pct: battery_pct.min(100),
+        charging,
+        temperature_c,
+        thermal_status: 0,
+    })
+}
+
+#[test]
+fn test_pause_reason() {
+    let policy = PowerPolicy::default();
+    let status = PowerStatus {
+        battery_pct: 50,
+        charging: false,
+        temperature_c: 38,
+        thermal_status: 0,
+    };
+    assert_eq!(pause_reason(&status, &policy, false), None);
+
+    let hot = PowerStatus {
+        temperature_c: 46,
+        ..status
+    };
+    assert!(pause_reason(&hot, &policy, false).is_some());
+    // Cooling to just below the limit is not enough to resume
+    let cooling = PowerStatus {
+        temperature_c: 43,
+        ..status
+    };
+    assert!(pause_reason(&cooling, &policy, true).is_some());
+    assert_eq!(pause_reason(&cooling, &policy, false), None);
+
+    let low = PowerStatus {
+        battery_pct: 10,
+        ..status
+    };
+    assert!(pause_reason(&low, &policy, false).is_some());
+    let charging = PowerStatus {
+        charging: true,
+        ..low
+    };
+    assert_eq!(pause_reason(&charging, &policy, false), None);
+
+    let throttled = PowerStatus {
+        thermal_status: THERMAL_STATUS_SEVERE,
+        ..status
+    };
+    assert!(pause_reason(&throttled, &policy, false).is_some());
+}
+
+#[test]
+fn test_read_sysfs() {
+    let root = tempfile::tempdir().unwrap();
+    let write = |path: &str, contents: &str| {
+        let path = root.path().join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    };
+    write("power_supply/battery/capacity", "64\n");
+    write("power_supply/battery/status", "Charging\n");
+    write("thermal/thermal_zone0/temp", "36500\n");
+    write("thermal/thermal_zone1/temp", "41\n");
+
+    let status = read_sysfs(root.path()).unwrap();
+    assert_eq!(status.battery_pct, 64);
+    assert!(status.charging);
+    assert_eq!(status.temperature_c, 41);
+    assert!(read_sysfs(&root.path().join("missing")).is_none());
+}
@@ -0,0 +1,150 @@
+//! Hard limits on the inference requests a worker takes, so one oversized
+//! prompt cannot tie up the device. The worker takes them from `Args`;
+//! mobile apps set them through `set_remote_worker_request_limits`. Failed
+//! checks carry the [`REQUEST_TOO_LARGE`] or [`LIMIT_EXCEEDED`] prefix the
+//! server answers with 413 and 422.
+
+#[cfg(not(target_os = "ios"))]
+use crate::llm_engine::{InferenceInput, InferenceRequest};
+use crate::util::cmd::Args;
+use anyhow::{anyhow, Result};
+use common::{LIMIT_EXCEEDED, REQUEST_TOO_LARGE};
+use std::sync::{Mutex, OnceLock};
+
+/// A limit of 0 is off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Longest prompt, or sum of chat message contents, in bytes
+    pub max_prompt_bytes: usize,
+    /// Longest prompt in tokens; the context limits it otherwise
+    pub max_prompt_tokens: usize,
+    /// Highest `max_tokens` a request may ask for
+    pub max_tokens: u32,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_prompt_bytes: 1024 * 1024,
+            max_prompt_tokens: 0,
+            max_tokens: 0,
+        }
+    }
+}
+
+impl RequestLimits {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            max_prompt_bytes: args.max_prompt_bytes,
+            max_prompt_tokens: args.max_prompt_tokens,
+            max_tokens: args.max_tokens_limit,
+        }
+    }
+
+    pub fn check_prompt_bytes(&self, bytes: usize) -> Result<()> {
+        if self.max_prompt_bytes > 0 && bytes > self.max_prompt_bytes {
+            return Err(anyhow!(
+                "{}: prompt of {} bytes exceeds the limit of {} bytes",
+                REQUEST_TOO_LARGE,
+                bytes,
+                self.max_prompt_bytes
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn check_prompt_tokens(&self, tokens: usize) -> Result<()> {
+        if self.max_prompt_tokens > 0 && tokens > self.max_prompt_tokens {
+            return Err(anyhow!(
+                "{}: prompt of {} tokens exceeds the limit of {} tokens",
+                REQUEST_TOO_LARGE,
+                tokens,
+                self.max_prompt_tokens
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn check_max_tokens(&self, max_tokens: u32) -> Result<()> {
+        if self.max_tokens > 0 && max_tokens > self.max_tokens {
+            return Err(anyhow!(
+                "{}: max_tokens {} exceeds the limit of {}",
+                LIMIT_EXCEEDED,
+                max_tokens,
+                self.max_tokens
+            ));
+        }
+        Ok(())
+    }
+
+    /// The checks that need no tokenizer
+    #[cfg(not(target_os = "ios"))]
+    pub fn check_request(&self, request: &InferenceRequest) -> Result<()> {
+        self.check_max_tokens(request.max_tokens)?;
+        let bytes = match &request.input {
+            InferenceInput::Prompt(prompt) => prompt.len(),
+            InferenceInput::Chat(messages) => messages.iter().map(|m| m.content.len()).sum(),
+        };
+        self.check_prompt_bytes(bytes)
+    }
+}
+
+static LIMITS: OnceLock<Mutex<RequestLimits>> = OnceLock::new();
+
+/// Limits of requests taken from now on
+pub fn set_limits(limits: RequestLimits) {
+    *LIMITS
+        .get_or_init(|| Mutex::new(RequestLimits::default()))
+        .lock()
+        .unwrap() = limits;
+}
+
+pub fn limits() -> RequestLimits {
+    LIMITS.get().map(|l| *l.lock().unwrap()).unwrap_or_default()
+}
+
+#[cfg(not(target_os = "ios"))]
+#[test]
+fn test_request_limits() {
+    let limits = RequestLimits {
+        max_prompt_bytes: 8,
+        max_prompt_tokens: 4,
+        max_tokens: 16,
+    };
+    let mut request = InferenceRequest {
+        model: None,
+        input: InferenceInput::Prompt("12345678".to_string()),
+        max_tokens: 16,
+        temperature: 0.7,
+        top_k: 40,
+        top_p: 0.9,
+        repeat_penalty: 1.1,
+        repeat_last_n: 64,
+        min_keep: 1,
+        prefix: None,
+        overflow: Default::default(),
+    };
+    assert!(limits.check_request(&request).is_ok());
+
+    request.max_tokens = 17;
+    let err = limits.check_request(&request).unwrap_err();
+    assert!(err.to_string().starts_with(LIMIT_EXCEEDED));
+
+    request.max_tokens = 16;
+    request.input = InferenceInput::Chat(vec![
+        common::ChatMessage {
+            role: "system".to_string(),
+            content: "12345".to_string(),
+        },
+        common::ChatMessage {
+            role: "user".to_string(),
+            content: "6789".to_string(),
+        },
+    ]);
+    let err = limits.check_request(&request).unwrap_err();
+    assert!(err.to_string().starts_with(REQUEST_TOO_LARGE));
+
+    assert!(limits.check_prompt_tokens(4).is_ok());
+    assert!(limits.check_prompt_tokens(5).is_err());
+    assert!(RequestLimits::default().check_max_tokens(u32::MAX).is_ok());
+}
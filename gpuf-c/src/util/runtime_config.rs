@@ -0,0 +1,279 @@
+use anyhow::{anyhow, Result};
+use common::WorkerConfig;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+use tokio::time::{sleep_until, Instant};
+use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
+
+pub const DEFAULT_HEARTBEAT_INTERVAL_SECS: u32 = 120;
+pub const DEFAULT_MONITOR_INTERVAL_SECS: u32 = 300;
+const MIN_INTERVAL_SECS: u32 = 5;
+
+static RUNTIME_CONFIG: OnceLock<Arc<RuntimeConfig>> = OnceLock::new();
+
+/// Settings currently in effect on this worker
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeSettings {
+    /// Version of the last config pushed by the server, 0 before any push
+    pub version: u64,
+    pub heartbeat_interval_secs: u32,
+    pub monitor_interval_secs: u32,
+    /// 0 means unlimited
+    pub max_concurrent_requests: u32,
+    pub log_level: Option<String>,
+    /// Seconds without requests before the model is unloaded, 0 never
+    pub idle_unload_secs: u32,
+}
+
+impl Default for RuntimeSettings {
+    fn default() -> Self {
+        Self {
+            version: 0,
+            heartbeat_interval_secs: DEFAULT_HEARTBEAT_INTERVAL_SECS,
+            monitor_interval_secs: DEFAULT_MONITOR_INTERVAL_SECS,
+            max_concurrent_requests: 0,
+            log_level: None,
+            idle_unload_secs: 0,
+        }
+    }
+}
+
+impl RuntimeSettings {
+    /// Settings after applying a pushed update, fields missing from the update are kept
+    pub fn merged(&self, version: u64, update: &WorkerConfig) -> Result<Self> {
+        if version < self.version {
+            return Err(anyhow!(
+                "Config version {} is older than the applied version {}",
+                version,
+                self.version
+            ));
+        }
+
+        let interval = |name: &str, value: Option<u32>, current: u32| match value {
+            Some(secs) if secs < MIN_INTERVAL_SECS => Err(anyhow!(
+                "{} must be at least {} seconds",
+                name,
+                MIN_INTERVAL_SECS
+            )),
+            Some(secs) => Ok(secs),
+            None => Ok(current),
+        };
+
+        if let Some(level) = &update.log_level {
+            LevelFilter::from_str(level).map_err(|_| anyhow!("Invalid log level: {}", level))?;
+        }
+
+        Ok(Self {
+            version,
+            heartbeat_interval_secs: interval(
+                "heartbeat_interval_secs",
+                update.heartbeat_interval_secs,
+                self.heartbeat_interval_secs,
+            )?,
+            monitor_interval_secs: interval(
+                "monitor_interval_secs",
+                update.monitor_interval_secs,
+                self.monitor_interval_secs,
+            )?,
+            max_concurrent_requests: update
+                .max_concurrent_requests
+                .unwrap_or(self.max_concurrent_requests),
+            log_level: update.log_level.clone().or_else(|| self.log_level.clone()),
+            idle_unload_secs: update.idle_unload_secs.unwrap_or(self.idle_unload_secs),
+        })
+    }
+}
+
+/// Runtime configuration shared by the worker tasks. It outlives a single
+/// server connection, so pushed settings survive reconnects.
+pub struct RuntimeConfig {
+    settings: watch::Sender<RuntimeSettings>,
+    in_flight: AtomicU32,
+    slot_freed: Notify,
+    /// When the last request started or finished
+    last_active: Mutex<Instant>,
+}
+
+impl RuntimeConfig {
+    pub fn global() -> Arc<RuntimeConfig> {
+        RUNTIME_CONFIG
+            .get_or_init(|| {
+                let (settings, _) = watch::channel(RuntimeSettings::default());
+                Arc::new(RuntimeConfig {
+                    settings,
+                    in_flight: AtomicU32::new(0),
+                    slot_freed: Notify::new(),
+                    last_active: Mutex::new(Instant::now()),
+                })
+            })
+            .clone()
+    }
+
+    pub fn settings(&self) -> RuntimeSettings {
+        self.settings.borrow().clone()
+    }
+
+    /// Validate and apply a config pushed by the server
+    pub fn apply(&self, version: u64, update: &WorkerConfig) -> Result<RuntimeSettings> {
+        let next = self.settings().merged(version, update)?;
+        if let Some(level) = &update.log_level {
+            crate::util::set_log_level(level)?;
+        }
+        info!("Applied config version {}: {:?}", version, next);
+        self.settings.send_replace(next.clone());
+        // A raised concurrency limit may unblock waiting requests
+        self.slot_freed.notify_waiters();
+        Ok(next)
+    }
+
+    /// Idle timeout given on the command line, unless the server pushed a
+    /// config already
+    pub fn set_default_idle_unload_secs(&self, secs: u32) {
+        self.settings.send_if_modified(|settings| {
+            let apply = settings.version == 0 && settings.idle_unload_secs != secs;
+            if apply {
+                settings.idle_unload_secs = secs;
+            }
+            apply
+        });
+    }
+
+    /// Count a request that bypasses the request slots as activity
+    pub fn mark_active(&self) {
+        *self.last_active.lock().unwrap() = Instant::now();
+    }
+
+    /// How long no request has been in flight, None while one is
+    pub fn idle_for(&self) -> Option<Duration> {
+        if self.in_flight.load(Ordering::SeqCst) > 0 {
+            return None;
+        }
+        Some(self.last_active.lock().unwrap().elapsed())
+    }
+
+    /// Ticker for a periodic task whose interval is taken from the settings
+    pub fn ticker(&self, period: fn(&RuntimeSettings) -> u32) -> ConfigTicker {
+        ConfigTicker {
+            settings: self.settings.subscribe(),
+            period,
+            last: None,
+        }
+    }
+
+    /// Wait until the number of in-flight requests is below `max_concurrent_requests`
+    pub async fn acquire_request_slot(self: &Arc<Self>) -> RequestSlot {
+        loop {
+            let notified = self.slot_freed.notified();
+            let limit = self.settings.borrow().max_concurrent_requests;
+            let current = self.in_flight.load(Ordering::SeqCst);
+            if limit == 0 || current < limit {
+                if self
+                    .in_flight
+                    .compare_exchange(current, current + 1, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    self.mark_active();
+                    return RequestSlot {
+                        config: Arc::clone(self),
+                    };
+                }
+                continue;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// One in-flight request, released on drop
+pub struct RequestSlot {
+    config: Arc<RuntimeConfig>,
+}
+
+impl Drop for RequestSlot {
+    fn drop(&mut self) {
+        self.config.mark_active();
+        self.config.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.config.slot_freed.notify_waiters();
+    }
+}
+
+/// Like `tokio::time::interval`, but picks up interval changes right away
+pub struct ConfigTicker {
+    settings: watch::Receiver<RuntimeSettings>,
+    period: fn(&RuntimeSettings) -> u32,
+    last: Option<Instant>,
+}
+
+impl ConfigTicker {
+    /// Completes immediately on the first call, then once per interval
+    pub async fn tick(&mut self) {
+        if let Some(last) = self.last {
+            loop {
+                let secs = (self.period)(&self.settings.borrow());
+                let deadline = last + Duration::from_secs(secs as u64);
+                tokio::select! {
+                    _ = sleep_until(deadline) => break,
+                    changed = self.settings.changed() => {
+                        if changed.is_err() {
+                            sleep_until(deadline).await;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.last = Some(Instant::now());
+    }
+}
+
+#[test]
+fn test_merge_runtime_settings() {
+    let current = RuntimeSettings::default();
+
+    let next = current
+        .merged(
+            3,
+            &WorkerConfig {
+                heartbeat_interval_secs: Some(30),
+                max_concurrent_requests: Some(4),
+                idle_unload_secs: Some(600),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(next.version, 3);
+    assert_eq!(next.heartbeat_interval_secs, 30);
+    assert_eq!(next.monitor_interval_secs, DEFAULT_MONITOR_INTERVAL_SECS);
+    assert_eq!(next.max_concurrent_requests, 4);
+    assert_eq!(next.idle_unload_secs, 600);
+    assert_eq!(
+        next.merged(5, &WorkerConfig::default())
+            .unwrap()
+            .idle_unload_secs,
+        600
+    );
+
+    assert!(current
+        .merged(
+            4,
+            &WorkerConfig {
+                heartbeat_interval_secs: Some(1),
+                ..Default::default()
+            }
+        )
+        .is_err());
+    assert!(next.merged(2, &WorkerConfig::default()).is_err());
+    assert!(current
+        .merged(
+            4,
+            &WorkerConfig {
+                log_level: Some("verbose".to_string()),
+                ..Default::default()
+            }
+        )
+        .is_err());
+}
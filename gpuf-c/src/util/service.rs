@@ -0,0 +1,397 @@
+//! Running the worker as a system service: `--install-service` registers a
+//! systemd unit or a Windows service that starts at boot and restarts the
+//! worker when it exits, and under systemd the worker reports readiness and
+//! pings the watchdog through `sd_notify`.
+
+use anyhow::{anyhow, Context, Result};
+use std::time::Duration;
+use tracing::info;
+
+pub const SERVICE_NAME: &str = "gpuf-c";
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+const SERVICE_DESCRIPTION: &str = "GPUFabric worker";
+/// How long the service manager waits before restarting a worker that exited
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+/// Watchdog timeout of the systemd unit, pinged at half this interval
+#[cfg(target_os = "linux")]
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Flags that only apply to the call installing or running the service
+const SERVICE_FLAGS: [&str; 3] = [
+    "--install-service",
+    "--uninstall-service",
+    "--run-as-service",
+];
+
+/// Arguments the service runs with: those of this call, without the program
+/// name and the service flags
+pub fn service_args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    args.into_iter()
+        .skip(1)
+        .filter(|arg| !SERVICE_FLAGS.contains(&arg.as_str()))
+        .collect()
+}
+
+/// Register and start the service, running this binary with `args`
+pub fn install(args: &[String]) -> Result<()> {
+    let exe = std::env::current_exe().context("Failed to locate the gpuf-c binary")?;
+    platform::install(&exe, args)?;
+    info!("Installed and started the {} service", SERVICE_NAME);
+    Ok(())
+}
+
+/// Stop the service and remove it
+pub fn uninstall() -> Result<()> {
+    platform::uninstall()?;
+    info!("Removed the {} service", SERVICE_NAME);
+    Ok(())
+}
+
+/// Tell systemd the worker is up, a no-op elsewhere
+pub fn notify_ready() {
+    sd_notify("READY=1");
+}
+
+/// Status line systemd shows for the service
+pub fn notify_status(status: &str) {
+    sd_notify(&format!("STATUS={}", status));
+}
+
+/// Interval to ping the systemd watchdog at, when the unit has one
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Ping the systemd watchdog for as long as the runtime keeps running, so a
+/// hung worker is restarted
+pub async fn run_watchdog(interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        sd_notify("WATCHDOG=1");
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn sd_notify(state: &str) {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let sent =
+        UnixDatagram::unbound().and_then(|socket| match path.as_bytes().strip_prefix(b"@") {
+            Some(name) => {
+                let addr = SocketAddr::from_abstract_name(name)?;
+                socket.send_to_addr(state.as_bytes(), &addr)
+            }
+            None => socket.send_to(state.as_bytes(), &path),
+        });
+    if let Err(e) = sent {
+        tracing::debug!("Failed to notify systemd: {}", e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sd_notify(_state: &str) {}
+
+/// Quote an argument for `ExecStart=`, keeping systemd from expanding
+/// specifiers and variables in it
+#[cfg(target_os = "linux")]
+fn systemd_quote(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(target_os = "linux")]
+fn systemd_unit(exe: &str, args: &[String], working_dir: &str, user: Option<&str>) -> String {
+    let exec_start = std::iter::once(exe)
+        .chain(args.iter().map(String::as_str))
+        .map(systemd_quote)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let user = user
+        .map(|user| format!("User={}\n", user))
+        .unwrap_or_default();
+    format!(
+        "[Unit]\n\
+         Description={description}\n\
+         Wants=network-online.target\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart={exec_start}\n\
+         WorkingDirectory={working_dir}\n\
+         {user}\
+         Restart=always\n\
+         RestartSec={restart_secs}\n\
+         WatchdogSec={watchdog_secs}\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        description = SERVICE_DESCRIPTION,
+        restart_secs = RESTART_DELAY.as_secs(),
+        watchdog_secs = WATCHDOG_TIMEOUT.as_secs(),
+    )
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+    use std::path::Path;
+    use std::process::Command;
+
+    const UNIT_PATH: &str = "/etc/systemd/system/gpuf-c.service";
+
+    fn systemctl(args: &[&str]) -> Result<()> {
+        let status = Command::new("systemctl")
+            .args(args)
+            .status()
+            .context("Failed to run systemctl")?;
+        if !status.success() {
+            return Err(anyhow!("systemctl {} failed: {}", args.join(" "), status));
+        }
+        Ok(())
+    }
+
+    pub fn install(exe: &Path, args: &[String]) -> Result<()> {
+        let working_dir = std::env::current_dir()?;
+        // Run as the user who called sudo, whose model cache and config
+        // the worker would use when started by hand
+        let user = std::env::var("SUDO_USER")
+            .ok()
+            .filter(|user| !user.is_empty() && user != "root");
+        let unit = systemd_unit(
+            &exe.to_string_lossy(),
+            args,
+            &working_dir.to_string_lossy(),
+            user.as_deref(),
+        );
+        std::fs::write(UNIT_PATH, unit)
+            .with_context(|| format!("Failed to write {}, run as root", UNIT_PATH))?;
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", "--now", SERVICE_NAME])
+    }
+
+    pub fn uninstall() -> Result<()> {
+        if !Path::new(UNIT_PATH).exists() {
+            return Err(anyhow!("{} is not installed", UNIT_PATH));
+        }
+        systemctl(&["disable", "--now", SERVICE_NAME])?;
+        std::fs::remove_file(UNIT_PATH)
+            .with_context(|| format!("Failed to remove {}", UNIT_PATH))?;
+        systemctl(&["daemon-reload"])
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::*;
+    use std::ffi::{OsStr, OsString};
+    use std::future::Future;
+    use std::path::Path;
+    use std::pin::Pin;
+    use std::sync::{Mutex, OnceLock};
+    use tracing::error;
+    use windows_service::service::{
+        ServiceAccess, ServiceAction, ServiceActionType, ServiceControl, ServiceControlAccept,
+        ServiceErrorControl, ServiceExitCode, ServiceFailureActions, ServiceFailureResetPeriod,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    type Worker = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+    /// The worker `run` hands to the service thread the dispatcher starts
+    static WORKER: Mutex<Option<Worker>> = Mutex::new(None);
+    static RUNTIME: OnceLock<tokio::runtime::Handle> = OnceLock::new();
+
+    pub fn install(exe: &Path, args: &[String]) -> Result<()> {
+        let manager = ServiceManager::local_computer(
+            None::<&str>,
+            ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+        )
+        .context("Failed to open the service manager, run as administrator")?;
+        let mut launch_arguments: Vec<OsString> = args.iter().map(OsString::from).collect();
+        launch_arguments.push(OsString::from("--run-as-service"));
+        let info = ServiceInfo {
+            name: OsString::from(SERVICE_NAME),
+            display_name: OsString::from(SERVICE_DESCRIPTION),
+            service_type: ServiceType::OWN_PROCESS,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path: exe.to_path_buf(),
+            launch_arguments,
+            dependencies: vec![],
+            account_name: None,
+            account_password: None,
+        };
+        let service =
+            manager.create_service(&info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)?;
+        service.set_description(SERVICE_DESCRIPTION)?;
+        // Restart after every failure, also when the worker exits with an error
+        let restart = || ServiceAction {
+            action_type: ServiceActionType::Restart,
+            delay: RESTART_DELAY,
+        };
+        service.update_failure_actions(ServiceFailureActions {
+            reset_period: ServiceFailureResetPeriod::After(Duration::from_secs(24 * 60 * 60)),
+            reboot_msg: None,
+            command: None,
+            actions: Some(vec![restart(), restart(), restart()]),
+        })?;
+        service.set_failure_actions_on_non_crash_failures(true)?;
+        service.start(&[] as &[&OsStr])?;
+        Ok(())
+    }
+
+    pub fn uninstall() -> Result<()> {
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+            .context("Failed to open the service manager, run as administrator")?;
+        let service = manager.open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )?;
+        if service.query_status()?.current_state != ServiceState::Stopped {
+            service.stop()?;
+        }
+        service.delete()?;
+        Ok(())
+    }
+
+    /// Run `worker` under the service control manager until the service is
+    /// stopped. Blocks the calling thread.
+    pub fn run(worker: impl Future<Output = Result<()>> + Send + 'static) -> Result<()> {
+        let _ = RUNTIME.set(tokio::runtime::Handle::current());
+        *WORKER.lock().unwrap() = Some(Box::pin(worker));
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+            .context("Failed to start the service dispatcher")
+    }
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = run_service() {
+            error!("{} service failed: {}", SERVICE_NAME, e);
+        }
+    }
+
+    fn status(state: ServiceState, exit_code: u32) -> ServiceStatus {
+        ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: match state {
+                ServiceState::Running => {
+                    ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+                }
+                _ => ServiceControlAccept::empty(),
+            },
+            exit_code: ServiceExitCode::Win32(exit_code),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        }
+    }
+
+    fn run_service() -> Result<()> {
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel();
+        let stop_tx = Mutex::new(Some(stop_tx));
+        let status_handle =
+            service_control_handler::register(SERVICE_NAME, move |control| match control {
+                ServiceControl::Stop | ServiceControl::Shutdown => {
+                    if let Some(stop) = stop_tx.lock().unwrap().take() {
+                        let _ = stop.send(());
+                    }
+                    ServiceControlHandlerResult::NoError
+                }
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                _ => ServiceControlHandlerResult::NotImplemented,
+            })?;
+        status_handle.set_service_status(status(ServiceState::Running, 0))?;
+
+        let worker = WORKER
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| anyhow!("No worker to run"))?;
+        let runtime = RUNTIME
+            .get()
+            .ok_or_else(|| anyhow!("No runtime to run the worker on"))?;
+        let result = runtime.block_on(async {
+            tokio::select! {
+                result = worker => result,
+                _ = stop_rx => Ok(()),
+            }
+        });
+
+        // A non-zero exit code makes the service manager apply the restart
+        let exit_code = if result.is_ok() { 0 } else { 1 };
+        status_handle.set_service_status(status(ServiceState::Stopped, exit_code))?;
+        result
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub use platform::run as run_windows_service;
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+mod platform {
+    use super::*;
+    use std::path::Path;
+
+    pub fn install(_exe: &Path, _args: &[String]) -> Result<()> {
+        Err(anyhow!(
+            "Services can only be installed on Linux and Windows"
+        ))
+    }
+
+    pub fn uninstall() -> Result<()> {
+        Err(anyhow!(
+            "Services can only be installed on Linux and Windows"
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_systemd_unit() {
+    let args = service_args(
+        [
+            "gpuf-c",
+            "--install-service",
+            "--config",
+            "my config.toml",
+            "--region",
+            "eu-$1",
+        ]
+        .map(String::from),
+    );
+    assert_eq!(args, ["--config", "my config.toml", "--region", "eu-$1"]);
+
+    let unit = systemd_unit("/usr/local/bin/gpuf-c", &args, "/home/gpu", Some("gpu"));
+    assert!(unit.contains(
+        "ExecStart=\"/usr/local/bin/gpuf-c\" \"--config\" \"my config.toml\" \"--region\" \"eu-$$1\"\n"
+    ));
+    assert!(unit.contains("Type=notify\n"));
+    assert!(unit.contains("User=gpu\n"));
+    assert!(unit.contains("Restart=always\n"));
+    assert!(!systemd_unit("/bin/gpuf-c", &[], "/", None).contains("User="));
+
+    assert_eq!(systemd_quote(r#"a"b\c%d"#), r#""a\"b\\c%%d""#);
+}
@@ -18,7 +18,11 @@ use tracing::warn;
 #[cfg(target_os = "macos")]
 use crate::util::device_info::read_power_metrics;
 
-#[cfg(all(not(target_os = "macos"), not(target_os = "android"), feature = "nvml"))]
+#[cfg(all(
+    not(target_os = "macos"),
+    not(target_os = "android"),
+    any(feature = "cuda", feature = "nvml")
+))]
 use nvml_wrapper::NVML;
 
 #[cfg(target_os = "macos")]
@@ -169,10 +173,32 @@ pub fn get_pci_ids_by_lspci(device_index: u32) -> Result<(u16, u16)> {
     Ok((vendor_id, device_id))
 }
 
-#[cfg(all(not(target_os = "macos"), not(target_os = "android"), feature = "nvml"))]
+/// Shared NVML handle, initialized on first use
+#[cfg(all(
+    not(target_os = "macos"),
+    not(target_os = "android"),
+    any(feature = "cuda", feature = "nvml")
+))]
+fn nvml_handle() -> Option<&'static NVML> {
+    static NVML_HANDLE: std::sync::OnceLock<Option<NVML>> = std::sync::OnceLock::new();
+    NVML_HANDLE
+        .get_or_init(|| match NVML::init() {
+            Ok(nvml) => Some(nvml),
+            Err(e) => {
+                debug!("NVML initialization failed: {}", e);
+                None
+            }
+        })
+        .as_ref()
+}
+
+#[cfg(all(
+    not(target_os = "macos"),
+    not(target_os = "android"),
+    any(feature = "cuda", feature = "nvml")
+))]
 pub fn get_gpu_count() -> Result<usize, Box<dyn std::error::Error>> {
-    // Initialize NVML
-    let nvml = NVML::init()?;
+    let nvml = nvml_handle().ok_or("NVML not available")?;
     // Get GPU device count
     let device_count = nvml.device_count()?;
     if device_count > 1 && !is_power_of_two_divide(device_count as i32) {
@@ -182,7 +208,7 @@ pub fn get_gpu_count() -> Result<usize, Box<dyn std::error::Error>> {
 }
 
 // Fallback implementation when NVML is not available
-#[cfg(all(not(target_os = "macos"), not(feature = "nvml")))]
+#[cfg(all(not(target_os = "macos"), not(any(feature = "cuda", feature = "nvml"))))]
 pub fn get_gpu_count() -> Result<usize, Box<dyn std::error::Error>> {
     debug!("NVML feature disabled. GPU count unavailable.");
     Ok(0)
@@ -452,7 +478,7 @@ fn read_disk_usage() -> Option<u32> {
 #[cfg(all(
     not(target_os = "macos"),
     not(target_os = "android"),
-    not(feature = "cuda")
+    not(any(feature = "cuda", feature = "nvml"))
 ))]
 pub async fn collect_device_info(engine_type: common::EngineType) -> Result<(DevicesInfo, u32)> {
     debug!("Using system API for device info (NVML available but not CUDA-specific).");
@@ -474,6 +500,10 @@ pub async fn collect_device_info(engine_type: common::EngineType) -> Result<(Dev
 
         #[cfg(target_os = "linux")]
         {
+            #[cfg(any(feature = "rocm", feature = "intel"))]
+            if let Some(found) = super::device_info::collect_vendor_gpus(engine_type) {
+                return Ok(found);
+            }
             collect_device_info_sysfs().await
         }
 
@@ -528,7 +558,7 @@ pub async fn collect_device_info(engine_type: common::EngineType) -> Result<(Dev
 }
 
 // Windows WMI-based device info collection
-#[cfg(all(target_os = "windows", not(feature = "nvml")))]
+#[cfg(all(target_os = "windows", not(any(feature = "cuda", feature = "nvml"))))]
 async fn collect_device_info_wmi() -> Result<(DevicesInfo, u32)> {
     use std::collections::HashMap;
     use wmi::{COMLibrary, Variant, WMIConnection};
@@ -608,7 +638,7 @@ async fn collect_device_info_wmi() -> Result<(DevicesInfo, u32)> {
 }
 
 // Linux sysfs-based device info collection
-#[cfg(all(target_os = "linux", not(feature = "nvml")))]
+#[cfg(all(target_os = "linux", not(any(feature = "cuda", feature = "nvml"))))]
 async fn collect_device_info_sysfs() -> Result<(DevicesInfo, u32)> {
     use std::fs;
 
@@ -673,7 +703,7 @@ async fn collect_device_info_sysfs() -> Result<(DevicesInfo, u32)> {
 #[cfg(all(
     not(target_os = "macos"),
     not(target_os = "android"),
-    not(feature = "nvml")
+    not(any(feature = "cuda", feature = "nvml"))
 ))]
 async fn collect_device_info_cpu() -> Result<(DevicesInfo, u32)> {
     let mut sys = System::new_all();
@@ -710,39 +740,18 @@ async fn collect_device_info_cpu() -> Result<(DevicesInfo, u32)> {
     Ok((device_info, (total_memory >> 30) as u32))
 }
 
-#[cfg(all(not(target_os = "macos"), not(target_os = "android"), feature = "cuda"))]
+#[cfg(all(
+    not(target_os = "macos"),
+    not(target_os = "android"),
+    any(feature = "cuda", feature = "nvml")
+))]
 pub async fn collect_device_info(engine_type: common::EngineType) -> Result<(DevicesInfo, u32)> {
     use common::{set_u16_to_u128, set_u8_to_u64, to_tflops};
-    use nvml_wrapper::NVML as NVMLWrapper;
-    use std::sync::Once;
-
-    static INIT: Once = Once::new();
-    static mut NVML: Option<Result<NVMLWrapper, nvml_wrapper::error::Error>> = None;
-
-    // Initialize NVML only once
-    INIT.call_once(|| unsafe {
-        NVML = Some(NVMLWrapper::init());
-    });
 
-    let nvml_ptr = &raw const NVML;
-
-    // Get the NVML instance
-    let nvml = match unsafe { &*nvml_ptr } {
-        Some(Ok(nvml)) => nvml,
-        Some(Err(e)) => {
-            debug!(
-                "NVML initialization failed: {}. Returning empty device list.",
-                e
-            );
-            return Err(anyhow::anyhow!("{:?}", e));
-        }
-        None => {
-            debug!("NVML not initialized. Returning empty device list.");
-            return Err(anyhow::anyhow!("NVML not initialized"));
-        }
+    let Some(nvml) = nvml_handle() else {
+        return Err(anyhow!("NVML not available"));
     };
 
-    // Rest of the function remains the same...
     match nvml.device_count() {
         Ok(count) => {
             let mut device_info = DevicesInfo::default();
@@ -832,16 +841,17 @@ pub async fn collect_device_info(engine_type: common::EngineType) -> Result<(Dev
                             index as usize,
                             utilization.gpu as u8,
                         );
+                        // VRAM in use, not memory controller utilization
                         set_u8_to_u64(
                             &mut device_info.mem_usage,
                             index as usize,
-                            utilization.memory as u8,
+                            (meminfo.used * 100 / meminfo.total.max(1)) as u8,
                         );
-                        //info!("power_usage {}", power_usage/1024);
+                        // NVML reports milliwatts
                         set_u8_to_u64(
                             &mut device_info.power_usage,
                             index as usize,
-                            (power_usage / 1024).try_into().unwrap(),
+                            (power_usage / 1000).min(u8::MAX as u32) as u8,
                         );
 
                         set_u8_to_u64(
@@ -856,12 +866,11 @@ pub async fn collect_device_info(engine_type: common::EngineType) -> Result<(Dev
                             index as usize,
                             (meminfo.total >> 30) as u16,
                         );
-                        //TODO: power_limit   watts unit
                         //TODO: total_memory  gb unit
                         set_u16_to_u128(
                             &mut device_info.powerlimit_w,
                             index as usize,
-                            power_limit as u16,
+                            (power_limit / 1000) as u16,
                         );
 
                         total_tflops += to_tflops(device_id).unwrap_or(0.0);
@@ -883,6 +892,86 @@ pub async fn collect_device_info(engine_type: common::EngineType) -> Result<(Dev
     }
 }
 
+/// Per-GPU utilization, VRAM, temperature, power and clocks for the heartbeat.
+/// Readings NVML does not support on a device are reported as 0.
+#[cfg(all(
+    not(target_os = "macos"),
+    not(target_os = "android"),
+    any(feature = "cuda", feature = "nvml")
+))]
+pub fn collect_gpu_metrics() -> Vec<common::GpuMetrics> {
+    use nvml_wrapper::enum_wrappers::device::{Clock, TemperatureSensor};
+
+    let Some(nvml) = nvml_handle() else {
+        return Vec::new();
+    };
+    let count = match nvml.device_count() {
+        Ok(count) => count,
+        Err(e) => {
+            debug!("Failed to get device count: {}", e);
+            return Vec::new();
+        }
+    };
+
+    (0..count)
+        .filter_map(|i| {
+            let device = nvml.device_by_index(i).ok()?;
+            let memory = device.memory_info().ok();
+            Some(common::GpuMetrics {
+                index: i,
+                name: device.name().unwrap_or_default(),
+                utilization_pct: device
+                    .utilization_rates()
+                    .map(|u| u.gpu.min(100) as u8)
+                    .unwrap_or(0),
+                memory_used_mb: memory.as_ref().map(|m| m.used >> 20).unwrap_or(0),
+                memory_total_mb: memory.as_ref().map(|m| m.total >> 20).unwrap_or(0),
+                temperature_c: device.temperature(TemperatureSensor::Gpu).unwrap_or(0),
+                power_draw_mw: device.power_usage().unwrap_or(0),
+                power_limit_mw: device.enforced_power_limit().unwrap_or(0),
+                graphics_clock_mhz: device.clock_info(Clock::Graphics).unwrap_or(0),
+                memory_clock_mhz: device.clock_info(Clock::Memory).unwrap_or(0),
+            })
+        })
+        .collect()
+}
+
+#[cfg(not(all(
+    not(target_os = "macos"),
+    not(target_os = "android"),
+    any(feature = "cuda", feature = "nvml")
+)))]
+pub fn collect_gpu_metrics() -> Vec<common::GpuMetrics> {
+    Vec::new()
+}
+
+/// Free VRAM summed over all GPUs, 0 without a discrete GPU NVML can read.
+/// Unified memory of Apple silicon and phones is counted as free RAM instead.
+#[cfg(all(
+    not(target_os = "macos"),
+    not(target_os = "android"),
+    any(feature = "cuda", feature = "nvml")
+))]
+pub fn free_vram_bytes() -> u64 {
+    let Some(nvml) = nvml_handle() else {
+        return 0;
+    };
+    let count = nvml.device_count().unwrap_or(0);
+    (0..count)
+        .filter_map(|i| nvml.device_by_index(i).ok()?.memory_info().ok())
+        .map(|memory| memory.free)
+        .sum()
+}
+
+#[cfg(not(all(
+    not(target_os = "macos"),
+    not(target_os = "android"),
+    any(feature = "cuda", feature = "nvml")
+)))]
+pub fn free_vram_bytes() -> u64 {
+    0
+}
+
 #[cfg(target_os = "macos")]
 fn _get_chip_info() -> String {
     let output = Command::new("sysctl")
@@ -964,6 +1053,22 @@ pub async fn collect_device_info(engine_type: common::EngineType) -> Result<(Dev
         gpu_power = metrics.total_mw;
     }
 
+    // Unified memory: the GPU gets what Metal lets it wire, not the whole RAM
+    let gpu = apple_gpu_info();
+    info!(
+        "Apple GPU: {} cores, Metal {}, {} GB unified memory, {} GB usable by the GPU",
+        gpu.gpu_cores,
+        if gpu.metal_supported { "supported" } else { "not supported" },
+        gpu.unified_memory_gb,
+        gpu.metal_working_set_gb
+    );
+    // Without Metal llama.cpp runs on the CPU out of the same RAM
+    let gpu_memory_gb = if gpu.metal_supported {
+        gpu.metal_working_set_gb
+    } else {
+        gpu.unified_memory_gb
+    };
+
     let device_info = DevicesInfo {
         pod_id: 0,
         num: 1,
@@ -971,7 +1076,7 @@ pub async fn collect_device_info(engine_type: common::EngineType) -> Result<(Dev
         ip: 0,
         os_type: common::OsType::MACOS,
         engine_type: engine_type,
-        memtotal_gb: (total_memory >> 30) as u16,
+        memtotal_gb: gpu_memory_gb as u16,
         usage: (gpu_busy * 100.) as u64,
         mem_usage: (used_memory as f32 / total_memory as f32 * 100.) as u64,
         power_usage: (gpu_power as f32 / 1000.) as u64,
@@ -979,13 +1084,86 @@ pub async fn collect_device_info(engine_type: common::EngineType) -> Result<(Dev
             .saturating_add_signed(rand::rng().random_range(-5i32..=5i32)) as u64,
         vendor_id: 0x6810 as u128,
         device_id: get_device_id().unwrap_or(0) as u128,
-        memsize_gb: (total_memory / 1024 / 1024) as u128,
+        memsize_gb: gpu_memory_gb as u128,
         powerlimit_w: gpu_power as u128,
         total_tflops: common::to_tflops(get_device_id().unwrap_or(0)).unwrap_or_default() as u16,
     };
     debug!("device_info: {:?}", device_info);
     debug!("total_memory: {} bytes", total_memory);
-    anyhow::Ok((device_info, (total_memory / 1024 / 1024) as u32))
+    anyhow::Ok((device_info, gpu_memory_gb))
+}
+
+/// Apple GPU as seen by Metal
+#[cfg(target_os = "macos")]
+#[derive(Debug, Default, Clone)]
+pub struct AppleGpuInfo {
+    pub gpu_cores: usize,
+    pub unified_memory_gb: u32,
+    /// Part of the unified memory the GPU may wire, the budget for offloaded layers
+    pub metal_working_set_gb: u32,
+    pub metal_supported: bool,
+}
+
+#[cfg(target_os = "macos")]
+pub fn apple_gpu_info() -> AppleGpuInfo {
+    let unified_memory_gb = sysctl_u64("hw.memsize").map(|b| (b >> 30) as u32).unwrap_or(0);
+    let metal_supported = system_profiler_display()
+        .map(|display| {
+            display["spdisplays_mtlgpufamilysupport"]
+                .as_str()
+                .or_else(|| display["spdisplays_metal"].as_str())
+                .is_some_and(|family| family.contains("metal"))
+        })
+        .unwrap_or(false);
+
+    AppleGpuInfo {
+        gpu_cores: get_apple_gpu_cores().unwrap_or(0),
+        unified_memory_gb,
+        metal_working_set_gb: metal_working_set_gb(
+            unified_memory_gb,
+            sysctl_u64("iogpu.wired_limit_mb").unwrap_or(0),
+        ),
+        metal_supported,
+    }
+}
+
+/// Metal's default `recommendedMaxWorkingSetSize` is about 2/3 of RAM, 3/4 above
+/// 36 GB. `iogpu.wired_limit_mb` overrides it when set.
+#[cfg(any(target_os = "macos", test))]
+fn metal_working_set_gb(unified_memory_gb: u32, wired_limit_mb: u64) -> u32 {
+    if wired_limit_mb > 0 {
+        return ((wired_limit_mb >> 10) as u32).min(unified_memory_gb);
+    }
+    if unified_memory_gb > 36 {
+        unified_memory_gb * 3 / 4
+    } else {
+        unified_memory_gb * 2 / 3
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn sysctl_u64(name: &str) -> Option<u64> {
+    let output = Command::new("sysctl").arg("-n").arg(name).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+/// First entry of `system_profiler SPDisplaysDataType`
+#[cfg(target_os = "macos")]
+fn system_profiler_display() -> Option<serde_json::Value> {
+    let output = Command::new("system_profiler")
+        .args(["SPDisplaysDataType", "-json"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let mut parsed: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+    Some(parsed["SPDisplaysDataType"][0].take())
 }
 
 #[cfg(target_os = "macos")]
@@ -1025,23 +1203,12 @@ fn map_thermal(level: &str) -> u32 {
 
 #[cfg(target_os = "macos")]
 pub fn get_apple_gpu_cores() -> Option<usize> {
-    let output = Command::new("system_profiler")
-        .args(["SPDisplaysDataType", "-json"])
-        .output()
-        .ok()?;
+    let display = system_profiler_display()?;
 
-    if !output.status.success() {
-        return None;
-    }
-
-    let output_str = String::from_utf8_lossy(&output.stdout);
-    let parsed: serde_json::Value = serde_json::from_str(&output_str).ok()?;
-
-    // Extract GPU core count
-    parsed["SPDisplaysDataType"][0]["spdisplays_gpu_cores"]
-        .as_array()?
-        .first()?
-        .as_str()?
+    // Apple Silicon reports the core count as `sppci_cores`
+    display["sppci_cores"]
+        .as_str()
+        .or_else(|| display["spdisplays_gpu_cores"].as_array()?.first()?.as_str())?
         .parse::<usize>()
         .ok()
 }
@@ -1239,3 +1406,11 @@ async fn test_collect_device_info() {
     println!("device_info: {:?}", device_info);
     assert!(device_info.is_ok());
 }
+
+#[test]
+fn test_metal_working_set_gb() {
+    assert_eq!(metal_working_set_gb(16, 0), 10);
+    assert_eq!(metal_working_set_gb(64, 0), 48);
+    assert_eq!(metal_working_set_gb(64, 57344), 56);
+    assert_eq!(metal_working_set_gb(16, 1 << 20), 16);
+}
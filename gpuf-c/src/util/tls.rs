@@ -0,0 +1,257 @@
+//! Trust in the gpuf-s proxy certificate: the `--cert-chain-path` file, a
+//! private CA bundle, and optional pins of the server certificate or key
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio_rustls::rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    client::WebPkiServerVerifier,
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, Error as TlsError, RootCertStore, SignatureScheme,
+};
+
+/// Prefix of SPKI pins, the form curl's `--pinnedpubkey` takes
+const SPKI_PIN_PREFIX: &str = "sha256/";
+
+/// Accepted server certificate, by SHA-256 hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerPin {
+    /// `sha256/<base64>` of the subject public key info, which survives
+    /// renewals that keep the key
+    Spki([u8; 32]),
+    /// Hex of the whole certificate, as `openssl x509 -fingerprint -sha256`
+    /// prints it, colons optional
+    Cert([u8; 32]),
+}
+
+impl FromStr for ServerPin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (bytes, spki) = match s.strip_prefix(SPKI_PIN_PREFIX) {
+            Some(b64) => (
+                BASE64
+                    .decode(b64)
+                    .map_err(|e| format!("Invalid base64 in pin {}: {}", s, e))?,
+                true,
+            ),
+            None => (
+                hex::decode(s.replace(':', ""))
+                    .map_err(|e| format!("Invalid hex in pin {}: {}", s, e))?,
+                false,
+            ),
+        };
+        let hash: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| format!("Pin {} is not a SHA-256 hash", s))?;
+        Ok(if spki {
+            ServerPin::Spki(hash)
+        } else {
+            ServerPin::Cert(hash)
+        })
+    }
+}
+
+impl ServerPin {
+    fn matches(&self, cert: &[u8]) -> bool {
+        match self {
+            ServerPin::Spki(hash) => {
+                spki(cert).is_some_and(|key| Sha256::digest(key)[..] == hash[..])
+            }
+            ServerPin::Cert(hash) => Sha256::digest(cert)[..] == hash[..],
+        }
+    }
+}
+
+/// `sha256/<base64>` pin of the key of `cert`, for error messages
+fn spki_pin(cert: &[u8]) -> String {
+    match spki(cert) {
+        Some(key) => format!("{}{}", SPKI_PIN_PREFIX, BASE64.encode(Sha256::digest(key))),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Client config for the proxy port. The certificates of `cert_chain_path`
+/// and of `ca_bundle` are trusted; `cert_chain_path` may be missing when a
+/// bundle is given. With `pins` the server certificate must also match one
+/// of them.
+pub fn client_config(
+    cert_chain_path: &str,
+    ca_bundle: Option<&str>,
+    pins: &[ServerPin],
+) -> Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    if ca_bundle.is_none() || Path::new(cert_chain_path).exists() {
+        roots.add_parsable_certificates(load_certs(cert_chain_path)?);
+    }
+    if let Some(ca_bundle) = ca_bundle {
+        let (added, ignored) = roots.add_parsable_certificates(load_certs(ca_bundle)?);
+        if added == 0 {
+            bail!(
+                "No usable CA certificates in {}, {} ignored",
+                ca_bundle,
+                ignored
+            );
+        }
+    }
+    if roots.is_empty() {
+        bail!("No usable CA certificates in {}", cert_chain_path);
+    }
+
+    if pins.is_empty() {
+        return Ok(ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth());
+    }
+    let verifier = PinnedVerifier {
+        inner: WebPkiServerVerifier::builder(Arc::new(roots))
+            .build()
+            .map_err(|e| anyhow!("Invalid CA certificates: {}", e))?,
+        pins: pins.to_vec(),
+    };
+    Ok(ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let f = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(f))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse {}", path))?;
+    if certs.is_empty() {
+        bail!("no certificates found in {}", path);
+    }
+    Ok(certs)
+}
+
+/// Verifies the chain as usual, then requires a pinned certificate
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<ServerPin>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, TlsError> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        if !self.pins.iter().any(|pin| pin.matches(end_entity)) {
+            return Err(TlsError::General(format!(
+                "Server certificate matches no --server-cert-pin, its key is pinned as {}",
+                spki_pin(end_entity)
+            )));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// DER element at the start of `der` as (tag, content, rest)
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n]
+            .iter()
+            .fold(0usize, |len, &b| (len << 8) | b as usize);
+        (len, &rest[n..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+/// DER of the subjectPublicKeyInfo of an X.509 certificate
+fn spki(cert: &[u8]) -> Option<&[u8]> {
+    let (_, cert, _) = der_element(cert)?;
+    let (_, mut tbs, _) = der_element(cert)?;
+    // Optional explicit version
+    if tbs.first() == Some(&0xa0) {
+        tbs = der_element(tbs)?.2;
+    }
+    // serialNumber, signature, issuer, validity and subject
+    for _ in 0..5 {
+        tbs = der_element(tbs)?.2;
+    }
+    let (_, _, rest) = der_element(tbs)?;
+    Some(&tbs[..tbs.len() - rest.len()])
+}
+
+#[test]
+fn test_server_pin() {
+    // Certificate { TBSCertificate { [0] version, serial, signature, issuer,
+    // validity, subject, spki, ... }, ... } with empty sequences
+    let key = [0x30, 0x04, 0x03, 0x02, 0x00, 0xff];
+    let mut tbs = vec![0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x01];
+    tbs.extend_from_slice(&[0x30, 0x00, 0x30, 0x00, 0x30, 0x00, 0x30, 0x00]);
+    tbs.extend_from_slice(&key);
+    let mut cert = vec![0x30, tbs.len() as u8 + 2, 0x30, tbs.len() as u8];
+    cert.extend_from_slice(&tbs);
+    assert_eq!(spki(&cert), Some(&key[..]));
+    assert_eq!(spki(&cert[..cert.len() - 1]), None);
+
+    let key_pin = spki_pin(&cert);
+    let pin: ServerPin = key_pin.parse().unwrap();
+    assert!(pin.matches(&cert));
+    let fingerprint = hex::encode_upper(Sha256::digest(&cert))
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| std::str::from_utf8(pair).unwrap())
+        .collect::<Vec<_>>()
+        .join(":");
+    let pin: ServerPin = fingerprint.parse().unwrap();
+    assert!(pin.matches(&cert));
+    assert!(!pin.matches(&key));
+
+    assert!("sha256/abc".parse::<ServerPin>().is_err());
+    assert!("zz".parse::<ServerPin>().is_err());
+}
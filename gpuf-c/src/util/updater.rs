@@ -0,0 +1,280 @@
+//! Self-update of the worker binary. The server offers the latest release
+//! with one build per platform; a worker on an older version downloads its
+//! build, checks the Ed25519 signature over its version, target and SHA-256
+//! digest against the keys pinned with `--update-key`, swaps it in for the running binary and
+//! restarts once no request was in flight for a while. Without pinned keys
+//! the worker only logs that a release is available.
+
+use crate::util::model_downloader::{DownloadConfig, ModelDownloader};
+use crate::util::model_signature;
+use crate::util::runtime_config::RuntimeConfig;
+use anyhow::{anyhow, Context, Result};
+use common::{is_newer_version, is_release_version, WorkerBuild, WorkerRelease};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// How long no request may have been in flight before the worker restarts
+const IDLE_WINDOW: Duration = Duration::from_secs(60);
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+static UPDATE_KEYS: OnceLock<Vec<Vec<u8>>> = OnceLock::new();
+/// Set while an update downloads or waits for the idle window
+static UPDATING: AtomicBool = AtomicBool::new(false);
+
+/// Pin the base64 Ed25519 public keys worker builds must be signed with. No
+/// keys turns self-update off.
+pub fn set_update_keys(keys: &[String]) -> Result<()> {
+    let keys = model_signature::decode_public_keys(keys, "update key")?;
+    let _ = UPDATE_KEYS.set(keys);
+    Ok(())
+}
+
+fn update_keys() -> &'static [Vec<u8>] {
+    UPDATE_KEYS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// Platform of this binary as builds of a release name it
+pub fn build_target() -> String {
+    let accelerator = if cfg!(feature = "cuda") {
+        "cuda"
+    } else if cfg!(feature = "vulkan") {
+        "vulkan"
+    } else if cfg!(feature = "metal") {
+        "metal"
+    } else {
+        "cpu"
+    };
+    format!(
+        "{}-{}-{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS,
+        accelerator
+    )
+}
+
+/// Build of `release` to update to, None when `current` is up to date or
+/// the release has no build for `target`
+fn pick_build<'a>(
+    release: &'a WorkerRelease,
+    current: &str,
+    target: &str,
+) -> Option<&'a WorkerBuild> {
+    if !is_newer_version(&release.version, current) {
+        return None;
+    }
+    release.builds.iter().find(|build| build.target == target)
+}
+
+/// Update to `release` when it is newer than this binary, then restart.
+/// `as_service` is set under the Windows service manager, see [`restart`].
+pub async fn apply(release: WorkerRelease, as_service: bool) -> Result<()> {
+    let current = env!("CARGO_PKG_VERSION");
+    let target = build_target();
+    let Some(build) = pick_build(&release, current, &target) else {
+        debug!(
+            "No update from {} to release {} for {}",
+            current, release.version, target
+        );
+        return Ok(());
+    };
+    if update_keys().is_empty() {
+        info!(
+            "gpuf-c {} is available, pin an --update-key to update automatically",
+            release.version
+        );
+        return Ok(());
+    }
+    if UPDATING.swap(true, Ordering::SeqCst) {
+        debug!("An update is in progress already");
+        return Ok(());
+    }
+    let result = install(build, &release.version, &target, as_service).await;
+    UPDATING.store(false, Ordering::SeqCst);
+    result
+}
+
+async fn install(build: &WorkerBuild, version: &str, target: &str, as_service: bool) -> Result<()> {
+    if !is_release_version(version) {
+        return Err(anyhow!(
+            "Refusing gpuf-c release with version '{}'",
+            version
+        ));
+    }
+    let exe = std::env::current_exe().context("Failed to locate the gpuf-c binary")?;
+    let staged = sibling_path(&exe, &format!(".{{}}-{}.download", version));
+    info!("Downloading gpuf-c {} from {}", version, build.url);
+    let downloader = ModelDownloader::new(DownloadConfig {
+        url: build.url.clone(),
+        output_path: staged.clone(),
+        expected_size: build.size,
+        ..Default::default()
+    });
+    downloader
+        .fetch()
+        .await
+        .with_context(|| format!("Failed to download gpuf-c {}", version))?;
+
+    let digest = model_signature::digest_files(std::slice::from_ref(&staged)).await?;
+    let message = signed_message(version, target, &digest);
+    if let Err(e) = model_signature::verify_signature(&message, &build.signature, update_keys()) {
+        let _ = tokio::fs::remove_file(&staged).await;
+        return Err(e.context(format!("gpuf-c {} is not signed by an update key", version)));
+    }
+    make_executable(&staged)?;
+
+    info!(
+        "gpuf-c {} verified, restarting after {}s without requests",
+        version,
+        IDLE_WINDOW.as_secs()
+    );
+    wait_for_idle().await;
+    swap(&exe, &staged)?;
+    info!("Updated to gpuf-c {}, restarting", version);
+    restart(&exe, as_service)
+}
+
+/// What the signature of a build covers. Binding the version and target
+/// keeps a signed build from being offered as another release or platform.
+fn signed_message(version: &str, target: &str, digest: &[u8; 32]) -> Vec<u8> {
+    format!("gpuf-c {} {} {}", version, target, hex::encode(digest)).into_bytes()
+}
+
+/// Path next to `exe`, named by `pattern` with `{}` standing for the binary's
+/// file name, so renaming it over the binary stays on one file system
+fn sibling_path(exe: &Path, pattern: &str) -> PathBuf {
+    let name = exe
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "gpuf-c".to_string());
+    exe.with_file_name(pattern.replace("{}", &name))
+}
+
+async fn wait_for_idle() {
+    let config = RuntimeConfig::global();
+    while !config.idle_for().is_some_and(|idle| idle >= IDLE_WINDOW) {
+        tokio::time::sleep(IDLE_CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Failed to make {:?} executable", path))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Replace the binary, keeping the old one as `<name>.old` to roll back by
+/// hand. A running binary can be renamed on every platform, but not always
+/// overwritten.
+fn swap(exe: &Path, staged: &Path) -> Result<()> {
+    let previous = sibling_path(exe, "{}.old");
+    let _ = std::fs::remove_file(&previous);
+    std::fs::rename(exe, &previous).with_context(|| format!("Failed to move {:?} aside", exe))?;
+    if let Err(e) = std::fs::rename(staged, exe) {
+        let _ = std::fs::rename(&previous, exe);
+        return Err(e).with_context(|| format!("Failed to replace {:?}", exe));
+    }
+    Ok(())
+}
+
+/// Run the new binary with the arguments of this process. On Unix it
+/// replaces the process, keeping its PID for systemd. Under the Windows
+/// service manager the worker exits with an error so the service restarts
+/// it; otherwise it starts the new binary and exits.
+fn restart(exe: &Path, as_service: bool) -> Result<()> {
+    let args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        let _ = as_service;
+        let e = std::process::Command::new(exe).args(&args).exec();
+        Err(anyhow!("Failed to restart gpuf-c: {}", e))
+    }
+    #[cfg(not(unix))]
+    {
+        if !as_service {
+            std::process::Command::new(exe)
+                .args(&args)
+                .spawn()
+                .context("Failed to start the updated gpuf-c")?;
+        }
+        std::process::exit(if as_service { 1 } else { 0 })
+    }
+}
+
+#[test]
+fn test_pick_build() {
+    let build = |target: &str| WorkerBuild {
+        target: target.to_string(),
+        url: format!("https://example.com/{}/gpuf-c", target),
+        size: None,
+        signature: "c2ln".to_string(),
+    };
+    let release = WorkerRelease {
+        version: "1.2.0".to_string(),
+        builds: vec![build("x86_64-linux-cpu"), build("x86_64-linux-cuda")],
+    };
+    assert_eq!(
+        pick_build(&release, "1.1.0", "x86_64-linux-cuda").map(|b| b.target.as_str()),
+        Some("x86_64-linux-cuda")
+    );
+    assert!(pick_build(&release, "1.1.0", "aarch64-macos-metal").is_none());
+    assert!(pick_build(&release, "1.2.0", "x86_64-linux-cpu").is_none());
+
+    let exe = Path::new("/opt/gpuf/gpuf-c");
+    assert_eq!(
+        sibling_path(exe, ".{}-1.2.0.download"),
+        Path::new("/opt/gpuf/.gpuf-c-1.2.0.download")
+    );
+    assert_eq!(
+        sibling_path(exe, "{}.old"),
+        Path::new("/opt/gpuf/gpuf-c.old")
+    );
+    assert_eq!(build_target().split('-').count(), 3);
+}
+
+#[test]
+fn test_signed_message() {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    let publisher = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+    let keys = vec![publisher.public_key().as_ref().to_vec()];
+    let digest = [3u8; 32];
+    let message = signed_message("1.2.0", "x86_64-linux-cuda", &digest);
+    assert!(String::from_utf8(message.clone())
+        .unwrap()
+        .starts_with("gpuf-c 1.2.0 x86_64-linux-cuda 0303"));
+    let signature = BASE64.encode(publisher.sign(&message).as_ref());
+
+    let verify = |version: &str, target: &str, digest: &[u8; 32]| {
+        model_signature::verify_signature(
+            &signed_message(version, target, digest),
+            &signature,
+            &keys,
+        )
+    };
+    assert!(verify("1.2.0", "x86_64-linux-cuda", &digest).is_ok());
+    assert!(verify("1.3.0", "x86_64-linux-cuda", &digest).is_err());
+    assert!(verify("1.2.0", "x86_64-linux-cpu", &digest).is_err());
+    assert!(verify("1.2.0", "x86_64-linux-cuda", &[4u8; 32]).is_err());
+    // A bare digest signature, as models use, is not accepted for a build
+    let digest_signature = BASE64.encode(publisher.sign(&digest).as_ref());
+    assert!(model_signature::verify_signature(
+        &signed_message("1.2.0", "x86_64-linux-cuda", &digest),
+        &digest_signature,
+        &keys
+    )
+    .is_err());
+}
@@ -0,0 +1,53 @@
+//! Build and platform versions the worker reports with its heartbeats, so the
+//! server can tell outdated workers apart and gate features by version.
+
+use common::{EngineVersion, WorkerVersion};
+use sysinfo::System;
+
+/// Cargo features this binary was built with
+pub fn features() -> Vec<String> {
+    [
+        ("cuda", cfg!(feature = "cuda")),
+        ("metal", cfg!(feature = "metal")),
+        ("vulkan", cfg!(feature = "vulkan")),
+        ("android", cfg!(feature = "android")),
+        ("nvml", cfg!(feature = "nvml")),
+        ("rocm", cfg!(feature = "rocm")),
+        ("intel", cfg!(feature = "intel")),
+        ("otel", cfg!(feature = "otel")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| feature.to_string())
+    .collect()
+}
+
+/// OS name and version with the kernel release, which carries the patch
+/// level on Linux, e.g. `Linux 22.04 Ubuntu (6.8.0-45-generic)`
+pub fn os_version() -> String {
+    let os = System::long_os_version().unwrap_or_else(|| std::env::consts::OS.to_string());
+    match System::kernel_version() {
+        Some(kernel) => format!("{} ({})", os, kernel),
+        None => os,
+    }
+}
+
+/// llama.cpp linked into this binary, versioned by the llama-cpp-sys-2
+/// release that pins its revision
+pub fn llama_cpp_version() -> Option<EngineVersion> {
+    let version = env!("GPUF_LLAMA_CPP_VERSION");
+    (!version.is_empty()).then(|| EngineVersion {
+        engine: "llama.cpp".to_string(),
+        version: version.to_string(),
+    })
+}
+
+/// Versions of this worker, with llama.cpp and `engine` as its engines
+pub fn worker_version(engine: Option<EngineVersion>) -> WorkerVersion {
+    WorkerVersion {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        engines: llama_cpp_version().into_iter().chain(engine).collect(),
+        features: features(),
+        os_version: os_version(),
+    }
+}
@@ -0,0 +1,20 @@
+-- Staged rollout of catalog entries. A canary entry is only assigned to
+-- rollout_percent of eligible workers plus the client ids (hex) in
+-- rollout_allowlist, until load results promote it to stable or roll it back.
+ALTER TABLE client_models
+ADD COLUMN IF NOT EXISTS rollout_state VARCHAR(16) NOT NULL DEFAULT 'stable',
+ADD COLUMN IF NOT EXISTS rollout_percent SMALLINT NOT NULL DEFAULT 100,
+ADD COLUMN IF NOT EXISTS rollout_allowlist TEXT[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE client_models
+ADD CONSTRAINT rollout_percent_check CHECK (rollout_percent BETWEEN 0 AND 100);
+
+-- Latest download result of each worker for each canary entry
+CREATE TABLE IF NOT EXISTS model_rollout_results (
+    model_id INTEGER NOT NULL REFERENCES client_models (id) ON DELETE CASCADE,
+    client_id BYTEA NOT NULL,
+    succeeded BOOLEAN NOT NULL,
+    error TEXT,
+    reported_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (model_id, client_id)
+);
@@ -0,0 +1,21 @@
+-- Inference failures reported by workers. `signature` is the error kind and
+-- the message with numbers and ids masked, so repeats of one failure group.
+CREATE TABLE IF NOT EXISTS "public"."inference_errors" (
+    "id" BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    "client_id" BYTEA NOT NULL,
+    "model" VARCHAR(255),
+    "severity" VARCHAR(16) NOT NULL,
+    "kind" VARCHAR(64) NOT NULL,
+    "message" TEXT NOT NULL,
+    "signature" VARCHAR(255) NOT NULL,
+    "request_id" VARCHAR(64),
+    "engine" VARCHAR(64),
+    "occurred_at" TIMESTAMPTZ NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_inference_errors_client_id_created_at
+ON "public"."inference_errors" (client_id, created_at DESC);
+
+CREATE INDEX IF NOT EXISTS idx_inference_errors_created_at
+ON "public"."inference_errors" (created_at);
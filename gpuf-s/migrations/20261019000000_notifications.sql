@@ -0,0 +1,33 @@
+-- Where providers want to hear about events of their devices and payouts.
+-- Digest preferences collect notifications and send them together.
+CREATE TABLE IF NOT EXISTS "public"."notification_preferences" (
+    "user_id" VARCHAR(64) NOT NULL,
+    "event_type" VARCHAR(32) NOT NULL,
+    "channel" VARCHAR(16) NOT NULL,
+    "target" VARCHAR(512) NOT NULL,
+    "digest" BOOLEAN NOT NULL DEFAULT FALSE,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY ("user_id", "event_type", "channel")
+);
+
+-- One notification for one preference, until it is sent or gives up
+CREATE TABLE IF NOT EXISTS "public"."notification_deliveries" (
+    "id" BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    "user_id" VARCHAR(64) NOT NULL,
+    "event_type" VARCHAR(32) NOT NULL,
+    "channel" VARCHAR(16) NOT NULL,
+    "target" VARCHAR(512) NOT NULL,
+    "digest" BOOLEAN NOT NULL,
+    "subject" TEXT NOT NULL,
+    "body" TEXT NOT NULL,
+    "attempts" INTEGER NOT NULL DEFAULT 0,
+    "last_error" TEXT,
+    "next_attempt_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "sent_at" TIMESTAMPTZ,
+    "failed_at" TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_notification_deliveries_pending
+ON "public"."notification_deliveries" (next_attempt_at)
+WHERE sent_at IS NULL AND failed_at IS NULL;
@@ -0,0 +1,10 @@
+-- Region the worker declared at login, or the country of its address
+ALTER TABLE "public"."gpu_assets" ADD COLUMN IF NOT EXISTS "region" VARCHAR(32);
+-- ISO 3166 country code of the worker's address, lowercase
+ALTER TABLE "public"."gpu_assets" ADD COLUMN IF NOT EXISTS "country" VARCHAR(2);
+
+CREATE INDEX IF NOT EXISTS idx_gpu_assets_region ON "public"."gpu_assets" ("region");
+
+-- Region the key prefers its workers in. Pinned keys only use workers there.
+ALTER TABLE "public"."tokens" ADD COLUMN IF NOT EXISTS "region" VARCHAR(32);
+ALTER TABLE "public"."tokens" ADD COLUMN IF NOT EXISTS "region_pinned" BOOLEAN NOT NULL DEFAULT FALSE;
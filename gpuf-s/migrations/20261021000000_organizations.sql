@@ -0,0 +1,27 @@
+-- Organizations own API keys and workers, and through their keys usage
+-- records. Keys and workers outside any organization stay with their user.
+CREATE TABLE IF NOT EXISTS "public"."organizations" (
+    "id" BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    "name" VARCHAR(128) NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- owner, admin or member
+CREATE TABLE IF NOT EXISTS "public"."organization_members" (
+    "org_id" BIGINT NOT NULL REFERENCES "public"."organizations" ("id") ON DELETE CASCADE,
+    "user_id" VARCHAR(64) NOT NULL,
+    "role" VARCHAR(16) NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY ("org_id", "user_id")
+);
+
+CREATE INDEX IF NOT EXISTS idx_organization_members_user_id
+ON "public"."organization_members" ("user_id");
+
+ALTER TABLE "public"."tokens"
+ADD COLUMN IF NOT EXISTS "org_id" BIGINT REFERENCES "public"."organizations" ("id");
+CREATE INDEX IF NOT EXISTS idx_tokens_org_id ON "public"."tokens" ("org_id");
+
+ALTER TABLE "public"."gpu_assets"
+ADD COLUMN IF NOT EXISTS "org_id" BIGINT REFERENCES "public"."organizations" ("id");
+CREATE INDEX IF NOT EXISTS idx_gpu_assets_org_id ON "public"."gpu_assets" ("org_id");
@@ -0,0 +1,23 @@
+-- Staff roles of JWT users on the admin endpoints: viewer, operator, admin
+-- or owner. Users without a row have no staff access.
+CREATE TABLE IF NOT EXISTS "public"."staff_roles" (
+    "user_id" VARCHAR(64) PRIMARY KEY,
+    "role" VARCHAR(16) NOT NULL,
+    "granted_by" VARCHAR(64),
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Requests of authenticated users the role check turned away, role NULL
+-- for users without one
+CREATE TABLE IF NOT EXISTS "public"."access_denials" (
+    "id" BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    "user_id" VARCHAR(64) NOT NULL,
+    "role" VARCHAR(16),
+    "permission" VARCHAR(32) NOT NULL,
+    "method" VARCHAR(16) NOT NULL,
+    "path" VARCHAR(512) NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_access_denials_created_at
+ON "public"."access_denials" (created_at DESC);
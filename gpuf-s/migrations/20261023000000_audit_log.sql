@@ -0,0 +1,37 @@
+-- Mutations made through the management and admin APIs. Rows are never
+-- changed or removed; the trigger below turns away UPDATE and DELETE.
+CREATE TABLE IF NOT EXISTS "public"."audit_log" (
+    "id" BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    -- User id, or api_key / admin_key for the shared keys
+    "actor" VARCHAR(64) NOT NULL,
+    "method" VARCHAR(16) NOT NULL,
+    -- Matched route, e.g. /api/admin/models/:id
+    "route" VARCHAR(256) NOT NULL,
+    "path" VARCHAR(512) NOT NULL,
+    "status" SMALLINT NOT NULL,
+    -- Request body with secrets redacted
+    "request" JSONB,
+    -- State before the change, for the endpoints that report it
+    "before" JSONB,
+    -- Data of the response
+    "after" JSONB,
+    "ip" VARCHAR(45),
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_created_at
+ON "public"."audit_log" (created_at DESC);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_actor
+ON "public"."audit_log" (actor, created_at DESC);
+
+CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS audit_log_append_only ON "public"."audit_log";
+CREATE TRIGGER audit_log_append_only
+BEFORE UPDATE OR DELETE ON "public"."audit_log"
+FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
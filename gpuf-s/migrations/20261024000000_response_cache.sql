@@ -0,0 +1,2 @@
+-- Keys whose temperature 0 completions are answered from the response cache
+ALTER TABLE "public"."tokens" ADD COLUMN IF NOT EXISTS "cache_responses" BOOLEAN NOT NULL DEFAULT FALSE;
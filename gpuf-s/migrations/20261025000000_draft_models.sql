@@ -0,0 +1,3 @@
+-- Draft model of a catalog entry, for speculative decoding on llama.cpp workers
+ALTER TABLE client_models ADD COLUMN IF NOT EXISTS draft_download_url TEXT;
+ALTER TABLE client_models ADD COLUMN IF NOT EXISTS draft_checksum VARCHAR(128);
@@ -0,0 +1,12 @@
+-- Build and platform the worker reported in its latest heartbeat, to find
+-- outdated workers
+ALTER TABLE "public"."gpu_assets" ADD COLUMN IF NOT EXISTS "worker_version" TEXT;
+-- [{"engine": "vllm", "version": "0.6.3"}, ...]
+ALTER TABLE "public"."gpu_assets" ADD COLUMN IF NOT EXISTS "engine_versions" JSONB;
+-- Cargo features the worker binary was built with, e.g. cuda
+ALTER TABLE "public"."gpu_assets" ADD COLUMN IF NOT EXISTS "build_features" TEXT[];
+-- OS name, version and kernel release
+ALTER TABLE "public"."gpu_assets" ADD COLUMN IF NOT EXISTS "os_version" TEXT;
+ALTER TABLE "public"."gpu_assets" ADD COLUMN IF NOT EXISTS "version_reported_at" TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_gpu_assets_worker_version ON "public"."gpu_assets" ("worker_version");
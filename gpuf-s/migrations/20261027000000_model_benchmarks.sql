@@ -0,0 +1,14 @@
+-- Results of `gpuf-c bench --upload`, the latest per worker and model
+CREATE TABLE IF NOT EXISTS "public"."client_model_benchmarks" (
+    "client_id" BYTEA NOT NULL REFERENCES "public"."gpu_assets" ("client_id") ON DELETE CASCADE,
+    -- File name of the GGUF model
+    "model" TEXT NOT NULL,
+    "model_bytes" BIGINT NOT NULL,
+    "n_gpu_layers" INTEGER NOT NULL,
+    -- [{"n_ctx": 2048, "n_batch": 512, "n_prompt": 512, "n_gen": 128,
+    --   "prompt_tokens_per_sec": 812.4, "gen_tokens_per_sec": 41.7,
+    --   "vram_bytes": ..., "ram_bytes": ...}, ...]
+    "runs" JSONB NOT NULL,
+    "reported_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY ("client_id", "model")
+);
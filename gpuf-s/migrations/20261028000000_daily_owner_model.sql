@@ -0,0 +1,15 @@
+-- Owner and model of the worker at its last heartbeat of the day, so payouts
+-- credit and price each day as it was rather than as the worker is today
+ALTER TABLE "public"."client_daily_stats" ADD COLUMN IF NOT EXISTS "user_id" VARCHAR;
+ALTER TABLE "public"."client_daily_stats" ADD COLUMN IF NOT EXISTS "model" VARCHAR;
+ALTER TABLE "public"."client_daily_stats" ADD COLUMN IF NOT EXISTS "model_version" VARCHAR;
+
+-- Earlier days were never recorded, the current owner and model is the best
+-- guess left for them
+UPDATE "public"."client_daily_stats" cds
+SET user_id = ga.user_id,
+    model = ga.model,
+    model_version = ga.model_version
+FROM "public"."gpu_assets" ga
+WHERE ga.client_id = cds.client_id
+  AND cds.user_id IS NULL;
@@ -0,0 +1,504 @@
+use crate::api_server::audit::{self, AuditTrail};
+use crate::api_server::auth::Principal;
+use crate::api_server::ApiServer;
+use crate::db::models::{self, MemoryProfile, ModelFilter, ModelSpec, Models};
+use crate::db::rollouts::{self, RolloutSummary, RolloutUpdate};
+use crate::util::msg::{ApiResponse, ListParams, Page};
+use crate::util::protoc::ClientId;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info};
+
+type AdminError = (StatusCode, Json<ApiResponse<()>>);
+type AdminResult<T> = Result<Json<ApiResponse<T>>, AdminError>;
+
+const GIB: i64 = 1024 * 1024 * 1024;
+
+fn admin_error(code: StatusCode, msg: impl Into<String>) -> AdminError {
+    (code, Json(ApiResponse::<()>::error(msg.into())))
+}
+
+fn db_error(context: &str, e: sqlx::Error) -> AdminError {
+    if e.as_database_error()
+        .is_some_and(|e| e.is_unique_violation())
+    {
+        return admin_error(
+            StatusCode::CONFLICT,
+            "A model with this name and version or version_code already exists",
+        );
+    }
+    error!("{}: {}", context, e);
+    admin_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+}
+
+/// The model catalog decides what the whole fleet downloads, end users cannot
+/// change it
+fn require_operator(principal: Option<&Principal>) -> Result<(), AdminError> {
+    match principal {
+        Some(Principal::User(_)) => Err(admin_error(StatusCode::FORBIDDEN, "Forbidden")),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DryRunQuery {
+    /// Validate and report fleet compatibility without writing the catalog
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// How many approved clients have enough memory for a model
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct FleetCompatibility {
+    pub required_memory_gb: i64,
+    pub total_clients: i64,
+    pub compatible_clients: i64,
+    pub online_compatible_clients: i64,
+    /// Clients that never reported their memory, counted as incompatible
+    pub unknown_memory_clients: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelChangeResponse {
+    /// The stored entry, None for a dry run
+    pub model: Option<Models>,
+    pub compatibility: FleetCompatibility,
+}
+
+/// Check a catalog entry before it is written. A download_url needs the
+/// SHA-256 checksum workers verify the file against.
+pub fn validate_model_spec(spec: &ModelSpec) -> Result<(), String> {
+    if spec.name.trim().is_empty() || spec.name.len() > 100 {
+        return Err("name must be 1 to 100 characters".to_string());
+    }
+    if spec.version.trim().is_empty() || spec.version.len() > 50 {
+        return Err("version must be 1 to 50 characters".to_string());
+    }
+    if spec.version_code <= 0 {
+        return Err("version_code must be positive".to_string());
+    }
+    if !(1..=8).contains(&spec.engine_type) {
+        return Err(format!("Unknown engine_type {}", spec.engine_type));
+    }
+    if spec.min_memory_mb.is_some_and(|mb| mb < 0) {
+        return Err("min_memory_mb must be zero or more".to_string());
+    }
+    if spec.min_gpu_memory_gb.is_some_and(|gb| gb < 0) {
+        return Err("min_gpu_memory_gb must be zero or more".to_string());
+    }
+    if spec.expected_size.is_some_and(|size| size <= 0) {
+        return Err("expected_size must be positive".to_string());
+    }
+    for (field, url) in [
+        ("download_url", &spec.download_url),
+        ("chunk_manifest_url", &spec.chunk_manifest_url),
+        ("draft_download_url", &spec.draft_download_url),
+    ] {
+        if let Some(url) = url {
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                _ => return Err(format!("{} must be an http(s) URL", field)),
+            }
+        }
+    }
+    validate_checksum(
+        "checksum",
+        &spec.checksum,
+        "download_url",
+        &spec.download_url,
+    )?;
+    validate_checksum(
+        "draft_checksum",
+        &spec.draft_checksum,
+        "draft_download_url",
+        &spec.draft_download_url,
+    )?;
+    Ok(())
+}
+
+/// A hex SHA-256 `checksum`, required when `url` is set
+fn validate_checksum(
+    field: &str,
+    checksum: &Option<String>,
+    url_field: &str,
+    url: &Option<String>,
+) -> Result<(), String> {
+    match checksum {
+        Some(checksum) => {
+            if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("{} must be a hex SHA-256 digest", field));
+            }
+        }
+        None if url.is_some() => {
+            return Err(format!("{} is required with {}", field, url_field));
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+/// Memory a client needs for the model: its min_gpu_memory_gb, or the file
+/// size rounded up to whole GB when that is larger
+fn required_memory_gb(spec: &ModelSpec) -> i64 {
+    let by_size = spec
+        .expected_size
+        .map(|size| (size + GIB - 1) / GIB)
+        .unwrap_or(0);
+    (spec.min_gpu_memory_gb.unwrap_or(0) as i64).max(by_size)
+}
+
+pub fn fleet_compatibility(spec: &ModelSpec, profiles: &[MemoryProfile]) -> FleetCompatibility {
+    let required_memory_gb = required_memory_gb(spec);
+    let mut report = FleetCompatibility {
+        required_memory_gb,
+        total_clients: 0,
+        compatible_clients: 0,
+        online_compatible_clients: 0,
+        unknown_memory_clients: 0,
+    };
+    for profile in profiles {
+        report.total_clients += profile.clients;
+        if profile.memory_gb <= 0 {
+            report.unknown_memory_clients += profile.clients;
+        } else if profile.memory_gb >= required_memory_gb {
+            report.compatible_clients += profile.clients;
+            report.online_compatible_clients += profile.online;
+        }
+    }
+    report
+}
+
+async fn check_spec(
+    app_state: &ApiServer,
+    spec: &ModelSpec,
+) -> Result<FleetCompatibility, AdminError> {
+    validate_model_spec(spec).map_err(|msg| admin_error(StatusCode::BAD_REQUEST, msg))?;
+    let profiles = models::fleet_memory_profiles(&app_state.db_pool)
+        .await
+        .map_err(|e| db_error("Failed to load fleet memory profiles", e))?;
+    Ok(fleet_compatibility(spec, &profiles))
+}
+
+// List catalog entries, e.g. /api/admin/models?name=llama&is_active=true
+pub async fn list_models(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Query(filter): Query<ModelFilter>,
+    Query(list): Query<ListParams>,
+) -> AdminResult<Page<Models>> {
+    require_operator(principal.as_deref())?;
+    let list = list
+        .resolve(&models::MODEL_LIST)
+        .map_err(|e| admin_error(StatusCode::BAD_REQUEST, e))?;
+
+    models::list_models(&app_state.db_pool, &filter, &list)
+        .await
+        .map(|page| Json(ApiResponse::success(page)))
+        .map_err(|e| db_error("Failed to list models", e))
+}
+
+// Add a catalog entry, /api/admin/models?dry_run=true only checks it
+pub async fn create_model(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Query(query): Query<DryRunQuery>,
+    Json(spec): Json<ModelSpec>,
+) -> AdminResult<ModelChangeResponse> {
+    require_operator(principal.as_deref())?;
+    let compatibility = check_spec(&app_state, &spec).await?;
+    if query.dry_run {
+        return Ok(Json(ApiResponse::success(ModelChangeResponse {
+            model: None,
+            compatibility,
+        })));
+    }
+
+    let model = models::insert_model(&app_state.db_pool, &spec)
+        .await
+        .map_err(|e| db_error("Failed to create model", e))?;
+    info!(
+        "Created model {}:{} (id {})",
+        model.name, model.version, model.id
+    );
+    Ok(Json(ApiResponse::success(ModelChangeResponse {
+        model: Some(model),
+        compatibility,
+    })))
+}
+
+// Replace a catalog entry, /api/admin/models/:id?dry_run=true only checks it
+pub async fn update_model(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path(id): Path<i32>,
+    Query(query): Query<DryRunQuery>,
+    Json(spec): Json<ModelSpec>,
+) -> AdminResult<ModelChangeResponse> {
+    require_operator(principal.as_deref())?;
+    let compatibility = check_spec(&app_state, &spec).await?;
+    let current = models::get_model(&app_state.db_pool, id)
+        .await
+        .map_err(|e| db_error("Failed to load model", e))?
+        .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Unknown model"))?;
+    if query.dry_run {
+        return Ok(Json(ApiResponse::success(ModelChangeResponse {
+            model: None,
+            compatibility,
+        })));
+    }
+    audit::set_before(trail.as_deref(), &current);
+
+    let model = models::update_model(&app_state.db_pool, id, &spec)
+        .await
+        .map_err(|e| db_error("Failed to update model", e))?
+        .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Unknown model"))?;
+    info!(
+        "Updated model {}:{} (id {})",
+        model.name, model.version, model.id
+    );
+    Ok(Json(ApiResponse::success(ModelChangeResponse {
+        model: Some(model),
+        compatibility,
+    })))
+}
+
+pub async fn activate_model(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path(id): Path<i32>,
+) -> AdminResult<Models> {
+    set_active(&app_state, principal.as_deref(), trail.as_deref(), id, true).await
+}
+
+pub async fn deactivate_model(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path(id): Path<i32>,
+) -> AdminResult<Models> {
+    set_active(
+        &app_state,
+        principal.as_deref(),
+        trail.as_deref(),
+        id,
+        false,
+    )
+    .await
+}
+
+async fn set_active(
+    app_state: &ApiServer,
+    principal: Option<&Principal>,
+    trail: Option<&AuditTrail>,
+    id: i32,
+    is_active: bool,
+) -> AdminResult<Models> {
+    require_operator(principal)?;
+    let current = models::get_model(&app_state.db_pool, id)
+        .await
+        .map_err(|e| db_error("Failed to load model", e))?
+        .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Unknown model"))?;
+    audit::set_before(trail, &current);
+
+    let model = models::set_model_active(&app_state.db_pool, id, is_active)
+        .await
+        .map_err(|e| db_error("Failed to change model state", e))?
+        .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Unknown model"))?;
+    info!(
+        "Model {}:{} {}",
+        model.name,
+        model.version,
+        if is_active {
+            "activated"
+        } else {
+            "deactivated"
+        }
+    );
+    Ok(Json(ApiResponse::success(model)))
+}
+
+// Remove a catalog entry. Versions pinned to clients are kept, deactivate
+// them or unpin the clients first.
+pub async fn delete_model(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path(id): Path<i32>,
+) -> AdminResult<()> {
+    require_operator(principal.as_deref())?;
+
+    let model = models::get_model(&app_state.db_pool, id)
+        .await
+        .map_err(|e| db_error("Failed to load model", e))?
+        .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Unknown model"))?;
+    let pinned = models::count_pinned_clients(&app_state.db_pool, &model.name, &model.version)
+        .await
+        .map_err(|e| db_error("Failed to count pinned clients", e))?;
+    if pinned > 0 {
+        return Err(admin_error(
+            StatusCode::CONFLICT,
+            format!("Model is pinned to {} clients", pinned),
+        ));
+    }
+    audit::set_before(trail.as_deref(), &model);
+
+    if !models::delete_model(&app_state.db_pool, id)
+        .await
+        .map_err(|e| db_error("Failed to delete model", e))?
+    {
+        return Err(admin_error(StatusCode::NOT_FOUND, "Unknown model"));
+    }
+    info!("Deleted model {}:{} (id {})", model.name, model.version, id);
+    Ok(Json(ApiResponse::success(())))
+}
+
+// Rollout state of an entry with the download results of its canary
+pub async fn get_rollout(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<i32>,
+) -> AdminResult<RolloutSummary> {
+    require_operator(principal.as_deref())?;
+
+    rollouts::get_summary(&app_state.db_pool, id)
+        .await
+        .map_err(|e| db_error("Failed to load rollout", e))?
+        .map(|summary| Json(ApiResponse::success(summary)))
+        .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Unknown model"))
+}
+
+// Start a canary, or promote or roll back an entry by hand
+pub async fn set_rollout(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<i32>,
+    Json(update): Json<RolloutUpdate>,
+) -> AdminResult<RolloutSummary> {
+    require_operator(principal.as_deref())?;
+
+    if update.percent.is_some_and(|p| !(0..=100).contains(&p)) {
+        return Err(admin_error(
+            StatusCode::BAD_REQUEST,
+            "percent must be 0 to 100",
+        ));
+    }
+    if let Some(allowlist) = &update.allowlist {
+        if let Some(bad) = allowlist.iter().find(|c| c.parse::<ClientId>().is_err()) {
+            return Err(admin_error(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid client_id {} in allowlist", bad),
+            ));
+        }
+    }
+
+    if !rollouts::set_rollout(&app_state.db_pool, id, &update)
+        .await
+        .map_err(|e| db_error("Failed to change rollout", e))?
+    {
+        return Err(admin_error(StatusCode::NOT_FOUND, "Unknown model"));
+    }
+    info!("Rollout of model {} set to {}", id, update.state.as_str());
+    get_rollout(State(app_state), principal, Path(id)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> ModelSpec {
+        ModelSpec {
+            name: "llama-3-8b".to_string(),
+            version: "1.0".to_string(),
+            version_code: 1,
+            engine_type: 1,
+            is_active: None,
+            min_memory_mb: None,
+            min_gpu_memory_gb: Some(8),
+            download_url: Some("https://models.example.com/llama-3-8b.gguf".to_string()),
+            checksum: Some("ab".repeat(32)),
+            expected_size: Some(5 * GIB + 1),
+            signature: None,
+            chunk_manifest_url: None,
+            draft_download_url: None,
+            draft_checksum: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_model_spec() {
+        assert_eq!(validate_model_spec(&spec()), Ok(()));
+
+        let mut bad = spec();
+        bad.download_url = Some("ftp://models.example.com/a.gguf".to_string());
+        assert!(validate_model_spec(&bad).is_err());
+
+        let mut bad = spec();
+        bad.checksum = None;
+        assert!(validate_model_spec(&bad).is_err());
+
+        let mut bad = spec();
+        bad.checksum = Some("xyz".to_string());
+        assert!(validate_model_spec(&bad).is_err());
+
+        let mut bad = spec();
+        bad.engine_type = 0;
+        assert!(validate_model_spec(&bad).is_err());
+
+        let mut drafted = spec();
+        drafted.draft_download_url = Some("https://models.example.com/draft.gguf".to_string());
+        assert!(validate_model_spec(&drafted).is_err());
+        drafted.draft_checksum = Some("cd".repeat(32));
+        assert_eq!(validate_model_spec(&drafted), Ok(()));
+    }
+
+    #[test]
+    fn test_fleet_compatibility() {
+        let profiles = [
+            MemoryProfile {
+                memory_gb: 0,
+                clients: 2,
+                online: 1,
+            },
+            MemoryProfile {
+                memory_gb: 4,
+                clients: 5,
+                online: 3,
+            },
+            MemoryProfile {
+                memory_gb: 8,
+                clients: 3,
+                online: 2,
+            },
+            MemoryProfile {
+                memory_gb: 24,
+                clients: 1,
+                online: 0,
+            },
+        ];
+        let report = fleet_compatibility(&spec(), &profiles);
+        assert_eq!(
+            report,
+            FleetCompatibility {
+                required_memory_gb: 8,
+                total_clients: 11,
+                compatible_clients: 4,
+                online_compatible_clients: 2,
+                unknown_memory_clients: 2,
+            }
+        );
+
+        let mut large = spec();
+        large.expected_size = Some(20 * GIB);
+        assert_eq!(
+            fleet_compatibility(&large, &profiles).required_memory_gb,
+            20
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use crate::api_server::auth::Principal;
+use crate::api_server::ApiServer;
+use crate::db::audit::{self, AuditEntry, AuditFilter, NewAuditEntry};
+use crate::util::msg::{ApiResponse, ListParams, Page};
+use crate::util::net::{client_ip, IpRange};
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, MatchedPath, Query, State},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Pool, Postgres};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tracing::{error, warn};
+
+/// Largest request body the audit layer buffers, the same as axum's `Json` limit
+const MAX_AUDITED_BODY: usize = 2 * 1024 * 1024;
+/// Fields whose name contains one of these are stored as [`REDACTED`]
+const SECRET_FIELDS: &[&str] = &["secret", "password", "token", "api_key", "private_key"];
+const REDACTED: &str = "[redacted]";
+
+type AuditError = (StatusCode, Json<ApiResponse<()>>);
+type AuditResult<T> = Result<Json<ApiResponse<T>>, AuditError>;
+
+fn audit_error(code: StatusCode, msg: impl Into<String>) -> AuditError {
+    (code, Json(ApiResponse::<()>::error(msg.into())))
+}
+
+/// State of [`audit_middleware`]
+#[derive(Clone)]
+pub struct Auditor {
+    pub pool: Pool<Postgres>,
+    /// Actor recorded for [`Principal::Service`], e.g. `api_key`
+    pub service_actor: &'static str,
+    /// Peers whose `X-Forwarded-For` is believed
+    pub trusted_proxies: Arc<Vec<IpRange>>,
+}
+
+/// Request extension for handlers to report the state a mutation replaces
+#[derive(Clone, Default)]
+pub struct AuditTrail(Arc<Mutex<Option<Value>>>);
+
+/// Keep `before` as the state the request changed, a no-op outside the audit layer
+pub fn set_before(trail: Option<&AuditTrail>, before: &impl Serialize) {
+    let Some(trail) = trail else {
+        return;
+    };
+    match serde_json::to_value(before) {
+        Ok(mut value) => {
+            redact(&mut value);
+            *trail.0.lock().unwrap() = Some(value);
+        }
+        Err(e) => warn!("Failed to serialize audit state: {}", e),
+    }
+}
+
+/// Record successful POST, PUT, PATCH and DELETE requests in `audit_log`
+/// with the caller, redacted request body, the state handlers reported
+/// before the change and the response data after it
+pub async fn audit_middleware(
+    State(auditor): State<Auditor>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    if !matches!(
+        *req.method(),
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    ) {
+        return next.run(req).await;
+    }
+
+    let (mut parts, body) = req.into_parts();
+    let body = match to_bytes(body, MAX_AUDITED_BODY).await {
+        Ok(body) => body,
+        Err(_) => {
+            return audit_error(StatusCode::PAYLOAD_TOO_LARGE, "Request body too large")
+                .into_response();
+        }
+    };
+    let request = parse_json(&parts.headers, &body);
+    let method = parts.method.clone();
+    let path = parts.uri.path().to_string();
+    let route = parts
+        .extensions
+        .get::<MatchedPath>()
+        .map_or_else(|| path.clone(), |p| p.as_str().to_string());
+    let actor = match parts.extensions.get::<Principal>() {
+        Some(Principal::Service) => auditor.service_actor.to_string(),
+        Some(Principal::User(user_id)) | Some(Principal::Staff { user_id, .. }) => user_id.clone(),
+        None => "anonymous".to_string(),
+    };
+    let peer = parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| client_ip(*addr));
+    let ip = peer.map(|peer| caller_ip(peer, &parts.headers, &auditor.trusted_proxies).to_string());
+    let trail = AuditTrail::default();
+    parts.extensions.insert(trail.clone());
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to read response of {} {}: {}", method, path, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    // The `data` of an ApiResponse, or the whole body of other responses
+    let after = parse_json(&parts.headers, &body).map(|mut value| match value.get_mut("data") {
+        Some(data) => data.take(),
+        None => value,
+    });
+    let before = trail.0.lock().unwrap().take();
+
+    let entry = NewAuditEntry {
+        actor: &actor,
+        method: method.as_str(),
+        route: &route,
+        path: &path,
+        status: status.as_u16(),
+        request: request.as_ref(),
+        before: before.as_ref(),
+        after: after.as_ref(),
+        ip: ip.as_deref(),
+    };
+    if let Err(e) = audit::insert_entry(&auditor.pool, &entry).await {
+        warn!(
+            "Failed to record {} {} in the audit log: {}",
+            method, path, e
+        );
+    }
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Redacted JSON body, None for empty and non-JSON bodies
+fn parse_json(headers: &HeaderMap, body: &[u8]) -> Option<Value> {
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !is_json || body.is_empty() {
+        return None;
+    }
+    let mut value = serde_json::from_slice(body).ok()?;
+    redact(&mut value);
+    Some(value)
+}
+
+/// Address of the caller behind `peer`. `X-Forwarded-For` is only believed
+/// from a trusted proxy, anyone else could write any address into it. Each
+/// proxy appends the address it saw, so the caller is the last address that
+/// is not a trusted proxy itself.
+fn caller_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpRange]) -> IpAddr {
+    let trusted = |ip: IpAddr| trusted_proxies.iter().any(|range| range.contains(ip));
+    if !trusted(peer) {
+        return peer;
+    }
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+        .map(|ip| ip.to_canonical())
+        .collect();
+    forwarded
+        .iter()
+        .rev()
+        .find(|ip| !trusted(**ip))
+        .or(forwarded.first())
+        .copied()
+        .unwrap_or(peer)
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                if SECRET_FIELDS.iter().any(|field| key.contains(field)) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+}
+
+// Audit log entries, newest first, e.g.
+// GET /api/admin/audit_log?filter=actor:42&start_date=2026-10-01
+pub async fn list_audit_log(
+    State(app_state): State<Arc<ApiServer>>,
+    Query(query): Query<AuditQuery>,
+    Query(list): Query<ListParams>,
+) -> AuditResult<Page<AuditEntry>> {
+    let list = list
+        .resolve(&audit::AUDIT_LIST)
+        .map_err(|e| audit_error(StatusCode::BAD_REQUEST, e))?;
+    let filter = AuditFilter {
+        start_date: query.start_date,
+        end_date: query.end_date,
+    };
+    let entries = audit::list_entries(&app_state.db_pool, &filter, &list)
+        .await
+        .map_err(|e| {
+            error!("Failed to list audit log: {}", e);
+            audit_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+        })?;
+    Ok(Json(ApiResponse::success(entries)))
+}
+
+#[test]
+fn test_redact() {
+    let mut value = serde_json::json!({
+        "name": "rtx-4090",
+        "api_key": "sk-123",
+        "config": {"webhook_secret": "s", "url": "https://example.com"},
+        "users": [{"Password": "hunter2", "id": 7}],
+    });
+    redact(&mut value);
+    assert_eq!(
+        value,
+        serde_json::json!({
+            "name": "rtx-4090",
+            "api_key": REDACTED,
+            "config": {"webhook_secret": REDACTED, "url": "https://example.com"},
+            "users": [{"Password": REDACTED, "id": 7}],
+        })
+    );
+
+    let mut headers = HeaderMap::new();
+    assert_eq!(parse_json(&headers, br#"{"a": 1}"#), None);
+    headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+    assert_eq!(
+        parse_json(&headers, br#"{"token": "t"}"#),
+        Some(serde_json::json!({"token": REDACTED}))
+    );
+    assert_eq!(parse_json(&headers, b""), None);
+}
+
+#[test]
+fn test_caller_ip() {
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    let trusted: Vec<IpRange> = vec!["10.0.0.0/8".parse().unwrap()];
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "x-forwarded-for",
+        "198.51.100.1, 203.0.113.7, 10.0.0.2".parse().unwrap(),
+    );
+
+    // A spoofed header from an untrusted peer is ignored
+    assert_eq!(
+        caller_ip(ip("192.0.2.9"), &headers, &trusted),
+        ip("192.0.2.9")
+    );
+    assert_eq!(caller_ip(ip("10.0.0.1"), &headers, &[]), ip("10.0.0.1"));
+    // The address left of the trusted proxies, not the one the caller sent
+    assert_eq!(
+        caller_ip(ip("10.0.0.1"), &headers, &trusted),
+        ip("203.0.113.7")
+    );
+    assert_eq!(
+        caller_ip(ip("10.0.0.1"), &HeaderMap::new(), &trusted),
+        ip("10.0.0.1")
+    );
+}
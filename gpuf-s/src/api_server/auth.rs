@@ -0,0 +1,392 @@
+use crate::api_server::ApiServer;
+use crate::db::staff::{self, NewDenial};
+use crate::util::msg::ApiResponse;
+use crate::util::rbac::{Permission, Role};
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{MatchedPath, Query, State},
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::{debug, error, warn};
+
+/// Cached JWKS are refreshed after this long, or earlier when an unknown `kid` shows up
+const JWKS_TTL: Duration = Duration::from_secs(600);
+/// Minimum interval between forced refreshes triggered by unknown key ids
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct JwtConfig {
+    pub issuer: String,
+    /// Taken from the issuer's OpenID discovery document when not set
+    pub jwks_url: Option<String>,
+    pub audience: Option<String>,
+    /// Claim holding the user id, `sub` by default
+    pub user_id_claim: String,
+}
+
+/// Authenticated caller of the management API
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Principal {
+    /// Shared API key, may act on behalf of any user
+    Service,
+    /// End user authenticated with a JWT
+    User(String),
+    /// JWT user whose staff role allows the admin endpoint it calls, may act
+    /// on behalf of any user there
+    Staff { user_id: String, role: Role },
+}
+
+impl Principal {
+    /// Resolve the user an endpoint should act on. JWT users are always mapped to
+    /// their own id and may not query other users.
+    pub fn resolve_user_id(
+        principal: Option<&Principal>,
+        requested: Option<&str>,
+    ) -> Result<Option<String>, StatusCode> {
+        match principal {
+            Some(Principal::User(user_id)) => match requested {
+                Some(requested) if !requested.is_empty() && requested != user_id => {
+                    Err(StatusCode::FORBIDDEN)
+                }
+                _ => Ok(Some(user_id.clone())),
+            },
+            _ => Ok(requested.filter(|s| !s.is_empty()).map(str::to_string)),
+        }
+    }
+}
+
+/// Like [`Principal::resolve_user_id`] for endpoints where a user id is mandatory
+pub fn require_user_id(
+    principal: Option<&Principal>,
+    requested: &str,
+) -> Result<String, StatusCode> {
+    Principal::resolve_user_id(principal, Some(requested))?.ok_or(StatusCode::BAD_REQUEST)
+}
+
+#[derive(Deserialize)]
+struct OpenIdConfiguration {
+    jwks_uri: String,
+}
+
+struct JwksCache {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+pub struct JwtVerifier {
+    config: JwtConfig,
+    http: reqwest::Client,
+    cache: RwLock<Option<JwksCache>>,
+}
+
+impl JwtVerifier {
+    pub fn new(config: JwtConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+            cache: RwLock::new(None),
+        }
+    }
+
+    async fn jwks_url(&self) -> Result<String> {
+        if let Some(url) = &self.config.jwks_url {
+            return Ok(url.clone());
+        }
+        let discovery = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let doc: OpenIdConfiguration = self
+            .http
+            .get(&discovery)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(doc.jwks_uri)
+    }
+
+    async fn fetch_keys(&self) -> Result<JwkSet> {
+        let url = self.jwks_url().await?;
+        debug!("Fetching JWKS from {}", url);
+        Ok(self
+            .http
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn decoding_key(&self, kid: &str) -> Result<DecodingKey> {
+        {
+            let cache = self.cache.read().await;
+            if let Some(cache) = cache.as_ref() {
+                let fresh = cache.fetched_at.elapsed() < JWKS_TTL;
+                match cache.keys.find(kid) {
+                    Some(jwk) if fresh => return Ok(DecodingKey::from_jwk(jwk)?),
+                    None if cache.fetched_at.elapsed() < JWKS_MIN_REFRESH => {
+                        return Err(anyhow!("Unknown key id {}", kid));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let keys = self.fetch_keys().await?;
+        let key = keys
+            .find(kid)
+            .map(DecodingKey::from_jwk)
+            .transpose()?
+            .ok_or_else(|| anyhow!("Unknown key id {}", kid));
+        *self.cache.write().await = Some(JwksCache {
+            keys,
+            fetched_at: Instant::now(),
+        });
+        key
+    }
+
+    /// Validate signature, issuer, audience and expiry, and return the user id claim
+    pub async fn verify(&self, token: &str) -> Result<String> {
+        let header = decode_header(token)?;
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(anyhow!("Symmetric JWT algorithms are not accepted"));
+        }
+        let kid = header.kid.ok_or_else(|| anyhow!("JWT has no kid"))?;
+        let key = self.decoding_key(&kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer]);
+        match &self.config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let data = decode::<HashMap<String, Value>>(token, &key, &validation)?;
+        match data.claims.get(&self.config.user_id_claim) {
+            Some(Value::String(s)) if !s.is_empty() => Ok(s.clone()),
+            Some(Value::Number(n)) => Ok(n.to_string()),
+            _ => Err(anyhow!(
+                "JWT is missing claim {}",
+                self.config.user_id_claim
+            )),
+        }
+    }
+}
+
+/// Authentication settings of the management API. With neither an API key nor
+/// a JWT issuer configured, the API is left open as before.
+#[derive(Default)]
+pub struct ApiAuth {
+    pub api_key: Option<String>,
+    pub jwt: Option<JwtVerifier>,
+}
+
+impl ApiAuth {
+    pub fn new(api_key: Option<String>, jwt: Option<JwtConfig>) -> Self {
+        Self {
+            api_key: api_key.filter(|k| !k.is_empty()),
+            jwt: jwt.map(JwtVerifier::new),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.api_key.is_some() || self.jwt.is_some()
+    }
+
+    pub async fn authenticate(&self, credential: &str) -> Result<Principal> {
+        // Constant time, so response timing does not leak how much of a guess matched
+        let key_matches = self.api_key.as_deref().is_some_and(|key| {
+            ring::constant_time::verify_slices_are_equal(key.as_bytes(), credential.as_bytes())
+                .is_ok()
+        });
+        if key_matches {
+            return Ok(Principal::Service);
+        }
+        match &self.jwt {
+            Some(verifier) => verifier.verify(credential).await.map(Principal::User),
+            None => Err(anyhow!("Invalid API key")),
+        }
+    }
+}
+
+pub async fn auth_middleware(
+    State(app_state): State<Arc<ApiServer>>,
+    mut req: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    if req.method() == axum::http::Method::OPTIONS || !app_state.auth.enabled() {
+        return next.run(req).await;
+    }
+
+    let credential = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .or_else(|| req.headers().get("x-api-key").and_then(|v| v.to_str().ok()))
+        .map(str::to_string)
+        .or_else(|| websocket_token(&req));
+
+    let Some(credential) = credential else {
+        return unauthorized("Missing credentials");
+    };
+
+    match app_state.auth.authenticate(&credential).await {
+        Ok(principal) => {
+            req.extensions_mut().insert(principal);
+            next.run(req).await
+        }
+        Err(e) => {
+            warn!("Management API authentication failed: {}", e);
+            unauthorized("Invalid credentials")
+        }
+    }
+}
+
+/// Check the staff role of JWT users on admin endpoints, which then act as
+/// [`Principal::Staff`]. The shared API key acts as owner. Denied requests
+/// are recorded in `access_denials`. With authentication off nobody has a
+/// role, so admin endpoints are refused.
+pub async fn rbac_middleware(
+    State(app_state): State<Arc<ApiServer>>,
+    mut req: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| req.uri().path(), MatchedPath::as_str);
+    let Some(permission) = Permission::for_api(req.method(), route) else {
+        return next.run(req).await;
+    };
+    let user_id = match req.extensions().get::<Principal>().cloned() {
+        Some(Principal::User(user_id)) => user_id,
+        Some(_) => return next.run(req).await,
+        None => {
+            warn!(
+                "Refused {} {} with management API authentication off",
+                req.method(),
+                req.uri().path()
+            );
+            return (
+                StatusCode::FORBIDDEN,
+                Json(ApiResponse::<()>::error(format!(
+                    "Requires the {} permission, set --api-key or --jwt-issuer",
+                    permission.as_str()
+                ))),
+            )
+                .into_response();
+        }
+    };
+
+    let role = match staff::get_role(&app_state.db_pool, &user_id).await {
+        Ok(role) => role,
+        Err(e) => {
+            error!("Failed to get staff role of {}: {}", user_id, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::<()>::error(
+                    "internal server error".to_string(),
+                )),
+            )
+                .into_response();
+        }
+    };
+    match role {
+        Some(role) if role.allows(permission) => {
+            req.extensions_mut()
+                .insert(Principal::Staff { user_id, role });
+            next.run(req).await
+        }
+        _ => {
+            let denial = NewDenial {
+                user_id: &user_id,
+                role,
+                permission,
+                method: req.method().as_str(),
+                path: req.uri().path(),
+            };
+            record_denial(&app_state.db_pool, &denial).await;
+            (
+                StatusCode::FORBIDDEN,
+                Json(ApiResponse::<()>::error(format!(
+                    "Requires the {} permission",
+                    permission.as_str()
+                ))),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Log a denied request and keep it for the denial listing
+pub async fn record_denial(pool: &sqlx::Pool<sqlx::Postgres>, denial: &NewDenial<'_>) {
+    warn!(
+        "Denied {} {} to user {} with role {}: requires {}",
+        denial.method,
+        denial.path,
+        denial.user_id,
+        denial.role.map_or("none", Role::as_str),
+        denial.permission.as_str()
+    );
+    if let Err(e) = staff::record_denial(pool, denial).await {
+        warn!("Failed to record access denial: {}", e);
+    }
+}
+
+/// Browsers cannot set headers on WebSocket handshakes, so those may pass
+/// the credential as an `access_token` query parameter
+fn websocket_token(req: &Request<axum::body::Body>) -> Option<String> {
+    let upgrade = req.headers().get(header::UPGRADE)?.to_str().ok()?;
+    if !upgrade.eq_ignore_ascii_case("websocket") {
+        return None;
+    }
+    let Query(mut params) = Query::<HashMap<String, String>>::try_from_uri(req.uri()).ok()?;
+    params.remove("access_token")
+}
+
+fn unauthorized(message: &str) -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(ApiResponse::<()>::error(message.to_string())),
+    )
+        .into_response()
+}
+
+#[test]
+fn test_resolve_user_id() {
+    let user = Principal::User("42".to_string());
+    assert_eq!(
+        Principal::resolve_user_id(Some(&user), None),
+        Ok(Some("42".to_string()))
+    );
+    assert_eq!(
+        Principal::resolve_user_id(Some(&user), Some("42")),
+        Ok(Some("42".to_string()))
+    );
+    assert_eq!(
+        Principal::resolve_user_id(Some(&user), Some("7")),
+        Err(StatusCode::FORBIDDEN)
+    );
+    assert_eq!(
+        Principal::resolve_user_id(Some(&Principal::Service), Some("7")),
+        Ok(Some("7".to_string()))
+    );
+    assert_eq!(Principal::resolve_user_id(None, Some("")), Ok(None));
+}
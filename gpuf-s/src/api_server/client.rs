@@ -1,18 +1,21 @@
 use axum::{
-    extract::{Query, State},
+    extract::{Extension, Query, State},
     http::StatusCode,
     Json,
 };
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
-use crate::util::msg::ApiResponse;
+use crate::util::msg::{ApiResponse, ListParams, Page};
 use crate::util::protoc::ClientId;
 use std::sync::Arc;
 use tracing::{error, info};
 
+use crate::api_server::auth::{require_user_id, Principal};
+use crate::api_server::orgs;
 use crate::api_server::ApiServer;
 use crate::api_server::ClientInfoResponse;
+use crate::db::orgs::OrgRole;
 use crate::db::stats::{ClientHeartbeatInfo, ClientMonitorInfo};
 use crate::db::{
     client::{self, ClientDeviceDetailResponse, ClientDeviceInfo},
@@ -23,6 +26,7 @@ use crate::db::{
 #[derive(Debug, Validate, Serialize, Deserialize)]
 pub struct CreateClientRequest {
     #[validate(length(min = 1, max = 32))]
+    #[serde(default)]
     pub user_id: String,
     #[validate(length(min = 1, max = 32))]
     pub client_id: String,
@@ -35,14 +39,84 @@ pub struct CreateClientRequest {
 
 #[derive(serde::Serialize)]
 pub struct ClientListResponse {
-    pub total: usize,
+    /// Clients matching the filters over all pages
+    pub total: i64,
+    pub page: u32,
+    pub page_size: u32,
+    pub next_cursor: Option<String>,
     pub devices: Vec<ClientDeviceInfo>,
 }
 
+impl ClientListResponse {
+    fn from_page(page: Page<ClientDeviceInfo>) -> Self {
+        Self {
+            total: page.total,
+            page: page.page,
+            page_size: page.page_size,
+            next_cursor: page.next_cursor,
+            devices: page.items,
+        }
+    }
+}
+
+/// Client listing shared by the client list and client status list
+async fn list_user_clients(
+    app_state: &ApiServer,
+    principal: Option<&Principal>,
+    query: &ClientListQuery,
+    list: &ListParams,
+) -> Result<ClientListResponse, StatusCode> {
+    let user_id = require_user_id(principal, &query.user_id)?;
+    if let Some(org_id) = query.org_id {
+        orgs::authorize_org(
+            &app_state.db_pool,
+            principal,
+            Some(&user_id),
+            org_id,
+            OrgRole::Member,
+        )
+        .await?;
+    }
+    let list = list.resolve(&client::CLIENT_LIST).map_err(|e| {
+        info!("Invalid client list parameters: {}", e);
+        StatusCode::BAD_REQUEST
+    })?;
+    let mut page = client::get_user_client_status_list(
+        &app_state.db_pool,
+        &user_id,
+        query.org_id,
+        query.client_id.as_ref(),
+        query.status.as_ref(),
+        query.name.as_ref(),
+        query.valid_status.as_ref(),
+        &list,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to get user clients: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let client_ids: Vec<String> = page.items.iter().map(|d| d.client_id.clone()).collect();
+    let models_map =
+        client::get_loaded_models_batch_from_redis(&app_state.redis_client, &client_ids)
+            .await
+            .unwrap_or_default();
+    for d in &mut page.items {
+        if let Some(models) = models_map.get(&d.client_id) {
+            d.loaded_models = models.clone();
+        }
+    }
+    Ok(ClientListResponse::from_page(page))
+}
+
 //#@ get_user_clients api
 #[derive(Debug, Deserialize)]
 pub struct ClientListQuery {
+    #[serde(default)]
     pub user_id: String,
+    /// Clients of this organization instead of the user's
+    pub org_id: Option<i64>,
     pub client_id: Option<String>,
     pub status: Option<String>,
     pub name: Option<String>,
@@ -52,8 +126,10 @@ pub struct ClientListQuery {
 // API Handlers
 pub async fn insert_client(
     State(app_state): State<Arc<ApiServer>>,
-    Json(payload): Json<CreateClientRequest>,
+    principal: Option<Extension<Principal>>,
+    Json(mut payload): Json<CreateClientRequest>,
 ) -> Result<Json<ApiResponse<Vec<ClientInfoResponse>>>, StatusCode> {
+    payload.user_id = require_user_id(principal.as_deref(), &payload.user_id)?;
     if payload.user_id.is_empty() || payload.client_id.is_empty() {
         error!("Invalid user_id or client_id");
         return Err(StatusCode::BAD_REQUEST);
@@ -83,76 +159,28 @@ pub async fn insert_client(
 // #get_user_clients
 pub async fn get_user_clients(
     State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
     Query(query): Query<ClientListQuery>,
+    Query(list): Query<ListParams>,
 ) -> Result<Json<ApiResponse<ClientListResponse>>, StatusCode> {
-    // Get database connection
-    let mut devices = client::get_user_client_status_list(
-        &app_state.db_pool,
-        &query.user_id,
-        query.client_id.as_ref(),
-        query.status.as_ref(),
-        query.name.as_ref(),
-        query.valid_status.as_ref(),
-    )
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to get user clients: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    let client_ids: Vec<String> = devices.iter().map(|d| d.client_id.clone()).collect();
-    let models_map = client::get_loaded_models_batch_from_redis(&app_state.redis_client, &client_ids)
-        .await
-        .unwrap_or_default();
-    for d in &mut devices {
-        if let Some(models) = models_map.get(&d.client_id) {
-            d.loaded_models = models.clone();
-        }
-    }
-    let response = ClientListResponse {
-        total: devices.len(),
-        devices,
-    };
+    let response = list_user_clients(&app_state, principal.as_deref(), &query, &list).await?;
     Ok(Json(ApiResponse::success(response)))
 }
 
 pub async fn get_user_client_status_list(
     State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
     Query(query): Query<ClientListQuery>,
+    Query(list): Query<ListParams>,
 ) -> Result<Json<ApiResponse<ClientListResponse>>, StatusCode> {
-    let mut devices = client::get_user_client_status_list(
-        &app_state.db_pool,
-        &query.user_id,
-        query.client_id.as_ref(),
-        query.status.as_ref(),
-        query.name.as_ref(),
-        query.valid_status.as_ref(),
-    )
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to get user status clients: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    let client_ids: Vec<String> = devices.iter().map(|d| d.client_id.clone()).collect();
-    let models_map = client::get_loaded_models_batch_from_redis(&app_state.redis_client, &client_ids)
-        .await
-        .unwrap_or_default();
-    for d in &mut devices {
-        if let Some(models) = models_map.get(&d.client_id) {
-            d.loaded_models = models.clone();
-        }
-    }
-    let response = ClientListResponse {
-        total: devices.len(),
-        devices,
-    };
+    let response = list_user_clients(&app_state, principal.as_deref(), &query, &list).await?;
     Ok(Json(ApiResponse::success(response)))
 }
 
 //#@ get_user_clients_device_detail api
 #[derive(Debug, Deserialize)]
 pub struct ClientDetailQuery {
+    #[serde(default)]
     pub user_id: String,
     pub client_id: String,
     #[allow(dead_code)] // Optional query parameters for future filtering
@@ -163,21 +191,22 @@ pub struct ClientDetailQuery {
 
 pub async fn get_client_detail(
     State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
     Query(query): Query<ClientDetailQuery>,
 ) -> Result<Json<ApiResponse<ClientDeviceDetailResponse>>, StatusCode> {
+    let user_id = require_user_id(principal.as_deref(), &query.user_id)?;
     // Get database connection
     let client_id_bytes = query
         .client_id
         .parse::<ClientId>()
         .map_err(|_| StatusCode::BAD_REQUEST)?;
 
-    let devices =
-        client::get_client_device_detail(&app_state.db_pool, &query.user_id, &client_id_bytes)
-            .await
-            .map_err(|e| {
-                tracing::error!("/api/user/client_list: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
+    let devices = client::get_client_device_detail(&app_state.db_pool, &user_id, &client_id_bytes)
+        .await
+        .map_err(|e| {
+            tracing::error!("/api/user/client_list: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
     Ok(Json(ApiResponse::success(devices)))
 }
@@ -185,8 +214,10 @@ pub async fn get_client_detail(
 // Edit client info handler
 pub async fn edit_client_info(
     State(app_state): State<Arc<ApiServer>>,
-    Json(payload): Json<EditClientRequest>,
+    principal: Option<Extension<Principal>>,
+    Json(mut payload): Json<EditClientRequest>,
 ) -> Result<Json<ApiResponse<()>>, StatusCode> {
+    payload.user_id = require_user_id(principal.as_deref(), &payload.user_id)?;
     if payload.user_id.is_empty() || payload.client_id.is_empty() {
         error!("Missing required fields");
         return Err(StatusCode::BAD_REQUEST);
@@ -221,17 +252,20 @@ pub async fn edit_client_info(
 // Request query parameters
 #[derive(Debug, Deserialize)]
 pub struct ClientStatQuery {
+    #[serde(default)]
     pub user_id: String,
 }
 
 pub async fn get_client_stats(
     State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
     Query(query): Query<ClientStatQuery>,
 ) -> Result<Json<ApiResponse<stats::ClientStatResponse>>, StatusCode> {
+    let user_id = require_user_id(principal.as_deref(), &query.user_id)?;
     // Get database connection
     let devices = stats::get_client_stats(
         &app_state.db_pool,
-        &query.user_id,
+        &user_id,
         Some(time::Duration::minutes(2)),
         Some(time::Duration::hours(48)),
     )
@@ -248,21 +282,23 @@ pub async fn get_client_stats(
 #[derive(Debug, Validate, Serialize, Deserialize)]
 pub struct ClientMonitorQuery {
     #[validate(length(min = 1, max = 32))]
+    #[serde(default)]
     pub user_id: String,
     pub client_id: Option<String>,
 }
 
 pub async fn get_client_monitor(
     State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
     Query(query): Query<ClientMonitorQuery>,
 ) -> Result<Json<ApiResponse<Vec<ClientMonitorInfo>>>, StatusCode> {
-    let devices_info =
-        stats::get_client_monitor(&app_state.db_pool, &query.user_id, query.client_id)
-            .await
-            .map_err(|e| {
-                tracing::error!("Failed to get client stats: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
+    let user_id = require_user_id(principal.as_deref(), &query.user_id)?;
+    let devices_info = stats::get_client_monitor(&app_state.db_pool, &user_id, query.client_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get client stats: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
     Ok(Json(ApiResponse::success(devices_info)))
 }
@@ -270,6 +306,7 @@ pub async fn get_client_monitor(
 #[derive(Debug, Validate, Serialize, Deserialize)]
 pub struct ClientHealthQuery {
     #[validate(length(min = 1, max = 32))]
+    #[serde(default)]
     pub user_id: String,
     pub client_id: Option<String>,
     pub start_date: Option<String>,
@@ -278,11 +315,13 @@ pub struct ClientHealthQuery {
 
 pub async fn get_client_health(
     State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
     Query(query): Query<ClientHealthQuery>,
 ) -> Result<Json<ApiResponse<Vec<ClientHeartbeatInfo>>>, StatusCode> {
+    let user_id = require_user_id(principal.as_deref(), &query.user_id)?;
     let devices_info = stats::get_client_heartbeats(
         &app_state.db_pool,
-        &query.user_id,
+        &user_id,
         query.client_id,
         query.start_date,
         query.end_date,
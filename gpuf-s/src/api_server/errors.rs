@@ -0,0 +1,247 @@
+use crate::api_server::auth::Principal;
+use crate::api_server::ApiServer;
+use crate::db::dashboard_events::{self, DashboardEvent, DashboardEventKind};
+use crate::db::inference_errors::{
+    self, ErrorFilter, ErrorSeverity, InferenceError, ModelErrorCount, NewInferenceError,
+    SignatureCount,
+};
+use crate::util::msg::{ApiResponse, ListParams, Page};
+use crate::util::protoc::ClientId;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, warn};
+use validator::Validate;
+
+/// Reports one client may send per minute, the rest are dropped with 429
+const MAX_REPORTS_PER_MINUTE: u64 = 30;
+/// Reports dated further ahead than this are rejected as clock errors
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+const DEFAULT_SIGNATURE_LIMIT: i64 = 20;
+const MAX_SIGNATURE_LIMIT: i64 = 100;
+
+type ErrorsError = (StatusCode, Json<ApiResponse<()>>);
+type ErrorsResult<T> = Result<Json<ApiResponse<T>>, ErrorsError>;
+
+fn errors_error(code: StatusCode, msg: impl Into<String>) -> ErrorsError {
+    (code, Json(ApiResponse::<()>::error(msg.into())))
+}
+
+fn internal_error(context: &str, e: impl std::fmt::Display) -> ErrorsError {
+    error!("{}: {}", context, e);
+    errors_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct InferenceErrorReport {
+    pub client_id: String,
+    #[validate(length(min = 1, max = 255))]
+    pub model: Option<String>,
+    #[serde(default = "default_severity")]
+    pub severity: ErrorSeverity,
+    /// Short class of the failure, e.g. `oom` or `timeout`
+    #[validate(length(min = 1, max = 64))]
+    pub kind: String,
+    #[validate(length(min = 1, max = 8192))]
+    pub message: String,
+    #[validate(length(max = 64))]
+    pub request_id: Option<String>,
+    #[validate(length(max = 64))]
+    pub engine: Option<String>,
+    /// When the failure happened, the time of the report when omitted
+    pub occurred_at: Option<DateTime<Utc>>,
+}
+
+fn default_severity() -> ErrorSeverity {
+    ErrorSeverity::Error
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReportResponse {
+    pub id: i64,
+}
+
+// Store an inference failure a worker reports. Workers hold no management
+// credentials, so this route is outside the auth layer and only takes
+// reports of known, valid clients.
+pub async fn report_inference_error(
+    State(app_state): State<Arc<ApiServer>>,
+    Json(report): Json<InferenceErrorReport>,
+) -> ErrorsResult<ReportResponse> {
+    report
+        .validate()
+        .map_err(|e| errors_error(StatusCode::BAD_REQUEST, e.to_string()))?;
+    let client_id: ClientId = report
+        .client_id
+        .parse()
+        .map_err(|_| errors_error(StatusCode::BAD_REQUEST, "Invalid client_id"))?;
+    let now = Utc::now();
+    let occurred_at = report.occurred_at.unwrap_or(now);
+    if occurred_at > now + Duration::seconds(MAX_CLOCK_SKEW_SECS) {
+        return Err(errors_error(
+            StatusCode::BAD_REQUEST,
+            "occurred_at is in the future",
+        ));
+    }
+
+    // Limits are best effort, a Redis failure lets the report through
+    match inference_errors::count_client_report(&app_state.redis_client, &client_id).await {
+        Ok(count) if count > MAX_REPORTS_PER_MINUTE => {
+            return Err(errors_error(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many error reports from this client",
+            ));
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Error report rate check failed for {}: {}", client_id, e),
+    }
+
+    let id = inference_errors::insert_inference_error(
+        &app_state.db_pool,
+        &NewInferenceError {
+            client_id: &client_id,
+            model: report.model.as_deref(),
+            severity: report.severity,
+            kind: &report.kind,
+            message: &report.message,
+            request_id: report.request_id.as_deref(),
+            engine: report.engine.as_deref(),
+            occurred_at,
+        },
+    )
+    .await
+    .map_err(|e| internal_error("Failed to store error report", e))?
+    .ok_or_else(|| errors_error(StatusCode::NOT_FOUND, "Unknown client"))?;
+
+    let event = DashboardEvent::new(DashboardEventKind::ErrorReported {
+        client_id,
+        model: report.model,
+        severity: report.severity,
+        kind: report.kind,
+    });
+    if let Err(e) = dashboard_events::publish(&app_state.redis_client, &event).await {
+        warn!("Failed to publish error report of {}: {}", client_id, e);
+    }
+    Ok(Json(ApiResponse::success(ReportResponse { id })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ErrorQuery {
+    pub client_id: Option<String>,
+    pub model: Option<String>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    /// Signatures to return, top signatures only
+    pub limit: Option<i64>,
+}
+
+/// Reports the caller may see, providers only those of their own clients
+fn error_filter(
+    principal: Option<&Principal>,
+    query: &ErrorQuery,
+) -> Result<ErrorFilter, ErrorsError> {
+    let client_id = query
+        .client_id
+        .as_deref()
+        .map(str::parse::<ClientId>)
+        .transpose()
+        .map_err(|_| errors_error(StatusCode::BAD_REQUEST, "Invalid client_id"))?;
+    let user_id = match principal {
+        Some(Principal::User(user_id)) => Some(user_id.clone()),
+        _ => None,
+    };
+    Ok(ErrorFilter {
+        user_id,
+        client_id,
+        model: query.model.clone(),
+        start_date: query.start_date,
+        end_date: query.end_date,
+    })
+}
+
+// List error reports, e.g. GET /api/errors?model=llama3&filter=severity:fatal
+pub async fn list_errors(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Query(query): Query<ErrorQuery>,
+    Query(list): Query<ListParams>,
+) -> ErrorsResult<Page<InferenceError>> {
+    let filter = error_filter(principal.as_deref(), &query)?;
+    let list = list
+        .resolve(&inference_errors::ERROR_LIST)
+        .map_err(|e| errors_error(StatusCode::BAD_REQUEST, e))?;
+    inference_errors::list_inference_errors(&app_state.db_pool, &filter, &list)
+        .await
+        .map(|page| Json(ApiResponse::success(page)))
+        .map_err(|e| internal_error("Failed to list error reports", e))
+}
+
+// Most frequent error signatures, e.g. GET /api/errors/signatures?start_date=2025-08-01
+pub async fn top_signatures(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Query(query): Query<ErrorQuery>,
+) -> ErrorsResult<Vec<SignatureCount>> {
+    let filter = error_filter(principal.as_deref(), &query)?;
+    let limit = query.limit.unwrap_or(DEFAULT_SIGNATURE_LIMIT);
+    if !(1..=MAX_SIGNATURE_LIMIT).contains(&limit) {
+        return Err(errors_error(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be 1 to {}", MAX_SIGNATURE_LIMIT),
+        ));
+    }
+    inference_errors::top_error_signatures(&app_state.db_pool, &filter, limit)
+        .await
+        .map(|rows| Json(ApiResponse::success(rows)))
+        .map_err(|e| internal_error("Failed to count error signatures", e))
+}
+
+// Error reports per model, e.g. GET /api/errors/models?start_date=2025-08-01
+pub async fn errors_by_model(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Query(query): Query<ErrorQuery>,
+) -> ErrorsResult<Vec<ModelErrorCount>> {
+    let filter = error_filter(principal.as_deref(), &query)?;
+    inference_errors::errors_per_model(&app_state.db_pool, &filter)
+        .await
+        .map(|rows| Json(ApiResponse::success(rows)))
+        .map_err(|e| internal_error("Failed to count errors per model", e))
+}
+
+#[test]
+fn test_inference_error_report() {
+    let report: InferenceErrorReport = serde_json::from_value(serde_json::json!({
+        "client_id": "0123456789abcdef0123456789abcdef",
+        "kind": "oom",
+        "message": "CUDA out of memory",
+    }))
+    .unwrap();
+    assert_eq!(report.severity, ErrorSeverity::Error);
+    assert!(report.validate().is_ok());
+
+    let report: InferenceErrorReport = serde_json::from_value(serde_json::json!({
+        "client_id": "0123456789abcdef0123456789abcdef",
+        "severity": "fatal",
+        "kind": "",
+        "message": "engine exited",
+    }))
+    .unwrap();
+    assert_eq!(report.severity, ErrorSeverity::Fatal);
+    assert!(report.validate().is_err());
+
+    assert!(
+        serde_json::from_value::<InferenceErrorReport>(serde_json::json!({
+            "client_id": "0123456789abcdef0123456789abcdef",
+            "severity": "panic",
+            "kind": "oom",
+            "message": "x",
+        }))
+        .is_err()
+    );
+}
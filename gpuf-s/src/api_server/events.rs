@@ -0,0 +1,212 @@
+use crate::api_server::auth::Principal;
+use crate::api_server::ApiServer;
+use crate::db::client;
+use crate::db::dashboard_events::{self, DashboardEvent};
+use crate::util::msg::ApiResponse;
+use crate::util::protoc::ClientId;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::StatusCode,
+    response::Response,
+    Extension, Json,
+};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, warn};
+
+/// Keeps proxies from closing idle event streams
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+type EventsError = (StatusCode, Json<ApiResponse<()>>);
+
+fn events_error(code: StatusCode, msg: impl Into<String>) -> EventsError {
+    (code, Json(ApiResponse::<()>::error(msg.into())))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    /// Comma-separated event types, all when omitted
+    pub types: Option<String>,
+    /// Comma-separated client ids in hex, all visible clients when omitted
+    pub client_ids: Option<String>,
+}
+
+/// Which events a subscriber gets
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    pub types: Option<HashSet<String>>,
+    pub clients: Option<HashSet<ClientId>>,
+    /// Providers only see events of their own workers
+    pub workers_only: bool,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &DashboardEvent) -> bool {
+        if let Some(types) = &self.types {
+            if !types.contains(event.kind.name()) {
+                return false;
+            }
+        }
+        match event.kind.client_id() {
+            Some(client_id) => self
+                .clients
+                .as_ref()
+                .map_or(true, |clients| clients.contains(&client_id)),
+            None => !self.workers_only && self.clients.is_none(),
+        }
+    }
+}
+
+fn split_list(raw: Option<&str>) -> Option<Vec<&str>> {
+    raw.map(|raw| {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect()
+    })
+}
+
+// Stream live events to a dashboard, e.g. GET /api/events/ws?types=worker_online,worker_offline
+pub async fn events_ws(
+    ws: WebSocketUpgrade,
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Query(query): Query<EventsQuery>,
+) -> Result<Response, EventsError> {
+    let types = split_list(query.types.as_deref())
+        .map(|types| types.into_iter().map(str::to_string).collect());
+    let mut clients: Option<HashSet<ClientId>> = split_list(query.client_ids.as_deref())
+        .map(|ids| {
+            ids.into_iter()
+                .map(|id| {
+                    id.parse::<ClientId>().map_err(|_| {
+                        events_error(StatusCode::BAD_REQUEST, format!("Invalid client_id {}", id))
+                    })
+                })
+                .collect()
+        })
+        .transpose()?;
+
+    let mut workers_only = false;
+    if let Some(Principal::User(user_id)) = principal.as_deref() {
+        // Ownership is checked once, workers added later need a reconnect
+        let owned: HashSet<ClientId> = client::get_user_client_ids(&app_state.db_pool, user_id)
+            .await
+            .map_err(|e| {
+                error!("Failed to load clients of {}: {}", user_id, e);
+                events_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+            })?
+            .into_iter()
+            .collect();
+        clients = Some(match clients {
+            Some(requested) => requested.intersection(&owned).copied().collect(),
+            None => owned,
+        });
+        workers_only = true;
+    }
+
+    let filter = EventFilter {
+        types,
+        clients,
+        workers_only,
+    };
+    Ok(ws.on_upgrade(move |socket| stream_events(socket, app_state, filter)))
+}
+
+async fn stream_events(mut socket: WebSocket, app_state: Arc<ApiServer>, filter: EventFilter) {
+    let events = match dashboard_events::subscribe(&app_state.redis_client).await {
+        Ok(events) => events,
+        Err(e) => {
+            error!("Failed to subscribe to dashboard events: {}", e);
+            let _ = socket.send(Message::Close(None)).await;
+            return;
+        }
+    };
+    let mut events = std::pin::pin!(events);
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    ping.tick().await;
+
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let Some(event) = event else {
+                    warn!("Dashboard event subscription closed");
+                    let _ = socket.send(Message::Close(None)).await;
+                    return;
+                };
+                if !filter.matches(&event) {
+                    continue;
+                }
+                let json = match serde_json::to_string(&event) {
+                    Ok(json) => json,
+                    Err(e) => {
+                        warn!("Failed to serialize dashboard event: {}", e);
+                        continue;
+                    }
+                };
+                if socket.send(Message::Text(json)).await.is_err() {
+                    return;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | None => return,
+                Some(Err(e)) => {
+                    debug!("Dashboard event stream closed: {}", e);
+                    return;
+                }
+                // Subscribers do not send anything, pongs are answered by axum
+                Some(Ok(_)) => {}
+            },
+            _ = ping.tick() => {
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_event_filter() {
+    use crate::db::dashboard_events::DashboardEventKind;
+
+    let mine = ClientId([1; 16]);
+    let other = ClientId([2; 16]);
+    let loaded = |client_id| {
+        DashboardEvent::new(DashboardEventKind::ModelLoaded {
+            client_id,
+            model: "llama3".to_string(),
+        })
+    };
+    let spike = DashboardEvent::new(DashboardEventKind::RequestSpike {
+        instance_id: "a".to_string(),
+        requests: 500,
+        baseline: 100.0,
+        window_secs: 60,
+    });
+
+    let all = EventFilter::default();
+    assert!(all.matches(&loaded(other)));
+    assert!(all.matches(&spike));
+
+    let provider = EventFilter {
+        clients: Some(HashSet::from([mine])),
+        workers_only: true,
+        ..Default::default()
+    };
+    assert!(provider.matches(&loaded(mine)));
+    assert!(!provider.matches(&loaded(other)));
+    assert!(!provider.matches(&spike));
+
+    let offline_only = EventFilter {
+        types: Some(HashSet::from(["worker_offline".to_string()])),
+        ..Default::default()
+    };
+    assert!(!offline_only.matches(&loaded(mine)));
+}
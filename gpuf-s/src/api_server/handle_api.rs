@@ -2,24 +2,35 @@ use super::*;
 
 use anyhow::Result;
 use axum::{
-    routing::{get, post},
+    middleware,
+    routing::{get, post, put},
     Router,
 };
 
-use crate::api_server::{apk, client, models, points};
+use crate::api_server::{
+    admin_models, apk, audit, auth, client, errors, events, health, leaderboard, models,
+    notifications, orgs, payouts, pins, points, staff, timeline, usage,
+};
+use crate::util::net::ListenAddr;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tower_http::cors::CorsLayer;
 use tracing::info;
 
 #[allow(dead_code)] // API server utility methods
 impl ApiServer {
-    pub async fn run_api_server(self: Arc<Self>, port: u16) -> Result<()> {
+    pub async fn run_api_server(self: Arc<Self>, listen_addr: ListenAddr, port: u16) -> Result<()> {
         let app = self.create_api_router().await;
-        let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}")).await?;
+        let listener = listen_addr.bind(port)?;
 
         info!("API server listening on port {}", port);
 
-        axum::serve(listener, app).await.map_err(Into::into)
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .map_err(Into::into)
     }
 
     // Create API Router
@@ -46,16 +57,128 @@ impl ApiServer {
             .route("/api/user/client_monitor", get(client::get_client_monitor))
             .route("/api/user/client_health", get(client::get_client_health))
             // Model Download Progress
-            .route("/api/user/model_download_progress", get(client::get_model_download_progress))
+            .route(
+                "/api/user/model_download_progress",
+                get(client::get_model_download_progress),
+            )
             // Model Management APIs
             .route("/api/models/insert", post(models::create_or_update_model))
             .route("/api/models/get", get(models::get_models))
+            .route("/api/models/compare", get(models::compare_model_versions))
+            .route("/api/models/inventory", get(models::get_model_inventory))
             // Points Management APIs
             .route("/api/user/points", get(points::get_user_points))
+            .route("/api/user/work_points", get(points::get_user_work_points))
+            // Usage Accounting APIs
+            .route("/api/usage", get(usage::get_usage))
+            // Inference error reports
+            .route("/api/errors", get(errors::list_errors))
+            .route("/api/errors/signatures", get(errors::top_signatures))
+            .route("/api/errors/models", get(errors::errors_by_model))
+            // Notification APIs
+            .route(
+                "/api/notifications/preferences",
+                get(notifications::get_preferences).put(notifications::set_preferences),
+            )
+            // Organization APIs
+            .route("/api/orgs", get(orgs::list_orgs).post(orgs::create_org))
+            .route("/api/orgs/:org_id/members", get(orgs::list_members))
+            .route(
+                "/api/orgs/:org_id/members/:member_id",
+                put(orgs::set_member).delete(orgs::remove_member),
+            )
+            .route(
+                "/api/orgs/:org_id/keys/:key_id",
+                post(orgs::add_key).delete(orgs::remove_key),
+            )
+            .route(
+                "/api/orgs/:org_id/clients/:client_id",
+                post(orgs::add_client).delete(orgs::remove_client),
+            )
+            // Live events for dashboards
+            .route("/api/events/ws", get(events::events_ws))
+            // Leaderboard APIs
+            .route("/api/leaderboard", get(leaderboard::get_leaderboard))
+            .route(
+                "/api/providers/:user_id/summary",
+                get(leaderboard::get_provider_summary),
+            )
+            // Payout APIs
+            .route("/api/payouts", get(payouts::list_payouts))
+            .route("/api/payouts/generate", post(payouts::generate_payouts))
+            .route("/api/payouts/:id/status", post(payouts::set_payout_status))
+            .route(
+                "/api/payouts/rate_cards",
+                get(payouts::list_rate_cards).post(payouts::upsert_rate_card),
+            )
+            // Model pinning APIs
+            .route("/api/clients/pin", post(pins::bulk_pin_model))
+            .route(
+                "/api/clients/:client_id/pin",
+                post(pins::pin_client_model).delete(pins::unpin_client_model),
+            )
+            // Client history for support
+            .route(
+                "/api/clients/:client_id/timeline",
+                get(timeline::get_client_timeline),
+            )
+            // Model catalog admin APIs
+            .route(
+                "/api/admin/models",
+                get(admin_models::list_models).post(admin_models::create_model),
+            )
+            .route(
+                "/api/admin/models/:id",
+                put(admin_models::update_model).delete(admin_models::delete_model),
+            )
+            .route(
+                "/api/admin/models/:id/activate",
+                post(admin_models::activate_model),
+            )
+            .route(
+                "/api/admin/models/:id/deactivate",
+                post(admin_models::deactivate_model),
+            )
+            .route(
+                "/api/admin/models/:id/rollout",
+                get(admin_models::get_rollout).post(admin_models::set_rollout),
+            )
+            // Staff roles, checked by the RBAC layer like the other admin endpoints
+            .route("/api/admin/roles", get(staff::list_roles))
+            .route(
+                "/api/admin/roles/:user_id",
+                put(staff::set_role).delete(staff::remove_role),
+            )
+            .route("/api/admin/access_denials", get(staff::list_denials))
+            .route("/api/admin/audit_log", get(audit::list_audit_log))
             // APK Management APIs
             .route("/api/apk/upsert", post(apk::upsert_apk))
             .route("/api/apk/get", get(apk::get_apk))
             .route("/api/apk/list", get(apk::list_apk))
+            .route_layer(middleware::from_fn_with_state(
+                audit::Auditor {
+                    pool: self.db_pool.clone(),
+                    service_actor: "api_key",
+                    trusted_proxies: self.trusted_proxies.clone(),
+                },
+                audit::audit_middleware,
+            ))
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                auth::rbac_middleware,
+            ))
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                auth::auth_middleware,
+            ))
+            // Workers report errors without management credentials
+            .route(
+                "/api/errors/inference",
+                post(errors::report_inference_error),
+            )
+            // Probes are added after the auth layer so they stay unauthenticated
+            .route("/healthz", get(health::healthz))
+            .route("/readyz", get(health::readyz))
             .layer(CorsLayer::permissive())
             .with_state(state)
     }
@@ -0,0 +1,186 @@
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{DateTime, Utc};
+use rdkafka::producer::{FutureProducer, Producer};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::api_server::ApiServer;
+
+/// Upper bound for a single dependency check
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Probes arriving within this window reuse the last result
+const CACHE_TTL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyState {
+    Up,
+    Down,
+    /// Not configured for this deployment, does not affect readiness
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DependencyStatus {
+    pub status: DependencyState,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub ready: bool,
+    pub postgres: DependencyStatus,
+    pub redis: DependencyStatus,
+    pub kafka: DependencyStatus,
+    pub checked_at: DateTime<Utc>,
+}
+
+impl HealthReport {
+    fn new(postgres: DependencyStatus, redis: DependencyStatus, kafka: DependencyStatus) -> Self {
+        let ready = [&postgres, &redis, &kafka]
+            .iter()
+            .all(|dep| dep.status != DependencyState::Down);
+        Self {
+            ready,
+            postgres,
+            redis,
+            kafka,
+            checked_at: Utc::now(),
+        }
+    }
+}
+
+/// Dependency checks behind `/healthz` and `/readyz`
+#[derive(Default)]
+pub struct HealthChecker {
+    /// Only checked when the API server was given a Kafka bootstrap server
+    kafka: Option<Arc<FutureProducer>>,
+    last: Mutex<Option<(Instant, Arc<HealthReport>)>>,
+}
+
+impl HealthChecker {
+    pub fn with_kafka(producer: Arc<FutureProducer>) -> Self {
+        Self {
+            kafka: Some(producer),
+            ..Default::default()
+        }
+    }
+
+    /// Cached report, refreshed at most once per `CACHE_TTL`. Concurrent probes
+    /// wait on the same refresh instead of each hitting the dependencies.
+    pub async fn report(&self, server: &ApiServer) -> Arc<HealthReport> {
+        let mut last = self.last.lock().await;
+        if let Some((at, report)) = last.as_ref() {
+            if at.elapsed() < CACHE_TTL {
+                return Arc::clone(report);
+            }
+        }
+
+        let (postgres, redis, kafka) = tokio::join!(
+            check(async {
+                sqlx::query("SELECT 1").execute(&server.db_pool).await?;
+                Ok(())
+            }),
+            check(async {
+                let mut conn = server.redis_client.get_async_connection().await?;
+                redis::cmd("PING").query_async::<_, ()>(&mut conn).await?;
+                Ok(())
+            }),
+            self.check_kafka(),
+        );
+
+        let report = Arc::new(HealthReport::new(postgres, redis, kafka));
+        if !report.ready {
+            warn!("Readiness check failed: {:?}", report);
+        }
+        *last = Some((Instant::now(), Arc::clone(&report)));
+        report
+    }
+
+    async fn check_kafka(&self) -> DependencyStatus {
+        let Some(producer) = self.kafka.clone() else {
+            return DependencyStatus {
+                status: DependencyState::Skipped,
+                latency_ms: None,
+                error: None,
+            };
+        };
+        check(async move {
+            // librdkafka metadata requests block the calling thread
+            tokio::task::spawn_blocking(move || {
+                producer.client().fetch_metadata(None, CHECK_TIMEOUT)
+            })
+            .await??;
+            Ok(())
+        })
+        .await
+    }
+}
+
+async fn check<F>(probe: F) -> DependencyStatus
+where
+    F: std::future::Future<Output = anyhow::Result<()>>,
+{
+    let started = Instant::now();
+    let error = match tokio::time::timeout(CHECK_TIMEOUT, probe).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some(format!("timed out after {}s", CHECK_TIMEOUT.as_secs())),
+    };
+    DependencyStatus {
+        status: if error.is_none() {
+            DependencyState::Up
+        } else {
+            DependencyState::Down
+        },
+        latency_ms: Some(started.elapsed().as_millis() as u64),
+        error,
+    }
+}
+
+/// Liveness: answers 200 while the process serves requests, the dependency
+/// report is informational so a flaky database does not restart the pod
+pub async fn healthz(State(server): State<Arc<ApiServer>>) -> Json<HealthReport> {
+    Json((*server.health.report(&server).await).clone())
+}
+
+/// Readiness: 503 while any configured dependency is down
+pub async fn readyz(State(server): State<Arc<ApiServer>>) -> (StatusCode, Json<HealthReport>) {
+    let report = server.health.report(&server).await;
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json((*report).clone()))
+}
+
+#[test]
+fn test_report_readiness() {
+    let up = DependencyStatus {
+        status: DependencyState::Up,
+        latency_ms: Some(1),
+        error: None,
+    };
+    let skipped = DependencyStatus {
+        status: DependencyState::Skipped,
+        latency_ms: None,
+        error: None,
+    };
+    let down = DependencyStatus {
+        status: DependencyState::Down,
+        latency_ms: Some(2000),
+        error: Some("timed out after 2s".to_string()),
+    };
+
+    assert!(HealthReport::new(up.clone(), up.clone(), skipped.clone()).ready);
+    assert!(!HealthReport::new(up.clone(), down, skipped).ready);
+
+    let json = serde_json::to_value(HealthReport::new(up.clone(), up.clone(), up)).unwrap();
+    assert_eq!(json["postgres"]["status"], "up");
+    assert_eq!(json["ready"], true);
+}
@@ -0,0 +1,170 @@
+use crate::api_server::auth::Principal;
+use crate::api_server::ApiServer;
+use crate::db::leaderboard::{
+    self, LeaderboardEntry, LeaderboardMetric, LeaderboardWindow, ProviderClients,
+    ProviderWindowStats,
+};
+use crate::util::msg::ApiResponse;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::Utc;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, warn};
+
+/// Leaderboards are served from Redis for this long before being recomputed
+const LEADERBOARD_CACHE_TTL_SECS: u64 = 300;
+const MAX_LEADERBOARD_LIMIT: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct LeaderboardQueryRequest {
+    pub window: Option<LeaderboardWindow>,
+    pub sort_by: Option<LeaderboardMetric>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderboardResponse {
+    pub window: LeaderboardWindow,
+    pub sort_by: LeaderboardMetric,
+    pub entries: Vec<LeaderboardEntry>,
+    pub generated_at: chrono::DateTime<Utc>,
+}
+
+// Top providers, e.g. /api/leaderboard?window=weekly&sort_by=tokens&limit=20
+pub async fn get_leaderboard(
+    State(app_state): State<Arc<ApiServer>>,
+    Query(params): Query<LeaderboardQueryRequest>,
+) -> Result<Json<ApiResponse<LeaderboardResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let window = params.window.unwrap_or(LeaderboardWindow::Weekly);
+    let sort_by = params.sort_by.unwrap_or(LeaderboardMetric::Points);
+    let limit = params.limit.unwrap_or(20);
+    if !(1..=MAX_LEADERBOARD_LIMIT).contains(&limit) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(format!(
+                "limit must be between 1 and {}",
+                MAX_LEADERBOARD_LIMIT
+            ))),
+        ));
+    }
+
+    let cache_key = format!(
+        "leaderboard:{}:{}:{}",
+        window.as_str(),
+        sort_by.as_str(),
+        limit
+    );
+    // The cache only saves work, leaderboards are computed when Redis is unavailable
+    let mut conn = match app_state.redis_client.get_async_connection().await {
+        Ok(conn) => Some(conn),
+        Err(e) => {
+            warn!("Leaderboard cache unavailable: {}", e);
+            None
+        }
+    };
+    if let Some(conn) = conn.as_mut() {
+        let cached: Option<String> = conn.get(&cache_key).await.unwrap_or_default();
+        if let Some(response) = cached.and_then(|c| serde_json::from_str(&c).ok()) {
+            return Ok(Json(ApiResponse::success(response)));
+        }
+    }
+
+    let start = window.start(Utc::now().date_naive());
+    let entries = leaderboard::get_leaderboard(&app_state.db_pool, start, sort_by, limit)
+        .await
+        .map_err(|e| {
+            error!("Failed to compute leaderboard: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::<()>::error(
+                    "internal server error".to_string(),
+                )),
+            )
+        })?;
+    let response = LeaderboardResponse {
+        window,
+        sort_by,
+        entries,
+        generated_at: Utc::now(),
+    };
+
+    if let (Some(conn), Ok(json)) = (conn.as_mut(), serde_json::to_string(&response)) {
+        let cached: redis::RedisResult<()> = conn
+            .set_ex(&cache_key, json, LEADERBOARD_CACHE_TTL_SECS)
+            .await;
+        if let Err(e) = cached {
+            warn!("Failed to cache leaderboard {}: {}", cache_key, e);
+        }
+    }
+
+    Ok(Json(ApiResponse::success(response)))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProviderSummaryResponse {
+    pub user_id: String,
+    #[serde(flatten)]
+    pub clients: ProviderClients,
+    pub daily: ProviderWindowStats,
+    pub weekly: ProviderWindowStats,
+    pub monthly: ProviderWindowStats,
+}
+
+// Clients, points and work of one provider over the leaderboard windows
+pub async fn get_provider_summary(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<ApiResponse<ProviderSummaryResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let user_id = Principal::resolve_user_id(principal.as_deref(), Some(&user_id))
+        .map_err(|code| {
+            (
+                code,
+                Json(ApiResponse::<()>::error("Forbidden".to_string())),
+            )
+        })?
+        .unwrap_or(user_id);
+
+    let pool = &app_state.db_pool;
+    let today = Utc::now().date_naive();
+    let (clients, daily, weekly, monthly) = tokio::try_join!(
+        leaderboard::get_provider_clients(pool, &user_id),
+        leaderboard::get_provider_window_stats(
+            pool,
+            &user_id,
+            LeaderboardWindow::Daily.start(today)
+        ),
+        leaderboard::get_provider_window_stats(
+            pool,
+            &user_id,
+            LeaderboardWindow::Weekly.start(today)
+        ),
+        leaderboard::get_provider_window_stats(
+            pool,
+            &user_id,
+            LeaderboardWindow::Monthly.start(today)
+        ),
+    )
+    .map_err(|e| {
+        error!("Failed to summarize provider {}: {}", user_id, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(
+                "internal server error".to_string(),
+            )),
+        )
+    })?;
+
+    Ok(Json(ApiResponse::success(ProviderSummaryResponse {
+        user_id,
+        clients,
+        daily,
+        weekly,
+        monthly,
+    })))
+}
@@ -1,10 +1,29 @@
+pub mod admin_models;
 pub mod apk;
+pub mod audit;
+pub mod auth;
 pub mod client;
+pub mod errors;
+pub mod events;
 pub mod handle_api;
+pub mod health;
+pub mod leaderboard;
 pub mod models;
+pub mod notifications;
+pub mod orgs;
+pub mod payouts;
+pub mod pins;
 pub mod points;
+pub mod staff;
+pub mod timeline;
+pub mod usage;
 
+use crate::util::net::IpRange;
 use anyhow::Result;
+use auth::ApiAuth;
+use health::HealthChecker;
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::FutureProducer;
 use redis::Client as RedisClient;
 use sqlx::postgres::Postgres;
 use sqlx::Pool;
@@ -15,6 +34,10 @@ use tracing::error;
 pub struct ApiServer {
     pub db_pool: Pool<Postgres>,
     pub redis_client: Arc<RedisClient>,
+    pub auth: ApiAuth,
+    pub health: HealthChecker,
+    /// Load balancers whose `X-Forwarded-For` the audit log believes
+    pub trusted_proxies: Arc<Vec<IpRange>>,
 }
 
 impl ApiServer {
@@ -32,8 +55,32 @@ impl ApiServer {
         Ok(ApiServer {
             db_pool,
             redis_client,
+            auth: ApiAuth::default(),
+            health: HealthChecker::default(),
+            trusted_proxies: Arc::new(Vec::new()),
         })
     }
+
+    /// Require an API key and/or a JWT from the configured issuer on all endpoints
+    pub fn with_auth(mut self, auth: ApiAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Believe the `X-Forwarded-For` header of requests from these addresses
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Vec<IpRange>) -> Self {
+        self.trusted_proxies = Arc::new(trusted_proxies);
+        self
+    }
+
+    /// Include Kafka in the `/readyz` dependency checks
+    pub fn with_kafka(mut self, bootstrap_server: &str) -> Result<Self> {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", bootstrap_server)
+            .create()?;
+        self.health = HealthChecker::with_kafka(Arc::new(producer));
+        Ok(self)
+    }
 }
 
 use chrono::{DateTime, Utc};
@@ -1,10 +1,11 @@
+use crate::api_server::auth::Principal;
 use crate::api_server::ApiServer;
-use crate::db::models;
-use crate::util::msg::ApiResponse;
+use crate::db::{inventory, model_perf, models};
+use crate::util::msg::{ApiResponse, ListParams, Page};
 use axum::{
     extract::{Query, State},
     http::StatusCode,
-    Json,
+    Extension, Json,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -25,6 +26,11 @@ pub struct CreateOrUpdateModelRequest {
     pub download_url: Option<String>,
     pub checksum: Option<String>,
     pub expected_size: Option<i64>,
+    /// Base64 Ed25519 signature over the SHA-256 digest of the model file
+    pub signature: Option<String>,
+    /// URL of the chunk manifest of this version, lets workers holding an
+    /// older version download only the chunks that changed
+    pub chunk_manifest_url: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -40,6 +46,8 @@ pub struct ModelResponse {
     pub download_url: Option<String>,
     pub checksum: Option<String>,
     pub expected_size: Option<i64>,
+    pub signature: Option<String>,
+    pub chunk_manifest_url: Option<String>,
 }
 
 // Create or update a model
@@ -63,6 +71,8 @@ pub async fn create_or_update_model(
         payload.download_url,
         payload.checksum,
         payload.expected_size,
+        payload.signature,
+        payload.chunk_manifest_url,
     )
     .await
     {
@@ -78,30 +88,36 @@ pub async fn create_or_update_model(
 pub async fn get_models(
     State(app_state): State<Arc<ApiServer>>,
     Query(params): Query<HashMap<String, String>>,
-) -> Result<Json<ApiResponse<Vec<ModelResponse>>>, StatusCode> {
-    let is_active = params.get("is_active").and_then(|s| s.parse::<bool>().ok());
-    let min_gpu_memory_gb = params
-        .get("min_gpu_memory_gb")
-        .and_then(|s| s.parse::<i32>().ok());
+    Query(list): Query<ListParams>,
+) -> Result<Json<ApiResponse<Page<ModelResponse>>>, StatusCode> {
+    let filter = models::ModelFilter {
+        is_active: params.get("is_active").and_then(|s| s.parse::<bool>().ok()),
+        max_gpu_memory_gb: params
+            .get("min_gpu_memory_gb")
+            .and_then(|s| s.parse::<i32>().ok()),
+        ..Default::default()
+    };
+    let list = list
+        .resolve(&models::MODEL_LIST)
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
 
-    match models::get_models_list(&app_state.db_pool, is_active, None, min_gpu_memory_gb).await {
-        Ok(models) => {
-            let models = models
-                .into_iter()
-                .map(|model| ModelResponse {
-                    id: model.id,
-                    name: model.name,
-                    version: model.version,
-                    version_code: model.version_code,
-                    is_active: model.is_active,
-                    min_memory_mb: model.min_memory_mb,
-                    min_gpu_memory_gb: model.min_gpu_memory_gb,
-                    created_at: model.created_at,
-                    download_url: model.download_url,
-                    checksum: model.checksum,
-                    expected_size: model.expected_size,
-                })
-                .collect();
+    match models::list_models(&app_state.db_pool, &filter, &list).await {
+        Ok(page) => {
+            let models = page.map(|model| ModelResponse {
+                id: model.id,
+                name: model.name,
+                version: model.version,
+                version_code: model.version_code,
+                is_active: model.is_active,
+                min_memory_mb: model.min_memory_mb,
+                min_gpu_memory_gb: model.min_gpu_memory_gb,
+                created_at: model.created_at,
+                download_url: model.download_url,
+                checksum: model.checksum,
+                expected_size: model.expected_size,
+                signature: model.signature,
+                chunk_manifest_url: model.chunk_manifest_url,
+            });
             Ok(Json(ApiResponse::success(models)))
         }
         Err(e) => {
@@ -110,3 +126,66 @@ pub async fn get_models(
         }
     }
 }
+
+// Compare two versions of a model using fleet performance samples
+pub async fn compare_model_versions(
+    State(app_state): State<Arc<ApiServer>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<ApiResponse<model_perf::ModelVersionComparison>>, StatusCode> {
+    let (Some(name), Some(base_version), Some(candidate_version)) = (
+        params.get("name"),
+        params.get("base_version"),
+        params.get("candidate_version"),
+    ) else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    let days = params
+        .get("days")
+        .and_then(|s| s.parse::<i32>().ok())
+        .filter(|d| *d > 0)
+        .unwrap_or(7);
+
+    match model_perf::compare_model_versions(
+        &app_state.db_pool,
+        name,
+        base_version,
+        candidate_version,
+        days,
+    )
+    .await
+    {
+        Ok(comparison) => Ok(Json(ApiResponse::success(comparison))),
+        Err(e) => {
+            error!("Failed to compare model versions: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Loaded models across the fleet, e.g. /api/models/inventory?model=llama-3-8b.
+// Lists clients of every provider, so JWT users are refused.
+pub async fn get_model_inventory(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<ApiResponse<inventory::ModelInventory>>, StatusCode> {
+    if let Some(Principal::User(_)) = principal.as_deref() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let mut fleet = inventory::get_model_inventory(&app_state.db_pool, &app_state.redis_client)
+        .await
+        .map_err(|e| {
+            error!("Failed to get model inventory: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    if let Some(model) = params.get("model") {
+        fleet.models.retain(|m| &m.model == model);
+    }
+    if let Some(client_id) = params.get("client_id") {
+        let client_id = client_id.to_lowercase();
+        fleet.models.retain(|m| m.client_ids.contains(&client_id));
+    }
+    Ok(Json(ApiResponse::success(fleet)))
+}
@@ -0,0 +1,140 @@
+use crate::api_server::audit::{self, AuditTrail};
+use crate::api_server::auth::{require_user_id, Principal};
+use crate::api_server::ApiServer;
+use crate::db::notifications::{self, Preference};
+use crate::util::msg::ApiResponse;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::{error, info};
+
+/// Preferences one user may keep, one per event type and channel is far less
+const MAX_PREFERENCES: usize = 32;
+
+type NotificationError = (StatusCode, Json<ApiResponse<()>>);
+type NotificationResult<T> = Result<Json<ApiResponse<T>>, NotificationError>;
+
+fn notification_error(code: StatusCode, msg: impl Into<String>) -> NotificationError {
+    (code, Json(ApiResponse::<()>::error(msg.into())))
+}
+
+fn internal_error(context: &str, e: sqlx::Error) -> NotificationError {
+    error!("{}: {}", context, e);
+    notification_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+}
+
+fn user_id_error(code: StatusCode) -> NotificationError {
+    notification_error(code, "Invalid user_id")
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreferencesQuery {
+    #[serde(default)]
+    pub user_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreferencesRequest {
+    #[serde(default)]
+    pub user_id: String,
+    pub preferences: Vec<Preference>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreferencesResponse {
+    pub user_id: String,
+    pub preferences: Vec<Preference>,
+}
+
+/// Check targets and that every event type and channel appears once
+fn check_preferences(preferences: &[Preference]) -> Result<(), String> {
+    if preferences.len() > MAX_PREFERENCES {
+        return Err(format!("At most {} preferences", MAX_PREFERENCES));
+    }
+    let mut seen = HashSet::new();
+    for preference in preferences {
+        if !preference.channel.accepts(&preference.target) {
+            return Err(format!(
+                "Invalid {} target {}",
+                preference.channel.as_str(),
+                preference.target
+            ));
+        }
+        if !seen.insert((preference.event_type, preference.channel)) {
+            return Err(format!(
+                "Duplicate {} preference for {}",
+                preference.channel.as_str(),
+                preference.event_type.as_str()
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Notification preferences of a user, e.g. GET /api/notifications/preferences?user_id=42
+pub async fn get_preferences(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Query(query): Query<PreferencesQuery>,
+) -> NotificationResult<PreferencesResponse> {
+    let user_id = require_user_id(principal.as_deref(), &query.user_id).map_err(user_id_error)?;
+    let preferences = notifications::get_preferences(&app_state.db_pool, &user_id)
+        .await
+        .map_err(|e| internal_error("Failed to get notification preferences", e))?;
+    Ok(Json(ApiResponse::success(PreferencesResponse {
+        user_id,
+        preferences,
+    })))
+}
+
+// Replace the notification preferences of a user, an empty list turns them off
+pub async fn set_preferences(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Json(payload): Json<PreferencesRequest>,
+) -> NotificationResult<PreferencesResponse> {
+    let user_id = require_user_id(principal.as_deref(), &payload.user_id).map_err(user_id_error)?;
+    check_preferences(&payload.preferences)
+        .map_err(|e| notification_error(StatusCode::BAD_REQUEST, e))?;
+    let current = notifications::get_preferences(&app_state.db_pool, &user_id)
+        .await
+        .map_err(|e| internal_error("Failed to get notification preferences", e))?;
+    audit::set_before(trail.as_deref(), &current);
+    notifications::replace_preferences(&app_state.db_pool, &user_id, &payload.preferences)
+        .await
+        .map_err(|e| internal_error("Failed to set notification preferences", e))?;
+    info!(
+        "Set {} notification preferences of user {}",
+        payload.preferences.len(),
+        user_id
+    );
+    Ok(Json(ApiResponse::success(PreferencesResponse {
+        user_id,
+        preferences: payload.preferences,
+    })))
+}
+
+#[test]
+fn test_check_preferences() {
+    let preferences: Vec<Preference> = serde_json::from_value(serde_json::json!([
+        {"event_type": "device_offline", "channel": "email", "target": "ops@example.com"},
+        {"event_type": "device_offline", "channel": "webhook", "target": "https://example.com/h"},
+        {"event_type": "payout_processed", "channel": "email", "target": "ops@example.com", "digest": true},
+    ]))
+    .unwrap();
+    assert!(check_preferences(&preferences).is_ok());
+
+    let mut duplicate = preferences.clone();
+    duplicate.push(preferences[0].clone());
+    assert!(check_preferences(&duplicate).is_err());
+
+    let mut invalid = preferences;
+    invalid[1].target = "example.com/h".to_string();
+    assert!(check_preferences(&invalid).is_err());
+}
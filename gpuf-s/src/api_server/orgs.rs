@@ -0,0 +1,379 @@
+use crate::api_server::audit::{self, AuditTrail};
+use crate::api_server::auth::{require_user_id, Principal};
+use crate::api_server::ApiServer;
+use crate::db::orgs::{self, Member, Membership, OrgRole, Organization};
+use crate::util::msg::ApiResponse;
+use crate::util::protoc::ClientId;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::{error, info};
+
+const MAX_ORG_NAME_CHARS: usize = 128;
+const MAX_USER_ID_CHARS: usize = 64;
+
+type OrgError = (StatusCode, Json<ApiResponse<()>>);
+type OrgResult<T> = Result<Json<ApiResponse<T>>, OrgError>;
+
+fn org_error(code: StatusCode, msg: impl Into<String>) -> OrgError {
+    (code, Json(ApiResponse::<()>::error(msg.into())))
+}
+
+fn internal_error(context: &str, e: sqlx::Error) -> OrgError {
+    error!("{}: {}", context, e);
+    org_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+}
+
+fn user_id_error(code: StatusCode) -> OrgError {
+    org_error(code, "Invalid user_id")
+}
+
+/// Check that the caller acting as `requested` user has at least `min` role
+/// in the organization. Service callers that act as no user may see every
+/// organization.
+pub async fn authorize_org(
+    pool: &Pool<Postgres>,
+    principal: Option<&Principal>,
+    requested: Option<&str>,
+    org_id: i64,
+    min: OrgRole,
+) -> Result<(), StatusCode> {
+    let Some(user_id) = Principal::resolve_user_id(principal, requested)? else {
+        return Ok(());
+    };
+    let role = orgs::get_role(pool, org_id, &user_id).await.map_err(|e| {
+        error!("Failed to get role in organization {}: {}", org_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    match role {
+        Some(role) if role >= min => Ok(()),
+        _ => Err(StatusCode::FORBIDDEN),
+    }
+}
+
+/// Role of the acting user, who must be a member
+async fn require_role(
+    app_state: &ApiServer,
+    org_id: i64,
+    user_id: &str,
+    min: OrgRole,
+) -> Result<OrgRole, OrgError> {
+    let role = orgs::get_role(&app_state.db_pool, org_id, user_id)
+        .await
+        .map_err(|e| internal_error("Failed to get organization role", e))?;
+    match role {
+        Some(role) if role >= min => Ok(role),
+        _ => Err(org_error(
+            StatusCode::FORBIDDEN,
+            format!("Requires {} of the organization", min.as_str()),
+        )),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ActingUserQuery {
+    #[serde(default)]
+    pub user_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateOrgRequest {
+    #[serde(default)]
+    pub user_id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetMemberRequest {
+    #[serde(default)]
+    pub user_id: String,
+    pub role: OrgRole,
+}
+
+/// Organization a key or worker was in, audited when it moves
+#[derive(Debug, Serialize)]
+struct Placement {
+    org_id: Option<i64>,
+}
+
+// Create an organization owned by the caller, e.g. POST /api/orgs {"name": "Acme"}
+pub async fn create_org(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Json(payload): Json<CreateOrgRequest>,
+) -> OrgResult<Organization> {
+    let user_id = require_user_id(principal.as_deref(), &payload.user_id).map_err(user_id_error)?;
+    let name = payload.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_ORG_NAME_CHARS {
+        return Err(org_error(
+            StatusCode::BAD_REQUEST,
+            format!("Name must be 1 to {} characters", MAX_ORG_NAME_CHARS),
+        ));
+    }
+    let org = orgs::create_org(&app_state.db_pool, name, &user_id)
+        .await
+        .map_err(|e| internal_error("Failed to create organization", e))?;
+    info!("User {} created organization {}", user_id, org.id);
+    Ok(Json(ApiResponse::success(org)))
+}
+
+// Organizations of a user with the user's role, e.g. GET /api/orgs?user_id=42
+pub async fn list_orgs(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Query(query): Query<ActingUserQuery>,
+) -> OrgResult<Vec<Membership>> {
+    let user_id = require_user_id(principal.as_deref(), &query.user_id).map_err(user_id_error)?;
+    let orgs = orgs::list_user_orgs(&app_state.db_pool, &user_id)
+        .await
+        .map_err(|e| internal_error("Failed to list organizations", e))?;
+    Ok(Json(ApiResponse::success(orgs)))
+}
+
+// Members of an organization, visible to every member
+pub async fn list_members(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Path(org_id): Path<i64>,
+    Query(query): Query<ActingUserQuery>,
+) -> OrgResult<Vec<Member>> {
+    let user_id = require_user_id(principal.as_deref(), &query.user_id).map_err(user_id_error)?;
+    require_role(&app_state, org_id, &user_id, OrgRole::Member).await?;
+    let members = orgs::list_members(&app_state.db_pool, org_id)
+        .await
+        .map_err(|e| internal_error("Failed to list organization members", e))?;
+    Ok(Json(ApiResponse::success(members)))
+}
+
+/// Refuse changes that would leave the organization without an owner
+async fn keep_an_owner(
+    app_state: &ApiServer,
+    org_id: i64,
+    current: Option<OrgRole>,
+    new: Option<OrgRole>,
+) -> Result<(), OrgError> {
+    if current != Some(OrgRole::Owner) || new == Some(OrgRole::Owner) {
+        return Ok(());
+    }
+    let owners = orgs::count_owners(&app_state.db_pool, org_id)
+        .await
+        .map_err(|e| internal_error("Failed to count organization owners", e))?;
+    if owners <= 1 {
+        return Err(org_error(
+            StatusCode::CONFLICT,
+            "An organization needs at least one owner",
+        ));
+    }
+    Ok(())
+}
+
+// Add a member or change its role, e.g. PUT /api/orgs/3/members/57 {"role": "admin"}
+pub async fn set_member(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path((org_id, member_id)): Path<(i64, String)>,
+    Json(payload): Json<SetMemberRequest>,
+) -> OrgResult<()> {
+    let user_id = require_user_id(principal.as_deref(), &payload.user_id).map_err(user_id_error)?;
+    if member_id.is_empty() || member_id.len() > MAX_USER_ID_CHARS {
+        return Err(org_error(StatusCode::BAD_REQUEST, "Invalid member id"));
+    }
+    let role = require_role(&app_state, org_id, &user_id, OrgRole::Admin).await?;
+    let current = orgs::get_role(&app_state.db_pool, org_id, &member_id)
+        .await
+        .map_err(|e| internal_error("Failed to get organization role", e))?;
+    if !role.can_change(current, Some(payload.role)) {
+        return Err(org_error(
+            StatusCode::FORBIDDEN,
+            "Only owners manage owners",
+        ));
+    }
+    keep_an_owner(&app_state, org_id, current, Some(payload.role)).await?;
+    audit::set_before(trail.as_deref(), &current);
+    orgs::set_member(&app_state.db_pool, org_id, &member_id, payload.role)
+        .await
+        .map_err(|e| internal_error("Failed to set organization member", e))?;
+    info!(
+        "User {} made {} {} of organization {}",
+        user_id,
+        member_id,
+        payload.role.as_str(),
+        org_id
+    );
+    Ok(Json(ApiResponse::success(())))
+}
+
+// Remove a member; members may always leave
+pub async fn remove_member(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path((org_id, member_id)): Path<(i64, String)>,
+    Query(query): Query<ActingUserQuery>,
+) -> OrgResult<()> {
+    let user_id = require_user_id(principal.as_deref(), &query.user_id).map_err(user_id_error)?;
+    let role = require_role(&app_state, org_id, &user_id, OrgRole::Member).await?;
+    let current = orgs::get_role(&app_state.db_pool, org_id, &member_id)
+        .await
+        .map_err(|e| internal_error("Failed to get organization role", e))?;
+    if current.is_none() {
+        return Err(org_error(StatusCode::NOT_FOUND, "Not a member"));
+    }
+    if member_id != user_id && !role.can_change(current, None) {
+        return Err(org_error(
+            StatusCode::FORBIDDEN,
+            "Not allowed to remove this member",
+        ));
+    }
+    keep_an_owner(&app_state, org_id, current, None).await?;
+    audit::set_before(trail.as_deref(), &current);
+    orgs::remove_member(&app_state.db_pool, org_id, &member_id)
+        .await
+        .map_err(|e| internal_error("Failed to remove organization member", e))?;
+    info!(
+        "User {} removed {} from organization {}",
+        user_id, member_id, org_id
+    );
+    Ok(Json(ApiResponse::success(())))
+}
+
+// Move one of the caller's API keys into the organization
+pub async fn add_key(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path((org_id, key_id)): Path<(i64, i64)>,
+    Query(query): Query<ActingUserQuery>,
+) -> OrgResult<()> {
+    move_key(
+        &app_state,
+        principal.as_deref(),
+        trail.as_deref(),
+        org_id,
+        key_id,
+        &query,
+        true,
+    )
+    .await
+}
+
+// Move one of the caller's API keys out of the organization
+pub async fn remove_key(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path((org_id, key_id)): Path<(i64, i64)>,
+    Query(query): Query<ActingUserQuery>,
+) -> OrgResult<()> {
+    move_key(
+        &app_state,
+        principal.as_deref(),
+        trail.as_deref(),
+        org_id,
+        key_id,
+        &query,
+        false,
+    )
+    .await
+}
+
+async fn move_key(
+    app_state: &ApiServer,
+    principal: Option<&Principal>,
+    trail: Option<&AuditTrail>,
+    org_id: i64,
+    key_id: i64,
+    query: &ActingUserQuery,
+    join: bool,
+) -> OrgResult<()> {
+    let user_id = require_user_id(principal, &query.user_id).map_err(user_id_error)?;
+    require_role(app_state, org_id, &user_id, OrgRole::Admin).await?;
+    let previous = orgs::move_key(&app_state.db_pool, key_id, &user_id, org_id, join)
+        .await
+        .map_err(|e| internal_error("Failed to move API key", e))?
+        .ok_or_else(|| org_error(StatusCode::NOT_FOUND, "Unknown key"))?;
+    audit::set_before(trail, &Placement { org_id: previous });
+    info!(
+        "User {} moved key {} {} organization {}",
+        user_id,
+        key_id,
+        if join { "into" } else { "out of" },
+        org_id
+    );
+    Ok(Json(ApiResponse::success(())))
+}
+
+// Move one of the caller's workers into the organization
+pub async fn add_client(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path((org_id, client_id)): Path<(i64, String)>,
+    Query(query): Query<ActingUserQuery>,
+) -> OrgResult<()> {
+    move_client(
+        &app_state,
+        principal.as_deref(),
+        trail.as_deref(),
+        org_id,
+        &client_id,
+        &query,
+        true,
+    )
+    .await
+}
+
+// Move one of the caller's workers out of the organization
+pub async fn remove_client(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path((org_id, client_id)): Path<(i64, String)>,
+    Query(query): Query<ActingUserQuery>,
+) -> OrgResult<()> {
+    move_client(
+        &app_state,
+        principal.as_deref(),
+        trail.as_deref(),
+        org_id,
+        &client_id,
+        &query,
+        false,
+    )
+    .await
+}
+
+async fn move_client(
+    app_state: &ApiServer,
+    principal: Option<&Principal>,
+    trail: Option<&AuditTrail>,
+    org_id: i64,
+    client_id: &str,
+    query: &ActingUserQuery,
+    join: bool,
+) -> OrgResult<()> {
+    let user_id = require_user_id(principal, &query.user_id).map_err(user_id_error)?;
+    let client_id = ClientId::from_str(client_id)
+        .map_err(|e| org_error(StatusCode::BAD_REQUEST, format!("Invalid client_id: {}", e)))?;
+    require_role(app_state, org_id, &user_id, OrgRole::Admin).await?;
+    let previous = orgs::move_client(&app_state.db_pool, &client_id, &user_id, org_id, join)
+        .await
+        .map_err(|e| internal_error("Failed to move worker", e))?
+        .ok_or_else(|| org_error(StatusCode::NOT_FOUND, "Unknown worker"))?;
+    audit::set_before(trail, &Placement { org_id: previous });
+    info!(
+        "User {} moved client {} {} organization {}",
+        user_id,
+        client_id,
+        if join { "into" } else { "out of" },
+        org_id
+    );
+    Ok(Json(ApiResponse::success(())))
+}
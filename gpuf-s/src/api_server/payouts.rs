@@ -0,0 +1,219 @@
+use crate::api_server::audit::{self, AuditTrail};
+use crate::api_server::auth::Principal;
+use crate::api_server::ApiServer;
+use crate::db::notifications::{self, NotificationEvent};
+use crate::db::payouts::{self, GpuTier, ModelClass, PayoutStatement, PayoutStatus, RateCard, ANY};
+use crate::util::msg::ApiResponse;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+type PayoutError = (StatusCode, Json<ApiResponse<()>>);
+
+fn payout_error(code: StatusCode, msg: impl Into<String>) -> PayoutError {
+    (code, Json(ApiResponse::<()>::error(msg.into())))
+}
+
+fn internal_error(context: &str, e: sqlx::Error) -> PayoutError {
+    error!("{}: {}", context, e);
+    payout_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+}
+
+/// Rate cards and statement generation are for operators, end users only read
+/// their own statements. Money moves, so a server running without
+/// authentication refuses them too.
+fn require_operator(principal: Option<&Principal>) -> Result<(), PayoutError> {
+    match principal {
+        Some(Principal::Service) | Some(Principal::Staff { .. }) => Ok(()),
+        _ => Err(payout_error(StatusCode::FORBIDDEN, "Forbidden")),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PayoutQueryRequest {
+    pub user_id: Option<String>,
+    pub status: Option<PayoutStatus>,
+}
+
+// List payout statements, e.g. /api/payouts?user_id=2&status=pending
+pub async fn list_payouts(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Query(params): Query<PayoutQueryRequest>,
+) -> Result<Json<ApiResponse<Vec<PayoutStatement>>>, PayoutError> {
+    let user_id = Principal::resolve_user_id(principal.as_deref(), params.user_id.as_deref())
+        .map_err(|code| payout_error(code, "Forbidden"))?;
+
+    payouts::list_statements(&app_state.db_pool, user_id.as_deref(), params.status)
+        .await
+        .map(|statements| Json(ApiResponse::success(statements)))
+        .map_err(|e| internal_error("Failed to list payout statements", e))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GeneratePayoutsRequest {
+    /// Any day of the month to compute
+    pub month: NaiveDate,
+    #[serde(default = "default_currency")]
+    pub currency: String,
+}
+
+fn default_currency() -> String {
+    "USD".to_string()
+}
+
+#[derive(Debug, Serialize)]
+pub struct GeneratePayoutsResponse {
+    pub month: NaiveDate,
+    pub statements: u64,
+}
+
+// Compute the month's statements from device points and the rate cards
+pub async fn generate_payouts(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Json(payload): Json<GeneratePayoutsRequest>,
+) -> Result<Json<ApiResponse<GeneratePayoutsResponse>>, PayoutError> {
+    require_operator(principal.as_deref())?;
+
+    let month = payouts::month_start(payload.month);
+    let statements = payouts::generate_statements(&app_state.db_pool, month, &payload.currency)
+        .await
+        .map_err(|e| internal_error("Failed to generate payout statements", e))?;
+    info!("Generated {} payout statements for {}", statements, month);
+
+    Ok(Json(ApiResponse::success(GeneratePayoutsResponse {
+        month,
+        statements,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PayoutStatusRequest {
+    pub status: PayoutStatus,
+}
+
+// Approve or mark paid a statement, e.g. POST /api/payouts/12/status {"status":"approved"}
+pub async fn set_payout_status(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path(id): Path<i64>,
+    Json(payload): Json<PayoutStatusRequest>,
+) -> Result<Json<ApiResponse<PayoutStatement>>, PayoutError> {
+    require_operator(principal.as_deref())?;
+
+    let statement = payouts::get_statement(&app_state.db_pool, id)
+        .await
+        .map_err(|e| internal_error("Failed to get payout statement", e))?
+        .ok_or_else(|| payout_error(StatusCode::NOT_FOUND, "Payout statement not found"))?;
+    audit::set_before(trail.as_deref(), &statement);
+    let current = PayoutStatus::parse(&statement.status).ok_or_else(|| {
+        payout_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Unknown payout status {}", statement.status),
+        )
+    })?;
+    if !current.can_become(payload.status) {
+        return Err(payout_error(
+            StatusCode::CONFLICT,
+            format!(
+                "Payout statement is {}, it cannot become {}",
+                current.as_str(),
+                payload.status.as_str()
+            ),
+        ));
+    }
+
+    let statement = payouts::set_statement_status(&app_state.db_pool, id, current, payload.status)
+        .await
+        .map_err(|e| internal_error("Failed to update payout statement", e))?
+        .ok_or_else(|| {
+            payout_error(
+                StatusCode::CONFLICT,
+                "Payout statement changed status meanwhile",
+            )
+        })?;
+
+    if payload.status == PayoutStatus::Paid {
+        let month = statement.month.format("%Y-%m");
+        if let Err(e) = notifications::notify_user(
+            &app_state.db_pool,
+            &statement.user_id,
+            NotificationEvent::PayoutProcessed,
+            &format!("Payout for {} paid", month),
+            &format!(
+                "{} {} for {:.2} points earned in {} has been paid.",
+                payouts::format_micros(statement.amount_micros),
+                statement.currency,
+                statement.points,
+                month
+            ),
+        )
+        .await
+        {
+            warn!("Failed to notify payout {}: {}", statement.id, e);
+        }
+    }
+    Ok(Json(ApiResponse::success(statement)))
+}
+
+// List the rate cards used to price points
+pub async fn list_rate_cards(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+) -> Result<Json<ApiResponse<Vec<RateCard>>>, PayoutError> {
+    require_operator(principal.as_deref())?;
+
+    payouts::list_rate_cards(&app_state.db_pool)
+        .await
+        .map(|cards| Json(ApiResponse::success(cards)))
+        .map_err(|e| internal_error("Failed to list payout rate cards", e))
+}
+
+// Create or replace the rate card of a tier and model class
+pub async fn upsert_rate_card(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Json(card): Json<RateCard>,
+) -> Result<Json<ApiResponse<()>>, PayoutError> {
+    require_operator(principal.as_deref())?;
+
+    if card.gpu_tier != ANY && GpuTier::parse(&card.gpu_tier).is_none() {
+        return Err(payout_error(
+            StatusCode::BAD_REQUEST,
+            format!("Unknown gpu_tier {}", card.gpu_tier),
+        ));
+    }
+    if card.model_class != ANY && ModelClass::parse(&card.model_class).is_none() {
+        return Err(payout_error(
+            StatusCode::BAD_REQUEST,
+            format!("Unknown model_class {}", card.model_class),
+        ));
+    }
+    if card.rate_per_point_micros < 0 {
+        return Err(payout_error(
+            StatusCode::BAD_REQUEST,
+            "rate_per_point_micros must be zero or more",
+        ));
+    }
+
+    let current = payouts::list_rate_cards(&app_state.db_pool)
+        .await
+        .map_err(|e| internal_error("Failed to list payout rate cards", e))?
+        .into_iter()
+        .find(|c| c.gpu_tier == card.gpu_tier && c.model_class == card.model_class);
+    audit::set_before(trail.as_deref(), &current);
+
+    payouts::upsert_rate_card(&app_state.db_pool, &card)
+        .await
+        .map(|_| Json(ApiResponse::success(())))
+        .map_err(|e| internal_error("Failed to save payout rate card", e))
+}
@@ -0,0 +1,219 @@
+use crate::api_server::audit::{self, AuditTrail};
+use crate::api_server::auth::Principal;
+use crate::api_server::ApiServer;
+use crate::db::notifications::{self, NotificationEvent};
+use crate::db::{client, models};
+use crate::util::msg::ApiResponse;
+use crate::util::protoc::ClientId;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+type PinError = (StatusCode, Json<ApiResponse<()>>);
+type PinResult<T> = Result<Json<ApiResponse<T>>, PinError>;
+
+/// Clients one bulk request may change
+const MAX_BULK_CLIENTS: usize = 1000;
+
+fn pin_error(code: StatusCode, msg: impl Into<String>) -> PinError {
+    (code, Json(ApiResponse::<()>::error(msg.into())))
+}
+
+fn internal_error(context: &str, e: anyhow::Error) -> PinError {
+    error!("{}: {}", context, e);
+    pin_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+}
+
+/// Providers may only change their own clients and those of organizations
+/// they administer, operators any client
+fn owner(principal: Option<&Principal>) -> Option<&str> {
+    match principal {
+        Some(Principal::User(user_id)) => Some(user_id.as_str()),
+        _ => None,
+    }
+}
+
+fn parse_client_ids(raw: &[String]) -> Result<Vec<ClientId>, PinError> {
+    if raw.is_empty() || raw.len() > MAX_BULK_CLIENTS {
+        return Err(pin_error(
+            StatusCode::BAD_REQUEST,
+            format!("client_ids must hold 1 to {} clients", MAX_BULK_CLIENTS),
+        ));
+    }
+    raw.iter()
+        .map(|id| {
+            id.parse::<ClientId>().map_err(|_| {
+                pin_error(StatusCode::BAD_REQUEST, format!("Invalid client_id {}", id))
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PinRequest {
+    pub model: String,
+    /// Newest active version when omitted
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BulkPinRequest {
+    pub client_ids: Vec<String>,
+    /// Restores automatic models when omitted
+    pub model: Option<String>,
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PinResponse {
+    /// None when the clients got automatic models back
+    pub model: Option<String>,
+    pub version: Option<String>,
+    pub updated: Vec<String>,
+    /// Unknown clients, or clients of another provider
+    pub not_found: Vec<String>,
+}
+
+/// Resolve the pinned version, the newest active one when none is given
+async fn resolve_pin(
+    app_state: &ApiServer,
+    model: &str,
+    version: Option<&str>,
+) -> Result<String, PinError> {
+    models::find_model_version(&app_state.db_pool, model, version)
+        .await
+        .map_err(|e| internal_error("Failed to resolve model version", e))?
+        .ok_or_else(|| pin_error(StatusCode::NOT_FOUND, "Unknown model or version"))
+}
+
+async fn apply_pin(
+    app_state: &ApiServer,
+    principal: Option<&Principal>,
+    trail: Option<&AuditTrail>,
+    client_ids: &[ClientId],
+    model: Option<&str>,
+    version: Option<&str>,
+) -> Result<PinResponse, PinError> {
+    let version = match model {
+        Some(model) => Some(resolve_pin(app_state, model, version).await?),
+        None => None,
+    };
+    let pinned = model.zip(version.as_deref());
+    let previous =
+        client::set_clients_pinned_model(&app_state.db_pool, client_ids, owner(principal), pinned)
+            .await
+            .map_err(|e| internal_error("Failed to pin model", e))?;
+    audit::set_before(trail, &previous);
+    let updated: Vec<ClientId> = previous.iter().map(|pin| pin.client_id).collect();
+
+    match pinned {
+        Some((model, version)) => {
+            info!(
+                "Pinned model {}:{} to {} clients",
+                model,
+                version,
+                updated.len()
+            );
+            if let Err(e) = notifications::notify_client_owners(
+                &app_state.db_pool,
+                &updated,
+                NotificationEvent::ModelAssigned,
+                &format!("Model {}:{} assigned", model, version),
+                &format!(
+                    "{}:{} was pinned to {} of your devices, they load it on their next heartbeat.",
+                    model,
+                    version,
+                    updated.len()
+                ),
+            )
+            .await
+            {
+                warn!("Failed to notify pin of {}:{}: {}", model, version, e);
+            }
+        }
+        None => info!("Unpinned model of {} clients", updated.len()),
+    }
+    let not_found = client_ids
+        .iter()
+        .filter(|id| !updated.contains(*id))
+        .map(ToString::to_string)
+        .collect();
+    Ok(PinResponse {
+        model: model.map(str::to_string),
+        version,
+        updated: updated.iter().map(ToString::to_string).collect(),
+        not_found,
+    })
+}
+
+// Pin a model version to one client, e.g. POST /api/clients/:client_id/pin
+pub async fn pin_client_model(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path(client_id): Path<String>,
+    Json(payload): Json<PinRequest>,
+) -> PinResult<PinResponse> {
+    let client_ids = parse_client_ids(&[client_id])?;
+    let response = apply_pin(
+        &app_state,
+        principal.as_deref(),
+        trail.as_deref(),
+        &client_ids,
+        Some(payload.model.as_str()),
+        payload.version.as_deref(),
+    )
+    .await?;
+    if response.updated.is_empty() {
+        return Err(pin_error(StatusCode::NOT_FOUND, "Unknown client"));
+    }
+    Ok(Json(ApiResponse::success(response)))
+}
+
+// Give one client automatic models back
+pub async fn unpin_client_model(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path(client_id): Path<String>,
+) -> PinResult<PinResponse> {
+    let client_ids = parse_client_ids(&[client_id])?;
+    let response = apply_pin(
+        &app_state,
+        principal.as_deref(),
+        trail.as_deref(),
+        &client_ids,
+        None,
+        None,
+    )
+    .await?;
+    if response.updated.is_empty() {
+        return Err(pin_error(StatusCode::NOT_FOUND, "Unknown client"));
+    }
+    Ok(Json(ApiResponse::success(response)))
+}
+
+// Pin a model version to, or unpin, a group of clients
+pub async fn bulk_pin_model(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Json(payload): Json<BulkPinRequest>,
+) -> PinResult<PinResponse> {
+    let client_ids = parse_client_ids(&payload.client_ids)?;
+    apply_pin(
+        &app_state,
+        principal.as_deref(),
+        trail.as_deref(),
+        &client_ids,
+        payload.model.as_deref(),
+        payload.version.as_deref(),
+    )
+    .await
+    .map(|response| Json(ApiResponse::success(response)))
+}
@@ -1,9 +1,12 @@
+use crate::api_server::auth::{require_user_id, Principal};
+use crate::api_server::orgs;
 use crate::api_server::ApiServer;
+use crate::db::orgs::OrgRole;
 use crate::util::msg::ApiResponse;
 use axum::{
     extract::{Query, State},
     http::StatusCode,
-    Json,
+    Extension, Json,
 };
 use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
@@ -15,7 +18,10 @@ use sqlx::Row;
 // Request parameters for points query
 #[derive(Debug, Deserialize, Validate)]
 pub struct PointsQueryRequest {
+    #[serde(default)]
     pub user_id: String,
+    /// Points of this organization's workers instead of the user's
+    pub org_id: Option<i64>,
     pub client_id: Option<String>,
     pub client_name: Option<String>,
     pub device_id: Option<i32>,
@@ -50,11 +56,45 @@ pub struct PointsListResponse {
     pub page_size: i32,
 }
 
+/// Check that `user_id` is a member of the organization whose points it asks for
+async fn authorize_org(
+    app_state: &ApiServer,
+    principal: Option<&Principal>,
+    user_id: &str,
+    org_id: i64,
+) -> Result<(), (StatusCode, Json<ApiResponse<()>>)> {
+    orgs::authorize_org(
+        &app_state.db_pool,
+        principal,
+        Some(user_id),
+        org_id,
+        OrgRole::Member,
+    )
+    .await
+    .map_err(|code| {
+        (
+            code,
+            Json(ApiResponse::<()>::error("Forbidden".to_string())),
+        )
+    })
+}
+
 // Query device points for a user with optional filters
 pub async fn get_user_points(
     State(app_state): State<Arc<ApiServer>>,
-    Query(params): Query<PointsQueryRequest>,
+    principal: Option<Extension<Principal>>,
+    Query(mut params): Query<PointsQueryRequest>,
 ) -> Result<Json<ApiResponse<PointsListResponse>>, (StatusCode, Json<ApiResponse<()>>)> {
+    params.user_id = require_user_id(principal.as_deref(), &params.user_id).map_err(|code| {
+        (
+            code,
+            Json(ApiResponse::<()>::error("Invalid user_id".to_string())),
+        )
+    })?;
+    if let Some(org_id) = params.org_id {
+        authorize_org(&app_state, principal.as_deref(), &params.user_id, org_id).await?;
+    }
+
     // Validate input
     if let Err(validation_errors) = params.validate() {
         error!("Validation errors: {:?}", validation_errors);
@@ -103,7 +143,11 @@ pub async fn get_user_points(
     };
 
     // Build the base query with dynamic WHERE conditions
-    let mut query_conditions = vec!["ga.user_id = $1".to_string(), "dpd.points > 0".to_string()];
+    let owner_condition = match params.org_id {
+        Some(_) => "ga.org_id = $1",
+        None => "ga.user_id = $1",
+    };
+    let mut query_conditions = vec![owner_condition.to_string(), "dpd.points > 0".to_string()];
     let mut param_index = 2;
 
     // Add client_id filter if provided (hex string)
@@ -175,8 +219,11 @@ pub async fn get_user_points(
     // Execute query with parameters
     let mut query_builder = sqlx::query(&query);
     
-    // Bind user_id (first parameter)
-    query_builder = query_builder.bind(&params.user_id);
+    // Bind org_id or user_id (first parameter)
+    query_builder = match params.org_id {
+        Some(org_id) => query_builder.bind(org_id),
+        None => query_builder.bind(&params.user_id),
+    };
     
     // Bind optional parameters
     if let Some(client_id_bytes) = client_id_bytes {
@@ -268,3 +315,79 @@ pub async fn get_user_points(
         page_size,
     })))
 }
+
+// Request parameters for work points query
+#[derive(Debug, Deserialize)]
+pub struct WorkPointsQueryRequest {
+    #[serde(default)]
+    pub user_id: String,
+    /// Points of this organization's workers instead of the user's
+    pub org_id: Option<i64>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+}
+
+// Work points of one worker on one day
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct WorkPointsResponse {
+    pub client_id: String,
+    pub client_name: String,
+    pub date: NaiveDate,
+    pub requests: i64,
+    pub completion_tokens: i64,
+    pub sla_met_requests: i64,
+    pub points: f64,
+}
+
+// Query the points a user's workers earned serving inference requests
+pub async fn get_user_work_points(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Query(params): Query<WorkPointsQueryRequest>,
+) -> Result<Json<ApiResponse<Vec<WorkPointsResponse>>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let user_id = require_user_id(principal.as_deref(), &params.user_id).map_err(|code| {
+        (
+            code,
+            Json(ApiResponse::<()>::error("Invalid user_id".to_string())),
+        )
+    })?;
+    if let Some(org_id) = params.org_id {
+        authorize_org(&app_state, principal.as_deref(), &user_id, org_id).await?;
+    }
+
+    let rows = sqlx::query_as::<_, WorkPointsResponse>(
+        r#"
+        SELECT
+            encode(wp.client_id, 'hex') as client_id,
+            COALESCE(ga.client_name, '') as client_name,
+            wp.date,
+            wp.requests,
+            wp.completion_tokens,
+            wp.sla_met_requests,
+            wp.points::DOUBLE PRECISION as points
+        FROM public.client_work_points_daily wp
+        INNER JOIN public.gpu_assets ga ON wp.client_id = ga.client_id
+        WHERE CASE WHEN $4::BIGINT IS NULL THEN ga.user_id = $1 ELSE ga.org_id = $4 END
+          AND ($2::date IS NULL OR wp.date >= $2)
+          AND ($3::date IS NULL OR wp.date <= $3)
+        ORDER BY wp.date DESC, wp.client_id
+        "#,
+    )
+    .bind(&user_id)
+    .bind(params.start_date)
+    .bind(params.end_date)
+    .bind(params.org_id)
+    .fetch_all(&app_state.db_pool)
+    .await
+    .map_err(|e| {
+        error!("Failed to query user work points: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(
+                "internal server error".to_string(),
+            )),
+        )
+    })?;
+
+    Ok(Json(ApiResponse::success(rows)))
+}
@@ -0,0 +1,106 @@
+use crate::api_server::audit::{self, AuditTrail};
+use crate::api_server::auth::Principal;
+use crate::api_server::ApiServer;
+use crate::db::staff::{self, AccessDenial, StaffRole};
+use crate::util::msg::{ApiResponse, ListParams, Page};
+use crate::util::rbac::Role;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, info};
+
+const MAX_USER_ID_CHARS: usize = 64;
+
+type StaffError = (StatusCode, Json<ApiResponse<()>>);
+type StaffResult<T> = Result<Json<ApiResponse<T>>, StaffError>;
+
+fn staff_error(code: StatusCode, msg: impl Into<String>) -> StaffError {
+    (code, Json(ApiResponse::<()>::error(msg.into())))
+}
+
+fn internal_error(context: &str, e: sqlx::Error) -> StaffError {
+    error!("{}: {}", context, e);
+    staff_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRoleRequest {
+    pub role: Role,
+}
+
+// Users with a staff role
+pub async fn list_roles(State(app_state): State<Arc<ApiServer>>) -> StaffResult<Vec<StaffRole>> {
+    let roles = staff::list_roles(&app_state.db_pool)
+        .await
+        .map_err(|e| internal_error("Failed to list staff roles", e))?;
+    Ok(Json(ApiResponse::success(roles)))
+}
+
+// Grant a staff role, e.g. PUT /api/admin/roles/42 {"role": "operator"}
+pub async fn set_role(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path(user_id): Path<String>,
+    Json(payload): Json<SetRoleRequest>,
+) -> StaffResult<()> {
+    if user_id.is_empty() || user_id.len() > MAX_USER_ID_CHARS {
+        return Err(staff_error(StatusCode::BAD_REQUEST, "Invalid user_id"));
+    }
+    let current = staff::get_role(&app_state.db_pool, &user_id)
+        .await
+        .map_err(|e| internal_error("Failed to get staff role", e))?;
+    audit::set_before(trail.as_deref(), &current);
+    let granted_by = match principal.as_deref() {
+        Some(Principal::Staff { user_id, .. }) => Some(user_id.as_str()),
+        _ => None,
+    };
+    staff::set_role(&app_state.db_pool, &user_id, payload.role, granted_by)
+        .await
+        .map_err(|e| internal_error("Failed to set staff role", e))?;
+    info!(
+        "User {} is now {}, granted by {}",
+        user_id,
+        payload.role.as_str(),
+        granted_by.unwrap_or("the API key")
+    );
+    Ok(Json(ApiResponse::success(())))
+}
+
+// Revoke a staff role
+pub async fn remove_role(
+    State(app_state): State<Arc<ApiServer>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path(user_id): Path<String>,
+) -> StaffResult<()> {
+    let current = staff::get_role(&app_state.db_pool, &user_id)
+        .await
+        .map_err(|e| internal_error("Failed to get staff role", e))?;
+    audit::set_before(trail.as_deref(), &current);
+    let removed = staff::remove_role(&app_state.db_pool, &user_id)
+        .await
+        .map_err(|e| internal_error("Failed to remove staff role", e))?;
+    if !removed {
+        return Err(staff_error(StatusCode::NOT_FOUND, "User has no staff role"));
+    }
+    info!("Revoked staff role of user {}", user_id);
+    Ok(Json(ApiResponse::success(())))
+}
+
+// Requests the role check denied, newest first
+pub async fn list_denials(
+    State(app_state): State<Arc<ApiServer>>,
+    Query(list): Query<ListParams>,
+) -> StaffResult<Page<AccessDenial>> {
+    let list = list
+        .resolve(&staff::DENIAL_LIST)
+        .map_err(|e| staff_error(StatusCode::BAD_REQUEST, e))?;
+    let denials = staff::list_denials(&app_state.db_pool, &list)
+        .await
+        .map_err(|e| internal_error("Failed to list access denials", e))?;
+    Ok(Json(ApiResponse::success(denials)))
+}
@@ -0,0 +1,120 @@
+use crate::api_server::auth::Principal;
+use crate::api_server::ApiServer;
+use crate::db::orgs;
+use crate::db::timeline::{self, TimelineEntry};
+use crate::util::msg::ApiResponse;
+use crate::util::protoc::ClientId;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::error;
+
+/// Days shown when the query names no start date
+const DEFAULT_TIMELINE_DAYS: i64 = 7;
+/// Longest range one request may cover
+const MAX_TIMELINE_DAYS: i64 = 31;
+
+type TimelineError = (StatusCode, Json<ApiResponse<()>>);
+
+fn timeline_error(code: StatusCode, msg: impl Into<String>) -> TimelineError {
+    (code, Json(ApiResponse::<()>::error(msg.into())))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimelineQuery {
+    /// First day to include, `DEFAULT_TIMELINE_DAYS` before `end_date` when omitted
+    pub start_date: Option<NaiveDate>,
+    /// Last day to include, today when omitted
+    pub end_date: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimelineResponse {
+    pub client_id: String,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub entries: Vec<TimelineEntry>,
+}
+
+/// Resolve the requested range, both days included
+fn timeline_range(
+    query: &TimelineQuery,
+    today: NaiveDate,
+) -> Result<(NaiveDate, NaiveDate), String> {
+    let end = query.end_date.unwrap_or(today);
+    let start = query
+        .start_date
+        .unwrap_or(end - Duration::days(DEFAULT_TIMELINE_DAYS - 1));
+    if start > end {
+        return Err("start_date is after end_date".to_string());
+    }
+    if (end - start).num_days() >= MAX_TIMELINE_DAYS {
+        return Err(format!("At most {} days per request", MAX_TIMELINE_DAYS));
+    }
+    Ok((start, end))
+}
+
+// Merged history of one client, e.g. GET /api/clients/:client_id/timeline?start_date=2025-08-01
+pub async fn get_client_timeline(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Path(client_id): Path<String>,
+    Query(query): Query<TimelineQuery>,
+) -> Result<Json<ApiResponse<TimelineResponse>>, TimelineError> {
+    let client_id: ClientId = client_id
+        .parse()
+        .map_err(|_| timeline_error(StatusCode::BAD_REQUEST, "Invalid client_id"))?;
+    let (start, end) = timeline_range(&query, Utc::now().date_naive())
+        .map_err(|e| timeline_error(StatusCode::BAD_REQUEST, e))?;
+
+    // Providers only see their own clients and those of their organizations
+    if let Some(Principal::User(user_id)) = principal.as_deref() {
+        let visible = orgs::can_see_client(&app_state.db_pool, &client_id, user_id)
+            .await
+            .map_err(|e| {
+                error!("Failed to check access to {}: {}", client_id, e);
+                timeline_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+            })?;
+        if !visible {
+            return Err(timeline_error(StatusCode::NOT_FOUND, "Unknown client"));
+        }
+    }
+
+    let entries = timeline::get_client_timeline(&app_state.db_pool, &client_id, start, end)
+        .await
+        .map_err(|e| {
+            error!("Failed to build timeline of {}: {}", client_id, e);
+            timeline_error(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+        })?;
+    Ok(Json(ApiResponse::success(TimelineResponse {
+        client_id: client_id.to_string(),
+        start_date: start,
+        end_date: end,
+        entries,
+    })))
+}
+
+#[test]
+fn test_timeline_range() {
+    let day = |d| NaiveDate::from_ymd_opt(2025, 8, d).unwrap();
+    let query = |start, end| TimelineQuery {
+        start_date: start,
+        end_date: end,
+    };
+
+    assert_eq!(
+        timeline_range(&query(None, None), day(10)),
+        Ok((day(4), day(10)))
+    );
+    assert_eq!(
+        timeline_range(&query(Some(day(1)), Some(day(1))), day(10)),
+        Ok((day(1), day(1)))
+    );
+    assert!(timeline_range(&query(Some(day(5)), Some(day(4))), day(10)).is_err());
+    assert!(timeline_range(&query(Some(day(1)), None), day(31).succ_opt().unwrap()).is_err());
+}
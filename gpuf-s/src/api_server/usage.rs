@@ -0,0 +1,107 @@
+use crate::api_server::auth::Principal;
+use crate::api_server::orgs;
+use crate::api_server::ApiServer;
+use crate::db::orgs::OrgRole;
+use crate::db::usage::{self, UsageAggregate, UsageFilter};
+use crate::util::msg::{ApiResponse, ListParams, Page};
+use crate::util::protoc::ClientId;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::error;
+
+// Request parameters for usage aggregation
+#[derive(Debug, Deserialize)]
+pub struct UsageQueryRequest {
+    /// Comma separated dimensions: day, hour, model, client_id, api_key, status
+    pub group_by: Option<String>,
+    pub user_id: Option<i64>,
+    /// Usage of this organization's keys instead of the user's
+    pub org_id: Option<i64>,
+    pub api_key: Option<String>,
+    pub client_id: Option<String>,
+    pub model: Option<String>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+}
+
+// Aggregate usage records, e.g. /api/usage?group_by=day,model&user_id=2
+pub async fn get_usage(
+    State(app_state): State<Arc<ApiServer>>,
+    principal: Option<Extension<Principal>>,
+    Query(params): Query<UsageQueryRequest>,
+    Query(list): Query<ListParams>,
+) -> Result<Json<ApiResponse<Page<UsageAggregate>>>, (StatusCode, Json<ApiResponse<()>>)> {
+    let bad_request = |msg: String| (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(msg)));
+
+    let group_by =
+        usage::parse_group_by(params.group_by.as_deref().unwrap_or("day")).map_err(bad_request)?;
+
+    let requested_user_id = params.user_id.map(|id| id.to_string());
+    if let Some(org_id) = params.org_id {
+        orgs::authorize_org(
+            &app_state.db_pool,
+            principal.as_deref(),
+            requested_user_id.as_deref(),
+            org_id,
+            OrgRole::Member,
+        )
+        .await
+        .map_err(|code| {
+            (
+                code,
+                Json(ApiResponse::<()>::error("Forbidden".to_string())),
+            )
+        })?;
+    }
+    let user_id = Principal::resolve_user_id(principal.as_deref(), requested_user_id.as_deref())
+        .map_err(|code| {
+            (
+                code,
+                Json(ApiResponse::<()>::error("Forbidden".to_string())),
+            )
+        })?
+        .map(|id| id.parse::<i64>())
+        .transpose()
+        .map_err(|_| bad_request("Invalid user_id".to_string()))?;
+
+    let client_id = match params.client_id.as_deref() {
+        Some(raw) => Some(
+            ClientId::from_str(raw)
+                .map_err(|e| bad_request(format!("Invalid client_id: {}", e)))?,
+        ),
+        None => None,
+    };
+
+    let filter = UsageFilter {
+        // An organization's usage covers the keys of all its members
+        user_id: user_id.filter(|_| params.org_id.is_none()),
+        org_id: params.org_id,
+        api_key: params.api_key,
+        client_id,
+        model: params.model,
+        start_date: params.start_date,
+        end_date: params.end_date,
+    };
+
+    let list = list.resolve(&usage::USAGE_LIST).map_err(bad_request)?;
+
+    match usage::get_usage_aggregates(&app_state.db_pool, &group_by, &filter, &list).await {
+        Ok(rows) => Ok(Json(ApiResponse::success(rows))),
+        Err(e) => {
+            error!("Failed to aggregate usage: {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::<()>::error(
+                    "Failed to aggregate usage".to_string(),
+                )),
+            ))
+        }
+    }
+}
@@ -1,6 +1,12 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
-use gpuf_s::api_server::ApiServer;
+use common::secrets::load_secret;
+use gpuf_s::api_server::{
+    auth::{ApiAuth, JwtConfig},
+    ApiServer,
+};
+use gpuf_s::util::net::{IpRange, ListenAddr};
+use std::net::IpAddr;
 use std::sync::Arc;
 use tracing::Level;
 
@@ -10,11 +16,65 @@ struct Args {
     #[arg(short, long, default_value_t = 18081)]
     port: u16,
 
-    #[arg(long, env = "DATABASE_URL")]
-    database_url: String,
+    /// Address to listen on; `::` accepts IPv4 and IPv6 clients
+    #[arg(long, default_value = "::", env = "API_SERVER_BIND_ADDR")]
+    bind_addr: IpAddr,
+
+    /// Accept only IPv6 clients on `::` instead of both
+    #[arg(long)]
+    ipv6_only: bool,
+
+    #[arg(
+        long,
+        env = "DATABASE_URL",
+        required_unless_present = "database_url_file"
+    )]
+    database_url: Option<String>,
+
+    /// File holding the database URL, e.g. a Docker or Kubernetes secret
+    #[arg(long, env = "DATABASE_URL_FILE")]
+    database_url_file: Option<String>,
 
     #[arg(long, default_value = "redis://localhost:6379", env = "REDIS_URL")]
     redis_url: String,
+
+    /// File holding the Redis URL, instead of `--redis-url`
+    #[arg(long, env = "REDIS_URL_FILE")]
+    redis_url_file: Option<String>,
+
+    /// Kafka broker checked by `/readyz`; Kafka is not checked when unset
+    #[arg(long, env = "KAFKA_BOOTSTRAP_SERVER")]
+    bootstrap_server: Option<String>,
+
+    /// Shared API key accepted as `Authorization: Bearer` or `X-API-Key`
+    #[arg(long, env = "API_SERVER_KEY")]
+    api_key: Option<String>,
+
+    /// File holding the API key, instead of `--api-key`
+    #[arg(long, env = "API_SERVER_KEY_FILE")]
+    api_key_file: Option<String>,
+
+    /// OIDC issuer; enables JWT authentication when set
+    #[arg(long, env = "JWT_ISSUER")]
+    jwt_issuer: Option<String>,
+
+    /// JWKS endpoint, discovered from the issuer when not set
+    #[arg(long, env = "JWT_JWKS_URL")]
+    jwt_jwks_url: Option<String>,
+
+    /// Expected `aud` claim
+    #[arg(long, env = "JWT_AUDIENCE")]
+    jwt_audience: Option<String>,
+
+    /// Claim mapped to user_id
+    #[arg(long, default_value = "sub", env = "JWT_USER_CLAIM")]
+    jwt_user_claim: String,
+
+    /// Load balancers in front of the API, addresses or CIDR ranges,
+    /// comma-separated. The audit log only takes the client IP from
+    /// `X-Forwarded-For` on requests they pass on.
+    #[arg(long, env = "API_SERVER_TRUSTED_PROXIES", value_delimiter = ',')]
+    trusted_proxies: Vec<IpRange>,
 }
 
 #[tokio::main]
@@ -24,9 +84,51 @@ async fn main() -> Result<()> {
         .init();
 
     let args = Args::parse();
+    // Values may also be `env:NAME` to read another environment variable
+    let database_url = load_secret(
+        "database_url",
+        args.database_url.as_deref(),
+        false,
+        args.database_url_file.as_deref(),
+        None,
+    )?
+    .context("--database-url or --database-url-file is required")?;
+    let redis_url = load_secret(
+        "redis_url",
+        Some(&args.redis_url),
+        false,
+        args.redis_url_file.as_deref(),
+        None,
+    )?
+    .unwrap_or(args.redis_url);
+    let api_key = load_secret(
+        "api_key",
+        args.api_key.as_deref(),
+        false,
+        args.api_key_file.as_deref(),
+        None,
+    )?;
+
+    let jwt = args.jwt_issuer.map(|issuer| JwtConfig {
+        issuer,
+        jwks_url: args.jwt_jwks_url,
+        audience: args.jwt_audience,
+        user_id_claim: args.jwt_user_claim,
+    });
 
-    let server_state = Arc::new(ApiServer::new(&args.database_url, &args.redis_url).await?);
+    let mut server = ApiServer::new(&database_url, &redis_url)
+        .await?
+        .with_auth(ApiAuth::new(api_key, jwt))
+        .with_trusted_proxies(args.trusted_proxies);
+    if let Some(bootstrap_server) = args.bootstrap_server.as_deref() {
+        server = server.with_kafka(bootstrap_server)?;
+    }
+    let server_state = Arc::new(server);
 
-    server_state.run_api_server(args.port).await?;
+    let listen_addr = ListenAddr {
+        ip: args.bind_addr,
+        ipv6_only: args.ipv6_only,
+    };
+    server_state.run_api_server(listen_addr, args.port).await?;
     Ok(())
 }
@@ -0,0 +1,121 @@
+//! Heartbeats the consumer could not process are published unchanged to a
+//! dead-letter topic, with headers saying where and why they failed, so
+//! `replay_dlq` can process them again once the cause is fixed.
+
+use anyhow::Result;
+use rdkafka::config::ClientConfig;
+use rdkafka::message::{Header, Message, OwnedHeaders, OwnedMessage};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use std::fmt;
+use std::time::Duration;
+use tracing::error;
+
+pub const HEARTBEAT_DLQ_TOPIC: &str = "client-heartbeats-dlq";
+
+/// Step of heartbeat processing that failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadLetterStage {
+    /// The payload is not a bincode `HeartbeatMessage`
+    Decode,
+    /// Storing the heartbeat in PostgreSQL failed
+    Database,
+}
+
+impl DeadLetterStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Decode => "decode",
+            Self::Database => "database",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ProcessError {
+    pub stage: DeadLetterStage,
+    pub error: String,
+}
+
+impl ProcessError {
+    pub fn new(stage: DeadLetterStage, error: impl fmt::Display) -> Self {
+        Self {
+            stage,
+            error: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: {}", self.stage.as_str(), self.error)
+    }
+}
+
+pub struct DeadLetterQueue {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl DeadLetterQueue {
+    pub fn new(bootstrap_servers: &str, topic: &str) -> Result<Self> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", bootstrap_servers)
+            .create()?;
+        Ok(Self {
+            producer,
+            topic: topic.to_string(),
+        })
+    }
+
+    /// Publish a failed message with its original key, payload and timestamp.
+    /// Delivery errors are only logged, the message is lost then.
+    pub async fn send(&self, message: &OwnedMessage, failure: &ProcessError) {
+        let partition = message.partition().to_string();
+        let offset = message.offset().to_string();
+        let failed_at = chrono::Utc::now().to_rfc3339();
+        let headers = OwnedHeaders::new()
+            .insert(Header {
+                key: "dlq-stage",
+                value: Some(failure.stage.as_str()),
+            })
+            .insert(Header {
+                key: "dlq-error",
+                value: Some(failure.error.as_str()),
+            })
+            .insert(Header {
+                key: "dlq-source-topic",
+                value: Some(message.topic()),
+            })
+            .insert(Header {
+                key: "dlq-source-partition",
+                value: Some(partition.as_str()),
+            })
+            .insert(Header {
+                key: "dlq-source-offset",
+                value: Some(offset.as_str()),
+            })
+            .insert(Header {
+                key: "dlq-failed-at",
+                value: Some(failed_at.as_str()),
+            });
+
+        let mut record = FutureRecord::<[u8], [u8]>::to(&self.topic).headers(headers);
+        if let Some(key) = message.key() {
+            record = record.key(key);
+        }
+        if let Some(payload) = message.payload() {
+            record = record.payload(payload);
+        }
+        // Replays take the heartbeat's event time from the message timestamp
+        if let Some(ms) = message.timestamp().to_millis() {
+            record = record.timestamp(ms);
+        }
+
+        if let Err((e, _)) = self.producer.send(record, Duration::from_secs(0)).await {
+            error!(
+                "Failed to publish message at offset {} to {}: {}",
+                offset, self.topic, e
+            );
+        }
+    }
+}
@@ -1,13 +1,23 @@
-use anyhow::Result;
-use chrono::{TimeZone, Utc};
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use futures::future::join_all;
 use rdkafka::message::Timestamp;
 use rdkafka::message::{Message, OwnedMessage};
 use sqlx::{Pool, Postgres};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use tokio::sync::mpsc;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-use crate::db::stats::{insert_heartbeat, ClientDailyStats, DeviceDailyStats};
-use crate::util::protoc;
+use crate::consumer::dead_letter::{DeadLetterQueue, DeadLetterStage, ProcessError};
+use crate::consumer::heartbeat_validator::{
+    HeartbeatAnomaly, HeartbeatValidationConfig, HeartbeatValidator,
+};
+use crate::consumer::telemetry::TelemetrySink;
+use crate::db::anomalies::{self, AnomalyRecord};
+use crate::db::stats::{store_heartbeats, HeartbeatRow};
+use crate::util::protoc::{self, ClientId};
 use common::format_bytes;
 
 #[allow(dead_code)]
@@ -16,11 +26,16 @@ pub async fn start_processor(
     db_pool: Pool<Postgres>,
     batch_size: usize,
     batch_timeout_secs: u64,
+    parallelism: usize,
+    validation: HeartbeatValidationConfig,
+    dead_letters: Option<DeadLetterQueue>,
+    telemetry: Option<TelemetrySink>,
 ) -> Result<()> {
     info!(
-        "Starting heartbeat processor with batch size: {}, timeout: {}s",
-        batch_size, batch_timeout_secs
+        "Starting heartbeat processor with batch size: {}, timeout: {}s, parallelism: {}",
+        batch_size, batch_timeout_secs, parallelism
     );
+    let mut validator = HeartbeatValidator::new(validation);
 
     loop {
         match rx.recv().await {
@@ -28,7 +43,16 @@ pub async fn start_processor(
                 let pool = db_pool.clone();
                 let message_count = messages.len();
 
-                if let Err(e) = process_batch(messages, pool).await {
+                if let Err(e) = process_batch(
+                    messages,
+                    pool,
+                    &mut validator,
+                    dead_letters.as_ref(),
+                    telemetry.as_ref(),
+                    parallelism,
+                )
+                .await
+                {
                     error!("Error processing batch: {}", e);
                 }
 
@@ -44,127 +68,376 @@ pub async fn start_processor(
     Ok(())
 }
 
+/// A decoded heartbeat that passed validation
+struct Accepted {
+    message: OwnedMessage,
+    heartbeat: protoc::HeartbeatMessage,
+    event_ts: DateTime<Utc>,
+}
+
+/// Decode and validate a batch in message order, then store it in up to
+/// `parallelism` concurrent transactions. Kafka keys heartbeats by client, so
+/// a client's heartbeats arrive in order from one partition; each transaction
+/// gets a disjoint set of clients and stores their heartbeats in that order,
+/// with one multi-row statement per table for each round. The stored ones
+/// are copied to the telemetry sink, if any.
 #[allow(dead_code)]
-async fn process_batch(messages: Vec<OwnedMessage>, db_pool: Pool<Postgres>) -> Result<()> {
+async fn process_batch(
+    messages: Vec<OwnedMessage>,
+    db_pool: Pool<Postgres>,
+    validator: &mut HeartbeatValidator,
+    dead_letters: Option<&DeadLetterQueue>,
+    telemetry: Option<&TelemetrySink>,
+    parallelism: usize,
+) -> Result<()> {
+    let mut decoded = Vec::with_capacity(messages.len());
     for message in messages {
-        match message.key() {
-            Some(_key) => {
-                let event_ts = match message.timestamp() {
-                    Timestamp::NotAvailable => Utc::now(),
-                    Timestamp::CreateTime(ms) | Timestamp::LogAppendTime(ms) => Utc
-                        .timestamp_millis_opt(ms)
-                        .single()
-                        .unwrap_or_else(Utc::now),
-                };
-
-                // Parse the message payload
-                let payload = match message.payload() {
-                    Some(p) => p,
-                    None => {
-                        error!("Message has no payload, skipping");
-                        continue;
-                    }
-                };
-
-                // Log raw payload for debugging
-                debug!(
-                    "Raw payload: {:?}",
-                    std::str::from_utf8(payload).unwrap_or("[invalid utf8]")
-                );
-                let cfg = bincode::config::standard()
-                    .with_fixed_int_encoding()
-                    .with_little_endian();
-                // Try to deserialize as JSON first
-                let (heartbeat, _): (protoc::HeartbeatMessage, _) =
-                    match bincode::decode_from_slice(payload, cfg) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            error!("Failed to deserialize heartbeat: {}", e);
-                            continue;
-                        }
-                    };
-
-                let mut transaction = match db_pool.begin().await {
-                    Ok(tx) => tx,
-                    Err(e) => {
-                        error!("Failed to start DB transaction: {}", e);
-                        continue;
-                    }
-                };
-
-                info!("Heartbeat received from client {} total_tflops {} cpu_usage {}% memory_usage {}% disk_usage {}% network_up {} network_down {}", heartbeat.client_id, heartbeat.total_tflops, heartbeat.system_info.cpu_usage, heartbeat.system_info.memory_usage, heartbeat.system_info.disk_usage,  format_bytes!(heartbeat.system_info.network_tx),format_bytes!(heartbeat.system_info.network_rx));
-                // Update last seen timestamp with safe type conversion
-                if let Err(e) = insert_heartbeat(
-                    &mut transaction,
-                    &heartbeat.client_id,
-                    &heartbeat.system_info,
-                    &heartbeat.devices_info,
-                    heartbeat.device_memtotal_gb.try_into().unwrap_or(0),
-                    heartbeat.device_count.try_into().unwrap_or(0),
-                    heartbeat.total_tflops.try_into().unwrap_or(0),
-                    Some(event_ts),
-                )
-                .await
-                {
-                    error!(
-                        "Failed to update heartbeat for client {:?}: {}",
-                        heartbeat.client_id, e
-                    );
-                    let _ = transaction.rollback().await;
-                    continue;
-                }
+        match decode_message(&message) {
+            Ok(Some((heartbeat, event_ts))) => decoded.push(Accepted {
+                message,
+                heartbeat,
+                event_ts,
+            }),
+            Ok(None) => {}
+            Err(e) => dead_letter(dead_letters, &message, &e).await,
+        }
+    }
 
-                if let Err(e) = ClientDailyStats::upsert(
-                    &mut transaction,
-                    &heartbeat.client_id,
-                    Some(heartbeat.system_info.cpu_usage as f64),
-                    Some(heartbeat.system_info.memory_usage as f64),
-                    Some(heartbeat.system_info.disk_usage as f64),
-                    Some(heartbeat.system_info.network_rx.try_into().unwrap_or(0)),
-                    Some(heartbeat.system_info.network_tx.try_into().unwrap_or(0)),
-                    event_ts,
-                )
-                .await
-                {
-                    error!(
-                        "Failed to update client heartbeat for client {}: {}",
-                        heartbeat.client_id, e
-                    );
-                    let _ = transaction.rollback().await;
-                    continue;
-                }
-                if let Err(e) = DeviceDailyStats::upsert_batch(
-                    &mut transaction,
-                    &heartbeat.client_id,
-                    &heartbeat.devices_info,
-                    event_ts,
-                )
-                .await
-                {
-                    error!(
-                        "Failed to update device heartbeat for client {}: {}",
-                        heartbeat.client_id, e
-                    );
-                    let _ = transaction.rollback().await;
-                    continue;
-                }
+    let client_ids: Vec<ClientId> = decoded.iter().map(|a| a.heartbeat.client_id).collect();
+    let mut quarantined = load_quarantined(&db_pool, validator, &client_ids).await;
+    let mut accepted = Vec::with_capacity(decoded.len());
+    for item in decoded {
+        let payload = item.message.payload().unwrap_or_default();
+        if check_heartbeat(
+            &db_pool,
+            validator,
+            &mut quarantined,
+            &item.heartbeat,
+            payload,
+            item.event_ts,
+        )
+        .await
+        {
+            log_heartbeat(&item.heartbeat);
+            accepted.push((item.heartbeat.client_id, item));
+        }
+    }
 
-                if let Err(e) = transaction.commit().await {
-                    error!("Failed to commit transaction for client {}: {}", heartbeat.client_id, e);
-                    continue;
-                }
+    let shards = shard_rounds(accepted, parallelism.max(1));
+    let rows: Vec<Vec<Vec<HeartbeatRow>>> = shards
+        .iter()
+        .map(|rounds| {
+            rounds
+                .iter()
+                .map(|round| {
+                    round
+                        .iter()
+                        .map(|a| heartbeat_row(&a.heartbeat, a.event_ts))
+                        .collect()
+                })
+                .collect()
+        })
+        .collect();
+    let results = join_all(rows.iter().map(|rounds| store_rounds(&db_pool, rounds))).await;
 
-                debug!(
-                    "Successfully processed heartbeat for client: {}",
-                    heartbeat.client_id
-                );
-            }
-            None => {
-                debug!("Received message with no key, skipping");
-                continue;
+    let mut stored = Vec::new();
+    for ((rounds, shard_rows), result) in shards.iter().zip(&rows).zip(results) {
+        if let Err(e) = result {
+            // The whole transaction rolled back, none of its heartbeats are stored
+            let failure = ProcessError::new(DeadLetterStage::Database, format!("{:#}", e));
+            for item in rounds.iter().flatten() {
+                dead_letter(dead_letters, &item.message, &failure).await;
             }
+        } else {
+            stored.extend(shard_rows.iter().flatten().copied());
+        }
+    }
+
+    if let Some(telemetry) = telemetry {
+        // Best effort, the heartbeats and their rollups are already stored
+        if let Err(e) = telemetry.write(&stored).await {
+            error!(
+                "Failed to write {} heartbeats to telemetry sink: {}",
+                stored.len(),
+                e
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Decode, validate and store one heartbeat message. Messages without a key
+/// or payload and rejected heartbeats are skipped, not failures.
+pub async fn process_message(
+    message: &OwnedMessage,
+    db_pool: &Pool<Postgres>,
+    validator: &mut HeartbeatValidator,
+) -> Result<(), ProcessError> {
+    let Some((heartbeat, event_ts)) = decode_message(message)? else {
+        return Ok(());
+    };
+    let payload = message.payload().unwrap_or_default();
+
+    let mut quarantined = load_quarantined(db_pool, validator, &[heartbeat.client_id]).await;
+    if !check_heartbeat(
+        db_pool,
+        validator,
+        &mut quarantined,
+        &heartbeat,
+        payload,
+        event_ts,
+    )
+    .await
+    {
+        return Ok(());
+    }
+
+    log_heartbeat(&heartbeat);
+    store_rounds(db_pool, &[vec![heartbeat_row(&heartbeat, event_ts)]])
+        .await
+        .map_err(|e| ProcessError::new(DeadLetterStage::Database, format!("{:#}", e)))?;
+
+    debug!(
+        "Successfully processed heartbeat for client: {}",
+        heartbeat.client_id
+    );
+    Ok(())
+}
+
+/// Decode a heartbeat and its event time. Messages without a key or payload
+/// give `None`.
+fn decode_message(
+    message: &OwnedMessage,
+) -> Result<Option<(protoc::HeartbeatMessage, DateTime<Utc>)>, ProcessError> {
+    if message.key().is_none() {
+        debug!("Received message with no key, skipping");
+        return Ok(None);
+    }
+    let event_ts = match message.timestamp() {
+        Timestamp::NotAvailable => Utc::now(),
+        Timestamp::CreateTime(ms) | Timestamp::LogAppendTime(ms) => Utc
+            .timestamp_millis_opt(ms)
+            .single()
+            .unwrap_or_else(Utc::now),
+    };
+
+    // Parse the message payload
+    let payload = match message.payload() {
+        Some(p) => p,
+        None => {
+            error!("Message has no payload, skipping");
+            return Ok(None);
         }
+    };
+
+    // Log raw payload for debugging
+    debug!(
+        "Raw payload: {:?}",
+        std::str::from_utf8(payload).unwrap_or("[invalid utf8]")
+    );
+    let cfg = bincode::config::standard()
+        .with_fixed_int_encoding()
+        .with_little_endian();
+    let (heartbeat, _): (protoc::HeartbeatMessage, _) = bincode::decode_from_slice(payload, cfg)
+        .map_err(|e| ProcessError::new(DeadLetterStage::Decode, e))?;
+    Ok(Some((heartbeat, event_ts)))
+}
+
+fn log_heartbeat(heartbeat: &protoc::HeartbeatMessage) {
+    info!("Heartbeat received from client {} total_tflops {} cpu_usage {}% memory_usage {}% disk_usage {}% network_up {} network_down {}", heartbeat.client_id, heartbeat.total_tflops, heartbeat.system_info.cpu_usage, heartbeat.system_info.memory_usage, heartbeat.system_info.disk_usage,  format_bytes!(heartbeat.system_info.network_tx),format_bytes!(heartbeat.system_info.network_rx));
+}
+
+async fn dead_letter(
+    dead_letters: Option<&DeadLetterQueue>,
+    message: &OwnedMessage,
+    failure: &ProcessError,
+) {
+    error!(
+        "Heartbeat at offset {} of partition {}: {}",
+        message.offset(),
+        message.partition(),
+        failure
+    );
+    if let Some(dead_letters) = dead_letters {
+        dead_letters.send(message, failure).await;
+    }
+}
+
+fn heartbeat_row(
+    heartbeat: &protoc::HeartbeatMessage,
+    event_ts: DateTime<Utc>,
+) -> HeartbeatRow<'_> {
+    HeartbeatRow {
+        client_id: &heartbeat.client_id,
+        system_info: &heartbeat.system_info,
+        devices_info: &heartbeat.devices_info,
+        device_memtotal_gb: heartbeat.device_memtotal_gb.try_into().unwrap_or(0),
+        device_count: heartbeat.device_count.try_into().unwrap_or(0),
+        total_tflops: heartbeat.total_tflops.try_into().unwrap_or(0),
+        timestamp: event_ts,
+        worker_version: heartbeat.worker_version.as_ref(),
+    }
+}
+
+/// Store heartbeats and their daily stats in one transaction, round by round
+async fn store_rounds(db_pool: &Pool<Postgres>, rounds: &[Vec<HeartbeatRow<'_>>]) -> Result<()> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("failed to start DB transaction")?;
+
+    for rows in rounds {
+        store_heartbeats(&mut transaction, rows)
+            .await
+            .context("failed to store heartbeats")?;
     }
 
+    // Dropping the transaction on an error above rolls it back
+    transaction
+        .commit()
+        .await
+        .context("failed to commit transaction")?;
     Ok(())
 }
+
+/// Split items into at most `shards` groups by client, each a list of rounds
+/// with at most one item per client. A client's items stay in one group, in
+/// their original order across its rounds.
+fn shard_rounds<T>(items: Vec<(ClientId, T)>, shards: usize) -> Vec<Vec<Vec<T>>> {
+    let mut grouped: Vec<Vec<Vec<T>>> = (0..shards).map(|_| Vec::new()).collect();
+    let mut next_round: HashMap<ClientId, usize> = HashMap::new();
+    for (client_id, item) in items {
+        let mut hasher = DefaultHasher::new();
+        client_id.hash(&mut hasher);
+        let rounds = &mut grouped[(hasher.finish() % shards as u64) as usize];
+
+        let round = next_round.entry(client_id).or_insert(0);
+        if rounds.len() <= *round {
+            rounds.push(Vec::new());
+        }
+        rounds[*round].push(item);
+        *round += 1;
+    }
+    grouped.retain(|rounds| !rounds.is_empty());
+    grouped
+}
+
+/// The quarantined ones of `client_ids`, empty while quarantine is off
+async fn load_quarantined(
+    db_pool: &Pool<Postgres>,
+    validator: &HeartbeatValidator,
+    client_ids: &[ClientId],
+) -> HashSet<ClientId> {
+    if validator.config().quarantine_after <= 0 || client_ids.is_empty() {
+        return HashSet::new();
+    }
+    anomalies::quarantined_clients(db_pool, client_ids)
+        .await
+        .unwrap_or_else(|e| {
+            error!(
+                "Failed to check quarantine of {} clients: {}",
+                client_ids.len(),
+                e
+            );
+            HashSet::new()
+        })
+}
+
+/// Validate a heartbeat, record its anomalies and quarantine clients with too
+/// many of them, adding them to `quarantined`. Returns whether the heartbeat
+/// should be stored.
+async fn check_heartbeat(
+    db_pool: &Pool<Postgres>,
+    validator: &mut HeartbeatValidator,
+    quarantined: &mut HashSet<ClientId>,
+    heartbeat: &protoc::HeartbeatMessage,
+    payload: &[u8],
+    event_ts: DateTime<Utc>,
+) -> bool {
+    let client_id = &heartbeat.client_id;
+    let quarantine_after = validator.config().quarantine_after;
+    if quarantined.contains(client_id) {
+        debug!("Dropping heartbeat of quarantined client {}", client_id);
+        return false;
+    }
+
+    let found = validator.validate(heartbeat, payload, event_ts, Utc::now());
+    if found.is_empty() {
+        return true;
+    }
+    let rejected = found.iter().any(HeartbeatAnomaly::rejects);
+    let records: Vec<AnomalyRecord> = found
+        .iter()
+        .map(|anomaly| AnomalyRecord {
+            kind: anomaly.kind(),
+            detail: anomaly.detail(),
+            rejected,
+        })
+        .collect();
+    warn!(
+        "Heartbeat from client {} {}: {}",
+        client_id,
+        if rejected { "rejected" } else { "flagged" },
+        records
+            .iter()
+            .map(|r| r.detail.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    if let Err(e) =
+        anomalies::insert_heartbeat_anomalies(db_pool, client_id, event_ts, &records).await
+    {
+        error!(
+            "Failed to record heartbeat anomalies of client {}: {}",
+            client_id, e
+        );
+    }
+
+    if quarantine_after > 0 {
+        match anomalies::count_recent_anomalies(db_pool, client_id).await {
+            Ok(count) if count >= quarantine_after => {
+                match anomalies::quarantine_client(db_pool, client_id).await {
+                    Ok(true) => {
+                        warn!(
+                            "Quarantined client {} after {} heartbeat anomalies in a day",
+                            client_id, count
+                        );
+                        quarantined.insert(*client_id);
+                    }
+                    Ok(false) => {}
+                    Err(e) => error!("Failed to quarantine client {}: {}", client_id, e),
+                }
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to count anomalies of client {}: {}", client_id, e),
+        }
+    }
+
+    !rejected
+}
+
+#[test]
+fn test_shard_rounds() {
+    let a = ClientId([1; 16]);
+    let b = ClientId([2; 16]);
+    let items = vec![(a, "a1"), (b, "b1"), (a, "a2"), (a, "a3")];
+
+    assert_eq!(
+        shard_rounds(items.clone(), 1),
+        vec![vec![vec!["a1", "b1"], vec!["a2"], vec!["a3"]]]
+    );
+
+    let shards = shard_rounds(items, 4);
+    assert_eq!(shards.iter().flatten().flatten().count(), 4);
+    let of_a: Vec<&str> = shards
+        .iter()
+        .find(|rounds| rounds[0].contains(&"a1"))
+        .unwrap()
+        .iter()
+        .flatten()
+        .copied()
+        .filter(|item| item.starts_with('a'))
+        .collect();
+    assert_eq!(of_a, vec!["a1", "a2", "a3"]);
+}
@@ -0,0 +1,272 @@
+//! Plausibility checks on heartbeats before they count towards points.
+//! Heartbeats that would inflate the heartbeat count are rejected, values a
+//! working client cannot report are only flagged.
+
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+use crate::util::protoc::{ClientId, HeartbeatMessage};
+
+/// Highest network traffic reported per second, 100 Gbit/s
+const MAX_NETWORK_BYTES_PER_SEC: u64 = 12_500_000_000;
+/// Highest device temperature reported, in degrees Celsius
+const MAX_DEVICE_TEMP: u64 = 150;
+
+#[derive(Debug, Clone)]
+pub struct HeartbeatValidationConfig {
+    /// Heartbeats of a client arriving closer together are rejected
+    pub min_interval: Duration,
+    /// Heartbeats timestamped further in the future are rejected
+    pub max_clock_skew: Duration,
+    /// Quarantine a client after this many anomalies within a day, 0 never does
+    pub quarantine_after: i64,
+}
+
+impl Default for HeartbeatValidationConfig {
+    fn default() -> Self {
+        Self {
+            min_interval: Duration::seconds(60),
+            max_clock_skew: Duration::seconds(300),
+            quarantine_after: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeartbeatAnomaly {
+    /// Same payload as the client's previous heartbeat, sooner than the minimum interval
+    Replay {
+        elapsed_secs: i64,
+    },
+    /// Different payload, sooner than the minimum interval
+    TooFrequent {
+        elapsed_secs: i64,
+    },
+    FutureTimestamp {
+        skew_secs: i64,
+    },
+    /// CPU, memory, disk or device utilization above 100%
+    UtilizationOutOfRange {
+        field: String,
+        value: u64,
+    },
+    DeviceTemperatureOutOfRange {
+        device: u16,
+        temp: u64,
+    },
+    /// Traffic that only a counter going backwards or an impossible link can produce
+    ImplausibleNetwork {
+        field: &'static str,
+        bytes: u64,
+    },
+}
+
+impl HeartbeatAnomaly {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Replay { .. } => "replay",
+            Self::TooFrequent { .. } => "too_frequent",
+            Self::FutureTimestamp { .. } => "future_timestamp",
+            Self::UtilizationOutOfRange { .. } => "utilization_out_of_range",
+            Self::DeviceTemperatureOutOfRange { .. } => "temperature_out_of_range",
+            Self::ImplausibleNetwork { .. } => "implausible_network",
+        }
+    }
+
+    /// Whether the heartbeat is dropped rather than stored with the anomaly
+    pub fn rejects(&self) -> bool {
+        matches!(
+            self,
+            Self::Replay { .. } | Self::TooFrequent { .. } | Self::FutureTimestamp { .. }
+        )
+    }
+
+    pub fn detail(&self) -> String {
+        match self {
+            Self::Replay { elapsed_secs } => {
+                format!(
+                    "identical payload {}s after the previous heartbeat",
+                    elapsed_secs
+                )
+            }
+            Self::TooFrequent { elapsed_secs } => {
+                format!("{}s after the previous heartbeat", elapsed_secs)
+            }
+            Self::FutureTimestamp { skew_secs } => format!("{}s in the future", skew_secs),
+            Self::UtilizationOutOfRange { field, value } => format!("{} at {}%", field, value),
+            Self::DeviceTemperatureOutOfRange { device, temp } => {
+                format!("device {} at {}°C", device, temp)
+            }
+            Self::ImplausibleNetwork { field, bytes } => format!("{} of {} bytes", field, bytes),
+        }
+    }
+}
+
+struct LastHeartbeat {
+    payload_hash: [u8; 16],
+    event_ts: DateTime<Utc>,
+}
+
+/// Remembers the last accepted heartbeat of each client
+#[derive(Default)]
+pub struct HeartbeatValidator {
+    config: HeartbeatValidationConfig,
+    last: HashMap<ClientId, LastHeartbeat>,
+}
+
+impl HeartbeatValidator {
+    pub fn new(config: HeartbeatValidationConfig) -> Self {
+        Self {
+            config,
+            last: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &HeartbeatValidationConfig {
+        &self.config
+    }
+
+    /// Check a heartbeat received at `now`. It is remembered as the client's
+    /// last one unless an anomaly rejects it.
+    pub fn validate(
+        &mut self,
+        heartbeat: &HeartbeatMessage,
+        payload: &[u8],
+        event_ts: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Vec<HeartbeatAnomaly> {
+        let mut anomalies = Vec::new();
+        let payload_hash = md5::compute(payload).0;
+
+        let skew = event_ts - now;
+        if skew > self.config.max_clock_skew {
+            anomalies.push(HeartbeatAnomaly::FutureTimestamp {
+                skew_secs: skew.num_seconds(),
+            });
+        }
+
+        let last = self.last.get(&heartbeat.client_id);
+        if let Some(last) = last {
+            let elapsed = event_ts - last.event_ts;
+            if elapsed < self.config.min_interval {
+                let elapsed_secs = elapsed.num_seconds();
+                anomalies.push(if last.payload_hash == payload_hash {
+                    HeartbeatAnomaly::Replay { elapsed_secs }
+                } else {
+                    HeartbeatAnomaly::TooFrequent { elapsed_secs }
+                });
+            }
+        }
+
+        let system = &heartbeat.system_info;
+        for (field, value) in [
+            ("cpu_usage", system.cpu_usage),
+            ("memory_usage", system.memory_usage),
+            ("disk_usage", system.disk_usage),
+        ] {
+            if value > 100 {
+                anomalies.push(HeartbeatAnomaly::UtilizationOutOfRange {
+                    field: field.to_string(),
+                    value: value as u64,
+                });
+            }
+        }
+        for device in &heartbeat.devices_info {
+            for (field, value) in [("usage", device.usage), ("mem_usage", device.mem_usage)] {
+                if value > 100 {
+                    anomalies.push(HeartbeatAnomaly::UtilizationOutOfRange {
+                        field: format!("device {} {}", device.num, field),
+                        value,
+                    });
+                }
+            }
+            if device.temp > MAX_DEVICE_TEMP {
+                anomalies.push(HeartbeatAnomaly::DeviceTemperatureOutOfRange {
+                    device: device.num,
+                    temp: device.temp,
+                });
+            }
+        }
+
+        // Network figures are bytes since the previous heartbeat. A worker
+        // whose counter went backwards reports a wrapped, huge delta.
+        let window_secs = last
+            .map(|last| (event_ts - last.event_ts).num_seconds())
+            .unwrap_or(0)
+            .max(self.config.min_interval.num_seconds())
+            .max(1) as u64;
+        for (field, bytes) in [
+            ("network_rx", system.network_rx),
+            ("network_tx", system.network_tx),
+        ] {
+            if bytes > i64::MAX as u64 || bytes / window_secs > MAX_NETWORK_BYTES_PER_SEC {
+                anomalies.push(HeartbeatAnomaly::ImplausibleNetwork { field, bytes });
+            }
+        }
+
+        if !anomalies.iter().any(HeartbeatAnomaly::rejects) {
+            self.last.insert(
+                heartbeat.client_id,
+                LastHeartbeat {
+                    payload_hash,
+                    event_ts,
+                },
+            );
+        }
+        anomalies
+    }
+}
+
+#[test]
+fn test_heartbeat_validator() {
+    use common::SystemInfo;
+
+    let heartbeat = |cpu_usage: u8, network_rx: u64| HeartbeatMessage {
+        client_id: ClientId([7; 16]),
+        system_info: SystemInfo {
+            cpu_usage,
+            memory_usage: 40,
+            disk_usage: 50,
+            network_rx,
+            network_tx: 1024,
+        },
+        device_memtotal_gb: 24,
+        device_count: 0,
+        total_tflops: 82,
+        devices_info: Vec::new(),
+        worker_version: None,
+    };
+    let kinds =
+        |anomalies: Vec<HeartbeatAnomaly>| anomalies.iter().map(|a| a.kind()).collect::<Vec<_>>();
+    let mut validator = HeartbeatValidator::new(HeartbeatValidationConfig::default());
+    let t0 = Utc::now();
+    let at = |secs: i64| t0 + Duration::seconds(secs);
+
+    assert!(validator
+        .validate(&heartbeat(20, 4096), b"a", t0, t0)
+        .is_empty());
+    assert_eq!(
+        kinds(validator.validate(&heartbeat(20, 4096), b"a", at(5), at(5))),
+        ["replay"]
+    );
+    assert_eq!(
+        kinds(validator.validate(&heartbeat(21, 4096), b"b", at(10), at(10))),
+        ["too_frequent"]
+    );
+    // Rejected heartbeats do not move the interval
+    assert!(validator
+        .validate(&heartbeat(21, 4096), b"b", at(60), at(60))
+        .is_empty());
+
+    let flagged = validator.validate(&heartbeat(180, u64::MAX - 10), b"c", at(120), at(120));
+    assert_eq!(
+        kinds(flagged.clone()),
+        ["utilization_out_of_range", "implausible_network"]
+    );
+    assert!(!flagged.iter().any(HeartbeatAnomaly::rejects));
+
+    assert_eq!(
+        kinds(validator.validate(&heartbeat(20, 0), b"d", at(3600), at(180))),
+        ["future_timestamp"]
+    );
+}
@@ -1,5 +1,11 @@
+pub mod dead_letter;
 pub mod heartbeat_consumer;
 pub mod heartbeat_processor;
+pub mod heartbeat_validator;
+pub mod telemetry;
+pub mod usage_processor;
+pub mod work_points;
+pub mod worker_event_processor;
 
 use anyhow::Result;
 use rdkafka::config::ClientConfig;
@@ -11,6 +17,29 @@ use std::sync::Arc;
 use tokio::sync::mpsc;
 use tracing::error;
 
+use crate::db::{usage::INFERENCE_USAGE_TOPIC, worker_events::WORKER_EVENTS_TOPIC};
+
+fn create_consumer(
+    bootstrap_servers: &str,
+    group_id: &str,
+    topic: &str,
+) -> Result<Arc<StreamConsumer>> {
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", bootstrap_servers)
+        .set("group.id", group_id)
+        .set("enable.partition.eof", "false")
+        .set("enable.auto.commit", "false")
+        .set("session.timeout.ms", "30000") // Increase session timeout
+        .set("max.poll.interval.ms", "300000") // Increase max poll interval
+        .set("fetch.max.bytes", "1048576") // Max bytes per fetch
+        .set("max.partition.fetch.bytes", "1048576") // Max bytes per partition fetch
+        .create()?;
+
+    // Subscribe to the topic
+    consumer.subscribe(&[topic])?;
+    Ok(Arc::new(consumer))
+}
+
 #[allow(dead_code)] // Consumer service management
 pub async fn start_consumer_services(
     bootstrap_servers: &str,
@@ -19,23 +48,20 @@ pub async fn start_consumer_services(
     db_pool: Pool<Postgres>,
     batch_size: usize,
     batch_timeout_secs: u64,
+    parallelism: usize,
+    validation: heartbeat_validator::HeartbeatValidationConfig,
+    dlq_topic: Option<&str>,
+    telemetry: Option<telemetry::TelemetrySink>,
 ) -> Result<()> {
-    // Create Kafka consumer with Arc for shared ownership
-    let consumer: Arc<StreamConsumer> = Arc::new(
-        ClientConfig::new()
-            .set("bootstrap.servers", bootstrap_servers)
-            .set("group.id", group_id)
-            .set("enable.partition.eof", "false")
-            .set("enable.auto.commit", "false")
-            .set("session.timeout.ms", "30000") // Increase session timeout
-            .set("max.poll.interval.ms", "300000") // Increase max poll interval
-            .set("fetch.max.bytes", "1048576") // Max bytes per fetch
-            .set("max.partition.fetch.bytes", "1048576") // Max bytes per partition fetch
-            .create()?,
-    );
+    let consumer = create_consumer(bootstrap_servers, group_id, topic)?;
 
-    // Subscribe to the topic
-    consumer.subscribe(&[topic])?;
+    let dead_letters = match dlq_topic {
+        Some(dlq_topic) => Some(dead_letter::DeadLetterQueue::new(
+            bootstrap_servers,
+            dlq_topic,
+        )?),
+        None => None,
+    };
 
     // Create channel for batching
     let (tx, rx) = mpsc::channel::<Vec<OwnedMessage>>(32);
@@ -46,6 +72,10 @@ pub async fn start_consumer_services(
         db_pool.clone(),
         batch_size,
         batch_timeout_secs,
+        parallelism,
+        validation,
+        dead_letters,
+        telemetry,
     ));
 
     // Clone the Arc for the consumer task
@@ -74,3 +104,65 @@ pub async fn start_consumer_services(
 
     Ok(())
 }
+
+/// Ingest the `inference-usage` and `worker-events` topics into their tables,
+/// each with its own consumer group and processor
+#[allow(dead_code)] // Consumer service management
+pub async fn start_event_consumer_services(
+    bootstrap_servers: &str,
+    db_pool: Pool<Postgres>,
+    batch_size: usize,
+) -> Result<()> {
+    let usage_consumer = create_consumer(
+        bootstrap_servers,
+        "inference-usage-consumer-group",
+        INFERENCE_USAGE_TOPIC,
+    )?;
+    let (usage_tx, usage_rx) = mpsc::channel::<Vec<OwnedMessage>>(32);
+    let usage_processor = tokio::spawn(usage_processor::start_processor(usage_rx, db_pool.clone()));
+    let usage_handle = tokio::spawn(heartbeat_consumer::start_consumer(
+        usage_consumer,
+        usage_tx,
+        batch_size,
+    ));
+
+    let events_consumer = create_consumer(
+        bootstrap_servers,
+        "worker-events-consumer-group",
+        WORKER_EVENTS_TOPIC,
+    )?;
+    let (events_tx, events_rx) = mpsc::channel::<Vec<OwnedMessage>>(32);
+    let events_processor =
+        tokio::spawn(worker_event_processor::start_processor(events_rx, db_pool));
+    let events_handle = tokio::spawn(heartbeat_consumer::start_consumer(
+        events_consumer,
+        events_tx,
+        batch_size,
+    ));
+
+    // Wait for any task to complete
+    tokio::select! {
+        res = usage_handle => {
+            if let Err(e) = res {
+                error!("Usage consumer task failed: {}", e);
+            }
+        }
+        res = usage_processor => {
+            if let Err(e) = res {
+                error!("Usage processor task failed: {}", e);
+            }
+        }
+        res = events_handle => {
+            if let Err(e) = res {
+                error!("Worker events consumer task failed: {}", e);
+            }
+        }
+        res = events_processor => {
+            if let Err(e) = res {
+                error!("Worker events processor task failed: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! Raw heartbeat and per-device samples written to TimescaleDB hypertables,
+//! so Postgres only needs the daily rollups. See `scripts/timescale.sql` for
+//! the schema. Connecting requires building with the `timescale` feature.
+
+use anyhow::Result;
+use common::get_u8_from_u64;
+use sqlx::{Pool, Postgres, QueryBuilder};
+
+use crate::db::stats::{device_rows, DeviceRow, HeartbeatRow};
+
+pub const TELEMETRY_HEARTBEATS_TABLE: &str = "telemetry_heartbeats";
+pub const TELEMETRY_DEVICE_SAMPLES_TABLE: &str = "telemetry_device_samples";
+
+/// Heartbeats written per multi-row statement, keeps device rows well below
+/// PostgreSQL's limit of 65535 bind parameters
+const MAX_HEARTBEATS_PER_STATEMENT: usize = 256;
+
+pub struct TelemetrySink {
+    pool: Pool<Postgres>,
+}
+
+impl TelemetrySink {
+    /// Connect to the TimescaleDB database and make its hypertables drop
+    /// chunks older than `retention_days`, 0 keeps them forever
+    #[cfg(feature = "timescale")]
+    pub async fn connect(database_url: &str, retention_days: u32) -> Result<Self> {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(4)
+            .connect(database_url)
+            .await?;
+
+        for table in [TELEMETRY_HEARTBEATS_TABLE, TELEMETRY_DEVICE_SAMPLES_TABLE] {
+            sqlx::query("SELECT remove_retention_policy($1::regclass, if_exists => TRUE)")
+                .bind(table)
+                .execute(&pool)
+                .await?;
+            if retention_days > 0 {
+                sqlx::query(
+                    "SELECT add_retention_policy($1::regclass, drop_after => make_interval(days => $2))",
+                )
+                .bind(table)
+                .bind(retention_days as i32)
+                .execute(&pool)
+                .await?;
+            }
+        }
+
+        Ok(Self { pool })
+    }
+
+    #[cfg(not(feature = "timescale"))]
+    pub async fn connect(_database_url: &str, _retention_days: u32) -> Result<Self> {
+        anyhow::bail!("telemetry sink requires building gpuf-s with the timescale feature")
+    }
+
+    /// Write the samples of stored heartbeats
+    pub async fn write(&self, rows: &[HeartbeatRow<'_>]) -> Result<()> {
+        for rows in rows.chunks(MAX_HEARTBEATS_PER_STATEMENT) {
+            self.write_chunk(rows).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_chunk(&self, rows: &[HeartbeatRow<'_>]) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut query_builder = QueryBuilder::<Postgres>::new(format!(
+            "INSERT INTO {} (time, client_id, cpu_usage, mem_usage, disk_usage, \
+             network_rx, network_tx, total_tflops, device_count) ",
+            TELEMETRY_HEARTBEATS_TABLE
+        ));
+        query_builder.push_values(rows, |mut b, row| {
+            b.push_bind(row.timestamp)
+                .push_bind(row.client_id)
+                .push_bind(row.system_info.cpu_usage as i16)
+                .push_bind(row.system_info.memory_usage as i16)
+                .push_bind(row.system_info.disk_usage as i16)
+                .push_bind(row.system_info.network_rx as i64)
+                .push_bind(row.system_info.network_tx as i64)
+                .push_bind(row.total_tflops)
+                .push_bind(row.device_count);
+        });
+        query_builder.build().execute(&self.pool).await?;
+
+        let devices: Vec<DeviceRow> = rows
+            .iter()
+            .flat_map(|row| device_rows(row.client_id, row.devices_info, row.timestamp))
+            .collect();
+        if devices.is_empty() {
+            return Ok(());
+        }
+
+        let mut query_builder = QueryBuilder::<Postgres>::new(format!(
+            "INSERT INTO {} (time, client_id, device_index, device_name, \
+             utilization, temperature, power_usage, memory_usage) ",
+            TELEMETRY_DEVICE_SAMPLES_TABLE
+        ));
+        query_builder.push_values(&devices, |mut b, row| {
+            let index = row.index as usize;
+            b.push_bind(row.timestamp)
+                .push_bind(row.client_id)
+                .push_bind(row.index as i16)
+                .push_bind(row.device_name())
+                .push_bind(get_u8_from_u64(row.device.usage, index) as i16)
+                .push_bind(get_u8_from_u64(row.device.temp, index) as i16)
+                .push_bind(get_u8_from_u64(row.device.power_usage, index) as i16)
+                .push_bind(get_u8_from_u64(row.device.mem_usage, index) as i16);
+        });
+        query_builder.build().execute(&self.pool).await?;
+        Ok(())
+    }
+}
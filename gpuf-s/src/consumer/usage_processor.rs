@@ -0,0 +1,44 @@
+use anyhow::Result;
+use rdkafka::message::{Message, OwnedMessage};
+use sqlx::{Pool, Postgres};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+use crate::db::usage::{insert_usage_records, UsageRecord};
+
+/// Write usage records published by the server to `usage_records`, one
+/// multi-row insert per batch
+pub async fn start_processor(
+    mut rx: mpsc::Receiver<Vec<OwnedMessage>>,
+    db_pool: Pool<Postgres>,
+) -> Result<()> {
+    info!("Starting usage processor");
+
+    while let Some(messages) = rx.recv().await {
+        let records: Vec<UsageRecord> = messages.iter().filter_map(decode_record).collect();
+        if let Err(e) = insert_usage_records(&db_pool, &records).await {
+            error!("Failed to write {} usage records: {}", records.len(), e);
+        } else {
+            debug!("Wrote {} usage records", records.len());
+        }
+    }
+
+    info!("Usage processor shutting down");
+    Ok(())
+}
+
+fn decode_record(message: &OwnedMessage) -> Option<UsageRecord> {
+    let payload = message.payload()?;
+    match serde_json::from_slice(payload) {
+        Ok(record) => Some(record),
+        Err(e) => {
+            warn!(
+                "Skipping usage record at offset {} of partition {}: {}",
+                message.offset(),
+                message.partition(),
+                e
+            );
+            None
+        }
+    }
+}
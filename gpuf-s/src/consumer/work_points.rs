@@ -0,0 +1,96 @@
+use anyhow::Result;
+use chrono::{Duration as ChronoDuration, NaiveDate, Utc};
+use sqlx::{Pool, Postgres};
+use std::time::Duration;
+use tracing::{error, info};
+
+/// Days recomputed on every run besides today, so usage records written
+/// late by the server's batching still count towards their day
+const ACCRUAL_LOOKBACK_DAYS: i64 = 1;
+
+/// Turn successful usage records created on or after `since` into points per
+/// client and day, with the rates of `work_points_formula`. Days are
+/// recomputed from scratch, so running twice yields the same points. Returns
+/// the number of client days written.
+pub async fn accrue_work_points(pool: &Pool<Postgres>, since: NaiveDate) -> Result<u64> {
+    // Model classes follow the payout rate cards: large above 16 GB of GPU
+    // memory, medium above 8 GB, small otherwise or when not in the catalog
+    let result = sqlx::query(
+        r#"
+        INSERT INTO "public"."client_work_points_daily" (
+            client_id,
+            date,
+            requests,
+            completion_tokens,
+            sla_met_requests,
+            points
+        )
+        SELECT
+            w.client_id,
+            w.date,
+            COUNT(*),
+            SUM(w.completion_tokens)::BIGINT,
+            COUNT(*) FILTER (WHERE w.sla_met),
+            SUM(
+                w.completion_tokens::NUMERIC / 1000 * w.points_per_1k_tokens
+                * CASE WHEN w.sla_met THEN w.sla_met_multiplier ELSE w.sla_missed_multiplier END
+            )
+        FROM (
+            SELECT
+                u.client_id,
+                (u.created_at AT TIME ZONE 'UTC')::date AS date,
+                u.completion_tokens,
+                COALESCE(f.points_per_1k_tokens, d.points_per_1k_tokens, 0) AS points_per_1k_tokens,
+                COALESCE(f.sla_met_multiplier, d.sla_met_multiplier, 1) AS sla_met_multiplier,
+                COALESCE(f.sla_missed_multiplier, d.sla_missed_multiplier, 1) AS sla_missed_multiplier,
+                u.latency_ms <= COALESCE(f.sla_latency_ms, d.sla_latency_ms, 0) AS sla_met
+            FROM "public"."usage_records" u
+            LEFT JOIN (
+                SELECT name, MAX(COALESCE(min_gpu_memory_gb, 0)) AS min_gpu_memory_gb
+                FROM "public"."client_models"
+                GROUP BY name
+            ) cm ON cm.name = u.model
+            LEFT JOIN "public"."work_points_formula" f
+                ON f.model_class = CASE
+                    WHEN COALESCE(cm.min_gpu_memory_gb, 0) > 16 THEN 'large'
+                    WHEN COALESCE(cm.min_gpu_memory_gb, 0) > 8 THEN 'medium'
+                    ELSE 'small'
+                END
+            LEFT JOIN "public"."work_points_formula" d
+                ON d.model_class = '*'
+            WHERE u.status = 'success'
+              AND u.created_at >= $1::date
+        ) w
+        GROUP BY w.client_id, w.date
+        ON CONFLICT (client_id, date) DO UPDATE SET
+            requests = EXCLUDED.requests,
+            completion_tokens = EXCLUDED.completion_tokens,
+            sla_met_requests = EXCLUDED.sla_met_requests,
+            points = EXCLUDED.points,
+            updated_at = NOW()
+        "#,
+    )
+    .bind(since)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Accrue work points every `interval`, recomputing today and the lookback days
+pub async fn start_work_points_accrual(pool: Pool<Postgres>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+
+        let since = Utc::now().date_naive() - ChronoDuration::days(ACCRUAL_LOOKBACK_DAYS);
+        match accrue_work_points(&pool, since).await {
+            Ok(n) => {
+                info!("Accrued work points for {} client days since {}", n, since);
+            }
+            Err(e) => {
+                error!("Failed to accrue work points: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use anyhow::Result;
+use rdkafka::message::{Message, OwnedMessage};
+use sqlx::{Pool, Postgres};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+use crate::db::worker_events::{insert_worker_events, WorkerEvent};
+
+/// Write worker events published by the server to `worker_events`, one
+/// multi-row insert per batch
+pub async fn start_processor(
+    mut rx: mpsc::Receiver<Vec<OwnedMessage>>,
+    db_pool: Pool<Postgres>,
+) -> Result<()> {
+    info!("Starting worker event processor");
+
+    while let Some(messages) = rx.recv().await {
+        let events: Vec<WorkerEvent> = messages.iter().filter_map(decode_event).collect();
+        if let Err(e) = insert_worker_events(&db_pool, &events).await {
+            error!("Failed to write {} worker events: {}", events.len(), e);
+        } else {
+            debug!("Wrote {} worker events", events.len());
+        }
+    }
+
+    info!("Worker event processor shutting down");
+    Ok(())
+}
+
+fn decode_event(message: &OwnedMessage) -> Option<WorkerEvent> {
+    let payload = message.payload()?;
+    match serde_json::from_slice(payload) {
+        Ok(event) => Some(event),
+        Err(e) => {
+            warn!(
+                "Skipping worker event at offset {} of partition {}: {}",
+                message.offset(),
+                message.partition(),
+                e
+            );
+            None
+        }
+    }
+}
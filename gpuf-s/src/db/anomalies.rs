@@ -0,0 +1,84 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres, QueryBuilder};
+use std::collections::HashSet;
+
+use crate::db::{GPU_ASSETS_TABLE, HEARTBEAT_ANOMALIES_TABLE};
+use crate::util::protoc::ClientId;
+
+/// One implausible value of a heartbeat
+pub struct AnomalyRecord<'a> {
+    pub kind: &'a str,
+    pub detail: String,
+    /// The heartbeat was dropped instead of stored
+    pub rejected: bool,
+}
+
+pub async fn insert_heartbeat_anomalies(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+    event_ts: DateTime<Utc>,
+    anomalies: &[AnomalyRecord<'_>],
+) -> Result<()> {
+    if anomalies.is_empty() {
+        return Ok(());
+    }
+
+    let mut query_builder = QueryBuilder::<Postgres>::new("INSERT INTO ");
+    query_builder
+        .push(HEARTBEAT_ANOMALIES_TABLE)
+        .push(" (client_id, kind, detail, rejected, heartbeat_at) ");
+    query_builder.push_values(anomalies, |mut b, a| {
+        b.push_bind(client_id)
+            .push_bind(a.kind)
+            .push_bind(&a.detail)
+            .push_bind(a.rejected)
+            .push_bind(event_ts);
+    });
+    query_builder.build().execute(pool).await?;
+    Ok(())
+}
+
+/// Anomalies recorded for a client over the last day
+pub async fn count_recent_anomalies(pool: &Pool<Postgres>, client_id: &ClientId) -> Result<i64> {
+    let count = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM {} WHERE client_id = $1 AND created_at > NOW() - INTERVAL '1 day'",
+        HEARTBEAT_ANOMALIES_TABLE
+    ))
+    .bind(client_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
+/// Stop counting a client's heartbeats until an operator clears `quarantined_at`.
+/// Returns false if the client was already quarantined or is unknown.
+pub async fn quarantine_client(pool: &Pool<Postgres>, client_id: &ClientId) -> Result<bool> {
+    let result = sqlx::query(&format!(
+        "UPDATE {} SET quarantined_at = NOW(), updated_at = NOW() \
+         WHERE client_id = $1 AND quarantined_at IS NULL",
+        GPU_ASSETS_TABLE
+    ))
+    .bind(client_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// The quarantined ones of `client_ids`
+pub async fn quarantined_clients(
+    pool: &Pool<Postgres>,
+    client_ids: &[ClientId],
+) -> Result<HashSet<ClientId>> {
+    let rows: Vec<Vec<u8>> = sqlx::query_scalar(&format!(
+        "SELECT client_id FROM {} WHERE client_id = ANY($1) AND quarantined_at IS NOT NULL",
+        GPU_ASSETS_TABLE
+    ))
+    .bind(client_ids)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|bytes| bytes.try_into().ok().map(ClientId))
+        .collect())
+}
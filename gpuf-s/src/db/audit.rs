@@ -0,0 +1,147 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{FromRow, Pool, Postgres, QueryBuilder};
+
+use crate::db::AUDIT_LOG_TABLE;
+use crate::util::msg::{FilterOp, ListField, ListQuery, ListSpec, Page};
+
+pub struct NewAuditEntry<'a> {
+    pub actor: &'a str,
+    pub method: &'a str,
+    pub route: &'a str,
+    pub path: &'a str,
+    pub status: u16,
+    pub request: Option<&'a Value>,
+    pub before: Option<&'a Value>,
+    pub after: Option<&'a Value>,
+    pub ip: Option<&'a str>,
+}
+
+pub async fn insert_entry(
+    pool: &Pool<Postgres>,
+    entry: &NewAuditEntry<'_>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "INSERT INTO {} (actor, method, route, path, status, request, before, after, ip) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        AUDIT_LOG_TABLE
+    ))
+    .bind(entry.actor)
+    .bind(entry.method)
+    .bind(entry.route)
+    .bind(entry.path)
+    .bind(entry.status as i16)
+    .bind(entry.request)
+    .bind(entry.before)
+    .bind(entry.after)
+    .bind(entry.ip)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor: String,
+    pub method: String,
+    pub route: String,
+    pub path: String,
+    pub status: i16,
+    pub request: Option<Value>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Which entries the listing covers, all optional
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+}
+
+impl AuditFilter {
+    fn push_scope(&self, query_builder: &mut QueryBuilder<'_, Postgres>) {
+        query_builder.push(AUDIT_LOG_TABLE).push(" a WHERE TRUE");
+        if let Some(start_date) = self.start_date {
+            query_builder
+                .push(" AND a.created_at >= ")
+                .push_bind(start_date)
+                .push("::date");
+        }
+        if let Some(end_date) = self.end_date {
+            query_builder
+                .push(" AND a.created_at < ")
+                .push_bind(end_date)
+                .push("::date + 1");
+        }
+    }
+}
+
+/// Sort and filter fields of the audit log listing
+pub static AUDIT_LIST: ListSpec = ListSpec {
+    fields: &[
+        ListField {
+            name: "created_at",
+            expr: "l.created_at",
+            sql_type: "timestamptz",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "actor",
+            expr: "l.actor",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "method",
+            expr: "l.method",
+            sql_type: "text",
+            sortable: false,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "route",
+            expr: "l.route",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "path",
+            expr: "l.path",
+            sql_type: "text",
+            sortable: false,
+            filter: Some(FilterOp::Contains),
+        },
+        ListField {
+            name: "ip",
+            expr: "COALESCE(l.ip, '')",
+            sql_type: "text",
+            sortable: false,
+            filter: Some(FilterOp::Eq),
+        },
+    ],
+    default_sort: "-created_at",
+    key: "lpad(l.id::text, 20, '0')",
+};
+
+pub async fn list_entries(
+    pool: &Pool<Postgres>,
+    filter: &AuditFilter,
+    list: &ListQuery,
+) -> Result<Page<AuditEntry>, sqlx::Error> {
+    list.fetch(pool, |query_builder| {
+        query_builder.push(
+            "SELECT a.id, a.actor, a.method, a.route, a.path, a.status, a.request, a.before, \
+             a.after, a.ip, a.created_at FROM ",
+        );
+        filter.push_scope(query_builder);
+    })
+    .await
+}
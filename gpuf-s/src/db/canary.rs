@@ -0,0 +1,72 @@
+use anyhow::Result;
+use sqlx::{Pool, Postgres};
+
+use crate::db::{CANARY_RESULTS_TABLE, GPU_ASSETS_TABLE};
+use crate::util::protoc::ClientId;
+
+/// Quality score of a worker that has not failed a canary
+pub const MAX_QUALITY_SCORE: f32 = 1.0;
+/// Longest part of a worker's answer kept with a canary result
+const MAX_STORED_OUTPUT_CHARS: usize = 512;
+
+/// Outcome of one canary prompt run on a worker
+pub struct CanaryResult<'a> {
+    pub prompt: &'a str,
+    pub expected: &'a str,
+    pub verdict: &'a str,
+    pub output: Option<&'a str>,
+    pub latency_ms: i64,
+}
+
+/// Store a canary result and the worker's quality score after it
+pub async fn record_canary_result(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+    result: &CanaryResult<'_>,
+    quality_score: f32,
+) -> Result<()> {
+    let output = result.output.map(|output| {
+        output
+            .chars()
+            .take(MAX_STORED_OUTPUT_CHARS)
+            .collect::<String>()
+    });
+
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!(
+        "INSERT INTO {} (client_id, prompt, expected, verdict, output, latency_ms) \
+         VALUES ($1, $2, $3, $4, $5, $6)",
+        CANARY_RESULTS_TABLE
+    ))
+    .bind(client_id)
+    .bind(result.prompt)
+    .bind(result.expected)
+    .bind(result.verdict)
+    .bind(output)
+    .bind(result.latency_ms)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(&format!(
+        "UPDATE {} SET quality_score = $2, updated_at = NOW() WHERE client_id = $1",
+        GPU_ASSETS_TABLE
+    ))
+    .bind(client_id)
+    .bind(quality_score)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn get_quality_score(pool: &Pool<Postgres>, client_id: &ClientId) -> Result<Option<f32>> {
+    let score = sqlx::query_scalar(&format!(
+        "SELECT quality_score FROM {} WHERE client_id = $1",
+        GPU_ASSETS_TABLE
+    ))
+    .bind(client_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(score)
+}
@@ -0,0 +1,55 @@
+use anyhow::Result;
+use sqlx::{Pool, Postgres};
+
+use crate::db::{CLIENT_CHALLENGE_DAILY_TABLE, GPU_ASSETS_TABLE};
+use crate::util::protoc::ClientId;
+
+/// Trust gained per passed challenge
+const TRUST_PASS_REWARD: i16 = 2;
+/// Trust lost per failed challenge, so one failure outweighs several passes
+const TRUST_FAIL_PENALTY: i16 = 20;
+const MAX_TRUST_SCORE: i16 = 100;
+
+/// Count a challenge towards today's points eligibility and move the
+/// client's trust score. Returns the new trust score, None for unknown clients.
+pub async fn record_challenge_result(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+    passed: bool,
+) -> Result<Option<i16>> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(&format!(
+        "INSERT INTO {table} (client_id, date, passed, failed) \
+         VALUES ($1, (NOW() AT TIME ZONE 'UTC')::date, $2, $3) \
+         ON CONFLICT (client_id, date) DO UPDATE SET \
+         passed = {table}.passed + EXCLUDED.passed, \
+         failed = {table}.failed + EXCLUDED.failed, \
+         updated_at = NOW()",
+        table = CLIENT_CHALLENGE_DAILY_TABLE
+    ))
+    .bind(client_id)
+    .bind(passed as i32)
+    .bind(!passed as i32)
+    .execute(&mut *tx)
+    .await?;
+
+    let delta = if passed {
+        TRUST_PASS_REWARD
+    } else {
+        -TRUST_FAIL_PENALTY
+    };
+    let trust_score: Option<i16> = sqlx::query_scalar(&format!(
+        "UPDATE {} SET trust_score = LEAST(GREATEST(trust_score + $2, 0), $3), \
+         updated_at = NOW() WHERE client_id = $1 RETURNING trust_score",
+        GPU_ASSETS_TABLE
+    ))
+    .bind(client_id)
+    .bind(delta)
+    .bind(MAX_TRUST_SCORE)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(trust_score)
+}
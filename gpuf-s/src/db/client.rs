@@ -1,8 +1,10 @@
+use crate::util::geo::{KeyRegion, Location};
+use crate::util::msg::{FilterOp, ListField, ListQuery, ListSpec, Page};
+use crate::util::policy::{AccessLevel, RateLimitPolicy};
 use crate::util::protoc::ClientId;
-use crate::util::policy::AccessLevel;
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
-use common::Model;
+use common::{EngineVersion, Model, ModelBenchmark, ModelBenchmarkRun};
 use redis::{AsyncCommands, Client as RedisClient, Commands};
 use sqlx::{postgres::Postgres, FromRow, Pool};
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -16,17 +18,55 @@ struct ClientRecord {
 #[derive(FromRow)]
 struct TokenInfo {
     user_id: String,
+    org_id: Option<i64>,
     access_level: i32,
+    region: Option<String>,
+    region_pinned: bool,
 }
 
+pub async fn get_token_rate_limit(pool: &Pool<Postgres>, token: &str) -> Result<RateLimitPolicy> {
+    let policy = sqlx::query_as::<_, RateLimitPolicy>(
+        r#"
+        SELECT rate_limit_rps, tokens_per_minute, max_concurrent
+        FROM tokens
+        WHERE key = $1::varchar(48) AND deleted_at IS NULL
+        "#,
+    )
+    .bind(token)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(policy.unwrap_or_default())
+}
+
+/// Whether the key opted in to the response cache
+pub async fn get_token_cache_opt_in(pool: &Pool<Postgres>, token: &str) -> Result<bool> {
+    let enabled: Option<bool> = sqlx::query_scalar(
+        r#"
+        SELECT cache_responses
+        FROM tokens
+        WHERE key = $1::varchar(48) AND deleted_at IS NULL
+        "#,
+    )
+    .bind(token)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(enabled.unwrap_or(false))
+}
+
+/// Online clients the key may use, its access level and region setting.
+/// Keys of an organization get its clients, other keys those of their user,
+/// unless they may use all devices. Keys pinned to a region only get clients
+/// in it.
 pub async fn get_user_client_by_token(
     pool: &Pool<Postgres>,
     token: &str,
-) -> Result<(Vec<ClientId>, AccessLevel)> {
+) -> Result<(Vec<ClientId>, AccessLevel, KeyRegion)> {
     // First, get the token details including user_id and access_level
     let token_info = match sqlx::query_as::<_, TokenInfo>(
         r#"
-        SELECT user_id::text as user_id, access_level 
+        SELECT user_id::text as user_id, org_id, access_level, region, region_pinned
         FROM tokens 
         WHERE key = $1::varchar(48)
           AND status = 1
@@ -43,23 +83,38 @@ pub async fn get_user_client_by_token(
     };
 
     let access_level = AccessLevel::from(token_info.access_level);
+    let key_region = KeyRegion {
+        region: token_info.region,
+        pinned: token_info.region_pinned,
+    };
+    let pinned_region = key_region.region.as_deref().filter(|_| key_region.pinned);
 
     // Then query devices based on access level
     let query = if access_level.is_metered() {
         // Access to all devices
         "SELECT client_id FROM gpu_assets 
-         WHERE client_status = 'online' AND valid_status = 'valid'"
+         WHERE client_status = 'online' AND valid_status = 'valid'
+           AND ($1::varchar IS NULL OR region = $1 OR country = $1)"
+    } else if token_info.org_id.is_some() {
+        // Access only to the organization's devices
+        "SELECT client_id FROM gpu_assets 
+         WHERE org_id = $2 AND client_status = 'online' AND valid_status = 'valid'
+           AND ($1::varchar IS NULL OR region = $1 OR country = $1)"
     } else {
         // Access only to user's devices
         "SELECT client_id FROM gpu_assets 
-         WHERE user_id = $1 AND client_status = 'online' AND valid_status = 'valid'"
+         WHERE user_id = $2 AND client_status = 'online' AND valid_status = 'valid'
+           AND ($1::varchar IS NULL OR region = $1 OR country = $1)"
     };
 
-    let mut query = sqlx::query_as::<_, ClientRecord>(query);
+    let mut query = sqlx::query_as::<_, ClientRecord>(query).bind(pinned_region);
 
-    // Only bind user_id parameter if access_level is not -1
+    // Only bind the owner parameter if access_level is not -1
     if !access_level.is_metered() {
-        query = query.bind(&token_info.user_id);
+        query = match token_info.org_id {
+            Some(org_id) => query.bind(org_id),
+            None => query.bind(&token_info.user_id),
+        };
     }
 
     let rows = query.fetch_all(pool).await?;
@@ -80,10 +135,60 @@ pub async fn get_user_client_by_token(
         .collect::<Result<Vec<ClientId>>>()?;
 
     if client_ids.is_empty() {
-        return Ok((vec![], access_level));
+        return Ok((vec![], access_level, key_region));
     }
 
-    Ok((client_ids, access_level))
+    Ok((client_ids, access_level, key_region))
+}
+
+/// Store where a client was at login
+pub async fn set_client_location(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+    location: &Location,
+) -> Result<()> {
+    sqlx::query(
+        r#"UPDATE "public"."gpu_assets" SET region = $2, country = $3 WHERE client_id = $1"#,
+    )
+    .bind(client_id)
+    .bind(&location.region)
+    .bind(&location.country)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Set the region setting of an API key by its id. Returns false when no
+/// such key exists.
+pub async fn set_key_region(
+    pool: &Pool<Postgres>,
+    key_id: i64,
+    key_region: &KeyRegion,
+) -> Result<bool> {
+    let result = sqlx::query(
+        r#"UPDATE "public"."tokens" SET region = $2, region_pinned = $3
+        WHERE id = $1 AND deleted_at IS NULL"#,
+    )
+    .bind(key_id)
+    .bind(&key_region.region)
+    .bind(key_region.pinned)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Opt an API key in or out of the response cache by its id. Returns false
+/// when no such key exists.
+pub async fn set_key_cache(pool: &Pool<Postgres>, key_id: i64, enabled: bool) -> Result<bool> {
+    let result = sqlx::query(
+        r#"UPDATE "public"."tokens" SET cache_responses = $2
+        WHERE id = $1 AND deleted_at IS NULL"#,
+    )
+    .bind(key_id)
+    .bind(enabled)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
 }
 
 pub async fn update_client_db(
@@ -186,17 +291,71 @@ pub async fn get_loaded_models_batch_from_redis(
 
     Ok(out)
 }
-#[allow(dead_code)]
-pub async fn get_user_client_status_list(
-    pool: &Pool<Postgres>,
-    user_id: &str,
-    client_id: Option<&String>,
-    status: Option<&String>,
-    name: Option<&String>,
-    valid_status: Option<&String>,
-) -> Result<Vec<ClientDeviceInfo>> {
-    // Build the base query
-    let mut query = r#"
+/// Sort and filter fields of client listings
+pub static CLIENT_LIST: ListSpec = ListSpec {
+    fields: &[
+        ListField {
+            name: "name",
+            expr: "l.client_name",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Contains),
+        },
+        ListField {
+            name: "status",
+            expr: "l.client_status",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "os_type",
+            expr: "COALESCE(l.os_type, '')",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "device_name",
+            expr: "COALESCE(l.device_name, '')",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Contains),
+        },
+        ListField {
+            name: "tflops",
+            expr: "COALESCE(l.total_tflops, 0)",
+            sql_type: "integer",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "health",
+            expr: "l.health_rate",
+            sql_type: "double precision",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "created_at",
+            expr: "l.created_at",
+            sql_type: "timestamptz",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "last_online",
+            expr: "l.last_online",
+            sql_type: "timestamptz",
+            sortable: true,
+            filter: None,
+        },
+    ],
+    default_sort: "name",
+    key: "encode(l.client_id, 'hex')",
+};
+
+const CLIENT_STATUS_QUERY: &str = r#"
     SELECT 
         ga.client_id as client_id,
         ga.client_name as client_name,
@@ -232,97 +391,68 @@ pub async fn get_user_client_status_list(
             ROW_NUMBER() OVER (PARTITION BY client_id ORDER BY created_at DESC) as rn
         FROM system_info
     ) si ON ga.client_id = si.client_id AND si.rn = 1
-    WHERE ga.user_id = $1 AND ga.valid_status = 'valid'
-    "#
-    .to_string();
-
-    // 2. Collect all parameters in a vector
-    let mut param_count = 1;
-    let mut query_builder = sqlx::query_as::<_, ClientStatusRow>(&query).bind(user_id);
-
-    // 3. Add optional conditions
-    if let Some(client_id) = client_id {
-        param_count += 1;
-        query.push_str(&format!(" AND ga.client_id = ${}", param_count));
-        // Create a new query builder with the updated query
-        query_builder = sqlx::query_as::<_, ClientStatusRow>(&query)
-            .bind(user_id)
-            .bind(client_id.parse::<ClientId>()?);
-    }
-    if let Some(status) = status {
-        param_count += 1;
-        query.push_str(&format!(" AND ga.client_status = ${}", param_count));
-        query_builder = sqlx::query_as::<_, ClientStatusRow>(&query).bind(user_id);
-        if let Some(cid) = client_id {
-            query_builder = query_builder.bind(cid.parse::<ClientId>()?);
-        }
-        query_builder = query_builder.bind(status);
-    }
-    if let Some(valid_status) = valid_status {
-        param_count += 1;
-        query.push_str(&format!(" AND ga.valid_status = ${}", param_count));
-
-        query_builder = sqlx::query_as::<_, ClientStatusRow>(&query).bind(user_id);
-        if let Some(cid) = client_id {
-            query_builder = query_builder.bind(cid.parse::<ClientId>()?);
-        }
-        if let Some(s) = status {
-            query_builder = query_builder.bind(s);
-        }
-        query_builder = query_builder.bind(valid_status);
-    }
-    if let Some(name) = name {
-        param_count += 1;
-        query.push_str(&format!(" AND ga.client_name ILIKE ${}", param_count));
-        let mut temp_builder = sqlx::query_as::<_, ClientStatusRow>(&query).bind(user_id);
-
-        if let Some(cid) = client_id {
-            temp_builder = temp_builder.bind(cid.parse::<ClientId>()?);
-        }
-        if let Some(s) = status {
-            temp_builder = temp_builder.bind(s);
-        }
-        if let Some(vs) = valid_status {
-            temp_builder = temp_builder.bind(vs);
-        }
-        query_builder = temp_builder.bind(format!("%{}%", name));
-    }
-    // 4. Execute the query
-    let mut conn = pool
-        .acquire()
-        .await
-        .map_err(|_| anyhow!("Failed to acquire database connection"))?;
+    WHERE "#;
 
-    // get online info in DB
-    let devices = query_builder
-        .fetch_all(&mut *conn)
-        .await
-        .map_err(|e| anyhow!("Failed to fetch user client list: {}", e))?;
+/// Clients of the user, or of the organization when `org_id` is set
+pub async fn get_user_client_status_list(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+    org_id: Option<i64>,
+    client_id: Option<&String>,
+    status: Option<&String>,
+    name: Option<&String>,
+    valid_status: Option<&String>,
+    list: &ListQuery,
+) -> Result<Page<ClientDeviceInfo>> {
+    let client_id = client_id.map(|id| id.parse::<ClientId>()).transpose()?;
 
-    let devices: Vec<ClientDeviceInfo> = devices
-        .into_iter()
-        .map(|row: ClientStatusRow| {
-            let client_id = ClientId(row.client_id);
-            ClientDeviceInfo {
-                client_id: client_id.to_string(),
-                client_name: row.client_name,
-                os_type: row.os_type.unwrap_or("".to_string()),
-                client_status: row.client_status,
-                health: row.health_rate as u8,
-                cpu_usage: row.cpu_usage.unwrap_or(0) as u8,
-                memory_usage: row.memory_usage.unwrap_or(0) as u8,
-                storage_usage: row.storage_usage.unwrap_or(0) as u8,
-                device_name: row.device_name.unwrap_or("".to_string()),
-                tflops: row.total_tflops.unwrap_or(0) as u16,
-                last_online: row.last_online,
-                created_at: row.created_at,
-                uptime_days: row.uptime_days.unwrap_or(0) as u32,
-                loaded_models: vec![],
+    let page = list
+        .fetch::<ClientStatusRow>(pool, |query| {
+            query.push(CLIENT_STATUS_QUERY);
+            match org_id {
+                Some(org_id) => query.push("ga.org_id = ").push_bind(org_id),
+                None => query.push("ga.user_id = ").push_bind(user_id),
+            };
+            query.push(" AND ga.valid_status = 'valid'");
+            if let Some(client_id) = client_id {
+                query.push(" AND ga.client_id = ").push_bind(client_id);
+            }
+            if let Some(status) = status {
+                query.push(" AND ga.client_status = ").push_bind(status);
+            }
+            if let Some(valid_status) = valid_status {
+                query
+                    .push(" AND ga.valid_status = ")
+                    .push_bind(valid_status);
+            }
+            if let Some(name) = name {
+                query
+                    .push(" AND ga.client_name ILIKE ")
+                    .push_bind(format!("%{}%", name));
             }
         })
-        .collect();
+        .await
+        .map_err(|e| anyhow!("Failed to fetch user client list: {}", e))?;
 
-    Ok(devices)
+    Ok(page.map(|row: ClientStatusRow| {
+        let client_id = ClientId(row.client_id);
+        ClientDeviceInfo {
+            client_id: client_id.to_string(),
+            client_name: row.client_name,
+            os_type: row.os_type.unwrap_or("".to_string()),
+            client_status: row.client_status,
+            health: row.health_rate as u8,
+            cpu_usage: row.cpu_usage.unwrap_or(0) as u8,
+            memory_usage: row.memory_usage.unwrap_or(0) as u8,
+            storage_usage: row.storage_usage.unwrap_or(0) as u8,
+            device_name: row.device_name.unwrap_or("".to_string()),
+            tflops: row.total_tflops.unwrap_or(0) as u16,
+            last_online: row.last_online,
+            created_at: row.created_at,
+            uptime_days: row.uptime_days.unwrap_or(0) as u32,
+            loaded_models: vec![],
+        }
+    }))
 }
 
 #[derive(serde::Serialize)]
@@ -559,7 +689,7 @@ pub async fn upsert_client_status(
     client_id: &ClientId,
     status: &str,
 ) -> Result<()> {
-    sqlx::query("UPDATE \"public\".\"gpu_assets\" SET client_status = $1, \"updated_at\" = NOW() WHERE \"client_id\" = $2")
+    sqlx::query("UPDATE \"public\".\"gpu_assets\" SET client_status = CASE WHEN maintenance THEN 'maintenance' ELSE $1 END, \"updated_at\" = NOW() WHERE \"client_id\" = $2")
     .bind(status)
     .bind(client_id)
     .execute(pool)
@@ -567,6 +697,364 @@ pub async fn upsert_client_status(
     Ok(())
 }
 
+/// Operator overrides stored on a client
+#[derive(Debug, Default, FromRow)]
+pub struct ClientAdminState {
+    pub maintenance: bool,
+    pub pinned_model: Option<String>,
+    pub pinned_version: Option<String>,
+}
+
+pub async fn get_client_admin_state(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+) -> Result<ClientAdminState> {
+    let state = sqlx::query_as::<_, ClientAdminState>(
+        r#"
+        SELECT
+            COALESCE(maintenance, false) AS maintenance,
+            CASE WHEN outo_set_model = false THEN model END AS pinned_model,
+            CASE WHEN outo_set_model = false THEN model_version END AS pinned_version
+        FROM "public"."gpu_assets"
+        WHERE client_id = $1
+        "#,
+    )
+    .bind(client_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(state.unwrap_or_default())
+}
+
+/// Whether an operator let a worker in. New workers start pending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApprovalStatus {
+    Pending,
+    Approved,
+    Rejected,
+    Banned,
+}
+
+impl ApprovalStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Rejected => "rejected",
+            Self::Banned => "banned",
+        }
+    }
+
+    fn parse(status: &str) -> Result<Self> {
+        match status {
+            "pending" => Ok(Self::Pending),
+            "approved" => Ok(Self::Approved),
+            "rejected" => Ok(Self::Rejected),
+            "banned" => Ok(Self::Banned),
+            other => Err(anyhow!("Unknown approval status {}", other)),
+        }
+    }
+
+    /// Decisions an operator can take from this status. Nothing goes back
+    /// to pending, and an approved worker is banned rather than rejected.
+    pub fn can_become(&self, next: Self) -> bool {
+        match (self, next) {
+            (_, Self::Pending) => false,
+            (Self::Approved, Self::Rejected) => false,
+            (current, next) => *current != next,
+        }
+    }
+}
+
+/// Approval of a client, None if the client is unknown
+pub async fn get_client_approval(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+) -> Result<Option<ApprovalStatus>> {
+    let status: Option<String> = sqlx::query_scalar(
+        r#"
+        SELECT COALESCE(approval_status, 'approved')
+        FROM "public"."gpu_assets"
+        WHERE client_id = $1
+        "#,
+    )
+    .bind(client_id)
+    .fetch_optional(pool)
+    .await?;
+
+    status.as_deref().map(ApprovalStatus::parse).transpose()
+}
+
+/// Set the approval of a client. Returns false if the client is unknown.
+pub async fn set_client_approval(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+    status: ApprovalStatus,
+) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE "public"."gpu_assets"
+        SET approval_status = $1,
+            "updated_at" = NOW()
+        WHERE client_id = $2
+        "#,
+    )
+    .bind(status.as_str())
+    .bind(client_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Worker waiting for an operator
+#[derive(Debug, serde::Serialize, FromRow)]
+pub struct PendingClient {
+    /// Hex encoded
+    pub client_id: String,
+    pub user_id: Option<String>,
+    pub client_name: Option<String>,
+    pub os_type: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Workers waiting for approval, oldest first
+pub async fn list_pending_clients(pool: &Pool<Postgres>) -> Result<Vec<PendingClient>> {
+    let clients = sqlx::query_as::<_, PendingClient>(
+        r#"
+        SELECT
+            encode(client_id, 'hex') AS client_id,
+            user_id,
+            client_name,
+            os_type,
+            created_at::TIMESTAMPTZ AS created_at
+        FROM "public"."gpu_assets"
+        WHERE approval_status = 'pending' AND valid_status = 'valid'
+        ORDER BY created_at
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(clients)
+}
+
+/// Versions a worker last reported in its heartbeats
+#[derive(Debug, serde::Serialize, FromRow)]
+pub struct ClientVersion {
+    /// Hex encoded
+    pub client_id: String,
+    pub client_name: Option<String>,
+    pub client_status: Option<String>,
+    /// None until the worker reports its versions
+    pub worker_version: Option<String>,
+    pub engine_versions: Option<sqlx::types::Json<Vec<EngineVersion>>>,
+    pub build_features: Option<Vec<String>>,
+    pub os_version: Option<String>,
+    pub version_reported_at: Option<DateTime<Utc>>,
+}
+
+/// Versions of every registered worker, by client id
+pub async fn list_client_versions(pool: &Pool<Postgres>) -> Result<Vec<ClientVersion>> {
+    let clients = sqlx::query_as::<_, ClientVersion>(
+        r#"
+        SELECT
+            encode(client_id, 'hex') AS client_id,
+            client_name,
+            client_status,
+            worker_version,
+            engine_versions,
+            build_features,
+            os_version,
+            version_reported_at
+        FROM "public"."gpu_assets"
+        WHERE valid_status = 'valid'
+        ORDER BY client_id
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(clients)
+}
+
+/// Latest `gpuf-c bench` results of a worker for one model
+#[derive(Debug, serde::Serialize, FromRow)]
+pub struct ClientModelBenchmark {
+    pub model: String,
+    pub model_bytes: i64,
+    pub n_gpu_layers: i32,
+    pub runs: sqlx::types::Json<Vec<ModelBenchmarkRun>>,
+    pub reported_at: DateTime<Utc>,
+}
+
+/// Keep `benchmark` as the latest of the client for its model
+pub async fn upsert_model_benchmark(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+    benchmark: &ModelBenchmark,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO "public"."client_model_benchmarks"
+            (client_id, model, model_bytes, n_gpu_layers, runs, reported_at)
+        VALUES ($1, $2, $3, $4, $5, NOW())
+        ON CONFLICT (client_id, model) DO UPDATE
+        SET model_bytes = EXCLUDED.model_bytes,
+            n_gpu_layers = EXCLUDED.n_gpu_layers,
+            runs = EXCLUDED.runs,
+            reported_at = EXCLUDED.reported_at
+        "#,
+    )
+    .bind(client_id)
+    .bind(&benchmark.model)
+    .bind(benchmark.model_bytes as i64)
+    .bind(benchmark.n_gpu_layers as i32)
+    .bind(sqlx::types::Json(&benchmark.runs))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Benchmarks the client uploaded, latest first
+pub async fn list_model_benchmarks(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+) -> Result<Vec<ClientModelBenchmark>> {
+    let benchmarks = sqlx::query_as::<_, ClientModelBenchmark>(
+        r#"
+        SELECT model, model_bytes, n_gpu_layers, runs, reported_at
+        FROM "public"."client_model_benchmarks"
+        WHERE client_id = $1
+        ORDER BY reported_at DESC
+        "#,
+    )
+    .bind(client_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(benchmarks)
+}
+
+/// Put a client in or out of maintenance. Returns false if the client is unknown.
+pub async fn set_client_maintenance(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+    maintenance: bool,
+    online: bool,
+) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE "public"."gpu_assets"
+        SET maintenance = $1,
+            client_status = CASE WHEN $1 THEN 'maintenance' WHEN $2 THEN 'online' ELSE 'offline' END,
+            "updated_at" = NOW()
+        WHERE client_id = $3
+        "#,
+    )
+    .bind(maintenance)
+    .bind(online)
+    .bind(client_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Model pinned to a client, None for automatic models
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ClientPin {
+    pub client_id: ClientId,
+    pub model: Option<String>,
+    pub version: Option<String>,
+}
+
+/// Pin a model version to a client, or clear the pin with `None` so the
+/// client goes back to automatic model selection. Returns the pin it
+/// replaced, None when the client is unknown.
+pub async fn set_client_pinned_model(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+    pinned: Option<(&str, &str)>,
+) -> Result<Option<ClientPin>> {
+    let previous = set_clients_pinned_model(pool, &[*client_id], None, pinned).await?;
+    Ok(previous.into_iter().next())
+}
+
+/// Pin `pinned`, or restore automatic models when None, on every client of
+/// `client_ids` that belongs to `owner` or to an organization `owner` is an
+/// admin or owner of (any client when None). Returns the pins the updated
+/// clients had before.
+pub async fn set_clients_pinned_model(
+    pool: &Pool<Postgres>,
+    client_ids: &[ClientId],
+    owner: Option<&str>,
+    pinned: Option<(&str, &str)>,
+) -> Result<Vec<ClientPin>> {
+    let (model, version) = pinned.unzip();
+    let ids: Vec<Vec<u8>> = client_ids.iter().map(|id| id.0.to_vec()).collect();
+    let previous: Vec<(Vec<u8>, Option<String>, Option<String>)> = sqlx::query_as(
+        r#"
+        UPDATE "public"."gpu_assets" ga
+        SET outo_set_model = $1::varchar IS NULL,
+            model = $1,
+            model_version = $2,
+            model_version_code = (SELECT version_code FROM client_models WHERE name = $1 AND version = $2),
+            "updated_at" = NOW()
+        FROM "public"."gpu_assets" old
+        WHERE old.client_id = ga.client_id
+          AND ga.client_id = ANY($3)
+          AND ($4::varchar IS NULL OR ga.user_id = $4 OR ga.org_id IN (
+              SELECT org_id FROM "public"."organization_members"
+              WHERE user_id = $4 AND role IN ('admin', 'owner')))
+        RETURNING ga.client_id,
+            CASE WHEN old.outo_set_model = false THEN old.model END,
+            CASE WHEN old.outo_set_model = false THEN old.model_version END
+        "#,
+    )
+    .bind(model)
+    .bind(version)
+    .bind(&ids)
+    .bind(owner)
+    .fetch_all(pool)
+    .await?;
+
+    previous
+        .into_iter()
+        .map(|(id, model, version)| {
+            let client_id = id
+                .try_into()
+                .map(ClientId)
+                .map_err(|_| anyhow!("Invalid client_id length"))?;
+            Ok(ClientPin {
+                client_id,
+                model,
+                version,
+            })
+        })
+        .collect()
+}
+
+/// Ids of the valid clients of a user
+pub async fn get_user_client_ids(pool: &Pool<Postgres>, user_id: &str) -> Result<Vec<ClientId>> {
+    let ids: Vec<Vec<u8>> = sqlx::query_scalar(
+        r#"SELECT client_id FROM "public"."gpu_assets" WHERE user_id = $1 AND valid_status = 'valid'"#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    ids.into_iter()
+        .map(|id| {
+            id.try_into()
+                .map(ClientId)
+                .map_err(|_| anyhow!("Invalid client_id length"))
+        })
+        .collect()
+}
+
 // redis
 #[allow(dead_code)] // Redis utility function for heartbeat info
 pub async fn upsert_heartbeat_info_in_redis<F, Fut>(
@@ -676,3 +1164,26 @@ pub async fn validate_client(
 
     Ok(is_valid)
 }
+
+#[test]
+fn test_approval_transitions() {
+    use ApprovalStatus::*;
+
+    assert!(Pending.can_become(Approved));
+    assert!(Pending.can_become(Rejected));
+    assert!(Pending.can_become(Banned));
+    assert!(Rejected.can_become(Approved));
+    assert!(Approved.can_become(Banned));
+    // Unbanning approves the worker again
+    assert!(Banned.can_become(Approved));
+
+    assert!(!Approved.can_become(Rejected));
+    assert!(!Approved.can_become(Pending));
+    assert!(!Banned.can_become(Pending));
+    assert!(!Approved.can_become(Approved));
+
+    for status in [Pending, Approved, Rejected, Banned] {
+        assert_eq!(ApprovalStatus::parse(status.as_str()).unwrap(), status);
+    }
+    assert!(ApprovalStatus::parse("valid").is_err());
+}
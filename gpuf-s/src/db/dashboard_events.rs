@@ -0,0 +1,227 @@
+//! Live events for dashboards. Servers publish them on `DASHBOARD_CHANNEL`,
+//! worker connects and disconnects come from the presence channel.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt};
+use redis::{AsyncCommands, Client as RedisClient};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::db::inference_errors::ErrorSeverity;
+use crate::db::presence::{PresenceEvent, PRESENCE_CHANNEL};
+use crate::db::worker_events::{WorkerEvent, WorkerEventKind};
+use crate::util::protoc::ClientId;
+
+pub const DASHBOARD_CHANNEL: &str = "dashboard:events";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DashboardEventKind {
+    WorkerOnline {
+        client_id: ClientId,
+        instance_id: String,
+    },
+    WorkerOffline {
+        client_id: ClientId,
+        instance_id: String,
+    },
+    ModelLoaded {
+        client_id: ClientId,
+        model: String,
+    },
+    ModelUnloaded {
+        client_id: ClientId,
+        model: String,
+    },
+    /// Inference failure a worker reported
+    ErrorReported {
+        client_id: ClientId,
+        model: Option<String>,
+        severity: ErrorSeverity,
+        kind: String,
+    },
+    /// Public requests of an instance well above their recent average
+    RequestSpike {
+        instance_id: String,
+        requests: u64,
+        baseline: f64,
+        window_secs: u64,
+    },
+}
+
+impl DashboardEventKind {
+    /// Name used by the `types` filter of subscribers
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::WorkerOnline { .. } => "worker_online",
+            Self::WorkerOffline { .. } => "worker_offline",
+            Self::ModelLoaded { .. } => "model_loaded",
+            Self::ModelUnloaded { .. } => "model_unloaded",
+            Self::ErrorReported { .. } => "error_reported",
+            Self::RequestSpike { .. } => "request_spike",
+        }
+    }
+
+    /// Model events of a worker. Connects and disconnects are taken from the
+    /// presence channel instead.
+    pub fn from_worker_event(event: &WorkerEvent) -> Option<Self> {
+        let model = event.model.clone()?;
+        match event.kind {
+            WorkerEventKind::ModelLoaded => Some(Self::ModelLoaded {
+                client_id: event.client_id,
+                model,
+            }),
+            WorkerEventKind::ModelUnloaded => Some(Self::ModelUnloaded {
+                client_id: event.client_id,
+                model,
+            }),
+            WorkerEventKind::Connected | WorkerEventKind::Disconnected => None,
+        }
+    }
+
+    /// Worker the event is about, None for fleet-wide events
+    pub fn client_id(&self) -> Option<ClientId> {
+        match self {
+            Self::WorkerOnline { client_id, .. }
+            | Self::WorkerOffline { client_id, .. }
+            | Self::ModelLoaded { client_id, .. }
+            | Self::ModelUnloaded { client_id, .. }
+            | Self::ErrorReported { client_id, .. } => Some(*client_id),
+            Self::RequestSpike { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DashboardEvent {
+    #[serde(flatten)]
+    pub kind: DashboardEventKind,
+    pub at: DateTime<Utc>,
+}
+
+impl DashboardEvent {
+    pub fn new(kind: DashboardEventKind) -> Self {
+        Self {
+            kind,
+            at: Utc::now(),
+        }
+    }
+}
+
+impl From<PresenceEvent> for DashboardEvent {
+    fn from(event: PresenceEvent) -> Self {
+        let kind = if event.online {
+            DashboardEventKind::WorkerOnline {
+                client_id: event.client_id,
+                instance_id: event.instance_id,
+            }
+        } else {
+            DashboardEventKind::WorkerOffline {
+                client_id: event.client_id,
+                instance_id: event.instance_id,
+            }
+        };
+        Self { kind, at: event.at }
+    }
+}
+
+pub async fn publish(redis_client: &RedisClient, event: &DashboardEvent) -> Result<()> {
+    let mut conn = redis_client.get_async_connection().await?;
+    conn.publish::<_, _, ()>(DASHBOARD_CHANNEL, serde_json::to_string(event)?)
+        .await?;
+    Ok(())
+}
+
+fn decode(channel: &str, payload: &str) -> Result<DashboardEvent> {
+    if channel == PRESENCE_CHANNEL {
+        Ok(serde_json::from_str::<PresenceEvent>(payload)?.into())
+    } else {
+        Ok(serde_json::from_str(payload)?)
+    }
+}
+
+/// Dashboard and presence events of all instances until the subscription drops
+pub async fn subscribe(redis_client: &RedisClient) -> Result<impl Stream<Item = DashboardEvent>> {
+    let mut pubsub = redis_client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(DASHBOARD_CHANNEL).await?;
+    pubsub.subscribe(PRESENCE_CHANNEL).await?;
+    Ok(pubsub.into_on_message().filter_map(|message| {
+        let event = message
+            .get_payload::<String>()
+            .map_err(anyhow::Error::from)
+            .and_then(|payload| decode(message.get_channel_name(), &payload));
+        if let Err(e) = &event {
+            warn!("Dropping dashboard event: {}", e);
+        }
+        std::future::ready(event.ok())
+    }))
+}
+
+/// Flags windows whose request count jumps well above the moving average of
+/// the windows before
+#[derive(Debug, Clone)]
+pub struct SpikeDetector {
+    /// A window is a spike above this multiple of the average
+    pub factor: f64,
+    /// Windows with fewer requests are never spikes
+    pub min_requests: u64,
+    average: Option<f64>,
+}
+
+impl SpikeDetector {
+    pub fn new(factor: f64, min_requests: u64) -> Self {
+        Self {
+            factor,
+            min_requests,
+            average: None,
+        }
+    }
+
+    /// Feed the requests of the last window. Returns the average it was
+    /// compared against when the window is a spike.
+    pub fn observe(&mut self, requests: u64) -> Option<f64> {
+        let count = requests as f64;
+        let Some(average) = self.average else {
+            self.average = Some(count);
+            return None;
+        };
+        self.average = Some(average * 0.8 + count * 0.2);
+        (requests >= self.min_requests && count > average * self.factor).then_some(average)
+    }
+}
+
+#[test]
+fn test_dashboard_event_json() {
+    let event = DashboardEvent::new(DashboardEventKind::ModelLoaded {
+        client_id: ClientId([0x2a; 16]),
+        model: "llama3".to_string(),
+    });
+    let json = serde_json::to_value(&event).unwrap();
+    assert_eq!(json["type"], "model_loaded");
+    assert_eq!(json["model"], "llama3");
+    assert_eq!(decode(DASHBOARD_CHANNEL, &json.to_string()).unwrap(), event);
+
+    let presence = PresenceEvent {
+        client_id: ClientId([0x2a; 16]),
+        instance_id: "gpuf-s-a".to_string(),
+        online: false,
+        at: Utc::now(),
+    };
+    let decoded = decode(PRESENCE_CHANNEL, &serde_json::to_string(&presence).unwrap()).unwrap();
+    assert_eq!(decoded.kind.name(), "worker_offline");
+    assert_eq!(decoded.kind.client_id(), Some(ClientId([0x2a; 16])));
+}
+
+#[test]
+fn test_spike_detector() {
+    let mut detector = SpikeDetector::new(3.0, 50);
+    assert_eq!(detector.observe(20), None);
+    assert_eq!(detector.observe(20), None);
+    // Tripled but still below the minimum
+    assert_eq!(detector.observe(45), None);
+    let mut detector = SpikeDetector::new(3.0, 50);
+    detector.observe(100);
+    assert_eq!(detector.observe(400), Some(100.0));
+    assert_eq!(detector.observe(250), None);
+}
@@ -0,0 +1,333 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use redis::Client as RedisClient;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Postgres, QueryBuilder};
+
+use crate::db::{GPU_ASSETS_TABLE, INFERENCE_ERRORS_TABLE};
+use crate::util::msg::{FilterOp, ListField, ListQuery, ListSpec, Page};
+use crate::util::protoc::ClientId;
+
+/// Signatures are cut to the size of their column
+const MAX_SIGNATURE_CHARS: usize = 255;
+/// Rate limit windows are kept a little longer than they last
+const REPORT_WINDOW_TTL_SECS: i64 = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorSeverity {
+    /// The request still got an answer, e.g. after a retry
+    Warning,
+    /// The request failed
+    Error,
+    /// The engine or worker had to restart
+    Fatal,
+}
+
+impl ErrorSeverity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Warning => "warning",
+            Self::Error => "error",
+            Self::Fatal => "fatal",
+        }
+    }
+}
+
+/// Mask numbers and ids so that repeats of one failure share a signature
+fn mask_word(word: &str) -> String {
+    let core = word.trim_matches(|c: char| !c.is_ascii_alphanumeric());
+    if core.len() >= 8
+        && core.chars().any(|c| c.is_ascii_digit())
+        && core.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+    {
+        return word.replacen(core, "<id>", 1);
+    }
+    let mut masked = String::with_capacity(word.len());
+    let mut in_number = false;
+    for c in word.chars() {
+        if c.is_ascii_digit() {
+            if !in_number {
+                masked.push_str("<n>");
+                in_number = true;
+            }
+        } else {
+            masked.push(c);
+            in_number = false;
+        }
+    }
+    masked
+}
+
+/// Group key of a report, its kind and the first line of the message with
+/// numbers and ids masked
+pub fn error_signature(kind: &str, message: &str) -> String {
+    let first_line = message.lines().next().unwrap_or("");
+    let masked: Vec<String> = first_line.split_whitespace().map(mask_word).collect();
+    let signature = format!("{}: {}", kind, masked.join(" "));
+    match signature.char_indices().nth(MAX_SIGNATURE_CHARS) {
+        Some((end, _)) => signature[..end].to_string(),
+        None => signature,
+    }
+}
+
+pub struct NewInferenceError<'a> {
+    pub client_id: &'a ClientId,
+    pub model: Option<&'a str>,
+    pub severity: ErrorSeverity,
+    pub kind: &'a str,
+    pub message: &'a str,
+    pub request_id: Option<&'a str>,
+    pub engine: Option<&'a str>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// Store a report. Returns None, storing nothing, when the client is unknown
+/// or not valid.
+pub async fn insert_inference_error(
+    pool: &Pool<Postgres>,
+    report: &NewInferenceError<'_>,
+) -> Result<Option<i64>> {
+    let id = sqlx::query_scalar(&format!(
+        "INSERT INTO {} (client_id, model, severity, kind, message, signature, request_id, \
+         engine, occurred_at) \
+         SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9 \
+         WHERE EXISTS (SELECT 1 FROM {} WHERE client_id = $1 AND valid_status = 'valid') \
+         RETURNING id",
+        INFERENCE_ERRORS_TABLE, GPU_ASSETS_TABLE
+    ))
+    .bind(report.client_id)
+    .bind(report.model)
+    .bind(report.severity.as_str())
+    .bind(report.kind)
+    .bind(report.message)
+    .bind(error_signature(report.kind, report.message))
+    .bind(report.request_id)
+    .bind(report.engine)
+    .bind(report.occurred_at)
+    .fetch_optional(pool)
+    .await?;
+    Ok(id)
+}
+
+/// Count a report of the client in the current minute, returns the reports
+/// of the minute so far
+pub async fn count_client_report(redis_client: &RedisClient, client_id: &ClientId) -> Result<u64> {
+    let minute = Utc::now().timestamp() / 60;
+    let key = format!("ratelimit:errors:{}:{}", client_id, minute);
+    let mut conn = redis_client.get_async_connection().await?;
+    let (count,): (u64,) = redis::pipe()
+        .incr(&key, 1)
+        .expire(&key, REPORT_WINDOW_TTL_SECS)
+        .ignore()
+        .query_async(&mut conn)
+        .await?;
+    Ok(count)
+}
+
+/// Which reports the queries cover, all optional
+#[derive(Debug, Default)]
+pub struct ErrorFilter {
+    /// Only clients of this user
+    pub user_id: Option<String>,
+    pub client_id: Option<ClientId>,
+    pub model: Option<String>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+}
+
+impl ErrorFilter {
+    fn push_scope(&self, query_builder: &mut QueryBuilder<'_, Postgres>) {
+        query_builder.push(INFERENCE_ERRORS_TABLE).push(" e");
+        if let Some(user_id) = &self.user_id {
+            query_builder
+                .push(" JOIN ")
+                .push(GPU_ASSETS_TABLE)
+                .push(" ga ON ga.client_id = e.client_id AND ga.user_id = ")
+                .push_bind(user_id.clone());
+        }
+        query_builder.push(" WHERE TRUE");
+        if let Some(client_id) = self.client_id {
+            query_builder
+                .push(" AND e.client_id = ")
+                .push_bind(client_id);
+        }
+        if let Some(model) = &self.model {
+            query_builder
+                .push(" AND e.model = ")
+                .push_bind(model.clone());
+        }
+        if let Some(start_date) = self.start_date {
+            query_builder
+                .push(" AND e.occurred_at >= ")
+                .push_bind(start_date)
+                .push("::date");
+        }
+        if let Some(end_date) = self.end_date {
+            query_builder
+                .push(" AND e.occurred_at < ")
+                .push_bind(end_date)
+                .push("::date + 1");
+        }
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct InferenceError {
+    pub id: i64,
+    pub client_id: String,
+    pub model: Option<String>,
+    pub severity: String,
+    pub kind: String,
+    pub message: String,
+    pub signature: String,
+    pub request_id: Option<String>,
+    pub engine: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Sort and filter fields of the error listing
+pub static ERROR_LIST: ListSpec = ListSpec {
+    fields: &[
+        ListField {
+            name: "occurred_at",
+            expr: "l.occurred_at",
+            sql_type: "timestamptz",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "severity",
+            expr: "l.severity",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "kind",
+            expr: "l.kind",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "signature",
+            expr: "l.signature",
+            sql_type: "text",
+            sortable: false,
+            filter: Some(FilterOp::Contains),
+        },
+        ListField {
+            name: "engine",
+            expr: "COALESCE(l.engine, '')",
+            sql_type: "text",
+            sortable: false,
+            filter: Some(FilterOp::Eq),
+        },
+    ],
+    default_sort: "-occurred_at",
+    key: "lpad(l.id::text, 20, '0')",
+};
+
+pub async fn list_inference_errors(
+    pool: &Pool<Postgres>,
+    filter: &ErrorFilter,
+    list: &ListQuery,
+) -> Result<Page<InferenceError>, sqlx::Error> {
+    list.fetch(pool, |query_builder| {
+        query_builder.push(
+            "SELECT e.id, encode(e.client_id, 'hex') AS client_id, e.model, e.severity, e.kind, \
+             e.message, e.signature, e.request_id, e.engine, e.occurred_at, e.created_at FROM ",
+        );
+        filter.push_scope(query_builder);
+    })
+    .await
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct SignatureCount {
+    pub signature: String,
+    pub kind: String,
+    pub count: i64,
+    pub clients: i64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Message of the latest report
+    pub sample_message: String,
+}
+
+/// The most frequent signatures, most reports first
+pub async fn top_error_signatures(
+    pool: &Pool<Postgres>,
+    filter: &ErrorFilter,
+    limit: i64,
+) -> Result<Vec<SignatureCount>, sqlx::Error> {
+    let mut query_builder = QueryBuilder::<Postgres>::new(
+        "SELECT e.signature, MIN(e.kind) AS kind, COUNT(*) AS count, \
+         COUNT(DISTINCT e.client_id) AS clients, MIN(e.occurred_at) AS first_seen, \
+         MAX(e.occurred_at) AS last_seen, \
+         (array_agg(e.message ORDER BY e.occurred_at DESC))[1] AS sample_message FROM ",
+    );
+    filter.push_scope(&mut query_builder);
+    query_builder
+        .push(" GROUP BY e.signature ORDER BY count DESC, e.signature LIMIT ")
+        .push_bind(limit);
+    query_builder
+        .build_query_as::<SignatureCount>()
+        .fetch_all(pool)
+        .await
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct ModelErrorCount {
+    /// None for reports that named no model
+    pub model: Option<String>,
+    pub count: i64,
+    pub warnings: i64,
+    pub errors: i64,
+    pub fatal: i64,
+    pub clients: i64,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Reports per model, most reports first
+pub async fn errors_per_model(
+    pool: &Pool<Postgres>,
+    filter: &ErrorFilter,
+) -> Result<Vec<ModelErrorCount>, sqlx::Error> {
+    let mut query_builder = QueryBuilder::<Postgres>::new(
+        "SELECT e.model, COUNT(*) AS count, \
+         COUNT(*) FILTER (WHERE e.severity = 'warning') AS warnings, \
+         COUNT(*) FILTER (WHERE e.severity = 'error') AS errors, \
+         COUNT(*) FILTER (WHERE e.severity = 'fatal') AS fatal, \
+         COUNT(DISTINCT e.client_id) AS clients, MAX(e.occurred_at) AS last_seen FROM ",
+    );
+    filter.push_scope(&mut query_builder);
+    query_builder.push(" GROUP BY e.model ORDER BY count DESC, e.model");
+    query_builder
+        .build_query_as::<ModelErrorCount>()
+        .fetch_all(pool)
+        .await
+}
+
+#[test]
+fn test_error_signature() {
+    assert_eq!(
+        error_signature(
+            "oom",
+            "CUDA out of memory. Tried to allocate 2.50 GiB (GPU 0)\nstack trace"
+        ),
+        "oom: CUDA out of memory. Tried to allocate <n>.<n> GiB (GPU <n>)"
+    );
+    assert_eq!(
+        error_signature("timeout", "request 9f86d081884c7d65 timed out after 30s"),
+        error_signature("timeout", "request 2c26b46b68ffc68f timed out after 120s")
+    );
+    assert_eq!(
+        error_signature("engine", "slot   a  failed"),
+        "engine: slot a failed"
+    );
+    let long = error_signature("engine", &"é".repeat(400));
+    assert_eq!(long.chars().count(), MAX_SIGNATURE_CHARS);
+}
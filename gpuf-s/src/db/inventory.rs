@@ -0,0 +1,166 @@
+//! Fleet-wide view of the models workers reported as loaded. Built from the
+//! `client:<id>:models` keys the server refreshes on every model status
+//! report, and cached in Redis for a short while.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use common::Model;
+use redis::{AsyncCommands, Client as RedisClient};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Postgres};
+use std::collections::BTreeMap;
+use tracing::warn;
+
+use crate::db::{GPU_ASSETS_TABLE, SYSTEM_INFO_TABLE};
+
+const INVENTORY_CACHE_KEY: &str = "fleet:model_inventory";
+/// Seconds a built inventory is served from Redis
+pub const INVENTORY_CACHE_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelInventory {
+    pub generated_at: DateTime<Utc>,
+    /// Online clients that reported their models
+    pub reporting_clients: usize,
+    pub models: Vec<ModelReplicas>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelReplicas {
+    pub model: String,
+    /// Online clients with the model loaded
+    pub replicas: usize,
+    /// Sum of the TFLOPS of those clients
+    pub total_tflops: i64,
+    pub client_ids: Vec<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct OnlineClient {
+    client_id: Vec<u8>,
+    total_tflops: i32,
+}
+
+/// Group the reports of online clients by model, most replicas first
+pub fn aggregate(reports: &[(String, i64, Vec<Model>)]) -> Vec<ModelReplicas> {
+    let mut by_model: BTreeMap<&str, ModelReplicas> = BTreeMap::new();
+    for (client_id, tflops, models) in reports {
+        for model in models {
+            let entry = by_model
+                .entry(model.id.as_str())
+                .or_insert_with(|| ModelReplicas {
+                    model: model.id.clone(),
+                    replicas: 0,
+                    total_tflops: 0,
+                    client_ids: Vec::new(),
+                });
+            // A client listing a model twice is still one replica
+            if entry.client_ids.last() == Some(client_id) {
+                continue;
+            }
+            entry.replicas += 1;
+            entry.total_tflops += tflops;
+            entry.client_ids.push(client_id.clone());
+        }
+    }
+    let mut models: Vec<ModelReplicas> = by_model.into_values().collect();
+    models.sort_by(|a, b| b.replicas.cmp(&a.replicas).then(a.model.cmp(&b.model)));
+    models
+}
+
+/// The cached inventory, or a freshly built one when the cache expired
+pub async fn get_model_inventory(
+    pool: &Pool<Postgres>,
+    redis_client: &RedisClient,
+) -> Result<ModelInventory> {
+    let mut conn = redis_client.get_async_connection().await?;
+    let cached: Option<String> = conn.get(INVENTORY_CACHE_KEY).await.unwrap_or(None);
+    if let Some(inventory) = cached.and_then(|json| serde_json::from_str(&json).ok()) {
+        return Ok(inventory);
+    }
+
+    let inventory = build_model_inventory(pool, &mut conn).await?;
+    match serde_json::to_string(&inventory) {
+        Ok(json) => {
+            let stored: redis::RedisResult<()> = conn
+                .set_ex(INVENTORY_CACHE_KEY, json, INVENTORY_CACHE_SECS)
+                .await;
+            if let Err(e) = stored {
+                warn!("Failed to cache model inventory: {}", e);
+            }
+        }
+        Err(e) => warn!("Failed to serialize model inventory: {}", e),
+    }
+    Ok(inventory)
+}
+
+async fn build_model_inventory(
+    pool: &Pool<Postgres>,
+    conn: &mut redis::aio::Connection,
+) -> Result<ModelInventory> {
+    let clients = sqlx::query_as::<_, OnlineClient>(&format!(
+        "SELECT ga.client_id, COALESCE(si.total_tflops, 0) AS total_tflops \
+         FROM {} ga LEFT JOIN {} si ON si.client_id = ga.client_id \
+         WHERE ga.client_status = 'online' AND ga.valid_status = 'valid'",
+        GPU_ASSETS_TABLE, SYSTEM_INFO_TABLE
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    let mut reports = Vec::with_capacity(clients.len());
+    if !clients.is_empty() {
+        let keys: Vec<String> = clients
+            .iter()
+            .map(|c| format!("client:{}:models", hex::encode(&c.client_id)))
+            .collect();
+        let values: Vec<Option<String>> = conn.get(keys).await?;
+        for (client, value) in clients.iter().zip(values) {
+            let Some(json) = value else {
+                continue;
+            };
+            match serde_json::from_str::<Vec<Model>>(&json) {
+                Ok(models) => reports.push((
+                    hex::encode(&client.client_id),
+                    client.total_tflops as i64,
+                    models,
+                )),
+                Err(e) => warn!(
+                    "Failed to parse models of client {}: {}",
+                    hex::encode(&client.client_id),
+                    e
+                ),
+            }
+        }
+    }
+
+    Ok(ModelInventory {
+        generated_at: Utc::now(),
+        reporting_clients: reports.len(),
+        models: aggregate(&reports),
+    })
+}
+
+#[test]
+fn test_aggregate_inventory() {
+    let model = |id: &str| Model {
+        id: id.to_string(),
+        object: "model".to_string(),
+        created: 0,
+        owned_by: "gpuf".to_string(),
+    };
+    let reports = vec![
+        ("aa".to_string(), 10, vec![model("llama"), model("qwen")]),
+        ("bb".to_string(), 20, vec![model("llama"), model("llama")]),
+        ("cc".to_string(), 5, vec![]),
+    ];
+
+    let models = aggregate(&reports);
+    assert_eq!(models.len(), 2);
+    assert_eq!(models[0].model, "llama");
+    assert_eq!(models[0].replicas, 2);
+    assert_eq!(models[0].total_tflops, 30);
+    assert_eq!(models[0].client_ids, vec!["aa", "bb"]);
+    assert_eq!(models[1].model, "qwen");
+    assert_eq!(models[1].replicas, 1);
+    assert_eq!(models[1].total_tflops, 10);
+}
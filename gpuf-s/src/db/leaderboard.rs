@@ -0,0 +1,218 @@
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Postgres};
+
+use crate::db::{
+    CLIENT_WORK_POINTS_DAILY_TABLE, DEVICE_INFO_TABLE, DEVICE_POINTS_DAILY_VIEW, GPU_ASSETS_TABLE,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaderboardWindow {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl LeaderboardWindow {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+            Self::Monthly => "monthly",
+        }
+    }
+
+    /// First day counted when the window ends on `today`
+    pub fn start(&self, today: NaiveDate) -> NaiveDate {
+        let days = match self {
+            Self::Daily => 1,
+            Self::Weekly => 7,
+            Self::Monthly => 30,
+        };
+        today - Duration::days(days - 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaderboardMetric {
+    /// Heartbeat and work points together
+    Points,
+    /// Completion tokens served
+    Tokens,
+}
+
+impl LeaderboardMetric {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Points => "points",
+            Self::Tokens => "tokens",
+        }
+    }
+
+    fn order_by(&self) -> &'static str {
+        match self {
+            Self::Points => "total_points DESC, tokens_served DESC",
+            Self::Tokens => "tokens_served DESC, total_points DESC",
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    #[sqlx(default)]
+    pub rank: i64,
+    pub user_id: String,
+    pub clients: i64,
+    pub heartbeat_points: f64,
+    pub work_points: f64,
+    pub total_points: f64,
+    pub tokens_served: i64,
+    pub requests: i64,
+}
+
+/// Top `limit` providers by `metric` between `start` and today
+pub async fn get_leaderboard(
+    pool: &Pool<Postgres>,
+    start: NaiveDate,
+    metric: LeaderboardMetric,
+    limit: i64,
+) -> Result<Vec<LeaderboardEntry>, sqlx::Error> {
+    let mut entries = sqlx::query_as::<_, LeaderboardEntry>(&format!(
+        r#"
+            WITH heartbeat AS (
+                SELECT ga.user_id, SUM(dpd.points) AS points, COUNT(DISTINCT dpd.client_id) AS clients
+                FROM {points} dpd
+                INNER JOIN {assets} ga ON ga.client_id = dpd.client_id
+                WHERE dpd.date >= $1 AND ga.user_id IS NOT NULL
+                GROUP BY ga.user_id
+            ),
+            work AS (
+                SELECT
+                    ga.user_id,
+                    SUM(wp.points) AS points,
+                    SUM(wp.completion_tokens) AS tokens,
+                    SUM(wp.requests) AS requests,
+                    COUNT(DISTINCT wp.client_id) AS clients
+                FROM {work} wp
+                INNER JOIN {assets} ga ON ga.client_id = wp.client_id
+                WHERE wp.date >= $1 AND ga.user_id IS NOT NULL
+                GROUP BY ga.user_id
+            )
+            SELECT
+                COALESCE(h.user_id, w.user_id) AS user_id,
+                GREATEST(COALESCE(h.clients, 0), COALESCE(w.clients, 0))::BIGINT AS clients,
+                COALESCE(h.points, 0)::DOUBLE PRECISION AS heartbeat_points,
+                COALESCE(w.points, 0)::DOUBLE PRECISION AS work_points,
+                (COALESCE(h.points, 0) + COALESCE(w.points, 0))::DOUBLE PRECISION AS total_points,
+                COALESCE(w.tokens, 0)::BIGINT AS tokens_served,
+                COALESCE(w.requests, 0)::BIGINT AS requests
+            FROM heartbeat h
+            FULL OUTER JOIN work w ON w.user_id = h.user_id
+            ORDER BY {order_by}, user_id
+            LIMIT $2
+        "#,
+        points = DEVICE_POINTS_DAILY_VIEW,
+        assets = GPU_ASSETS_TABLE,
+        work = CLIENT_WORK_POINTS_DAILY_TABLE,
+        order_by = metric.order_by()
+    ))
+    .bind(start)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    for (i, entry) in entries.iter_mut().enumerate() {
+        entry.rank = i as i64 + 1;
+    }
+    Ok(entries)
+}
+
+/// Points and work of a provider in one window
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct ProviderWindowStats {
+    pub heartbeat_points: f64,
+    pub work_points: f64,
+    pub tokens_served: i64,
+    pub requests: i64,
+    pub sla_met_requests: i64,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct ProviderClients {
+    pub clients: i64,
+    pub online_clients: i64,
+    pub devices: i64,
+}
+
+pub async fn get_provider_clients(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+) -> Result<ProviderClients, sqlx::Error> {
+    sqlx::query_as::<_, ProviderClients>(&format!(
+        r#"
+            SELECT
+                COUNT(*) AS clients,
+                COUNT(*) FILTER (
+                    WHERE ga.client_status = 'online' AND ga.valid_status = 'valid'
+                ) AS online_clients,
+                COALESCE(SUM(
+                    (SELECT COUNT(*) FROM {devices} di WHERE di.client_id = ga.client_id)
+                ), 0)::BIGINT AS devices
+            FROM {assets} ga
+            WHERE ga.user_id = $1
+        "#,
+        assets = GPU_ASSETS_TABLE,
+        devices = DEVICE_INFO_TABLE
+    ))
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn get_provider_window_stats(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+    start: NaiveDate,
+) -> Result<ProviderWindowStats, sqlx::Error> {
+    sqlx::query_as::<_, ProviderWindowStats>(&format!(
+        r#"
+            SELECT
+                COALESCE((
+                    SELECT SUM(dpd.points)
+                    FROM {points} dpd
+                    INNER JOIN {assets} ga ON ga.client_id = dpd.client_id
+                    WHERE ga.user_id = $1 AND dpd.date >= $2
+                ), 0)::DOUBLE PRECISION AS heartbeat_points,
+                COALESCE(SUM(wp.points), 0)::DOUBLE PRECISION AS work_points,
+                COALESCE(SUM(wp.completion_tokens), 0)::BIGINT AS tokens_served,
+                COALESCE(SUM(wp.requests), 0)::BIGINT AS requests,
+                COALESCE(SUM(wp.sla_met_requests), 0)::BIGINT AS sla_met_requests
+            FROM {work} wp
+            INNER JOIN {assets} ga ON ga.client_id = wp.client_id
+            WHERE ga.user_id = $1 AND wp.date >= $2
+        "#,
+        points = DEVICE_POINTS_DAILY_VIEW,
+        assets = GPU_ASSETS_TABLE,
+        work = CLIENT_WORK_POINTS_DAILY_TABLE
+    ))
+    .bind(user_id)
+    .bind(start)
+    .fetch_one(pool)
+    .await
+}
+
+#[test]
+fn test_leaderboard_window_start() {
+    let today = NaiveDate::from_ymd_opt(2025, 8, 3).unwrap();
+    assert_eq!(LeaderboardWindow::Daily.start(today), today);
+    assert_eq!(
+        LeaderboardWindow::Weekly.start(today),
+        NaiveDate::from_ymd_opt(2025, 7, 28).unwrap()
+    );
+    assert_eq!(
+        LeaderboardWindow::Monthly.start(today),
+        NaiveDate::from_ymd_opt(2025, 7, 5).unwrap()
+    );
+}
@@ -1,14 +1,54 @@
+pub mod anomalies;
 pub mod apk;
+pub mod audit;
+pub mod canary;
+pub mod challenges;
 pub mod client;
+pub mod dashboard_events;
+pub mod inference_errors;
+pub mod inventory;
+pub mod leaderboard;
+pub mod model_perf;
 pub mod models;
+pub mod notifications;
+pub mod orgs;
+pub mod payouts;
+pub mod presence;
+pub mod rollouts;
+pub mod staff;
 pub mod stats;
+pub mod timeline;
+pub mod usage;
+pub mod worker_events;
+
+/// Schema migrations in `gpuf-s/migrations`, embedded at build time
+pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
 
 const GPU_ASSETS_TABLE: &str = "gpu_assets";
 const HEARTBEAT_TABLE: &str = "heartbeat";
 const DEVICE_INFO_TABLE: &str = "device_info";
 const SYSTEM_INFO_TABLE: &str = "system_info";
 const APK_VERSIONS_TABLE: &str = "apk_versions";
-#[allow(dead_code)]
 const CLIENT_MODELS_TABLE: &str = "client_models";
 const CLIENT_DAILY_STATS_TABLE: &str = "client_daily_stats";
 const DEVICE_DAILY_STATS_TABLE: &str = "device_daily_stats";
+const MODEL_PERF_SAMPLES_TABLE: &str = "model_perf_samples";
+const TOKENS_TABLE: &str = "tokens";
+const USAGE_RECORDS_TABLE: &str = "usage_records";
+const DEVICE_POINTS_DAILY_VIEW: &str = "device_points_daily";
+const PAYOUT_RATE_CARDS_TABLE: &str = "payout_rate_cards";
+const PAYOUT_STATEMENTS_TABLE: &str = "payout_statements";
+const CLIENT_WORK_POINTS_DAILY_TABLE: &str = "client_work_points_daily";
+const HEARTBEAT_ANOMALIES_TABLE: &str = "heartbeat_anomalies";
+const CLIENT_CHALLENGE_DAILY_TABLE: &str = "client_challenge_daily";
+const CANARY_RESULTS_TABLE: &str = "canary_results";
+const WORKER_EVENTS_TABLE: &str = "worker_events";
+const MODEL_ROLLOUT_RESULTS_TABLE: &str = "model_rollout_results";
+const INFERENCE_ERRORS_TABLE: &str = "inference_errors";
+const NOTIFICATION_PREFERENCES_TABLE: &str = "notification_preferences";
+const NOTIFICATION_DELIVERIES_TABLE: &str = "notification_deliveries";
+const ORGANIZATIONS_TABLE: &str = "organizations";
+const ORGANIZATION_MEMBERS_TABLE: &str = "organization_members";
+const STAFF_ROLES_TABLE: &str = "staff_roles";
+const ACCESS_DENIALS_TABLE: &str = "access_denials";
+const AUDIT_LOG_TABLE: &str = "audit_log";
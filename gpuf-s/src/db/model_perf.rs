@@ -0,0 +1,266 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Postgres};
+
+use crate::db::{CLIENT_MODELS_TABLE, GPU_ASSETS_TABLE, MODEL_PERF_SAMPLES_TABLE};
+use crate::util::protoc::ClientId;
+
+/// Minimum number of samples per version before a recommendation is made
+const MIN_SAMPLES_FOR_VERDICT: i64 = 50;
+/// Allowed absolute increase of the error rate for the candidate version
+const MAX_ERROR_RATE_REGRESSION: f64 = 0.02;
+/// Allowed relative increase of the p95 latency for the candidate version
+const MAX_P95_LATENCY_REGRESSION: f64 = 0.25;
+
+#[derive(Debug, Clone)]
+pub struct ModelPerfSample {
+    pub model_name: String,
+    pub client_id: ClientId,
+    pub latency_ms: i64,
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    pub success: bool,
+    pub truncated: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct ModelVersionStats {
+    pub model_version: String,
+    pub request_count: i64,
+    pub client_count: i64,
+    pub error_rate: f64,
+    pub truncated_rate: f64,
+    pub empty_output_rate: f64,
+    pub avg_latency_ms: f64,
+    pub p50_latency_ms: Option<f64>,
+    pub p95_latency_ms: Option<f64>,
+    pub tokens_per_sec: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RolloutVerdict {
+    Promote,
+    Rollback,
+    InsufficientData,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelVersionComparison {
+    pub model_name: String,
+    pub days: i32,
+    pub base: Option<ModelVersionStats>,
+    pub candidate: Option<ModelVersionStats>,
+    pub error_rate_delta: Option<f64>,
+    pub p95_latency_delta_ms: Option<f64>,
+    pub tokens_per_sec_delta: Option<f64>,
+    pub verdict: RolloutVerdict,
+}
+
+/// Store one inference sample. The model version is resolved from the
+/// version pinned on the device, falling back to the newest active catalog entry.
+pub async fn insert_model_perf_sample(
+    pool: &Pool<Postgres>,
+    sample: &ModelPerfSample,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        r#"
+            INSERT INTO {table} (
+                model_name,
+                model_version,
+                client_id,
+                latency_ms,
+                prompt_tokens,
+                completion_tokens,
+                success,
+                truncated,
+                error
+            )
+            VALUES (
+                $1,
+                COALESCE(
+                    (SELECT model_version FROM {assets} WHERE client_id = $2 AND model = $1 LIMIT 1),
+                    (SELECT version FROM {models} WHERE name = $1 AND is_active = true
+                     ORDER BY version_code DESC LIMIT 1),
+                    'unknown'
+                ),
+                $2, $3, $4, $5, $6, $7, $8
+            )
+            "#,
+        table = MODEL_PERF_SAMPLES_TABLE,
+        assets = GPU_ASSETS_TABLE,
+        models = CLIENT_MODELS_TABLE
+    ))
+    .bind(&sample.model_name)
+    .bind(sample.client_id)
+    .bind(sample.latency_ms)
+    .bind(sample.prompt_tokens)
+    .bind(sample.completion_tokens)
+    .bind(sample.success)
+    .bind(sample.truncated)
+    .bind(&sample.error)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Aggregate samples of the given versions of a model over the last `days` days
+pub async fn get_model_version_stats(
+    pool: &Pool<Postgres>,
+    model_name: &str,
+    versions: &[String],
+    days: i32,
+) -> Result<Vec<ModelVersionStats>, sqlx::Error> {
+    sqlx::query_as(&format!(
+        r#"
+            SELECT
+                model_version,
+                COUNT(*) AS request_count,
+                COUNT(DISTINCT client_id) AS client_count,
+                AVG(CASE WHEN success THEN 0 ELSE 1 END)::DOUBLE PRECISION AS error_rate,
+                AVG(CASE WHEN truncated THEN 1 ELSE 0 END)::DOUBLE PRECISION AS truncated_rate,
+                AVG(CASE WHEN success AND completion_tokens = 0 THEN 1 ELSE 0 END)::DOUBLE PRECISION AS empty_output_rate,
+                AVG(latency_ms)::DOUBLE PRECISION AS avg_latency_ms,
+                percentile_cont(0.5) WITHIN GROUP (ORDER BY latency_ms) AS p50_latency_ms,
+                percentile_cont(0.95) WITHIN GROUP (ORDER BY latency_ms) AS p95_latency_ms,
+                COALESCE(
+                    SUM(completion_tokens) FILTER (WHERE success)::DOUBLE PRECISION * 1000
+                        / NULLIF(SUM(latency_ms) FILTER (WHERE success), 0),
+                    0
+                ) AS tokens_per_sec
+            FROM {table}
+            WHERE model_name = $1
+              AND model_version = ANY($2)
+              AND created_at >= NOW() - ($3 * INTERVAL '1 day')
+            GROUP BY model_version
+            "#,
+        table = MODEL_PERF_SAMPLES_TABLE
+    ))
+    .bind(model_name)
+    .bind(versions)
+    .bind(days)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn compare_model_versions(
+    pool: &Pool<Postgres>,
+    model_name: &str,
+    base_version: &str,
+    candidate_version: &str,
+    days: i32,
+) -> Result<ModelVersionComparison, sqlx::Error> {
+    let versions = vec![base_version.to_string(), candidate_version.to_string()];
+    let mut stats = get_model_version_stats(pool, model_name, &versions, days).await?;
+
+    let take = |stats: &mut Vec<ModelVersionStats>, version: &str| {
+        stats
+            .iter()
+            .position(|s| s.model_version == version)
+            .map(|i| stats.swap_remove(i))
+    };
+    let base = take(&mut stats, base_version);
+    let candidate = take(&mut stats, candidate_version);
+
+    Ok(ModelVersionComparison::new(
+        model_name.to_string(),
+        days,
+        base,
+        candidate,
+    ))
+}
+
+impl ModelVersionComparison {
+    pub fn new(
+        model_name: String,
+        days: i32,
+        base: Option<ModelVersionStats>,
+        candidate: Option<ModelVersionStats>,
+    ) -> Self {
+        let (error_rate_delta, p95_latency_delta_ms, tokens_per_sec_delta, verdict) =
+            match (&base, &candidate) {
+                (Some(b), Some(c)) => {
+                    let p95_delta = match (b.p95_latency_ms, c.p95_latency_ms) {
+                        (Some(bp), Some(cp)) => Some(cp - bp),
+                        _ => None,
+                    };
+                    (
+                        Some(c.error_rate - b.error_rate),
+                        p95_delta,
+                        Some(c.tokens_per_sec - b.tokens_per_sec),
+                        rollout_verdict(b, c),
+                    )
+                }
+                _ => (None, None, None, RolloutVerdict::InsufficientData),
+            };
+
+        Self {
+            model_name,
+            days,
+            base,
+            candidate,
+            error_rate_delta,
+            p95_latency_delta_ms,
+            tokens_per_sec_delta,
+            verdict,
+        }
+    }
+}
+
+fn rollout_verdict(base: &ModelVersionStats, candidate: &ModelVersionStats) -> RolloutVerdict {
+    if base.request_count < MIN_SAMPLES_FOR_VERDICT
+        || candidate.request_count < MIN_SAMPLES_FOR_VERDICT
+    {
+        return RolloutVerdict::InsufficientData;
+    }
+
+    if candidate.error_rate - base.error_rate > MAX_ERROR_RATE_REGRESSION {
+        return RolloutVerdict::Rollback;
+    }
+
+    if let (Some(bp), Some(cp)) = (base.p95_latency_ms, candidate.p95_latency_ms) {
+        if bp > 0.0 && (cp - bp) / bp > MAX_P95_LATENCY_REGRESSION {
+            return RolloutVerdict::Rollback;
+        }
+    }
+
+    RolloutVerdict::Promote
+}
+
+#[test]
+fn test_rollout_verdict() {
+    let stats = |version: &str, count: i64, error_rate: f64, p95: f64| ModelVersionStats {
+        model_version: version.to_string(),
+        request_count: count,
+        client_count: 1,
+        error_rate,
+        truncated_rate: 0.0,
+        empty_output_rate: 0.0,
+        avg_latency_ms: p95 / 2.0,
+        p50_latency_ms: Some(p95 / 2.0),
+        p95_latency_ms: Some(p95),
+        tokens_per_sec: 10.0,
+    };
+
+    let base = stats("v1", 100, 0.01, 1000.0);
+    assert_eq!(
+        rollout_verdict(&base, &stats("v2", 100, 0.01, 1100.0)),
+        RolloutVerdict::Promote
+    );
+    assert_eq!(
+        rollout_verdict(&base, &stats("v2", 100, 0.05, 1000.0)),
+        RolloutVerdict::Rollback
+    );
+    assert_eq!(
+        rollout_verdict(&base, &stats("v2", 100, 0.01, 1500.0)),
+        RolloutVerdict::Rollback
+    );
+    assert_eq!(
+        rollout_verdict(&base, &stats("v2", 10, 0.5, 5000.0)),
+        RolloutVerdict::InsufficientData
+    );
+
+    let cmp = ModelVersionComparison::new("m".to_string(), 7, Some(base), None);
+    assert_eq!(cmp.verdict, RolloutVerdict::InsufficientData);
+    assert!(cmp.error_rate_delta.is_none());
+}
@@ -1,10 +1,12 @@
 use crate::db::GPU_ASSETS_TABLE;
+use crate::util::msg::{FilterOp, ListField, ListQuery, ListSpec, Page};
 use crate::util::protoc::ClientId;
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use common::{DevicesInfo, EngineType, OsType, PodModel};
+use common::{DevicesInfo, EngineType, OsType, PodModelV2};
 use lru::LruCache;
-use sqlx::{Pool, Postgres};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, QueryBuilder};
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -31,12 +33,25 @@ impl HotModelClass {
     fn align_gpu_memory(mem_mb: u32) -> u32 {
         (mem_mb / GB50_IN_MB) * GB50_IN_MB
     }
-    pub async fn get_hot_model(&self, mem_total_gb: u32, engine_type: i16) -> Result<String> {
-        let model_info = self.get_hot_model_with_details(mem_total_gb, engine_type).await?;
+    pub async fn get_hot_model(
+        &self,
+        client_id: &ClientId,
+        mem_total_gb: u32,
+        engine_type: i16,
+    ) -> Result<String> {
+        let model_info = self
+            .get_hot_model_with_details(client_id, mem_total_gb, engine_type)
+            .await?;
         Ok(model_info.name)
     }
 
-    pub async fn get_hot_model_with_details(&self, mem_total_gb: u32, engine_type: i16) -> Result<ModelInfo> {
+    /// Best compatible model whose rollout includes the client
+    pub async fn get_hot_model_with_details(
+        &self,
+        client_id: &ClientId,
+        mem_total_gb: u32,
+        engine_type: i16,
+    ) -> Result<ModelInfo> {
         let model = match get_models_list(
             &self.pool,
             Some(true),
@@ -51,27 +66,142 @@ impl HotModelClass {
                 return Err(anyhow::anyhow!("Failed to get client model"));
             }
         };
-        if model.is_empty() {
-            warn!("No compatible models found for memory {} GB", mem_total_gb);
-            return Ok(ModelInfo::default());
+        match model.iter().find(|model| model.assignable_to(client_id)) {
+            Some(model) => Ok(ModelInfo::from(model)),
+            None => {
+                warn!("No compatible models found for memory {} GB", mem_total_gb);
+                Ok(ModelInfo::default())
+            }
         }
+    }
 
-        Ok(ModelInfo {
-            name: model[0].name.clone(),
-            download_url: model[0].download_url.clone(),
-            checksum: model[0].checksum.clone(),
-            expected_size: model[0].expected_size,
-        })
+    /// Largest compatible model of at most `max_bytes` not in `exclude`, for a
+    /// worker whose free memory cannot fit the recommended one
+    pub async fn get_smaller_model_with_details(
+        &self,
+        client_id: &ClientId,
+        mem_total_gb: u32,
+        engine_type: i16,
+        max_bytes: u64,
+        exclude: &[String],
+    ) -> Result<ModelInfo> {
+        let models = get_models_list(
+            &self.pool,
+            Some(true),
+            Some(engine_type),
+            Some(mem_total_gb as i32),
+        )
+        .await?;
+        let model = models
+            .iter()
+            .filter(|model| !exclude.contains(&model.name) && model.assignable_to(client_id))
+            .filter_map(|model| {
+                let size = model.expected_size.filter(|&size| size > 0)? as u64;
+                (size <= max_bytes).then_some((size, model))
+            })
+            .max_by_key(|(size, _)| *size);
+        match model {
+            Some((_, model)) => Ok(ModelInfo::from(model)),
+            None => {
+                warn!(
+                    "No compatible model within {} bytes for memory {} GB",
+                    max_bytes, mem_total_gb
+                );
+                Ok(ModelInfo::default())
+            }
+        }
     }
 }
 
 /// Model information including download details
-#[derive(Default, Clone)]
+#[derive(Default, Clone, sqlx::FromRow)]
 pub struct ModelInfo {
     pub name: String,
     pub download_url: Option<String>,
     pub checksum: Option<String>,
     pub expected_size: Option<i64>,
+    pub signature: Option<String>,
+    pub version: Option<String>,
+    pub chunk_manifest_url: Option<String>,
+    pub draft_download_url: Option<String>,
+    pub draft_checksum: Option<String>,
+}
+
+impl From<&Models> for ModelInfo {
+    fn from(model: &Models) -> Self {
+        Self {
+            name: model.name.clone(),
+            download_url: model.download_url.clone(),
+            checksum: model.checksum.clone(),
+            expected_size: model.expected_size,
+            signature: model.signature.clone(),
+            version: Some(model.version.clone()),
+            chunk_manifest_url: model.chunk_manifest_url.clone(),
+            draft_download_url: model.draft_download_url.clone(),
+            draft_checksum: model.draft_checksum.clone(),
+        }
+    }
+}
+
+impl HotModelClass {
+    /// Model pinned to the client by an operator, overrides the hot model recommendation
+    pub async fn get_pinned_model(&self, client_id: &ClientId) -> Result<Option<ModelInfo>> {
+        let model = sqlx::query_as::<_, ModelInfo>(&format!(
+            "
+            SELECT cm.name, cm.download_url, cm.checksum, cm.expected_size, cm.signature,
+                cm.version, cm.chunk_manifest_url, cm.draft_download_url, cm.draft_checksum
+            FROM {} ga
+            JOIN client_models cm ON cm.name = ga.model AND cm.version = ga.model_version
+            WHERE ga.client_id = $1 AND ga.outo_set_model = false
+            ",
+            GPU_ASSETS_TABLE
+        ))
+        .bind(client_id)
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(model)
+    }
+}
+
+/// Resolve a model version from the catalog, the newest active version when `version` is None
+pub async fn find_model_version(
+    pool: &Pool<Postgres>,
+    name: &str,
+    version: Option<&str>,
+) -> Result<Option<String>> {
+    let version = sqlx::query_scalar::<_, String>(
+        "
+        SELECT version FROM client_models
+        WHERE name = $1 AND ($2::varchar IS NULL OR version = $2)
+          AND ($2::varchar IS NOT NULL OR is_active = true)
+        ORDER BY version_code DESC
+        LIMIT 1
+        ",
+    )
+    .bind(name)
+    .bind(version)
+    .fetch_optional(pool)
+    .await?;
+    Ok(version)
+}
+
+/// Assign the same model to every pod of a client
+pub fn pinned_pods_model(devices_info: &[DevicesInfo], model: &ModelInfo) -> Vec<PodModelV2> {
+    devices_info
+        .iter()
+        .map(|device| PodModelV2 {
+            pod_id: device.pod_id,
+            model_name: Some(model.name.clone()),
+            download_url: model.download_url.clone(),
+            checksum: model.checksum.clone(),
+            expected_size: model.expected_size.map(|s| s as u64),
+            signature: model.signature.clone(),
+            version: model.version.clone(),
+            chunk_manifest_url: model.chunk_manifest_url.clone(),
+            draft_download_url: model.draft_download_url.clone(),
+            draft_checksum: model.draft_checksum.clone(),
+        })
+        .collect()
 }
 
 #[derive(Clone)]
@@ -169,11 +299,13 @@ pub async fn create_or_update_model(
     download_url: Option<String>,
     checksum: Option<String>,
     expected_size: Option<i64>,
+    signature: Option<String>,
+    chunk_manifest_url: Option<String>,
 ) -> Result<()> {
     let _result = sqlx::query(
         "
-        INSERT INTO client_models (name, version, version_code, is_active, min_memory_mb, engine_type, min_gpu_memory_gb, download_url, checksum, expected_size)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        INSERT INTO client_models (name, version, version_code, is_active, min_memory_mb, engine_type, min_gpu_memory_gb, download_url, checksum, expected_size, signature, chunk_manifest_url)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
         ON CONFLICT (name, version) 
         DO UPDATE SET 
             version_code = EXCLUDED.version_code,
@@ -183,7 +315,9 @@ pub async fn create_or_update_model(
             min_gpu_memory_gb = EXCLUDED.min_gpu_memory_gb,
             download_url = EXCLUDED.download_url,
             checksum = EXCLUDED.checksum,
-            expected_size = EXCLUDED.expected_size
+            expected_size = EXCLUDED.expected_size,
+            signature = EXCLUDED.signature,
+            chunk_manifest_url = EXCLUDED.chunk_manifest_url
         RETURNING id
         ",
     )
@@ -197,6 +331,8 @@ pub async fn create_or_update_model(
     .bind(download_url)
     .bind(checksum)
     .bind(expected_size)
+    .bind(signature)
+    .bind(chunk_manifest_url)
     .fetch_one(pool)
     .await
     .map_err(|e| {
@@ -206,12 +342,13 @@ pub async fn create_or_update_model(
     Ok(())
 }
 
-#[derive(sqlx::FromRow)]
+#[derive(sqlx::FromRow, Serialize)]
 pub struct Models {
     pub id: i32,
     pub name: String,
     pub version: String,
     pub version_code: i64,
+    pub engine_type: i16,
     pub is_active: bool,
     pub min_memory_mb: Option<i32>,
     pub min_gpu_memory_gb: Option<i32>,
@@ -219,6 +356,73 @@ pub struct Models {
     pub download_url: Option<String>,
     pub checksum: Option<String>,
     pub expected_size: Option<i64>,
+    pub signature: Option<String>,
+    pub chunk_manifest_url: Option<String>,
+    pub draft_download_url: Option<String>,
+    pub draft_checksum: Option<String>,
+    /// stable, canary or rolled_back, see `RolloutState`
+    pub rollout_state: String,
+    pub rollout_percent: i16,
+    /// Client ids, in hex, a canary is always assigned to
+    pub rollout_allowlist: Vec<String>,
+}
+
+impl Models {
+    /// Whether the hot model recommendation may offer this entry to the client
+    pub fn assignable_to(&self, client_id: &ClientId) -> bool {
+        match RolloutState::parse(&self.rollout_state) {
+            Some(RolloutState::Stable) => true,
+            Some(RolloutState::Canary) => {
+                let hex = client_id.to_string();
+                self.rollout_allowlist
+                    .iter()
+                    .any(|c| c.eq_ignore_ascii_case(&hex))
+                    || (rollout_bucket(client_id, self.id) as i16) < self.rollout_percent
+            }
+            Some(RolloutState::RolledBack) | None => false,
+        }
+    }
+}
+
+/// Stable bucket 0 to 99 of a client for one catalog entry, so raising the
+/// percentage of a canary keeps the workers that already have it
+pub fn rollout_bucket(client_id: &ClientId, model_id: i32) -> u8 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&client_id.0[..8]);
+    let hash = u64::from_le_bytes(bytes)
+        .wrapping_add(model_id as u64)
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    ((hash >> 32) % 100) as u8
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RolloutState {
+    /// Assigned to every eligible worker
+    Stable,
+    /// Assigned to a share of eligible workers and an allowlist
+    Canary,
+    /// Assigned to no worker, after too many failed loads
+    RolledBack,
+}
+
+impl RolloutState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Stable => "stable",
+            Self::Canary => "canary",
+            Self::RolledBack => "rolled_back",
+        }
+    }
+
+    pub fn parse(state: &str) -> Option<Self> {
+        match state {
+            "stable" => Some(Self::Stable),
+            "canary" => Some(Self::Canary),
+            "rolled_back" => Some(Self::RolledBack),
+            _ => None,
+        }
+    }
 }
 
 pub async fn get_models_list(
@@ -228,7 +432,7 @@ pub async fn get_models_list(
     min_gpu_memory_gb: Option<i32>,
 ) -> Result<Vec<Models>> {
     debug!("get_models_list is_active: {:?}, engine_type: {:?}, min_gpu_memory_gb: {:?}", is_active, engine_type, min_gpu_memory_gb);
-    let mut query_builder = sqlx::QueryBuilder::new("SELECT id,name,version,version_code,is_active,min_memory_mb,min_gpu_memory_gb,created_at,download_url,checksum,expected_size FROM client_models WHERE 1=1");
+    let mut query_builder = sqlx::QueryBuilder::new("SELECT id,name,version,version_code,engine_type,is_active,min_memory_mb,min_gpu_memory_gb,created_at,download_url,checksum,expected_size,signature,chunk_manifest_url,draft_download_url,draft_checksum,rollout_state,rollout_percent,rollout_allowlist FROM client_models WHERE 1=1");
 
     if let Some(active) = is_active {
         query_builder.push(" AND is_active = ").push_bind(active);
@@ -254,10 +458,291 @@ pub async fn get_models_list(
     Ok(models)
 }
 
+const MODEL_COLUMNS: &str = "id, name, version, version_code, engine_type, is_active, \
+     min_memory_mb, min_gpu_memory_gb, created_at, download_url, checksum, expected_size, \
+     signature, chunk_manifest_url, draft_download_url, draft_checksum, rollout_state, \
+     rollout_percent, rollout_allowlist";
+
+/// Catalog entry as written by the admin API
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelSpec {
+    pub name: String,
+    pub version: String,
+    pub version_code: i64,
+    pub engine_type: i16,
+    /// Defaults to active on create, left unchanged on update when omitted
+    pub is_active: Option<bool>,
+    pub min_memory_mb: Option<i32>,
+    pub min_gpu_memory_gb: Option<i32>,
+    pub download_url: Option<String>,
+    pub checksum: Option<String>,
+    pub expected_size: Option<i64>,
+    pub signature: Option<String>,
+    pub chunk_manifest_url: Option<String>,
+    /// Draft model for speculative decoding, a GGUF with the same vocabulary
+    pub draft_download_url: Option<String>,
+    /// SHA-256 of the draft model file
+    pub draft_checksum: Option<String>,
+}
+
+/// Filters of the admin model listing, all optional
+#[derive(Debug, Default, Deserialize)]
+pub struct ModelFilter {
+    /// Substring of the model name, case insensitive
+    pub name: Option<String>,
+    pub is_active: Option<bool>,
+    pub engine_type: Option<i16>,
+    /// Only models that fit in this much GPU memory
+    pub max_gpu_memory_gb: Option<i32>,
+}
+
+pub async fn get_model(pool: &Pool<Postgres>, id: i32) -> Result<Option<Models>, sqlx::Error> {
+    sqlx::query_as::<_, Models>(&format!(
+        "SELECT {} FROM client_models WHERE id = $1",
+        MODEL_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Sort and filter fields of catalog listings
+pub static MODEL_LIST: ListSpec = ListSpec {
+    fields: &[
+        ListField {
+            name: "name",
+            expr: "l.name",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Contains),
+        },
+        ListField {
+            name: "version",
+            expr: "l.version",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "version_code",
+            expr: "l.version_code",
+            sql_type: "bigint",
+            sortable: true,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "engine_type",
+            expr: "l.engine_type",
+            sql_type: "smallint",
+            sortable: true,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "is_active",
+            expr: "l.is_active",
+            sql_type: "boolean",
+            sortable: false,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "min_gpu_memory_gb",
+            expr: "COALESCE(l.min_gpu_memory_gb, 0)",
+            sql_type: "integer",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "rollout_state",
+            expr: "l.rollout_state",
+            sql_type: "text",
+            sortable: false,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "created_at",
+            expr: "l.created_at",
+            sql_type: "timestamptz",
+            sortable: true,
+            filter: None,
+        },
+    ],
+    default_sort: "name",
+    // Padded so the text order is the numeric order
+    key: "lpad(l.id::text, 10, '0')",
+};
+
+pub async fn list_models(
+    pool: &Pool<Postgres>,
+    filter: &ModelFilter,
+    list: &ListQuery,
+) -> Result<Page<Models>, sqlx::Error> {
+    list.fetch(pool, |query_builder| {
+        query_builder.push(format!(
+            "SELECT {} FROM client_models WHERE 1=1",
+            MODEL_COLUMNS
+        ));
+        if let Some(name) = &filter.name {
+            query_builder
+                .push(" AND name ILIKE ")
+                .push_bind(format!("%{}%", name));
+        }
+        if let Some(active) = filter.is_active {
+            query_builder.push(" AND is_active = ").push_bind(active);
+        }
+        if let Some(engine_type) = filter.engine_type {
+            query_builder
+                .push(" AND engine_type = ")
+                .push_bind(engine_type);
+        }
+        if let Some(mem) = filter.max_gpu_memory_gb {
+            query_builder
+                .push(" AND (min_gpu_memory_gb IS NULL OR min_gpu_memory_gb <= ")
+                .push_bind(mem)
+                .push(")");
+        }
+    })
+    .await
+}
+
+/// Insert a catalog entry, fails with a unique violation when the name and
+/// version or version code already exist
+pub async fn insert_model(pool: &Pool<Postgres>, spec: &ModelSpec) -> Result<Models, sqlx::Error> {
+    sqlx::query_as::<_, Models>(&format!(
+        "
+        INSERT INTO client_models (name, version, version_code, engine_type, is_active,
+            min_memory_mb, min_gpu_memory_gb, download_url, checksum, expected_size,
+            signature, chunk_manifest_url, draft_download_url, draft_checksum)
+        VALUES ($1, $2, $3, $4, COALESCE($5, true), $6, $7, $8, $9, $10, $11, $12, $13, $14)
+        RETURNING {}
+        ",
+        MODEL_COLUMNS
+    ))
+    .bind(&spec.name)
+    .bind(&spec.version)
+    .bind(spec.version_code)
+    .bind(spec.engine_type)
+    .bind(spec.is_active)
+    .bind(spec.min_memory_mb)
+    .bind(spec.min_gpu_memory_gb)
+    .bind(&spec.download_url)
+    .bind(&spec.checksum)
+    .bind(spec.expected_size)
+    .bind(&spec.signature)
+    .bind(&spec.chunk_manifest_url)
+    .bind(&spec.draft_download_url)
+    .bind(&spec.draft_checksum)
+    .fetch_one(pool)
+    .await
+}
+
+/// Replace a catalog entry, None when it does not exist
+pub async fn update_model(
+    pool: &Pool<Postgres>,
+    id: i32,
+    spec: &ModelSpec,
+) -> Result<Option<Models>, sqlx::Error> {
+    sqlx::query_as::<_, Models>(&format!(
+        "
+        UPDATE client_models SET name = $2, version = $3, version_code = $4, engine_type = $5,
+            is_active = COALESCE($6, is_active), min_memory_mb = $7, min_gpu_memory_gb = $8,
+            download_url = $9, checksum = $10, expected_size = $11, signature = $12,
+            chunk_manifest_url = $13, draft_download_url = $14, draft_checksum = $15
+        WHERE id = $1
+        RETURNING {}
+        ",
+        MODEL_COLUMNS
+    ))
+    .bind(id)
+    .bind(&spec.name)
+    .bind(&spec.version)
+    .bind(spec.version_code)
+    .bind(spec.engine_type)
+    .bind(spec.is_active)
+    .bind(spec.min_memory_mb)
+    .bind(spec.min_gpu_memory_gb)
+    .bind(&spec.download_url)
+    .bind(&spec.checksum)
+    .bind(spec.expected_size)
+    .bind(&spec.signature)
+    .bind(&spec.chunk_manifest_url)
+    .bind(&spec.draft_download_url)
+    .bind(&spec.draft_checksum)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn set_model_active(
+    pool: &Pool<Postgres>,
+    id: i32,
+    is_active: bool,
+) -> Result<Option<Models>, sqlx::Error> {
+    sqlx::query_as::<_, Models>(&format!(
+        "UPDATE client_models SET is_active = $2 WHERE id = $1 RETURNING {}",
+        MODEL_COLUMNS
+    ))
+    .bind(id)
+    .bind(is_active)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn delete_model(pool: &Pool<Postgres>, id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM client_models WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Clients an operator pinned to this model version
+pub async fn count_pinned_clients(
+    pool: &Pool<Postgres>,
+    name: &str,
+    version: &str,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(&format!(
+        "SELECT COUNT(*) FROM {} WHERE outo_set_model = false AND model = $1 AND model_version = $2",
+        GPU_ASSETS_TABLE
+    ))
+    .bind(name)
+    .bind(version)
+    .fetch_one(pool)
+    .await
+}
+
+/// Approved, valid clients grouped by reported device memory
+#[derive(Debug, Clone, Copy, sqlx::FromRow, Serialize)]
+pub struct MemoryProfile {
+    pub memory_gb: i64,
+    pub clients: i64,
+    pub online: i64,
+}
+
+pub async fn fleet_memory_profiles(
+    pool: &Pool<Postgres>,
+) -> Result<Vec<MemoryProfile>, sqlx::Error> {
+    sqlx::query_as::<_, MemoryProfile>(&format!(
+        "
+        SELECT COALESCE(si.device_memsize, 0) AS memory_gb,
+            COUNT(*) AS clients,
+            COUNT(*) FILTER (WHERE ga.client_status = 'online') AS online
+        FROM {} ga
+        LEFT JOIN system_info si ON si.client_id = ga.client_id
+        WHERE ga.valid_status = 'valid' AND ga.approval_status = 'approved'
+        GROUP BY 1
+        ORDER BY 1
+        ",
+        GPU_ASSETS_TABLE
+    ))
+    .fetch_all(pool)
+    .await
+}
+
 pub async fn get_models_batch(
     hot_models: &Arc<HotModelClass>,
+    client_id: &ClientId,
     devices_info: &Vec<DevicesInfo>,
-) -> Result<Vec<PodModel>> {
+) -> Result<Vec<PodModelV2>> {
     let mut pod_model = Vec::new();
 
     for device_info in devices_info {
@@ -265,17 +750,23 @@ pub async fn get_models_batch(
             || device_info.os_type == OsType::NONE
             || device_info.memtotal_gb == 0
         {
-            pod_model.push(PodModel {
+            pod_model.push(PodModelV2 {
                 pod_id: device_info.pod_id,
                 model_name: None,
                 download_url: None,
                 checksum: None,
                 expected_size: None,
+                signature: None,
+                version: None,
+                chunk_manifest_url: None,
+                draft_download_url: None,
+                draft_checksum: None,
             });
             continue;
         }
         match hot_models
             .get_hot_model_with_details(
+                client_id,
                 device_info.memtotal_gb as u32,
                 device_info.engine_type.to_i16(),
             )
@@ -283,31 +774,46 @@ pub async fn get_models_batch(
         {
             Ok(model_info) => {
                 if model_info.name.is_empty() {
-                    pod_model.push(PodModel {
+                    pod_model.push(PodModelV2 {
                         pod_id: device_info.pod_id,
                         model_name: None,
                         download_url: None,
                         checksum: None,
                         expected_size: None,
+                        signature: None,
+                        version: None,
+                        chunk_manifest_url: None,
+                        draft_download_url: None,
+                        draft_checksum: None,
                     });
                 } else {
-                    pod_model.push(PodModel {
+                    pod_model.push(PodModelV2 {
                         pod_id: device_info.pod_id,
                         model_name: Some(model_info.name),
                         download_url: model_info.download_url,
                         checksum: model_info.checksum,
                         expected_size: model_info.expected_size.map(|s| s as u64),
+                        signature: model_info.signature,
+                        version: model_info.version,
+                        chunk_manifest_url: model_info.chunk_manifest_url,
+                        draft_download_url: model_info.draft_download_url,
+                        draft_checksum: model_info.draft_checksum,
                     });
                 }
             }
             Err(e) => {
                 warn!("Failed to get hot model: {}", e);
-                pod_model.push(PodModel {
+                pod_model.push(PodModelV2 {
                     pod_id: device_info.pod_id,
                     model_name: None,
                     download_url: None,
                     checksum: None,
                     expected_size: None,
+                    signature: None,
+                    version: None,
+                    chunk_manifest_url: None,
+                    draft_download_url: None,
+                    draft_checksum: None,
                 });
             }
         }
@@ -0,0 +1,330 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Postgres, QueryBuilder};
+
+use crate::db::{GPU_ASSETS_TABLE, NOTIFICATION_DELIVERIES_TABLE, NOTIFICATION_PREFERENCES_TABLE};
+use crate::util::protoc::ClientId;
+
+/// Deliveries that failed this often are given up
+pub const MAX_DELIVERY_ATTEMPTS: i32 = 5;
+/// Claimed deliveries are not claimed again for this long, in case the
+/// instance sending them dies
+const CLAIM_LEASE_SECS: i64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+    DeviceOffline,
+    PayoutProcessed,
+    ModelAssigned,
+}
+
+impl NotificationEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::DeviceOffline => "device_offline",
+            Self::PayoutProcessed => "payout_processed",
+            Self::ModelAssigned => "model_assigned",
+        }
+    }
+
+    pub fn parse(event: &str) -> Option<Self> {
+        match event {
+            "device_offline" => Some(Self::DeviceOffline),
+            "payout_processed" => Some(Self::PayoutProcessed),
+            "model_assigned" => Some(Self::ModelAssigned),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationChannel {
+    Email,
+    Webhook,
+    Push,
+}
+
+impl NotificationChannel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Email => "email",
+            Self::Webhook => "webhook",
+            Self::Push => "push",
+        }
+    }
+
+    pub fn parse(channel: &str) -> Option<Self> {
+        match channel {
+            "email" => Some(Self::Email),
+            "webhook" => Some(Self::Webhook),
+            "push" => Some(Self::Push),
+            _ => None,
+        }
+    }
+
+    /// Whether `target` is an address this channel can deliver to
+    pub fn accepts(self, target: &str) -> bool {
+        if target.is_empty() || target.len() > 512 || target.contains(char::is_whitespace) {
+            return false;
+        }
+        match self {
+            Self::Email => target
+                .split_once('@')
+                .is_some_and(|(user, domain)| !user.is_empty() && domain.contains('.')),
+            Self::Webhook => target.starts_with("https://") || target.starts_with("http://"),
+            Self::Push => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Preference {
+    pub event_type: NotificationEvent,
+    pub channel: NotificationChannel,
+    /// Email address, webhook URL or push token
+    pub target: String,
+    /// Collect notifications and send them together
+    #[serde(default)]
+    pub digest: bool,
+}
+
+#[derive(FromRow)]
+struct PreferenceRow {
+    event_type: String,
+    channel: String,
+    target: String,
+    digest: bool,
+}
+
+pub async fn get_preferences(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+) -> Result<Vec<Preference>, sqlx::Error> {
+    let rows: Vec<PreferenceRow> = sqlx::query_as(&format!(
+        "SELECT event_type, channel, target, digest FROM {} \
+         WHERE user_id = $1 ORDER BY event_type, channel",
+        NOTIFICATION_PREFERENCES_TABLE
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    // Rows of event types or channels this version does not know are skipped
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(Preference {
+                event_type: NotificationEvent::parse(&row.event_type)?,
+                channel: NotificationChannel::parse(&row.channel)?,
+                target: row.target,
+                digest: row.digest,
+            })
+        })
+        .collect())
+}
+
+/// Replace all preferences of a user
+pub async fn replace_preferences(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+    preferences: &[Preference],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!(
+        "DELETE FROM {} WHERE user_id = $1",
+        NOTIFICATION_PREFERENCES_TABLE
+    ))
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    if !preferences.is_empty() {
+        let mut query_builder = QueryBuilder::<Postgres>::new("INSERT INTO ");
+        query_builder
+            .push(NOTIFICATION_PREFERENCES_TABLE)
+            .push(" (user_id, event_type, channel, target, digest) ");
+        query_builder.push_values(preferences, |mut b, p| {
+            b.push_bind(user_id)
+                .push_bind(p.event_type.as_str())
+                .push_bind(p.channel.as_str())
+                .push_bind(&p.target)
+                .push_bind(p.digest);
+        });
+        query_builder.build().execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Queue a notification for every preference of the user for `event`.
+/// Returns the deliveries queued.
+pub async fn notify_user(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+    event: NotificationEvent,
+    subject: &str,
+    body: &str,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(&format!(
+        "INSERT INTO {} (user_id, event_type, channel, target, digest, subject, body) \
+         SELECT user_id, event_type, channel, target, digest, $3, $4 FROM {} \
+         WHERE user_id = $1 AND event_type = $2",
+        NOTIFICATION_DELIVERIES_TABLE, NOTIFICATION_PREFERENCES_TABLE
+    ))
+    .bind(user_id)
+    .bind(event.as_str())
+    .bind(subject)
+    .bind(body)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Like [`notify_user`] for the owners of the clients
+pub async fn notify_client_owners(
+    pool: &Pool<Postgres>,
+    client_ids: &[ClientId],
+    event: NotificationEvent,
+    subject: &str,
+    body: &str,
+) -> Result<u64, sqlx::Error> {
+    if client_ids.is_empty() {
+        return Ok(0);
+    }
+    let result = sqlx::query(&format!(
+        "INSERT INTO {} (user_id, event_type, channel, target, digest, subject, body) \
+         SELECT p.user_id, p.event_type, p.channel, p.target, p.digest, $3, $4 FROM {} p \
+         WHERE p.event_type = $2 \
+         AND p.user_id IN (SELECT user_id FROM {} WHERE client_id = ANY($1))",
+        NOTIFICATION_DELIVERIES_TABLE, NOTIFICATION_PREFERENCES_TABLE, GPU_ASSETS_TABLE
+    ))
+    .bind(client_ids)
+    .bind(event.as_str())
+    .bind(subject)
+    .bind(body)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct Delivery {
+    pub id: i64,
+    pub user_id: String,
+    pub event_type: String,
+    pub channel: String,
+    pub target: String,
+    pub subject: String,
+    pub body: String,
+    pub attempts: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+const DELIVERY_COLUMNS: &str =
+    "id, user_id, event_type, channel, target, subject, body, attempts, created_at";
+
+/// Claim due deliveries that are sent one by one
+pub async fn claim_immediate(
+    pool: &Pool<Postgres>,
+    limit: i64,
+) -> Result<Vec<Delivery>, sqlx::Error> {
+    sqlx::query_as(&format!(
+        "UPDATE {table} SET attempts = attempts + 1, \
+         next_attempt_at = NOW() + $2 * INTERVAL '1 second' \
+         WHERE id IN (SELECT id FROM {table} \
+             WHERE NOT digest AND sent_at IS NULL AND failed_at IS NULL \
+             AND next_attempt_at <= NOW() \
+             ORDER BY id LIMIT $1 FOR UPDATE SKIP LOCKED) \
+         RETURNING {columns}",
+        table = NOTIFICATION_DELIVERIES_TABLE,
+        columns = DELIVERY_COLUMNS
+    ))
+    .bind(limit)
+    .bind(CLAIM_LEASE_SECS)
+    .fetch_all(pool)
+    .await
+}
+
+/// Claim the digest deliveries of every user, channel and target whose
+/// oldest pending notification waited `digest_secs`
+pub async fn claim_digests(
+    pool: &Pool<Postgres>,
+    digest_secs: i64,
+) -> Result<Vec<Delivery>, sqlx::Error> {
+    sqlx::query_as(&format!(
+        "UPDATE {table} SET attempts = attempts + 1, \
+         next_attempt_at = NOW() + $2 * INTERVAL '1 second' \
+         WHERE id IN (SELECT id FROM {table} \
+             WHERE digest AND sent_at IS NULL AND failed_at IS NULL \
+             AND next_attempt_at <= NOW() \
+             AND (user_id, channel, target) IN ( \
+                 SELECT user_id, channel, target FROM {table} \
+                 WHERE digest AND sent_at IS NULL AND failed_at IS NULL \
+                 GROUP BY user_id, channel, target \
+                 HAVING MIN(created_at) <= NOW() - $1 * INTERVAL '1 second') \
+             FOR UPDATE SKIP LOCKED) \
+         RETURNING {columns}",
+        table = NOTIFICATION_DELIVERIES_TABLE,
+        columns = DELIVERY_COLUMNS
+    ))
+    .bind(digest_secs)
+    .bind(CLAIM_LEASE_SECS)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn mark_sent(pool: &Pool<Postgres>, ids: &[i64]) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "UPDATE {} SET sent_at = NOW(), last_error = NULL WHERE id = ANY($1)",
+        NOTIFICATION_DELIVERIES_TABLE
+    ))
+    .bind(ids)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Record a failed attempt. Deliveries out of attempts are given up, the
+/// others are retried with a growing delay.
+pub async fn mark_failed(
+    pool: &Pool<Postgres>,
+    ids: &[i64],
+    error: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "UPDATE {} SET last_error = $2, \
+         failed_at = CASE WHEN attempts >= $3 THEN NOW() END, \
+         next_attempt_at = NOW() + attempts * attempts * INTERVAL '1 minute' \
+         WHERE id = ANY($1)",
+        NOTIFICATION_DELIVERIES_TABLE
+    ))
+    .bind(ids)
+    .bind(error)
+    .bind(MAX_DELIVERY_ATTEMPTS)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[test]
+fn test_notification_targets() {
+    use NotificationChannel::*;
+
+    assert!(Email.accepts("ops@example.com"));
+    assert!(!Email.accepts("ops@localhost"));
+    assert!(!Email.accepts("@example.com"));
+    assert!(!Email.accepts("ops @example.com"));
+    assert!(Webhook.accepts("https://hooks.example.com/gpuf"));
+    assert!(!Webhook.accepts("ftp://example.com"));
+    assert!(Push.accepts("fcm:abc123"));
+    assert!(!Push.accepts(""));
+
+    let preference: Preference = serde_json::from_value(serde_json::json!({
+        "event_type": "device_offline",
+        "channel": "webhook",
+        "target": "https://hooks.example.com/gpuf",
+    }))
+    .unwrap();
+    assert_eq!(preference.event_type, NotificationEvent::DeviceOffline);
+    assert!(!preference.digest);
+}
@@ -0,0 +1,322 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Postgres};
+
+use crate::db::{GPU_ASSETS_TABLE, ORGANIZATIONS_TABLE, ORGANIZATION_MEMBERS_TABLE, TOKENS_TABLE};
+use crate::util::protoc::ClientId;
+
+/// Role of a user in an organization, ordered by what it may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrgRole {
+    /// Sees the organization's workers, keys and usage
+    Member,
+    /// Also manages members and moves keys and workers in and out
+    Admin,
+    /// Also manages admins and owners
+    Owner,
+}
+
+impl OrgRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Member => "member",
+            Self::Admin => "admin",
+            Self::Owner => "owner",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "member" => Some(Self::Member),
+            "admin" => Some(Self::Admin),
+            "owner" => Some(Self::Owner),
+            _ => None,
+        }
+    }
+
+    /// Whether a user with this role may change a member's role from
+    /// `current` to `new`, None being no membership. Admins may not touch
+    /// owners or make new ones.
+    pub fn can_change(self, current: Option<OrgRole>, new: Option<OrgRole>) -> bool {
+        match self {
+            Self::Owner => true,
+            Self::Admin => current.max(new) < Some(Self::Owner),
+            Self::Member => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct Organization {
+    pub id: i64,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An organization as one of its members sees it
+#[derive(Debug, Clone, Serialize)]
+pub struct Membership {
+    pub org_id: i64,
+    pub name: String,
+    pub role: OrgRole,
+    pub joined_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Member {
+    pub user_id: String,
+    pub role: OrgRole,
+    pub joined_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct MembershipRow {
+    org_id: i64,
+    name: String,
+    role: String,
+    joined_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct MemberRow {
+    user_id: String,
+    role: String,
+    joined_at: DateTime<Utc>,
+}
+
+/// Create an organization with `owner` as its first owner
+pub async fn create_org(
+    pool: &Pool<Postgres>,
+    name: &str,
+    owner: &str,
+) -> Result<Organization, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let org: Organization = sqlx::query_as(&format!(
+        "INSERT INTO {} (name) VALUES ($1) RETURNING id, name, created_at",
+        ORGANIZATIONS_TABLE
+    ))
+    .bind(name)
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query(&format!(
+        "INSERT INTO {} (org_id, user_id, role) VALUES ($1, $2, $3)",
+        ORGANIZATION_MEMBERS_TABLE
+    ))
+    .bind(org.id)
+    .bind(owner)
+    .bind(OrgRole::Owner.as_str())
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(org)
+}
+
+/// Organizations the user belongs to, oldest membership first
+pub async fn list_user_orgs(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+) -> Result<Vec<Membership>, sqlx::Error> {
+    let rows: Vec<MembershipRow> = sqlx::query_as(&format!(
+        "SELECT o.id AS org_id, o.name, m.role, m.created_at AS joined_at \
+         FROM {} m JOIN {} o ON o.id = m.org_id \
+         WHERE m.user_id = $1 ORDER BY m.created_at, o.id",
+        ORGANIZATION_MEMBERS_TABLE, ORGANIZATIONS_TABLE
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(Membership {
+                org_id: row.org_id,
+                name: row.name,
+                role: OrgRole::parse(&row.role)?,
+                joined_at: row.joined_at,
+            })
+        })
+        .collect())
+}
+
+/// Role of the user in the organization, None when not a member
+pub async fn get_role(
+    pool: &Pool<Postgres>,
+    org_id: i64,
+    user_id: &str,
+) -> Result<Option<OrgRole>, sqlx::Error> {
+    let role: Option<String> = sqlx::query_scalar(&format!(
+        "SELECT role FROM {} WHERE org_id = $1 AND user_id = $2",
+        ORGANIZATION_MEMBERS_TABLE
+    ))
+    .bind(org_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(role.as_deref().and_then(OrgRole::parse))
+}
+
+pub async fn list_members(pool: &Pool<Postgres>, org_id: i64) -> Result<Vec<Member>, sqlx::Error> {
+    let rows: Vec<MemberRow> = sqlx::query_as(&format!(
+        "SELECT user_id, role, created_at AS joined_at FROM {} \
+         WHERE org_id = $1 ORDER BY created_at, user_id",
+        ORGANIZATION_MEMBERS_TABLE
+    ))
+    .bind(org_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(Member {
+                user_id: row.user_id,
+                role: OrgRole::parse(&row.role)?,
+                joined_at: row.joined_at,
+            })
+        })
+        .collect())
+}
+
+/// Add a member or change its role
+pub async fn set_member(
+    pool: &Pool<Postgres>,
+    org_id: i64,
+    user_id: &str,
+    role: OrgRole,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "INSERT INTO {} (org_id, user_id, role) VALUES ($1, $2, $3) \
+         ON CONFLICT (org_id, user_id) DO UPDATE SET role = EXCLUDED.role",
+        ORGANIZATION_MEMBERS_TABLE
+    ))
+    .bind(org_id)
+    .bind(user_id)
+    .bind(role.as_str())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns false when the user was not a member
+pub async fn remove_member(
+    pool: &Pool<Postgres>,
+    org_id: i64,
+    user_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(&format!(
+        "DELETE FROM {} WHERE org_id = $1 AND user_id = $2",
+        ORGANIZATION_MEMBERS_TABLE
+    ))
+    .bind(org_id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn count_owners(pool: &Pool<Postgres>, org_id: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM {} WHERE org_id = $1 AND role = $2",
+        ORGANIZATION_MEMBERS_TABLE
+    ))
+    .bind(org_id)
+    .bind(OrgRole::Owner.as_str())
+    .fetch_one(pool)
+    .await
+}
+
+/// Whether `user_id` owns the valid worker, or is a member of the
+/// organization it belongs to
+pub async fn can_see_client(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+    user_id: &str,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(&format!(
+        "SELECT EXISTS (SELECT 1 FROM {} ga \
+         WHERE ga.client_id = $1 AND ga.valid_status = 'valid' AND (ga.user_id = $2 \
+         OR ga.org_id IN (SELECT org_id FROM {} WHERE user_id = $2)))",
+        GPU_ASSETS_TABLE, ORGANIZATION_MEMBERS_TABLE
+    ))
+    .bind(client_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Move an API key of `user_id` into the organization when `join`, or out
+/// of it. Keys of another organization are not taken over. Returns the
+/// organization the key was in, None when the user has no such key.
+pub async fn move_key(
+    pool: &Pool<Postgres>,
+    key_id: i64,
+    user_id: &str,
+    org_id: i64,
+    join: bool,
+) -> Result<Option<Option<i64>>, sqlx::Error> {
+    let (org, condition) = if join {
+        ("$3", "(t.org_id IS NULL OR t.org_id = $3)")
+    } else {
+        ("NULL", "t.org_id = $3")
+    };
+    sqlx::query_scalar(&format!(
+        "UPDATE {table} t SET org_id = {} FROM {table} old \
+         WHERE old.id = t.id AND t.id = $1 AND t.user_id::text = $2 \
+         AND t.deleted_at IS NULL AND {} \
+         RETURNING old.org_id",
+        org,
+        condition,
+        table = TOKENS_TABLE
+    ))
+    .bind(key_id)
+    .bind(user_id)
+    .bind(org_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Like [`move_key`] for a worker of `user_id`
+pub async fn move_client(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+    user_id: &str,
+    org_id: i64,
+    join: bool,
+) -> Result<Option<Option<i64>>, sqlx::Error> {
+    let (org, condition) = if join {
+        ("$3", "(ga.org_id IS NULL OR ga.org_id = $3)")
+    } else {
+        ("NULL", "ga.org_id = $3")
+    };
+    sqlx::query_scalar(&format!(
+        "UPDATE {table} ga SET org_id = {} FROM {table} old \
+         WHERE old.client_id = ga.client_id AND ga.client_id = $1 AND ga.user_id = $2 AND {} \
+         RETURNING old.org_id",
+        org,
+        condition,
+        table = GPU_ASSETS_TABLE
+    ))
+    .bind(client_id)
+    .bind(user_id)
+    .bind(org_id)
+    .fetch_optional(pool)
+    .await
+}
+
+#[test]
+fn test_role_changes() {
+    use OrgRole::*;
+
+    assert_eq!(OrgRole::parse("admin"), Some(Admin));
+    assert_eq!(OrgRole::parse("Owner"), None);
+    assert!(Member < Admin && Admin < Owner);
+
+    assert!(Owner.can_change(Some(Owner), None));
+    assert!(Owner.can_change(None, Some(Owner)));
+    assert!(Admin.can_change(None, Some(Admin)));
+    assert!(Admin.can_change(Some(Admin), Some(Member)));
+    assert!(Admin.can_change(Some(Member), None));
+    assert!(!Admin.can_change(Some(Owner), Some(Admin)));
+    assert!(!Admin.can_change(Some(Member), Some(Owner)));
+    assert!(!Member.can_change(None, Some(Member)));
+}
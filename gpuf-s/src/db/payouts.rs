@@ -0,0 +1,503 @@
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Postgres, QueryBuilder};
+use std::collections::BTreeMap;
+use tracing::warn;
+
+use crate::db::{
+    CLIENT_DAILY_STATS_TABLE, CLIENT_MODELS_TABLE, DEVICE_POINTS_DAILY_VIEW,
+    PAYOUT_RATE_CARDS_TABLE, PAYOUT_STATEMENTS_TABLE,
+};
+
+/// Rate card column matching every tier or model class
+pub const ANY: &str = "*";
+
+/// Money is counted in millionths of the currency unit, the scale of the
+/// NUMERIC columns it is stored in, so no amount goes through a float
+pub const MICROS_PER_UNIT: i64 = 1_000_000;
+/// Points are priced in ten-thousandths, the scale of `payout_statements.points`
+pub const POINTS_SCALE: i64 = 10_000;
+
+/// `micros` as an amount with two decimals, e.g. 30.65
+pub fn format_micros(micros: i64) -> String {
+    let cents = (micros + 5_000) / 10_000;
+    format!("{}.{:02}", cents / 100, cents % 100)
+}
+
+/// Performance tier of a device, from its TFLOPS in `device_types`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuTier {
+    Entry,
+    Mid,
+    High,
+    Datacenter,
+}
+
+impl GpuTier {
+    pub fn from_tflops(tflops: Option<f64>) -> Self {
+        match tflops.unwrap_or(0.0) {
+            t if t >= 200.0 => Self::Datacenter,
+            t if t >= 40.0 => Self::High,
+            t if t >= 10.0 => Self::Mid,
+            _ => Self::Entry,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Entry => "entry",
+            Self::Mid => "mid",
+            Self::High => "high",
+            Self::Datacenter => "datacenter",
+        }
+    }
+
+    pub fn parse(tier: &str) -> Option<Self> {
+        match tier {
+            "entry" => Some(Self::Entry),
+            "mid" => Some(Self::Mid),
+            "high" => Some(Self::High),
+            "datacenter" => Some(Self::Datacenter),
+            _ => None,
+        }
+    }
+}
+
+/// Size class of the model a worker serves, from the GPU memory the catalog asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelClass {
+    /// No model set on the worker
+    None,
+    Small,
+    Medium,
+    Large,
+}
+
+impl ModelClass {
+    /// `min_gpu_memory_gb` is None when the worker serves no model, and 0
+    /// when the model is missing from the catalog or sets no minimum.
+    pub fn from_min_gpu_memory_gb(min_gpu_memory_gb: Option<i32>) -> Self {
+        match min_gpu_memory_gb {
+            None => Self::None,
+            Some(gb) if gb > 16 => Self::Large,
+            Some(gb) if gb > 8 => Self::Medium,
+            Some(_) => Self::Small,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Small => "small",
+            Self::Medium => "medium",
+            Self::Large => "large",
+        }
+    }
+
+    pub fn parse(class: &str) -> Option<Self> {
+        match class {
+            "none" => Some(Self::None),
+            "small" => Some(Self::Small),
+            "medium" => Some(Self::Medium),
+            "large" => Some(Self::Large),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct RateCard {
+    /// Tier name or `*`
+    pub gpu_tier: String,
+    /// Model class name or `*`
+    pub model_class: String,
+    /// Amount paid per point, in millionths of the statement currency
+    pub rate_per_point_micros: i64,
+}
+
+impl RateCard {
+    /// Rate for points earned by `tier` serving `class`. The most specific
+    /// card wins, a tier specific card before a model class specific one.
+    pub fn rate_for(cards: &[RateCard], tier: GpuTier, class: ModelClass) -> Option<i64> {
+        let candidates = [
+            (tier.as_str(), class.as_str()),
+            (tier.as_str(), ANY),
+            (ANY, class.as_str()),
+            (ANY, ANY),
+        ];
+        candidates.iter().find_map(|(tier, class)| {
+            cards
+                .iter()
+                .find(|card| card.gpu_tier == *tier && card.model_class == *class)
+                .map(|card| card.rate_per_point_micros)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutStatus {
+    Pending,
+    Approved,
+    Paid,
+}
+
+impl PayoutStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Paid => "paid",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "pending" => Some(Self::Pending),
+            "approved" => Some(Self::Approved),
+            "paid" => Some(Self::Paid),
+            _ => None,
+        }
+    }
+
+    /// Statements only move forward, pending to approved to paid
+    pub fn can_become(&self, next: Self) -> bool {
+        matches!(
+            (self, next),
+            (Self::Pending, Self::Approved) | (Self::Approved, Self::Paid)
+        )
+    }
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct PayoutStatement {
+    pub id: i64,
+    pub user_id: String,
+    pub month: NaiveDate,
+    pub points: f64,
+    /// In millionths of `currency`
+    pub amount_micros: i64,
+    pub currency: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub approved_at: Option<DateTime<Utc>>,
+    pub paid_at: Option<DateTime<Utc>>,
+}
+
+/// Points a user's devices of one tier earned serving one model class
+#[derive(Debug, Clone, FromRow)]
+pub struct MonthlyPoints {
+    pub user_id: String,
+    pub tflops: Option<f64>,
+    pub min_gpu_memory_gb: Option<i32>,
+    /// In ten-thousandths of a point
+    pub points_e4: i64,
+}
+
+/// Points and amount owed to one user for a month
+#[derive(Debug, Clone, PartialEq)]
+pub struct Earnings {
+    pub user_id: String,
+    /// In ten-thousandths of a point
+    pub points_e4: i64,
+    /// In millionths of the statement currency
+    pub amount_micros: i64,
+}
+
+/// First day of the month `date` falls in
+pub fn month_start(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap_or(date)
+}
+
+/// Price each user's points with the rate cards. Points no card covers
+/// still count towards the user's points but earn nothing.
+pub fn compute_earnings(points: &[MonthlyPoints], cards: &[RateCard]) -> Vec<Earnings> {
+    let mut earnings: BTreeMap<&str, Earnings> = BTreeMap::new();
+    for row in points {
+        let tier = GpuTier::from_tflops(row.tflops);
+        let class = ModelClass::from_min_gpu_memory_gb(row.min_gpu_memory_gb);
+        let rate = RateCard::rate_for(cards, tier, class).unwrap_or_else(|| {
+            warn!(
+                "No payout rate card for tier {} and model class {}",
+                tier.as_str(),
+                class.as_str()
+            );
+            0
+        });
+        // Round half up to the micro, i128 so large months cannot overflow
+        let amount = (row.points_e4 as i128 * rate as i128 + (POINTS_SCALE / 2) as i128)
+            / POINTS_SCALE as i128;
+        let entry = earnings.entry(&row.user_id).or_insert_with(|| Earnings {
+            user_id: row.user_id.clone(),
+            points_e4: 0,
+            amount_micros: 0,
+        });
+        entry.points_e4 += row.points_e4;
+        entry.amount_micros += amount as i64;
+    }
+    earnings.into_values().collect()
+}
+
+pub async fn list_rate_cards(pool: &Pool<Postgres>) -> Result<Vec<RateCard>, sqlx::Error> {
+    sqlx::query_as::<_, RateCard>(&format!(
+        r#"
+            SELECT gpu_tier, model_class,
+                   (rate_per_point * 1000000)::BIGINT AS rate_per_point_micros
+            FROM {table}
+            ORDER BY gpu_tier, model_class
+        "#,
+        table = PAYOUT_RATE_CARDS_TABLE
+    ))
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn upsert_rate_card(pool: &Pool<Postgres>, card: &RateCard) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        r#"
+            INSERT INTO {table} (gpu_tier, model_class, rate_per_point)
+            VALUES ($1, $2, $3::NUMERIC / 1000000)
+            ON CONFLICT (gpu_tier, model_class) DO UPDATE SET
+                rate_per_point = EXCLUDED.rate_per_point,
+                updated_at = NOW()
+        "#,
+        table = PAYOUT_RATE_CARDS_TABLE
+    ))
+    .bind(&card.gpu_tier)
+    .bind(&card.model_class)
+    .bind(card.rate_per_point_micros)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Points earned in the month starting at `month`, per user, device tier and
+/// model class. Each day goes to the owner and is priced with the model the
+/// worker had on that day, not the ones it has now.
+pub async fn get_monthly_points(
+    pool: &Pool<Postgres>,
+    month: NaiveDate,
+) -> Result<Vec<MonthlyPoints>, sqlx::Error> {
+    sqlx::query_as::<_, MonthlyPoints>(&format!(
+        r#"
+            SELECT
+                cds.user_id,
+                dpd.tflops,
+                CASE WHEN cds.model IS NULL THEN NULL
+                     ELSE COALESCE(cm.min_gpu_memory_gb, 0)
+                END AS min_gpu_memory_gb,
+                ROUND(SUM(dpd.points) * 10000)::BIGINT AS points_e4
+            FROM {points} dpd
+            INNER JOIN {daily} cds
+                ON cds.client_id = dpd.client_id
+               AND cds.date = dpd.date
+            LEFT JOIN {models} cm
+                ON cm.name = cds.model
+               AND cm.version = cds.model_version
+            WHERE dpd.date >= $1
+              AND dpd.date < ($1 + INTERVAL '1 month')::date
+              AND dpd.points > 0
+              AND cds.user_id IS NOT NULL
+            GROUP BY 1, 2, 3
+        "#,
+        points = DEVICE_POINTS_DAILY_VIEW,
+        daily = CLIENT_DAILY_STATS_TABLE,
+        models = CLIENT_MODELS_TABLE
+    ))
+    .bind(month)
+    .fetch_all(pool)
+    .await
+}
+
+/// Compute the statements of the month `month` falls in. Pending statements
+/// are recomputed, approved and paid ones are left as they are. Returns the
+/// number of statements written.
+pub async fn generate_statements(
+    pool: &Pool<Postgres>,
+    month: NaiveDate,
+    currency: &str,
+) -> Result<u64, sqlx::Error> {
+    let month = month_start(month);
+    let cards = list_rate_cards(pool).await?;
+    let points = get_monthly_points(pool, month).await?;
+    let earnings = compute_earnings(&points, &cards);
+    if earnings.is_empty() {
+        return Ok(0);
+    }
+
+    let mut query_builder = QueryBuilder::<Postgres>::new("INSERT INTO ");
+    query_builder
+        .push(PAYOUT_STATEMENTS_TABLE)
+        .push(" (user_id, month, points, amount, currency) ");
+    query_builder.push_values(&earnings, |mut b, e| {
+        b.push_bind(&e.user_id)
+            .push_bind(month)
+            .push_bind(e.points_e4)
+            .push_unseparated("::NUMERIC / 10000")
+            .push_bind(e.amount_micros)
+            .push_unseparated("::NUMERIC / 1000000")
+            .push_bind(currency);
+    });
+    query_builder.push(
+        " ON CONFLICT (user_id, month) DO UPDATE SET \
+         points = EXCLUDED.points, \
+         amount = EXCLUDED.amount, \
+         currency = EXCLUDED.currency, \
+         updated_at = NOW() ",
+    );
+    query_builder
+        .push("WHERE ")
+        .push(PAYOUT_STATEMENTS_TABLE)
+        .push(".status = 'pending'");
+
+    let result = query_builder.build().execute(pool).await?;
+    Ok(result.rows_affected())
+}
+
+const STATEMENT_COLUMNS: &str = "id, user_id, month, \
+    points::DOUBLE PRECISION AS points, (amount * 1000000)::BIGINT AS amount_micros, \
+    currency, status, created_at, updated_at, approved_at, paid_at";
+
+/// Statements, newest month first, optionally of one user or in one status
+pub async fn list_statements(
+    pool: &Pool<Postgres>,
+    user_id: Option<&str>,
+    status: Option<PayoutStatus>,
+) -> Result<Vec<PayoutStatement>, sqlx::Error> {
+    let mut query_builder = QueryBuilder::<Postgres>::new("SELECT ");
+    query_builder
+        .push(STATEMENT_COLUMNS)
+        .push(" FROM ")
+        .push(PAYOUT_STATEMENTS_TABLE)
+        .push(" WHERE 1=1");
+    if let Some(user_id) = user_id {
+        query_builder.push(" AND user_id = ").push_bind(user_id);
+    }
+    if let Some(status) = status {
+        query_builder
+            .push(" AND status = ")
+            .push_bind(status.as_str());
+    }
+    query_builder.push(" ORDER BY month DESC, user_id");
+
+    query_builder
+        .build_query_as::<PayoutStatement>()
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn get_statement(
+    pool: &Pool<Postgres>,
+    id: i64,
+) -> Result<Option<PayoutStatement>, sqlx::Error> {
+    sqlx::query_as::<_, PayoutStatement>(&format!(
+        "SELECT {columns} FROM {table} WHERE id = $1",
+        columns = STATEMENT_COLUMNS,
+        table = PAYOUT_STATEMENTS_TABLE
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Move statement `id` from `from` to `to`. Returns None if the statement
+/// is no longer in `from`, e.g. when another request moved it first.
+pub async fn set_statement_status(
+    pool: &Pool<Postgres>,
+    id: i64,
+    from: PayoutStatus,
+    to: PayoutStatus,
+) -> Result<Option<PayoutStatement>, sqlx::Error> {
+    sqlx::query_as::<_, PayoutStatement>(&format!(
+        r#"
+            UPDATE {table}
+            SET status = $3,
+                approved_at = CASE WHEN $3 = 'approved' THEN NOW() ELSE approved_at END,
+                paid_at = CASE WHEN $3 = 'paid' THEN NOW() ELSE paid_at END,
+                updated_at = NOW()
+            WHERE id = $1 AND status = $2
+            RETURNING {columns}
+        "#,
+        table = PAYOUT_STATEMENTS_TABLE,
+        columns = STATEMENT_COLUMNS
+    ))
+    .bind(id)
+    .bind(from.as_str())
+    .bind(to.as_str())
+    .fetch_optional(pool)
+    .await
+}
+
+#[test]
+fn test_compute_earnings() {
+    let card = |gpu_tier: &str, model_class: &str, rate_per_point_micros: i64| RateCard {
+        gpu_tier: gpu_tier.to_string(),
+        model_class: model_class.to_string(),
+        rate_per_point_micros,
+    };
+    let points = |user_id: &str, tflops: f64, min_gpu_memory_gb: Option<i32>, points_e4: i64| {
+        MonthlyPoints {
+            user_id: user_id.to_string(),
+            tflops: Some(tflops),
+            min_gpu_memory_gb,
+            points_e4,
+        }
+    };
+    let cards = vec![
+        card(ANY, ANY, 10_000),
+        card(ANY, "large", 30_000),
+        card("high", ANY, 20_000),
+        card("high", "large", 50_000),
+    ];
+
+    assert_eq!(
+        RateCard::rate_for(&cards, GpuTier::High, ModelClass::Large),
+        Some(50_000)
+    );
+    assert_eq!(
+        RateCard::rate_for(&cards, GpuTier::High, ModelClass::Small),
+        Some(20_000)
+    );
+    assert_eq!(
+        RateCard::rate_for(&cards, GpuTier::Mid, ModelClass::Large),
+        Some(30_000)
+    );
+    assert_eq!(
+        RateCard::rate_for(&cards, GpuTier::Entry, ModelClass::None),
+        Some(10_000)
+    );
+    assert_eq!(
+        RateCard::rate_for(&cards[1..], GpuTier::Entry, ModelClass::None),
+        None
+    );
+
+    let earnings = compute_earnings(
+        &[
+            // RTX 4090 serving a 24 GB model
+            points("2", 82.6, Some(24), 1_000_000),
+            // M2 without a model
+            points("2", 3.6, None, 500_000),
+            // 0.0001 points at 0.03 rounds to 3 micros
+            points("7", 82.6, Some(8), 100_000),
+            points("7", 20.0, Some(24), 1),
+        ],
+        &cards,
+    );
+    assert_eq!(earnings.len(), 2);
+    assert_eq!(earnings[0].user_id, "2");
+    assert_eq!(earnings[0].points_e4, 1_500_000);
+    assert_eq!(earnings[0].amount_micros, 5_500_000);
+    assert_eq!(earnings[1].amount_micros, 200_003);
+    assert_eq!(format_micros(earnings[0].amount_micros), "5.50");
+    assert_eq!(format_micros(30_654_999), "30.65");
+
+    assert!(PayoutStatus::Pending.can_become(PayoutStatus::Approved));
+    assert!(PayoutStatus::Approved.can_become(PayoutStatus::Paid));
+    assert!(!PayoutStatus::Pending.can_become(PayoutStatus::Paid));
+    assert!(!PayoutStatus::Paid.can_become(PayoutStatus::Pending));
+    assert_eq!(
+        month_start(NaiveDate::from_ymd_opt(2025, 7, 29).unwrap()),
+        NaiveDate::from_ymd_opt(2025, 7, 1).unwrap()
+    );
+}
@@ -0,0 +1,237 @@
+//! Worker liveness shared by all gpuf-s instances. Each connected worker has a
+//! `presence:<client_id>` key holding the id of the instance it is attached
+//! to, refreshed on every heartbeat and expiring when they stop. Connects and
+//! disconnects are published on `PRESENCE_CHANNEL`.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt};
+use redis::{AsyncCommands, Client as RedisClient, Script};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::db::GPU_ASSETS_TABLE;
+use crate::util::protoc::ClientId;
+
+pub const PRESENCE_CHANNEL: &str = "presence:events";
+
+/// Deletes the key only while it still names this instance. Returns -1 when
+/// the worker already reconnected to another instance, which keeps it online.
+const RELEASE_SCRIPT: &str = r#"
+local owner = redis.call('GET', KEYS[1])
+if owner == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+if owner then
+    return -1
+end
+return 0
+"#;
+
+fn presence_key(client_id: &ClientId) -> String {
+    format!("presence:{}", client_id)
+}
+
+fn forward_addr_key(instance_id: &str) -> String {
+    format!("instance:{}:forward_addr", instance_id)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresenceEvent {
+    pub client_id: ClientId,
+    pub instance_id: String,
+    pub online: bool,
+    pub at: DateTime<Utc>,
+}
+
+pub struct Presence {
+    redis_client: Arc<RedisClient>,
+    instance_id: String,
+    ttl_secs: u64,
+}
+
+impl Presence {
+    pub fn new(redis_client: Arc<RedisClient>, instance_id: String, ttl_secs: u64) -> Self {
+        Self {
+            redis_client,
+            instance_id,
+            ttl_secs,
+        }
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Claim the worker for this instance and announce it
+    pub async fn mark_online(&self, client_id: &ClientId) -> Result<()> {
+        let mut conn = self.redis_client.get_async_connection().await?;
+        conn.set_ex::<_, _, ()>(presence_key(client_id), &self.instance_id, self.ttl_secs)
+            .await?;
+        self.publish(&mut conn, client_id, true).await
+    }
+
+    /// Keep the worker online for another TTL, called on every heartbeat
+    pub async fn touch(&self, client_id: &ClientId) -> Result<()> {
+        let mut conn = self.redis_client.get_async_connection().await?;
+        conn.set_ex::<_, _, ()>(presence_key(client_id), &self.instance_id, self.ttl_secs)
+            .await?;
+        Ok(())
+    }
+
+    /// Release the worker when its connection to this instance closes.
+    /// Returns true when another instance holds the worker by now.
+    pub async fn mark_offline(&self, client_id: &ClientId) -> Result<bool> {
+        let mut conn = self.redis_client.get_async_connection().await?;
+        let released: i64 = Script::new(RELEASE_SCRIPT)
+            .key(presence_key(client_id))
+            .arg(&self.instance_id)
+            .invoke_async(&mut conn)
+            .await?;
+        if released < 0 {
+            return Ok(true);
+        }
+        self.publish(&mut conn, client_id, false).await?;
+        Ok(false)
+    }
+
+    /// Tell the other instances where to forward requests for workers
+    /// attached here, refreshed like a heartbeat
+    pub async fn advertise_forward_addr(&self, addr: &str) -> Result<()> {
+        let mut conn = self.redis_client.get_async_connection().await?;
+        conn.set_ex::<_, _, ()>(forward_addr_key(&self.instance_id), addr, self.ttl_secs)
+            .await?;
+        Ok(())
+    }
+
+    async fn publish(
+        &self,
+        conn: &mut redis::aio::Connection,
+        client_id: &ClientId,
+        online: bool,
+    ) -> Result<()> {
+        let event = PresenceEvent {
+            client_id: *client_id,
+            instance_id: self.instance_id.clone(),
+            online,
+            at: Utc::now(),
+        };
+        conn.publish::<_, _, ()>(PRESENCE_CHANNEL, serde_json::to_string(&event)?)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Instance each client is attached to, None for clients that are offline
+pub async fn get_instances(
+    redis_client: &RedisClient,
+    client_ids: &[ClientId],
+) -> Result<Vec<Option<String>>> {
+    if client_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut conn = redis_client.get_async_connection().await?;
+    let keys: Vec<String> = client_ids.iter().map(presence_key).collect();
+    // MGET returns a plain value rather than a list for a single key
+    if keys.len() == 1 {
+        return Ok(vec![conn.get(&keys[0]).await?]);
+    }
+    Ok(conn.get(keys).await?)
+}
+
+/// Address requests for workers attached to an instance are forwarded to,
+/// None when the instance does not accept forwarded requests
+pub async fn get_forward_addr(
+    redis_client: &RedisClient,
+    instance_id: &str,
+) -> Result<Option<String>> {
+    let mut conn = redis_client.get_async_connection().await?;
+    Ok(conn.get(forward_addr_key(instance_id)).await?)
+}
+
+/// Mark clients offline whose row went stale and who have no presence key
+/// left, so workers connected to a live instance survive a slow heartbeat
+/// pipeline. Returns the number of clients marked offline.
+pub async fn sweep_stale_clients(
+    pool: &Pool<Postgres>,
+    redis_client: &RedisClient,
+    offline_after_secs: i64,
+) -> Result<u64> {
+    let stale: Vec<Vec<u8>> = sqlx::query_scalar(&format!(
+        "SELECT client_id FROM {} \
+         WHERE valid_status = 'valid' AND client_status <> 'offline' \
+         AND updated_at < (NOW() - ($1 * INTERVAL '1 second'))",
+        GPU_ASSETS_TABLE
+    ))
+    .bind(offline_after_secs)
+    .fetch_all(pool)
+    .await?;
+    let stale: Vec<ClientId> = stale
+        .into_iter()
+        .filter_map(|id| id.try_into().ok().map(ClientId))
+        .collect();
+    if stale.is_empty() {
+        return Ok(0);
+    }
+
+    let instances = get_instances(redis_client, &stale).await?;
+    let gone: Vec<ClientId> = stale
+        .into_iter()
+        .zip(instances)
+        .filter_map(|(client_id, instance)| instance.is_none().then_some(client_id))
+        .collect();
+    if gone.is_empty() {
+        return Ok(0);
+    }
+
+    // Staleness is checked again in case a heartbeat landed meanwhile
+    let result = sqlx::query(&format!(
+        "UPDATE {} SET client_status = 'offline', updated_at = NOW() \
+         WHERE client_id = ANY($1) AND client_status <> 'offline' \
+         AND updated_at < (NOW() - ($2 * INTERVAL '1 second'))",
+        GPU_ASSETS_TABLE
+    ))
+    .bind(&gone)
+    .bind(offline_after_secs)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Presence events of all instances, including this one, until the
+/// subscription drops
+pub async fn subscribe(redis_client: &RedisClient) -> Result<impl Stream<Item = PresenceEvent>> {
+    let mut pubsub = redis_client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(PRESENCE_CHANNEL).await?;
+    Ok(pubsub.into_on_message().filter_map(|message| {
+        let event = message
+            .get_payload::<String>()
+            .map_err(anyhow::Error::from)
+            .and_then(|payload| {
+                serde_json::from_str::<PresenceEvent>(&payload).map_err(Into::into)
+            });
+        if let Err(e) = &event {
+            warn!("Dropping presence event: {}", e);
+        }
+        std::future::ready(event.ok())
+    }))
+}
+
+#[test]
+fn test_presence_event_json() {
+    let event = PresenceEvent {
+        client_id: ClientId([0x1f; 16]),
+        instance_id: "gpuf-s-a".to_string(),
+        online: true,
+        at: Utc::now(),
+    };
+    let json = serde_json::to_value(&event).unwrap();
+    assert_eq!(json["client_id"], "1f".repeat(16));
+
+    let decoded: PresenceEvent = serde_json::from_value(json).unwrap();
+    assert_eq!(decoded.client_id, event.client_id);
+    assert_eq!(decoded.instance_id, "gpuf-s-a");
+    assert!(decoded.online);
+}
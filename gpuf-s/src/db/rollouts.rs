@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Postgres};
+
+use crate::db::models::RolloutState;
+use crate::db::{CLIENT_MODELS_TABLE, MODEL_ROLLOUT_RESULTS_TABLE};
+use crate::util::protoc::ClientId;
+
+/// When the load results of a canary decide its fate
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RolloutPolicy {
+    /// Results needed before a canary is promoted or rolled back
+    pub min_samples: u32,
+    /// Share of successful loads, 0 to 1, a canary needs to be promoted
+    pub min_success_rate: f64,
+}
+
+impl RolloutPolicy {
+    /// Stable or rolled back once enough workers reported, None while the
+    /// canary keeps running. A canary whose failures already keep it below
+    /// the success rate is rolled back without waiting for more samples.
+    pub fn decide(&self, succeeded: i64, failed: i64) -> Option<RolloutState> {
+        let min_samples = self.min_samples.max(1) as i64;
+        let total = succeeded + failed;
+        let rate = if total < min_samples {
+            // Best rate the first min_samples results can still reach
+            (min_samples - failed).max(0) as f64 / min_samples as f64
+        } else {
+            succeeded as f64 / total as f64
+        };
+        if rate < self.min_success_rate {
+            Some(RolloutState::RolledBack)
+        } else if total < min_samples {
+            None
+        } else {
+            Some(RolloutState::Stable)
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct RolloutSummary {
+    pub model_id: i32,
+    pub rollout_state: String,
+    pub rollout_percent: i16,
+    pub rollout_allowlist: Vec<String>,
+    pub succeeded: i64,
+    pub failed: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RolloutUpdate {
+    pub state: RolloutState,
+    /// Share of eligible workers a canary goes to, kept when omitted
+    pub percent: Option<i16>,
+    /// Client ids in hex a canary always goes to, kept when omitted
+    pub allowlist: Option<Vec<String>>,
+}
+
+/// Canary entry of a model name, the newest one when several are running
+pub async fn find_canary(pool: &Pool<Postgres>, name: &str) -> Result<Option<i32>, sqlx::Error> {
+    sqlx::query_scalar::<_, i32>(&format!(
+        "SELECT id FROM {} WHERE name = $1 AND rollout_state = $2 \
+         ORDER BY version_code DESC LIMIT 1",
+        CLIENT_MODELS_TABLE
+    ))
+    .bind(name)
+    .bind(RolloutState::Canary.as_str())
+    .fetch_optional(pool)
+    .await
+}
+
+/// Keep the latest load result of a worker for a canary
+pub async fn record_result(
+    pool: &Pool<Postgres>,
+    model_id: i32,
+    client_id: &ClientId,
+    succeeded: bool,
+    error: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "INSERT INTO {} (model_id, client_id, succeeded, error, reported_at) \
+         VALUES ($1, $2, $3, $4, NOW()) \
+         ON CONFLICT (model_id, client_id) DO UPDATE SET \
+         succeeded = EXCLUDED.succeeded, error = EXCLUDED.error, reported_at = NOW()",
+        MODEL_ROLLOUT_RESULTS_TABLE
+    ))
+    .bind(model_id)
+    .bind(client_id)
+    .bind(succeeded)
+    .bind(error)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_summary(
+    pool: &Pool<Postgres>,
+    model_id: i32,
+) -> Result<Option<RolloutSummary>, sqlx::Error> {
+    sqlx::query_as::<_, RolloutSummary>(&format!(
+        "
+        SELECT cm.id AS model_id, cm.rollout_state, cm.rollout_percent, cm.rollout_allowlist,
+            COUNT(r.client_id) FILTER (WHERE r.succeeded) AS succeeded,
+            COUNT(r.client_id) FILTER (WHERE NOT r.succeeded) AS failed
+        FROM {} cm
+        LEFT JOIN {} r ON r.model_id = cm.id
+        WHERE cm.id = $1
+        GROUP BY cm.id
+        ",
+        CLIENT_MODELS_TABLE, MODEL_ROLLOUT_RESULTS_TABLE
+    ))
+    .bind(model_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Change the rollout of an entry. Starting a canary clears the results of an
+/// earlier one. Returns false for an unknown entry.
+pub async fn set_rollout(
+    pool: &Pool<Postgres>,
+    model_id: i32,
+    update: &RolloutUpdate,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query(&format!(
+        "UPDATE {} SET rollout_state = $2, \
+         rollout_percent = COALESCE($3, rollout_percent), \
+         rollout_allowlist = COALESCE($4, rollout_allowlist) \
+         WHERE id = $1",
+        CLIENT_MODELS_TABLE
+    ))
+    .bind(model_id)
+    .bind(update.state.as_str())
+    .bind(update.percent)
+    .bind(&update.allowlist)
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    if update.state == RolloutState::Canary {
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE model_id = $1",
+            MODEL_ROLLOUT_RESULTS_TABLE
+        ))
+        .bind(model_id)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Promote or roll back a canary when its results are conclusive. Returns the
+/// new state, None while the canary keeps running.
+pub async fn evaluate(
+    pool: &Pool<Postgres>,
+    model_id: i32,
+    policy: &RolloutPolicy,
+) -> Result<Option<RolloutState>, sqlx::Error> {
+    let Some(summary) = get_summary(pool, model_id).await? else {
+        return Ok(None);
+    };
+    if summary.rollout_state != RolloutState::Canary.as_str() {
+        return Ok(None);
+    }
+    let Some(state) = policy.decide(summary.succeeded, summary.failed) else {
+        return Ok(None);
+    };
+
+    // Only the first evaluation to reach a verdict applies it
+    let result = sqlx::query(&format!(
+        "UPDATE {} SET rollout_state = $2, \
+         rollout_percent = CASE WHEN $2 = 'stable' THEN 100 ELSE rollout_percent END \
+         WHERE id = $1 AND rollout_state = $3",
+        CLIENT_MODELS_TABLE
+    ))
+    .bind(model_id)
+    .bind(state.as_str())
+    .bind(RolloutState::Canary.as_str())
+    .execute(pool)
+    .await?;
+    Ok((result.rows_affected() > 0).then_some(state))
+}
+
+#[test]
+fn test_rollout_policy_decide() {
+    let policy = RolloutPolicy {
+        min_samples: 10,
+        min_success_rate: 0.8,
+    };
+    assert_eq!(policy.decide(5, 1), None);
+    assert_eq!(policy.decide(9, 1), Some(RolloutState::Stable));
+    assert_eq!(policy.decide(16, 4), Some(RolloutState::Stable));
+    // Three failures can never reach 80% of 10 samples
+    assert_eq!(policy.decide(0, 3), Some(RolloutState::RolledBack));
+    assert_eq!(policy.decide(20, 6), Some(RolloutState::RolledBack));
+    assert_eq!(policy.decide(40, 3), Some(RolloutState::Stable));
+}
@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, Pool, Postgres};
+
+use crate::db::{ACCESS_DENIALS_TABLE, STAFF_ROLES_TABLE};
+use crate::util::msg::{FilterOp, ListField, ListQuery, ListSpec, Page};
+use crate::util::rbac::{Permission, Role};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StaffRole {
+    pub user_id: String,
+    pub role: Role,
+    pub granted_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct StaffRoleRow {
+    user_id: String,
+    role: String,
+    granted_by: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+/// Staff role of the user, None when it has none
+pub async fn get_role(pool: &Pool<Postgres>, user_id: &str) -> Result<Option<Role>, sqlx::Error> {
+    let role: Option<String> = sqlx::query_scalar(&format!(
+        "SELECT role FROM {} WHERE user_id = $1",
+        STAFF_ROLES_TABLE
+    ))
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(role.as_deref().and_then(Role::parse))
+}
+
+pub async fn list_roles(pool: &Pool<Postgres>) -> Result<Vec<StaffRole>, sqlx::Error> {
+    let rows: Vec<StaffRoleRow> = sqlx::query_as(&format!(
+        "SELECT user_id, role, granted_by, created_at FROM {} ORDER BY user_id",
+        STAFF_ROLES_TABLE
+    ))
+    .fetch_all(pool)
+    .await?;
+    // Roles this version does not know are skipped
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(StaffRole {
+                user_id: row.user_id,
+                role: Role::parse(&row.role)?,
+                granted_by: row.granted_by,
+                created_at: row.created_at,
+            })
+        })
+        .collect())
+}
+
+/// Grant a role or change it
+pub async fn set_role(
+    pool: &Pool<Postgres>,
+    user_id: &str,
+    role: Role,
+    granted_by: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "INSERT INTO {} (user_id, role, granted_by) VALUES ($1, $2, $3) \
+         ON CONFLICT (user_id) DO UPDATE \
+         SET role = EXCLUDED.role, granted_by = EXCLUDED.granted_by, created_at = NOW()",
+        STAFF_ROLES_TABLE
+    ))
+    .bind(user_id)
+    .bind(role.as_str())
+    .bind(granted_by)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns false when the user had no role
+pub async fn remove_role(pool: &Pool<Postgres>, user_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(&format!(
+        "DELETE FROM {} WHERE user_id = $1",
+        STAFF_ROLES_TABLE
+    ))
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub struct NewDenial<'a> {
+    pub user_id: &'a str,
+    pub role: Option<Role>,
+    pub permission: Permission,
+    pub method: &'a str,
+    pub path: &'a str,
+}
+
+pub async fn record_denial(
+    pool: &Pool<Postgres>,
+    denial: &NewDenial<'_>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "INSERT INTO {} (user_id, role, permission, method, path) VALUES ($1, $2, $3, $4, $5)",
+        ACCESS_DENIALS_TABLE
+    ))
+    .bind(denial.user_id)
+    .bind(denial.role.map(Role::as_str))
+    .bind(denial.permission.as_str())
+    .bind(denial.method)
+    .bind(denial.path)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct AccessDenial {
+    pub id: i64,
+    pub user_id: String,
+    pub role: Option<String>,
+    pub permission: String,
+    pub method: String,
+    pub path: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Sort and filter fields of the denial listing
+pub static DENIAL_LIST: ListSpec = ListSpec {
+    fields: &[
+        ListField {
+            name: "created_at",
+            expr: "l.created_at",
+            sql_type: "timestamptz",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "user_id",
+            expr: "l.user_id",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "permission",
+            expr: "l.permission",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "path",
+            expr: "l.path",
+            sql_type: "text",
+            sortable: false,
+            filter: Some(FilterOp::Contains),
+        },
+    ],
+    default_sort: "-created_at",
+    key: "lpad(l.id::text, 20, '0')",
+};
+
+pub async fn list_denials(
+    pool: &Pool<Postgres>,
+    list: &ListQuery,
+) -> Result<Page<AccessDenial>, sqlx::Error> {
+    list.fetch(pool, |query_builder| {
+        query_builder.push(format!(
+            "SELECT id, user_id, role, permission, method, path, created_at FROM {}",
+            ACCESS_DENIALS_TABLE
+        ));
+    })
+    .await
+}
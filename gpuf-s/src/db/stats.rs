@@ -5,12 +5,61 @@ use crate::db::{
 use crate::util::protoc::ClientId;
 use anyhow::Result;
 use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
-use common::{get_u16_from_u128, get_u8_from_u64, DevicesInfo, SystemInfo};
+use common::{get_u16_from_u128, get_u8_from_u64, DevicesInfo, SystemInfo, WorkerVersion};
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
 use sqlx::{FromRow, PgPool, Pool, Postgres, QueryBuilder, Transaction};
+use std::collections::HashMap;
 use tracing::{debug, info};
 use validator::Validate;
 
+/// Clients stored per multi-row statement, keeps device rows well below
+/// PostgreSQL's limit of 65535 bind parameters
+const MAX_CLIENTS_PER_STATEMENT: usize = 256;
+/// Heartbeat interval of days without a `heartbeat_config_daily` row
+const DEFAULT_HEARTBEAT_INTERVAL_SECS: i64 = 120;
+
+/// Stats of a heartbeat only count once per interval bucket of its day
+const CLIENT_DAILY_STATS_CONFLICT: &str = r#"
+            ON CONFLICT (client_id, date) 
+            DO UPDATE SET
+                avg_cpu_usage = CASE
+                    WHEN EXCLUDED.last_heartbeat_bucket > client_daily_stats.last_heartbeat_bucket THEN
+                        (COALESCE(client_daily_stats.avg_cpu_usage, 0) * client_daily_stats.total_heartbeats + EXCLUDED.avg_cpu_usage) /
+                        (client_daily_stats.total_heartbeats + 1)
+                    ELSE client_daily_stats.avg_cpu_usage
+                END,
+                avg_memory_usage = CASE
+                    WHEN EXCLUDED.last_heartbeat_bucket > client_daily_stats.last_heartbeat_bucket THEN
+                        (COALESCE(client_daily_stats.avg_memory_usage, 0) * client_daily_stats.total_heartbeats + EXCLUDED.avg_memory_usage) /
+                        (client_daily_stats.total_heartbeats + 1)
+                    ELSE client_daily_stats.avg_memory_usage
+                END,
+                avg_disk_usage = CASE
+                    WHEN EXCLUDED.last_heartbeat_bucket > client_daily_stats.last_heartbeat_bucket THEN
+                        (COALESCE(client_daily_stats.avg_disk_usage, 0) * client_daily_stats.total_heartbeats + EXCLUDED.avg_disk_usage) /
+                        (client_daily_stats.total_heartbeats + 1)
+                    ELSE client_daily_stats.avg_disk_usage
+                END,
+                total_network_in_bytes = CASE
+                    WHEN EXCLUDED.last_heartbeat_bucket > client_daily_stats.last_heartbeat_bucket THEN
+                        COALESCE(EXCLUDED.total_network_in_bytes, 0) + COALESCE(client_daily_stats.total_network_in_bytes, 0)
+                    ELSE client_daily_stats.total_network_in_bytes
+                END,
+                total_network_out_bytes = CASE
+                    WHEN EXCLUDED.last_heartbeat_bucket > client_daily_stats.last_heartbeat_bucket THEN
+                        COALESCE(EXCLUDED.total_network_out_bytes, 0) + COALESCE(client_daily_stats.total_network_out_bytes, 0)
+                    ELSE client_daily_stats.total_network_out_bytes
+                END,
+                total_heartbeats = client_daily_stats.total_heartbeats + CASE
+                    WHEN EXCLUDED.last_heartbeat_bucket > client_daily_stats.last_heartbeat_bucket THEN 1
+                    ELSE 0
+                END,
+                last_heartbeat = GREATEST(client_daily_stats.last_heartbeat, EXCLUDED.last_heartbeat),
+                last_heartbeat_bucket = GREATEST(client_daily_stats.last_heartbeat_bucket, EXCLUDED.last_heartbeat_bucket),
+                updated_at = NOW()
+"#;
+
 #[derive(Debug, FromRow, Serialize, Deserialize)]
 pub struct ClientDailyStats {
     pub id: i64,
@@ -59,7 +108,7 @@ impl ClientDailyStats {
     ) -> Result<Self, sqlx::Error> {
         let day = timestamp.date_naive();
 
-        let record = sqlx::query_as(
+        let record = sqlx::query_as(&format!(
             r#"
             INSERT INTO client_daily_stats (
                 date, client_id, 
@@ -79,46 +128,11 @@ impl ClientDailyStats {
                 $8,
                 (EXTRACT(EPOCH FROM $8)::BIGINT / COALESCE((SELECT heartbeat_interval_secs FROM heartbeat_config_daily WHERE date = $1), 120)::BIGINT)
             )
-            ON CONFLICT (client_id, date) 
-            DO UPDATE SET
-                avg_cpu_usage = CASE
-                    WHEN EXCLUDED.last_heartbeat_bucket > client_daily_stats.last_heartbeat_bucket THEN
-                        (COALESCE(client_daily_stats.avg_cpu_usage, 0) * client_daily_stats.total_heartbeats + EXCLUDED.avg_cpu_usage) /
-                        (client_daily_stats.total_heartbeats + 1)
-                    ELSE client_daily_stats.avg_cpu_usage
-                END,
-                avg_memory_usage = CASE
-                    WHEN EXCLUDED.last_heartbeat_bucket > client_daily_stats.last_heartbeat_bucket THEN
-                        (COALESCE(client_daily_stats.avg_memory_usage, 0) * client_daily_stats.total_heartbeats + EXCLUDED.avg_memory_usage) /
-                        (client_daily_stats.total_heartbeats + 1)
-                    ELSE client_daily_stats.avg_memory_usage
-                END,
-                avg_disk_usage = CASE
-                    WHEN EXCLUDED.last_heartbeat_bucket > client_daily_stats.last_heartbeat_bucket THEN
-                        (COALESCE(client_daily_stats.avg_disk_usage, 0) * client_daily_stats.total_heartbeats + EXCLUDED.avg_disk_usage) /
-                        (client_daily_stats.total_heartbeats + 1)
-                    ELSE client_daily_stats.avg_disk_usage
-                END,
-                total_network_in_bytes = CASE
-                    WHEN EXCLUDED.last_heartbeat_bucket > client_daily_stats.last_heartbeat_bucket THEN
-                        COALESCE(EXCLUDED.total_network_in_bytes, 0) + COALESCE(client_daily_stats.total_network_in_bytes, 0)
-                    ELSE client_daily_stats.total_network_in_bytes
-                END,
-                total_network_out_bytes = CASE
-                    WHEN EXCLUDED.last_heartbeat_bucket > client_daily_stats.last_heartbeat_bucket THEN
-                        COALESCE(EXCLUDED.total_network_out_bytes, 0) + COALESCE(client_daily_stats.total_network_out_bytes, 0)
-                    ELSE client_daily_stats.total_network_out_bytes
-                END,
-                total_heartbeats = client_daily_stats.total_heartbeats + CASE
-                    WHEN EXCLUDED.last_heartbeat_bucket > client_daily_stats.last_heartbeat_bucket THEN 1
-                    ELSE 0
-                END,
-                last_heartbeat = GREATEST(client_daily_stats.last_heartbeat, EXCLUDED.last_heartbeat),
-                last_heartbeat_bucket = GREATEST(client_daily_stats.last_heartbeat_bucket, EXCLUDED.last_heartbeat_bucket),
-                updated_at = NOW()
+            {}
             RETURNING *
             "#,
-        )
+            CLIENT_DAILY_STATS_CONFLICT
+        ))
         .bind(day)
         .bind(client_id)
         .bind(cpu_usage)
@@ -133,6 +147,75 @@ impl ClientDailyStats {
         Ok(record)
     }
 
+    /// Multi-row `upsert`, `rows` must not hold two heartbeats of a client
+    async fn upsert_rows(
+        tx: &mut Transaction<'_, Postgres>,
+        rows: &[HeartbeatRow<'_>],
+        intervals: &HashMap<NaiveDate, i64>,
+    ) -> Result<u64, sqlx::Error> {
+        if rows.is_empty() {
+            return Ok(0);
+        }
+
+        let mut query_builder = QueryBuilder::<Postgres>::new(format!(
+            "INSERT INTO {} (date, client_id, avg_cpu_usage, avg_memory_usage, avg_disk_usage, \
+             total_network_in_bytes, total_network_out_bytes, \
+             total_heartbeats, last_heartbeat, last_heartbeat_bucket) ",
+            CLIENT_DAILY_STATS_TABLE
+        ));
+        query_builder.push_values(rows, |mut b, row| {
+            let network_in: i64 = row.system_info.network_rx.try_into().unwrap_or(0);
+            let network_out: i64 = row.system_info.network_tx.try_into().unwrap_or(0);
+            b.push_bind(row.timestamp.date_naive())
+                .push_bind(row.client_id)
+                .push_bind(row.system_info.cpu_usage as f64)
+                .push_bind(row.system_info.memory_usage as f64)
+                .push_bind(row.system_info.disk_usage as f64)
+                .push_bind(network_in)
+                .push_bind(network_out)
+                .push_bind(1)
+                .push_bind(row.timestamp)
+                .push_bind(heartbeat_bucket(intervals, row.timestamp));
+        });
+        query_builder.push(CLIENT_DAILY_STATS_CONFLICT);
+
+        let result = query_builder.build().execute(&mut **tx).await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Copy the current owner and model of each client onto the day of its
+    /// heartbeat, payouts credit and price points per day from these
+    async fn record_owner_model(
+        tx: &mut Transaction<'_, Postgres>,
+        rows: &[HeartbeatRow<'_>],
+    ) -> Result<u64, sqlx::Error> {
+        if rows.is_empty() {
+            return Ok(0);
+        }
+
+        let client_ids: Vec<ClientId> = rows.iter().map(|row| *row.client_id).collect();
+        let days: Vec<NaiveDate> = rows.iter().map(|row| row.timestamp.date_naive()).collect();
+        let result = sqlx::query(&format!(
+            r#"
+            UPDATE {stats} cds
+            SET user_id = ga.user_id,
+                model = ga.model,
+                model_version = ga.model_version
+            FROM {assets} ga, UNNEST($1::BYTEA[], $2::DATE[]) AS h (client_id, date)
+            WHERE ga.client_id = h.client_id
+              AND cds.client_id = h.client_id
+              AND cds.date = h.date
+            "#,
+            stats = CLIENT_DAILY_STATS_TABLE,
+            assets = GPU_ASSETS_TABLE
+        ))
+        .bind(&client_ids)
+        .bind(&days)
+        .execute(&mut **tx)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
     #[allow(dead_code)] // Get client statistics for date range
     pub async fn get_stats(
         pool: &PgPool,
@@ -182,17 +265,23 @@ impl DeviceDailyStats {
             return Ok(0);
         }
 
-        let day = timestamp.date_naive();
-        let interval_secs: i64 = sqlx::query_scalar(
-            "SELECT COALESCE(heartbeat_interval_secs, 120)::BIGINT FROM heartbeat_config_daily WHERE date = $1",
-        )
-        .bind(day)
-        .fetch_optional(&mut **tx)
-        .await?
-        .unwrap_or(120);
+        let intervals = heartbeat_intervals(tx, &[timestamp.date_naive()]).await?;
+        let rows = device_rows(client_id, devices, timestamp);
+        let upserted = Self::upsert_rows(tx, &rows, &intervals).await?;
+        Ok(upserted as i32)
+    }
+
+    /// Multi-row upsert of device stats, `rows` must not hold two heartbeats
+    /// of a client
+    async fn upsert_rows(
+        tx: &mut Transaction<'_, Postgres>,
+        rows: &[DeviceRow<'_>],
+        intervals: &HashMap<NaiveDate, i64>,
+    ) -> Result<u64, sqlx::Error> {
+        if rows.is_empty() {
+            return Ok(0);
+        }
 
-        let interval_secs = interval_secs.max(1);
-        let bucket: i64 = (timestamp.timestamp() / interval_secs).max(0);
         let mut query_builder = QueryBuilder::new(
             format!(
                 "
@@ -206,33 +295,18 @@ impl DeviceDailyStats {
             )
             .as_str(),
         );
-        // Flatten devices into individual rows first
-        let mut flattened_devices: Vec<(i16, &common::DevicesInfo)> = Vec::new();
-        for device in devices {
-            for index in 0..device.num {
-                flattened_devices.push((index as i16, device));
-            }
-        }
-        
-        //batch insert with flattened devices
-        query_builder.push_values(&flattened_devices, |mut b, (index, device)| {
-            b.push_bind(day)
-                .push_bind(client_id)
-                .push_bind(*index)
-                .push_bind(format!(
-                    "{} {}",
-                    common::id_to_vendor(get_u16_from_u128(device.vendor_id, *index as usize))
-                        .unwrap_or("Unknown"),
-                    common::id_to_model(get_u16_from_u128(device.device_id, *index as usize))
-                        .unwrap_or("Unknown".to_string())
-                ))
-                .push_bind(Some(device.usage as f64))
-                .push_bind(Some(device.temp as f64))
-                .push_bind(Some(device.power_usage as f64))
-                .push_bind(Some(device.mem_usage as f64))
+        query_builder.push_values(rows, |mut b, row| {
+            b.push_bind(row.timestamp.date_naive())
+                .push_bind(row.client_id)
+                .push_bind(row.index as i16)
+                .push_bind(row.device_name())
+                .push_bind(Some(row.device.usage as f64))
+                .push_bind(Some(row.device.temp as f64))
+                .push_bind(Some(row.device.power_usage as f64))
+                .push_bind(Some(row.device.mem_usage as f64))
                 .push_bind(1)
-                .push_bind(timestamp)
-                .push_bind(bucket);
+                .push_bind(row.timestamp)
+                .push_bind(heartbeat_bucket(intervals, row.timestamp));
         });
 
         let t = DEVICE_DAILY_STATS_TABLE;
@@ -284,16 +358,14 @@ impl DeviceDailyStats {
                 last_heartbeat = GREATEST({t}.last_heartbeat, EXCLUDED.last_heartbeat),
                 last_heartbeat_bucket = GREATEST({t}.last_heartbeat_bucket, EXCLUDED.last_heartbeat_bucket),
                 updated_at = NOW()
-            RETURNING *
             "
         ));
-        
-        let sql = query_builder.sql();
-        tracing::info!("DeviceDailyStats upsert SQL: {}", sql);
+
+        debug!("DeviceDailyStats upsert SQL: {}", query_builder.sql());
 
         let records = query_builder.build().execute(&mut **tx).await?;
 
-        Ok(records.rows_affected() as i32)
+        Ok(records.rows_affected())
     }
 
     #[allow(dead_code)] // Get device statistics for date range
@@ -360,7 +432,7 @@ pub async fn insert_heartbeat(
     .await?;
 
     // Update GPU assets status
-    sqlx::query(&format!("UPDATE  {} SET client_status = $1, updated_at = NOW() WHERE client_id = $2 AND valid_status = 'valid' ",GPU_ASSETS_TABLE))
+    sqlx::query(&format!("UPDATE  {} SET client_status = CASE WHEN maintenance THEN 'maintenance' ELSE $1 END, updated_at = NOW() WHERE client_id = $2 AND valid_status = 'valid' ",GPU_ASSETS_TABLE))
     .bind("online")
     .bind(client_id)
     .execute(&mut **tx)  // Properly deref the transaction
@@ -493,6 +565,237 @@ pub async fn insert_heartbeat(
     Ok(())
 }
 
+/// One heartbeat to store with `store_heartbeats`
+#[derive(Clone, Copy)]
+pub struct HeartbeatRow<'a> {
+    pub client_id: &'a ClientId,
+    pub system_info: &'a SystemInfo,
+    pub devices_info: &'a [DevicesInfo],
+    pub device_memtotal_gb: i32,
+    pub device_count: i32,
+    pub total_tflops: i32,
+    pub timestamp: DateTime<Utc>,
+    pub worker_version: Option<&'a WorkerVersion>,
+}
+
+/// One device of a heartbeat, devices info entries hold up to `num` devices
+pub(crate) struct DeviceRow<'a> {
+    pub client_id: &'a ClientId,
+    pub device: &'a DevicesInfo,
+    pub index: u16,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl DeviceRow<'_> {
+    pub fn device_name(&self) -> String {
+        let index = self.index as usize;
+        format!(
+            "{} {}",
+            common::id_to_vendor(get_u16_from_u128(self.device.vendor_id, index))
+                .unwrap_or("Unknown"),
+            common::id_to_model(get_u16_from_u128(self.device.device_id, index))
+                .unwrap_or("Unknown".to_string())
+        )
+    }
+}
+
+pub(crate) fn device_rows<'a>(
+    client_id: &'a ClientId,
+    devices: &'a [DevicesInfo],
+    timestamp: DateTime<Utc>,
+) -> Vec<DeviceRow<'a>> {
+    devices
+        .iter()
+        .flat_map(|device| {
+            (0..device.num).map(move |index| DeviceRow {
+                client_id,
+                device,
+                index,
+                timestamp,
+            })
+        })
+        .collect()
+}
+
+/// Heartbeat interval of each of `days` that has a config row
+async fn heartbeat_intervals(
+    tx: &mut Transaction<'_, Postgres>,
+    days: &[NaiveDate],
+) -> Result<HashMap<NaiveDate, i64>, sqlx::Error> {
+    let intervals: Vec<(NaiveDate, i64)> = sqlx::query_as(
+        "SELECT date, COALESCE(heartbeat_interval_secs, 120)::BIGINT FROM heartbeat_config_daily WHERE date = ANY($1)",
+    )
+    .bind(days)
+    .fetch_all(&mut **tx)
+    .await?;
+    Ok(intervals.into_iter().collect())
+}
+
+fn heartbeat_bucket(intervals: &HashMap<NaiveDate, i64>, timestamp: DateTime<Utc>) -> i64 {
+    let interval_secs = intervals
+        .get(&timestamp.date_naive())
+        .copied()
+        .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL_SECS)
+        .max(1);
+    (timestamp.timestamp() / interval_secs).max(0)
+}
+
+/// Delete raw heartbeats older than `retention_days`, the daily stats keep
+/// their rollups
+pub async fn prune_heartbeats(pool: &PgPool, retention_days: u32) -> Result<u64> {
+    let result = sqlx::query(&format!(
+        "DELETE FROM {} WHERE timestamp < NOW() - make_interval(days => $1)",
+        HEARTBEAT_TABLE
+    ))
+    .bind(retention_days as i32)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Batched `insert_heartbeat` plus daily stats, with one multi-row statement
+/// per table. An upsert cannot touch a row twice, so `rows` must not hold two
+/// heartbeats of a client; callers store later ones in another call.
+pub async fn store_heartbeats(
+    tx: &mut Transaction<'_, Postgres>,
+    rows: &[HeartbeatRow<'_>],
+) -> Result<()> {
+    for rows in rows.chunks(MAX_CLIENTS_PER_STATEMENT) {
+        store_heartbeat_chunk(tx, rows).await?;
+    }
+    Ok(())
+}
+
+async fn store_heartbeat_chunk(
+    tx: &mut Transaction<'_, Postgres>,
+    rows: &[HeartbeatRow<'_>],
+) -> Result<()> {
+    let client_ids: Vec<ClientId> = rows.iter().map(|row| *row.client_id).collect();
+    let mut days: Vec<NaiveDate> = rows.iter().map(|row| row.timestamp.date_naive()).collect();
+    days.sort();
+    days.dedup();
+    let intervals = heartbeat_intervals(tx, &days).await?;
+
+    let mut query_builder = QueryBuilder::<Postgres>::new(format!(
+        "INSERT INTO {} (client_id, cpu_usage, mem_usage, disk_usage, network_up, network_down, timestamp) ",
+        HEARTBEAT_TABLE
+    ));
+    query_builder.push_values(rows, |mut b, row| {
+        b.push_bind(row.client_id)
+            .push_bind(row.system_info.cpu_usage as i16)
+            .push_bind(row.system_info.memory_usage as i16)
+            .push_bind(row.system_info.disk_usage as i16)
+            .push_bind(row.system_info.network_tx as i64)
+            .push_bind(row.system_info.network_rx as i64)
+            .push_bind(row.timestamp);
+    });
+    query_builder.push(" ON CONFLICT (client_id, timestamp) DO NOTHING");
+    query_builder.build().execute(&mut **tx).await?;
+
+    sqlx::query(&format!(
+        "UPDATE {} SET client_status = CASE WHEN maintenance THEN 'maintenance' ELSE 'online' END, \
+         updated_at = NOW() WHERE client_id = ANY($1) AND valid_status = 'valid'",
+        GPU_ASSETS_TABLE
+    ))
+    .bind(&client_ids)
+    .execute(&mut **tx)
+    .await?;
+
+    let versions: Vec<(&ClientId, &WorkerVersion)> = rows
+        .iter()
+        .filter_map(|row| Some((row.client_id, row.worker_version?)))
+        .collect();
+    if !versions.is_empty() {
+        let mut query_builder = QueryBuilder::<Postgres>::new(format!(
+            "UPDATE {} AS ga SET worker_version = v.worker_version, \
+             engine_versions = v.engine_versions, build_features = v.build_features, \
+             os_version = v.os_version, version_reported_at = NOW() FROM (",
+            GPU_ASSETS_TABLE
+        ));
+        query_builder.push_values(&versions, |mut b, (client_id, version)| {
+            b.push_bind(*client_id)
+                .push_bind(&version.version)
+                .push_bind(Json(&version.engines))
+                .push_bind(&version.features)
+                .push_bind(&version.os_version);
+        });
+        query_builder.push(
+            ") AS v (client_id, worker_version, engine_versions, build_features, os_version) \
+             WHERE ga.client_id = v.client_id",
+        );
+        query_builder.build().execute(&mut **tx).await?;
+    }
+
+    let mut query_builder = QueryBuilder::<Postgres>::new(format!(
+        "INSERT INTO {} (client_id, cpu_usage, mem_usage, disk_usage, total_tflops, \
+         device_memsize, device_count, created_at, updated_at) ",
+        SYSTEM_INFO_TABLE
+    ));
+    query_builder.push_values(rows, |mut b, row| {
+        b.push_bind(row.client_id)
+            .push_bind(row.system_info.cpu_usage as i16)
+            .push_bind(row.system_info.memory_usage as i16)
+            .push_bind(row.system_info.disk_usage as i16)
+            .push_bind(row.total_tflops)
+            .push_bind(row.device_memtotal_gb)
+            .push_bind(row.device_count)
+            .push("NOW()")
+            .push("NOW()");
+    });
+    query_builder.push(
+        " ON CONFLICT (client_id) DO UPDATE SET \
+         cpu_usage = EXCLUDED.cpu_usage, \
+         mem_usage = EXCLUDED.mem_usage, \
+         disk_usage = EXCLUDED.disk_usage, \
+         total_tflops = EXCLUDED.total_tflops, \
+         device_memsize = EXCLUDED.device_memsize, \
+         device_count = EXCLUDED.device_count, \
+         updated_at = NOW()",
+    );
+    query_builder.build().execute(&mut **tx).await?;
+
+    sqlx::query(&format!(
+        "DELETE FROM {} WHERE client_id = ANY($1)",
+        DEVICE_INFO_TABLE
+    ))
+    .bind(&client_ids)
+    .execute(&mut **tx)
+    .await?;
+
+    let devices: Vec<DeviceRow> = rows
+        .iter()
+        .flat_map(|row| device_rows(row.client_id, row.devices_info, row.timestamp))
+        .collect();
+    if !devices.is_empty() {
+        let mut query_builder = QueryBuilder::<Postgres>::new(format!(
+            "INSERT INTO {} (client_id, device_name, device_index, device_id, vendor_id, \
+             device_memusage, device_gpuusage, device_powerusage, device_temp, \
+             created_at, updated_at) ",
+            DEVICE_INFO_TABLE
+        ));
+        query_builder.push_values(&devices, |mut b, row| {
+            let index = row.index as usize;
+            b.push_bind(row.client_id)
+                .push_bind(row.device_name())
+                .push_bind(row.index as i16)
+                .push_bind(get_u16_from_u128(row.device.device_id, index) as i32)
+                .push_bind(get_u16_from_u128(row.device.vendor_id, index) as i32)
+                .push_bind(get_u8_from_u64(row.device.mem_usage, index) as i16)
+                .push_bind(get_u8_from_u64(row.device.usage, index) as i16)
+                .push_bind(get_u8_from_u64(row.device.power_usage, index) as i16)
+                .push_bind(get_u8_from_u64(row.device.temp, index) as i16)
+                .push("NOW()")
+                .push("NOW()");
+        });
+        query_builder.build().execute(&mut **tx).await?;
+    }
+
+    ClientDailyStats::upsert_rows(tx, rows, &intervals).await?;
+    ClientDailyStats::record_owner_model(tx, rows).await?;
+    DeviceDailyStats::upsert_rows(tx, &devices, &intervals).await?;
+    Ok(())
+}
+
 #[derive(Debug, Serialize)]
 pub struct ClientStatResponse {
     pub systems_total_number: i64,
@@ -637,6 +940,7 @@ async fn test_device_daily_stats() {
 
 #[derive(Debug, Validate, Serialize, Deserialize)]
 pub struct EditClientRequest {
+    #[serde(default)]
     #[validate(length(min = 1, max = 255))]
     pub user_id: String,
     #[validate(length(min = 1, max = 255))]
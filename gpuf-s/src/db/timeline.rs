@@ -0,0 +1,318 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, Pool, Postgres};
+
+use crate::db::{
+    CANARY_RESULTS_TABLE, CLIENT_DAILY_STATS_TABLE, CLIENT_MODELS_TABLE, HEARTBEAT_ANOMALIES_TABLE,
+    INFERENCE_ERRORS_TABLE, MODEL_ROLLOUT_RESULTS_TABLE, WORKER_EVENTS_TABLE,
+};
+use crate::util::protoc::ClientId;
+
+/// Something that happened to a client, as shown on its timeline
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TimelineEvent {
+    Connected {
+        /// Peer address
+        detail: Option<String>,
+    },
+    Disconnected {
+        /// Reason of the disconnect
+        detail: Option<String>,
+    },
+    ModelLoaded {
+        model: Option<String>,
+    },
+    ModelUnloaded {
+        model: Option<String>,
+    },
+    HeartbeatAnomaly {
+        kind: String,
+        detail: Option<String>,
+        rejected: bool,
+    },
+    /// Inference failure the worker reported
+    ErrorReported {
+        severity: String,
+        kind: String,
+        model: Option<String>,
+        message: String,
+    },
+    /// Known-answer prompt the worker did not pass
+    CanaryFailed {
+        verdict: String,
+        latency_ms: i64,
+    },
+    /// Download of a canary catalog entry the worker reported as failed
+    ModelDownloadFailed {
+        model: String,
+        version: String,
+        error: Option<String>,
+    },
+    /// Aggregates of one day, placed at its start
+    DailyStats {
+        heartbeats: i32,
+        avg_cpu_usage: Option<f64>,
+        avg_memory_usage: Option<f64>,
+        avg_disk_usage: Option<f64>,
+        network_in_bytes: Option<i64>,
+        network_out_bytes: Option<i64>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimelineEntry {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: TimelineEvent,
+}
+
+#[derive(FromRow)]
+struct WorkerEventRow {
+    kind: String,
+    model: Option<String>,
+    detail: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct AnomalyRow {
+    kind: String,
+    detail: Option<String>,
+    rejected: bool,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct ErrorReportRow {
+    severity: String,
+    kind: String,
+    model: Option<String>,
+    message: String,
+    occurred_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct CanaryRow {
+    verdict: String,
+    latency_ms: i64,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct DownloadFailureRow {
+    model: String,
+    version: String,
+    error: Option<String>,
+    reported_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct DailyStatsRow {
+    date: NaiveDate,
+    total_heartbeats: i32,
+    avg_cpu_usage: Option<f64>,
+    avg_memory_usage: Option<f64>,
+    avg_disk_usage: Option<f64>,
+    total_network_in_bytes: Option<i64>,
+    total_network_out_bytes: Option<i64>,
+}
+
+fn worker_event(row: WorkerEventRow) -> Option<TimelineEntry> {
+    let event = match row.kind.as_str() {
+        "connected" => TimelineEvent::Connected { detail: row.detail },
+        "disconnected" => TimelineEvent::Disconnected { detail: row.detail },
+        "model_loaded" => TimelineEvent::ModelLoaded { model: row.model },
+        "model_unloaded" => TimelineEvent::ModelUnloaded { model: row.model },
+        _ => return None,
+    };
+    Some(TimelineEntry {
+        at: row.created_at,
+        event,
+    })
+}
+
+/// Sort entries by time. Entries at the same instant keep the order of the
+/// sources they were read from.
+fn merge(mut entries: Vec<TimelineEntry>) -> Vec<TimelineEntry> {
+    entries.sort_by_key(|entry| entry.at);
+    entries
+}
+
+/// Events and daily stats of a client from `start` to `end`, both days
+/// included, oldest first
+pub async fn get_client_timeline(
+    pool: &Pool<Postgres>,
+    client_id: &ClientId,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<Vec<TimelineEntry>> {
+    let mut entries = Vec::new();
+
+    let events: Vec<WorkerEventRow> = sqlx::query_as(&format!(
+        "SELECT kind, model, detail, created_at FROM {} \
+         WHERE client_id = $1 AND created_at >= $2::date AND created_at < $3::date + 1",
+        WORKER_EVENTS_TABLE
+    ))
+    .bind(client_id)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+    entries.extend(events.into_iter().filter_map(worker_event));
+
+    let anomalies: Vec<AnomalyRow> = sqlx::query_as(&format!(
+        "SELECT kind, detail, rejected, created_at FROM {} \
+         WHERE client_id = $1 AND created_at >= $2::date AND created_at < $3::date + 1",
+        HEARTBEAT_ANOMALIES_TABLE
+    ))
+    .bind(client_id)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+    entries.extend(anomalies.into_iter().map(|row| TimelineEntry {
+        at: row.created_at,
+        event: TimelineEvent::HeartbeatAnomaly {
+            kind: row.kind,
+            detail: row.detail,
+            rejected: row.rejected,
+        },
+    }));
+
+    let reports: Vec<ErrorReportRow> = sqlx::query_as(&format!(
+        "SELECT severity, kind, model, message, occurred_at FROM {} \
+         WHERE client_id = $1 AND occurred_at >= $2::date AND occurred_at < $3::date + 1",
+        INFERENCE_ERRORS_TABLE
+    ))
+    .bind(client_id)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+    entries.extend(reports.into_iter().map(|row| TimelineEntry {
+        at: row.occurred_at,
+        event: TimelineEvent::ErrorReported {
+            severity: row.severity,
+            kind: row.kind,
+            model: row.model,
+            message: row.message,
+        },
+    }));
+
+    let canaries: Vec<CanaryRow> = sqlx::query_as(&format!(
+        "SELECT verdict, latency_ms, created_at FROM {} \
+         WHERE client_id = $1 AND verdict <> 'passed' \
+         AND created_at >= $2::date AND created_at < $3::date + 1",
+        CANARY_RESULTS_TABLE
+    ))
+    .bind(client_id)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+    entries.extend(canaries.into_iter().map(|row| TimelineEntry {
+        at: row.created_at,
+        event: TimelineEvent::CanaryFailed {
+            verdict: row.verdict,
+            latency_ms: row.latency_ms,
+        },
+    }));
+
+    let downloads: Vec<DownloadFailureRow> = sqlx::query_as(&format!(
+        "SELECT m.name AS model, m.version, r.error, r.reported_at FROM {} r \
+         JOIN {} m ON m.id = r.model_id \
+         WHERE r.client_id = $1 AND NOT r.succeeded \
+         AND r.reported_at >= $2::date AND r.reported_at < $3::date + 1",
+        MODEL_ROLLOUT_RESULTS_TABLE, CLIENT_MODELS_TABLE
+    ))
+    .bind(client_id)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+    entries.extend(downloads.into_iter().map(|row| TimelineEntry {
+        at: row.reported_at,
+        event: TimelineEvent::ModelDownloadFailed {
+            model: row.model,
+            version: row.version,
+            error: row.error,
+        },
+    }));
+
+    let days: Vec<DailyStatsRow> = sqlx::query_as(&format!(
+        "SELECT date, total_heartbeats, avg_cpu_usage, avg_memory_usage, avg_disk_usage, \
+         total_network_in_bytes, total_network_out_bytes FROM {} \
+         WHERE client_id = $1 AND date BETWEEN $2 AND $3",
+        CLIENT_DAILY_STATS_TABLE
+    ))
+    .bind(client_id)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await?;
+    entries.extend(days.into_iter().map(|row| TimelineEntry {
+        at: row.date.and_time(chrono::NaiveTime::MIN).and_utc(),
+        event: TimelineEvent::DailyStats {
+            heartbeats: row.total_heartbeats,
+            avg_cpu_usage: row.avg_cpu_usage,
+            avg_memory_usage: row.avg_memory_usage,
+            avg_disk_usage: row.avg_disk_usage,
+            network_in_bytes: row.total_network_in_bytes,
+            network_out_bytes: row.total_network_out_bytes,
+        },
+    }));
+
+    Ok(merge(entries))
+}
+
+#[test]
+fn test_merge_timeline() {
+    let at = |h: u32| {
+        NaiveDate::from_ymd_opt(2025, 8, 2)
+            .unwrap()
+            .and_hms_opt(h, 0, 0)
+            .unwrap()
+            .and_utc()
+    };
+    let row = |kind: &str, h| WorkerEventRow {
+        kind: kind.to_string(),
+        model: Some("llama3".to_string()),
+        detail: None,
+        created_at: at(h),
+    };
+
+    assert!(worker_event(row("restarted", 1)).is_none());
+    let mut entries: Vec<TimelineEntry> = [row("model_loaded", 9), row("connected", 8)]
+        .into_iter()
+        .filter_map(worker_event)
+        .collect();
+    entries.push(TimelineEntry {
+        at: at(0),
+        event: TimelineEvent::DailyStats {
+            heartbeats: 1440,
+            avg_cpu_usage: Some(12.5),
+            avg_memory_usage: None,
+            avg_disk_usage: None,
+            network_in_bytes: Some(0),
+            network_out_bytes: Some(0),
+        },
+    });
+
+    let merged = merge(entries);
+    let types: Vec<String> = merged
+        .iter()
+        .map(|entry| serde_json::to_value(entry).unwrap()["type"].to_string())
+        .collect();
+    assert_eq!(
+        types,
+        ["\"daily_stats\"", "\"connected\"", "\"model_loaded\""]
+    );
+    assert_eq!(
+        merged[2].event,
+        TimelineEvent::ModelLoaded {
+            model: Some("llama3".to_string())
+        }
+    );
+}
@@ -0,0 +1,486 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Postgres, QueryBuilder};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, warn};
+
+use crate::db::{TOKENS_TABLE, USAGE_RECORDS_TABLE};
+use crate::util::msg::{FilterOp, ListField, ListQuery, ListSpec, Page};
+use crate::util::protoc::ClientId;
+
+const USAGE_CHANNEL_CAPACITY: usize = 10_000;
+
+/// Topic usage records are published to, as JSON keyed by client id, when the
+/// server leaves writing them to the consumer
+pub const INFERENCE_USAGE_TOPIC: &str = "inference-usage";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageStatus {
+    Success,
+    Error,
+    Timeout,
+    Cancelled,
+    /// Forwarded through the raw public proxy before the proxy read the
+    /// response, kept for those records
+    Proxied,
+}
+
+impl UsageStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UsageStatus::Success => "success",
+            UsageStatus::Error => "error",
+            UsageStatus::Timeout => "timeout",
+            UsageStatus::Cancelled => "cancelled",
+            UsageStatus::Proxied => "proxied",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub request_id: Option<String>,
+    pub api_key: String,
+    pub client_id: ClientId,
+    pub model: String,
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    pub latency_ms: i64,
+    pub status: UsageStatus,
+    pub created_at: DateTime<Utc>,
+}
+
+pub async fn insert_usage_records(
+    pool: &Pool<Postgres>,
+    records: &[UsageRecord],
+) -> Result<(), sqlx::Error> {
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    let mut query_builder = QueryBuilder::<Postgres>::new("INSERT INTO ");
+    query_builder.push(USAGE_RECORDS_TABLE).push(
+        " (request_id, api_key, client_id, model, prompt_tokens, completion_tokens, latency_ms, status, created_at) ",
+    );
+    query_builder.push_values(records, |mut b, r| {
+        b.push_bind(&r.request_id)
+            .push_bind(&r.api_key)
+            .push_bind(r.client_id)
+            .push_bind(&r.model)
+            .push_bind(r.prompt_tokens)
+            .push_bind(r.completion_tokens)
+            .push_bind(r.latency_ms)
+            .push_bind(r.status.as_str())
+            .push_bind(r.created_at);
+    });
+
+    query_builder.build().execute(pool).await?;
+    Ok(())
+}
+
+/// Buffers usage records in memory and writes them in batches, so request
+/// paths never wait on the database. With a Kafka producer the records are
+/// published to `INFERENCE_USAGE_TOPIC` instead and the consumer writes them.
+pub struct UsageRecorder {
+    sink: UsageSink,
+}
+
+enum UsageSink {
+    Database(mpsc::Sender<UsageMessage>),
+    Kafka(Arc<FutureProducer>),
+}
+
+enum UsageMessage {
+    Record(UsageRecord),
+    /// Write the buffered records now and answer once they are written
+    Flush(oneshot::Sender<()>),
+}
+
+impl UsageRecorder {
+    pub fn start(pool: Arc<Pool<Postgres>>, batch_size: usize, flush_interval: Duration) -> Self {
+        let (tx, mut rx) = mpsc::channel::<UsageMessage>(USAGE_CHANNEL_CAPACITY);
+
+        tokio::spawn(async move {
+            let mut buffer: Vec<UsageRecord> = Vec::with_capacity(batch_size);
+            let mut ticker = tokio::time::interval(flush_interval);
+
+            loop {
+                tokio::select! {
+                    message = rx.recv() => match message {
+                        Some(UsageMessage::Record(record)) => {
+                            buffer.push(record);
+                            if buffer.len() >= batch_size {
+                                flush_usage_records(&pool, &mut buffer).await;
+                            }
+                        }
+                        Some(UsageMessage::Flush(done)) => {
+                            flush_usage_records(&pool, &mut buffer).await;
+                            let _ = done.send(());
+                        }
+                        None => {
+                            flush_usage_records(&pool, &mut buffer).await;
+                            break;
+                        }
+                    },
+                    _ = ticker.tick() => {
+                        flush_usage_records(&pool, &mut buffer).await;
+                    }
+                }
+            }
+        });
+
+        Self {
+            sink: UsageSink::Database(tx),
+        }
+    }
+
+    pub fn kafka(producer: Arc<FutureProducer>) -> Self {
+        Self {
+            sink: UsageSink::Kafka(producer),
+        }
+    }
+
+    pub fn record(&self, record: UsageRecord) {
+        match &self.sink {
+            UsageSink::Database(tx) => {
+                if let Err(e) = tx.try_send(UsageMessage::Record(record)) {
+                    warn!("Dropping usage record: {}", e);
+                }
+            }
+            UsageSink::Kafka(producer) => {
+                let payload = match serde_json::to_vec(&record) {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        warn!("Dropping usage record: {}", e);
+                        return;
+                    }
+                };
+                let key = record.client_id.to_string();
+                // Only enqueues, the producer delivers in the background
+                if let Err((e, _)) = producer.send_result(
+                    FutureRecord::to(INFERENCE_USAGE_TOPIC)
+                        .payload(&payload)
+                        .key(&key),
+                ) {
+                    warn!("Dropping usage record: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Write the records buffered so far, for shutdown. Published records
+    /// are left to flushing the Kafka producer.
+    pub async fn flush(&self) {
+        let UsageSink::Database(tx) = &self.sink else {
+            return;
+        };
+        let (done, written) = oneshot::channel();
+        if tx.send(UsageMessage::Flush(done)).await.is_ok() {
+            let _ = written.await;
+        }
+    }
+}
+
+async fn flush_usage_records(pool: &Pool<Postgres>, buffer: &mut Vec<UsageRecord>) {
+    if buffer.is_empty() {
+        return;
+    }
+    if let Err(e) = insert_usage_records(pool, buffer).await {
+        error!("Failed to write {} usage records: {}", buffer.len(), e);
+    }
+    buffer.clear();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageDimension {
+    Day,
+    Hour,
+    Model,
+    ClientId,
+    ApiKey,
+    Status,
+}
+
+impl UsageDimension {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim() {
+            "day" => Some(Self::Day),
+            "hour" => Some(Self::Hour),
+            "model" => Some(Self::Model),
+            "client_id" => Some(Self::ClientId),
+            "api_key" => Some(Self::ApiKey),
+            "status" => Some(Self::Status),
+            _ => None,
+        }
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Hour => "hour",
+            Self::Model => "model",
+            Self::ClientId => "client_id",
+            Self::ApiKey => "api_key",
+            Self::Status => "status",
+        }
+    }
+
+    fn select_expr(self, selected: bool) -> &'static str {
+        match (self, selected) {
+            (Self::Day, true) => "(u.created_at AT TIME ZONE 'UTC')::date",
+            (Self::Day, false) => "NULL::date",
+            (Self::Hour, true) => "date_trunc('hour', u.created_at)",
+            (Self::Hour, false) => "NULL::timestamptz",
+            (Self::Model, true) => "u.model",
+            (Self::ClientId, true) => "encode(u.client_id, 'hex')",
+            (Self::ApiKey, true) => "u.api_key::text",
+            (Self::Status, true) => "u.status",
+            (_, false) => "NULL::text",
+        }
+    }
+
+    fn position(self) -> usize {
+        Self::ALL.iter().position(|d| *d == self).unwrap_or(0) + 1
+    }
+
+    const ALL: [UsageDimension; 6] = [
+        Self::Day,
+        Self::Hour,
+        Self::Model,
+        Self::ClientId,
+        Self::ApiKey,
+        Self::Status,
+    ];
+}
+
+/// Parse a comma separated `group_by` parameter such as `day,model`
+pub fn parse_group_by(group_by: &str) -> Result<Vec<UsageDimension>, String> {
+    let mut dims = Vec::new();
+    for part in group_by.split(',').filter(|p| !p.trim().is_empty()) {
+        let dim = UsageDimension::parse(part)
+            .ok_or_else(|| format!("Unsupported group_by dimension: {}", part.trim()))?;
+        if !dims.contains(&dim) {
+            dims.push(dim);
+        }
+    }
+    Ok(dims)
+}
+
+#[derive(Debug, Default)]
+pub struct UsageFilter {
+    pub user_id: Option<i64>,
+    /// Records of the organization's keys
+    pub org_id: Option<i64>,
+    pub api_key: Option<String>,
+    pub client_id: Option<ClientId>,
+    pub model: Option<String>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct UsageAggregate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub day: Option<NaiveDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hour: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    pub request_count: i64,
+    pub error_count: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub total_tokens: i64,
+    pub avg_latency_ms: f64,
+}
+
+/// Sort and filter fields of usage aggregates, dimensions left out of
+/// `group_by` sort as if empty
+pub static USAGE_LIST: ListSpec = ListSpec {
+    fields: &[
+        ListField {
+            name: "day",
+            expr: "COALESCE(l.day, 'epoch'::date)",
+            sql_type: "date",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "hour",
+            expr: "COALESCE(l.hour, 'epoch'::timestamptz)",
+            sql_type: "timestamptz",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "model",
+            expr: "COALESCE(l.model, '')",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Contains),
+        },
+        ListField {
+            name: "client_id",
+            expr: "COALESCE(l.client_id, '')",
+            sql_type: "text",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "api_key",
+            expr: "COALESCE(l.api_key, '')",
+            sql_type: "text",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "status",
+            expr: "COALESCE(l.status, '')",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Eq),
+        },
+        ListField {
+            name: "request_count",
+            expr: "l.request_count",
+            sql_type: "bigint",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "error_count",
+            expr: "l.error_count",
+            sql_type: "bigint",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "total_tokens",
+            expr: "l.total_tokens",
+            sql_type: "bigint",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "avg_latency_ms",
+            expr: "l.avg_latency_ms",
+            sql_type: "double precision",
+            sortable: true,
+            filter: None,
+        },
+    ],
+    default_sort: "day",
+    key: "concat_ws('|', l.day, l.hour, l.model, l.client_id, l.api_key, l.status)",
+};
+
+pub async fn get_usage_aggregates(
+    pool: &Pool<Postgres>,
+    group_by: &[UsageDimension],
+    filter: &UsageFilter,
+    list: &ListQuery,
+) -> Result<Page<UsageAggregate>, sqlx::Error> {
+    list.fetch(pool, |query_builder| {
+        push_usage_query(query_builder, group_by, filter)
+    })
+    .await
+}
+
+fn push_usage_query(
+    query_builder: &mut QueryBuilder<'_, Postgres>,
+    group_by: &[UsageDimension],
+    filter: &UsageFilter,
+) {
+    query_builder.push("SELECT ");
+    for dim in UsageDimension::ALL {
+        query_builder
+            .push(dim.select_expr(group_by.contains(&dim)))
+            .push(" AS ")
+            .push(dim.column())
+            .push(", ");
+    }
+    query_builder.push(
+        "COUNT(*) AS request_count, \
+         COUNT(*) FILTER (WHERE u.status IN ('error', 'timeout')) AS error_count, \
+         COALESCE(SUM(u.prompt_tokens), 0)::BIGINT AS prompt_tokens, \
+         COALESCE(SUM(u.completion_tokens), 0)::BIGINT AS completion_tokens, \
+         COALESCE(SUM(u.prompt_tokens + u.completion_tokens), 0)::BIGINT AS total_tokens, \
+         COALESCE(AVG(u.latency_ms), 0)::DOUBLE PRECISION AS avg_latency_ms FROM ",
+    );
+    query_builder.push(USAGE_RECORDS_TABLE).push(" u");
+
+    if filter.user_id.is_some() || filter.org_id.is_some() {
+        query_builder
+            .push(" JOIN ")
+            .push(TOKENS_TABLE)
+            .push(" t ON t.key = u.api_key");
+    }
+    query_builder.push(" WHERE 1=1");
+
+    if let Some(user_id) = filter.user_id {
+        query_builder.push(" AND t.user_id = ").push_bind(user_id);
+    }
+    if let Some(org_id) = filter.org_id {
+        query_builder.push(" AND t.org_id = ").push_bind(org_id);
+    }
+    if let Some(api_key) = &filter.api_key {
+        query_builder
+            .push(" AND u.api_key = ")
+            .push_bind(api_key.clone());
+    }
+    if let Some(client_id) = filter.client_id {
+        query_builder
+            .push(" AND u.client_id = ")
+            .push_bind(client_id);
+    }
+    if let Some(model) = &filter.model {
+        query_builder
+            .push(" AND u.model = ")
+            .push_bind(model.clone());
+    }
+    if let Some(start_date) = filter.start_date {
+        query_builder
+            .push(" AND u.created_at >= ")
+            .push_bind(start_date)
+            .push("::date");
+    }
+    if let Some(end_date) = filter.end_date {
+        query_builder
+            .push(" AND u.created_at < ")
+            .push_bind(end_date)
+            .push("::date + 1");
+    }
+
+    if !group_by.is_empty() {
+        // Group by output position, the aliases clash with columns of the joined tables
+        let positions = group_by
+            .iter()
+            .map(|dim| dim.position().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        query_builder.push(" GROUP BY ").push(positions);
+    }
+}
+
+#[test]
+fn test_parse_group_by() {
+    assert_eq!(
+        parse_group_by("day,model").unwrap(),
+        vec![UsageDimension::Day, UsageDimension::Model]
+    );
+    assert_eq!(
+        parse_group_by(" model , model,").unwrap(),
+        vec![UsageDimension::Model]
+    );
+    assert!(parse_group_by("").unwrap().is_empty());
+    assert!(parse_group_by("day,user").is_err());
+}
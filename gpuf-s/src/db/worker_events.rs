@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, QueryBuilder};
+use tracing::warn;
+
+use crate::db::WORKER_EVENTS_TABLE;
+use crate::util::protoc::ClientId;
+
+/// Topic the server publishes worker events to, as JSON keyed by client id
+pub const WORKER_EVENTS_TOPIC: &str = "worker-events";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerEventKind {
+    Connected,
+    Disconnected,
+    ModelLoaded,
+    ModelUnloaded,
+}
+
+impl WorkerEventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Connected => "connected",
+            Self::Disconnected => "disconnected",
+            Self::ModelLoaded => "model_loaded",
+            Self::ModelUnloaded => "model_unloaded",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerEvent {
+    pub client_id: ClientId,
+    pub kind: WorkerEventKind,
+    /// Model of a model event
+    pub model: Option<String>,
+    /// Peer address of a connect, reason of a disconnect
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl WorkerEvent {
+    pub fn new(client_id: ClientId, kind: WorkerEventKind) -> Self {
+        Self {
+            client_id,
+            kind,
+            model: None,
+            detail: None,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+/// Enqueue an event on the producer. Delivery errors are only logged, the
+/// event is lost then.
+pub fn publish_worker_event(producer: &FutureProducer, event: &WorkerEvent) {
+    let payload = match serde_json::to_vec(event) {
+        Ok(payload) => payload,
+        Err(e) => {
+            warn!("Dropping worker event: {}", e);
+            return;
+        }
+    };
+    let key = event.client_id.to_string();
+    if let Err((e, _)) = producer.send_result(
+        FutureRecord::to(WORKER_EVENTS_TOPIC)
+            .payload(&payload)
+            .key(&key),
+    ) {
+        warn!(
+            "Dropping {} event of client {}: {}",
+            event.kind.as_str(),
+            event.client_id,
+            e
+        );
+    }
+}
+
+pub async fn insert_worker_events(
+    pool: &Pool<Postgres>,
+    events: &[WorkerEvent],
+) -> Result<(), sqlx::Error> {
+    if events.is_empty() {
+        return Ok(());
+    }
+
+    let mut query_builder = QueryBuilder::<Postgres>::new("INSERT INTO ");
+    query_builder
+        .push(WORKER_EVENTS_TABLE)
+        .push(" (client_id, kind, model, detail, created_at) ");
+    query_builder.push_values(events, |mut b, e| {
+        b.push_bind(e.client_id)
+            .push_bind(e.kind.as_str())
+            .push_bind(&e.model)
+            .push_bind(&e.detail)
+            .push_bind(e.created_at);
+    });
+
+    query_builder.build().execute(pool).await?;
+    Ok(())
+}
+
+#[test]
+fn test_worker_event_json() {
+    let mut event = WorkerEvent::new(ClientId([0xab; 16]), WorkerEventKind::ModelLoaded);
+    event.model = Some("llama-3-8b".to_string());
+
+    let json = serde_json::to_value(&event).unwrap();
+    assert_eq!(json["kind"], "model_loaded");
+    assert_eq!(json["client_id"], "ab".repeat(16));
+
+    let decoded: WorkerEvent = serde_json::from_value(json).unwrap();
+    assert_eq!(decoded.client_id, event.client_id);
+    assert_eq!(decoded.kind, WorkerEventKind::ModelLoaded);
+    assert_eq!(decoded.model.as_deref(), Some("llama-3-8b"));
+}
@@ -0,0 +1,988 @@
+use super::*;
+
+use crate::api_server::audit::{self, AuditTrail, Auditor};
+use crate::api_server::auth::{self, ApiAuth, Principal};
+use crate::db::staff::{self, NewDenial};
+use crate::db::{client, models};
+use crate::inference::cache::CacheStats;
+use crate::util::geo::{normalize_region, KeyRegion};
+use crate::util::msg::ApiResponse;
+use crate::util::rbac::Permission;
+use axum::{
+    extract::{MatchedPath, Path, Query, State},
+    http::{header, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use common::{is_newer_version, is_release_version, JobSpec, WorkerConfig};
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use tracing::warn;
+use uuid::Uuid;
+
+type AdminError = (StatusCode, Json<ApiResponse<()>>);
+type AdminResult<T> = Result<Json<ApiResponse<T>>, AdminError>;
+
+/// Live view of a connected worker
+#[derive(Debug, Serialize)]
+pub struct WorkerStatus {
+    pub client_id: String,
+    pub authed: bool,
+    pub version: u32,
+    pub maintenance: bool,
+    /// Paused by the worker itself, e.g. for heat or low battery
+    pub busy: bool,
+    /// Engine health last reported by the worker
+    pub engine_state: Option<EngineState>,
+    /// Model still loading or not warmed up
+    pub cold: bool,
+    pub connected_at: DateTime<Utc>,
+    pub last_heartbeat_secs: Option<u64>,
+    pub cpu_usage: Option<u8>,
+    pub memory_usage: Option<u8>,
+    pub disk_usage: Option<u8>,
+    pub total_tflops: Option<u32>,
+    pub device_count: usize,
+    pub models: Vec<String>,
+    pub config_version: Option<u64>,
+    pub config_acked: Option<u64>,
+    pub gpus: Vec<GpuMetrics>,
+    pub benchmark: Option<BenchmarkResult>,
+    pub network: Option<NetworkQuality>,
+    pub power: Option<PowerStatus>,
+    /// Canary check score, see `ClientInfo::quality_score`
+    pub quality_score: f32,
+    pub location: Location,
+    pub worker_version: Option<WorkerVersion>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FleetVersionsQuery {
+    /// Version workers are compared with, the published release's when omitted
+    pub min_version: Option<String>,
+}
+
+/// gpuf-c versions across the registered workers
+#[derive(Debug, Serialize)]
+pub struct FleetVersions {
+    /// Version the workers are compared with, None when neither
+    /// `min_version` nor a release is set
+    pub current: Option<String>,
+    /// Workers per gpuf-c version, `unknown` for those that never reported one
+    pub versions: BTreeMap<String, usize>,
+    /// Workers older than `current` or that never reported a version
+    pub outdated: Vec<client::ClientVersion>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PinModelRequest {
+    pub model: String,
+    /// Newest active version when omitted
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PinModelResponse {
+    pub model: String,
+    pub version: String,
+    /// Whether the assignment was pushed to a connected worker right away
+    pub pushed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PushConfigResponse {
+    pub version: u64,
+    /// Workers the config was sent to; each one acks separately
+    pub pushed: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MigrateRequest {
+    /// Address of the gpuf-s the workers move to
+    pub server_addr: String,
+    /// This server's control port when omitted
+    pub control_port: Option<u16>,
+    /// This server's proxy port when omitted
+    pub proxy_port: Option<u16>,
+    /// Seconds the workers' reconnects are spread over
+    #[serde(default)]
+    pub delay_window_secs: u32,
+    /// Every connected worker when omitted
+    pub client_ids: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MigrateResponse {
+    /// Workers told to move
+    pub sent: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReleaseResponse {
+    pub version: String,
+    /// Connected workers the release was sent to
+    pub sent: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApprovalRequest {
+    pub status: client::ApprovalStatus,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DispatchJobRequest {
+    pub spec: JobSpec,
+    /// Seconds the worker lets the job run, no limit when omitted
+    #[serde(default)]
+    pub timeout_secs: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DispatchJobResponse {
+    pub job_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MaintenanceRequest {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyCacheRequest {
+    pub enabled: bool,
+}
+
+impl ServerState {
+    /// Serve the admin API on the admin port. The admin key acts as owner,
+    /// JWT users need a staff role that allows the endpoint.
+    pub async fn handle_admin_api(self: Arc<Self>, auth: ApiAuth) -> Result<()> {
+        let port = self.config.admin_port;
+        let app = Router::new()
+            .route("/admin/workers", get(list_workers))
+            .route("/admin/workers/config", post(push_config_all))
+            .route("/admin/workers/migrate", post(migrate_workers))
+            .route(
+                "/admin/workers/release",
+                get(get_worker_release).post(publish_worker_release),
+            )
+            .route("/admin/workers/versions", get(fleet_versions))
+            .route("/admin/workers/pending", get(list_pending_workers))
+            .route("/admin/workers/:client_id/approval", post(set_approval))
+            .route(
+                "/admin/workers/:client_id/disconnect",
+                post(disconnect_worker),
+            )
+            .route(
+                "/admin/workers/:client_id/pin",
+                post(pin_model).delete(unpin_model),
+            )
+            .route(
+                "/admin/workers/:client_id/maintenance",
+                post(set_maintenance),
+            )
+            .route("/admin/workers/:client_id/config", post(push_config))
+            .route("/admin/workers/:client_id/command", post(send_command))
+            .route(
+                "/admin/workers/:client_id/jobs",
+                get(list_jobs).post(dispatch_job),
+            )
+            .route(
+                "/admin/workers/:client_id/benchmarks",
+                get(list_model_benchmarks),
+            )
+            .route("/admin/keys/:key_id/region", post(set_key_region))
+            .route("/admin/keys/:key_id/cache", post(set_key_cache))
+            .route("/admin/cache/stats", get(cache_stats))
+            .route_layer(middleware::from_fn_with_state(
+                Auditor {
+                    pool: (*self.db_pool).clone(),
+                    service_actor: "admin_key",
+                    trusted_proxies: self.config.trusted_proxies.clone(),
+                },
+                audit::audit_middleware,
+            ))
+            .route_layer(middleware::from_fn_with_state(
+                (Arc::clone(&self), Arc::new(auth)),
+                admin_auth_middleware,
+            ))
+            .with_state(self);
+
+        let listener = self.config.listen_addr.bind(port)?;
+        info!("Admin API listening on port {}", port);
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .map_err(Into::into)
+    }
+}
+
+async fn admin_auth_middleware(
+    State((state, api_auth)): State<(Arc<ServerState>, Arc<ApiAuth>)>,
+    mut req: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .or_else(|| req.headers().get("x-api-key").and_then(|v| v.to_str().ok()));
+
+    let principal = match provided {
+        Some(credential) => api_auth.authenticate(credential).await.ok(),
+        None => None,
+    };
+    let user_id = match principal {
+        Some(Principal::User(user_id)) => user_id,
+        Some(principal) => {
+            req.extensions_mut().insert(principal);
+            return next.run(req).await;
+        }
+        None => {
+            warn!("Rejected admin API request to {}", req.uri().path());
+            return admin_error(StatusCode::UNAUTHORIZED, "Invalid admin key").into_response();
+        }
+    };
+
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| req.uri().path(), MatchedPath::as_str);
+    let permission = Permission::for_admin(req.method(), route);
+    let role = match staff::get_role(&state.db_pool, &user_id).await {
+        Ok(role) => role,
+        Err(e) => return internal_error(e.into()).into_response(),
+    };
+    if let Some(role) = role.filter(|role| role.allows(permission)) {
+        req.extensions_mut()
+            .insert(Principal::Staff { user_id, role });
+        return next.run(req).await;
+    }
+    let denial = NewDenial {
+        user_id: &user_id,
+        role,
+        permission,
+        method: req.method().as_str(),
+        path: req.uri().path(),
+    };
+    auth::record_denial(&state.db_pool, &denial).await;
+    admin_error(
+        StatusCode::FORBIDDEN,
+        format!("Requires the {} permission", permission.as_str()),
+    )
+    .into_response()
+}
+
+fn admin_error(status: StatusCode, message: impl Into<String>) -> AdminError {
+    (status, Json(ApiResponse::<()>::error(message.into())))
+}
+
+fn internal_error(e: anyhow::Error) -> AdminError {
+    error!("Admin API error: {}", e);
+    admin_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn parse_client_id(raw: &str) -> Result<ClientId, AdminError> {
+    ClientId::from_str(raw)
+        .map_err(|e| admin_error(StatusCode::BAD_REQUEST, format!("Invalid client_id: {}", e)))
+}
+
+pub async fn list_workers(State(state): State<Arc<ServerState>>) -> AdminResult<Vec<WorkerStatus>> {
+    let clients = state.active_clients.lock().await;
+    let mut workers: Vec<WorkerStatus> = clients
+        .iter()
+        .map(|(client_id, info)| {
+            let system_info = info.system_info.as_ref();
+            WorkerStatus {
+                client_id: client_id.to_string(),
+                authed: info.authed,
+                version: info.version,
+                maintenance: info.maintenance,
+                busy: info.busy,
+                engine_state: info.engine_state,
+                cold: info.cold,
+                connected_at: info.connected_at,
+                last_heartbeat_secs: system_info
+                    .and_then(|s| s.last_heartbeat.elapsed().ok())
+                    .map(|d| d.as_secs()),
+                cpu_usage: system_info.map(|s| s.cpu_usage),
+                memory_usage: system_info.map(|s| s.memory_usage),
+                disk_usage: system_info.map(|s| s.disk_usage),
+                total_tflops: system_info.map(|s| s.total_tflops),
+                device_count: info.devices_info.len(),
+                models: info
+                    .models
+                    .as_ref()
+                    .map(|models| models.iter().map(|m| m.id.clone()).collect())
+                    .unwrap_or_default(),
+                config_version: info.config_version,
+                config_acked: info.config_acked,
+                gpus: info.gpu_metrics.clone(),
+                benchmark: info.benchmark,
+                network: info.network_quality,
+                power: info.power,
+                quality_score: info.quality_score,
+                location: info.location.clone(),
+                worker_version: info.worker_version.clone(),
+            }
+        })
+        .collect();
+    drop(clients);
+
+    workers.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+    Ok(Json(ApiResponse::success(workers)))
+}
+
+pub async fn disconnect_worker(
+    State(state): State<Arc<ServerState>>,
+    Path(client_id): Path<String>,
+) -> AdminResult<()> {
+    let client_id = parse_client_id(&client_id)?;
+    let clients = state.active_clients.lock().await;
+    let Some(info) = clients.get(&client_id) else {
+        return Err(admin_error(
+            StatusCode::NOT_FOUND,
+            "Worker is not connected",
+        ));
+    };
+    info.disconnect.notify_one();
+    info!("Admin requested disconnect of client {}", client_id);
+    Ok(Json(ApiResponse::success(())))
+}
+
+/// Which gpuf-c versions the fleet runs and which workers are outdated,
+/// offline ones included
+pub async fn fleet_versions(
+    State(state): State<Arc<ServerState>>,
+    Query(query): Query<FleetVersionsQuery>,
+) -> AdminResult<FleetVersions> {
+    let current = query.min_version.or_else(|| {
+        let release = state.worker_release.read().unwrap();
+        release.as_ref().map(|release| release.version.clone())
+    });
+    let workers = client::list_client_versions(&state.db_pool)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(ApiResponse::success(summarize_versions(
+        workers, current,
+    ))))
+}
+
+fn summarize_versions(
+    workers: Vec<client::ClientVersion>,
+    current: Option<String>,
+) -> FleetVersions {
+    let mut versions = BTreeMap::new();
+    let mut outdated = Vec::new();
+    for worker in workers {
+        let version = worker.worker_version.as_deref().unwrap_or("unknown");
+        *versions.entry(version.to_string()).or_insert(0) += 1;
+        let is_outdated = current.as_deref().is_some_and(|current| {
+            worker
+                .worker_version
+                .as_deref()
+                .map_or(true, |version| is_newer_version(current, version))
+        });
+        if is_outdated {
+            outdated.push(worker);
+        }
+    }
+    FleetVersions {
+        current,
+        versions,
+        outdated,
+    }
+}
+
+pub async fn list_pending_workers(
+    State(state): State<Arc<ServerState>>,
+) -> AdminResult<Vec<client::PendingClient>> {
+    let pending = client::list_pending_clients(&state.db_pool)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(ApiResponse::success(pending)))
+}
+
+pub async fn set_approval(
+    State(state): State<Arc<ServerState>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path(client_id): Path<String>,
+    Json(payload): Json<ApprovalRequest>,
+) -> AdminResult<()> {
+    let client_id = parse_client_id(&client_id)?;
+    let current = client::get_client_approval(&state.db_pool, &client_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Unknown worker"))?;
+    audit::set_before(trail.as_deref(), &ApprovalRequest { status: current });
+    if !current.can_become(payload.status) {
+        return Err(admin_error(
+            StatusCode::CONFLICT,
+            format!(
+                "Worker is {} and cannot become {}",
+                current.as_str(),
+                payload.status.as_str()
+            ),
+        ));
+    }
+    client::set_client_approval(&state.db_pool, &client_id, payload.status)
+        .await
+        .map_err(internal_error)?;
+    info!(
+        "Approval of client {} changed from {} to {}",
+        client_id,
+        current.as_str(),
+        payload.status.as_str()
+    );
+
+    // Rejected and banned workers lose their session, and a worker approved
+    // while connected logs in again to get its models
+    if let Some(info) = state.active_clients.lock().await.get(&client_id) {
+        if payload.status != client::ApprovalStatus::Approved || !info.authed {
+            info.disconnect.notify_one();
+        }
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+pub async fn pin_model(
+    State(state): State<Arc<ServerState>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path(client_id): Path<String>,
+    Json(payload): Json<PinModelRequest>,
+) -> AdminResult<PinModelResponse> {
+    let client_id = parse_client_id(&client_id)?;
+
+    let version =
+        models::find_model_version(&state.db_pool, &payload.model, payload.version.as_deref())
+            .await
+            .map_err(internal_error)?
+            .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Unknown model or version"))?;
+
+    let pinned = Some((payload.model.as_str(), version.as_str()));
+    let previous = client::set_client_pinned_model(&state.db_pool, &client_id, pinned)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Unknown worker"))?;
+    audit::set_before(trail.as_deref(), &previous);
+    info!(
+        "Pinned model {}:{} to client {}",
+        payload.model, version, client_id
+    );
+
+    // Push the new assignment to the worker if it is connected
+    let pushed = match state.hot_models.get_pinned_model(&client_id).await {
+        Ok(Some(pinned)) => {
+            let target = state
+                .active_clients
+                .lock()
+                .await
+                .get(&client_id)
+                .map(|info| (info.writer.clone(), info.version, info.devices_info.clone()));
+            match target {
+                Some((writer, protocol, devices_info)) => {
+                    let command = CommandV1::PullModelResultV2 {
+                        pods_model: models::pinned_pods_model(&devices_info, &pinned),
+                        error: None,
+                    };
+                    write_command_for(&mut *writer.lock().await, &Command::V1(command), protocol)
+                        .await
+                        .map_err(internal_error)?
+                }
+                None => false,
+            }
+        }
+        Ok(None) => false,
+        Err(e) => return Err(internal_error(e)),
+    };
+
+    Ok(Json(ApiResponse::success(PinModelResponse {
+        model: payload.model,
+        version,
+        pushed,
+    })))
+}
+
+pub async fn unpin_model(
+    State(state): State<Arc<ServerState>>,
+    trail: Option<Extension<AuditTrail>>,
+    Path(client_id): Path<String>,
+) -> AdminResult<()> {
+    let client_id = parse_client_id(&client_id)?;
+    let previous = client::set_client_pinned_model(&state.db_pool, &client_id, None)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Unknown worker"))?;
+    audit::set_before(trail.as_deref(), &previous);
+    info!("Unpinned model of client {}", client_id);
+    Ok(Json(ApiResponse::success(())))
+}
+
+pub async fn set_maintenance(
+    State(state): State<Arc<ServerState>>,
+    Path(client_id): Path<String>,
+    Json(payload): Json<MaintenanceRequest>,
+) -> AdminResult<()> {
+    let client_id = parse_client_id(&client_id)?;
+
+    let mut clients = state.active_clients.lock().await;
+    let online = clients.contains_key(&client_id);
+    let updated =
+        client::set_client_maintenance(&state.db_pool, &client_id, payload.enabled, online)
+            .await
+            .map_err(internal_error)?;
+    if !updated {
+        return Err(admin_error(StatusCode::NOT_FOUND, "Unknown worker"));
+    }
+    if let Some(info) = clients.get_mut(&client_id) {
+        info.maintenance = payload.enabled;
+    }
+    info!(
+        "Client {} maintenance mode {}",
+        client_id,
+        if payload.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+    Ok(Json(ApiResponse::success(())))
+}
+
+/// Set the region of an API key; a pinned key only uses workers there
+pub async fn set_key_region(
+    State(state): State<Arc<ServerState>>,
+    Path(key_id): Path<i64>,
+    Json(payload): Json<KeyRegion>,
+) -> AdminResult<KeyRegion> {
+    let region = match payload.region.as_deref() {
+        Some(region) => Some(
+            normalize_region(region)
+                .ok_or_else(|| admin_error(StatusCode::BAD_REQUEST, "Invalid region"))?,
+        ),
+        None => None,
+    };
+    if payload.pinned && region.is_none() {
+        return Err(admin_error(
+            StatusCode::BAD_REQUEST,
+            "A pinned key needs a region",
+        ));
+    }
+    let key_region = KeyRegion {
+        region,
+        pinned: payload.pinned,
+    };
+    let updated = client::set_key_region(&state.db_pool, key_id, &key_region)
+        .await
+        .map_err(internal_error)?;
+    if !updated {
+        return Err(admin_error(StatusCode::NOT_FOUND, "Unknown key"));
+    }
+    info!(
+        "Key {} region set to {:?}{}",
+        key_id,
+        key_region.region,
+        if key_region.pinned { " (pinned)" } else { "" }
+    );
+    Ok(Json(ApiResponse::success(key_region)))
+}
+
+/// Opt an API key in or out of the response cache
+pub async fn set_key_cache(
+    State(state): State<Arc<ServerState>>,
+    Path(key_id): Path<i64>,
+    Json(payload): Json<KeyCacheRequest>,
+) -> AdminResult<KeyCacheRequest> {
+    let updated = client::set_key_cache(&state.db_pool, key_id, payload.enabled)
+        .await
+        .map_err(internal_error)?;
+    if !updated {
+        return Err(admin_error(StatusCode::NOT_FOUND, "Unknown key"));
+    }
+    info!("Key {} response cache enabled: {}", key_id, payload.enabled);
+    Ok(Json(ApiResponse::success(payload)))
+}
+
+/// Hits and misses of the response cache
+pub async fn cache_stats(State(state): State<Arc<ServerState>>) -> AdminResult<CacheStats> {
+    let stats = state
+        .response_cache
+        .stats()
+        .await
+        .map_err(|e| internal_error(e.into()))?;
+    Ok(Json(ApiResponse::success(stats)))
+}
+
+/// Versions are millisecond timestamps so they keep increasing across server restarts
+fn next_config_version() -> u64 {
+    Utc::now().timestamp_millis().max(0) as u64
+}
+
+/// False when the worker's protocol predates pushed config
+async fn write_config(
+    writer: &Arc<Mutex<OwnedWriteHalf>>,
+    protocol: u32,
+    version: u64,
+    config: &WorkerConfig,
+) -> Result<bool> {
+    let command = CommandV1::UpdateConfig {
+        version,
+        config: config.clone(),
+    };
+    write_command_for(&mut *writer.lock().await, &Command::V1(command), protocol).await
+}
+
+pub async fn push_config(
+    State(state): State<Arc<ServerState>>,
+    Path(client_id): Path<String>,
+    Json(config): Json<WorkerConfig>,
+) -> AdminResult<PushConfigResponse> {
+    let client_id = parse_client_id(&client_id)?;
+    let version = next_config_version();
+
+    let (writer, protocol) = state
+        .active_clients
+        .lock()
+        .await
+        .get(&client_id)
+        .map(|info| (info.writer.clone(), info.version))
+        .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Worker is not connected"))?;
+    let sent = write_config(&writer, protocol, version, &config)
+        .await
+        .map_err(internal_error)?;
+    if !sent {
+        return Err(admin_error(
+            StatusCode::CONFLICT,
+            "Worker's protocol does not support pushed config",
+        ));
+    }
+    if let Some(info) = state.active_clients.lock().await.get_mut(&client_id) {
+        if Arc::ptr_eq(&info.writer, &writer) {
+            info.config_version = Some(version);
+        }
+    }
+    info!(
+        "Pushed config version {} to client {}: {:?}",
+        version, client_id, config
+    );
+
+    Ok(Json(ApiResponse::success(PushConfigResponse {
+        version,
+        pushed: 1,
+    })))
+}
+
+pub async fn push_config_all(
+    State(state): State<Arc<ServerState>>,
+    Json(config): Json<WorkerConfig>,
+) -> AdminResult<PushConfigResponse> {
+    let version = next_config_version();
+
+    // Write without holding the client map, one slow worker would stall
+    // every login and request meanwhile
+    let targets: Vec<_> = state
+        .active_clients
+        .lock()
+        .await
+        .iter()
+        .filter(|(_, info)| info.authed)
+        .map(|(client_id, info)| (*client_id, info.writer.clone(), info.version))
+        .collect();
+    let mut sent = Vec::new();
+    for (client_id, writer, protocol) in targets {
+        match write_config(&writer, protocol, version, &config).await {
+            Ok(true) => sent.push((client_id, writer)),
+            Ok(false) => {}
+            Err(e) => warn!(
+                "Failed to push config version {} to client {}: {}",
+                version, client_id, e
+            ),
+        }
+    }
+
+    // Workers that reconnected meanwhile have a new session without it
+    let mut clients = state.active_clients.lock().await;
+    for (client_id, writer) in &sent {
+        if let Some(info) = clients.get_mut(client_id) {
+            if Arc::ptr_eq(&info.writer, writer) {
+                info.config_version = Some(version);
+            }
+        }
+    }
+    drop(clients);
+    let pushed = sent.len();
+    info!(
+        "Pushed config version {} to {} workers: {:?}",
+        version, pushed, config
+    );
+
+    Ok(Json(ApiResponse::success(PushConfigResponse { version, pushed })))
+}
+
+/// Move connected workers to another server for a blue/green migration
+pub async fn migrate_workers(
+    State(state): State<Arc<ServerState>>,
+    Json(request): Json<MigrateRequest>,
+) -> AdminResult<MigrateResponse> {
+    if request.server_addr.trim().is_empty() {
+        return Err(admin_error(
+            StatusCode::BAD_REQUEST,
+            "server_addr must not be empty",
+        ));
+    }
+    let only = match &request.client_ids {
+        Some(ids) => Some(
+            ids.iter()
+                .map(|id| parse_client_id(id))
+                .collect::<Result<HashSet<_>, _>>()?,
+        ),
+        None => None,
+    };
+    let command = Command::V1(CommandV1::MigrateServer {
+        server_addr: request.server_addr.trim().to_string(),
+        control_port: request.control_port.unwrap_or(state.config.control_port),
+        proxy_port: request.proxy_port.unwrap_or(state.config.proxy_port),
+        delay_window_ms: request.delay_window_secs.saturating_mul(1000),
+    });
+
+    let mut sent = 0;
+    let clients = state.active_clients.lock().await;
+    let targets = clients.iter().filter(|(client_id, info)| {
+        info.authed && only.as_ref().map_or(true, |only| only.contains(*client_id))
+    });
+    for (client_id, info) in targets {
+        match write_command_for(&mut *info.writer.lock().await, &command, info.version).await {
+            Ok(true) => sent += 1,
+            Ok(false) => {}
+            Err(e) => warn!("Failed to migrate client {}: {}", client_id, e),
+        }
+    }
+    drop(clients);
+    info!(
+        "Told {} workers to move to {} within {}s",
+        sent, request.server_addr, request.delay_window_secs
+    );
+
+    Ok(Json(ApiResponse::success(MigrateResponse { sent })))
+}
+
+/// Read the release of `--worker-release-file`
+pub fn read_worker_release(path: &str) -> Result<WorkerRelease> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read worker release {}", path))?;
+    let release: WorkerRelease =
+        serde_json::from_str(&json).with_context(|| format!("Invalid worker release {}", path))?;
+    validate_worker_release(&release)?;
+    Ok(release)
+}
+
+fn validate_worker_release(release: &WorkerRelease) -> Result<()> {
+    if !is_release_version(&release.version) {
+        return Err(anyhow!(
+            "Worker release version {:?} must be dotted numbers like 1.2.0",
+            release.version
+        ));
+    }
+    if let Some(build) = release
+        .builds
+        .iter()
+        .find(|build| build.target.is_empty() || build.url.is_empty() || build.signature.is_empty())
+    {
+        return Err(anyhow!(
+            "Worker build {:?} needs a target, url and signature",
+            build.target
+        ));
+    }
+    Ok(())
+}
+
+pub async fn get_worker_release(
+    State(state): State<Arc<ServerState>>,
+) -> AdminResult<Option<WorkerRelease>> {
+    let release = state.worker_release.read().unwrap().clone();
+    Ok(Json(ApiResponse::success(release)))
+}
+
+/// Offer a gpuf-c release to the fleet: connected workers get it now, the
+/// others at their next login
+pub async fn publish_worker_release(
+    State(state): State<Arc<ServerState>>,
+    Json(release): Json<WorkerRelease>,
+) -> AdminResult<ReleaseResponse> {
+    validate_worker_release(&release)
+        .map_err(|e| admin_error(StatusCode::BAD_REQUEST, e.to_string()))?;
+    *state.worker_release.write().unwrap() = Some(release.clone());
+
+    let command = Command::V1(CommandV1::WorkerUpdate {
+        release: release.clone(),
+    });
+    let mut sent = 0;
+    let clients = state.active_clients.lock().await;
+    for (client_id, info) in clients.iter().filter(|(_, info)| info.authed) {
+        match write_command_for(&mut *info.writer.lock().await, &command, info.version).await {
+            Ok(true) => sent += 1,
+            Ok(false) => {}
+            Err(e) => warn!("Failed to send release to client {}: {}", client_id, e),
+        }
+    }
+    drop(clients);
+    info!(
+        "Published worker release {} to {} workers",
+        release.version, sent
+    );
+
+    Ok(Json(ApiResponse::success(ReleaseResponse {
+        version: release.version,
+        sent,
+    })))
+}
+
+pub async fn send_command(
+    State(state): State<Arc<ServerState>>,
+    Path(client_id): Path<String>,
+    Json(command): Json<CommandV1>,
+) -> AdminResult<()> {
+    let client_id = parse_client_id(&client_id)?;
+    let (writer, protocol) = state
+        .active_clients
+        .lock()
+        .await
+        .get(&client_id)
+        .map(|info| (info.writer.clone(), info.version))
+        .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Worker is not connected"))?;
+
+    info!(
+        "Admin sending command to client {}: {:?}",
+        client_id, command
+    );
+    let sent = write_command_for(&mut *writer.lock().await, &Command::V1(command), protocol)
+        .await
+        .map_err(internal_error)?;
+    if !sent {
+        return Err(admin_error(
+            StatusCode::CONFLICT,
+            "Worker's protocol does not know this command",
+        ));
+    }
+    Ok(Json(ApiResponse::success(())))
+}
+
+pub async fn dispatch_job(
+    State(state): State<Arc<ServerState>>,
+    Path(client_id): Path<String>,
+    Json(mut request): Json<DispatchJobRequest>,
+) -> AdminResult<DispatchJobResponse> {
+    let client_id = parse_client_id(&client_id)?;
+    let job_id = Uuid::new_v4().to_string();
+    let kind = request.spec.kind();
+    if let JobSpec::Inference { inputs, .. } = &mut request.spec {
+        for input in inputs.iter_mut() {
+            *input = state
+                .uploads
+                .input(&input.upload_id)
+                .await
+                .map_err(|e| admin_error(StatusCode::BAD_REQUEST, e.to_string()))?;
+        }
+    }
+
+    // Tracked under the lock the job's updates are recorded under, so none
+    // arrives before the job is known
+    let mut clients = state.active_clients.lock().await;
+    let info = clients
+        .get_mut(&client_id)
+        .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Worker is not connected"))?;
+    let command = CommandV1::Job {
+        job_id: job_id.clone(),
+        spec: request.spec,
+        timeout_secs: request.timeout_secs,
+    };
+    let sent = write_command_for(
+        &mut *info.writer.lock().await,
+        &Command::V1(command),
+        info.version,
+    )
+    .await
+    .map_err(internal_error)?;
+    if !sent {
+        return Err(admin_error(
+            StatusCode::CONFLICT,
+            "Worker's protocol does not support jobs",
+        ));
+    }
+    info.track_job(job_id.clone(), kind);
+    info!(
+        "Dispatched {:?} job {} to client {}",
+        kind, job_id, client_id
+    );
+
+    Ok(Json(ApiResponse::success(DispatchJobResponse { job_id })))
+}
+
+/// Jobs dispatched to the worker this session, newest first
+pub async fn list_jobs(
+    State(state): State<Arc<ServerState>>,
+    Path(client_id): Path<String>,
+) -> AdminResult<Vec<JobRecord>> {
+    let client_id = parse_client_id(&client_id)?;
+    let jobs = state
+        .active_clients
+        .lock()
+        .await
+        .get(&client_id)
+        .map(|info| info.jobs.iter().rev().cloned().collect())
+        .ok_or_else(|| admin_error(StatusCode::NOT_FOUND, "Worker is not connected"))?;
+    Ok(Json(ApiResponse::success(jobs)))
+}
+
+/// Results of `gpuf-c bench --upload`, the latest per model, offline workers
+/// included
+pub async fn list_model_benchmarks(
+    State(state): State<Arc<ServerState>>,
+    Path(client_id): Path<String>,
+) -> AdminResult<Vec<client::ClientModelBenchmark>> {
+    let client_id = parse_client_id(&client_id)?;
+    let benchmarks = client::list_model_benchmarks(&state.db_pool, &client_id)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(ApiResponse::success(benchmarks)))
+}
+
+#[test]
+fn test_summarize_versions() {
+    let worker = |id: &str, version: Option<&str>| client::ClientVersion {
+        client_id: id.to_string(),
+        client_name: None,
+        client_status: None,
+        worker_version: version.map(str::to_string),
+        engine_versions: None,
+        build_features: None,
+        os_version: None,
+        version_reported_at: None,
+    };
+    let workers = || {
+        vec![
+            worker("a", Some("0.2.0")),
+            worker("b", Some("0.1.9")),
+            worker("c", Some("0.2.0")),
+            worker("d", None),
+        ]
+    };
+
+    let fleet = summarize_versions(workers(), Some("0.2.0".to_string()));
+    assert_eq!(fleet.versions.get("0.2.0"), Some(&2));
+    assert_eq!(fleet.versions.get("0.1.9"), Some(&1));
+    assert_eq!(fleet.versions.get("unknown"), Some(&1));
+    let outdated: Vec<&str> = fleet
+        .outdated
+        .iter()
+        .map(|w| w.client_id.as_str())
+        .collect();
+    assert_eq!(outdated, ["b", "d"]);
+
+    assert!(summarize_versions(workers(), None).outdated.is_empty());
+}
@@ -1,7 +1,7 @@
 use super::*;
 use std::time::Duration;
 use tokio::net::TcpStream;
-use tracing::{error, info, warn};
+use tracing::{error, info, info_span, warn, Instrument, Span};
 use uuid::Uuid;
 
 use rdkafka::producer::{FutureProducer, FutureRecord};
@@ -10,6 +10,7 @@ use rdkafka::producer::{FutureProducer, FutureRecord};
 use tokio_uring::net::TcpStream as UringTcpStream;
 
 use crate::util::protoc::{ClientId, ProxyConnId, RequestIDAndClientIDMessage};
+use common::trace::new_request_id;
 use bytes::BytesMut;
 
 use std::collections::HashMap;
@@ -17,9 +18,7 @@ use std::collections::HashMap;
 use std::pin::Pin;
 #[cfg(feature = "experimental")]
 use std::task::{Context, Poll};
-use tokio::io::{self, AsyncRead, AsyncReadExt};
-#[cfg(feature = "experimental")]
-use tokio::io::AsyncWrite;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite};
 use twoway;
 
 use anyhow::{anyhow, Result};
@@ -30,37 +29,25 @@ use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpListener;
 
-use tokio_rustls::{rustls::server::ServerConfig, TlsAcceptor};
-
-#[cfg(feature = "aws_lc_rs")]
-use tokio_rustls::rustls::crypto::aws_lc_rs;
-#[cfg(feature = "ring")]
-use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::TlsAcceptor;
 
-use crate::db::client::get_user_client_by_token;
+use crate::db::client::{get_token_rate_limit, get_user_client_by_token};
+use crate::db::dashboard_events::{self, DashboardEvent, DashboardEventKind, SpikeDetector};
+use crate::db::usage::{UsageRecord, UsageStatus};
+use crate::handle::handle_forward::{ForwardHeader, Forwarder};
+use crate::util::geo::{KeyRegion, REGION_HEADER};
 use crate::util::msg::ApiResponse;
+use crate::util::net::client_ip;
 use crate::util::policy::{AccessLevel, REQUEST_MESSAGE_TOPIC};
+use crate::util::rate_limit::{RateLimitDecision, RateLimiter};
+use crate::util::tls::is_acme_challenge;
+use crate::util::usage_tap::{ResponseTap, UsageTap};
+use std::net::IpAddr;
 use tracing::debug;
 
 impl ServerState {
     pub async fn handle_proxy_connections(self: Arc<Self>, listener: TcpListener) -> Result<()> {
-        let cert_chain = self.cert_chain.clone();
-        let priv_key = self.priv_key.clone();
-
-        #[cfg(feature = "aws_lc_rs")]
-        aws_lc_rs::default_provider()
-            .install_default()
-            .expect("failed to install aws-lc-rs provider");
-
-        #[cfg(feature = "ring")]
-        ring::default_provider()
-            .install_default()
-            .expect("failed to install ring provider");
-
-        let server_config = ServerConfig::builder()
-            .with_no_client_auth()
-            .with_single_cert(cert_chain.to_vec(), priv_key.clone_key())?;
-        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let acceptor = TlsAcceptor::from(self.tls_config.clone());
 
         loop {
             let (proxy_stream, addr) = listener.accept().await?;
@@ -69,6 +56,9 @@ impl ServerState {
             let acceptor = acceptor.clone();
             let pending_clone = self.pending_connections.clone();
             let buffer_pool = self.buffer_pool.clone();
+            let proxied_requests = self.proxied_requests.clone();
+            let rate_limiter = self.rate_limiter.clone();
+            let usage_recorder = self.usage_recorder.clone();
             tokio::spawn(async move {
                 let mut buf = BytesMut::with_capacity(1024 * 1024);
 
@@ -79,17 +69,32 @@ impl ServerState {
                         return;
                     }
                 };
+                if is_acme_challenge(tls_proxy_stream.get_ref().1) {
+                    debug!("ACME challenge from {}", addr);
+                    return;
+                }
 
-                if let Ok(Command::V1(CommandV1::NewProxyConn { proxy_conn_id })) =
-                    read_command(&mut tls_proxy_stream, &mut buf).await
-                {
+                let first = read_command(&mut tls_proxy_stream, &mut buf).await;
+                if let Ok(Command::V1(CommandV1::NetworkProbe { reply_bytes, .. })) = first {
+                    if let Err(e) =
+                        answer_network_probes(&mut tls_proxy_stream, &mut buf, reply_bytes).await
+                    {
+                        debug!("Network probe from {} ended: {}", addr, e);
+                    }
+                } else if let Ok(Command::V1(CommandV1::NewProxyConn { proxy_conn_id })) = first {
                     info!(
                         "Received proxy conn notification for id: {:?}",
                         proxy_conn_id
                     );
                     let mut pending = pending_clone.lock().await;
 
-                    if let Some((user_stream, buf)) = pending.remove(&ProxyConnId(proxy_conn_id)) {
+                    if let Some(PendingConnection {
+                        user_stream,
+                        buffer: buf,
+                        usage,
+                        slot,
+                    }) = pending.remove(&ProxyConnId(proxy_conn_id))
+                    {
                         info!(
                             "Pairing user stream with proxy stream for id: {:?}",
                             proxy_conn_id
@@ -115,11 +120,23 @@ impl ServerState {
                         let _ = tls_proxy_stream.flush().await;
                         buffer_pool.put(buf).await;
 
+                        let in_flight = proxied_requests.enter();
                         tokio::spawn(async move {
-                            if let Err(e) = join_streams(user_stream, tls_proxy_stream).await {
+                            let _in_flight = in_flight;
+                            // Counts against max_concurrent until the connection closes
+                            let _slot = slot;
+                            let mut response = ResponseTap::default();
+                            let worker_stream = UsageTap::new(tls_proxy_stream, &mut response);
+                            let joined = join_streams(user_stream, worker_stream).await;
+                            if let Err(e) = &joined {
                                 error!("Error joining streams: {}", e);
                             }
                             info!("Streams for {:?} joined and finished.", proxy_conn_id);
+                            let usage = finish_proxied_usage(usage, &response, joined.is_ok());
+                            let tokens = (usage.prompt_tokens as u32)
+                                .saturating_add(usage.completion_tokens as u32);
+                            rate_limiter.record_tokens(&usage.api_key, tokens).await;
+                            usage_recorder.record(usage);
                         });
                     } else {
                         warn!(
@@ -147,7 +164,11 @@ impl ServerState {
 
             let producer_clone = self.producer.clone();
             let buffer_pool_clone = self.buffer_pool.clone();
+            let rate_limiter_clone = self.rate_limiter.clone();
+            let forwarder_clone = self.forwarder.clone();
+            let in_flight = self.proxied_requests.enter();
             tokio::spawn(async move {
+                let _in_flight = in_flight;
                 // Increment total connections counter
                 {
                     let mut counter = total_connections_clone.lock().await;
@@ -161,7 +182,17 @@ impl ServerState {
                     pending_connections_clone,
                     db_pool_clone,
                     producer_clone,
+                    rate_limiter_clone,
+                    forwarder_clone,
+                    client_ip(addr),
                 )
+                .instrument(info_span!(
+                    "public_request",
+                    peer = %addr,
+                    request_id = tracing::field::Empty,
+                    model = tracing::field::Empty,
+                    client_id = tracing::field::Empty,
+                ))
                 .await
                 {
                     //send_http_error_response(user_stream, 401, "Invalid API key").await;
@@ -171,6 +202,38 @@ impl ServerState {
         }
     }
 
+    /// Publish a request spike event when a window of public connections is
+    /// well above the recent average
+    pub async fn watch_request_rate(self: Arc<Self>, window: Duration, mut detector: SpikeDetector) {
+        let mut ticker = tokio::time::interval(window);
+        ticker.tick().await;
+        let mut last = *self.total_connections.lock().await;
+        loop {
+            ticker.tick().await;
+            let total = *self.total_connections.lock().await;
+            let requests = total.saturating_sub(last);
+            last = total;
+            let Some(baseline) = detector.observe(requests) else {
+                continue;
+            };
+            warn!(
+                "Request spike: {} public connections in {}s, average {:.0}",
+                requests,
+                window.as_secs(),
+                baseline
+            );
+            let event = DashboardEvent::new(DashboardEventKind::RequestSpike {
+                instance_id: self.presence.instance_id().to_string(),
+                requests,
+                baseline,
+                window_secs: window.as_secs(),
+            });
+            if let Err(e) = dashboard_events::publish(&self.redis_client, &event).await {
+                warn!("Failed to publish request spike: {}", e);
+            }
+        }
+    }
+
     #[cfg(all(target_os = "linux", feature = "experimental"))]
     pub async fn handle_proxy_connections_uring(
         self: Arc<Self>,
@@ -225,6 +288,36 @@ impl ServerState {
     }
 }
 
+/// Largest reply a worker may ask for in one network probe
+const MAX_PROBE_REPLY_BYTES: u32 = 4 << 20;
+/// Probes answered on one connection before it is closed
+const MAX_PROBES_PER_CONNECTION: usize = 8;
+
+/// Answers the bandwidth probes of a worker until it closes the connection
+async fn answer_network_probes<S>(
+    stream: &mut S,
+    buf: &mut BytesMut,
+    mut reply_bytes: u32,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    for _ in 0..MAX_PROBES_PER_CONNECTION {
+        let reply = Command::V1(CommandV1::NetworkProbe {
+            payload: vec![0u8; reply_bytes.min(MAX_PROBE_REPLY_BYTES) as usize],
+            reply_bytes: 0,
+        });
+        write_command(stream, &reply).await?;
+        match read_command(stream, buf).await? {
+            Command::V1(CommandV1::NetworkProbe {
+                reply_bytes: next, ..
+            }) => reply_bytes = next,
+            _ => return Err(anyhow!("Unexpected command during network probe")),
+        }
+    }
+    Ok(())
+}
+
 #[cfg(all(target_os = "linux", feature = "experimental"))]
 async fn route_public_connection_uring(
     _user_stream: UringTcpStream,
@@ -329,7 +422,7 @@ async fn parse_request(
 async fn authenticate_and_select_client(
     api_key: Option<String>,
     db_pool: &Pool<Postgres>,
-) -> Result<(Vec<ClientId>, AccessLevel)> {
+) -> Result<(Vec<ClientId>, AccessLevel, KeyRegion)> {
     let api_key = api_key.ok_or_else(|| anyhow::anyhow!("Missing API key"))?;
     if api_key.len() != 48 {
         warn!("Invalid API key length");
@@ -503,6 +596,11 @@ pub struct ChatRequestInfo {
     pub request_id: Option<String>,
     pub api_key: Option<String>,
     pub content_type: Option<String>,
+    /// Worker named by the `gpuf-worker` header, for follow-up requests
+    /// such as polling an image job that only that worker holds
+    pub worker: Option<ClientId>,
+    /// Region named by the `gpuf-region` header
+    pub region: Option<String>,
     // pub reader: R,
 }
 use http::header::{HeaderMap, HeaderName, HeaderValue};
@@ -617,10 +715,34 @@ async fn extract_chat_info<R: AsyncRead + Unpin>(
         .and_then(|s| s.strip_prefix("Bearer "))
         .map(|s| s.to_string());
 
-    let request_id = headers
-        .get("request-id")
+    // Proxied requests without a request-id get one, added to the headers the worker sees
+    let request_id = match headers.get("request-id").and_then(|v| v.to_str().ok()) {
+        Some(request_id) => request_id.to_string(),
+        None => {
+            let request_id = new_request_id();
+            let header = format!("request-id: {}\r\n", request_id);
+            // Insert before the blank line that ends the headers
+            let body = buffer.split_off(body_start - 2);
+            buffer.extend_from_slice(header.as_bytes());
+            buffer.extend_from_slice(&body);
+            body_start += header.len();
+            request_id
+        }
+    };
+    let request_id = Some(request_id);
+
+    let worker = match headers.get("gpuf-worker").and_then(|v| v.to_str().ok()) {
+        Some(worker) => Some(
+            worker
+                .parse::<ClientId>()
+                .map_err(|e| anyhow::anyhow!("Invalid gpuf-worker header: {}", e))?,
+        ),
+        None => None,
+    };
+    let region = headers
+        .get(REGION_HEADER)
         .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string());
+        .map(str::to_string);
 
     debug!(
         "api_key: {:?}, request_id: {:?}, content_type: {:?}",
@@ -632,9 +754,11 @@ async fn extract_chat_info<R: AsyncRead + Unpin>(
     let Some(ct) = content_type.as_deref() else {
         return Ok(ChatRequestInfo {
             model: None,
-            request_id: None,
+            request_id,
             api_key,
             content_type: None,
+            worker,
+            region,
         });
     };
 
@@ -642,9 +766,11 @@ async fn extract_chat_info<R: AsyncRead + Unpin>(
         warn!("Unsupported content type: {:?}", ct);
         return Ok(ChatRequestInfo {
             model: None,
-            request_id: None,
+            request_id,
             api_key,
             content_type,
+            worker,
+            region,
         });
     }
 
@@ -660,6 +786,8 @@ async fn extract_chat_info<R: AsyncRead + Unpin>(
         request_id,
         api_key,
         content_type,
+        worker,
+        region,
     })
 }
 
@@ -757,6 +885,28 @@ pub async fn send_http_error_response(
     Ok(())
 }
 
+async fn send_rate_limited_response(
+    mut stream: TcpStream,
+    decision: &RateLimitDecision,
+) -> Result<()> {
+    let json_body =
+        serde_json::to_string(&ApiResponse::<()>::error(decision.message().to_string()))?;
+    let headers: String = decision
+        .headers()
+        .into_iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect();
+
+    let response = format!(
+        "HTTP/1.1 429 Too Many Requests\r\nContent-Type: application/json\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        headers, json_body.len(), json_body
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
 async fn route_public_connection_new(
     mut user_stream: TcpStream,
     buffer_pool: Arc<BufferPool>,
@@ -764,6 +914,9 @@ async fn route_public_connection_new(
     pending_connections: PendingConnections,
     db_pool: Arc<Pool<Postgres>>,
     producer: Arc<FutureProducer>,
+    rate_limiter: Arc<RateLimiter>,
+    forwarder: Option<Arc<Forwarder>>,
+    peer_ip: IpAddr,
 ) -> Result<()> {
     // Request Parsing Module - Handle HTTP request parsing and validation
 
@@ -777,9 +930,17 @@ async fn route_public_connection_new(
         }
     };
 
+    let span = Span::current();
+    if let Some(request_id) = chat_info.request_id.as_deref() {
+        span.record("request_id", request_id);
+    }
+    if let Some(model) = chat_info.model.as_deref() {
+        span.record("model", model);
+    }
+
     // debug!("Request Parsing Module - Handle HTTP request parsing and validation chat_info {:?}", chat_info);
-    // Validate model and request_id
-    if chat_info.model.is_none() || chat_info.api_key.is_none() {
+    // Validate model and request_id. Requests to a named worker need no model.
+    if (chat_info.model.is_none() && chat_info.worker.is_none()) || chat_info.api_key.is_none() {
         buffer_pool.put(buffer).await;
         send_http_error_response(user_stream, 401, "Invalid model or api_key").await?;
         return Err(anyhow::anyhow!(
@@ -792,7 +953,8 @@ async fn route_public_connection_new(
     // TODO: use map to cacheclient info
     // Authentication Module - Handle API key validation
     debug!("Authentication Module - Handle API key validationt");
-    let (client_ids, access_level) =
+    let api_key = chat_info.api_key.clone().unwrap_or_default();
+    let (client_ids, access_level, key_region) =
         match authenticate_and_select_client(chat_info.api_key, &db_pool).await {
             Ok(client) => client,
             Err(e) => {
@@ -814,38 +976,160 @@ async fn route_public_connection_new(
         return Err(anyhow::anyhow!("No available clients"));
     }
 
-    // Route public connection to chosen client
+    // Rate Limiting Module - request rate and token budget per API key and peer address
+    let policy = get_token_rate_limit(&db_pool, &api_key)
+        .await
+        .unwrap_or_default();
+    let decision = rate_limiter.check(&api_key, Some(peer_ip), &policy).await;
+    if !decision.allowed() {
+        buffer_pool.put(buffer).await;
+        send_rate_limited_response(user_stream, &decision).await?;
+        return Err(anyhow::anyhow!("Rate limited: {}", decision.message()));
+    }
+    let slot = match rate_limiter.acquire_slot(&api_key, &policy).await {
+        Ok(slot) => slot,
+        Err(rejected) => {
+            buffer_pool.put(buffer).await;
+            send_rate_limited_response(user_stream, &rejected).await?;
+            return Err(anyhow::anyhow!("Rate limited: {}", rejected.message()));
+        }
+    };
+
+    // Route public connection to chosen client, in the preferred region when possible
     debug!("Route public connection to chosen client");
+    let region = key_region.preferred(chat_info.region.as_deref());
     let mut active_clients = active_clients.lock().await;
-
-    let chosen_client_id = match connect_client_filter_model_and_client(
-        chat_info.model.as_ref().unwrap(),
-        client_ids,
+    let local = connect_client_filter_model_and_client(
+        chat_info.model.as_deref(),
+        chat_info.worker,
+        region.as_deref(),
+        client_ids.clone(),
         &mut active_clients,
     )
-    .await
-    {
+    .await;
+    drop(active_clients);
+
+    let chosen_client_id = match local {
         Ok((chosen_client_id, chosen_client_proxy_conn_id)) => {
-            pending_connections
-                .lock()
-                .await
-                .insert(chosen_client_proxy_conn_id, (user_stream, buffer));
+            let usage = proxied_usage(
+                chat_info.request_id.clone(),
+                api_key.clone(),
+                chosen_client_id,
+                chat_info.model.clone().unwrap_or_default(),
+            );
+            pending_connections.lock().await.insert(
+                chosen_client_proxy_conn_id,
+                PendingConnection {
+                    user_stream,
+                    buffer,
+                    usage,
+                    slot,
+                },
+            );
+            span.record("client_id", chosen_client_id.to_string().as_str());
             chosen_client_id
         }
         Err(e) => {
+            // The worker may be attached to another instance
+            if let Some(forwarder) = forwarder {
+                let remote = forwarder
+                    .find_remote(&client_ids, chat_info.model.as_deref(), chat_info.worker)
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!("Failed to look up workers on other instances: {}", e);
+                        None
+                    });
+                if let Some(target) = remote {
+                    info!("Forwarding request to instance {}", target.instance_id);
+                    // The slot is held here while the other instance serves the request
+                    let _slot = slot;
+                    let header = ForwardHeader {
+                        secret: String::new(),
+                        from_instance: String::new(),
+                        client_ids: Vec::new(),
+                        model: chat_info.model,
+                        worker: chat_info.worker,
+                        region,
+                        request_id: chat_info.request_id,
+                        api_key,
+                        metered: access_level.is_metered(),
+                    };
+                    return forwarder.forward(&target, header, user_stream, buffer).await;
+                }
+            }
             buffer_pool.put(buffer).await;
             send_http_error_response(user_stream, 400, "No available clients").await?;
             return Err(anyhow::anyhow!("No available clients {}", e));
         }
     };
 
-    if !access_level.is_metered() {
+    publish_proxied_request(
+        producer,
+        chat_info.request_id,
+        chosen_client_id,
+        access_level.is_metered(),
+    )
+    .await
+}
+
+/// Usage of a request handed to a worker, before its response is seen
+pub(super) fn proxied_usage(
+    request_id: Option<String>,
+    api_key: String,
+    client_id: ClientId,
+    model: String,
+) -> UsageRecord {
+    UsageRecord {
+        request_id,
+        api_key,
+        client_id,
+        model,
+        prompt_tokens: 0,
+        completion_tokens: 0,
+        latency_ms: 0,
+        status: UsageStatus::Cancelled,
+        created_at: chrono::Utc::now(),
+    }
+}
+
+/// Fill in the tokens, status and latency of a proxied request from the
+/// response relayed to the user. A response without a status line is an
+/// error when the connection broke and a cancel when a side closed it.
+fn finish_proxied_usage(
+    mut usage: UsageRecord,
+    response: &ResponseTap,
+    joined: bool,
+) -> UsageRecord {
+    let elapsed = chrono::Utc::now() - usage.created_at;
+    usage.latency_ms = elapsed.num_milliseconds().max(0);
+    if let Some(tokens) = response.usage() {
+        usage.prompt_tokens = tokens.prompt_tokens.min(i32::MAX as u32) as i32;
+        usage.completion_tokens = tokens.completion_tokens.min(i32::MAX as u32) as i32;
+    }
+    usage.status = match response.status() {
+        Some(status) if (200..300).contains(&status) => UsageStatus::Success,
+        Some(_) => UsageStatus::Error,
+        None if joined => UsageStatus::Cancelled,
+        None => UsageStatus::Error,
+    };
+    usage
+}
+
+/// Publish the (request_id, client_id) pair of a request handed to a worker
+/// for metered keys
+pub(super) async fn publish_proxied_request(
+    producer: Arc<FutureProducer>,
+    request_id: Option<String>,
+    client_id: ClientId,
+    metered: bool,
+) -> Result<()> {
+    if !metered {
         debug!("Send kafka key-value (request_id, client_id) pair");
         return Ok(());
     }
 
     // share api Send kafka key-value (request_id, client_id) pair
-    match request_to_kafka(chat_info.request_id, chosen_client_id, producer).await {
+    match request_to_kafka(request_id, client_id, producer).await {
         Ok(_) => Ok(()),
         Err(e) => {
             error!("Failed to send request to Kafka: {:?}", e);
@@ -854,14 +1138,32 @@ async fn route_public_connection_new(
     }
 }
 
+/// Pick a client serving `model_name`, or `worker` when named, among
+/// `client_ids`, and ask it for a proxy connection. Clients in `region` come
+/// first when there are any.
 pub async fn connect_client_filter_model_and_client(
-    model_name: &str,
+    model_name: Option<&str>,
+    worker: Option<ClientId>,
+    region: Option<&str>,
     client_ids: Vec<ClientId>,
     clients: &mut HashMap<ClientId, ClientInfo>,
 ) -> Result<(ClientId, ProxyConnId)> {
-    let chosen_client: Option<(&ClientInfo, ClientId)> =
-        client_ids.into_iter().find_map(|client_id| {
+    let outside_region = |client_info: &ClientInfo| {
+        region.is_some_and(|region| !client_info.location.matches(region))
+    };
+    let chosen_client: Option<(&ClientInfo, ClientId)> = client_ids
+        .into_iter()
+        .filter_map(|client_id| {
+            if worker.is_some_and(|worker| worker != client_id) {
+                return None;
+            }
             if let Some(client_info) = clients.get(&client_id) {
+                if client_info.maintenance || !client_info.available() {
+                    return None;
+                }
+                let Some(model_name) = model_name else {
+                    return Some((client_info, client_id));
+                };
                 if let Some(models) = &client_info.models {
                     if models.iter().any(|m| m.id == model_name) {
                         return Some((client_info, client_id));
@@ -869,7 +1171,8 @@ pub async fn connect_client_filter_model_and_client(
                 }
             }
             None
-        });
+        })
+        .min_by_key(|(client_info, _)| outside_region(client_info));
     match chosen_client {
         Some((client_info, client_id)) => {
             if !client_info.authed {
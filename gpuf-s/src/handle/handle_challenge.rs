@@ -0,0 +1,146 @@
+use super::*;
+
+use crate::db::challenges;
+use rand::RngCore;
+use std::time::Duration;
+use tracing::debug;
+
+impl ServerState {
+    /// Challenge every logged-in worker each `interval`. A worker that has
+    /// not answered its previous challenge by then fails it, and so does a
+    /// worker whose protocol cannot answer one, so it earns no points
+    /// while challenges run.
+    pub async fn run_challenges(
+        self: Arc<Self>,
+        interval: Duration,
+        deadline: Duration,
+        difficulty: u8,
+    ) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            let now = Utc::now();
+            let mut expired = Vec::new();
+            let mut unanswerable = Vec::new();
+            let mut targets = Vec::new();
+            {
+                let mut clients = self.active_clients.lock().await;
+                for (client_id, info) in clients.iter_mut() {
+                    if info.challenge.as_ref().is_some_and(|c| c.deadline < now) {
+                        info.challenge = None;
+                        expired.push(*client_id);
+                    }
+                    if !info.authed || info.challenge.is_some() {
+                        continue;
+                    }
+                    if info.version >= PROTOCOL_VERSION {
+                        targets.push((*client_id, info.writer.clone()));
+                    } else {
+                        unanswerable.push(*client_id);
+                    }
+                }
+            }
+
+            for client_id in expired {
+                warn!("Client {} missed its liveness challenge", client_id);
+                self.record_challenge(&client_id, false).await;
+            }
+            for client_id in unanswerable {
+                debug!("Client {} cannot answer liveness challenges", client_id);
+                self.record_challenge(&client_id, false).await;
+            }
+            for (client_id, writer) in targets {
+                if let Err(e) = self
+                    .send_challenge(client_id, writer, deadline, difficulty)
+                    .await
+                {
+                    error!("Failed to challenge client {}: {}", client_id, e);
+                }
+            }
+        }
+    }
+
+    async fn send_challenge(
+        &self,
+        client_id: ClientId,
+        writer: Arc<Mutex<OwnedWriteHalf>>,
+        deadline: Duration,
+        difficulty: u8,
+    ) -> Result<()> {
+        let mut challenge_id = [0u8; 16];
+        let mut seed = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut challenge_id);
+        rand::thread_rng().fill_bytes(&mut seed);
+        let task = ChallengeTask::Work { seed, difficulty };
+        let deadline_ms = deadline.as_millis() as u32;
+
+        match self.active_clients.lock().await.get_mut(&client_id) {
+            Some(info) => {
+                info.challenge = Some(PendingChallenge {
+                    challenge_id,
+                    task: task.clone(),
+                    deadline: Utc::now() + chrono::Duration::milliseconds(deadline_ms as i64),
+                })
+            }
+            None => return Ok(()),
+        }
+        let command = CommandV1::Challenge {
+            challenge_id,
+            task,
+            deadline_ms,
+        };
+        write_command(&mut *writer.lock().await, &Command::V1(command)).await
+    }
+
+    /// Check a worker's answer against its pending challenge
+    pub async fn handle_challenge_response(
+        &self,
+        client_id: &ClientId,
+        challenge_id: [u8; 16],
+        answer: &[u8],
+    ) {
+        let now = Utc::now();
+        let challenge = {
+            let mut clients = self.active_clients.lock().await;
+            let Some(info) = clients.get_mut(client_id) else {
+                return;
+            };
+            match &info.challenge {
+                Some(challenge) if challenge.challenge_id == challenge_id => info.challenge.take(),
+                _ => None,
+            }
+        };
+        let Some(challenge) = challenge else {
+            warn!(
+                "Client {} answered unknown challenge {}",
+                client_id,
+                hex::encode(challenge_id)
+            );
+            return;
+        };
+
+        let passed = now <= challenge.deadline && challenge.task.verify(answer);
+        if passed {
+            debug!("Client {} passed its liveness challenge", client_id);
+        } else if now > challenge.deadline {
+            warn!("Client {} answered its liveness challenge late", client_id);
+        } else {
+            warn!("Client {} answered its liveness challenge wrong", client_id);
+        }
+        self.record_challenge(client_id, passed).await;
+    }
+
+    async fn record_challenge(&self, client_id: &ClientId, passed: bool) {
+        match challenges::record_challenge_result(&self.db_pool, client_id, passed).await {
+            Ok(Some(trust_score)) => {
+                debug!("Client {} trust score is {}", client_id, trust_score)
+            }
+            Ok(None) => {}
+            Err(e) => error!(
+                "Failed to record challenge result of client {}: {}",
+                client_id, e
+            ),
+        }
+    }
+}
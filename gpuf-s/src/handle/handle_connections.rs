@@ -1,15 +1,26 @@
 use super::*;
 
 use crate::db::{
+    canary::{self, MAX_QUALITY_SCORE},
     client,
-    models::{self, HotModelClass},
+    dashboard_events::{self, DashboardEvent, DashboardEventKind},
+    models::{self, HotModelClass, RolloutState},
+    notifications::{self, NotificationEvent},
+    rollouts,
+    worker_events::{publish_worker_event, WorkerEvent, WorkerEventKind},
 };
+use crate::util::geo::{GeoIp, Location};
+use crate::util::net::client_ip;
 use crate::util::protoc::{ClientId, HeartbeatMessage};
 use bytes::BytesMut;
 use std::collections::HashMap;
+use std::net::IpAddr;
 
 use anyhow::{anyhow, Result};
-use common::{format_bytes, os_type_str, CommandV2, DownloadStatus, Model, OsType, PodModel};
+use common::{
+    format_bytes, os_type_str, BenchmarkResult, CommandV2, DownloadStatus, EngineState, Model,
+    OsType, PodModelV2,
+};
 use redis::Client as RedisClient;
 use redis::AsyncCommands;
 use sqlx::{Pool, Postgres};
@@ -109,14 +120,22 @@ async fn handle_single_client(
     let (mut reader, writer) = stream.into_split();
     let writer = Arc::new(Mutex::new(writer));
     let addr = reader.peer_addr().expect("Failed to get peer address");
+    let local_addr = reader.local_addr()?;
 
     let mut authed = false;
     let mut session_client_id = ClientId([0; 16]);
+    // Protocol version the worker logged in with, what it is sent in
+    let mut session_protocol = 1;
     let mut buf = BytesMut::with_capacity(1024 * 1024);
+    let disconnect = Arc::new(Notify::new());
 
     loop {
-        match read_command(&mut reader, &mut buf).await {
-            Ok(Command::V1(CommandV1::Login {
+        let command = tokio::select! {
+            command = read_command(&mut reader, &mut buf) => command,
+            _ = disconnect.notified() => Err(anyhow!("disconnected by operator")),
+        };
+        match command {
+            Ok(Command::V1(CommandV1::LoginV2 {
                 version,
                 auto_models,
                 client_id: id,
@@ -125,11 +144,14 @@ async fn handle_single_client(
                 device_memtotal_gb,
                 device_total_tflops,
                 devices_info,
+                benchmark,
+                enrollment_token,
+                region,
             })) => {
                 info!("Registration attempt for client_id: {:?}", id);
                 debug!(
-                    "Registration attempt for devices_info: {:?} device_total_tflops {}",
-                    devices_info, device_total_tflops
+                    "Registration attempt for devices_info: {:?} device_total_tflops {} benchmark {:?}",
+                    devices_info, device_total_tflops, benchmark
                 );
 
                 let validate_result = match handle_login(
@@ -142,6 +164,7 @@ async fn handle_single_client(
                     &ClientId(id),
                     os_type,
                     devices_info,
+                    benchmark,
                     SystemInfo {
                         cpu_usage: system_info.cpu_usage,
                         memory_usage: system_info.memory_usage,
@@ -152,14 +175,22 @@ async fn handle_single_client(
                         last_heartbeat: Utc::now().into(),
                     },
                     &writer,
+                    &disconnect,
                     &mut authed,
+                    &server_state.config,
+                    enrollment_token.as_deref(),
+                    GeoIp::locate(
+                        server_state.geoip.as_deref(),
+                        region.as_deref(),
+                        client_ip(addr),
+                    ),
                 )
                 .await
                 {
                     Ok(validate_result) => validate_result,
                     Err(e) => {
                         error!("Failed to handle login: {}", e);
-                        CommandV1::LoginResult {
+                        CommandV1::LoginResultV2 {
                             success: false,
                             pods_model: Vec::new(),
                             error: Some(e.to_string()),
@@ -167,19 +198,58 @@ async fn handle_single_client(
                     }
                 };
                 session_client_id = ClientId(id);
+                session_protocol = version;
+
+                let logged_in = matches!(
+                    validate_result,
+                    CommandV1::LoginResultV2 { success: true, .. }
+                );
+                if logged_in {
+                    let mut event = WorkerEvent::new(session_client_id, WorkerEventKind::Connected);
+                    event.detail = Some(addr.to_string());
+                    publish_worker_event(&producer, &event);
+                    if let Err(e) = server_state.presence.mark_online(&session_client_id).await {
+                        warn!("Failed to publish presence of {}: {}", session_client_id, e);
+                    }
+                }
+
+                let result = Command::V1(validate_result);
+                write_command_for(&mut *writer.lock().await, &result, session_protocol).await?;
 
-                write_command(&mut *writer.lock().await, &Command::V1(validate_result)).await?;
+                let release = server_state.worker_release.read().unwrap().clone();
+                if let Some(release) = release.filter(|_| logged_in) {
+                    let update = Command::V1(CommandV1::WorkerUpdate { release });
+                    write_command_for(&mut *writer.lock().await, &update, session_protocol).await?;
+                }
             }
             // Device system status from client to server 120s
-            Ok(Command::V1(CommandV1::Heartbeat {
+            Ok(Command::V1(CommandV1::HeartbeatV2 {
                 client_id: id,
                 system_info,
                 device_memtotal_gb,
                 device_total_tflops,
                 device_count,
                 devices_info,
+                gpu_metrics,
+                network_quality,
+                power,
+                version,
             })) => {
                 info!("Heartbeat received from client {}", hex::encode(id));
+                if let Err(e) = server_state.presence.touch(&ClientId(id)).await {
+                    warn!("Failed to refresh presence of {}: {}", ClientId(id), e);
+                }
+                if let Some(info) = active_clients.lock().await.get_mut(&ClientId(id)) {
+                    info.gpu_metrics = gpu_metrics;
+                    info.power = power;
+                    // Probes run less often than heartbeats, keep the last one
+                    if network_quality.is_some() {
+                        info.network_quality = network_quality;
+                    }
+                    if version.is_some() {
+                        info.worker_version = version.clone();
+                    }
+                }
                 handle_heartbeat(
                     &producer,
                     &ClientId(id),
@@ -188,6 +258,7 @@ async fn handle_single_client(
                     device_memtotal_gb,
                     device_count as u32,
                     device_total_tflops,
+                    version,
                 )
                 .await;
             }
@@ -205,6 +276,23 @@ async fn handle_single_client(
 
                 upsert_client_models_in_redis(&redis_client, &ClientId(id), &models).await;
 
+                let previous = active_clients
+                    .lock()
+                    .await
+                    .get(&ClientId(id))
+                    .and_then(|info| info.models.clone());
+                for (kind, model) in model_changes(previous.as_deref(), &models) {
+                    let mut event = WorkerEvent::new(ClientId(id), kind);
+                    event.model = Some(model);
+                    publish_worker_event(&producer, &event);
+                    if let Some(kind) = DashboardEventKind::from_worker_event(&event) {
+                        let event = DashboardEvent::new(kind);
+                        if let Err(e) = dashboard_events::publish(&redis_client, &event).await {
+                            warn!("Failed to publish dashboard event: {}", e);
+                        }
+                    }
+                }
+
                 let pods_model = match handle_models_status(
                     &hot_models,
                     &active_clients,
@@ -214,24 +302,56 @@ async fn handle_single_client(
                 )
                 .await
                 {
-                    Ok(pods_model) => CommandV1::PullModelResult {
+                    Ok(pods_model) => CommandV1::PullModelResultV2 {
                         error: None,
                         pods_model,
                     },
                     Err(e) => {
                         error!("Failed to handle models status: {}", e);
-                        CommandV1::PullModelResult {
+                        CommandV1::PullModelResultV2 {
                             error: Some(e.to_string()),
                             pods_model: Vec::new(),
                         }
                     }
                 };
-                write_command(&mut *writer.lock().await, &Command::V1(pods_model)).await?;
+                let pods_model = Command::V1(pods_model);
+                write_command_for(&mut *writer.lock().await, &pods_model, session_protocol).await?;
             }
             Err(e) => {
                 info!("addr {} disconnected: {}", addr, e);
+                let mut moved = false;
+                if authed {
+                    let mut event =
+                        WorkerEvent::new(session_client_id, WorkerEventKind::Disconnected);
+                    event.detail = Some(e.to_string());
+                    publish_worker_event(&producer, &event);
+                    moved = server_state
+                        .presence
+                        .mark_offline(&session_client_id)
+                        .await
+                        .unwrap_or_else(|e| {
+                            warn!("Failed to release presence of {}: {}", session_client_id, e);
+                            false
+                        });
+                }
                 active_clients.lock().await.remove(&session_client_id);
-                client::upsert_client_status(&db_pool, &session_client_id, "offline").await?;
+                // A worker that logged in at another instance meanwhile stays online
+                if !moved {
+                    client::upsert_client_status(&db_pool, &session_client_id, "offline").await?;
+                    if authed {
+                        if let Err(err) = notifications::notify_client_owners(
+                            &db_pool,
+                            &[session_client_id],
+                            NotificationEvent::DeviceOffline,
+                            &format!("Device {} went offline", session_client_id),
+                            &format!("Disconnected at {}: {}", chrono::Utc::now(), e),
+                        )
+                        .await
+                        {
+                            warn!("Failed to notify owner of {}: {}", session_client_id, err);
+                        }
+                    }
+                }
                 return Ok(());
             }
             Ok(Command::V1(CommandV1::InferenceResult {
@@ -290,6 +410,16 @@ async fn handle_single_client(
                     )
                     .await;
             }
+            Ok(Command::V1(CommandV1::ContextOverflowApplied {
+                task_id,
+                strategy,
+                dropped_tokens,
+            })) => {
+                server_state
+                    .inference_scheduler
+                    .handle_context_overflow(task_id, strategy, dropped_tokens)
+                    .await;
+            }
 
             Ok(Command::V1(CommandV1::ModelDownloadProgress {
                 client_id: id,
@@ -345,6 +475,232 @@ async fn handle_single_client(
                     &status,
                     error.as_deref(),
                 ).await;
+
+                // Finished downloads decide the fate of a canary model
+                if matches!(status, DownloadStatus::Completed | DownloadStatus::Failed) {
+                    let db_pool = db_pool.clone();
+                    let policy = server_state.config.rollout_policy;
+                    tokio::spawn(async move {
+                        let succeeded = status == DownloadStatus::Completed;
+                        match record_rollout_result(
+                            &db_pool,
+                            &policy,
+                            &ClientId(id),
+                            &model_name,
+                            succeeded,
+                            error.as_deref(),
+                        )
+                        .await
+                        {
+                            Ok(Some(state)) => {
+                                info!("Canary of model {} is now {}", model_name, state.as_str())
+                            }
+                            Ok(None) => {}
+                            Err(e) => warn!(
+                                "Failed to record rollout result of model {}: {}",
+                                model_name, e
+                            ),
+                        }
+                    });
+                }
+            }
+
+            Ok(Command::V1(CommandV1::WorkerBusy {
+                client_id: id,
+                busy,
+                reason,
+            })) => {
+                if busy {
+                    warn!(
+                        "Client {} paused inference: {}",
+                        ClientId(id),
+                        reason.as_deref().unwrap_or("no reason given")
+                    );
+                } else {
+                    info!("Client {} resumed inference", ClientId(id));
+                }
+                if let Some(info) = active_clients.lock().await.get_mut(&ClientId(id)) {
+                    info.busy = busy;
+                }
+            }
+
+            Ok(Command::V1(CommandV1::ModelWarmth {
+                client_id: id,
+                warm,
+            })) => {
+                debug!(
+                    "Client {} model is {}",
+                    ClientId(id),
+                    if warm { "warm" } else { "cold" }
+                );
+                if let Some(info) = active_clients.lock().await.get_mut(&ClientId(id)) {
+                    info.cold = !warm;
+                }
+            }
+
+            Ok(Command::V1(CommandV1::UpdateConfigAck {
+                client_id: id,
+                version,
+                success,
+                error,
+            })) => {
+                if success {
+                    info!("Client {} applied config version {}", ClientId(id), version);
+                    if let Some(info) = active_clients.lock().await.get_mut(&ClientId(id)) {
+                        info.config_acked = Some(version);
+                    }
+                } else {
+                    warn!(
+                        "Client {} rejected config version {}: {}",
+                        ClientId(id),
+                        version,
+                        error.as_deref().unwrap_or("unknown error")
+                    );
+                }
+            }
+
+            Ok(Command::V1(CommandV1::EngineStatus {
+                client_id: id,
+                state,
+                restarts,
+                error,
+            })) => {
+                let error = error.as_deref().unwrap_or("none");
+                match state {
+                    EngineState::Healthy => info!(
+                        "Client {} engine is healthy after {} restarts",
+                        ClientId(id),
+                        restarts
+                    ),
+                    EngineState::Unhealthy | EngineState::Restarting => warn!(
+                        "Client {} engine is {:?} after {} restarts: {}",
+                        ClientId(id),
+                        state,
+                        restarts,
+                        error
+                    ),
+                    EngineState::CrashLoop => error!(
+                        "Client {} engine is crash looping after {} restarts: {}",
+                        ClientId(id),
+                        restarts,
+                        error
+                    ),
+                }
+                if let Some(info) = active_clients.lock().await.get_mut(&ClientId(id)) {
+                    info.engine_state = Some(state);
+                }
+            }
+
+            Ok(Command::V1(CommandV1::JobUpdate {
+                client_id: id,
+                job_id,
+                status,
+                output,
+                error,
+            })) => {
+                match status {
+                    JobStatus::Running | JobStatus::Succeeded => {
+                        info!("Client {} job {} is {:?}", ClientId(id), job_id, status)
+                    }
+                    JobStatus::Failed | JobStatus::Rejected => warn!(
+                        "Client {} job {} is {:?}: {}",
+                        ClientId(id),
+                        job_id,
+                        status,
+                        error.as_deref().unwrap_or("unknown error")
+                    ),
+                }
+                let mut clients = active_clients.lock().await;
+                let job = clients
+                    .get_mut(&ClientId(id))
+                    .and_then(|info| info.jobs.iter_mut().find(|job| job.job_id == job_id));
+                match job {
+                    Some(job) => {
+                        job.status = Some(status);
+                        job.output = output;
+                        job.error = error;
+                        job.updated_at = Utc::now();
+                    }
+                    None => debug!("Client {} reported untracked job {}", ClientId(id), job_id),
+                }
+            }
+
+            Ok(Command::V1(CommandV1::ChallengeResponse {
+                client_id: _,
+                challenge_id,
+                answer,
+            })) => {
+                // Answers only count for the worker logged in on this connection
+                server_state
+                    .handle_challenge_response(&session_client_id, challenge_id, &answer)
+                    .await;
+            }
+
+            Ok(Command::V1(CommandV1::ModelBenchmarkReport {
+                client_id: _,
+                benchmark,
+            })) => {
+                // Kept for the worker logged in on this connection
+                if authed {
+                    info!(
+                        "Client {} benchmarked {} at {} context and batch sizes",
+                        session_client_id,
+                        benchmark.model,
+                        benchmark.runs.len()
+                    );
+                    if let Err(e) =
+                        client::upsert_model_benchmark(&db_pool, &session_client_id, &benchmark)
+                            .await
+                    {
+                        warn!(
+                            "Failed to store benchmark of client {}: {}",
+                            session_client_id, e
+                        );
+                    }
+                } else {
+                    warn!("Dropped a benchmark sent before login from {}", addr);
+                }
+            }
+
+            Ok(Command::V1(CommandV1::RequestSmallerModel {
+                client_id: id,
+                pod_id,
+                rejected_models,
+                max_model_bytes,
+            })) => {
+                info!(
+                    "Client {} pod {} cannot fit {:?}, looking for a model within {}",
+                    ClientId(id),
+                    pod_id,
+                    rejected_models,
+                    format_bytes!(max_model_bytes)
+                );
+                // A lookup error leaves the pod idle, as an error result
+                // would make the worker drop the connection
+                let pods_model = match handle_smaller_model(
+                    &hot_models,
+                    &active_clients,
+                    &ClientId(id),
+                    pod_id,
+                    &rejected_models,
+                    max_model_bytes,
+                )
+                .await
+                {
+                    Ok(pod_model) => vec![pod_model],
+                    Err(e) => {
+                        error!("Failed to find a smaller model: {}", e);
+                        Vec::new()
+                    }
+                };
+                write_command(
+                    &mut *writer.lock().await,
+                    &Command::V1(CommandV1::PullModelResultV2 {
+                        error: None,
+                        pods_model,
+                    }),
+                )
+                .await?;
             }
 
             Ok(Command::V2(CommandV2::P2PConnectionRequest {
@@ -378,64 +734,30 @@ async fn handle_single_client(
                     (source, target)
                 };
 
-                let turn_host =
-                    std::env::var("TURN_HOST").map_err(|_| anyhow!("TURN_HOST env is required"))?;
-                let _turn_port: u16 = std::env::var("TURN_TURNS_PORT")
-                    .ok()
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(5349);
-                let turn_udp_port: u16 = std::env::var("TURN_TURN_UDP_PORT")
-                    .ok()
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(3478);
-                let stun_port: u16 = std::env::var("TURN_STUN_PORT")
-                    .ok()
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(3478);
-                let ttl_seconds: u64 = std::env::var("TURN_TTL_SECONDS")
-                    .ok()
-                    .and_then(|v| v.parse().ok())
-                    .unwrap_or(300);
-                let secret = std::env::var("TURN_REST_SECRET")
-                    .map_err(|_| anyhow!("TURN_REST_SECRET env is required"))?;
-
-                let now = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .map_err(|e| anyhow!("System time error: {e}"))?
-                    .as_secs();
-                let expires_at = now.saturating_add(ttl_seconds);
-                let username = format!("{}:{}", expires_at, hex::encode(source_client_id));
-                let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes())
-                    .map_err(|e| anyhow!("Invalid TURN_REST_SECRET: {e}"))?;
-                mac.update(username.as_bytes());
-                let password =
-                    base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes());
-
-                let stun_urls = vec![format!("stun:{}:{}", turn_host, stun_port)];
-                let turn_urls = vec![format!(
-                    "turn:{}:{}?transport=udp",
-                    turn_host, turn_udp_port
-                )];
-
+                let servers = p2p_servers(
+                    &server_state.config,
+                    client_ip(local_addr),
+                    &source_client_id,
+                )?;
                 let to_source = Command::V2(CommandV2::P2PConnectionConfig {
                     peer_id: target_client_id,
                     connection_id,
-                    stun_urls: stun_urls.clone(),
-                    turn_urls: turn_urls.clone(),
-                    turn_username: username.clone(),
-                    turn_password: password.clone(),
-                    expires_at,
+                    stun_urls: servers.stun_urls.clone(),
+                    turn_urls: servers.turn_urls.clone(),
+                    turn_username: servers.turn_username.clone(),
+                    turn_password: servers.turn_password.clone(),
+                    expires_at: servers.expires_at,
                     force_tls: false,
                 });
 
                 let to_target = Command::V2(CommandV2::P2PConnectionConfig {
                     peer_id: source_client_id,
                     connection_id,
-                    stun_urls,
-                    turn_urls,
-                    turn_username: username,
-                    turn_password: password,
-                    expires_at,
+                    stun_urls: servers.stun_urls,
+                    turn_urls: servers.turn_urls,
+                    turn_username: servers.turn_username,
+                    turn_password: servers.turn_password,
+                    expires_at: servers.expires_at,
                     force_tls: false,
                 });
 
@@ -495,6 +817,54 @@ async fn handle_single_client(
                 });
                 write_command(&mut *target_writer.lock().await, &forward).await?;
             }
+
+            // Outcome of a P2P attempt, passed on so the peer knows whether
+            // to use the direct path or keep going through gpuf-s
+            Ok(Command::V2(CommandV2::P2PConnectionEstablished {
+                peer_id,
+                connection_id,
+                connection_type,
+            })) => {
+                if !authed {
+                    return Err(anyhow!("P2PConnectionEstablished before login"));
+                }
+                info!(
+                    "P2P {} between {} and {}: {:?}",
+                    hex::encode(connection_id),
+                    session_client_id,
+                    ClientId(peer_id),
+                    connection_type
+                );
+                let forward = Command::V2(CommandV2::P2PConnectionEstablished {
+                    peer_id: session_client_id.0,
+                    connection_id,
+                    connection_type,
+                });
+                forward_to_peer(&active_clients, &ClientId(peer_id), &forward).await;
+            }
+
+            Ok(Command::V2(CommandV2::P2PConnectionFailed {
+                peer_id,
+                connection_id,
+                error,
+            })) => {
+                if !authed {
+                    return Err(anyhow!("P2PConnectionFailed before login"));
+                }
+                info!(
+                    "P2P {} between {} and {} failed, relaying through gpuf-s: {}",
+                    hex::encode(connection_id),
+                    session_client_id,
+                    ClientId(peer_id),
+                    error
+                );
+                let forward = Command::V2(CommandV2::P2PConnectionFailed {
+                    peer_id: session_client_id.0,
+                    connection_id,
+                    error,
+                });
+                forward_to_peer(&active_clients, &ClientId(peer_id), &forward).await;
+            }
             _ => {
                 warn!("Received unexpected command from client addr {}", addr);
             }
@@ -504,6 +874,92 @@ async fn handle_single_client(
     Ok(()) // This is theoretically unreachable but required by compiler
 }
 
+/// Servers of a P2P attempt: the STUN responder of this instance, and the
+/// TURN server of `TURN_HOST` with time-limited credentials (TURN REST API)
+/// when one is configured
+struct P2PServers {
+    stun_urls: Vec<String>,
+    turn_urls: Vec<String>,
+    turn_username: String,
+    turn_password: String,
+    expires_at: u64,
+}
+
+fn p2p_servers(
+    config: &ServerConfig,
+    local_ip: IpAddr,
+    client_id: &[u8; 16],
+) -> Result<P2PServers> {
+    let env_or = |name: &str, default: u64| {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default)
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| anyhow!("System time error: {e}"))?
+        .as_secs();
+    let expires_at = now.saturating_add(env_or("TURN_TTL_SECONDS", 300));
+
+    let mut servers = P2PServers {
+        stun_urls: Vec::new(),
+        turn_urls: Vec::new(),
+        turn_username: String::new(),
+        turn_password: String::new(),
+        expires_at,
+    };
+    if config.stun_port != 0 {
+        let host = match &config.stun_host {
+            Some(host) => host.clone(),
+            None if local_ip.is_ipv6() => format!("[{}]", local_ip),
+            None => local_ip.to_string(),
+        };
+        servers
+            .stun_urls
+            .push(format!("stun:{}:{}", host, config.stun_port));
+    }
+
+    let Ok(turn_host) = std::env::var("TURN_HOST") else {
+        return Ok(servers);
+    };
+    let turn_udp_port = env_or("TURN_TURN_UDP_PORT", 3478);
+    let stun_port = env_or("TURN_STUN_PORT", 3478);
+    let secret = std::env::var("TURN_REST_SECRET")
+        .map_err(|_| anyhow!("TURN_REST_SECRET env is required with TURN_HOST"))?;
+    let username = format!("{}:{}", expires_at, hex::encode(client_id));
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes())
+        .map_err(|e| anyhow!("Invalid TURN_REST_SECRET: {e}"))?;
+    mac.update(username.as_bytes());
+    servers
+        .stun_urls
+        .push(format!("stun:{}:{}", turn_host, stun_port));
+    servers.turn_urls.push(format!(
+        "turn:{}:{}?transport=udp",
+        turn_host, turn_udp_port
+    ));
+    servers.turn_username = username;
+    servers.turn_password =
+        base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes());
+    Ok(servers)
+}
+
+/// Send `command` to `peer` if it is connected here
+async fn forward_to_peer(active_clients: &ActiveClients, peer: &ClientId, command: &Command) {
+    let writer = {
+        let clients = active_clients.lock().await;
+        clients.get(peer).map(|c| c.writer.clone())
+    };
+    match writer {
+        Some(writer) => {
+            if let Err(e) = write_command(&mut *writer.lock().await, command).await {
+                warn!("Failed to forward P2P result to {}: {}", peer, e);
+            }
+        }
+        None => debug!("P2P peer {} is not connected here", peer),
+    }
+}
+
 async fn handle_login(
     version: u32,
     auto_models: bool,
@@ -514,9 +970,14 @@ async fn handle_login(
     client_id: &ClientId,
     os_type: OsType,
     devices_info: Vec<DevicesInfo>,
+    benchmark: Option<BenchmarkResult>,
     system_info: SystemInfo,
     writer: &Arc<Mutex<OwnedWriteHalf>>,
+    disconnect: &Arc<Notify>,
     authed: &mut bool,
+    config: &ServerConfig,
+    enrollment_token: Option<&str>,
+    location: Location,
 ) -> Result<CommandV1> {
     info!("Registration attempt for client_id: {}", client_id);
     let mut clients = active_clients.lock().await;
@@ -534,28 +995,43 @@ async fn handle_login(
     )
     .await?;
 
-    let validate_result = if is_valid {
+    let rejection = if is_valid {
+        match login_approval(db_pool, config, client_id, enrollment_token).await? {
+            client::ApprovalStatus::Approved => None,
+            client::ApprovalStatus::Pending => Some("Worker is waiting for operator approval"),
+            client::ApprovalStatus::Rejected => Some("Worker registration was rejected"),
+            client::ApprovalStatus::Banned => Some("Worker is banned"),
+        }
+    } else {
+        Some("Invalid client ID")
+    };
+
+    let validate_result = if let Some(error) = rejection {
+        warn!("Client {} login refused: {}", client_id, error);
+        CommandV1::LoginResultV2 {
+            success: false,
+            pods_model: Vec::new(),
+            error: Some(error.to_string()),
+        }
+    } else {
         info!("Client {} registered successfully", client_id);
         *authed = true;
-        
-        // Only recommend models if auto_models is enabled
-        let pods_model = if auto_models {
-            models::get_models_batch(&hot_models, &devices_info).await?
-        } else {
-            Vec::new()
+        if let Err(e) = client::set_client_location(db_pool, client_id, &location).await {
+            warn!("Failed to store location of client {}: {}", client_id, e);
+        }
+
+        // An operator pinned model wins, otherwise only recommend models if auto_models is enabled
+        let pods_model = match hot_models.get_pinned_model(client_id).await? {
+            Some(pinned) => models::pinned_pods_model(&devices_info, &pinned),
+            None if auto_models => models::get_models_batch(&hot_models, client_id, &devices_info).await?,
+            None => Vec::new(),
         };
 
-        CommandV1::LoginResult {
+        CommandV1::LoginResultV2 {
             success: true,
             pods_model,
             error: None,
         }
-    } else {
-        CommandV1::LoginResult {
-            success: false,
-            pods_model: Vec::new(),
-            error: Some("Invalid client ID".to_string()),
-        }
     };
 
     debug!(
@@ -563,6 +1039,23 @@ async fn handle_login(
         client_id, validate_result
     );
 
+    let admin_state = client::get_client_admin_state(db_pool, client_id)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Failed to load admin state for client {}: {}", client_id, e);
+            Default::default()
+        });
+    let quality_score = canary::get_quality_score(db_pool, client_id)
+        .await
+        .unwrap_or_else(|e| {
+            warn!(
+                "Failed to load quality score for client {}: {}",
+                client_id, e
+            );
+            None
+        })
+        .unwrap_or(MAX_QUALITY_SCORE);
+
     clients.insert(
         *client_id,
         ClientInfo {
@@ -581,47 +1074,115 @@ async fn handle_login(
             connected_at: Utc::now(),
             models: None,
             devices_info,
+            maintenance: admin_state.maintenance,
+            disconnect: disconnect.clone(),
+            config_version: None,
+            config_acked: None,
+            gpu_metrics: Vec::new(),
+            benchmark,
+            network_quality: None,
+            power: None,
+            busy: false,
+            engine_state: None,
+            cold: false,
+            jobs: VecDeque::new(),
+            challenge: None,
+            quality_score,
+            location,
+            worker_version: None,
         },
     );
     Ok(validate_result)
 }
 
+/// Approval of a known client at login, approving a pending one the
+/// approval policy lets in
+async fn login_approval(
+    db_pool: &Pool<Postgres>,
+    config: &ServerConfig,
+    client_id: &ClientId,
+    enrollment_token: Option<&str>,
+) -> Result<client::ApprovalStatus> {
+    let status = client::get_client_approval(db_pool, client_id)
+        .await?
+        .ok_or_else(|| anyhow!("Client {} is not registered", client_id))?;
+    if status != client::ApprovalStatus::Pending {
+        return Ok(status);
+    }
+
+    let approve = match config.approval_policy {
+        cmd::ApprovalPolicy::Auto => true,
+        cmd::ApprovalPolicy::Token => enrollment_token
+            .is_some_and(|token| config.enrollment_tokens.iter().any(|t| t == token)),
+        cmd::ApprovalPolicy::Manual => false,
+    };
+    if !approve {
+        return Ok(status);
+    }
+    client::set_client_approval(db_pool, client_id, client::ApprovalStatus::Approved).await?;
+    info!(
+        "Approved client {} at login by the {:?} policy",
+        client_id, config.approval_policy
+    );
+    Ok(client::ApprovalStatus::Approved)
+}
+
 async fn handle_models_status(
     hot_models: &Arc<HotModelClass>,
     active_clients: &Arc<Mutex<HashMap<ClientId, ClientInfo>>>,
     client_id: &ClientId,
     auto_models_device: Vec<DevicesInfo>,
     models: Vec<Model>,
-) -> Result<Vec<PodModel>> {
+) -> Result<Vec<PodModelV2>> {
     //TODO: push msg-> api filter
     let mut clients = active_clients.lock().await;
     if let Some(client) = clients.get_mut(client_id) {
         client.models = Some(models);
     }
 
-    let mut pods_model: Vec<PodModel> = Vec::with_capacity(auto_models_device.len());
+    drop(clients);
+
+    if let Some(pinned) = hot_models.get_pinned_model(client_id).await? {
+        return Ok(models::pinned_pods_model(&auto_models_device, &pinned));
+    }
+
+    let mut pods_model: Vec<PodModelV2> = Vec::with_capacity(auto_models_device.len());
 
     for device in auto_models_device {
         match hot_models
-            .get_hot_model_with_details(device.memtotal_gb as u32, device.engine_type.to_i16())
+            .get_hot_model_with_details(
+                client_id,
+                device.memtotal_gb as u32,
+                device.engine_type.to_i16(),
+            )
             .await
         {
             Ok(model_info) => {
-                pods_model.push(PodModel {
+                pods_model.push(PodModelV2 {
                     pod_id: device.pod_id,
                     model_name: if model_info.name.is_empty() { None } else { Some(model_info.name) },
                     download_url: model_info.download_url,
                     checksum: model_info.checksum,
                     expected_size: model_info.expected_size.map(|s| s as u64),
+                    signature: model_info.signature,
+                    version: model_info.version,
+                    chunk_manifest_url: model_info.chunk_manifest_url,
+                    draft_download_url: model_info.draft_download_url,
+                    draft_checksum: model_info.draft_checksum,
                 });
             }
             Err(e) => {
-                pods_model.push(PodModel {
+                pods_model.push(PodModelV2 {
                     pod_id: device.pod_id,
                     model_name: None,
                     download_url: None,
                     checksum: None,
                     expected_size: None,
+                    signature: None,
+                    version: None,
+                    chunk_manifest_url: None,
+                    draft_download_url: None,
+                    draft_checksum: None,
                 });
                 error!("Failed to get hot model: {}", e);
             }
@@ -631,6 +1192,108 @@ async fn handle_models_status(
     Ok(pods_model)
 }
 
+/// Count a finished download towards the canary of the model, if one is
+/// running, and return the canary's new state once it is decided
+async fn record_rollout_result(
+    db_pool: &Pool<Postgres>,
+    policy: &RolloutPolicy,
+    client_id: &ClientId,
+    model_name: &str,
+    succeeded: bool,
+    error: Option<&str>,
+) -> Result<Option<RolloutState>, sqlx::Error> {
+    let Some(model_id) = rollouts::find_canary(db_pool, model_name).await? else {
+        return Ok(None);
+    };
+    rollouts::record_result(db_pool, model_id, client_id, succeeded, error).await?;
+    rollouts::evaluate(db_pool, model_id, policy).await
+}
+
+/// Models loaded and unloaded between two model status reports of a client.
+/// The first report after login counts every model as loaded.
+fn model_changes(previous: Option<&[Model]>, current: &[Model]) -> Vec<(WorkerEventKind, String)> {
+    let previous = previous.unwrap_or_default();
+    let loaded = current
+        .iter()
+        .filter(|m| !previous.iter().any(|p| p.id == m.id))
+        .map(|m| (WorkerEventKind::ModelLoaded, m.id.clone()));
+    let unloaded = previous
+        .iter()
+        .filter(|p| !current.iter().any(|m| m.id == p.id))
+        .map(|p| (WorkerEventKind::ModelUnloaded, p.id.clone()));
+    loaded.chain(unloaded).collect()
+}
+
+/// Next model for a pod whose free memory cannot fit the ones it was offered.
+/// A pinned client gets no model rather than one the operator did not choose.
+async fn handle_smaller_model(
+    hot_models: &Arc<HotModelClass>,
+    active_clients: &Arc<Mutex<HashMap<ClientId, ClientInfo>>>,
+    client_id: &ClientId,
+    pod_id: u16,
+    rejected_models: &[String],
+    max_model_bytes: u64,
+) -> Result<PodModelV2> {
+    let mut pod_model = PodModelV2 {
+        pod_id,
+        model_name: None,
+        download_url: None,
+        checksum: None,
+        expected_size: None,
+        signature: None,
+        version: None,
+        chunk_manifest_url: None,
+        draft_download_url: None,
+        draft_checksum: None,
+    };
+
+    if hot_models.get_pinned_model(client_id).await?.is_some() {
+        warn!(
+            "Client {} cannot fit its pinned model, leaving pod {} without a model",
+            client_id, pod_id
+        );
+        return Ok(pod_model);
+    }
+
+    let device = {
+        let clients = active_clients.lock().await;
+        let devices = clients
+            .get(client_id)
+            .map(|client| client.devices_info.clone())
+            .unwrap_or_default();
+        devices
+            .iter()
+            .find(|device| device.pod_id == pod_id)
+            .or(devices.first())
+            .cloned()
+    };
+    let Some(device) = device else {
+        return Err(anyhow!("Unknown pod {} of client {}", pod_id, client_id));
+    };
+
+    let model_info = hot_models
+        .get_smaller_model_with_details(
+            client_id,
+            device.memtotal_gb as u32,
+            device.engine_type.to_i16(),
+            max_model_bytes,
+            rejected_models,
+        )
+        .await?;
+    if !model_info.name.is_empty() {
+        pod_model.model_name = Some(model_info.name);
+        pod_model.download_url = model_info.download_url;
+        pod_model.checksum = model_info.checksum;
+        pod_model.expected_size = model_info.expected_size.map(|s| s as u64);
+        pod_model.signature = model_info.signature;
+        pod_model.version = model_info.version;
+        pod_model.chunk_manifest_url = model_info.chunk_manifest_url;
+        pod_model.draft_download_url = model_info.draft_download_url;
+        pod_model.draft_checksum = model_info.draft_checksum;
+    }
+    Ok(pod_model)
+}
+
 async fn upsert_client_models_in_redis(
     redis_client: &Arc<RedisClient>,
     client_id: &ClientId,
@@ -662,6 +1325,7 @@ async fn handle_heartbeat(
     device_memtotal_gb: u32,
     device_count: u32,
     total_tflops: u32,
+    worker_version: Option<common::WorkerVersion>,
 ) {
     debug!("Sending heartbeat to consumer client_id {} cpu_usage {}%  memory_usage {}% disk_usage {}% device_memtotal_gb {} GB device_count {} total_tflops {} tflops", client_id, system_info.cpu_usage, system_info.memory_usage, system_info.disk_usage, device_memtotal_gb, device_count, total_tflops);
 
@@ -672,6 +1336,7 @@ async fn handle_heartbeat(
         total_tflops,
         system_info,
         devices_info,
+        worker_version,
     };
 
     let cfg = config::standard()
@@ -753,4 +1418,37 @@ async fn update_model_download_progress_in_redis(
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    fn model(id: &str) -> Model {
+        Model {
+            id: id.to_string(),
+            object: "model".to_string(),
+            created: 0,
+            owned_by: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_model_changes() {
+        let current = vec![model("a"), model("b")];
+        assert_eq!(
+            model_changes(None, &current),
+            vec![
+                (WorkerEventKind::ModelLoaded, "a".to_string()),
+                (WorkerEventKind::ModelLoaded, "b".to_string()),
+            ]
+        );
+
+        let previous = vec![model("b"), model("c")];
+        assert_eq!(
+            model_changes(Some(&previous), &current),
+            vec![
+                (WorkerEventKind::ModelLoaded, "a".to_string()),
+                (WorkerEventKind::ModelUnloaded, "c".to_string()),
+            ]
+        );
+        assert!(model_changes(Some(&current), &current).is_empty());
+    }
+}
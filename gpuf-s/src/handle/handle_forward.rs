@@ -0,0 +1,295 @@
+use super::*;
+
+use crate::db::presence;
+use crate::handle::handle_agent::{
+    connect_client_filter_model_and_client, proxied_usage, publish_proxied_request,
+    send_http_error_response,
+};
+use redis::AsyncCommands;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Largest header frame a peer may send
+const MAX_HEADER_BYTES: u32 = 1024 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// First frame of a forwarded request, a big-endian u32 length and the JSON
+/// header. The raw HTTP request of the user follows it. Nothing is encrypted,
+/// the forward port belongs on a private network.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardHeader {
+    pub secret: String,
+    pub from_instance: String,
+    /// Clients the API key may use that are attached to the receiving instance
+    pub client_ids: Vec<ClientId>,
+    pub model: Option<String>,
+    pub worker: Option<ClientId>,
+    /// Region the request prefers its worker in
+    #[serde(default)]
+    pub region: Option<String>,
+    pub request_id: Option<String>,
+    pub api_key: String,
+    pub metered: bool,
+}
+
+/// Another instance holding workers a request could go to
+#[derive(Debug, Clone)]
+pub struct RemoteTarget {
+    pub instance_id: String,
+    pub addr: String,
+    pub client_ids: Vec<ClientId>,
+}
+
+/// Hands public requests to the instance a worker is attached to, and takes
+/// such requests from the other instances
+pub struct Forwarder {
+    redis_client: Arc<RedisClient>,
+    presence: Arc<Presence>,
+    secret: String,
+    /// Address the other instances reach this one's forward port at
+    addr: String,
+}
+
+impl Forwarder {
+    pub fn new(
+        redis_client: Arc<RedisClient>,
+        presence: Arc<Presence>,
+        secret: String,
+        addr: String,
+    ) -> Self {
+        Self {
+            redis_client,
+            presence,
+            secret,
+            addr,
+        }
+    }
+
+    /// Keep the forward address of this instance published
+    pub async fn advertise(self: Arc<Self>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.presence.advertise_forward_addr(&self.addr).await {
+                warn!("Failed to advertise forward address {}: {}", self.addr, e);
+            }
+        }
+    }
+
+    /// The first instance holding one of `client_ids` that serves `model`,
+    /// or `worker` when named, with the candidates it holds
+    pub async fn find_remote(
+        &self,
+        client_ids: &[ClientId],
+        model: Option<&str>,
+        worker: Option<ClientId>,
+    ) -> Result<Option<RemoteTarget>> {
+        let candidates: Vec<ClientId> = client_ids
+            .iter()
+            .copied()
+            .filter(|id| worker.map_or(true, |worker| worker == *id))
+            .collect();
+        let instances = presence::get_instances(&self.redis_client, &candidates).await?;
+        let mut remote: Vec<(ClientId, String)> = candidates
+            .into_iter()
+            .zip(instances)
+            .filter_map(|(id, instance)| {
+                instance
+                    .filter(|instance| instance != self.presence.instance_id())
+                    .map(|instance| (id, instance))
+            })
+            .collect();
+        if let Some(model) = model {
+            remote = self.serving_model(remote, model).await?;
+        }
+
+        let mut tried: Vec<&str> = Vec::new();
+        for (_, instance_id) in &remote {
+            if tried.contains(&instance_id.as_str()) {
+                continue;
+            }
+            tried.push(instance_id);
+            let Some(addr) = presence::get_forward_addr(&self.redis_client, instance_id).await?
+            else {
+                continue;
+            };
+            return Ok(Some(RemoteTarget {
+                instance_id: instance_id.clone(),
+                addr,
+                client_ids: remote
+                    .iter()
+                    .filter(|(_, instance)| instance == instance_id)
+                    .map(|(id, _)| *id)
+                    .collect(),
+            }));
+        }
+        Ok(None)
+    }
+
+    /// Keep the clients whose last model report lists `model`
+    async fn serving_model(
+        &self,
+        clients: Vec<(ClientId, String)>,
+        model: &str,
+    ) -> Result<Vec<(ClientId, String)>> {
+        if clients.is_empty() {
+            return Ok(clients);
+        }
+        let mut conn = self.redis_client.get_async_connection().await?;
+        let keys: Vec<String> = clients
+            .iter()
+            .map(|(id, _)| format!("client:{}:models", id))
+            .collect();
+        let reports: Vec<Option<String>> = if keys.len() == 1 {
+            vec![conn.get(&keys[0]).await?]
+        } else {
+            conn.get(keys).await?
+        };
+        Ok(clients
+            .into_iter()
+            .zip(reports)
+            .filter(|(_, report)| {
+                report
+                    .as_deref()
+                    .and_then(|json| serde_json::from_str::<Vec<Model>>(json).ok())
+                    .is_some_and(|models| models.iter().any(|m| m.id == model))
+            })
+            .map(|(client, _)| client)
+            .collect())
+    }
+
+    /// Send the request to `target` and relay the streams until either side
+    /// closes
+    pub async fn forward(
+        &self,
+        target: &RemoteTarget,
+        mut header: ForwardHeader,
+        user_stream: TcpStream,
+        buffer: BytesMut,
+    ) -> Result<()> {
+        header.secret = self.secret.clone();
+        header.from_instance = self.presence.instance_id().to_string();
+        header.client_ids = target.client_ids.clone();
+
+        let mut peer = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&target.addr))
+            .await
+            .map_err(|_| anyhow!("Timed out connecting to instance {}", target.instance_id))??;
+        let _ = peer.set_nodelay(true);
+        let frame = serde_json::to_vec(&header)?;
+        peer.write_u32(frame.len() as u32).await?;
+        peer.write_all(&frame).await?;
+        peer.write_all(&buffer).await?;
+        peer.flush().await?;
+
+        join_streams(user_stream, peer).await?;
+        Ok(())
+    }
+}
+
+async fn read_header(stream: &mut TcpStream) -> Result<ForwardHeader> {
+    let len = stream.read_u32().await?;
+    if len > MAX_HEADER_BYTES {
+        return Err(anyhow!("Forward header of {} bytes is too large", len));
+    }
+    let mut frame = vec![0u8; len as usize];
+    stream.read_exact(&mut frame).await?;
+    Ok(serde_json::from_slice(&frame)?)
+}
+
+impl ServerState {
+    /// Take requests other instances forward for workers attached here
+    pub async fn handle_forward_connections(self: Arc<Self>, listener: TcpListener) -> Result<()> {
+        let secret = self
+            .forwarder
+            .as_ref()
+            .map(|forwarder| forwarder.secret.clone())
+            .ok_or_else(|| anyhow!("Forwarding is not configured"))?;
+        loop {
+            let (mut stream, addr) = listener.accept().await?;
+            let _ = stream.set_nodelay(true);
+            let state = self.clone();
+            let secret = secret.clone();
+            tokio::spawn(async move {
+                let header =
+                    match tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream)).await {
+                        Ok(Ok(header)) => header,
+                        Ok(Err(e)) => {
+                            warn!("Invalid forwarded request from {}: {}", addr, e);
+                            return;
+                        }
+                        Err(_) => {
+                            warn!("Forwarded request from {} sent no header", addr);
+                            return;
+                        }
+                    };
+                if ring::constant_time::verify_slices_are_equal(
+                    header.secret.as_bytes(),
+                    secret.as_bytes(),
+                )
+                .is_err()
+                {
+                    warn!("Rejected forwarded request from {}: wrong secret", addr);
+                    return;
+                }
+                if let Err(e) = state.route_forwarded(stream, header).await {
+                    error!("Failed to route forwarded request from {}: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    async fn route_forwarded(&self, stream: TcpStream, header: ForwardHeader) -> Result<()> {
+        info!(
+            "Forwarded request {} from instance {}",
+            header.request_id.as_deref().unwrap_or("-"),
+            header.from_instance
+        );
+        let mut active_clients = self.active_clients.lock().await;
+        let chosen = connect_client_filter_model_and_client(
+            header.model.as_deref(),
+            header.worker,
+            header.region.as_deref(),
+            header.client_ids,
+            &mut active_clients,
+        )
+        .await;
+        drop(active_clients);
+
+        let client_id = match chosen {
+            Ok((client_id, proxy_conn_id)) => {
+                // The request itself is still unread on the stream, the
+                // worker gets it once the proxy connection is joined
+                let buffer = self.buffer_pool.get().await;
+                self.pending_connections.lock().await.insert(
+                    proxy_conn_id,
+                    PendingConnection {
+                        user_stream: stream,
+                        buffer,
+                        usage: proxied_usage(
+                            header.request_id.clone(),
+                            header.api_key.clone(),
+                            client_id,
+                            header.model.clone().unwrap_or_default(),
+                        ),
+                        slot: None,
+                    },
+                );
+                client_id
+            }
+            Err(e) => {
+                send_http_error_response(stream, 400, "No available clients").await?;
+                return Err(anyhow!("No available clients {}", e));
+            }
+        };
+
+        publish_proxied_request(
+            self.producer.clone(),
+            header.request_id,
+            client_id,
+            header.metered,
+        )
+        .await
+    }
+}
@@ -0,0 +1,34 @@
+use super::*;
+
+use crate::db::presence;
+use futures_util::StreamExt;
+use std::time::Duration;
+
+impl ServerState {
+    /// Drop the local session of a worker that logged in at another instance,
+    /// its connection here is stale then
+    pub async fn watch_presence(self: Arc<Self>) {
+        loop {
+            match presence::subscribe(&self.redis_client).await {
+                Ok(events) => {
+                    let mut events = std::pin::pin!(events);
+                    while let Some(event) = events.next().await {
+                        if !event.online || event.instance_id == self.presence.instance_id() {
+                            continue;
+                        }
+                        if let Some(info) = self.active_clients.lock().await.get(&event.client_id) {
+                            info!(
+                                "Client {} moved to instance {}, dropping its session",
+                                event.client_id, event.instance_id
+                            );
+                            info.disconnect.notify_one();
+                        }
+                    }
+                    warn!("Presence subscription closed, resubscribing");
+                }
+                Err(e) => error!("Failed to subscribe to presence events: {}", e),
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+}
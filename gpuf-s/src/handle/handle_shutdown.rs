@@ -0,0 +1,160 @@
+//! Graceful shutdown for rolling deploys. Once the listeners are closed,
+//! requests in flight get up to the drain timeout to finish, then the
+//! workers are told where to reconnect and buffered usage records and Kafka
+//! messages are flushed before the server exits.
+
+use super::*;
+
+use futures::future::join_all;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// How often a drain checks whether the proxied requests are done
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Workers' reconnects are spread over this long, so the instance taking
+/// over is not hit by all of them at once
+const RECONNECT_SPREAD: Duration = Duration::from_secs(10);
+/// A worker whose connection stays busy this long is not told to reconnect;
+/// it notices the closed connection instead
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(1);
+const PRODUCER_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of requests in flight, so a shutdown can wait for them
+#[derive(Debug, Default)]
+pub struct InFlight(AtomicUsize);
+
+/// Counts one request in its [`InFlight`] until dropped
+pub struct InFlightGuard(Arc<InFlight>);
+
+impl InFlight {
+    pub fn enter(self: &Arc<Self>) -> InFlightGuard {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard(Arc::clone(self))
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0 .0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Delay of the `index`th of `workers` reconnecting workers
+fn reconnect_delay(index: usize, workers: usize) -> Duration {
+    if workers <= 1 {
+        return Duration::ZERO;
+    }
+    RECONNECT_SPREAD * index as u32 / workers as u32
+}
+
+impl ServerState {
+    /// Wait up to `timeout` for the inference gateway and the proxied
+    /// requests to finish, then send the workers `ServerRestarting` and flush
+    /// what is left to write. The accept loops must be stopped already.
+    pub async fn shut_down(
+        &self,
+        gateway: JoinHandle<()>,
+        timeout: Duration,
+        reconnect_to: Option<String>,
+    ) {
+        info!(
+            "Draining requests in flight for up to {}s",
+            timeout.as_secs()
+        );
+        let drained = tokio::time::timeout(timeout, async {
+            let _ = gateway.await;
+            while self.proxied_requests.count() > 0
+                || !self.pending_connections.lock().await.is_empty()
+            {
+                tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+            }
+        })
+        .await;
+        match drained {
+            Ok(_) => info!("All requests in flight finished"),
+            Err(_) => warn!(
+                "Drain timeout passed with {} proxied requests in flight",
+                self.proxied_requests.count()
+            ),
+        }
+
+        self.send_server_restarting(reconnect_to).await;
+
+        self.usage_recorder.flush().await;
+        if let Err(e) = self.producer.flush(PRODUCER_FLUSH_TIMEOUT) {
+            error!("Failed to flush Kafka producer: {:?}", e);
+        }
+    }
+
+    async fn send_server_restarting(&self, reconnect_to: Option<String>) {
+        let writers: Vec<_> = self
+            .active_clients
+            .lock()
+            .await
+            .iter()
+            // Older workers do not know ServerRestarting and notice the
+            // closed connection instead
+            .filter(|(_, info)| info.authed && info.version >= PROTOCOL_VERSION)
+            .map(|(client_id, info)| (*client_id, Arc::clone(&info.writer)))
+            .collect();
+        let workers = writers.len();
+
+        let sends = writers
+            .into_iter()
+            .enumerate()
+            .map(|(index, (client_id, writer))| {
+                let command = Command::V1(CommandV1::ServerRestarting {
+                    reconnect_to: reconnect_to.clone(),
+                    delay_ms: reconnect_delay(index, workers).as_millis() as u32,
+                });
+                async move {
+                    let sent = tokio::time::timeout(NOTIFY_TIMEOUT, async {
+                        write_command(&mut *writer.lock().await, &command).await
+                    })
+                    .await;
+                    match sent {
+                        Ok(Ok(())) => true,
+                        Ok(Err(e)) => {
+                            warn!("Failed to tell client {} to reconnect: {}", client_id, e);
+                            false
+                        }
+                        Err(_) => {
+                            warn!("Client {} stayed busy, not told to reconnect", client_id);
+                            false
+                        }
+                    }
+                }
+            });
+        let told = join_all(sends)
+            .await
+            .into_iter()
+            .filter(|sent| *sent)
+            .count();
+        match &reconnect_to {
+            Some(host) => info!("Told {}/{} workers to reconnect to {}", told, workers, host),
+            None => info!("Told {}/{} workers to reconnect", told, workers),
+        }
+    }
+}
+
+#[test]
+fn test_in_flight() {
+    let in_flight = Arc::new(InFlight::default());
+    let first = in_flight.enter();
+    let second = in_flight.enter();
+    assert_eq!(in_flight.count(), 2);
+    drop(first);
+    assert_eq!(in_flight.count(), 1);
+    drop(second);
+    assert_eq!(in_flight.count(), 0);
+
+    assert_eq!(reconnect_delay(0, 1), Duration::ZERO);
+    assert_eq!(reconnect_delay(0, 4), Duration::ZERO);
+    assert_eq!(reconnect_delay(2, 4), RECONNECT_SPREAD / 2);
+    assert!(reconnect_delay(3, 4) < RECONNECT_SPREAD);
+}
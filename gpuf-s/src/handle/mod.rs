@@ -1,46 +1,151 @@
+pub mod handle_admin;
 pub mod handle_agent;
+pub mod handle_challenge;
 pub mod handle_connections;
+pub mod handle_forward;
+pub mod handle_presence;
+pub mod handle_shutdown;
 
-use crate::db::{models::ClientModelClass, models::HotModelClass};
-use crate::inference::InferenceScheduler;
+use crate::db::usage::UsageRecord;
+use crate::db::{
+    models::ClientModelClass, models::HotModelClass, presence::Presence, rollouts::RolloutPolicy,
+    usage::UsageRecorder,
+};
+use crate::inference::{InferenceScheduler, RequestLimits, ResponseCache, UploadStore};
+use crate::util::geo::{GeoIp, Location};
+use crate::util::net::{IpRange, ListenAddr};
 use crate::util::pack::BufferPool;
+use crate::util::rate_limit::{ConcurrencySlot, RateLimiter};
 use crate::util::{
-    cmd, db,
+    cmd, config, db,
     protoc::{ClientId, ProxyConnId},
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use bytes::BytesMut;
 use chrono::{DateTime, Utc};
-use common::{join_streams, read_command, write_command, Command, CommandV1, DevicesInfo, Model};
+use common::{
+    join_streams, read_command, write_command, write_command_for, BenchmarkResult, ChallengeTask,
+    Command, CommandV1, DevicesInfo, EngineState, GpuMetrics, JobKind, JobOutput, JobStatus, Model,
+    NetworkQuality, PowerStatus, WorkerRelease, WorkerVersion, PROTOCOL_VERSION,
+};
 use rdkafka::producer::FutureProducer;
 use rdkafka::producer::Producer;
 use redis::Client as RedisClient;
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 use tokio::net::{tcp::OwnedWriteHalf, TcpStream};
-use tokio::sync::Mutex;
-use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use tracing::{error, info};
+use tokio::sync::{Mutex, Notify};
+use tracing::{error, info, warn};
 
 pub type UserDb = Arc<Mutex<HashMap<String, User>>>;
 pub type TokenDb = Arc<Mutex<HashMap<String, String>>>;
 pub type ActiveClients = Arc<Mutex<HashMap<ClientId, ClientInfo>>>;
-pub type PendingConnections = Arc<Mutex<HashMap<ProxyConnId, (TcpStream, BytesMut)>>>;
+pub type PendingConnections = Arc<Mutex<HashMap<ProxyConnId, PendingConnection>>>;
+
+/// A user connection waiting for the proxy connection of its worker
+pub struct PendingConnection {
+    pub user_stream: TcpStream,
+    /// Request bytes already read from `user_stream`
+    pub buffer: BytesMut,
+    /// Usage of the request, completed with the tokens, status and latency of
+    /// the response when the connection closes
+    pub usage: UsageRecord,
+    /// Concurrency slot of the API key, held until the connection closes
+    pub slot: Option<ConcurrencySlot>,
+}
 
 pub struct ClientInfo {
     pub writer: Arc<Mutex<OwnedWriteHalf>>,
     pub authed: bool,
-    #[allow(dead_code)] // Client protocol version
     pub version: u32,
     pub system_info: Option<SystemInfo>,
-    #[allow(dead_code)] // Connected devices information
     pub devices_info: Vec<DevicesInfo>,
-    #[allow(dead_code)] // Connection timestamp
     pub connected_at: DateTime<Utc>,
     pub models: Option<Vec<Model>>,
+    /// Connected but excluded from scheduling
+    pub maintenance: bool,
+    /// Wakes the connection task to drop the session
+    pub disconnect: Arc<Notify>,
+    /// Version of the last config pushed to the worker
+    pub config_version: Option<u64>,
+    /// Last config version the worker reported as applied
+    pub config_acked: Option<u64>,
+    /// Per-GPU telemetry from the latest heartbeat
+    pub gpu_metrics: Vec<GpuMetrics>,
+    /// Benchmark the worker measured before login
+    pub benchmark: Option<BenchmarkResult>,
+    /// Latest link probe reported by the worker
+    pub network_quality: Option<NetworkQuality>,
+    /// Battery and thermal state of mobile workers
+    pub power: Option<PowerStatus>,
+    /// Worker paused itself, e.g. a phone that is hot or low on battery
+    pub busy: bool,
+    /// Last engine health the worker reported, None until it reports one
+    pub engine_state: Option<EngineState>,
+    /// Worker reported its model as still loading or not warmed up
+    pub cold: bool,
+    /// Jobs dispatched to the worker, oldest first
+    pub jobs: VecDeque<JobRecord>,
+    /// Liveness challenge awaiting the worker's answer
+    pub challenge: Option<PendingChallenge>,
+    /// 0.0 to 1.0, lowered by wrong canary answers; low scores get no inference
+    pub quality_score: f32,
+    /// Region and country, preferred by callers asking for them
+    pub location: Location,
+    /// Build and engine versions from the latest heartbeat that had them
+    pub worker_version: Option<WorkerVersion>,
+}
+
+/// A liveness challenge sent to a worker, awaiting its answer
+#[derive(Debug, Clone)]
+pub struct PendingChallenge {
+    pub challenge_id: [u8; 16],
+    pub task: ChallengeTask,
+    pub deadline: DateTime<Utc>,
+}
+
+/// Jobs remembered per worker, the oldest dropped first
+const MAX_TRACKED_JOBS: usize = 64;
+
+/// A job dispatched to a worker and what it last reported
+#[derive(Debug, Clone, Serialize)]
+pub struct JobRecord {
+    pub job_id: String,
+    pub kind: JobKind,
+    /// None until the worker answers
+    pub status: Option<JobStatus>,
+    pub output: Option<JobOutput>,
+    pub error: Option<String>,
+    pub dispatched_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ClientInfo {
+    /// Whether the worker can take inference: not paused, and its engine is
+    /// healthy or has not reported otherwise
+    pub fn available(&self) -> bool {
+        !self.busy && matches!(self.engine_state, None | Some(EngineState::Healthy))
+    }
+
+    /// Remember a job dispatched to the worker
+    pub fn track_job(&mut self, job_id: String, kind: JobKind) {
+        if self.jobs.len() >= MAX_TRACKED_JOBS {
+            self.jobs.pop_front();
+        }
+        let now = Utc::now();
+        self.jobs.push_back(JobRecord {
+            job_id,
+            kind,
+            status: None,
+            output: None,
+            error: None,
+            dispatched_at: now,
+            updated_at: now,
+        });
+    }
 }
 
 pub struct User {
@@ -70,10 +175,22 @@ struct ServerStats {
 
 #[derive(Serialize, Clone)]
 pub struct ServerConfig {
+    pub listen_addr: ListenAddr,
     pub control_port: u16,
     pub proxy_port: u16,
     pub public_port: u16,
     pub api_port: u16,
+    pub admin_port: u16,
+    pub stun_port: u16,
+    pub stun_host: Option<String>,
+    pub instance_id: String,
+    pub approval_policy: cmd::ApprovalPolicy,
+    #[serde(skip)]
+    pub enrollment_tokens: Vec<String>,
+    /// Peers whose `X-Forwarded-For` the admin API's audit log believes
+    #[serde(skip)]
+    pub trusted_proxies: Arc<Vec<IpRange>>,
+    pub rollout_policy: RolloutPolicy,
 }
 
 #[derive(Clone)]
@@ -98,9 +215,25 @@ pub struct ServerState {
     pub inference_scheduler: Arc<InferenceScheduler>,
     pub client_model: Arc<ClientModelClass>,
     pub hot_models: Arc<HotModelClass>,
-    pub cert_chain: Arc<Vec<CertificateDer<'static>>>,
-    pub priv_key: Arc<PrivateKeyDer<'static>>,
+    /// TLS of the proxy port, see [`crate::util::tls`]
+    pub tls_config: Arc<tokio_rustls::rustls::ServerConfig>,
     pub buffer_pool: Arc<BufferPool>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub usage_recorder: Arc<UsageRecorder>,
+    pub presence: Arc<Presence>,
+    /// Set when requests can be forwarded between instances
+    pub forwarder: Option<Arc<handle_forward::Forwarder>>,
+    /// Country lookup of worker addresses, set with `--geoip-db`
+    pub geoip: Option<Arc<GeoIp>>,
+    /// Uploaded job inputs, see [`crate::inference::uploads`]
+    pub uploads: Arc<UploadStore>,
+    pub response_cache: Arc<ResponseCache>,
+    /// Limits of completion requests, see [`crate::inference::limits`]
+    pub request_limits: Arc<RequestLimits>,
+    /// Public connections being routed or proxied, drained on shutdown
+    pub proxied_requests: Arc<handle_shutdown::InFlight>,
+    /// gpuf-c release workers update to, sent at login
+    pub worker_release: Arc<std::sync::RwLock<Option<WorkerRelease>>>,
 }
 
 impl Drop for ServerState {
@@ -120,27 +253,7 @@ struct ChatCompletionRequest {
 }
 
 pub async fn new_server_state(args: &cmd::Args) -> Result<ServerState, anyhow::Error> {
-    // check cert chain path
-    let cert_chain_path = args.proxy_cert_chain_path.clone();
-    if std::path::Path::new(&cert_chain_path).exists() {
-        info!(" Certificate chain path exists: {}", cert_chain_path);
-    } else {
-        return Err(anyhow!(
-            " Certificate chain path does not exist: {}",
-            cert_chain_path
-        ));
-    }
-
-    // check private key path
-    let private_key_path = args.proxy_private_key_path.clone();
-    if std::path::Path::new(&private_key_path).exists() {
-        info!(" Private key path exists: {}", private_key_path);
-    } else {
-        return Err(anyhow!(
-            " Private key path does not exist: {}",
-            private_key_path
-        ));
-    }
+    let tls_config = crate::util::tls::proxy_tls_config(args)?;
 
     let (db_pool, redis_client, producer): (
         Arc<Pool<Postgres>>,
@@ -148,17 +261,101 @@ pub async fn new_server_state(args: &cmd::Args) -> Result<ServerState, anyhow::E
         Arc<FutureProducer>,
     ) = db::init_db(&args.bootstrap_server, &args.database_url, &args.redis_url).await?;
 
+    if args.skip_migrations {
+        info!("Skipping database migrations");
+    } else {
+        crate::db::MIGRATOR
+            .run(&*db_pool)
+            .await
+            .map_err(|e| anyhow!("Failed to run database migrations: {}", e))?;
+        info!("Database schema is up to date");
+    }
+
+    if args.approval_policy == cmd::ApprovalPolicy::Token && args.enrollment_tokens.is_empty() {
+        warn!(
+            "Approval policy is token but no enrollment tokens are set, new workers wait for an operator"
+        );
+    }
+
+    let instance_id = args
+        .instance_id
+        .clone()
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    info!("Instance id {}", instance_id);
+    let presence = Arc::new(Presence::new(
+        redis_client.clone(),
+        instance_id.clone(),
+        args.presence_ttl_secs,
+    ));
+
+    let forwarder = if args.forward_port > 0 {
+        let addr = args
+            .forward_addr
+            .clone()
+            .ok_or_else(|| anyhow!("--forward-addr is required with --forward-port"))?;
+        let secret = args
+            .forward_secret
+            .clone()
+            .ok_or_else(|| anyhow!("--forward-secret is required with --forward-port"))?;
+        info!("Accepting forwarded requests at {}", addr);
+        Some(Arc::new(handle_forward::Forwarder::new(
+            redis_client.clone(),
+            presence.clone(),
+            secret,
+            addr,
+        )))
+    } else {
+        None
+    };
+
+    let geoip = match &args.geoip_db {
+        Some(path) => {
+            let geoip = GeoIp::load(path)?;
+            info!("Loaded {} GeoIP ranges from {}", geoip.len(), path);
+            Some(Arc::new(geoip))
+        }
+        None => None,
+    };
+
+    let uploads = Arc::new(
+        UploadStore::new(
+            &args.upload_dir,
+            args.max_upload_bytes,
+            std::time::Duration::from_secs(args.upload_ttl_secs),
+            args.public_url.clone(),
+            args.forward_secret.as_deref(),
+        )
+        .with_context(|| format!("Failed to create upload directory {}", args.upload_dir))?,
+    );
+
+    let worker_release = match &args.worker_release_file {
+        Some(path) => Some(handle_admin::read_worker_release(path)?),
+        None => None,
+    };
+
     let active_clients = Arc::new(Mutex::new(HashMap::new()));
     let pending_connections = Arc::new(Mutex::new(HashMap::new()));
     let user_db = Arc::new(Mutex::new(HashMap::<String, User>::new()));
     let token_db = Arc::new(Mutex::new(HashMap::new()));
     let total_connections = Arc::new(Mutex::new(0u64));
     let server_start_time = Utc::now();
-    let cert_chain = crate::util::load_certs(&args.proxy_cert_chain_path)?;
-    let priv_key = crate::util::load_private_key(&args.proxy_private_key_path)?;
+
+    // Usage records are buffered and written in batches of 100 or every 5 seconds
+    let usage_recorder = Arc::new(match args.usage_sink {
+        cmd::UsageSink::Database => {
+            UsageRecorder::start(db_pool.clone(), 100, std::time::Duration::from_secs(5))
+        }
+        cmd::UsageSink::Kafka => UsageRecorder::kafka(producer.clone()),
+    });
 
     // Initialize inference scheduler
-    let inference_scheduler = Arc::new(InferenceScheduler::new(active_clients.clone()));
+    let inference_scheduler = Arc::new(InferenceScheduler::new(
+        active_clients.clone(),
+        db_pool.clone(),
+        usage_recorder.clone(),
+        config::routing_policy(args),
+        config::timeouts(args),
+    ));
 
     let app_state = ServerState {
         active_clients: active_clients.clone(),
@@ -168,20 +365,45 @@ pub async fn new_server_state(args: &cmd::Args) -> Result<ServerState, anyhow::E
         server_start_time,
         total_connections: total_connections.clone(),
         config: ServerConfig {
+            listen_addr: config::listen_addr(args),
             control_port: args.control_port,
             proxy_port: args.proxy_port,
             public_port: args.public_port,
             api_port: args.api_port,
+            admin_port: args.admin_port,
+            stun_port: args.stun_port,
+            stun_host: args.stun_host.clone(),
+            instance_id,
+            approval_policy: args.approval_policy,
+            enrollment_tokens: args.enrollment_tokens.clone(),
+            trusted_proxies: Arc::new(args.trusted_proxies.clone()),
+            rollout_policy: RolloutPolicy {
+                min_samples: args.rollout_min_samples,
+                min_success_rate: args.rollout_min_success_rate,
+            },
         },
         buffer_pool: Arc::new(BufferPool::new(8 * 1024, 16)),
         db_pool: db_pool.clone(),
         redis_client: redis_client.clone(),
         producer: producer.clone(),
-        cert_chain: cert_chain.into(),
-        priv_key: Arc::new(priv_key),
+        tls_config,
         hot_models: Arc::new(HotModelClass::new(db_pool.clone())),
         client_model: Arc::new(ClientModelClass::new(db_pool.clone())),
+        rate_limiter: Arc::new(RateLimiter::new(redis_client.clone(), args.ip_rate_limit_rps)),
+        usage_recorder,
         inference_scheduler,
+        presence,
+        forwarder,
+        geoip,
+        uploads,
+        response_cache: Arc::new(ResponseCache::new(
+            redis_client.clone(),
+            db_pool.clone(),
+            args.response_cache_ttl_secs,
+        )),
+        request_limits: Arc::new(RequestLimits::from_args(args)),
+        proxied_requests: Arc::new(handle_shutdown::InFlight::default()),
+        worker_release: Arc::new(std::sync::RwLock::new(worker_release)),
     };
     // If monitor flag is set, just print monitoring data and exit
     if args.monitor {
@@ -0,0 +1,230 @@
+//! Cache of deterministic completions in Redis. Requests at temperature 0
+//! from keys that opted in are answered from the cache when an identical
+//! request of the same key was answered within the TTL.
+
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use redis::{AsyncCommands, Client as RedisClient};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use crate::db::client::get_token_cache_opt_in;
+
+/// `hit` or `miss` on responses of cacheable requests
+pub const CACHE_HEADER: &str = "x-cache";
+const KEY_PREFIX: &str = "response_cache:";
+const STATS_KEY: &str = "response_cache:stats";
+
+pub struct ResponseCache {
+    redis_client: Arc<RedisClient>,
+    db_pool: Arc<Pool<Postgres>>,
+    /// 0 disables the cache
+    ttl_secs: u64,
+}
+
+/// What the cache has for a request
+pub enum CacheLookup {
+    /// Not cacheable, or the cache is off for it
+    Skip,
+    /// The cached response body
+    Hit(Vec<u8>),
+    /// Store the response under this key
+    Miss(String),
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+}
+
+impl ResponseCache {
+    pub fn new(
+        redis_client: Arc<RedisClient>,
+        db_pool: Arc<Pool<Postgres>>,
+        ttl_secs: u64,
+    ) -> Self {
+        Self {
+            redis_client,
+            db_pool,
+            ttl_secs,
+        }
+    }
+
+    /// Look up `request` of `kind`, e.g. `chat`, sent with `token`. Only
+    /// temperature 0 requests of keys that opted in are cached, and
+    /// `Cache-Control: no-cache` skips the lookup but stores the result.
+    /// Redis failures skip the cache.
+    pub async fn lookup(
+        &self,
+        token: &str,
+        headers: &HeaderMap,
+        kind: &str,
+        request: &impl Serialize,
+        temperature: Option<f32>,
+    ) -> CacheLookup {
+        if self.ttl_secs == 0 || temperature != Some(0.0) {
+            return CacheLookup::Skip;
+        }
+        let Some(key) = cache_key(token, kind, request) else {
+            return CacheLookup::Skip;
+        };
+        match get_token_cache_opt_in(&self.db_pool, token).await {
+            Ok(true) => {}
+            Ok(false) => return CacheLookup::Skip,
+            Err(e) => {
+                warn!("Failed to read the cache setting of a key: {}", e);
+                return CacheLookup::Skip;
+            }
+        }
+        let no_cache = headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .any(|v| v.to_ascii_lowercase().contains("no-cache"));
+        if no_cache {
+            return CacheLookup::Miss(key);
+        }
+
+        let cached: redis::RedisResult<Option<Vec<u8>>> = async {
+            let mut conn = self.redis_client.get_async_connection().await?;
+            let cached: Option<Vec<u8>> = conn.get(&key).await?;
+            let field = if cached.is_some() { "hits" } else { "misses" };
+            conn.hincr::<_, _, _, ()>(STATS_KEY, field, 1).await?;
+            Ok(cached)
+        }
+        .await;
+        match cached {
+            Ok(Some(body)) => {
+                debug!("Response cache hit for {}", key);
+                CacheLookup::Hit(body)
+            }
+            Ok(None) => CacheLookup::Miss(key),
+            Err(e) => {
+                warn!("Response cache lookup failed: {}", e);
+                CacheLookup::Skip
+            }
+        }
+    }
+
+    /// Keep `body` under `key` for the TTL
+    pub async fn store(&self, key: &str, body: &[u8]) {
+        let result: redis::RedisResult<()> = async {
+            let mut conn = self.redis_client.get_async_connection().await?;
+            conn.set_ex(key, body, self.ttl_secs).await
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("Failed to store a response in the cache: {}", e);
+        }
+    }
+
+    /// Hits and misses since the counters were created
+    pub async fn stats(&self) -> redis::RedisResult<CacheStats> {
+        let mut conn = self.redis_client.get_async_connection().await?;
+        let (hits, misses): (Option<u64>, Option<u64>) = redis::cmd("HMGET")
+            .arg(STATS_KEY)
+            .arg("hits")
+            .arg("misses")
+            .query_async(&mut conn)
+            .await?;
+        let (hits, misses) = (hits.unwrap_or(0), misses.unwrap_or(0));
+        let total = hits + misses;
+        Ok(CacheStats {
+            hits,
+            misses,
+            hit_rate: if total > 0 {
+                hits as f64 / total as f64
+            } else {
+                0.0
+            },
+        })
+    }
+}
+
+/// Redis key of `request`: its fields without `stream` and unset ones, in
+/// a fixed order, so requests differing only in those share an entry.
+/// Entries are per API key.
+fn cache_key(token: &str, kind: &str, request: &impl Serialize) -> Option<String> {
+    let Value::Object(mut fields) = serde_json::to_value(request).ok()? else {
+        return None;
+    };
+    fields.remove("stream");
+    fields.retain(|_, value| !value.is_null());
+    let mut hasher = Sha256::new();
+    hasher.update(token.as_bytes());
+    hasher.update([0]);
+    hasher.update(kind.as_bytes());
+    hasher.update([0]);
+    hasher.update(serde_json::to_vec(&fields).ok()?);
+    Some(format!("{}{}", KEY_PREFIX, hex::encode(hasher.finalize())))
+}
+
+/// Response of a cache hit
+pub fn cached_response(body: Vec<u8>) -> Response {
+    let mut response = ([(header::CONTENT_TYPE, "application/json")], body).into_response();
+    response
+        .headers_mut()
+        .insert(CACHE_HEADER, HeaderValue::from_static("hit"));
+    response
+}
+
+/// Answer with `body`, and store it when the lookup missed
+pub async fn respond(
+    cache: &ResponseCache,
+    lookup: &CacheLookup,
+    body: &impl Serialize,
+) -> Response {
+    let Ok(bytes) = serde_json::to_vec(body) else {
+        return axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let mut response =
+        ([(header::CONTENT_TYPE, "application/json")], bytes.clone()).into_response();
+    if let CacheLookup::Miss(key) = lookup {
+        cache.store(key, &bytes).await;
+        response
+            .headers_mut()
+            .insert(CACHE_HEADER, HeaderValue::from_static("miss"));
+    }
+    response
+}
+
+#[test]
+fn test_cache_key() {
+    let request = |prompt: &str, stream: Option<bool>, top_k: Option<u32>| {
+        serde_json::json!({
+            "prompt": prompt,
+            "temperature": 0.0,
+            "stream": stream,
+            "top_k": top_k,
+        })
+    };
+    let key = cache_key("key", "completion", &request("hi", None, None)).unwrap();
+    assert!(key.starts_with(KEY_PREFIX));
+    // Streaming and unset fields do not matter
+    assert_eq!(
+        cache_key("key", "completion", &request("hi", Some(false), None)),
+        Some(key.clone())
+    );
+    assert_ne!(
+        cache_key("key", "completion", &request("hi", None, Some(40))),
+        Some(key.clone())
+    );
+    assert_ne!(
+        cache_key("key", "completion", &request("hello", None, None)),
+        Some(key.clone())
+    );
+    assert_ne!(
+        cache_key("other", "completion", &request("hi", None, None)),
+        Some(key.clone())
+    );
+    assert_ne!(
+        cache_key("key", "chat", &request("hi", None, None)),
+        Some(key)
+    );
+}
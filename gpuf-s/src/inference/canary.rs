@@ -0,0 +1,272 @@
+//! Known-answer prompts run on workers to catch ones returning garbage,
+//! empty or truncated output. Results feed each worker's quality score,
+//! which the scheduler uses to keep inference away from bad workers.
+
+use rand::Rng;
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, warn};
+
+use crate::db::canary::{self, CanaryResult, MAX_QUALITY_SCORE};
+use crate::handle::ActiveClients;
+use crate::inference::InferenceScheduler;
+use crate::util::protoc::ClientId;
+
+/// Weight of the latest canary in the quality score; four failures in a row
+/// take a perfect score below the scheduler's threshold
+const QUALITY_WEIGHT: f32 = 0.2;
+const CANARY_MAX_TOKENS: u32 = 64;
+/// Letters and digits of codes a worker is asked to repeat, without ones
+/// that are easily confused
+const CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// A prompt made up for one canary run, so workers cannot learn the answers,
+/// and the exact answer it has
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanaryPrompt {
+    pub prompt: String,
+    pub expected: String,
+}
+
+impl CanaryPrompt {
+    /// Arithmetic on random operands, or a random code to repeat
+    pub fn random(rng: &mut impl Rng) -> Self {
+        let a: u32 = rng.gen_range(10..1000);
+        let b: u32 = rng.gen_range(10..1000);
+        let (question, expected, kind) = match rng.gen_range(0..4) {
+            0 => (
+                format!("What is {} plus {}?", a, b),
+                (a + b).to_string(),
+                "number",
+            ),
+            1 => (
+                format!("What is {} minus {}?", a.max(b), a.min(b)),
+                (a.max(b) - a.min(b)).to_string(),
+                "number",
+            ),
+            // Two digits times 2 to 10, which small models still get right
+            2 => (
+                format!("What is {} times {}?", a % 90 + 10, b % 9 + 2),
+                ((a % 90 + 10) * (b % 9 + 2)).to_string(),
+                "number",
+            ),
+            _ => {
+                let code: String = (0..6)
+                    .map(|_| CODE_CHARS[rng.gen_range(0..CODE_CHARS.len())] as char)
+                    .collect();
+                (format!("Repeat this code exactly: {}.", code), code, "code")
+            }
+        };
+        Self {
+            prompt: format!("{} Answer with the {} only.", question, kind),
+            expected,
+        }
+    }
+}
+
+/// What a worker returned for a canary prompt
+#[derive(Debug)]
+pub enum CanaryReply {
+    Output(String),
+    /// The worker reported a failed inference
+    Error(String),
+    TimedOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CanaryVerdict {
+    Passed,
+    WrongAnswer,
+    Empty,
+    Failed,
+    TimedOut,
+}
+
+impl CanaryVerdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Passed => "passed",
+            Self::WrongAnswer => "wrong_answer",
+            Self::Empty => "empty",
+            Self::Failed => "failed",
+            Self::TimedOut => "timed_out",
+        }
+    }
+
+    /// Compare a reply with the expected answer. The whole output must be
+    /// the answer, ignoring case, surrounding whitespace, quotes and a
+    /// final period.
+    pub fn judge(reply: &CanaryReply, expected: &str) -> Self {
+        match reply {
+            CanaryReply::TimedOut => Self::TimedOut,
+            CanaryReply::Error(_) => Self::Failed,
+            CanaryReply::Output(text) if text.trim().is_empty() => Self::Empty,
+            CanaryReply::Output(text)
+                if text
+                    .trim()
+                    .trim_end_matches('.')
+                    .trim_matches(|c| c == '"' || c == '\'' || c == '`')
+                    .eq_ignore_ascii_case(expected) =>
+            {
+                Self::Passed
+            }
+            CanaryReply::Output(_) => Self::WrongAnswer,
+        }
+    }
+}
+
+/// Moving average of canary passes, 1.0 for a pass and 0.0 for anything else
+pub fn next_quality_score(current: f32, verdict: CanaryVerdict) -> f32 {
+    let sample = if verdict == CanaryVerdict::Passed {
+        MAX_QUALITY_SCORE
+    } else {
+        0.0
+    };
+    current * (1.0 - QUALITY_WEIGHT) + sample * QUALITY_WEIGHT
+}
+
+/// Every `interval`, run a new canary prompt on each worker that could
+/// take inference right now
+pub async fn run_canaries(
+    scheduler: Arc<InferenceScheduler>,
+    active_clients: ActiveClients,
+    db_pool: Arc<Pool<Postgres>>,
+    interval: Duration,
+    timeout: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+
+        let targets: Vec<ClientId> = active_clients
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, info)| info.authed && !info.maintenance && info.available())
+            .map(|(client_id, _)| *client_id)
+            .collect();
+
+        for client_id in targets {
+            let scheduler = scheduler.clone();
+            let active_clients = active_clients.clone();
+            let db_pool = db_pool.clone();
+            tokio::spawn(async move {
+                run_canary(&scheduler, &active_clients, &db_pool, client_id, timeout).await
+            });
+        }
+    }
+}
+
+async fn run_canary(
+    scheduler: &InferenceScheduler,
+    active_clients: &ActiveClients,
+    db_pool: &Pool<Postgres>,
+    client_id: ClientId,
+    timeout: Duration,
+) {
+    let CanaryPrompt { prompt, expected } = CanaryPrompt::random(&mut rand::thread_rng());
+
+    let started_at = Instant::now();
+    let reply = match scheduler
+        .execute_canary(&client_id, prompt.clone(), CANARY_MAX_TOKENS, timeout)
+        .await
+    {
+        Ok(reply) => reply,
+        Err(e) => {
+            // Busy or gone, not the worker's fault
+            debug!("Skipped canary for client {}: {}", client_id, e);
+            return;
+        }
+    };
+    let latency_ms = started_at.elapsed().as_millis() as i64;
+    let verdict = CanaryVerdict::judge(&reply, &expected);
+
+    let quality_score = {
+        let mut clients = active_clients.lock().await;
+        let Some(info) = clients.get_mut(&client_id) else {
+            return;
+        };
+        info.quality_score = next_quality_score(info.quality_score, verdict);
+        info.quality_score
+    };
+    if verdict == CanaryVerdict::Passed {
+        debug!(
+            "Client {} passed a canary, quality score {:.2}",
+            client_id, quality_score
+        );
+    } else {
+        warn!(
+            "Client {} failed a canary with {}: {:?}, quality score {:.2}",
+            client_id,
+            verdict.as_str(),
+            reply,
+            quality_score
+        );
+    }
+
+    let output = match &reply {
+        CanaryReply::Output(text) | CanaryReply::Error(text) => Some(text.as_str()),
+        CanaryReply::TimedOut => None,
+    };
+    let result = CanaryResult {
+        prompt: &prompt,
+        expected: &expected,
+        verdict: verdict.as_str(),
+        output,
+        latency_ms,
+    };
+    if let Err(e) = canary::record_canary_result(db_pool, &client_id, &result, quality_score).await
+    {
+        error!(
+            "Failed to record canary result of client {}: {}",
+            client_id, e
+        );
+    }
+}
+
+#[test]
+fn test_canary_verdict() {
+    let output = |text: &str| CanaryReply::Output(text.to_string());
+
+    assert_eq!(
+        CanaryVerdict::judge(&output(" 742.\n"), "742"),
+        CanaryVerdict::Passed
+    );
+    assert_eq!(
+        CanaryVerdict::judge(&output("\"k7hq2x\""), "K7HQ2X"),
+        CanaryVerdict::Passed
+    );
+    assert_eq!(
+        CanaryVerdict::judge(&output("It is 742, not 741"), "742"),
+        CanaryVerdict::WrongAnswer
+    );
+    assert_eq!(
+        CanaryVerdict::judge(&output("7420"), "742"),
+        CanaryVerdict::WrongAnswer
+    );
+    assert_eq!(
+        CanaryVerdict::judge(&output(" \n"), "742"),
+        CanaryVerdict::Empty
+    );
+    assert_eq!(
+        CanaryVerdict::judge(&CanaryReply::TimedOut, "742"),
+        CanaryVerdict::TimedOut
+    );
+
+    let mut rng = rand::thread_rng();
+    for _ in 0..100 {
+        let canary = CanaryPrompt::random(&mut rng);
+        assert!(canary.prompt.contains("only"));
+        assert!(!canary.expected.is_empty());
+    }
+
+    let mut score = MAX_QUALITY_SCORE;
+    for _ in 0..3 {
+        score = next_quality_score(score, CanaryVerdict::WrongAnswer);
+    }
+    assert!(score > 0.5);
+    score = next_quality_score(score, CanaryVerdict::Empty);
+    assert!(score < 0.5);
+    assert!(next_quality_score(score, CanaryVerdict::Passed) > score);
+}
@@ -1,24 +1,37 @@
 use anyhow::Result;
 use axum::{
-    http::{header, Request, StatusCode},
+    extract::{ConnectInfo, DefaultBodyLimit},
+    http::{header, HeaderName, HeaderValue, Request, StatusCode},
     middleware::{self, Next},
     response::{IntoResponse, Response},
     routing::{get, post},
     Router,
 };
+use futures_util::StreamExt;
 use rdkafka::producer::FutureProducer;
 use sqlx::{Pool, Postgres};
+use std::net::SocketAddr;
 use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 use tower_http::cors::CorsLayer;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
-use crate::db::client::get_user_client_by_token;
+use crate::db::client::{get_token_rate_limit, get_user_client_by_token};
+#[cfg(feature = "experimental")]
+use crate::db::usage::UsageRecorder;
 #[cfg(feature = "experimental")]
 use crate::handle::ActiveClients;
-use crate::inference::{handlers, InferenceScheduler};
-use crate::util::protoc::{ClientId, RequestIDAndClientIDMessage};
+use crate::inference::{
+    handlers, limits::LimitError, uploads, InferenceScheduler, RequestLimits, ResponseCache,
+    UploadStore,
+};
+use crate::util::geo::{KeyRegion, REGION_HEADER};
+use crate::util::net::{self, ListenAddr};
 use crate::util::policy::{AccessLevel, REQUEST_MESSAGE_TOPIC};
+use crate::util::protoc::{ClientId, RequestIDAndClientIDMessage};
+use crate::util::rate_limit::{RateLimitDecision, RateLimiter};
 use anyhow::anyhow;
+use common::trace::{self, new_request_id, TraceParent};
 use rdkafka::producer::FutureRecord;
 use std::time::Duration;
 
@@ -27,13 +40,29 @@ pub struct AuthContext {
     pub client_ids: Vec<ClientId>,
     pub access_level: AccessLevel,
     pub token: String,
+    pub key_region: KeyRegion,
 }
 
+impl AuthContext {
+    /// Region the request prefers its worker in, from the `gpuf-region`
+    /// header or the key
+    pub fn region(&self, headers: &axum::http::HeaderMap) -> Option<String> {
+        self.key_region
+            .preferred(headers.get(REGION_HEADER).and_then(|v| v.to_str().ok()))
+    }
+}
+
+pub const REQUEST_ID_HEADER: &str = "request-id";
+
 /// Inference Gateway - Handles external API requests and routes them to Android devices
 pub struct InferenceGateway {
     pub scheduler: Arc<InferenceScheduler>,
     pub db_pool: Arc<Pool<Postgres>>,
     pub producer: Arc<FutureProducer>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub uploads: Arc<UploadStore>,
+    pub response_cache: Arc<ResponseCache>,
+    pub request_limits: Arc<RequestLimits>,
 }
 
 impl InferenceGateway {
@@ -41,11 +70,19 @@ impl InferenceGateway {
         scheduler: Arc<InferenceScheduler>,
         db_pool: Arc<Pool<Postgres>>,
         producer: Arc<FutureProducer>,
+        rate_limiter: Arc<RateLimiter>,
+        uploads: Arc<UploadStore>,
+        response_cache: Arc<ResponseCache>,
+        request_limits: Arc<RequestLimits>,
     ) -> Self {
         Self {
             scheduler,
             db_pool,
             producer,
+            rate_limiter,
+            uploads,
+            response_cache,
+            request_limits,
         }
     }
     #[cfg(feature = "experimental")]
@@ -53,12 +90,27 @@ impl InferenceGateway {
         active_clients: ActiveClients,
         db_pool: Arc<Pool<Postgres>>,
         producer: Arc<FutureProducer>,
+        rate_limiter: Arc<RateLimiter>,
+        usage_recorder: Arc<UsageRecorder>,
+        uploads: Arc<UploadStore>,
+        response_cache: Arc<ResponseCache>,
+        request_limits: Arc<RequestLimits>,
     ) -> Self {
-        let scheduler = Arc::new(InferenceScheduler::new(active_clients));
+        let scheduler = Arc::new(InferenceScheduler::new(
+            active_clients,
+            db_pool.clone(),
+            usage_recorder,
+            crate::inference::RoutingPolicy::default(),
+            crate::inference::Timeouts::default(),
+        ));
         Self {
             scheduler,
             db_pool,
             producer,
+            rate_limiter,
+            uploads,
+            response_cache,
+            request_limits,
         }
     }
 
@@ -84,12 +136,13 @@ impl InferenceGateway {
         };
         debug!("Received token: {}", token);
         match get_user_client_by_token(&db_pool, token.as_str()).await {
-            Ok((client_ids, access_level)) => {
+            Ok((client_ids, access_level, key_region)) => {
                 let mut req = req;
                 req.extensions_mut().insert(AuthContext {
                     client_ids,
                     access_level,
                     token,
+                    key_region,
                 });
                 next.run(req).await
             }
@@ -97,6 +150,140 @@ impl InferenceGateway {
         }
     }
 
+    /// Enforce per-key and per-IP limits. Runs after `auth_middleware`.
+    async fn rate_limit_middleware(
+        axum::extract::State(gateway): axum::extract::State<Arc<Self>>,
+        req: Request<axum::body::Body>,
+        next: Next,
+    ) -> Response {
+        let Some(auth) = req.extensions().get::<AuthContext>().cloned() else {
+            return next.run(req).await;
+        };
+        let client_ip = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| net::client_ip(*addr));
+
+        let policy = match get_token_rate_limit(&gateway.db_pool, &auth.token).await {
+            Ok(policy) => policy,
+            Err(e) => {
+                warn!("Failed to load rate limit policy: {}", e);
+                Default::default()
+            }
+        };
+
+        let decision = gateway
+            .rate_limiter
+            .check(&auth.token, client_ip, &policy)
+            .await;
+        if !decision.allowed() {
+            return rate_limited_response(&decision);
+        }
+
+        let slot = match gateway
+            .rate_limiter
+            .acquire_slot(&auth.token, &policy)
+            .await
+        {
+            Ok(slot) => slot,
+            Err(rejected) => return rate_limited_response(&rejected),
+        };
+
+        let mut response = next.run(req).await;
+        apply_rate_limit_headers(&mut response, &decision);
+
+        // Keep the concurrency slot until the (possibly streamed) body is finished
+        match slot {
+            Some(slot) => hold_until_body_ends(response, slot),
+            None => response,
+        }
+    }
+
+    /// Enforce the request limits of completion routes. Runs after
+    /// `auth_middleware`, before the handler parses the body.
+    async fn request_limits_middleware(
+        axum::extract::State(gateway): axum::extract::State<Arc<Self>>,
+        req: Request<axum::body::Body>,
+        next: Next,
+    ) -> Response {
+        let limits = gateway.request_limits.clone();
+        let (parts, body) = req.into_parts();
+        let bytes = match axum::body::to_bytes(body, limits.body_limit()).await {
+            Ok(bytes) => bytes,
+            Err(_) => return LimitError::BodyTooLarge(limits.max_request_bytes).into_response(),
+        };
+
+        // Bodies that are no JSON get the handler's error
+        let stream = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+            Ok(value) => {
+                if let Err(e) = limits.check(&value) {
+                    return e.into_response();
+                }
+                value.get("stream").and_then(|v| v.as_bool()) == Some(true)
+            }
+            Err(_) => false,
+        };
+
+        let auth = parts.extensions.get::<AuthContext>().cloned();
+        let slot = match auth {
+            Some(auth) if stream => match gateway
+                .rate_limiter
+                .acquire_stream_slot(&auth.token, limits.max_streams_per_key)
+                .await
+            {
+                Ok(slot) => slot,
+                Err(rejected) => return rate_limited_response(&rejected),
+            },
+            _ => None,
+        };
+
+        let req = Request::from_parts(parts, axum::body::Body::from(bytes));
+        let response = next.run(req).await;
+        match slot {
+            Some(slot) => hold_until_body_ends(response, slot),
+            None => response,
+        }
+    }
+
+    /// Give every request a `request-id`, echo it in the response and log
+    /// everything handling the request under a span carrying it
+    async fn request_id_middleware(mut req: Request<axum::body::Body>, next: Next) -> Response {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(new_request_id);
+        let Ok(value) = HeaderValue::from_str(&request_id) else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+        req.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+
+        // Same for the trace context, so the scheduler always has one to forward
+        let parent = TraceParent::from_header_or_root(
+            req.headers()
+                .get("traceparent")
+                .and_then(|v| v.to_str().ok()),
+        );
+        if let Ok(traceparent) = HeaderValue::from_str(&parent.to_string()) {
+            req.headers_mut().insert("traceparent", traceparent);
+        }
+
+        let span = info_span!(
+            "request",
+            request_id = %request_id,
+            trace_id = %parent.trace_id_hex(),
+            model = tracing::field::Empty,
+            client_id = tracing::field::Empty,
+        );
+        trace::set_span_parent(&span, &parent);
+        let mut response = next.run(req).instrument(span).await;
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+        response
+    }
+
     /// Send request metrics to Kafka if access_level requires it
     pub async fn send_request_metrics(
         &self,
@@ -139,24 +326,47 @@ impl InferenceGateway {
         Ok(())
     }
 
-    /// Run the inference gateway server
-    pub async fn run(self: Arc<Self>, port: u16) -> Result<()> {
+    /// Run the inference gateway server until `shutdown` is cancelled, then stop accepting and return
+    /// once the requests in flight are answered
+    pub async fn run(
+        self: Arc<Self>,
+        listen_addr: ListenAddr,
+        port: u16,
+        shutdown: CancellationToken,
+    ) -> Result<()> {
         let app = self.create_router().await;
-        let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+        let listener = listen_addr.bind(port)?;
 
         info!("Inference Gateway listening on port {}", port);
-        axum::serve(listener, app).await.map_err(Into::into)
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+        .map_err(Into::into)
     }
 
     /// Create API router for inference endpoints
     pub async fn create_router(self: Arc<Self>) -> Router {
         let state = Arc::clone(&self);
+        let limits = || {
+            tower::ServiceBuilder::new()
+                .layer(DefaultBodyLimit::max(self.request_limits.body_limit()))
+                .layer(middleware::from_fn_with_state(
+                    self.clone(),
+                    Self::request_limits_middleware,
+                ))
+        };
         Router::new()
             // OpenAI Compatible Inference APIs
-            .route("/v1/completions", post(handlers::handle_completion))
+            .route(
+                "/v1/completions",
+                post(handlers::handle_completion).layer(limits()),
+            )
             .route(
                 "/v1/chat/completions",
-                post(handlers::handle_chat_completion),
+                post(handlers::handle_chat_completion).layer(limits()),
             )
             .route("/v1/models", get(handlers::list_models))
             // Device Management APIs
@@ -165,11 +375,61 @@ impl InferenceGateway {
                 "/api/v1/devices/:id/status",
                 get(handlers::get_device_status),
             )
+            // Resumable uploads of job inputs
+            .route("/v1/uploads", post(uploads::create_upload))
+            .route(
+                "/v1/uploads/:id",
+                get(uploads::get_upload)
+                    .patch(uploads::append_upload)
+                    .delete(uploads::delete_upload)
+                    .layer(DefaultBodyLimit::max(uploads::MAX_CHUNK_BYTES)),
+            )
+            .route_layer(middleware::from_fn_with_state(
+                self.clone(),
+                Self::rate_limit_middleware,
+            ))
             .route_layer(middleware::from_fn_with_state(
                 self.db_pool.clone(),
                 Self::auth_middleware,
             ))
+            // Workers fetch uploads with a signed URL rather than an API key
+            .route("/v1/uploads/:id/content", get(uploads::upload_content))
+            .layer(middleware::from_fn(Self::request_id_middleware))
             .layer(CorsLayer::permissive())
             .with_state(state)
     }
 }
+
+/// Drop `guard` once the (possibly streamed) body of `response` is finished
+fn hold_until_body_ends<T: Send + 'static>(response: Response, guard: T) -> Response {
+    let (parts, body) = response.into_parts();
+    let body = axum::body::Body::from_stream(body.into_data_stream().map(move |chunk| {
+        let _guard = &guard;
+        chunk
+    }));
+    Response::from_parts(parts, body)
+}
+
+fn apply_rate_limit_headers(response: &mut Response, decision: &RateLimitDecision) {
+    for (name, value) in decision.headers() {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+}
+
+fn rate_limited_response(decision: &RateLimitDecision) -> Response {
+    let error_response = serde_json::json!({
+        "error": {
+            "message": decision.message(),
+            "type": "rate_limit_error",
+            "code": 429
+        }
+    });
+    let mut response = (StatusCode::TOO_MANY_REQUESTS, axum::Json(error_response)).into_response();
+    apply_rate_limit_headers(&mut response, decision);
+    response
+}
@@ -14,14 +14,17 @@ use tokio_stream::wrappers::ReceiverStream;
 use tracing::{debug, error, info};
 
 use crate::inference::{
+    cache::{self, CacheLookup},
     gateway::{AuthContext, InferenceGateway},
     scheduler::{
         ChatCompletionRequest, ChatCompletionResponse, CompletionRequest, DeviceInfo, ModelInfo,
-        StreamEvent,
+        RequestOrigin, StreamEvent,
     },
 };
 use crate::util::protoc::ClientId;
-use common::OutputPhase;
+use common::{
+    OutputPhase, CONTEXT_LENGTH_EXCEEDED, DEADLINE_EXCEEDED, LIMIT_EXCEEDED, REQUEST_TOO_LARGE,
+};
 
 #[cfg(feature = "experimental")]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -173,6 +176,40 @@ impl Drop for StreamCancelGuard {
     }
 }
 
+/// Status and body of an error a worker reported for a task. Requests that
+/// do not fit the worker's context or limits are the caller's to fix; tasks
+/// that ran out of time get 504.
+fn worker_error(message: &str) -> (StatusCode, Value) {
+    let invalid_request = [
+        (CONTEXT_LENGTH_EXCEEDED, StatusCode::BAD_REQUEST),
+        (REQUEST_TOO_LARGE, StatusCode::PAYLOAD_TOO_LARGE),
+        (LIMIT_EXCEEDED, StatusCode::UNPROCESSABLE_ENTITY),
+    ];
+    if let Some((code, status)) = invalid_request
+        .into_iter()
+        .find(|(code, _)| message.contains(code))
+    {
+        let body = json!({
+            "error": {
+                "message": message,
+                "type": "invalid_request_error",
+                "code": code
+            }
+        });
+        return (status, body);
+    }
+    if message.contains(DEADLINE_EXCEEDED) {
+        let body = json!({
+            "error": {"message": message, "type": "api_error", "code": DEADLINE_EXCEEDED}
+        });
+        return (StatusCode::GATEWAY_TIMEOUT, body);
+    }
+    let body = json!({
+        "error": {"message": message, "type": "api_error", "code": 500}
+    });
+    (StatusCode::INTERNAL_SERVER_ERROR, body)
+}
+
 // OpenAI Compatible API Handlers
 
 /// Handle text completion requests
@@ -180,7 +217,7 @@ pub async fn handle_completion(
     State(gateway): State<Arc<InferenceGateway>>,
     Extension(auth): Extension<AuthContext>,
     headers: HeaderMap,
-    Json(request): Json<CompletionRequest>,
+    Json(mut request): Json<CompletionRequest>,
 ) -> Response {
     info!(
         "Received completion request: {} chars",
@@ -194,6 +231,15 @@ pub async fn handle_completion(
         .map(|s| s.to_string());
 
     debug!("Request-ID: {:?}", request_id);
+    let origin = RequestOrigin {
+        api_key: auth.token.clone(),
+        request_id: request_id.clone(),
+        traceparent: headers
+            .get("traceparent")
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string()),
+        region: auth.region(&headers),
+    };
 
     let target_client_id = match headers
         .get("x-target-client-id")
@@ -242,7 +288,10 @@ pub async fn handle_completion(
     }
 
     if request.stream.unwrap_or(false) {
-        let max_tokens_effective: u32 = request.max_tokens.unwrap_or(4090);
+        let max_tokens_effective: u32 = request
+            .max_tokens
+            .unwrap_or(gateway.request_limits.default_max_tokens(4090));
+        request.max_tokens = Some(max_tokens_effective);
         let model_name = request.model.clone().unwrap_or_else(|| "gpuf".to_string());
         let created = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
@@ -256,7 +305,7 @@ pub async fn handle_completion(
 
         let stream_res = gateway
             .scheduler
-            .execute_inference_stream(request, Some(allowed_ids))
+            .execute_inference_stream(request, Some(allowed_ids), origin)
             .await;
 
         match stream_res {
@@ -284,6 +333,8 @@ pub async fn handle_completion(
                 });
                 let stop_state: Arc<Mutex<StopMarkerState>> =
                     Arc::new(Mutex::new(StopMarkerState::new(&[])));
+                let rate_limiter = gateway.rate_limiter.clone();
+                let api_key = auth.token.clone();
                 let s = ReceiverStream::new(rx)
                     .then(move |ev| {
                         let guard = guard.clone();
@@ -291,6 +342,8 @@ pub async fn handle_completion(
                         let task_id = task_id.clone();
                         let model_name = model_name.clone();
                         let finished = finished.clone();
+                        let rate_limiter = rate_limiter.clone();
+                        let api_key = api_key.clone();
                         async move {
                             let _guard = guard;
                             let data = match ev {
@@ -318,6 +371,9 @@ pub async fn handle_completion(
                                     payload.to_string()
                                 }
                                 StreamEvent::Finish(usage) => {
+                                    if let Some(u) = usage.as_ref() {
+                                        rate_limiter.record_tokens(&api_key, u.total_tokens).await;
+                                    }
                                     let tail = {
                                         let mut st = stop_state.lock().await;
                                         if st.stopped {
@@ -345,12 +401,7 @@ pub async fn handle_completion(
                                     });
                                     payload.to_string()
                                 }
-                                StreamEvent::Error(msg) => {
-                                    let payload = json!({
-                                        "error": {"message": msg, "type": "api_error", "code": 500}
-                                    });
-                                    payload.to_string()
-                                }
+                                StreamEvent::Error(msg) => worker_error(&msg).1.to_string(),
                                 StreamEvent::Done => {
                                     finished.store(true, Ordering::SeqCst);
                                     "[DONE]".to_string()
@@ -367,15 +418,30 @@ pub async fn handle_completion(
             }
             Err(e) => {
                 error!("Completion request failed: {}", e);
-                let error_response = json!({
-                    "error": {"message": e.to_string(), "type": "api_error", "code": 500}
-                });
-                return (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response();
+                let (status, error_response) = worker_error(&e.to_string());
+                return (status, Json(error_response)).into_response();
             }
         }
     }
 
-    let max_tokens_effective: u32 = request.max_tokens.unwrap_or(1024);
+    let max_tokens_effective: u32 = request
+        .max_tokens
+        .unwrap_or(gateway.request_limits.default_max_tokens(1024));
+
+    let cache_lookup = gateway
+        .response_cache
+        .lookup(
+            &auth.token,
+            &headers,
+            "completion",
+            &request,
+            request.temperature,
+        )
+        .await;
+    if let CacheLookup::Hit(body) = cache_lookup {
+        return cache::cached_response(body);
+    }
+    request.max_tokens = Some(max_tokens_effective);
 
     let allowed_ids = target_client_id
         .as_ref()
@@ -384,7 +450,7 @@ pub async fn handle_completion(
 
     match gateway
         .scheduler
-        .execute_inference(request, Some(allowed_ids))
+        .execute_inference(request, Some(allowed_ids), origin)
         .await
     {
         Ok(response) => {
@@ -401,6 +467,11 @@ pub async fn handle_completion(
                 }
             }
 
+            gateway
+                .rate_limiter
+                .record_tokens(&auth.token, response.usage.total_tokens)
+                .await;
+
             let mut response = response;
             let finish_reason = if response.usage.completion_tokens >= max_tokens_effective {
                 "length"
@@ -413,10 +484,14 @@ pub async fn handle_completion(
             }
 
             info!("Completion request completed successfully");
-            Json(response).into_response()
+            cache::respond(&gateway.response_cache, &cache_lookup, &response).await
         }
         Err(e) => {
             error!("Completion request failed: {}", e);
+            let (status, error_response) = worker_error(&e.to_string());
+            if status != StatusCode::INTERNAL_SERVER_ERROR {
+                return (status, Json(error_response)).into_response();
+            }
             // Return appropriate HTTP status code with JSON error message
             let (status, error_message) = if e
                 .to_string()
@@ -465,6 +540,15 @@ pub async fn handle_chat_completion(
         .map(|s| s.to_string());
 
     debug!("Request-ID: {:?}", request_id);
+    let origin = RequestOrigin {
+        api_key: auth.token.clone(),
+        request_id: request_id.clone(),
+        traceparent: headers
+            .get("traceparent")
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string()),
+        region: auth.region(&headers),
+    };
 
     let target_client_id = match headers
         .get("x-target-client-id")
@@ -513,7 +597,9 @@ pub async fn handle_chat_completion(
     }
 
     if request.stream.unwrap_or(false) {
-        let max_tokens_effective: u32 = request.max_tokens.unwrap_or(4090);
+        let max_tokens_effective: u32 = request
+            .max_tokens
+            .unwrap_or(gateway.request_limits.default_max_tokens(4090));
         let model_name = request.model.clone().unwrap_or_else(|| "gpuf".to_string());
         let created = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
@@ -530,14 +616,16 @@ pub async fn handle_chat_completion(
             .execute_chat_inference_stream(
                 model_name.clone(),
                 request.messages.clone(),
-                request.max_tokens.unwrap_or(4090),
+                max_tokens_effective,
                 request.temperature.unwrap_or(0.7),
                 request.top_k.unwrap_or(40),
                 request.top_p.unwrap_or(0.9),
                 request.repeat_penalty.unwrap_or(1.1),
                 request.repeat_last_n.unwrap_or(64),
                 request.min_keep.unwrap_or(1),
+                request.context_overflow.unwrap_or_default(),
                 Some(allowed_ids),
+                origin,
             )
             .await;
 
@@ -566,6 +654,8 @@ pub async fn handle_chat_completion(
                 });
                 let stop_state: Arc<Mutex<StopMarkerState>> =
                     Arc::new(Mutex::new(StopMarkerState::new(&[])));
+                let rate_limiter = gateway.rate_limiter.clone();
+                let api_key = auth.token.clone();
                 let s = ReceiverStream::new(rx)
                     .then(move |ev| {
                         let guard = guard.clone();
@@ -573,6 +663,8 @@ pub async fn handle_chat_completion(
                         let task_id = task_id.clone();
                         let model_name = model_name.clone();
                         let finished = finished.clone();
+                        let rate_limiter = rate_limiter.clone();
+                        let api_key = api_key.clone();
                         async move {
                             let _guard = guard;
                             let data = match ev {
@@ -607,6 +699,9 @@ pub async fn handle_chat_completion(
                                     payload.to_string()
                                 }
                                 StreamEvent::Finish(usage) => {
+                                    if let Some(u) = usage.as_ref() {
+                                        rate_limiter.record_tokens(&api_key, u.total_tokens).await;
+                                    }
                                     let tail = {
                                         let mut st = stop_state.lock().await;
                                         if st.stopped {
@@ -640,12 +735,7 @@ pub async fn handle_chat_completion(
                                     });
                                     payload.to_string()
                                 }
-                                StreamEvent::Error(msg) => {
-                                    let payload = json!({
-                                        "error": {"message": msg, "type": "api_error", "code": 500}
-                                    });
-                                    payload.to_string()
-                                }
+                                StreamEvent::Error(msg) => worker_error(&msg).1.to_string(),
                                 StreamEvent::Done => {
                                     finished.store(true, Ordering::SeqCst);
                                     "[DONE]".to_string()
@@ -662,10 +752,8 @@ pub async fn handle_chat_completion(
             }
             Err(e) => {
                 error!("Chat completion request failed: {}", e);
-                let error_response = json!({
-                    "error": {"message": e.to_string(), "type": "api_error", "code": 500}
-                });
-                return (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response();
+                let (status, error_response) = worker_error(&e.to_string());
+                return (status, Json(error_response)).into_response();
             }
         }
     }
@@ -676,6 +764,14 @@ pub async fn handle_chat_completion(
         .unwrap()
         .as_secs();
 
+    let cache_lookup = gateway
+        .response_cache
+        .lookup(&auth.token, &headers, "chat", &request, request.temperature)
+        .await;
+    if let CacheLookup::Hit(body) = cache_lookup {
+        return cache::cached_response(body);
+    }
+
     let allowed_ids = target_client_id
         .as_ref()
         .map(std::slice::from_ref)
@@ -686,14 +782,18 @@ pub async fn handle_chat_completion(
         .execute_chat_inference_stream(
             model_name.clone(),
             request.messages.clone(),
-            request.max_tokens.unwrap_or(4090),
+            request
+                .max_tokens
+                .unwrap_or(gateway.request_limits.default_max_tokens(4090)),
             request.temperature.unwrap_or(0.7),
             request.top_k.unwrap_or(40),
             request.top_p.unwrap_or(0.9),
             request.repeat_penalty.unwrap_or(1.1),
             request.repeat_last_n.unwrap_or(64),
             request.min_keep.unwrap_or(1),
+            request.context_overflow.unwrap_or_default(),
             Some(allowed_ids),
+            origin,
         )
         .await;
 
@@ -725,11 +825,8 @@ pub async fn handle_chat_completion(
                         usage_final = usage;
                     }
                     StreamEvent::Error(msg) => {
-                        let error_response = json!({
-                            "error": {"message": msg, "type": "api_error", "code": 500}
-                        });
-                        return (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-                            .into_response();
+                        let (status, error_response) = worker_error(&msg);
+                        return (status, Json(error_response)).into_response();
                     }
                     StreamEvent::Done => {
                         break;
@@ -743,8 +840,15 @@ pub async fn handle_chat_completion(
                 total_tokens: 0,
                 analysis_tokens: None,
                 final_tokens: None,
+                context_overflow: None,
             });
-            let max_tokens_effective: u32 = request.max_tokens.unwrap_or(1024);
+            gateway
+                .rate_limiter
+                .record_tokens(&auth.token, usage.total_tokens)
+                .await;
+            let max_tokens_effective: u32 = request
+                .max_tokens
+                .unwrap_or(gateway.request_limits.default_max_tokens(1024));
             let finish_reason = if usage.completion_tokens >= max_tokens_effective {
                 "length"
             } else {
@@ -767,14 +871,12 @@ pub async fn handle_chat_completion(
                 usage,
             };
 
-            Json(chat_response).into_response()
+            cache::respond(&gateway.response_cache, &cache_lookup, &chat_response).await
         }
         Err(e) => {
             error!("Chat completion request failed: {}", e);
-            let error_response = json!({
-                "error": {"message": e.to_string(), "type": "api_error", "code": 500}
-            });
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response)).into_response()
+            let (status, error_response) = worker_error(&e.to_string());
+            (status, Json(error_response)).into_response()
         }
     }
 }
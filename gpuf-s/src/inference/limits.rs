@@ -0,0 +1,214 @@
+//! Hard limits on completion requests, checked by the gateway before a
+//! request is routed, so one oversized prompt cannot tie up a worker.
+//! Workers check their own limits too; their errors carry the same codes.
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use common::{LIMIT_EXCEEDED, REQUEST_TOO_LARGE};
+use serde_json::{json, Value};
+use std::fmt;
+
+use crate::util::cmd::Args;
+
+/// A limit of 0 is off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Largest request body in bytes
+    pub max_request_bytes: usize,
+    /// Longest prompt, or sum of chat message contents, in bytes
+    pub max_prompt_bytes: usize,
+    /// Highest `max_tokens` a request may ask for, also capping the default
+    pub max_tokens: u32,
+    /// Streamed requests one API key may have open at once
+    pub max_streams_per_key: u32,
+}
+
+#[derive(Debug)]
+pub enum LimitError {
+    BodyTooLarge(usize),
+    PromptTooLarge { bytes: usize, max: usize },
+    MaxTokens { requested: u64, max: u32 },
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::BodyTooLarge(max) => {
+                write!(f, "Request bodies are limited to {} bytes", max)
+            }
+            LimitError::PromptTooLarge { bytes, max } => {
+                write!(
+                    f,
+                    "Prompt of {} bytes exceeds the limit of {} bytes",
+                    bytes, max
+                )
+            }
+            LimitError::MaxTokens { requested, max } => {
+                write!(f, "max_tokens {} exceeds the limit of {}", requested, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+impl IntoResponse for LimitError {
+    fn into_response(self) -> Response {
+        let (status, code) = match &self {
+            LimitError::BodyTooLarge(_) | LimitError::PromptTooLarge { .. } => {
+                (StatusCode::PAYLOAD_TOO_LARGE, REQUEST_TOO_LARGE)
+            }
+            LimitError::MaxTokens { .. } => (StatusCode::UNPROCESSABLE_ENTITY, LIMIT_EXCEEDED),
+        };
+        let body = json!({
+            "error": {
+                "message": self.to_string(),
+                "type": "invalid_request_error",
+                "code": code
+            }
+        });
+        (status, Json(body)).into_response()
+    }
+}
+
+impl RequestLimits {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            max_request_bytes: args.max_request_bytes,
+            max_prompt_bytes: args.max_prompt_bytes,
+            max_tokens: args.max_tokens_limit,
+            max_streams_per_key: args.max_streams_per_key,
+        }
+    }
+
+    /// Check the prompt and `max_tokens` of a completion or chat completion
+    /// request body. Fields of the wrong type are left to the handler.
+    pub fn check(&self, body: &Value) -> Result<(), LimitError> {
+        if self.max_prompt_bytes > 0 {
+            let bytes = prompt_bytes(body);
+            if bytes > self.max_prompt_bytes {
+                return Err(LimitError::PromptTooLarge {
+                    bytes,
+                    max: self.max_prompt_bytes,
+                });
+            }
+        }
+        if self.max_tokens > 0 {
+            if let Some(requested) = body.get("max_tokens").and_then(Value::as_u64) {
+                if requested > self.max_tokens as u64 {
+                    return Err(LimitError::MaxTokens {
+                        requested,
+                        max: self.max_tokens,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Largest body the gateway reads
+    pub fn body_limit(&self) -> usize {
+        match self.max_request_bytes {
+            0 => usize::MAX,
+            max => max,
+        }
+    }
+
+    /// The `max_tokens` of a request that left it to the default
+    pub fn default_max_tokens(&self, default: u32) -> u32 {
+        match self.max_tokens {
+            0 => default,
+            max => default.min(max),
+        }
+    }
+}
+
+fn prompt_bytes(body: &Value) -> usize {
+    if let Some(prompt) = body.get("prompt") {
+        return text_bytes(prompt);
+    }
+    body.get("messages")
+        .and_then(Value::as_array)
+        .map(|messages| {
+            messages
+                .iter()
+                .filter_map(|m| m.get("content"))
+                .map(text_bytes)
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// Bytes of text in a prompt or message content: a string, or an array of
+/// strings and `{"type": "text", "text": ...}` parts
+fn text_bytes(value: &Value) -> usize {
+    match value {
+        Value::String(text) => text.len(),
+        Value::Array(parts) => parts
+            .iter()
+            .map(|part| match part {
+                Value::String(text) => text.len(),
+                part => part.get("text").and_then(Value::as_str).map_or(0, str::len),
+            })
+            .sum(),
+        _ => 0,
+    }
+}
+
+#[test]
+fn test_request_limits() {
+    let limits = RequestLimits {
+        max_request_bytes: 1024,
+        max_prompt_bytes: 8,
+        max_tokens: 16,
+        max_streams_per_key: 0,
+    };
+    assert!(limits
+        .check(&json!({"prompt": "12345678", "max_tokens": 16}))
+        .is_ok());
+    assert!(matches!(
+        limits.check(&json!({"prompt": "123456789"})),
+        Err(LimitError::PromptTooLarge { bytes: 9, max: 8 })
+    ));
+    let chat = json!({"messages": [
+        {"role": "system", "content": "12345"},
+        {"role": "user", "content": "6789"}
+    ]});
+    assert!(matches!(
+        limits.check(&chat),
+        Err(LimitError::PromptTooLarge { bytes: 9, .. })
+    ));
+    let parts = json!({"messages": [
+        {"role": "user", "content": [
+            {"type": "text", "text": "12345"},
+            {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}},
+            {"type": "text", "text": "6789"}
+        ]}
+    ]});
+    assert!(matches!(
+        limits.check(&parts),
+        Err(LimitError::PromptTooLarge { bytes: 9, .. })
+    ));
+    assert!(matches!(
+        limits.check(&json!({"prompt": ["12345", "6789"]})),
+        Err(LimitError::PromptTooLarge { bytes: 9, .. })
+    ));
+    let err = limits
+        .check(&json!({"prompt": "hi", "max_tokens": 17}))
+        .unwrap_err();
+    assert_eq!(
+        err.into_response().status(),
+        StatusCode::UNPROCESSABLE_ENTITY
+    );
+
+    assert_eq!(limits.default_max_tokens(4090), 16);
+    assert_eq!(limits.body_limit(), 1024);
+    let unlimited = RequestLimits {
+        max_request_bytes: 0,
+        max_tokens: 0,
+        ..limits
+    };
+    assert_eq!(unlimited.default_max_tokens(4090), 4090);
+    assert_eq!(unlimited.body_limit(), usize::MAX);
+}
@@ -1,7 +1,16 @@
+pub mod cache;
+pub mod canary;
 pub mod gateway;
 pub mod handlers;
+pub mod limits;
 pub mod scheduler;
+pub mod timeouts;
+pub mod uploads;
 
 // Re-export main components
+pub use cache::ResponseCache;
 pub use gateway::InferenceGateway;
-pub use scheduler::InferenceScheduler;
+pub use limits::RequestLimits;
+pub use scheduler::{InferenceScheduler, RoutingPolicy};
+pub use timeouts::Timeouts;
+pub use uploads::UploadStore;
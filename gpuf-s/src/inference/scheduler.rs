@@ -1,22 +1,44 @@
 use anyhow::{anyhow, Result};
+use lru::LruCache;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc;
 use tokio::sync::{oneshot, Mutex};
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, warn, Span};
 use uuid::Uuid;
 
+use crate::db::model_perf::{insert_model_perf_sample, ModelPerfSample};
+use crate::db::usage::{UsageRecord, UsageRecorder, UsageStatus};
 use crate::handle::ActiveClients;
+use crate::inference::canary::CanaryReply;
+use crate::inference::timeouts::{millis_left, TimeoutError, TimeoutStage, Timeouts};
 use crate::util::protoc::ClientId;
-use common::{Command, CommandV1, OutputPhase};
+use common::trace::{self, TraceParent};
+use common::{
+    BenchmarkResult, Command, CommandV1, ContextOverflow, NetworkQuality, OutputPhase, PromptPrefix,
+};
+
+/// Shortest run of leading system messages worth a KV state on the worker
+const MIN_PREFIX_BYTES: usize = 256;
+/// Prompt prefixes whose last worker is remembered
+const PREFIX_WORKERS_CAPACITY: usize = 4096;
+/// How much busier (CPU + memory usage) than the best pick the worker holding
+/// a prefix may be and still get the request
+const MAX_AFFINITY_EXTRA_LOAD: u16 = 50;
+/// How often a queued request looks for an available worker again
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(250);
 
 // Type aliases for easier function signatures
 // Note: Can't create type alias for enum variants in Rust
 
 // OpenAI Compatible Request/Response Types
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct CompletionRequest {
     pub prompt: String,
     pub max_tokens: Option<u32>,
@@ -26,13 +48,14 @@ pub struct CompletionRequest {
     pub repeat_penalty: Option<f32>,
     pub repeat_last_n: Option<i32>,
     pub min_keep: Option<u32>,
-    #[allow(dead_code)] // Part of OpenAI API spec, will be used later
     pub model: Option<String>,
     #[allow(dead_code)] // Streaming support to be implemented later
     pub stream: Option<bool>,
+    /// What the worker does when the prompt and max_tokens exceed its context
+    pub context_overflow: Option<ContextOverflow>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ChatCompletionRequest {
     pub model: Option<String>,
     pub messages: Vec<ChatMessage>,
@@ -44,6 +67,7 @@ pub struct ChatCompletionRequest {
     pub repeat_last_n: Option<i32>,
     pub min_keep: Option<u32>,
     pub stream: Option<bool>,
+    pub context_overflow: Option<ContextOverflow>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -94,6 +118,16 @@ pub struct CompletionUsage {
     pub total_tokens: u32,
     pub analysis_tokens: Option<u32>,
     pub final_tokens: Option<u32>,
+    /// Set when the worker dropped prompt tokens to fit its context
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_overflow: Option<ContextOverflowReport>,
+}
+
+/// Overflow strategy a worker applied to a request
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct ContextOverflowReport {
+    pub strategy: ContextOverflow,
+    pub dropped_tokens: u32,
 }
 
 #[derive(Debug, Serialize)]
@@ -115,31 +149,275 @@ pub enum StreamEvent {
     Error(String),
 }
 
+/// Caller of an inference request, used for usage accounting
+#[derive(Debug, Clone, Default)]
+pub struct RequestOrigin {
+    pub api_key: String,
+    pub request_id: Option<String>,
+    /// `traceparent` header of the public request
+    pub traceparent: Option<String>,
+    /// Region the caller prefers its worker in
+    pub region: Option<String>,
+}
+
+// Per-task data needed to record usage and a model performance sample once the task ends
+struct TaskTelemetry {
+    model: String,
+    client_id: ClientId,
+    max_tokens: u32,
+    origin: RequestOrigin,
+    started_at: Instant,
+    // Open until the task ends, so the span covers the whole round trip to the worker
+    span: Span,
+}
+
 // Inference Scheduler
 pub struct InferenceScheduler {
     pending_tasks: Arc<Mutex<HashMap<String, PendingTask>>>,
     partial_results: Arc<Mutex<HashMap<String, String>>>,
     pending_streams: Arc<Mutex<HashMap<String, mpsc::Sender<StreamEvent>>>>,
     stream_usages: Arc<Mutex<HashMap<String, CompletionUsage>>>,
+    /// Overflow strategies workers applied to running tasks
+    context_overflows: Arc<Mutex<HashMap<String, ContextOverflowReport>>>,
+    task_telemetry: Arc<Mutex<HashMap<String, TaskTelemetry>>>,
     active_clients: ActiveClients,
+    db_pool: Arc<Pool<Postgres>>,
+    usage_recorder: Arc<UsageRecorder>,
+    routing: RwLock<RoutingPolicy>,
+    timeouts: RwLock<Timeouts>,
+    /// Worker that last served each prompt prefix, by hash
+    prefix_workers: Mutex<LruCache<String, ClientId>>,
 }
 
 impl InferenceScheduler {
-    pub fn new(active_clients: ActiveClients) -> Self {
+    pub fn new(
+        active_clients: ActiveClients,
+        db_pool: Arc<Pool<Postgres>>,
+        usage_recorder: Arc<UsageRecorder>,
+        routing: RoutingPolicy,
+        timeouts: Timeouts,
+    ) -> Self {
         Self {
             pending_tasks: Arc::new(Mutex::new(HashMap::new())),
             partial_results: Arc::new(Mutex::new(HashMap::new())),
             pending_streams: Arc::new(Mutex::new(HashMap::new())),
             stream_usages: Arc::new(Mutex::new(HashMap::new())),
+            context_overflows: Arc::new(Mutex::new(HashMap::new())),
+            task_telemetry: Arc::new(Mutex::new(HashMap::new())),
             active_clients,
+            db_pool,
+            usage_recorder,
+            routing: RwLock::new(routing),
+            timeouts: RwLock::new(timeouts),
+            prefix_workers: Mutex::new(LruCache::new(
+                NonZeroUsize::new(PREFIX_WORKERS_CAPACITY).unwrap(),
+            )),
         }
     }
 
-    pub async fn execute_inference_stream(
+    pub fn routing(&self) -> RoutingPolicy {
+        *self.routing.read().unwrap()
+    }
+
+    /// Apply new selection thresholds to the requests that follow
+    pub fn set_routing(&self, routing: RoutingPolicy) {
+        *self.routing.write().unwrap() = routing;
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        *self.timeouts.read().unwrap()
+    }
+
+    /// Apply new stage timeouts to the requests that follow
+    pub fn set_timeouts(&self, timeouts: Timeouts) {
+        *self.timeouts.write().unwrap() = timeouts;
+    }
+
+    /// Wait a little before a queued request looks for a worker again. Gives
+    /// `err` back when requests do not queue, and a timeout once the queue
+    /// timeout has passed since `queued_at`.
+    async fn wait_in_queue(&self, queued_at: Instant, err: anyhow::Error) -> Result<()> {
+        let timeouts = self.timeouts();
+        if timeouts.queue.is_zero() {
+            return Err(err);
+        }
+        let left = timeouts.queue.saturating_sub(queued_at.elapsed());
+        if left.is_zero() {
+            return Err(timeouts.error(TimeoutStage::Queue).into());
+        }
+        tokio::time::sleep(left.min(QUEUE_POLL_INTERVAL)).await;
+        Ok(())
+    }
+
+    /// Record a task that ran over `err`'s stage and stop it on its worker
+    async fn time_out_task(&self, task_id: &str, device_id: &ClientId, err: &TimeoutError) {
+        warn!(
+            "Task {} on device {} timed out: {}",
+            task_id, device_id, err
+        );
+        self.record_task_result(task_id, UsageStatus::Timeout, Some(err.to_string()), 0, 0)
+            .await;
+        if let Err(e) = self.cancel_inference(task_id, device_id).await {
+            debug!("Failed to cancel timed out task {}: {}", task_id, e);
+        }
+    }
+
+    /// Relay the events of a stream to the caller. Ends it with a timeout
+    /// error and stops the task on its worker when the first token, the next
+    /// one or the deadline is late.
+    fn watch_stream(
+        self: &Arc<Self>,
+        task_id: String,
+        device_id: ClientId,
+        deadline: Option<Instant>,
+        mut events: mpsc::Receiver<StreamEvent>,
+    ) -> mpsc::Receiver<StreamEvent> {
+        let (tx, rx) = mpsc::channel::<StreamEvent>(128);
+        let scheduler = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut got_token = false;
+            loop {
+                let timeouts = scheduler.timeouts();
+                let event = match timeouts.chunk_due(got_token, Instant::now(), deadline) {
+                    Some((due, stage)) => {
+                        let due = tokio::time::Instant::from_std(due);
+                        match tokio::time::timeout_at(due, events.recv()).await {
+                            Ok(event) => event,
+                            Err(_) => {
+                                let err = timeouts.error(stage);
+                                let _ = tx.send(StreamEvent::Error(err.to_string())).await;
+                                let _ = tx.send(StreamEvent::Done).await;
+                                scheduler.time_out_task(&task_id, &device_id, &err).await;
+                                return;
+                            }
+                        }
+                    }
+                    None => events.recv().await,
+                };
+                // Ends when the task does or the caller went away
+                let Some(event) = event else {
+                    return;
+                };
+                got_token |= matches!(event, StreamEvent::Delta(..));
+                let done = matches!(event, StreamEvent::Done);
+                if tx.send(event).await.is_err() || done {
+                    return;
+                }
+            }
+        });
+        rx
+    }
+
+    /// Returns the `traceparent` to forward to the worker. The task span is a
+    /// child of the gateway's request span, which joined the caller's trace.
+    async fn track_task(
         &self,
+        task_id: &str,
+        model: String,
+        client_id: ClientId,
+        max_tokens: u32,
+        origin: RequestOrigin,
+    ) -> String {
+        // Fill in the fields of the gateway's request span
+        let current = Span::current();
+        current.record("model", model.as_str());
+        current.record("client_id", client_id.to_string().as_str());
+
+        let parent = TraceParent::from_header_or_root(origin.traceparent.as_deref());
+        let span = info_span!(
+            "inference_task",
+            task_id = %task_id,
+            model = %model,
+            client_id = %client_id,
+        );
+        let traceparent = trace::outgoing_traceparent(&span, &parent).to_string();
+
+        let mut telemetry = self.task_telemetry.lock().await;
+        telemetry.insert(
+            task_id.to_string(),
+            TaskTelemetry {
+                model,
+                client_id,
+                max_tokens,
+                origin,
+                started_at: Instant::now(),
+                span,
+            },
+        );
+        traceparent
+    }
+
+    async fn untrack_task(&self, task_id: &str) {
+        let mut telemetry = self.task_telemetry.lock().await;
+        telemetry.remove(task_id);
+    }
+
+    /// Record usage and, for completed or failed tasks, a performance sample
+    async fn record_task_result(
+        &self,
+        task_id: &str,
+        status: UsageStatus,
+        error: Option<String>,
+        prompt_tokens: u32,
+        completion_tokens: u32,
+    ) {
+        let Some(task) = self.task_telemetry.lock().await.remove(task_id) else {
+            return;
+        };
+        let latency_ms = task.started_at.elapsed().as_millis() as i64;
+        task.span.in_scope(|| {
+            debug!(
+                "Task {} finished with {:?} after {}ms",
+                task_id, status, latency_ms
+            )
+        });
+
+        self.usage_recorder.record(UsageRecord {
+            request_id: task.origin.request_id,
+            api_key: task.origin.api_key,
+            client_id: task.client_id,
+            model: task.model.clone(),
+            prompt_tokens: prompt_tokens as i32,
+            completion_tokens: completion_tokens as i32,
+            latency_ms,
+            status,
+            created_at: chrono::Utc::now(),
+        });
+
+        if status == UsageStatus::Cancelled {
+            return;
+        }
+
+        let success = status == UsageStatus::Success;
+        let sample = ModelPerfSample {
+            model_name: task.model,
+            client_id: task.client_id,
+            latency_ms,
+            prompt_tokens: prompt_tokens as i32,
+            completion_tokens: completion_tokens as i32,
+            success,
+            truncated: success && completion_tokens >= task.max_tokens,
+            error,
+        };
+
+        let db_pool = self.db_pool.clone();
+        tokio::spawn(async move {
+            if let Err(e) = insert_model_perf_sample(&db_pool, &sample).await {
+                warn!(
+                    "Failed to record performance sample for model {}: {}",
+                    sample.model_name, e
+                );
+            }
+        });
+    }
+
+    pub async fn execute_inference_stream(
+        self: &Arc<Self>,
         request: CompletionRequest,
         allowed_client_ids: Option<&[ClientId]>,
+        origin: RequestOrigin,
     ) -> Result<(String, ClientId, mpsc::Receiver<StreamEvent>)> {
+        let started_at = Instant::now();
         let task_id = Uuid::new_v4().to_string();
         let (tx, rx) = mpsc::channel::<StreamEvent>(128);
 
@@ -148,27 +426,58 @@ impl InferenceScheduler {
             streams.insert(task_id.clone(), tx);
         }
 
-        let device_id = self.select_best_device(allowed_client_ids).await?;
+        let device_id = loop {
+            match self
+                .select_best_device(
+                    allowed_client_ids,
+                    request.prompt.len(),
+                    origin.region.as_deref(),
+                )
+                .await
+            {
+                Ok(device_id) => break device_id,
+                Err(e) => self.wait_in_queue(started_at, e).await?,
+            }
+        };
+        let deadline = self.timeouts().deadline(started_at);
+        let max_tokens = request.max_tokens.unwrap_or(4090);
+        let request_id = origin.request_id.clone();
+        let traceparent = self
+            .track_task(
+                &task_id,
+                request.model.clone().unwrap_or_else(|| "gpuf".to_string()),
+                device_id,
+                max_tokens,
+                origin,
+            )
+            .await;
         if let Err(e) = self
             .send_task_to_device(
                 &device_id,
                 task_id.clone(),
+                request_id,
+                traceparent,
                 request.prompt,
-                request.max_tokens.unwrap_or(4090),
+                max_tokens,
                 request.temperature.unwrap_or(0.7),
                 request.top_k.unwrap_or(40),
                 request.top_p.unwrap_or(0.9),
                 request.repeat_penalty.unwrap_or(1.1),
                 request.repeat_last_n.unwrap_or(64),
                 request.min_keep.unwrap_or(1),
+                request.context_overflow.unwrap_or_default(),
+                millis_left(deadline),
             )
             .await
         {
             let mut streams = self.pending_streams.lock().await;
             streams.remove(&task_id);
+            drop(streams);
+            self.untrack_task(&task_id).await;
             return Err(e);
         }
 
+        let rx = self.watch_stream(task_id.clone(), device_id, deadline, rx);
         Ok((task_id, device_id, rx))
     }
 
@@ -176,10 +485,13 @@ impl InferenceScheduler {
         &self,
         model_name: &str,
         allowed_client_ids: Option<&[ClientId]>,
+        payload_bytes: usize,
+        region: Option<&str>,
+        affinity: Option<ClientId>,
     ) -> Result<ClientId> {
         let clients = self.active_clients.lock().await;
 
-        let mut best_device: Option<(ClientId, u16)> = None;
+        let mut candidates = Vec::new();
 
         debug!("online Clients: {}", clients.len());
         for (client_id, client_info) in clients.iter() {
@@ -190,7 +502,7 @@ impl InferenceScheduler {
                 }
             }
             debug!("Client {} is authed {} model {}", client_id, client_info.authed, model_name);
-            if !client_info.authed {
+            if !client_info.authed || client_info.maintenance || !client_info.available() {
                 continue;
             }
             let Some(models) = &client_info.models else {
@@ -203,24 +515,26 @@ impl InferenceScheduler {
             let Some(system_info) = &client_info.system_info else {
                 continue;
             };
-            let total_load: u16 = (system_info.cpu_usage + system_info.memory_usage) as u16;
-
-            match best_device {
-                None => best_device = Some((*client_id, total_load)),
-                Some((_best_id, best_load)) if total_load < best_load => {
-                    best_device = Some((*client_id, total_load))
-                }
-                _ => {}
-            }
+            candidates.push(Candidate {
+                client_id: *client_id,
+                load: system_info.cpu_usage as u16 + system_info.memory_usage as u16,
+                benchmark: client_info.benchmark,
+                network: client_info.network_quality,
+                quality: client_info.quality_score,
+                in_region: region.map_or(true, |region| client_info.location.matches(region)),
+                warm: !client_info.cold,
+            });
         }
 
-        best_device
-            .map(|(id, _)| id)
+        let routing = self.routing();
+        let candidates = without_poor_links(candidates, payload_bytes, &routing);
+        let candidates = in_region_first(warm_first(without_low_quality(candidates, &routing)));
+        pick_with_affinity(&candidates, affinity)
             .ok_or_else(|| anyhow!("No compatible client found for model '{model_name}'"))
     }
 
     pub async fn execute_chat_inference_stream(
-        &self,
+        self: &Arc<Self>,
         model: String,
         messages: Vec<ChatMessage>,
         max_tokens: u32,
@@ -230,8 +544,11 @@ impl InferenceScheduler {
         repeat_penalty: f32,
         repeat_last_n: i32,
         min_keep: u32,
+        overflow: ContextOverflow,
         allowed_client_ids: Option<&[ClientId]>,
+        origin: RequestOrigin,
     ) -> Result<(String, ClientId, mpsc::Receiver<StreamEvent>)> {
+        let started_at = Instant::now();
         let task_id = Uuid::new_v4().to_string();
         let (tx, rx) = mpsc::channel::<StreamEvent>(128);
 
@@ -240,19 +557,44 @@ impl InferenceScheduler {
             streams.insert(task_id.clone(), tx);
         }
 
-        let device_id = match self
-            .select_best_device_for_model(&model, allowed_client_ids)
-            .await
-        {
-            Ok(d) => d,
-            Err(e) => {
-                warn!(
-                    "No model-compatible device found for model '{}': {}. Falling back to generic device selection.",
-                    model, e
-                );
-                self.select_best_device(allowed_client_ids).await?
+        let payload_bytes = messages.iter().map(|m| m.content.len()).sum();
+        // Requests sharing a prefix go where its KV state is kept
+        let prefix = prompt_prefix(&messages);
+        let affinity = match &prefix {
+            Some(prefix) => self.prefix_workers.lock().await.get(&prefix.hash).copied(),
+            None => None,
+        };
+        let device_id = loop {
+            let selected = match self
+                .select_best_device_for_model(
+                    &model,
+                    allowed_client_ids,
+                    payload_bytes,
+                    origin.region.as_deref(),
+                    affinity,
+                )
+                .await
+            {
+                Ok(d) => Ok(d),
+                Err(e) => {
+                    warn!(
+                        "No model-compatible device found for model '{}': {}. Falling back to generic device selection.",
+                        model, e
+                    );
+                    self.select_best_device(
+                        allowed_client_ids,
+                        payload_bytes,
+                        origin.region.as_deref(),
+                    )
+                    .await
+                }
+            };
+            match selected {
+                Ok(device_id) => break device_id,
+                Err(e) => self.wait_in_queue(started_at, e).await?,
             }
         };
+        let deadline = self.timeouts().deadline(started_at);
         debug!("Selected device {} for model {}", device_id, model);
         let common_messages = messages
             .into_iter()
@@ -262,10 +604,16 @@ impl InferenceScheduler {
             })
             .collect::<Vec<_>>();
 
+        let request_id = origin.request_id.clone();
+        let traceparent = self
+            .track_task(&task_id, model.clone(), device_id, max_tokens, origin)
+            .await;
         if let Err(e) = self
             .send_chat_task_to_device(
                 &device_id,
                 task_id.clone(),
+                request_id,
+                traceparent,
                 model,
                 common_messages,
                 max_tokens,
@@ -275,14 +623,23 @@ impl InferenceScheduler {
                 repeat_penalty,
                 repeat_last_n,
                 min_keep,
+                prefix.clone(),
+                overflow,
+                millis_left(deadline),
             )
             .await
         {
             let mut streams = self.pending_streams.lock().await;
             streams.remove(&task_id);
+            drop(streams);
+            self.untrack_task(&task_id).await;
             return Err(e);
         }
+        if let Some(prefix) = prefix {
+            self.prefix_workers.lock().await.put(prefix.hash, device_id);
+        }
 
+        let rx = self.watch_stream(task_id.clone(), device_id, deadline, rx);
         Ok((task_id, device_id, rx))
     }
 
@@ -295,6 +652,9 @@ impl InferenceScheduler {
             let mut streams = self.pending_streams.lock().await;
             streams.remove(task_id);
         }
+        self.record_task_result(task_id, UsageStatus::Cancelled, None, 0, 0)
+            .await;
+        self.take_context_overflow(task_id).await;
 
         use common::write_command;
 
@@ -322,6 +682,8 @@ impl InferenceScheduler {
         &self,
         device_id: &ClientId,
         task_id: String,
+        request_id: Option<String>,
+        traceparent: String,
         model: String,
         messages: Vec<common::ChatMessage>,
         max_tokens: u32,
@@ -331,26 +693,14 @@ impl InferenceScheduler {
         repeat_penalty: f32,
         repeat_last_n: i32,
         min_keep: u32,
+        prefix: Option<PromptPrefix>,
+        overflow: ContextOverflow,
+        deadline_ms: Option<u64>,
     ) -> Result<()> {
-        use common::write_command;
-
-        let mut clients = self.active_clients.lock().await;
-        let client_info = clients
-            .get_mut(device_id)
-            .ok_or_else(|| anyhow!("Device {:?} not found or not connected", device_id))?;
-
-        if !client_info.authed {
-            error!("Device {:?} not authenticated", device_id);
-            return Err(anyhow!("Device {:?} not authenticated", device_id));
-        }
-
-        let mut writer = client_info
-            .writer
-            .try_lock()
-            .map_err(|_| anyhow!("Device {:?} is busy, please try again", device_id))?;
-
-        let chat_task = CommandV1::ChatInferenceTask {
+        let chat_task = CommandV1::ChatInferenceTaskV2 {
             task_id: task_id.clone(),
+            request_id,
+            traceparent: Some(traceparent),
             model,
             messages,
             max_tokens,
@@ -360,6 +710,9 @@ impl InferenceScheduler {
             repeat_penalty,
             repeat_last_n,
             min_keep,
+            prefix,
+            overflow,
+            deadline_ms,
         };
 
         let command = Command::V1(chat_task);
@@ -367,11 +720,76 @@ impl InferenceScheduler {
             "sent chat inference task {} to device {:?} :{:?}",
             task_id, device_id, command
         );
-        write_command(&mut *writer, &command).await?;
+        self.send_to_device(device_id, &command).await
+    }
+
+    /// Write `command` to the worker's connection, waiting at most the
+    /// connect timeout for it to be free
+    async fn send_to_device(&self, device_id: &ClientId, command: &Command) -> Result<()> {
+        use common::write_command_for;
+
+        let (connection, version) = {
+            let clients = self.active_clients.lock().await;
+            let client_info = clients
+                .get(device_id)
+                .ok_or_else(|| anyhow!("Device {:?} not found or not connected", device_id))?;
+
+            if !client_info.authed {
+                error!("Device {:?} not authenticated", device_id);
+                return Err(anyhow!("Device {:?} not authenticated", device_id));
+            }
+            (client_info.writer.clone(), client_info.version)
+        };
+
+        let timeouts = self.timeouts();
+        let mut writer = if timeouts.connect.is_zero() {
+            connection.lock().await
+        } else {
+            tokio::time::timeout(timeouts.connect, connection.lock())
+                .await
+                .map_err(|_| timeouts.error(TimeoutStage::Connect))?
+        };
+        if !write_command_for(&mut *writer, command, version).await? {
+            return Err(anyhow!(
+                "Device {:?} is on protocol {} and does not know this command",
+                device_id,
+                version
+            ));
+        }
         writer.flush().await?;
         Ok(())
     }
 
+    /// Remember the overflow strategy a worker applied to a running task,
+    /// reported in the task's usage
+    pub async fn handle_context_overflow(
+        &self,
+        task_id: String,
+        strategy: ContextOverflow,
+        dropped_tokens: u32,
+    ) {
+        let running = self.pending_streams.lock().await.contains_key(&task_id)
+            || self.pending_tasks.lock().await.contains_key(&task_id);
+        if !running {
+            return;
+        }
+        debug!(
+            "Task {} dropped {} prompt tokens with {:?}",
+            task_id, dropped_tokens, strategy
+        );
+        self.context_overflows.lock().await.insert(
+            task_id,
+            ContextOverflowReport {
+                strategy,
+                dropped_tokens,
+            },
+        );
+    }
+
+    async fn take_context_overflow(&self, task_id: &str) -> Option<ContextOverflowReport> {
+        self.context_overflows.lock().await.remove(task_id)
+    }
+
     pub async fn handle_inference_result_chunk(
         &self,
         task_id: String,
@@ -392,6 +810,9 @@ impl InferenceScheduler {
 
         if let Some(sender) = stream_sender {
             if let Some(err) = error {
+                self.record_task_result(&task_id, UsageStatus::Error, Some(err.clone()), 0, 0)
+                    .await;
+                self.take_context_overflow(&task_id).await;
                 let _ = sender.send(StreamEvent::Error(err)).await;
                 let _ = sender.send(StreamEvent::Done).await;
                 let mut streams = self.pending_streams.lock().await;
@@ -406,12 +827,21 @@ impl InferenceScheduler {
             }
 
             if done {
+                self.record_task_result(
+                    &task_id,
+                    UsageStatus::Success,
+                    None,
+                    prompt_tokens,
+                    completion_tokens,
+                )
+                .await;
                 let usage = CompletionUsage {
                     prompt_tokens,
                     completion_tokens,
                     total_tokens: prompt_tokens.saturating_add(completion_tokens),
                     analysis_tokens: Some(analysis_tokens),
                     final_tokens: Some(final_tokens),
+                    context_overflow: self.take_context_overflow(&task_id).await,
                 };
                 {
                     let mut usages = self.stream_usages.lock().await;
@@ -478,6 +908,20 @@ impl InferenceScheduler {
             "Handling inference result for task {} (success: {})",
             task_id, success
         );
+        let context_overflow = self.take_context_overflow(&task_id).await;
+        let (status, task_error) = if success {
+            (UsageStatus::Success, None)
+        } else {
+            (UsageStatus::Error, Some(error.clone().unwrap_or_default()))
+        };
+        self.record_task_result(
+            &task_id,
+            status,
+            task_error,
+            prompt_tokens,
+            completion_tokens,
+        )
+        .await;
 
         let mut tasks = self.pending_tasks.lock().await;
         let all_tasks_before: Vec<String> = tasks.keys().cloned().collect();
@@ -512,6 +956,7 @@ impl InferenceScheduler {
                         total_tokens: prompt_tokens.saturating_add(completion_tokens),
                         analysis_tokens: None,
                         final_tokens: None,
+                        context_overflow,
                     },
                 })
             } else {
@@ -541,16 +986,18 @@ impl InferenceScheduler {
     async fn select_best_device(
         &self,
         allowed_client_ids: Option<&[ClientId]>,
+        payload_bytes: usize,
+        region: Option<&str>,
     ) -> Result<ClientId> {
         let clients = self.active_clients.lock().await;
 
-        let mut best_device: Option<(ClientId, u16)> = None;
-        let mut device_count = 0;
+        let mut candidates = Vec::new();
 
         let mut consider_device =
             |client_id: &ClientId, client_info: &crate::handle::ClientInfo| {
-                // Only consider authenticated Android devices
-                if !client_info.authed {
+                // Only consider authenticated Android devices that are not in maintenance,
+                // have not paused themselves and have a healthy engine
+                if !client_info.authed || client_info.maintenance || !client_info.available() {
                     return;
                 }
 
@@ -559,13 +1006,15 @@ impl InferenceScheduler {
                     return;
                 };
 
-                // Simple load balancing: choose device with lowest CPU + Memory usage
-                let total_load: u16 = (system_info.cpu_usage + system_info.memory_usage) as u16;
-                device_count += 1;
-
-                if best_device.is_none() || total_load < best_device.as_ref().unwrap().1 {
-                    best_device = Some((*client_id, total_load));
-                }
+                candidates.push(Candidate {
+                    client_id: *client_id,
+                    load: system_info.cpu_usage as u16 + system_info.memory_usage as u16,
+                    benchmark: client_info.benchmark,
+                    network: client_info.network_quality,
+                    quality: client_info.quality_score,
+                    in_region: region.map_or(true, |region| client_info.location.matches(region)),
+                    warm: !client_info.cold,
+                });
             };
 
         match allowed_client_ids {
@@ -585,10 +1034,15 @@ impl InferenceScheduler {
             }
         }
 
-        if let Some((client_id, _load)) = best_device {
+        let routing = self.routing();
+        let candidates = without_poor_links(candidates, payload_bytes, &routing);
+        let candidates = in_region_first(warm_first(without_low_quality(candidates, &routing)));
+        if let Some((client_id, _load)) = pick_device(&candidates) {
             info!(
                 "Selected device {:?} for inference (load: {}%, available devices: {})",
-                client_id, _load, device_count
+                client_id,
+                _load,
+                candidates.len()
             );
             Ok(client_id)
         } else {
@@ -601,6 +1055,8 @@ impl InferenceScheduler {
         &self,
         device_id: &ClientId,
         task_id: String,
+        request_id: Option<String>,
+        traceparent: String,
         prompt: String,
         max_tokens: u32,
         temperature: f32,
@@ -609,30 +1065,14 @@ impl InferenceScheduler {
         repeat_penalty: f32,
         repeat_last_n: i32,
         min_keep: u32,
+        overflow: ContextOverflow,
+        deadline_ms: Option<u64>,
     ) -> Result<()> {
-        use common::write_command;
-
-        // Find active client connection
-        let mut clients = self.active_clients.lock().await;
-        let client_info = clients
-            .get_mut(device_id)
-            .ok_or_else(|| anyhow!("Device {:?} not found or not connected", device_id))?;
-
-        // Check if client is authenticated and ready
-        if !client_info.authed {
-            error!("Device {:?} not authenticated", device_id);
-            return Err(anyhow!("Device {:?} not authenticated", device_id));
-        }
-
-        // Try to acquire writer lock (non-blocking to avoid deadlocks)
-        let mut writer = client_info
-            .writer
-            .try_lock()
-            .map_err(|_| anyhow!("Device {:?} is busy, please try again", device_id))?;
-
         // Create and send inference task command
-        let inference_task = CommandV1::InferenceTask {
+        let inference_task = CommandV1::InferenceTaskV2 {
             task_id: task_id.clone(),
+            request_id,
+            traceparent: Some(traceparent),
             prompt,
             max_tokens,
             temperature,
@@ -641,6 +1081,8 @@ impl InferenceScheduler {
             repeat_penalty,
             repeat_last_n,
             min_keep,
+            overflow,
+            deadline_ms,
         };
 
         let command = Command::V1(inference_task);
@@ -648,8 +1090,7 @@ impl InferenceScheduler {
             "sent inference task {} to device {:?} :{:?}",
             task_id, device_id, command
         );
-        write_command(&mut *writer, &command).await?;
-        writer.flush().await?;
+        self.send_to_device(device_id, &command).await?;
 
         info!(
             "Successfully sent inference task {} to device {:?}",
@@ -663,7 +1104,9 @@ impl InferenceScheduler {
         &self,
         request: CompletionRequest,
         allowed_client_ids: Option<&[ClientId]>,
+        origin: RequestOrigin,
     ) -> Result<CompletionResponse> {
+        let started_at = Instant::now();
         let task_id = Uuid::new_v4().to_string();
 
         // Create response channel
@@ -683,7 +1126,32 @@ impl InferenceScheduler {
         }
 
         // Select best available device
-        let device_id = self.select_best_device(allowed_client_ids).await?;
+        let device_id = loop {
+            match self
+                .select_best_device(
+                    allowed_client_ids,
+                    request.prompt.len(),
+                    origin.region.as_deref(),
+                )
+                .await
+            {
+                Ok(device_id) => break device_id,
+                Err(e) => self.wait_in_queue(started_at, e).await?,
+            }
+        };
+        let timeouts = self.timeouts();
+        let deadline = timeouts.deadline(started_at);
+        let max_tokens = request.max_tokens.unwrap_or(1024);
+        let request_id = origin.request_id.clone();
+        let traceparent = self
+            .track_task(
+                &task_id,
+                request.model.clone().unwrap_or_else(|| "gpuf".to_string()),
+                device_id,
+                max_tokens,
+                origin,
+            )
+            .await;
 
         // Send task to device
         info!("About to send task {} to device {:?}", task_id, device_id);
@@ -691,20 +1159,26 @@ impl InferenceScheduler {
             .send_task_to_device(
                 &device_id,
                 task_id.clone(),
+                request_id,
+                traceparent,
                 request.prompt,
-                request.max_tokens.unwrap_or(1024),
+                max_tokens,
                 request.temperature.unwrap_or(0.7),
                 request.top_k.unwrap_or(40),
                 request.top_p.unwrap_or(0.9),
                 request.repeat_penalty.unwrap_or(1.1),
                 request.repeat_last_n.unwrap_or(64),
                 request.min_keep.unwrap_or(1),
+                request.context_overflow.unwrap_or_default(),
+                millis_left(deadline),
             )
             .await
         {
             // Clean up pending task on failure
             let mut tasks = self.pending_tasks.lock().await;
             tasks.remove(&task_id);
+            drop(tasks);
+            self.untrack_task(&task_id).await;
             error!(
                 "Failed to send inference task to device {:?}: {}",
                 device_id, e
@@ -730,18 +1204,18 @@ impl InferenceScheduler {
             }
         }
 
-        // Wait for result with timeout
-        let timeout_secs: u64 = std::env::var("GPUF_INFERENCE_TIMEOUT_SECS")
-            .ok()
-            .and_then(|s| s.parse::<u64>().ok())
-            .filter(|&v| v > 0)
-            .unwrap_or(300);
-
+        // Wait for result until the deadline
         info!(
-            "Waiting for result of task {} with {}s timeout...",
-            task_id, timeout_secs
+            "Waiting for result of task {} with {:?} timeout...",
+            task_id, timeouts.total
         );
-        match tokio::time::timeout(std::time::Duration::from_secs(timeout_secs), receiver).await {
+        let result = match deadline {
+            Some(deadline) => {
+                tokio::time::timeout_at(tokio::time::Instant::from_std(deadline), receiver).await
+            }
+            None => Ok(receiver.await),
+        };
+        match result {
             Ok(Ok(response)) => {
                 info!("Task {} completed successfully", task_id);
                 response
@@ -754,15 +1228,72 @@ impl InferenceScheduler {
                 // Clean up pending task on timeout
                 let mut tasks = self.pending_tasks.lock().await;
                 tasks.remove(&task_id);
-                warn!("Task {} timed out after {} seconds", task_id, timeout_secs);
-                Err(anyhow!(
-                    "Inference task timed out after {} seconds",
-                    timeout_secs
-                ))
+                drop(tasks);
+                let err = timeouts.error(TimeoutStage::Total);
+                self.time_out_task(&task_id, &device_id, &err).await;
+                Err(err.into())
             }
         }
     }
 
+    /// Run a canary prompt on one worker with greedy sampling. Canaries are
+    /// not tracked, so they record no usage and earn no work points. Errors
+    /// only when the task could not be sent.
+    pub async fn execute_canary(
+        &self,
+        device_id: &ClientId,
+        prompt: String,
+        max_tokens: u32,
+        timeout: std::time::Duration,
+    ) -> Result<CanaryReply> {
+        let task_id = Uuid::new_v4().to_string();
+        let (sender, receiver) = oneshot::channel();
+        self.pending_tasks
+            .lock()
+            .await
+            .insert(task_id.clone(), sender);
+
+        if let Err(e) = self
+            .send_task_to_device(
+                device_id,
+                task_id.clone(),
+                None,
+                TraceParent::new_root().to_string(),
+                prompt,
+                max_tokens,
+                0.0,
+                1,
+                1.0,
+                1.0,
+                64,
+                1,
+                ContextOverflow::Error,
+                Some(timeout.as_millis() as u64),
+            )
+            .await
+        {
+            self.pending_tasks.lock().await.remove(&task_id);
+            return Err(e);
+        }
+
+        Ok(match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(Ok(response))) => CanaryReply::Output(
+                response
+                    .choices
+                    .into_iter()
+                    .next()
+                    .map(|c| c.text)
+                    .unwrap_or_default(),
+            ),
+            Ok(Ok(Err(e))) => CanaryReply::Error(e.to_string()),
+            Ok(Err(_)) => CanaryReply::Error("task response channel closed".to_string()),
+            Err(_) => {
+                self.pending_tasks.lock().await.remove(&task_id);
+                CanaryReply::TimedOut
+            }
+        })
+    }
+
     /// Get list of available devices
     pub async fn get_available_devices(
         &self,
@@ -778,7 +1309,11 @@ impl InferenceScheduler {
                 }
                 let device = DeviceInfo {
                     client_id: hex::encode(&client_id.0),
-                    status: if client_info.system_info.is_some() {
+                    status: if client_info.maintenance {
+                        "maintenance".to_string()
+                    } else if client_info.busy {
+                        "busy".to_string()
+                    } else if client_info.system_info.is_some() {
                         "online".to_string()
                     } else {
                         "initializing".to_string()
@@ -825,3 +1360,350 @@ pub struct DeviceInfo {
     pub memory_usage: u8,
     pub device_count: u32,
 }
+
+/// Thresholds of worker selection, changed on config reloads
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RoutingPolicy {
+    /// Payloads above this size are kept away from workers on slow links
+    pub large_payload_bytes: usize,
+    /// Longest acceptable estimated transfer time of a large payload
+    pub max_payload_transfer_secs: f32,
+    /// Workers scoring lower on canary prompts get no inference while others can take it
+    pub min_quality_score: f32,
+}
+
+impl Default for RoutingPolicy {
+    fn default() -> Self {
+        Self {
+            large_payload_bytes: 256 * 1024,
+            max_payload_transfer_secs: 1.0,
+            min_quality_score: 0.5,
+        }
+    }
+}
+
+struct Candidate {
+    client_id: ClientId,
+    /// CPU + memory usage, 0-200
+    load: u16,
+    benchmark: Option<BenchmarkResult>,
+    network: Option<NetworkQuality>,
+    /// Canary quality score, 0.0-1.0
+    quality: f32,
+    /// In the region the caller prefers, true when it has no preference
+    in_region: bool,
+    /// Model loaded and warmed up, or the worker never said otherwise
+    warm: bool,
+}
+
+/// Drops workers whose last network probe says a large payload would take too
+/// long to arrive. Workers without a probe are kept, and nothing is dropped if
+/// no worker would be left.
+fn without_poor_links(
+    candidates: Vec<Candidate>,
+    payload_bytes: usize,
+    policy: &RoutingPolicy,
+) -> Vec<Candidate> {
+    if payload_bytes < policy.large_payload_bytes {
+        return candidates;
+    }
+    let fast_enough = |c: &Candidate| {
+        c.network.map_or(true, |n| {
+            n.download_secs(payload_bytes) <= policy.max_payload_transfer_secs
+        })
+    };
+    if !candidates.iter().any(fast_enough) {
+        return candidates;
+    }
+    candidates.into_iter().filter(|c| fast_enough(c)).collect()
+}
+
+/// Drops workers whose canary answers pushed their quality score below
+/// `min_quality_score`, unless no worker would be left
+fn without_low_quality(candidates: Vec<Candidate>, policy: &RoutingPolicy) -> Vec<Candidate> {
+    let trusted = |c: &Candidate| c.quality >= policy.min_quality_score;
+    if !candidates.iter().any(trusted) {
+        return candidates;
+    }
+    candidates.into_iter().filter(|c| trusted(c)).collect()
+}
+
+/// Drops workers outside the caller's preferred region, unless no worker
+/// would be left
+fn in_region_first(candidates: Vec<Candidate>) -> Vec<Candidate> {
+    if !candidates.iter().any(|c| c.in_region) {
+        return candidates;
+    }
+    candidates.into_iter().filter(|c| c.in_region).collect()
+}
+
+/// Drops workers still loading or warming up their model, unless no worker
+/// would be left. Requests are interactive; a warm worker elsewhere answers
+/// sooner than a cold one nearby.
+fn warm_first(candidates: Vec<Candidate>) -> Vec<Candidate> {
+    if !candidates.iter().any(|c| c.warm) {
+        return candidates;
+    }
+    candidates.into_iter().filter(|c| c.warm).collect()
+}
+
+/// Device with the most spare measured throughput when every candidate sent a
+/// benchmark, otherwise the one with the lowest CPU + memory load
+fn pick_device(candidates: &[Candidate]) -> Option<(ClientId, u16)> {
+    let spare_throughput = |load: u16, benchmark: &BenchmarkResult| {
+        benchmark.tokens_per_sec * (200u16.saturating_sub(load)) as f32 / 200.0
+    };
+
+    if candidates.iter().all(|c| c.benchmark.is_some()) {
+        candidates
+            .iter()
+            .filter_map(|c| {
+                c.benchmark
+                    .map(|b| (c.client_id, c.load, spare_throughput(c.load, &b)))
+            })
+            .max_by(|a, b| a.2.total_cmp(&b.2))
+            .map(|(id, load, _)| (id, load))
+    } else {
+        candidates
+            .iter()
+            .min_by_key(|c| c.load)
+            .map(|c| (c.client_id, c.load))
+    }
+}
+
+/// `affinity` when it is a candidate not much busier than the best pick,
+/// otherwise the best pick
+fn pick_with_affinity(candidates: &[Candidate], affinity: Option<ClientId>) -> Option<ClientId> {
+    let (best, best_load) = pick_device(candidates)?;
+    let preferred = affinity.and_then(|id| candidates.iter().find(|c| c.client_id == id));
+    match preferred {
+        Some(c) if c.load <= best_load.saturating_add(MAX_AFFINITY_EXTRA_LOAD) => Some(c.client_id),
+        _ => Some(best),
+    }
+}
+
+/// The leading system messages of a chat, e.g. a system prompt or RAG
+/// header, when long enough to be worth keeping on the worker
+fn prompt_prefix(messages: &[ChatMessage]) -> Option<PromptPrefix> {
+    let count = messages
+        .iter()
+        .take_while(|m| m.role == "system" || m.role == "developer")
+        .count();
+    if count == 0 || count == messages.len() {
+        return None;
+    }
+    let prefix = &messages[..count];
+    if prefix.iter().map(|m| m.content.len()).sum::<usize>() < MIN_PREFIX_BYTES {
+        return None;
+    }
+    let mut hasher = Sha256::new();
+    for message in prefix {
+        hasher.update(message.role.as_bytes());
+        hasher.update([0]);
+        hasher.update(message.content.as_bytes());
+        hasher.update([0]);
+    }
+    Some(PromptPrefix {
+        hash: hex::encode(hasher.finalize()),
+        messages: count as u32,
+    })
+}
+
+#[cfg(test)]
+fn candidate(id: u8, load: u16, tokens_per_sec: Option<f32>) -> Candidate {
+    Candidate {
+        client_id: ClientId([id; 16]),
+        load,
+        benchmark: tokens_per_sec.map(|tokens_per_sec| BenchmarkResult {
+            tokens_per_sec,
+            ..Default::default()
+        }),
+        network: None,
+        quality: 1.0,
+        in_region: true,
+        warm: true,
+    }
+}
+
+#[test]
+fn test_pick_device() {
+    let (a, b) = (ClientId([1; 16]), ClientId([2; 16]));
+
+    // Faster worker wins even with somewhat higher load
+    assert_eq!(
+        pick_device(&[candidate(1, 20, Some(10.0)), candidate(2, 60, Some(30.0))]),
+        Some((b, 60))
+    );
+    // Fall back to load when a worker did not report a benchmark
+    assert_eq!(
+        pick_device(&[candidate(1, 20, None), candidate(2, 60, Some(30.0))]),
+        Some((a, 20))
+    );
+    assert_eq!(pick_device(&[]), None);
+}
+
+#[test]
+fn test_without_poor_links() {
+    let link = |rtt_ms, download_mbps| {
+        Some(NetworkQuality {
+            rtt_ms,
+            upload_mbps: download_mbps,
+            download_mbps,
+        })
+    };
+    let candidates = || {
+        let mut slow = candidate(1, 10, None);
+        slow.network = link(400, 2.0);
+        let mut fast = candidate(2, 90, None);
+        fast.network = link(20, 100.0);
+        vec![slow, fast, candidate(3, 50, None)]
+    };
+    let ids = |c: Vec<Candidate>| c.iter().map(|c| c.client_id.0[0]).collect::<Vec<_>>();
+
+    let policy = RoutingPolicy::default();
+
+    // Small prompts go anywhere
+    assert_eq!(
+        ids(without_poor_links(candidates(), 1024, &policy)),
+        vec![1, 2, 3]
+    );
+    // 1 MiB takes ~4s over 2 Mbps, unprobed workers are kept
+    assert_eq!(
+        ids(without_poor_links(candidates(), 1 << 20, &policy)),
+        vec![2, 3]
+    );
+    // Nothing is dropped when every worker is slow
+    let slow_only = candidates().into_iter().take(1).collect();
+    assert_eq!(
+        ids(without_poor_links(slow_only, 1 << 20, &policy)),
+        vec![1]
+    );
+    // Unless the policy accepts slower transfers
+    let patient = RoutingPolicy {
+        max_payload_transfer_secs: 10.0,
+        ..policy
+    };
+    assert_eq!(
+        ids(without_poor_links(candidates(), 1 << 20, &patient)),
+        vec![1, 2, 3]
+    );
+}
+
+#[test]
+fn test_without_low_quality() {
+    let candidates = |qualities: &[f32]| {
+        qualities
+            .iter()
+            .enumerate()
+            .map(|(i, &quality)| {
+                let mut c = candidate(i as u8, 10, None);
+                c.quality = quality;
+                c
+            })
+            .collect::<Vec<_>>()
+    };
+    let ids = |c: Vec<Candidate>| c.iter().map(|c| c.client_id.0[0]).collect::<Vec<_>>();
+
+    let policy = RoutingPolicy::default();
+
+    assert_eq!(
+        ids(without_low_quality(candidates(&[1.0, 0.3, 0.5]), &policy)),
+        vec![0, 2]
+    );
+    // Nothing is dropped when every worker scores low
+    assert_eq!(
+        ids(without_low_quality(candidates(&[0.2, 0.4]), &policy)),
+        vec![0, 1]
+    );
+    let strict = RoutingPolicy {
+        min_quality_score: 0.9,
+        ..policy
+    };
+    assert_eq!(
+        ids(without_low_quality(candidates(&[1.0, 0.3, 0.5]), &strict)),
+        vec![0]
+    );
+}
+
+#[test]
+fn test_in_region_first() {
+    let candidates = |in_region: &[bool]| {
+        in_region
+            .iter()
+            .enumerate()
+            .map(|(i, &in_region)| {
+                let mut c = candidate(i as u8, 10, None);
+                c.in_region = in_region;
+                c
+            })
+            .collect::<Vec<_>>()
+    };
+    let ids = |c: Vec<Candidate>| c.iter().map(|c| c.client_id.0[0]).collect::<Vec<_>>();
+
+    assert_eq!(
+        ids(in_region_first(candidates(&[false, true, true]))),
+        vec![1, 2]
+    );
+    // Workers elsewhere still serve when none is in the region
+    assert_eq!(
+        ids(in_region_first(candidates(&[false, false]))),
+        vec![0, 1]
+    );
+}
+
+#[test]
+fn test_warm_first() {
+    let candidates = |warm: &[bool]| {
+        warm.iter()
+            .enumerate()
+            .map(|(i, &warm)| {
+                let mut c = candidate(i as u8, 10, None);
+                c.warm = warm;
+                c
+            })
+            .collect::<Vec<_>>()
+    };
+    let ids = |c: Vec<Candidate>| c.iter().map(|c| c.client_id.0[0]).collect::<Vec<_>>();
+
+    assert_eq!(
+        ids(warm_first(candidates(&[false, true, true]))),
+        vec![1, 2]
+    );
+    // Cold workers still serve when no worker is warm
+    assert_eq!(ids(warm_first(candidates(&[false, false]))), vec![0, 1]);
+}
+
+#[test]
+fn test_pick_with_affinity() {
+    let (a, b) = (ClientId([1; 16]), ClientId([2; 16]));
+    let candidates = [candidate(1, 20, None), candidate(2, 60, None)];
+
+    assert_eq!(pick_with_affinity(&candidates, None), Some(a));
+    assert_eq!(pick_with_affinity(&candidates, Some(b)), Some(b));
+    // Too busy to wait for
+    let busy = [candidate(1, 20, None), candidate(2, 90, None)];
+    assert_eq!(pick_with_affinity(&busy, Some(b)), Some(a));
+    // Gone or filtered out
+    let gone = ClientId([3; 16]);
+    assert_eq!(pick_with_affinity(&candidates, Some(gone)), Some(a));
+}
+
+#[test]
+fn test_prompt_prefix() {
+    let message = |role: &str, content: &str| ChatMessage {
+        role: role.to_string(),
+        content: content.to_string(),
+    };
+    let system = "You answer questions about the attached manual. ".repeat(8);
+    let chat = |question: &str| vec![message("system", &system), message("user", question)];
+
+    let prefix = prompt_prefix(&chat("How do I reset it?")).unwrap();
+    assert_eq!(prefix.messages, 1);
+    assert_eq!(prompt_prefix(&chat("Where is the fuse?")), Some(prefix));
+    // Short system prompts are not worth it, and a chat of system messages
+    // alone shares nothing with a later request
+    let short = [message("system", "Be brief."), message("user", "Hi")];
+    assert_eq!(prompt_prefix(&short), None);
+    assert_eq!(prompt_prefix(&[message("system", &system)]), None);
+    assert_eq!(prompt_prefix(&[message("user", &system)]), None);
+}
@@ -0,0 +1,172 @@
+//! Deadlines of the stages of an inference request. The gateway gives up on
+//! a stage that runs over and cancels the task on its worker; workers get the
+//! time left with each task and drop it once that has passed.
+
+use common::DEADLINE_EXCEEDED;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// A timeout of 0 is off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// How long a request waits for a worker to become available; off fails
+    /// requests at once when none is
+    pub queue: Duration,
+    /// How long a task waits for its worker's connection to be free
+    pub connect: Duration,
+    /// How long a streamed request waits for its first token
+    pub first_token: Duration,
+    /// Longest a request may take overall, sent to the worker as its deadline
+    pub total: Duration,
+    /// Longest gap between two tokens of a streamed request
+    pub idle: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            queue: Duration::ZERO,
+            connect: Duration::from_secs(5),
+            first_token: Duration::from_secs(120),
+            total: Duration::from_secs(300),
+            idle: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutStage {
+    Queue,
+    Connect,
+    FirstToken,
+    Total,
+    Idle,
+}
+
+/// Carries the [`DEADLINE_EXCEEDED`] prefix the handlers answer with 504
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutError {
+    pub stage: TimeoutStage,
+    pub after: Duration,
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.after.as_secs_f32();
+        match self.stage {
+            TimeoutStage::Queue => write!(
+                f,
+                "{}: no worker became available within {}s",
+                DEADLINE_EXCEEDED, secs
+            ),
+            TimeoutStage::Connect => write!(
+                f,
+                "{}: the worker's connection stayed busy for over {}s",
+                DEADLINE_EXCEEDED, secs
+            ),
+            TimeoutStage::FirstToken => {
+                write!(f, "{}: no token within {}s", DEADLINE_EXCEEDED, secs)
+            }
+            TimeoutStage::Total => {
+                write!(f, "{}: request took over {}s", DEADLINE_EXCEEDED, secs)
+            }
+            TimeoutStage::Idle => write!(f, "{}: no token for {}s", DEADLINE_EXCEEDED, secs),
+        }
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+impl Timeouts {
+    pub fn of(&self, stage: TimeoutStage) -> Duration {
+        match stage {
+            TimeoutStage::Queue => self.queue,
+            TimeoutStage::Connect => self.connect,
+            TimeoutStage::FirstToken => self.first_token,
+            TimeoutStage::Total => self.total,
+            TimeoutStage::Idle => self.idle,
+        }
+    }
+
+    pub fn error(&self, stage: TimeoutStage) -> TimeoutError {
+        TimeoutError {
+            stage,
+            after: self.of(stage),
+        }
+    }
+
+    /// Deadline of a request that arrived at `start`
+    pub fn deadline(&self, start: Instant) -> Option<Instant> {
+        (!self.total.is_zero()).then(|| start + self.total)
+    }
+
+    /// When the next chunk of a stream is due, and the stage that runs over
+    /// when it is late. The first token is due sooner than the deadline
+    /// unless that is closer.
+    pub fn chunk_due(
+        &self,
+        got_token: bool,
+        now: Instant,
+        deadline: Option<Instant>,
+    ) -> Option<(Instant, TimeoutStage)> {
+        let (wait, stage) = if got_token {
+            (self.idle, TimeoutStage::Idle)
+        } else {
+            (self.first_token, TimeoutStage::FirstToken)
+        };
+        let stage_due = (!wait.is_zero()).then(|| (now + wait, stage));
+        match (stage_due, deadline) {
+            (Some((due, _)), Some(deadline)) if deadline <= due => {
+                Some((deadline, TimeoutStage::Total))
+            }
+            (None, Some(deadline)) => Some((deadline, TimeoutStage::Total)),
+            (stage_due, _) => stage_due,
+        }
+    }
+}
+
+/// Time left until `deadline`, as sent to workers with their tasks
+pub fn millis_left(deadline: Option<Instant>) -> Option<u64> {
+    deadline.map(|deadline| {
+        deadline
+            .saturating_duration_since(Instant::now())
+            .as_millis() as u64
+    })
+}
+
+#[test]
+fn test_chunk_due() {
+    let timeouts = Timeouts {
+        first_token: Duration::from_secs(10),
+        idle: Duration::from_secs(2),
+        ..Timeouts::default()
+    };
+    let now = Instant::now();
+    let deadline = timeouts.deadline(now);
+    assert_eq!(deadline, Some(now + Duration::from_secs(300)));
+
+    assert_eq!(
+        timeouts.chunk_due(false, now, deadline),
+        Some((now + Duration::from_secs(10), TimeoutStage::FirstToken))
+    );
+    assert_eq!(
+        timeouts.chunk_due(true, now, deadline),
+        Some((now + Duration::from_secs(2), TimeoutStage::Idle))
+    );
+    let late = now + Duration::from_secs(299);
+    assert_eq!(
+        timeouts.chunk_due(true, late, deadline),
+        Some((now + Duration::from_secs(300), TimeoutStage::Total))
+    );
+
+    let off = Timeouts {
+        first_token: Duration::ZERO,
+        total: Duration::ZERO,
+        ..timeouts
+    };
+    assert_eq!(off.chunk_due(false, now, off.deadline(now)), None);
+    assert!(off
+        .error(TimeoutStage::Idle)
+        .to_string()
+        .starts_with(DEADLINE_EXCEEDED));
+}
@@ -0,0 +1,648 @@
+//! Resumable uploads of job inputs, such as audio or documents for RAG. A
+//! consumer creates an upload with its size, sends the bytes in chunks at
+//! the offset the server reports and, after a dropped connection, asks for
+//! the offset and goes on from there. Jobs reference finished uploads by id;
+//! workers fetch them through a signed URL that expires.
+
+use axum::{
+    body::Bytes,
+    extract::{Extension, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use common::JobInput;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, io};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::inference::gateway::{AuthContext, InferenceGateway};
+
+/// Largest chunk one request may carry
+pub const MAX_CHUNK_BYTES: usize = 8 * 1024 * 1024;
+/// Offset a chunk starts at, and in responses the bytes received so far
+pub const UPLOAD_OFFSET_HEADER: &str = "upload-offset";
+/// How long a worker's download URL stays valid
+const URL_TTL_SECS: i64 = 3600;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Uploads on disk: the bytes received in `<id>.part`, the rest in `<id>.json`
+pub struct UploadStore {
+    dir: PathBuf,
+    max_bytes: u64,
+    ttl: chrono::Duration,
+    /// Base URL workers reach the gateway at
+    public_url: Option<String>,
+    /// Signs the download URLs of workers
+    key: Vec<u8>,
+    /// Held while an upload's files change
+    locks: std::sync::Mutex<HashMap<String, Arc<Mutex<()>>>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UploadMeta {
+    size: u64,
+    file_name: Option<String>,
+    /// Hex SHA-256 the consumer declared, and of the content once complete
+    sha256: Option<String>,
+    /// Hex SHA-256 of the API key that created the upload
+    owner: String,
+    complete: bool,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUpload {
+    pub size: u64,
+    #[serde(default)]
+    pub file_name: Option<String>,
+    /// Hex SHA-256 the content is checked against once complete
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadStatus {
+    pub id: String,
+    pub size: u64,
+    /// Bytes received; the next chunk starts here
+    pub offset: u64,
+    pub complete: bool,
+    pub file_name: Option<String>,
+    pub sha256: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContentQuery {
+    pub expires: i64,
+    pub signature: String,
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    NotFound,
+    Invalid(String),
+    TooLarge(u64),
+    /// The chunk does not start at the offset the upload is at
+    Conflict(u64),
+    /// The content does not match the declared hash; the upload starts over
+    HashMismatch,
+    Forbidden,
+    Io(io::Error),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::NotFound => write!(f, "Upload not found"),
+            UploadError::Invalid(msg) => write!(f, "{}", msg),
+            UploadError::TooLarge(max) => write!(f, "Uploads are limited to {} bytes", max),
+            UploadError::Conflict(offset) => write!(f, "Upload is at offset {}", offset),
+            UploadError::HashMismatch => {
+                write!(
+                    f,
+                    "Content does not match sha256, upload again from offset 0"
+                )
+            }
+            UploadError::Forbidden => write!(f, "Invalid or expired signature"),
+            UploadError::Io(e) => write!(f, "Upload storage failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+impl From<io::Error> for UploadError {
+    fn from(e: io::Error) -> Self {
+        UploadError::Io(e)
+    }
+}
+
+impl IntoResponse for UploadError {
+    fn into_response(self) -> Response {
+        let (status, kind) = match &self {
+            UploadError::NotFound => (StatusCode::NOT_FOUND, "not_found"),
+            UploadError::Invalid(_) => (StatusCode::BAD_REQUEST, "invalid_request_error"),
+            UploadError::TooLarge(_) => (StatusCode::PAYLOAD_TOO_LARGE, "invalid_request_error"),
+            UploadError::Conflict(_) => (StatusCode::CONFLICT, "offset_mismatch"),
+            UploadError::HashMismatch => (StatusCode::UNPROCESSABLE_ENTITY, "hash_mismatch"),
+            UploadError::Forbidden => (StatusCode::FORBIDDEN, "forbidden"),
+            UploadError::Io(e) => {
+                warn!("Upload storage failed: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "server_error")
+            }
+        };
+        let body = json!({
+            "error": {
+                "message": self.to_string(),
+                "type": kind,
+                "code": status.as_u16()
+            }
+        });
+        let mut response = (status, Json(body)).into_response();
+        if let UploadError::Conflict(offset) = self {
+            response
+                .headers_mut()
+                .insert(UPLOAD_OFFSET_HEADER, HeaderValue::from(offset));
+        }
+        response
+    }
+}
+
+impl UploadStore {
+    /// Store in `dir`. URLs are signed with `secret` when set, so every
+    /// instance sharing the directory and secret serves them, and otherwise
+    /// with a key of this process.
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        max_bytes: u64,
+        ttl: Duration,
+        public_url: Option<String>,
+        secret: Option<&str>,
+    ) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let key = match secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => rand::random::<[u8; 32]>().to_vec(),
+        };
+        Ok(Self {
+            dir,
+            max_bytes,
+            ttl: chrono::Duration::seconds(ttl.as_secs() as i64),
+            public_url: public_url.map(|url| url.trim_end_matches('/').to_string()),
+            key,
+            locks: Default::default(),
+        })
+    }
+
+    pub async fn create(
+        &self,
+        owner: &str,
+        request: CreateUpload,
+    ) -> Result<UploadStatus, UploadError> {
+        if request.size == 0 {
+            return Err(UploadError::Invalid("size must be at least 1".to_string()));
+        }
+        if request.size > self.max_bytes {
+            return Err(UploadError::TooLarge(self.max_bytes));
+        }
+        let sha256 = match request.sha256 {
+            Some(hash) if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                Some(hash.to_ascii_lowercase())
+            }
+            Some(_) => {
+                return Err(UploadError::Invalid(
+                    "sha256 must be 64 hex digits".to_string(),
+                ))
+            }
+            None => None,
+        };
+        let id = Uuid::new_v4().to_string();
+        let meta = UploadMeta {
+            size: request.size,
+            file_name: request.file_name,
+            sha256,
+            owner: owner.to_string(),
+            complete: false,
+            expires_at: Utc::now() + self.ttl,
+        };
+        tokio::fs::File::create(self.data_path(&id)).await?;
+        self.save(&id, &meta).await?;
+        info!("Created upload {} of {} bytes", id, meta.size);
+        Ok(status(id, &meta, 0))
+    }
+
+    pub async fn status(&self, owner: &str, id: &str) -> Result<UploadStatus, UploadError> {
+        let meta = self.load_owned(owner, id).await?;
+        let offset = self.offset(id).await?;
+        Ok(status(id.to_string(), &meta, offset))
+    }
+
+    /// Write `chunk` at `offset`, which must be where the upload is at. The
+    /// chunk that completes the upload has the content hashed and checked.
+    pub async fn append(
+        &self,
+        owner: &str,
+        id: &str,
+        offset: u64,
+        chunk: Bytes,
+    ) -> Result<UploadStatus, UploadError> {
+        let lock = self.lock(id);
+        let _guard = lock.lock().await;
+        let mut meta = self.load_owned(owner, id).await?;
+        let current = self.offset(id).await?;
+        if meta.complete || offset != current {
+            return Err(UploadError::Conflict(current));
+        }
+        let end = current + chunk.len() as u64;
+        if end > meta.size {
+            return Err(UploadError::Invalid(format!(
+                "Chunk ends at {}, past the upload's size of {} bytes",
+                end, meta.size
+            )));
+        }
+
+        let path = self.data_path(id);
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .await?;
+        file.write_all(&chunk).await?;
+        file.sync_data().await?;
+
+        if end == meta.size {
+            let digest = tokio::task::spawn_blocking(move || hash_file(&path))
+                .await
+                .map_err(io::Error::other)??;
+            if meta.sha256.as_ref().is_some_and(|hash| *hash != digest) {
+                file.set_len(0).await?;
+                warn!("Upload {} does not match its sha256, starting over", id);
+                return Err(UploadError::HashMismatch);
+            }
+            meta.sha256 = Some(digest);
+            meta.complete = true;
+            self.save(id, &meta).await?;
+            info!("Upload {} complete", id);
+        }
+        Ok(status(id.to_string(), &meta, end))
+    }
+
+    pub async fn delete(&self, owner: &str, id: &str) -> Result<(), UploadError> {
+        let lock = self.lock(id);
+        let _guard = lock.lock().await;
+        self.load_owned(owner, id).await?;
+        self.remove(id).await;
+        Ok(())
+    }
+
+    /// Input of a job referencing the complete upload `id`, with a download
+    /// URL for the worker
+    pub async fn input(&self, id: &str) -> Result<JobInput, UploadError> {
+        let public_url = self.public_url.as_ref().ok_or_else(|| {
+            UploadError::Invalid("Jobs cannot take uploads, --public-url is not set".to_string())
+        })?;
+        let meta = self.load(id).await?;
+        let (true, Some(sha256)) = (meta.complete, meta.sha256) else {
+            return Err(UploadError::Invalid(format!(
+                "Upload {} is not complete",
+                id
+            )));
+        };
+        let expires = Utc::now().timestamp() + URL_TTL_SECS;
+        Ok(JobInput {
+            upload_id: id.to_string(),
+            url: format!(
+                "{}/v1/uploads/{}/content?expires={}&signature={}",
+                public_url,
+                id,
+                expires,
+                self.sign(id, expires)
+            ),
+            size: meta.size,
+            sha256,
+        })
+    }
+
+    /// Content of the complete upload `id`, for a URL from [`Self::input`]
+    pub async fn content(&self, id: &str, query: &ContentQuery) -> Result<Vec<u8>, UploadError> {
+        let signature = hex::decode(&query.signature).map_err(|_| UploadError::Forbidden)?;
+        let mut mac = self.mac();
+        mac.update(format!("{}:{}", id, query.expires).as_bytes());
+        if query.expires < Utc::now().timestamp() || mac.verify_slice(&signature).is_err() {
+            return Err(UploadError::Forbidden);
+        }
+        if !self.load(id).await?.complete {
+            return Err(UploadError::NotFound);
+        }
+        Ok(tokio::fs::read(self.data_path(id)).await?)
+    }
+
+    /// Delete expired uploads every ten minutes
+    pub async fn run_sweeper(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.sweep().await {
+                warn!("Failed to sweep uploads in {}: {}", self.dir.display(), e);
+            }
+        }
+    }
+
+    async fn sweep(&self) -> io::Result<()> {
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        let mut removed = 0;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let Some(id) = name.to_str().and_then(|name| name.strip_suffix(".json")) else {
+                continue;
+            };
+            let expired = match tokio::fs::read(entry.path()).await {
+                Ok(data) => serde_json::from_slice::<UploadMeta>(&data)
+                    .map(|meta| meta.expires_at <= Utc::now())
+                    .unwrap_or(true),
+                Err(_) => false,
+            };
+            if expired {
+                self.remove(id).await;
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            info!("Removed {} expired uploads", removed);
+        }
+        Ok(())
+    }
+
+    fn lock(&self, id: &str) -> Arc<Mutex<()>> {
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.entry(id.to_string()).or_default().clone()
+    }
+
+    fn meta_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn data_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.part", id))
+    }
+
+    async fn load(&self, id: &str) -> Result<UploadMeta, UploadError> {
+        // Ids are UUIDs, which also keeps paths inside the directory
+        if Uuid::parse_str(id).is_err() {
+            return Err(UploadError::NotFound);
+        }
+        let data = match tokio::fs::read(self.meta_path(id)).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(UploadError::NotFound),
+            Err(e) => return Err(e.into()),
+        };
+        let meta: UploadMeta = serde_json::from_slice(&data).map_err(io::Error::other)?;
+        if meta.expires_at <= Utc::now() {
+            return Err(UploadError::NotFound);
+        }
+        Ok(meta)
+    }
+
+    /// Uploads of other keys are not found
+    async fn load_owned(&self, owner: &str, id: &str) -> Result<UploadMeta, UploadError> {
+        let meta = self.load(id).await?;
+        if meta.owner != owner {
+            return Err(UploadError::NotFound);
+        }
+        Ok(meta)
+    }
+
+    async fn save(&self, id: &str, meta: &UploadMeta) -> io::Result<()> {
+        let path = self.meta_path(id);
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(meta)?).await?;
+        tokio::fs::rename(&tmp, &path).await
+    }
+
+    async fn offset(&self, id: &str) -> io::Result<u64> {
+        match tokio::fs::metadata(self.data_path(id)).await {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn remove(&self, id: &str) {
+        let _ = tokio::fs::remove_file(self.data_path(id)).await;
+        let _ = tokio::fs::remove_file(self.meta_path(id)).await;
+        if let Ok(mut locks) = self.locks.lock() {
+            locks.remove(id);
+        }
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size")
+    }
+
+    fn sign(&self, id: &str, expires: i64) -> String {
+        let mut mac = self.mac();
+        mac.update(format!("{}:{}", id, expires).as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+fn status(id: String, meta: &UploadMeta, offset: u64) -> UploadStatus {
+    UploadStatus {
+        id,
+        size: meta.size,
+        offset,
+        complete: meta.complete,
+        file_name: meta.file_name.clone(),
+        sha256: meta.sha256.clone(),
+        expires_at: meta.expires_at,
+    }
+}
+
+fn hash_file(path: &std::path::Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Uploads belong to the API key that created them
+fn owner(auth: &AuthContext) -> String {
+    hex::encode(Sha256::digest(auth.token.as_bytes()))
+}
+
+fn respond(result: Result<UploadStatus, UploadError>, code: StatusCode) -> Response {
+    match result {
+        Ok(status) => {
+            let offset = HeaderValue::from(status.offset);
+            let mut response = (code, Json(status)).into_response();
+            response.headers_mut().insert(UPLOAD_OFFSET_HEADER, offset);
+            response
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+/// `POST /v1/uploads`
+pub async fn create_upload(
+    State(gateway): State<Arc<InferenceGateway>>,
+    Extension(auth): Extension<AuthContext>,
+    Json(request): Json<CreateUpload>,
+) -> Response {
+    respond(
+        gateway.uploads.create(&owner(&auth), request).await,
+        StatusCode::CREATED,
+    )
+}
+
+/// `GET` or `HEAD /v1/uploads/{id}`, the offset to resume at
+pub async fn get_upload(
+    State(gateway): State<Arc<InferenceGateway>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<String>,
+) -> Response {
+    respond(
+        gateway.uploads.status(&owner(&auth), &id).await,
+        StatusCode::OK,
+    )
+}
+
+/// `PATCH /v1/uploads/{id}` with a chunk starting at `Upload-Offset`
+pub async fn append_upload(
+    State(gateway): State<Arc<InferenceGateway>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    chunk: Bytes,
+) -> Response {
+    let Some(offset) = headers
+        .get(UPLOAD_OFFSET_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+    else {
+        return UploadError::Invalid("Missing or invalid Upload-Offset header".to_string())
+            .into_response();
+    };
+    respond(
+        gateway
+            .uploads
+            .append(&owner(&auth), &id, offset, chunk)
+            .await,
+        StatusCode::OK,
+    )
+}
+
+/// `DELETE /v1/uploads/{id}`
+pub async fn delete_upload(
+    State(gateway): State<Arc<InferenceGateway>>,
+    Extension(auth): Extension<AuthContext>,
+    Path(id): Path<String>,
+) -> Response {
+    match gateway.uploads.delete(&owner(&auth), &id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// `GET /v1/uploads/{id}/content`, authenticated by the URL's signature
+pub async fn upload_content(
+    State(gateway): State<Arc<InferenceGateway>>,
+    Path(id): Path<String>,
+    Query(query): Query<ContentQuery>,
+) -> Response {
+    match gateway.uploads.content(&id, &query).await {
+        Ok(content) => (
+            [(header::CONTENT_TYPE, "application/octet-stream")],
+            content,
+        )
+            .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[tokio::test]
+async fn test_upload_store() {
+    let dir = std::env::temp_dir().join(format!("gpuf-uploads-{}", Uuid::new_v4()));
+    let store = UploadStore::new(
+        &dir,
+        16,
+        Duration::from_secs(60),
+        Some("https://gpuf.example.com/".to_string()),
+        Some("secret"),
+    )
+    .unwrap();
+    let create = |size, sha256: Option<&str>| CreateUpload {
+        size,
+        file_name: Some("notes.txt".to_string()),
+        sha256: sha256.map(str::to_string),
+    };
+    assert!(matches!(
+        store.create("a", create(17, None)).await,
+        Err(UploadError::TooLarge(16))
+    ));
+
+    let upload = store.create("a", create(11, None)).await.unwrap();
+    let id = upload.id.clone();
+    assert_eq!(upload.offset, 0);
+    assert!(matches!(
+        store.status("b", &id).await,
+        Err(UploadError::NotFound)
+    ));
+    let status = store
+        .append("a", &id, 0, Bytes::from_static(b"hello"))
+        .await
+        .unwrap();
+    assert_eq!((status.offset, status.complete), (5, false));
+    // A chunk sent again after a lost response is refused with the offset
+    assert!(matches!(
+        store
+            .append("a", &id, 0, Bytes::from_static(b"hello"))
+            .await,
+        Err(UploadError::Conflict(5))
+    ));
+    assert!(matches!(
+        store.input(&id).await,
+        Err(UploadError::Invalid(_))
+    ));
+    let status = store
+        .append("a", &id, 5, Bytes::from_static(b" world"))
+        .await
+        .unwrap();
+    assert!(status.complete);
+    assert_eq!(
+        status.sha256.as_deref(),
+        Some("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9")
+    );
+
+    let input = store.input(&id).await.unwrap();
+    assert!(input.url.starts_with(&format!(
+        "https://gpuf.example.com/v1/uploads/{}/content?expires=",
+        id
+    )));
+    let query = |url: &str| {
+        let query = url.split_once('?').unwrap().1;
+        let (expires, signature) = query.split_once('&').unwrap();
+        ContentQuery {
+            expires: expires.trim_start_matches("expires=").parse().unwrap(),
+            signature: signature.trim_start_matches("signature=").to_string(),
+        }
+    };
+    let mut query = query(&input.url);
+    assert_eq!(store.content(&id, &query).await.unwrap(), b"hello world");
+    query.expires += 1;
+    assert!(matches!(
+        store.content(&id, &query).await,
+        Err(UploadError::Forbidden)
+    ));
+
+    // A declared hash the content misses starts the upload over
+    let upload = store
+        .create("a", create(2, Some(&"0".repeat(64))))
+        .await
+        .unwrap();
+    assert!(matches!(
+        store
+            .append("a", &upload.id, 0, Bytes::from_static(b"hi"))
+            .await,
+        Err(UploadError::HashMismatch)
+    ));
+    assert_eq!(store.status("a", &upload.id).await.unwrap().offset, 0);
+
+    store.delete("a", &id).await.unwrap();
+    assert!(matches!(
+        store.status("a", &id).await,
+        Err(UploadError::NotFound)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -8,11 +8,11 @@ mod util;
 mod xdp;
 
 use anyhow::Result;
-use clap::Parser;
+use api_server::auth::{ApiAuth, JwtConfig};
 use std::sync::Arc;
-use tokio::net::TcpListener;
 #[cfg(target_os = "linux")]
 use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info};
 
 #[cfg(debug_assertions)]
@@ -25,42 +25,148 @@ async fn main() -> Result<()> {
     #[cfg(debug_assertions)]
     let profiler = dhat::Profiler::new_heap();
 
-    //parse args
-    let args = util::cmd::Args::parse();
-    util::init_logging();
+    //parse args, then fill in the environment and config file
+    let args = util::config::load_args()?;
+    let log_level = util::init_logging(args.log_format, args.log_level.as_deref())?;
 
     //bind port
-    let control_listener = TcpListener::bind(format!("0.0.0.0:{}", args.control_port)).await?;
-    let proxy_listener = TcpListener::bind(format!("0.0.0.0:{}", args.proxy_port)).await?;
-    let public_listener = TcpListener::bind(format!("0.0.0.0:{}", args.public_port)).await?;
+    let listen_addr = util::config::listen_addr(&args);
+    let control_listener = listen_addr.bind(args.control_port)?;
+    let proxy_listener = listen_addr.bind(args.proxy_port)?;
+    let public_listener = listen_addr.bind(args.public_port)?;
     info!(
-        "gpuf-server listening on ports: Control={}, Proxy={}, Public={}, API={}",
-        args.control_port, args.proxy_port, args.public_port, args.api_port
+        "gpuf-server listening on {}, ports: Control={}, Proxy={}, Public={}, API={}",
+        args.bind_addr, args.control_port, args.proxy_port, args.public_port, args.api_port
     );
-    // Create a channel to signal when to drop ServerState
-    let (shutdown_tx, mut shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    // Cancelled on SIGTERM or SIGINT to stop accepting and drain
+    let shutdown = CancellationToken::new();
 
     // Spawn a task to handle signals
     let server_state = Arc::new(handle::new_server_state(&args).await?);
     let server_state1 = Arc::clone(&server_state);
     let server_state2 = Arc::clone(&server_state);
     let server_state3 = Arc::clone(&server_state);
-    let _server_state4 = Arc::clone(&server_state);
+    let server_state4 = Arc::clone(&server_state);
+    let server_state5 = Arc::clone(&server_state);
 
     // Start inference gateway on port 8081
     let inference_gateway = Arc::new(inference::InferenceGateway::new(
         server_state.inference_scheduler.clone(),
         server_state.db_pool.clone(),
         server_state.producer.clone(),
+        server_state.rate_limiter.clone(),
+        server_state.uploads.clone(),
+        server_state.response_cache.clone(),
+        server_state.request_limits.clone(),
     ));
-    let inference_gateway_task = tokio::spawn(async move {
+    tokio::spawn(server_state.uploads.clone().run_sweeper());
+    let gateway_shutdown = shutdown.clone();
+    let mut inference_gateway_task = tokio::spawn(async move {
         info!("Starting Inference Gateway on port 8081...");
-        if let Err(e) = inference_gateway.run(8081).await {
+        if let Err(e) = inference_gateway
+            .run(listen_addr, 8081, gateway_shutdown)
+            .await
+        {
             error!("Inference gateway failed: {}", e);
         }
     });
     info!("Inference Gateway spawned and will start on port 8081");
 
+    // Admin API is only exposed when an admin key is configured
+    if let Some(admin_key) = args.admin_key.clone() {
+        let jwt = args.jwt_issuer.clone().map(|issuer| JwtConfig {
+            issuer,
+            jwks_url: args.jwt_jwks_url.clone(),
+            audience: args.jwt_audience.clone(),
+            user_id_claim: args.jwt_user_claim.clone(),
+        });
+        let auth = ApiAuth::new(Some(admin_key), jwt);
+        tokio::spawn(async move {
+            if let Err(e) = server_state4.handle_admin_api(auth).await {
+                error!("Admin API failed: {}", e);
+            }
+        });
+    } else {
+        info!("Admin API disabled, set --admin-key to enable it");
+    }
+
+    tokio::spawn(Arc::clone(&server_state).watch_presence());
+
+    // P2P peers learn their public address here; without it they relay
+    if args.stun_port != 0 {
+        match listen_addr.bind_udp(args.stun_port) {
+            Ok(socket) => {
+                tokio::spawn(util::stun::run(socket));
+            }
+            Err(e) => error!("Failed to bind STUN port {}: {}", args.stun_port, e),
+        }
+    }
+
+    // Rate limits, routing thresholds and the log level follow SIGHUP
+    let reloadable = util::config::Reloadable {
+        rate_limiter: server_state.rate_limiter.clone(),
+        scheduler: server_state.inference_scheduler.clone(),
+        log_level,
+    };
+    tokio::spawn(reloadable.run());
+
+    if let Some(forwarder) = server_state.forwarder.clone() {
+        let forward_listener = listen_addr.bind(args.forward_port)?;
+        let interval = std::time::Duration::from_secs((args.presence_ttl_secs / 3).max(1));
+        tokio::spawn(forwarder.advertise(interval));
+        let forward_state = Arc::clone(&server_state);
+        tokio::spawn(async move {
+            if let Err(e) = forward_state.handle_forward_connections(forward_listener).await {
+                error!("Forward listener failed: {}", e);
+            }
+        });
+        info!("Forwarding requests between instances on port {}", args.forward_port);
+    }
+
+    if args.spike_window_secs > 0 {
+        tokio::spawn(Arc::clone(&server_state).watch_request_rate(
+            std::time::Duration::from_secs(args.spike_window_secs),
+            db::dashboard_events::SpikeDetector::new(args.spike_factor, args.spike_min_requests),
+        ));
+    }
+
+    if args.challenge_interval_secs > 0 {
+        tokio::spawn(server_state5.run_challenges(
+            std::time::Duration::from_secs(args.challenge_interval_secs),
+            std::time::Duration::from_secs(args.challenge_deadline_secs),
+            args.challenge_difficulty,
+        ));
+    } else {
+        info!("Liveness challenges disabled, set --challenge-interval-secs to enable them");
+    }
+
+    if args.canary_interval_secs > 0 {
+        tokio::spawn(inference::canary::run_canaries(
+            server_state.inference_scheduler.clone(),
+            server_state.active_clients.clone(),
+            server_state.db_pool.clone(),
+            std::time::Duration::from_secs(args.canary_interval_secs),
+            std::time::Duration::from_secs(args.canary_timeout_secs),
+        ));
+    } else {
+        info!("Canary checks disabled, set --canary-interval-secs to enable them");
+    }
+
+    if args.notify_interval_secs > 0 {
+        let notifier = util::notify::Notifier::new(
+            args.notify_email_url.clone(),
+            args.notify_push_url.clone(),
+        );
+        tokio::spawn(notifier.run(
+            server_state.db_pool.clone(),
+            std::time::Duration::from_secs(args.notify_interval_secs),
+            std::time::Duration::from_secs(args.notify_digest_secs),
+        ));
+    } else {
+        info!("Notification delivery disabled, set --notify-interval-secs to enable it");
+    }
+
+    let signal_shutdown = shutdown.clone();
     tokio::spawn(async move {
         #[cfg(target_os = "linux")]
         {
@@ -81,12 +187,15 @@ async fn main() -> Result<()> {
 
         #[cfg(not(target_os = "linux"))]
         {
-            // On Windows, we'll use Ctrl-C handling through tokio's default signal handling
-            info!("Running on Windows - signal handling through default mechanisms");
+            // On Windows, Ctrl-C stands in for SIGTERM
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                error!("Failed to listen for Ctrl-C: {}", e);
+                return;
+            }
+            info!("Received Ctrl-C, shutting down gracefully...");
         }
 
-        // Send shutdown signal
-        let _ = shutdown_tx.send(());
+        signal_shutdown.cancel();
     });
     //init server state
     let server_loop = async {
@@ -94,21 +203,33 @@ async fn main() -> Result<()> {
             res = server_state1.handle_client_connections(control_listener) => res,
             res = server_state2.handle_proxy_connections(proxy_listener) => res,
             res = server_state3.handle_public_connections(public_listener) => res,
-            _res = inference_gateway_task => {
+            _res = &mut inference_gateway_task => {
                 info!("Inference gateway task completed");
                 Ok(())
             }
-            _ = &mut shutdown_rx => {
+            _ = shutdown.cancelled() => {
                 info!("Shutdown signal received, stopping server...");
                 Ok(())
             }
         }
     };
 
+    // Leaving the loop drops the listeners, so nothing new is accepted while
+    // the requests in flight drain
     let result = server_loop.await;
+    if shutdown.is_cancelled() {
+        server_state
+            .shut_down(
+                inference_gateway_task,
+                std::time::Duration::from_secs(args.drain_timeout_secs),
+                args.shutdown_reconnect_to.clone(),
+            )
+            .await;
+    }
 
     info!("Dropping ServerState...");
     drop(server_state);
+    common::trace::shutdown_tracing();
 
     #[cfg(debug_assertions)]
     drop(profiler);
@@ -1,7 +1,62 @@
-use clap::Parser;
+use crate::util::net::IpRange;
+use clap::{Parser, ValueEnum};
+use std::net::IpAddr;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    #[clap(name = "compact")]
+    Compact,
+    #[clap(name = "json")]
+    Json,
+}
+
+/// Which pending workers are approved when they log in
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApprovalPolicy {
+    /// Every pending worker
+    #[clap(name = "auto")]
+    Auto,
+    /// Workers that present one of the enrollment tokens
+    #[clap(name = "token")]
+    Token,
+    /// None, operators approve through the admin API
+    #[clap(name = "manual")]
+    Manual,
+}
+
+/// Where the server puts per-request usage records
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageSink {
+    /// Written to usage_records in batches by the server
+    #[clap(name = "database")]
+    Database,
+    /// Published to the inference-usage topic for the heartbeat consumer
+    #[clap(name = "kafka")]
+    Kafka,
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
+    /// TOML or YAML file with the settings below, keyed by their long name
+    /// in snake_case. Flags and environment variables take precedence.
+    #[arg(long, env = "GPUF_CONFIG")]
+    pub config: Option<String>,
+
+    /// Directory of secret files named like the settings, e.g. `/run/secrets`
+    /// with `database_url`, used for secrets left at their default
+    #[arg(long, env = "GPUF_SECRETS_DIR")]
+    pub secrets_dir: Option<String>,
+
+    /// Address all listeners bind to; `::` accepts IPv4 and IPv6 clients
+    #[arg(long, env = "GPUF_BIND_ADDR", default_value = "::")]
+    pub bind_addr: IpAddr,
+
+    /// Accept only IPv6 clients on `::` instead of both
+    #[arg(long, default_value_t = false)]
+    pub ipv6_only: bool,
+
     #[arg(long, default_value_t = 17000)]
     pub control_port: u16,
 
@@ -14,6 +69,40 @@ pub struct Args {
     #[arg(long, default_value_t = 18081)]
     pub api_port: u16,
 
+    #[arg(long, default_value_t = 18082)]
+    pub admin_port: u16,
+
+    /// Key for the admin API, which is only started when set
+    #[arg(long, env = "GPUF_ADMIN_KEY")]
+    pub admin_key: Option<String>,
+
+    /// File holding the admin key, instead of `--admin-key`
+    #[arg(long)]
+    pub admin_key_file: Option<String>,
+
+    /// OIDC issuer whose JWTs the admin API also accepts, for users with a
+    /// staff role
+    #[arg(long, env = "GPUF_JWT_ISSUER")]
+    pub jwt_issuer: Option<String>,
+
+    /// JWKS endpoint, discovered from the issuer when not set
+    #[arg(long, env = "GPUF_JWT_JWKS_URL")]
+    pub jwt_jwks_url: Option<String>,
+
+    /// Expected `aud` claim
+    #[arg(long, env = "GPUF_JWT_AUDIENCE")]
+    pub jwt_audience: Option<String>,
+
+    /// Load balancers in front of the admin API, addresses or CIDR ranges,
+    /// comma-separated. The audit log only takes the client IP from
+    /// `X-Forwarded-For` on requests they pass on.
+    #[arg(long, env = "GPUF_TRUSTED_PROXIES", value_delimiter = ',')]
+    pub trusted_proxies: Vec<IpRange>,
+
+    /// Claim mapped to user_id
+    #[arg(long, default_value = "sub", env = "GPUF_JWT_USER_CLAIM")]
+    pub jwt_user_claim: String,
+
     /// Print client monitoring data
     #[arg(long)]
     pub monitor: bool,
@@ -22,6 +111,10 @@ pub struct Args {
     #[arg(long, default_value = "abc123")]
     pub api_key: String,
 
+    /// File holding the API key, instead of `--api-key`
+    #[arg(long)]
+    pub api_key_file: Option<String>,
+
     /// Database URL for PostgreSQL connection
     #[arg(
         long,
@@ -29,6 +122,10 @@ pub struct Args {
     )]
     pub database_url: String,
 
+    /// File holding the database URL, instead of `--database-url`
+    #[arg(long)]
+    pub database_url_file: Option<String>,
+
     /// Path to the certificate chain file
     #[arg(long, default_value = "cert.pem")]
     pub proxy_cert_chain_path: String,
@@ -37,10 +134,254 @@ pub struct Args {
     #[arg(long, default_value = "key.pem")]
     pub proxy_private_key_path: String,
 
+    /// Domains of the proxy certificate obtained over ACME, comma-separated;
+    /// replaces the certificate files when set. The ACME server checks the
+    /// domains on port 443, which must reach the proxy port.
+    #[arg(long, env = "GPUF_ACME_DOMAINS", value_delimiter = ',')]
+    pub acme_domains: Vec<String>,
+
+    /// Emails the ACME directory sends expiry notices to, comma-separated
+    #[arg(long, env = "GPUF_ACME_CONTACT", value_delimiter = ',')]
+    pub acme_contact: Vec<String>,
+
+    /// Directory keeping the ACME account and certificates across restarts
+    #[arg(long, default_value = "acme")]
+    pub acme_cache_dir: String,
+
+    /// Use the Let's Encrypt staging directory, for testing
+    #[arg(long, default_value_t = false)]
+    pub acme_staging: bool,
+
     /// Redis URL for caching
     #[arg(long, default_value = "redis://127.0.0.1:6379")]
     pub redis_url: String,
 
+    /// File holding the Redis URL, instead of `--redis-url`
+    #[arg(long)]
+    pub redis_url_file: Option<String>,
+
     #[arg(long, default_value = "localhost:9092")]
     pub bootstrap_server: String,
+
+    /// Which pending workers are approved when they log in
+    #[arg(long, default_value = "auto")]
+    pub approval_policy: ApprovalPolicy,
+
+    /// Enrollment tokens that approve a pending worker under the token policy
+    #[arg(long, env = "GPUF_ENROLLMENT_TOKENS", value_delimiter = ',')]
+    pub enrollment_tokens: Vec<String>,
+
+    /// Seconds between liveness challenges to each worker, 0 disables them
+    #[arg(long, default_value_t = 0)]
+    pub challenge_interval_secs: u64,
+
+    /// Seconds a worker has to answer a liveness challenge
+    #[arg(long, default_value_t = 10)]
+    pub challenge_deadline_secs: u64,
+
+    /// Leading zero bits a liveness challenge's hash must have, each bit
+    /// doubling the worker's work
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u8).range(1..=32))]
+    pub challenge_difficulty: u8,
+
+    /// Seconds between canary prompts to each worker, 0 disables them
+    #[arg(long, default_value_t = 0)]
+    pub canary_interval_secs: u64,
+
+    /// Seconds a worker has to answer a canary prompt
+    #[arg(long, default_value_t = 120)]
+    pub canary_timeout_secs: u64,
+
+    /// Id of this instance in the shared presence records, random when unset
+    #[arg(long, env = "GPUF_INSTANCE_ID")]
+    pub instance_id: Option<String>,
+
+    /// UDP port answering STUN binding requests of P2P peers, 0 disables it
+    #[arg(long, default_value_t = 3478)]
+    pub stun_port: u16,
+
+    /// Host name or address peers send STUN requests to, by default the
+    /// address the requesting client reached the control port at
+    #[arg(long, env = "GPUF_STUN_HOST")]
+    pub stun_host: Option<String>,
+
+    /// Seconds a worker stays online in Redis without a heartbeat
+    #[arg(long, default_value_t = 300)]
+    pub presence_ttl_secs: u64,
+
+    /// Port other instances forward requests for workers attached here to,
+    /// 0 disables forwarding
+    #[arg(long, default_value_t = 0)]
+    pub forward_port: u16,
+
+    /// Address other instances reach the forward port at, e.g. 10.0.0.5:17002
+    #[arg(long, env = "GPUF_FORWARD_ADDR")]
+    pub forward_addr: Option<String>,
+
+    /// Secret shared by all instances to authenticate forwarded requests
+    #[arg(long, env = "GPUF_FORWARD_SECRET")]
+    pub forward_secret: Option<String>,
+
+    /// File holding the forward secret, instead of `--forward-secret`
+    #[arg(long)]
+    pub forward_secret_file: Option<String>,
+
+    /// Seconds of public connections compared for request spike events, 0
+    /// disables them
+    #[arg(long, default_value_t = 60)]
+    pub spike_window_secs: u64,
+
+    /// A window is a spike above this multiple of the recent average
+    #[arg(long, default_value_t = 3.0)]
+    pub spike_factor: f64,
+
+    /// Windows with fewer public connections are never spikes
+    #[arg(long, default_value_t = 100)]
+    pub spike_min_requests: u64,
+
+    /// Download results a canary model needs before it is promoted or rolled back
+    #[arg(long, default_value_t = 20)]
+    pub rollout_min_samples: u32,
+
+    /// Share of successful downloads, 0 to 1, a canary model needs to be promoted
+    #[arg(long, default_value_t = 0.9)]
+    pub rollout_min_success_rate: f64,
+
+    /// Seconds between notification delivery rounds, 0 disables delivery
+    #[arg(long, default_value_t = 30)]
+    pub notify_interval_secs: u64,
+
+    /// Seconds digest notifications are collected before they are sent
+    #[arg(long, default_value_t = 86400)]
+    pub notify_digest_secs: u64,
+
+    /// Relay that sends notification emails, gets `{to, subject, text}`
+    #[arg(long, env = "GPUF_NOTIFY_EMAIL_URL")]
+    pub notify_email_url: Option<String>,
+
+    /// Relay that sends push notifications, gets `{token, title, body}`
+    #[arg(long, env = "GPUF_NOTIFY_PUSH_URL")]
+    pub notify_push_url: Option<String>,
+
+    /// CSV of `start_ip,end_ip,country_code` ranges to look up the country
+    /// of workers, e.g. the DB-IP lite country database
+    #[arg(long, env = "GPUF_GEOIP_DB")]
+    pub geoip_db: Option<String>,
+
+    /// Do not apply pending schema migrations at startup
+    #[arg(long)]
+    pub skip_migrations: bool,
+
+    /// Where per-request usage records go
+    #[arg(long, default_value = "database")]
+    pub usage_sink: UsageSink,
+
+    /// Requests per second from one client address on the public and
+    /// inference ports, 0 disables the limit. Reloaded on SIGHUP.
+    #[arg(long, env = "GPUF_IP_RATE_LIMIT_RPS", default_value_t = 0)]
+    pub ip_rate_limit_rps: u32,
+
+    /// Workers scoring lower on canary prompts get no inference while others
+    /// can take it. Reloaded on SIGHUP.
+    #[arg(long, default_value_t = 0.5)]
+    pub min_quality_score: f32,
+
+    /// Requests above this many bytes are kept away from workers on slow
+    /// links. Reloaded on SIGHUP.
+    #[arg(long, default_value_t = 256 * 1024)]
+    pub large_payload_bytes: usize,
+
+    /// Longest estimated transfer time of a large request to a worker.
+    /// Reloaded on SIGHUP.
+    #[arg(long, default_value_t = 1.0)]
+    pub max_payload_transfer_secs: f32,
+
+    /// Directory uploads of job inputs are kept in until they expire
+    #[arg(long, env = "GPUF_UPLOAD_DIR", default_value = "uploads")]
+    pub upload_dir: String,
+
+    /// Largest upload in bytes
+    #[arg(long, default_value_t = 64 * 1024 * 1024)]
+    pub max_upload_bytes: u64,
+
+    /// Seconds an upload is kept after it was created
+    #[arg(long, default_value_t = 24 * 3600)]
+    pub upload_ttl_secs: u64,
+
+    /// Base URL workers reach the inference gateway at, e.g.
+    /// `https://gpuf.example.com:8081`. Jobs take uploads as inputs only
+    /// when it is set.
+    #[arg(long, env = "GPUF_PUBLIC_URL")]
+    pub public_url: Option<String>,
+
+    /// Seconds cached completions are kept, 0 disables the response cache
+    #[arg(long, default_value_t = 0)]
+    pub response_cache_ttl_secs: u64,
+
+    /// Largest completion request body in bytes, 0 for no limit
+    #[arg(long, default_value_t = 2 * 1024 * 1024)]
+    pub max_request_bytes: usize,
+
+    /// Longest prompt, or sum of chat message contents, in bytes; 0 for no
+    /// limit
+    #[arg(long, default_value_t = 1024 * 1024)]
+    pub max_prompt_bytes: usize,
+
+    /// Highest max_tokens a completion request may ask for, which also caps
+    /// the default; 0 for no ceiling
+    #[arg(long, default_value_t = 0)]
+    pub max_tokens_limit: u32,
+
+    /// Streamed completions one API key may have open at once, 0 for no
+    /// limit
+    #[arg(long, default_value_t = 0)]
+    pub max_streams_per_key: u32,
+
+    /// Seconds a completion request waits for a worker to become available;
+    /// 0 fails it at once when none is
+    #[arg(long, default_value_t = 0)]
+    pub queue_timeout_secs: u64,
+
+    /// Seconds a task waits for its worker's connection to be free, 0 for no
+    /// limit
+    #[arg(long, default_value_t = 5)]
+    pub worker_connect_timeout_secs: u64,
+
+    /// Seconds a streamed completion waits for its first token, 0 for no
+    /// limit
+    #[arg(long, default_value_t = 120)]
+    pub first_token_timeout_secs: u64,
+
+    /// Seconds a completion may take overall, passed to the worker as the
+    /// task's deadline; 0 for no limit
+    #[arg(long, env = "GPUF_INFERENCE_TIMEOUT_SECS", default_value_t = 300)]
+    pub request_timeout_secs: u64,
+
+    /// Seconds a streamed completion may go without a token, 0 for no limit
+    #[arg(long, default_value_t = 60)]
+    pub stream_idle_timeout_secs: u64,
+
+    /// Seconds a shutdown waits for requests in flight to finish before the
+    /// server exits anyway
+    #[arg(long, default_value_t = 30)]
+    pub drain_timeout_secs: u64,
+
+    /// Host workers are told to reconnect to on shutdown, e.g. the instance
+    /// taking over in a rolling deploy; unset, they retry this one
+    #[arg(long, env = "GPUF_SHUTDOWN_RECONNECT_TO")]
+    pub shutdown_reconnect_to: Option<String>,
+
+    /// JSON file with the gpuf-c release offered to workers at login, as
+    /// posted to `/admin/workers/release`
+    #[arg(long)]
+    pub worker_release_file: Option<String>,
+
+    /// Log level or tracing filter, e.g. `info` or `gpuf_s=debug,sqlx=warn`;
+    /// debug in debug builds and info otherwise when unset. Reloaded on SIGHUP.
+    #[arg(long, env = "GPUF_LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    /// Log output format: compact for humans, json for log collectors
+    #[arg(long, env = "GPUF_LOG_FORMAT", default_value = "compact")]
+    pub log_format: LogFormat,
 }
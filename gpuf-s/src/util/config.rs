@@ -0,0 +1,315 @@
+use crate::inference::{InferenceScheduler, RoutingPolicy, Timeouts};
+use crate::util::cmd::Args;
+use crate::util::net::ListenAddr;
+use crate::util::rate_limit::RateLimiter;
+use crate::util::{log_filter, LogLevelHandle};
+use anyhow::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches};
+use common::secrets::load_secret;
+use serde_json::{Map, Value};
+use std::ffi::OsString;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(target_os = "linux")]
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
+
+/// Options without an `env` of their own are also read from this prefix
+/// followed by their name in upper case, e.g. `GPUF_CONTROL_PORT`
+const ENV_PREFIX: &str = "GPUF_";
+
+/// Parse the command line and fill the options it leaves at their default
+/// from `GPUF_*` environment variables, then from the `--config` file
+pub fn load_args() -> Result<Args> {
+    let cli: Vec<OsString> = std::env::args_os().collect();
+    let matches = Args::command().get_matches_from(&cli);
+    let settings = match matches.get_one::<String>("config") {
+        Some(path) => read_config(path)?,
+        None => Map::new(),
+    };
+    merge_args(cli, &matches, &settings, |name| std::env::var(name).ok())
+}
+
+/// Settings of a TOML file, or of a YAML file when it ends in `.yaml` or `.yml`
+pub fn read_config(path: &str) -> Result<Map<String, Value>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path))?;
+    let yaml = Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext == "yaml" || ext == "yml");
+    let settings: Value = if yaml {
+        serde_yaml::from_str(&text)
+            .with_context(|| format!("Failed to parse config file {}", path))?
+    } else {
+        toml::from_str(&text).with_context(|| format!("Failed to parse config file {}", path))?
+    };
+    match settings {
+        Value::Object(settings) => Ok(settings),
+        Value::Null => Ok(Map::new()),
+        _ => bail!("Config file {} is not a table of settings", path),
+    }
+}
+
+fn merge_args(
+    cli: Vec<OsString>,
+    matches: &ArgMatches,
+    settings: &Map<String, Value>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Args> {
+    let command = Args::command();
+    let setting = |id: &str| {
+        settings
+            .get(id)
+            .or_else(|| settings.get(&id.replace('_', "-")))
+    };
+    for key in settings.keys() {
+        let id = key.replace('-', "_");
+        if id == "config"
+            || !command
+                .get_arguments()
+                .any(|arg| arg.get_id() == id.as_str())
+        {
+            bail!("Unknown setting {} in config file", key);
+        }
+    }
+
+    let mut extra = Vec::new();
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        let given = !matches!(
+            matches.value_source(id),
+            None | Some(ValueSource::DefaultValue)
+        );
+        if id == "config" || given {
+            continue;
+        }
+        let value = match (
+            env(&format!("{}{}", ENV_PREFIX, id.to_ascii_uppercase())),
+            setting(id),
+        ) {
+            (Some(value), _) => value,
+            (None, Some(value)) => {
+                setting_value(value).with_context(|| format!("Invalid setting {}", id))?
+            }
+            (None, None) => continue,
+        };
+        match arg.get_action() {
+            ArgAction::SetTrue => {
+                if flag_value(&value).with_context(|| format!("Invalid setting {}", id))? {
+                    extra.push(OsString::from(format!("--{}", long)));
+                }
+            }
+            action if action.takes_values() => {
+                extra.push(OsString::from(format!("--{}={}", long, value)));
+            }
+            _ => {}
+        }
+    }
+    let matches = Args::command().try_get_matches_from(cli.into_iter().chain(extra))?;
+    let mut args = Args::from_arg_matches(&matches)?;
+    load_secrets(&mut args, &matches)?;
+    Ok(args)
+}
+
+/// Replace credentials with the content of their `--*-file`, or of the file
+/// named like them in `--secrets-dir`, and resolve `env:NAME` values
+fn load_secrets(args: &mut Args, matches: &ArgMatches) -> Result<()> {
+    let dir = args.secrets_dir.clone();
+    let secret = |id: &str, value: Option<&str>, file: Option<&str>| {
+        let defaulted = matches!(
+            matches.value_source(id),
+            None | Some(ValueSource::DefaultValue)
+        );
+        load_secret(id, value, defaulted, file, dir.as_deref())
+            .with_context(|| format!("Failed to load {}", id))
+    };
+
+    if let Some(database_url) = secret(
+        "database_url",
+        Some(&args.database_url),
+        args.database_url_file.as_deref(),
+    )? {
+        args.database_url = database_url;
+    }
+    if let Some(redis_url) = secret(
+        "redis_url",
+        Some(&args.redis_url),
+        args.redis_url_file.as_deref(),
+    )? {
+        args.redis_url = redis_url;
+    }
+    if let Some(api_key) = secret("api_key", Some(&args.api_key), args.api_key_file.as_deref())? {
+        args.api_key = api_key;
+    }
+    args.admin_key = secret(
+        "admin_key",
+        args.admin_key.as_deref(),
+        args.admin_key_file.as_deref(),
+    )?;
+    args.forward_secret = secret(
+        "forward_secret",
+        args.forward_secret.as_deref(),
+        args.forward_secret_file.as_deref(),
+    )?;
+    Ok(())
+}
+
+/// Command line form of a setting, lists comma-separated
+fn setting_value(value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Array(items) => Ok(items
+            .iter()
+            .map(setting_value)
+            .collect::<Result<Vec<_>>>()?
+            .join(",")),
+        _ => bail!("expected a string, number, boolean or list"),
+    }
+}
+
+fn flag_value(value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Ok(true),
+        "false" | "0" | "no" | "off" | "" => Ok(false),
+        _ => bail!("expected true or false, got {}", value),
+    }
+}
+
+pub fn listen_addr(args: &Args) -> ListenAddr {
+    ListenAddr {
+        ip: args.bind_addr,
+        ipv6_only: args.ipv6_only,
+    }
+}
+
+pub fn routing_policy(args: &Args) -> RoutingPolicy {
+    RoutingPolicy {
+        large_payload_bytes: args.large_payload_bytes,
+        max_payload_transfer_secs: args.max_payload_transfer_secs,
+        min_quality_score: args.min_quality_score,
+    }
+}
+
+pub fn timeouts(args: &Args) -> Timeouts {
+    Timeouts {
+        queue: Duration::from_secs(args.queue_timeout_secs),
+        connect: Duration::from_secs(args.worker_connect_timeout_secs),
+        first_token: Duration::from_secs(args.first_token_timeout_secs),
+        total: Duration::from_secs(args.request_timeout_secs),
+        idle: Duration::from_secs(args.stream_idle_timeout_secs),
+    }
+}
+
+/// Settings that change without a restart
+pub struct Reloadable {
+    pub rate_limiter: Arc<RateLimiter>,
+    pub scheduler: Arc<InferenceScheduler>,
+    pub log_level: LogLevelHandle,
+}
+
+impl Reloadable {
+    /// Load the settings again and apply the per-IP rate limit, routing
+    /// thresholds, timeouts and log level. Nothing changes when any of them
+    /// is invalid.
+    pub fn reload(&self) -> Result<()> {
+        let args = load_args()?;
+        let filter = log_filter(args.log_level.as_deref())?;
+        let routing = routing_policy(&args);
+        let timeouts = timeouts(&args);
+        self.log_level.reload(filter)?;
+        self.rate_limiter.set_ip_rps(args.ip_rate_limit_rps);
+        self.scheduler.set_routing(routing);
+        self.scheduler.set_timeouts(timeouts);
+        info!(
+            "Config reloaded: ip_rate_limit_rps={}, routing={:?}, timeouts={:?}, log_level={}",
+            args.ip_rate_limit_rps,
+            routing,
+            timeouts,
+            args.log_level.as_deref().unwrap_or("default")
+        );
+        Ok(())
+    }
+
+    /// Reload on every SIGHUP; connections are kept
+    #[cfg(target_os = "linux")]
+    pub async fn run(self) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                warn!("Failed to listen for SIGHUP, config reload disabled: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP, reloading config");
+            if let Err(e) = self.reload() {
+                warn!(
+                    "Config reload failed, keeping the current settings: {:#}",
+                    e
+                );
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub async fn run(self) {
+        info!("Config reload on SIGHUP is not supported on this platform");
+    }
+}
+
+#[test]
+fn test_merge_args() {
+    let cli = |flags: &[&str]| {
+        std::iter::once("gpuf-s")
+            .chain(flags.iter().copied())
+            .map(OsString::from)
+            .collect::<Vec<_>>()
+    };
+    let merge = |flags: &[&str], settings: &str, env: &[(&str, &str)]| {
+        let cli = cli(flags);
+        let matches = Args::command().get_matches_from(&cli);
+        let settings: Map<String, Value> = toml::from_str(settings).unwrap();
+        merge_args(cli, &matches, &settings, |name| {
+            env.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        })
+    };
+
+    let settings = r#"
+        control_port = 17100
+        api-port = 18100
+        admin_port = 18200
+        enrollment_tokens = ["a", "b"]
+        skip_migrations = true
+        min_quality_score = 0.7
+    "#;
+    let args = merge(
+        &["--control-port", "17200"],
+        settings,
+        &[("GPUF_API_PORT", "18300")],
+    )
+    .unwrap();
+    // Flags win over environment variables, which win over the file
+    assert_eq!(args.control_port, 17200);
+    assert_eq!(args.api_port, 18300);
+    assert_eq!(args.admin_port, 18200);
+    assert_eq!(args.enrollment_tokens, vec!["a", "b"]);
+    assert!(args.skip_migrations);
+    assert_eq!(args.min_quality_score, 0.7);
+    assert_eq!(args.proxy_port, 17001);
+
+    let args = merge(&[], "", &[("GPUF_MONITOR", "1")]).unwrap();
+    assert!(args.monitor);
+
+    assert!(merge(&[], "contol_port = 1", &[]).is_err());
+    assert!(merge(&[], "control_port = \"many\"", &[]).is_err());
+    assert!(merge(&[], "skip_migrations = \"maybe\"", &[]).is_err());
+}
@@ -0,0 +1,216 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// Request header naming the region a caller wants its worker in
+pub const REGION_HEADER: &str = "gpuf-region";
+const MAX_REGION_CHARS: usize = 32;
+
+/// Lowercase region name, e.g. `eu-west` or a country code like `de`. None
+/// for names that are empty, too long or hold other characters than ASCII
+/// letters, digits, `-` and `_`.
+pub fn normalize_region(region: &str) -> Option<String> {
+    let region = region.trim();
+    if region.is_empty()
+        || region.len() > MAX_REGION_CHARS
+        || !region
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return None;
+    }
+    Some(region.to_ascii_lowercase())
+}
+
+/// Where a worker is, as it declared at login or its address says
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Location {
+    pub region: Option<String>,
+    /// ISO 3166 country code of the worker's address
+    pub country: Option<String>,
+}
+
+impl Location {
+    /// Whether a caller asking for `region` should get this worker. Both the
+    /// region and the country count, so callers can ask for either.
+    pub fn matches(&self, region: &str) -> bool {
+        self.region.as_deref() == Some(region) || self.country.as_deref() == Some(region)
+    }
+}
+
+/// Region setting of an API key
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRegion {
+    pub region: Option<String>,
+    /// Set by an operator: the key only uses workers in its region and
+    /// callers cannot ask for another one
+    pub pinned: bool,
+}
+
+impl KeyRegion {
+    /// Region a request prefers its worker in: the pinned region, else the
+    /// region the caller asked for, else the key's region
+    pub fn preferred(&self, requested: Option<&str>) -> Option<String> {
+        if self.pinned {
+            return self.region.clone();
+        }
+        requested
+            .and_then(normalize_region)
+            .or_else(|| self.region.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct IpRange {
+    start: u128,
+    end: u128,
+    country: String,
+}
+
+/// An address as an IPv6 number, IPv4 addresses mapped
+fn ip_number(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().into(),
+        IpAddr::V6(ip) => ip
+            .to_ipv4_mapped()
+            .map_or(ip, |v4| v4.to_ipv6_mapped())
+            .into(),
+    }
+}
+
+/// An address as written in a range file, either literal or the decimal
+/// number IP2Location files use
+fn parse_ip(field: &str) -> Option<u128> {
+    if let Ok(ip) = field.parse::<IpAddr>() {
+        return Some(ip_number(ip));
+    }
+    let number: u128 = field.parse().ok()?;
+    Some(match u32::try_from(number) {
+        Ok(v4) => ip_number(IpAddr::V4(v4.into())),
+        Err(_) => number,
+    })
+}
+
+/// Country lookup from a CSV of `start_ip,end_ip,country_code` ranges, the
+/// layout of the DB-IP and IP2Location lite country databases
+#[derive(Debug, Default)]
+pub struct GeoIp {
+    ranges: Vec<IpRange>,
+}
+
+impl GeoIp {
+    pub fn parse(csv: &str) -> Result<Self> {
+        let mut ranges = Vec::new();
+        for (n, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line
+                .split(',')
+                .map(|f| f.trim().trim_matches('"'))
+                .collect();
+            let [start, end, country, ..] = fields[..] else {
+                return Err(anyhow!("line {}: expected start_ip,end_ip,country", n + 1));
+            };
+            let (Some(start), Some(end)) = (parse_ip(start), parse_ip(end)) else {
+                return Err(anyhow!("line {}: invalid address", n + 1));
+            };
+            // Reserved and unknown ranges have no country
+            if country.len() != 2 || country == "ZZ" || country == "-" {
+                continue;
+            }
+            ranges.push(IpRange {
+                start,
+                end,
+                country: country.to_ascii_lowercase(),
+            });
+        }
+        ranges.sort_by_key(|range| range.start);
+        Ok(Self { ranges })
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let csv = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read GeoIP database {}", path))?;
+        Self::parse(&csv)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Lowercase country code of `ip`
+    pub fn country(&self, ip: IpAddr) -> Option<&str> {
+        let ip = ip_number(ip);
+        let i = self.ranges.partition_point(|range| range.start <= ip);
+        let range = self.ranges.get(i.checked_sub(1)?)?;
+        (ip <= range.end).then_some(range.country.as_str())
+    }
+
+    /// Location of a worker connecting from `ip`. The region it declared
+    /// wins, otherwise its country is its region.
+    pub fn locate(geoip: Option<&Self>, declared: Option<&str>, ip: IpAddr) -> Location {
+        let country = geoip
+            .and_then(|geoip| geoip.country(ip))
+            .map(str::to_string);
+        let region = declared
+            .and_then(normalize_region)
+            .or_else(|| country.clone());
+        Location { region, country }
+    }
+}
+
+#[test]
+fn test_geoip_lookup() {
+    let geoip = GeoIp::parse(
+        "# start,end,country\n\
+         1.0.0.0,1.0.0.255,AU\n\
+         \"16777472\",\"16778239\",\"CN\"\n\
+         2001:db8::,2001:db8::ffff,DE\n\
+         10.0.0.0,10.255.255.255,ZZ\n",
+    )
+    .unwrap();
+    assert_eq!(geoip.len(), 3);
+    assert_eq!(geoip.country("1.0.0.7".parse().unwrap()), Some("au"));
+    assert_eq!(geoip.country("1.0.2.1".parse().unwrap()), Some("cn"));
+    assert_eq!(geoip.country("::ffff:1.0.0.9".parse().unwrap()), Some("au"));
+    assert_eq!(geoip.country("2001:db8::1".parse().unwrap()), Some("de"));
+    assert_eq!(geoip.country("10.1.1.1".parse().unwrap()), None);
+    assert_eq!(geoip.country("8.8.8.8".parse().unwrap()), None);
+    assert!(GeoIp::parse("1.0.0.0,AU\n").is_err());
+
+    let location = GeoIp::locate(Some(&geoip), None, "1.0.0.7".parse().unwrap());
+    assert_eq!(location.region.as_deref(), Some("au"));
+    let location = GeoIp::locate(Some(&geoip), Some("AP-South"), "1.0.0.7".parse().unwrap());
+    assert!(location.matches("ap-south") && location.matches("au"));
+    assert!(!location.matches("de"));
+}
+
+#[test]
+fn test_key_region() {
+    assert_eq!(normalize_region(" EU-West "), Some("eu-west".to_string()));
+    assert_eq!(normalize_region("eu west"), None);
+    assert_eq!(normalize_region(""), None);
+
+    let key = KeyRegion {
+        region: Some("eu-west".to_string()),
+        pinned: false,
+    };
+    assert_eq!(key.preferred(Some("US")), Some("us".to_string()));
+    assert_eq!(
+        key.preferred(Some("bad region")),
+        Some("eu-west".to_string())
+    );
+    assert_eq!(key.preferred(None), Some("eu-west".to_string()));
+    let pinned = KeyRegion {
+        pinned: true,
+        ..key
+    };
+    assert_eq!(pinned.preferred(Some("us")), Some("eu-west".to_string()));
+    assert_eq!(KeyRegion::default().preferred(None), None);
+}
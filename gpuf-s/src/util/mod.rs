@@ -1,14 +1,28 @@
 pub mod cmd;
+pub mod config;
 pub mod db;
+pub mod geo;
 pub mod msg;
+pub mod net;
+pub mod notify;
 pub mod pack;
 pub mod policy;
 pub mod protoc;
+pub mod rate_limit;
+pub mod rbac;
+pub mod stun;
+pub mod tls;
+pub mod usage_tap;
 use anyhow::Result;
 use std::fs::File;
 use std::io::BufReader;
 use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use tracing::{debug, info, Level};
+use cmd::LogFormat;
+use tracing::{debug, info};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Layer, Registry};
 
 pub fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
     let f = File::open(path)?;
@@ -44,32 +58,55 @@ pub fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>> {
     anyhow::bail!("no private keys found in {}", path);
 }
 
-pub fn init_logging() {
-    // Use DEBUG level for debug builds, INFO for release builds
+/// Changes the log filter of the installed subscriber
+pub type LogLevelHandle = reload::Handle<EnvFilter, Registry>;
 
-    #[cfg(not(debug_assertions))]
-    tracing_subscriber::fmt()
-        .with_max_level(Level::INFO)
-        .with_ansi(!cfg!(windows))
-        .with_target(false)
-        .with_thread_ids(false)
-        .with_thread_names(false)
-        .with_file(false)
-        .with_line_number(false)
-        .compact()
-        .init();
+/// Filter for `--log-level`, DEBUG for debug builds and INFO for release
+/// builds when unset
+pub fn log_filter(level: Option<&str>) -> Result<EnvFilter> {
+    match level {
+        Some(level) => EnvFilter::try_new(level)
+            .map_err(|e| anyhow::anyhow!("Invalid log level {}: {}", level, e)),
+        None if cfg!(debug_assertions) => Ok(EnvFilter::new(LevelFilter::DEBUG.to_string())),
+        None => Ok(EnvFilter::new(LevelFilter::INFO.to_string())),
+    }
+}
+
+/// Install the global subscriber. `json` writes one object per line with the
+/// fields of the current span (request_id, client_id, model, ...) merged in.
+/// The returned handle swaps the level filter on config reloads.
+pub fn init_logging(format: LogFormat, level: Option<&str>) -> Result<LogLevelHandle> {
+    let (level, handle) = reload::Layer::new(log_filter(level)?);
+
+    // Debug builds: show thread info, file, and line number
+    let verbose = cfg!(debug_assertions);
+    let fmt_layer = match format {
+        LogFormat::Compact => fmt::layer()
+            .with_ansi(!cfg!(windows))
+            .with_target(false)
+            .with_thread_ids(verbose)
+            .with_thread_names(verbose)
+            .with_file(verbose)
+            .with_line_number(verbose)
+            .compact()
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_target(false)
+            .with_file(verbose)
+            .with_line_number(verbose)
+            .boxed(),
+    };
 
-    #[cfg(debug_assertions)]
-    tracing_subscriber::fmt()
-        .with_max_level(Level::DEBUG)
-        .with_ansi(!cfg!(windows))
-        .with_target(false)
-        .with_thread_ids(true)
-        .with_thread_names(true)
-        .with_file(true)
-        .with_line_number(true)
-        .compact()
-        .init();
+    let subscriber = tracing_subscriber::registry().with(level).with(fmt_layer);
+    // Spans are exported over OTLP when built with `otel` and OTEL_EXPORTER_OTLP_ENDPOINT is set
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(common::trace::otlp_layer("gpuf-s"));
+    subscriber.init();
 
     debug!("Logging initialized");
+    Ok(handle)
 }
@@ -1,5 +1,8 @@
-use chrono::{DateTime, Utc};
-use serde::Serialize;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Pool, Postgres, QueryBuilder, Row};
 
 // API Response structures
 #[derive(Serialize)]
@@ -29,3 +32,389 @@ impl<T> ApiResponse<T> {
         }
     }
 }
+
+/// Rows a list endpoint returns unless `page_size` asks for another amount
+pub const DEFAULT_PAGE_SIZE: u32 = 20;
+pub const MAX_PAGE_SIZE: u32 = 100;
+
+/// Paging, sorting and filtering parameters shared by list endpoints, taken
+/// from the query string next to the endpoint's own parameters
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ListParams {
+    pub page: Option<u32>,
+    pub page_size: Option<u32>,
+    /// Field to order by, `-` in front for descending, e.g. `-created_at`
+    pub sort: Option<String>,
+    /// Comma-separated `field:value` conditions, e.g. `status:online,name:rtx`
+    pub filter: Option<String>,
+    /// `next_cursor` of the previous page, continues after it instead of
+    /// skipping `page` pages
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOp {
+    Eq,
+    /// Case-insensitive substring match
+    Contains,
+}
+
+/// Output column of a list query that can be sorted or filtered by
+#[derive(Debug)]
+pub struct ListField {
+    pub name: &'static str,
+    /// SQL expression over the list query aliased `l`, never NULL when sortable
+    pub expr: &'static str,
+    /// Postgres type of `expr`, filter and cursor values are cast to it
+    pub sql_type: &'static str,
+    pub sortable: bool,
+    pub filter: Option<FilterOp>,
+}
+
+/// Fields of a list endpoint and its order when the caller gives none
+#[derive(Debug)]
+pub struct ListSpec {
+    pub fields: &'static [ListField],
+    pub default_sort: &'static str,
+    /// Unique text expression that orders rows with equal sort values
+    pub key: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Cursor {
+    /// Sort the cursor was issued for, e.g. `-created_at`
+    s: String,
+    /// Sort value and key of the last row of the page
+    v: String,
+    k: String,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(raw: &str) -> Option<Self> {
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(raw).ok()?).ok()
+    }
+}
+
+/// Whether Postgres can cast `value` to `sql_type`, so bad filters are
+/// rejected with 400 rather than failing the query
+fn valid_literal(sql_type: &str, value: &str) -> bool {
+    match sql_type {
+        "boolean" => value.parse::<bool>().is_ok(),
+        "smallint" | "integer" | "bigint" => value.parse::<i64>().is_ok(),
+        "double precision" => value.parse::<f64>().is_ok(),
+        "date" => value.parse::<NaiveDate>().is_ok(),
+        "timestamptz" => DateTime::parse_from_rfc3339(value).is_ok(),
+        _ => true,
+    }
+}
+
+/// Validated [`ListParams`] of one endpoint
+#[derive(Debug)]
+pub struct ListQuery {
+    pub page: u32,
+    pub page_size: u32,
+    spec: &'static ListSpec,
+    sort: &'static ListField,
+    descending: bool,
+    filters: Vec<(&'static ListField, String)>,
+    cursor: Option<Cursor>,
+}
+
+impl ListParams {
+    pub fn resolve(&self, spec: &'static ListSpec) -> Result<ListQuery, String> {
+        let page = self.page.unwrap_or(1).max(1);
+        let page_size = self.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+        if page_size == 0 || page_size > MAX_PAGE_SIZE {
+            return Err(format!("page_size must be 1 to {}", MAX_PAGE_SIZE));
+        }
+
+        let sort_param = self
+            .sort
+            .as_deref()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .unwrap_or(spec.default_sort);
+        let (descending, sort_name) = match sort_param.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, sort_param),
+        };
+        let sort = spec
+            .fields
+            .iter()
+            .find(|f| f.sortable && f.name == sort_name)
+            .ok_or_else(|| format!("Cannot sort by {}", sort_name))?;
+
+        let mut filters = Vec::new();
+        for condition in self.filter.as_deref().unwrap_or("").split(',') {
+            let condition = condition.trim();
+            if condition.is_empty() {
+                continue;
+            }
+            let (name, value) = condition
+                .split_once(':')
+                .ok_or_else(|| format!("Filter {} is not field:value", condition))?;
+            let field = spec
+                .fields
+                .iter()
+                .find(|f| f.filter.is_some() && f.name == name.trim())
+                .ok_or_else(|| format!("Cannot filter by {}", name.trim()))?;
+            let value = value.trim();
+            if field.filter == Some(FilterOp::Eq) && !valid_literal(field.sql_type, value) {
+                return Err(format!("Invalid value {} for {}", value, field.name));
+            }
+            filters.push((field, value.to_string()));
+        }
+
+        let cursor = match self.cursor.as_deref().filter(|c| !c.is_empty()) {
+            Some(raw) => {
+                let cursor = Cursor::decode(raw).ok_or("Invalid cursor")?;
+                if cursor.s != sort_param {
+                    return Err("Cursor belongs to another sort order".to_string());
+                }
+                Some(cursor)
+            }
+            None => None,
+        };
+
+        Ok(ListQuery {
+            page,
+            page_size,
+            spec,
+            sort,
+            descending,
+            filters,
+            cursor,
+        })
+    }
+}
+
+/// One page of a list and where to continue
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Rows matching the filters over all pages
+    pub total: i64,
+    pub page: u32,
+    pub page_size: u32,
+    /// Pass as `cursor` for the next page, None on the last page
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            page: self.page,
+            page_size: self.page_size,
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+/// Row of a list query with the values its cursor is made of
+struct Keyed<T> {
+    row: T,
+    sort_value: String,
+    sort_key: String,
+}
+
+impl<'r, T: FromRow<'r, PgRow>> FromRow<'r, PgRow> for Keyed<T> {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            row: T::from_row(row)?,
+            sort_value: row.try_get("sort_value")?,
+            sort_key: row.try_get("sort_key")?,
+        })
+    }
+}
+
+impl ListQuery {
+    fn push_conditions(&self, qb: &mut QueryBuilder<'_, Postgres>, with_cursor: bool) {
+        qb.push(" WHERE TRUE");
+        for (field, value) in &self.filters {
+            qb.push(" AND ").push(field.expr);
+            match field.filter {
+                Some(FilterOp::Contains) => {
+                    qb.push("::text ILIKE ").push_bind(format!("%{}%", value));
+                }
+                _ => {
+                    qb.push(" = ")
+                        .push_bind(value.clone())
+                        .push("::")
+                        .push(field.sql_type);
+                }
+            }
+        }
+        let Some(cursor) = self.cursor.as_ref().filter(|_| with_cursor) else {
+            return;
+        };
+        let op = if self.descending { " < " } else { " > " };
+        qb.push(" AND (")
+            .push(self.sort.expr)
+            .push(op)
+            .push_bind(cursor.v.clone())
+            .push("::")
+            .push(self.sort.sql_type)
+            .push(" OR (")
+            .push(self.sort.expr)
+            .push(" = ")
+            .push_bind(cursor.v.clone())
+            .push("::")
+            .push(self.sort.sql_type)
+            .push(" AND ")
+            .push(self.spec.key)
+            .push(op)
+            .push_bind(cursor.k.clone())
+            .push("))");
+    }
+
+    /// Page of the rows of the list query `base` pushes, a SELECT whose
+    /// output columns the spec's fields refer to as `l`, and their total
+    pub async fn fetch<'args, T>(
+        &self,
+        pool: &Pool<Postgres>,
+        base: impl Fn(&mut QueryBuilder<'args, Postgres>),
+    ) -> Result<Page<T>, sqlx::Error>
+    where
+        T: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+    {
+        let mut count = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM (");
+        base(&mut count);
+        count.push(") l");
+        self.push_conditions(&mut count, false);
+        let total: i64 = count.build_query_scalar().fetch_one(pool).await?;
+
+        let direction = if self.descending { " DESC" } else { " ASC" };
+        let mut rows = QueryBuilder::<Postgres>::new("SELECT l.*, (");
+        rows.push(self.sort.expr)
+            .push(")::text AS sort_value, ")
+            .push(self.spec.key)
+            .push(" AS sort_key FROM (");
+        base(&mut rows);
+        rows.push(") l");
+        self.push_conditions(&mut rows, true);
+        rows.push(" ORDER BY ")
+            .push(self.sort.expr)
+            .push(direction)
+            .push(", ")
+            .push(self.spec.key)
+            .push(direction)
+            .push(" LIMIT ")
+            .push_bind(self.page_size as i64 + 1);
+        if self.cursor.is_none() {
+            rows.push(" OFFSET ")
+                .push_bind((self.page as i64 - 1) * self.page_size as i64);
+        }
+        let mut keyed: Vec<Keyed<T>> = rows.build_query_as().fetch_all(pool).await?;
+
+        let mut next_cursor = None;
+        if keyed.len() > self.page_size as usize {
+            keyed.truncate(self.page_size as usize);
+            next_cursor = keyed.last().map(|last| {
+                Cursor {
+                    s: self.sort_param(),
+                    v: last.sort_value.clone(),
+                    k: last.sort_key.clone(),
+                }
+                .encode()
+            });
+        }
+        Ok(Page {
+            items: keyed.into_iter().map(|k| k.row).collect(),
+            total,
+            page: self.page,
+            page_size: self.page_size,
+            next_cursor,
+        })
+    }
+
+    fn sort_param(&self) -> String {
+        if self.descending {
+            format!("-{}", self.sort.name)
+        } else {
+            self.sort.name.to_string()
+        }
+    }
+}
+
+#[test]
+fn test_resolve_list_params() {
+    static FIELDS: [ListField; 3] = [
+        ListField {
+            name: "name",
+            expr: "l.name",
+            sql_type: "text",
+            sortable: true,
+            filter: Some(FilterOp::Contains),
+        },
+        ListField {
+            name: "created_at",
+            expr: "l.created_at",
+            sql_type: "timestamptz",
+            sortable: true,
+            filter: None,
+        },
+        ListField {
+            name: "is_active",
+            expr: "l.is_active",
+            sql_type: "boolean",
+            sortable: false,
+            filter: Some(FilterOp::Eq),
+        },
+    ];
+    static SPEC: ListSpec = ListSpec {
+        fields: &FIELDS,
+        default_sort: "-created_at",
+        key: "l.id::text",
+    };
+
+    let query = ListParams::default().resolve(&SPEC).unwrap();
+    assert_eq!(query.page, 1);
+    assert_eq!(query.page_size, DEFAULT_PAGE_SIZE);
+    assert_eq!(query.sort_param(), "-created_at");
+
+    let params = ListParams {
+        sort: Some("name".to_string()),
+        filter: Some("is_active:true, name:llama".to_string()),
+        ..Default::default()
+    };
+    let query = params.resolve(&SPEC).unwrap();
+    assert!(!query.descending);
+    assert_eq!(query.filters.len(), 2);
+
+    let bad = |params: ListParams| params.resolve(&SPEC).is_err();
+    assert!(bad(ListParams {
+        sort: Some("is_active".to_string()),
+        ..Default::default()
+    }));
+    assert!(bad(ListParams {
+        filter: Some("is_active:maybe".to_string()),
+        ..Default::default()
+    }));
+    assert!(bad(ListParams {
+        page_size: Some(MAX_PAGE_SIZE + 1),
+        ..Default::default()
+    }));
+
+    let cursor = Cursor {
+        s: "-created_at".to_string(),
+        v: "2025-08-02 09:00:00+00".to_string(),
+        k: "42".to_string(),
+    };
+    assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor.clone()));
+    let params = ListParams {
+        cursor: Some(cursor.encode()),
+        ..Default::default()
+    };
+    assert_eq!(params.resolve(&SPEC).unwrap().cursor, Some(cursor.clone()));
+    assert!(bad(ListParams {
+        sort: Some("name".to_string()),
+        cursor: Some(cursor.encode()),
+        ..Default::default()
+    }));
+}
@@ -0,0 +1,160 @@
+//! Listening sockets for IPv4, IPv6 or both
+
+use serde::Serialize;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use tokio::net::{TcpListener, UdpSocket};
+use tracing::warn;
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Address the listeners bind to. The unspecified IPv6 address `::` takes
+/// IPv4 clients as well unless `ipv6_only` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ListenAddr {
+    pub ip: IpAddr,
+    pub ipv6_only: bool,
+}
+
+impl ListenAddr {
+    /// Listener on `port`. `::` falls back to `0.0.0.0` on hosts without
+    /// IPv6.
+    pub fn bind(self, port: u16) -> io::Result<TcpListener> {
+        match bind_socket(SocketAddr::new(self.ip, port), self.ipv6_only) {
+            Err(e) if self.ip.is_unspecified() && self.ip.is_ipv6() && !self.ipv6_only => {
+                warn!("IPv6 is unavailable ({}), listening on IPv4 only", e);
+                bind_socket(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port), false)
+            }
+            result => result,
+        }
+    }
+
+    /// UDP socket on `port`, with the same fallback as [`ListenAddr::bind`]
+    pub fn bind_udp(self, port: u16) -> io::Result<UdpSocket> {
+        match udp_socket(SocketAddr::new(self.ip, port), self.ipv6_only) {
+            Err(e) if self.ip.is_unspecified() && self.ip.is_ipv6() && !self.ipv6_only => {
+                warn!("IPv6 is unavailable ({}), listening on IPv4 only", e);
+                udp_socket(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port), false)
+            }
+            result => result,
+        }
+    }
+}
+
+fn new_socket(addr: SocketAddr, ty: Type, ipv6_only: bool) -> io::Result<Socket> {
+    let protocol = if ty == Type::STREAM {
+        Protocol::TCP
+    } else {
+        Protocol::UDP
+    };
+    let socket = Socket::new(Domain::for_address(addr), ty, Some(protocol))?;
+    if addr.is_ipv6() {
+        // The system default differs, e.g. Windows and some BSDs are v6-only
+        socket.set_only_v6(ipv6_only)?;
+    }
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+fn bind_socket(addr: SocketAddr, ipv6_only: bool) -> io::Result<TcpListener> {
+    let socket = new_socket(addr, Type::STREAM, ipv6_only)?;
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+fn udp_socket(addr: SocketAddr, ipv6_only: bool) -> io::Result<UdpSocket> {
+    let socket = new_socket(addr, Type::DGRAM, ipv6_only)?;
+    socket.bind(&addr.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// IPv4 clients of a dual-stack listener appear as `::ffff:a.b.c.d`; rate
+/// limits, GeoIP and logs want the plain IPv4 address
+pub fn client_ip(addr: SocketAddr) -> IpAddr {
+    addr.ip().to_canonical()
+}
+
+/// Address range in CIDR notation such as `10.0.0.0/8`, or a single address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.trim().split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s.trim(), None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address in {}", s))?;
+        let addr = addr.to_canonical();
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| format!("invalid prefix length in {}", s))?,
+            None => max_len,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+#[test]
+fn test_client_ip() {
+    let mapped: SocketAddr = "[::ffff:203.0.113.7]:4000".parse().unwrap();
+    assert_eq!(client_ip(mapped), "203.0.113.7".parse::<IpAddr>().unwrap());
+    let v6: SocketAddr = "[2001:db8::1]:4000".parse().unwrap();
+    assert_eq!(client_ip(v6), v6.ip());
+}
+
+#[test]
+fn test_ip_range() {
+    let range: IpRange = "10.0.0.0/8".parse().unwrap();
+    assert!(range.contains("10.1.2.3".parse().unwrap()));
+    assert!(range.contains("::ffff:10.1.2.3".parse().unwrap()));
+    assert!(!range.contains("11.0.0.1".parse().unwrap()));
+    assert!(!range.contains("2001:db8::1".parse().unwrap()));
+
+    let single: IpRange = "2001:db8::1".parse().unwrap();
+    assert!(single.contains("2001:db8::1".parse().unwrap()));
+    assert!(!single.contains("2001:db8::2".parse().unwrap()));
+    assert!("0.0.0.0/0"
+        .parse::<IpRange>()
+        .unwrap()
+        .contains("203.0.113.7".parse().unwrap()));
+
+    assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+    assert!("proxy.local".parse::<IpRange>().is_err());
+}
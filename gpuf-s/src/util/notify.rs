@@ -0,0 +1,193 @@
+use crate::db::notifications::{self, Delivery, NotificationChannel};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Deliveries sent one by one per round
+const IMMEDIATE_BATCH: i64 = 100;
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where notifications of each channel go. Webhooks are posted to the
+/// target itself, email and push through relays that own the provider
+/// credentials.
+pub struct Notifier {
+    http: reqwest::Client,
+    /// Gets `{to, subject, text}` for every email
+    email_relay: Option<String>,
+    /// Gets `{token, title, body}` for every push notification
+    push_relay: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookItem<'a> {
+    event_type: &'a str,
+    subject: &'a str,
+    body: &'a str,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// What a group of deliveries to one target looks like to the user
+fn compose(deliveries: &[Delivery]) -> (String, String) {
+    match deliveries {
+        [single] => (single.subject.clone(), single.body.clone()),
+        _ => {
+            let subject = format!("{} GPUFabric notifications", deliveries.len());
+            let body = deliveries
+                .iter()
+                .map(|d| format!("{}\n{}", d.subject, d.body))
+                .collect::<Vec<_>>()
+                .join("\n\n");
+            (subject, body)
+        }
+    }
+}
+
+impl Notifier {
+    pub fn new(email_relay: Option<String>, push_relay: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(SEND_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            email_relay,
+            push_relay,
+        }
+    }
+
+    /// Send deliveries that share user, channel and target as one message
+    async fn send(&self, deliveries: &[Delivery]) -> Result<()> {
+        let first = deliveries
+            .first()
+            .ok_or_else(|| anyhow!("Nothing to send"))?;
+        let channel = NotificationChannel::parse(&first.channel)
+            .ok_or_else(|| anyhow!("Unknown channel {}", first.channel))?;
+        let (subject, body) = compose(deliveries);
+        let request = match channel {
+            NotificationChannel::Webhook => {
+                let items: Vec<WebhookItem> = deliveries
+                    .iter()
+                    .map(|d| WebhookItem {
+                        event_type: &d.event_type,
+                        subject: &d.subject,
+                        body: &d.body,
+                        created_at: d.created_at,
+                    })
+                    .collect();
+                self.http.post(&first.target).json(&serde_json::json!({
+                    "user_id": first.user_id,
+                    "notifications": items,
+                }))
+            }
+            NotificationChannel::Email => {
+                let relay = self
+                    .email_relay
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("No email relay configured"))?;
+                self.http.post(relay).json(&serde_json::json!({
+                    "to": first.target,
+                    "subject": subject,
+                    "text": body,
+                }))
+            }
+            NotificationChannel::Push => {
+                let relay = self
+                    .push_relay
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("No push relay configured"))?;
+                self.http.post(relay).json(&serde_json::json!({
+                    "token": first.target,
+                    "title": subject,
+                    "body": body,
+                }))
+            }
+        };
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    async fn deliver(&self, pool: &Pool<Postgres>, deliveries: &[Delivery]) {
+        let ids: Vec<i64> = deliveries.iter().map(|d| d.id).collect();
+        let result = match self.send(deliveries).await {
+            Ok(()) => notifications::mark_sent(pool, &ids).await,
+            Err(e) => {
+                warn!(
+                    "Failed to notify user {} by {}: {}",
+                    deliveries[0].user_id, deliveries[0].channel, e
+                );
+                notifications::mark_failed(pool, &ids, &e.to_string()).await
+            }
+        };
+        if let Err(e) = result {
+            warn!("Failed to record notification delivery: {}", e);
+        }
+    }
+
+    async fn deliver_round(&self, pool: &Pool<Postgres>, digest_secs: i64) -> Result<()> {
+        for delivery in notifications::claim_immediate(pool, IMMEDIATE_BATCH).await? {
+            self.deliver(pool, std::slice::from_ref(&delivery)).await;
+        }
+
+        let mut digests: BTreeMap<(String, String, String), Vec<Delivery>> = BTreeMap::new();
+        for delivery in notifications::claim_digests(pool, digest_secs).await? {
+            let key = (
+                delivery.user_id.clone(),
+                delivery.channel.clone(),
+                delivery.target.clone(),
+            );
+            digests.entry(key).or_default().push(delivery);
+        }
+        for deliveries in digests.values() {
+            debug!(
+                "Sending digest of {} notifications to user {}",
+                deliveries.len(),
+                deliveries[0].user_id
+            );
+            self.deliver(pool, deliveries).await;
+        }
+        Ok(())
+    }
+
+    /// Send due notifications every `interval`. Instances share the queue,
+    /// each delivery is claimed by one of them.
+    pub async fn run(self, pool: Pool<Postgres>, interval: Duration, digest: Duration) {
+        info!(
+            "Delivering notifications every {:?}, digests every {:?}",
+            interval, digest
+        );
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.deliver_round(&pool, digest.as_secs() as i64).await {
+                warn!("Notification delivery round failed: {}", e);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_compose_digest() {
+    let delivery = |id, subject: &str| Delivery {
+        id,
+        user_id: "7".to_string(),
+        event_type: "device_offline".to_string(),
+        channel: "email".to_string(),
+        target: "ops@example.com".to_string(),
+        subject: subject.to_string(),
+        body: format!("details {}", id),
+        attempts: 1,
+        created_at: chrono::Utc::now(),
+    };
+
+    let single = [delivery(1, "rig-1 went offline")];
+    assert_eq!(
+        compose(&single),
+        ("rig-1 went offline".to_string(), "details 1".to_string())
+    );
+
+    let (subject, body) = compose(&[delivery(1, "rig-1 went offline"), delivery(2, "Paid")]);
+    assert_eq!(subject, "2 GPUFabric notifications");
+    assert_eq!(body, "rig-1 went offline\ndetails 1\n\nPaid\ndetails 2");
+}
@@ -22,3 +22,11 @@ impl From<AccessLevel> for i32 {
 }
 
 pub const REQUEST_MESSAGE_TOPIC: &str = "request-message";
+
+/// Per-token limits configured on the `tokens` table. `None` means unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, sqlx::FromRow)]
+pub struct RateLimitPolicy {
+    pub rate_limit_rps: Option<i32>,
+    pub tokens_per_minute: Option<i32>,
+    pub max_concurrent: Option<i32>,
+}
@@ -4,7 +4,7 @@ use std::error::Error;
 use std::fmt::Display;
 use std::str::FromStr;
 
-use common::{DevicesInfo, SystemInfo};
+use common::{DevicesInfo, SystemInfo, WorkerVersion};
 use serde::{de, ser::SerializeTuple, Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, bincode::Encode, bincode::Decode)]
@@ -161,6 +161,8 @@ pub struct HeartbeatMessage {
     pub device_count: u32,
     pub total_tflops: u32,
     pub devices_info: Vec<DevicesInfo>,
+    /// None from workers that do not report their versions
+    pub worker_version: Option<WorkerVersion>,
 }
 
 #[allow(dead_code)]
@@ -0,0 +1,366 @@
+use crate::util::policy::RateLimitPolicy;
+use anyhow::Result;
+use redis::{AsyncCommands, Client as RedisClient, Script};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+// Token bucket: refills `rate` tokens per second up to `capacity`, takes one token per call.
+// Returns {allowed, remaining, ms until next token}.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local rate = tonumber(ARGV[1])
+local capacity = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(bucket[1])
+local ts = tonumber(bucket[2])
+if tokens == nil or ts == nil then
+    tokens = capacity
+    ts = now
+end
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * rate / 1000)
+local allowed = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tokens, 'ts', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity * 1000 / rate) + 1000)
+local reset_ms = 0
+if tokens < 1 then
+    reset_ms = math.ceil((1 - tokens) * 1000 / rate)
+end
+return {allowed, math.floor(tokens), reset_ms}
+"#;
+
+const ACQUIRE_SLOT_SCRIPT: &str = r#"
+local n = redis.call('INCR', KEYS[1])
+redis.call('EXPIRE', KEYS[1], ARGV[2])
+if n > tonumber(ARGV[1]) then
+    redis.call('DECR', KEYS[1])
+    return 0
+end
+return 1
+"#;
+
+/// Safety TTL for concurrency counters in case a release is lost
+const CONCURRENCY_KEY_TTL_SECS: u64 = 600;
+const TPM_KEY_TTL_SECS: u64 = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    ClientIp,
+    Requests,
+    Tokens,
+    Concurrency,
+    Streams,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitDecision {
+    pub limited_by: Option<LimitKind>,
+    pub limit: Option<u32>,
+    pub remaining: Option<u32>,
+    pub reset_secs: u64,
+    pub token_limit: Option<u32>,
+    pub tokens_remaining: Option<u32>,
+}
+
+impl RateLimitDecision {
+    pub fn allowed(&self) -> bool {
+        self.limited_by.is_none()
+    }
+
+    /// `X-RateLimit-*` headers describing the limits applied to this request
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::new();
+        if let Some(limit) = self.limit {
+            headers.push(("X-RateLimit-Limit", limit.to_string()));
+            headers.push((
+                "X-RateLimit-Remaining",
+                self.remaining.unwrap_or(0).to_string(),
+            ));
+        }
+        if let Some(limit) = self.token_limit {
+            headers.push(("X-RateLimit-Limit-Tokens", limit.to_string()));
+            headers.push((
+                "X-RateLimit-Remaining-Tokens",
+                self.tokens_remaining.unwrap_or(0).to_string(),
+            ));
+        }
+        if self.limit.is_some() || self.token_limit.is_some() || !self.allowed() {
+            headers.push(("X-RateLimit-Reset", self.reset_secs.to_string()));
+        }
+        if !self.allowed() {
+            headers.push(("Retry-After", self.reset_secs.max(1).to_string()));
+        }
+        headers
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self.limited_by {
+            Some(LimitKind::ClientIp) => "Too many requests from this address",
+            Some(LimitKind::Requests) => "Request rate limit exceeded for this API key",
+            Some(LimitKind::Tokens) => "Token rate limit exceeded for this API key",
+            Some(LimitKind::Concurrency) => "Too many concurrent requests for this API key",
+            Some(LimitKind::Streams) => "Too many concurrent streams for this API key",
+            None => "",
+        }
+    }
+}
+
+/// Redis backed rate limiter shared by the public proxy and the inference gateway.
+/// Redis failures never block traffic, they are logged and the request is allowed.
+pub struct RateLimiter {
+    redis_client: Arc<RedisClient>,
+    ip_rps: AtomicU32,
+}
+
+/// Holds one concurrency slot of an API key, released on drop
+pub struct ConcurrencySlot {
+    redis_client: Arc<RedisClient>,
+    key: String,
+}
+
+impl Drop for ConcurrencySlot {
+    fn drop(&mut self) {
+        let redis_client = self.redis_client.clone();
+        let key = std::mem::take(&mut self.key);
+        tokio::spawn(async move {
+            if let Ok(mut conn) = redis_client.get_async_connection().await {
+                if let Err(e) = conn.decr::<_, _, i64>(&key, 1).await {
+                    warn!("Failed to release concurrency slot {}: {}", key, e);
+                }
+            }
+        });
+    }
+}
+
+impl RateLimiter {
+    /// `ip_rps` limits the requests per second of one client address, 0 disables it
+    pub fn new(redis_client: Arc<RedisClient>, ip_rps: u32) -> Self {
+        Self {
+            redis_client,
+            ip_rps: AtomicU32::new(ip_rps),
+        }
+    }
+
+    /// Change the per-IP limit, for config reloads
+    pub fn set_ip_rps(&self, ip_rps: u32) {
+        self.ip_rps.store(ip_rps, Ordering::Relaxed);
+    }
+
+    /// Check request rate and token budget for an API key and client address
+    pub async fn check(
+        &self,
+        api_key: &str,
+        client_ip: Option<IpAddr>,
+        policy: &RateLimitPolicy,
+    ) -> RateLimitDecision {
+        match self.try_check(api_key, client_ip, policy).await {
+            Ok(decision) => decision,
+            Err(e) => {
+                warn!("Rate limit check failed, allowing request: {}", e);
+                RateLimitDecision::default()
+            }
+        }
+    }
+
+    async fn try_check(
+        &self,
+        api_key: &str,
+        client_ip: Option<IpAddr>,
+        policy: &RateLimitPolicy,
+    ) -> Result<RateLimitDecision> {
+        let mut decision = RateLimitDecision::default();
+        let mut conn = self.redis_client.get_async_connection().await?;
+        let now_ms = now_millis();
+
+        let ip_rps = self.ip_rps.load(Ordering::Relaxed);
+        if let (Some(ip), true) = (client_ip, ip_rps > 0) {
+            let (allowed, _, reset_ms) =
+                take_token(&mut conn, &format!("ratelimit:ip:{}", ip), ip_rps, now_ms).await?;
+            if !allowed {
+                decision.limited_by = Some(LimitKind::ClientIp);
+                decision.reset_secs = reset_ms.div_ceil(1000);
+                return Ok(decision);
+            }
+        }
+
+        if let Some(rps) = positive(policy.rate_limit_rps) {
+            let (allowed, remaining, reset_ms) = take_token(
+                &mut conn,
+                &format!("ratelimit:key:{}", api_key),
+                rps,
+                now_ms,
+            )
+            .await?;
+            decision.limit = Some(rps);
+            decision.remaining = Some(remaining);
+            decision.reset_secs = reset_ms.div_ceil(1000);
+            if !allowed {
+                decision.limited_by = Some(LimitKind::Requests);
+                return Ok(decision);
+            }
+        }
+
+        if let Some(tpm) = positive(policy.tokens_per_minute) {
+            let used: Option<u64> = conn.get(tpm_key(api_key, now_ms)).await?;
+            let used = used.unwrap_or(0);
+            decision.token_limit = Some(tpm);
+            decision.tokens_remaining = Some((tpm as u64).saturating_sub(used) as u32);
+            if used >= tpm as u64 {
+                decision.limited_by = Some(LimitKind::Tokens);
+                decision.reset_secs = 60 - (now_ms / 1000) % 60;
+            }
+        }
+
+        Ok(decision)
+    }
+
+    /// Take a concurrency slot for the API key. `Ok(None)` means the key has no limit.
+    pub async fn acquire_slot(
+        &self,
+        api_key: &str,
+        policy: &RateLimitPolicy,
+    ) -> Result<Option<ConcurrencySlot>, RateLimitDecision> {
+        let Some(max) = positive(policy.max_concurrent) else {
+            return Ok(None);
+        };
+        self.acquire(
+            format!("ratelimit:concurrent:{}", api_key),
+            max,
+            LimitKind::Concurrency,
+        )
+        .await
+    }
+
+    /// Take one of the `max` stream slots of the API key, 0 for no limit
+    pub async fn acquire_stream_slot(
+        &self,
+        api_key: &str,
+        max: u32,
+    ) -> Result<Option<ConcurrencySlot>, RateLimitDecision> {
+        if max == 0 {
+            return Ok(None);
+        }
+        self.acquire(
+            format!("ratelimit:streams:{}", api_key),
+            max,
+            LimitKind::Streams,
+        )
+        .await
+    }
+
+    async fn acquire(
+        &self,
+        key: String,
+        max: u32,
+        kind: LimitKind,
+    ) -> Result<Option<ConcurrencySlot>, RateLimitDecision> {
+        let acquired: Result<i64> = async {
+            let mut conn = self.redis_client.get_async_connection().await?;
+            Ok(Script::new(ACQUIRE_SLOT_SCRIPT)
+                .key(&key)
+                .arg(max)
+                .arg(CONCURRENCY_KEY_TTL_SECS)
+                .invoke_async(&mut conn)
+                .await?)
+        }
+        .await;
+
+        match acquired {
+            Ok(1) => Ok(Some(ConcurrencySlot {
+                redis_client: self.redis_client.clone(),
+                key,
+            })),
+            Ok(_) => Err(RateLimitDecision {
+                limited_by: Some(kind),
+                reset_secs: 1,
+                ..Default::default()
+            }),
+            Err(e) => {
+                warn!("Concurrency check failed, allowing request: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Add consumed prompt and completion tokens to the key's current minute window
+    pub async fn record_tokens(&self, api_key: &str, tokens: u32) {
+        if tokens == 0 {
+            return;
+        }
+        let key = tpm_key(api_key, now_millis());
+        let result: Result<()> = async {
+            let mut conn = self.redis_client.get_async_connection().await?;
+            redis::pipe()
+                .incr(&key, tokens)
+                .ignore()
+                .expire(&key, TPM_KEY_TTL_SECS as i64)
+                .ignore()
+                .query_async::<_, ()>(&mut conn)
+                .await?;
+            Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("Failed to record token usage: {}", e);
+        }
+    }
+}
+
+async fn take_token(
+    conn: &mut redis::aio::Connection,
+    key: &str,
+    rate: u32,
+    now_ms: u64,
+) -> Result<(bool, u32, u64)> {
+    let (allowed, remaining, reset_ms): (i64, i64, i64) = Script::new(TOKEN_BUCKET_SCRIPT)
+        .key(key)
+        .arg(rate)
+        .arg(rate)
+        .arg(now_ms)
+        .invoke_async(conn)
+        .await?;
+    Ok((
+        allowed == 1,
+        remaining.max(0) as u32,
+        reset_ms.max(0) as u64,
+    ))
+}
+
+fn tpm_key(api_key: &str, now_ms: u64) -> String {
+    format!("ratelimit:tpm:{}:{}", api_key, now_ms / 60_000)
+}
+
+fn positive(value: Option<i32>) -> Option<u32> {
+    value.filter(|v| *v > 0).map(|v| v as u32)
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[test]
+fn test_rate_limit_headers() {
+    let decision = RateLimitDecision {
+        limited_by: Some(LimitKind::Requests),
+        limit: Some(10),
+        remaining: Some(0),
+        reset_secs: 0,
+        token_limit: None,
+        tokens_remaining: None,
+    };
+    let headers = decision.headers();
+    assert!(headers.contains(&("X-RateLimit-Limit", "10".to_string())));
+    assert!(headers.contains(&("X-RateLimit-Remaining", "0".to_string())));
+    assert!(headers.contains(&("Retry-After", "1".to_string())));
+
+    assert!(RateLimitDecision::default().headers().is_empty());
+}
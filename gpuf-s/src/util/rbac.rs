@@ -0,0 +1,182 @@
+use axum::http::Method;
+use serde::{Deserialize, Serialize};
+
+/// Staff role for the admin endpoints, ordered by what it may do. The shared
+/// API key and the admin key act as owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Operator,
+    Admin,
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Operator => "operator",
+            Self::Admin => "admin",
+            Self::Owner => "owner",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "viewer" => Some(Self::Viewer),
+            "operator" => Some(Self::Operator),
+            "admin" => Some(Self::Admin),
+            "owner" => Some(Self::Owner),
+            _ => None,
+        }
+    }
+
+    pub fn allows(self, permission: Permission) -> bool {
+        self >= permission.min_role()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Read workers, jobs, the catalog, rate cards and denials
+    ViewFleet,
+    /// Approve, disconnect, pin, configure and command workers
+    ManageWorkers,
+    /// Change the model catalog and rollouts
+    ManageModels,
+    /// Generate payouts, change their status and the rate cards
+    ManagePayouts,
+    /// Change the settings of API keys
+    ManageKeys,
+    /// Publish Android app releases
+    PublishApps,
+    /// Grant and revoke staff roles
+    ManageRoles,
+    /// Read the audit log
+    ViewAudit,
+}
+
+impl Permission {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ViewFleet => "view_fleet",
+            Self::ManageWorkers => "manage_workers",
+            Self::ManageModels => "manage_models",
+            Self::ManagePayouts => "manage_payouts",
+            Self::ManageKeys => "manage_keys",
+            Self::PublishApps => "publish_apps",
+            Self::ManageRoles => "manage_roles",
+            Self::ViewAudit => "view_audit",
+        }
+    }
+
+    pub fn min_role(self) -> Role {
+        match self {
+            Self::ViewFleet => Role::Viewer,
+            Self::ManageWorkers => Role::Operator,
+            Self::ManageModels
+            | Self::ManagePayouts
+            | Self::ManageKeys
+            | Self::PublishApps
+            | Self::ViewAudit => Role::Admin,
+            Self::ManageRoles => Role::Owner,
+        }
+    }
+
+    /// Permission an endpoint of the management API needs, by its route.
+    /// None for endpoints open to every authenticated caller.
+    pub fn for_api(method: &Method, route: &str) -> Option<Self> {
+        let read = method == Method::GET;
+        match route {
+            "/api/admin/roles" | "/api/admin/roles/:user_id" => Some(Self::ManageRoles),
+            "/api/admin/access_denials" => Some(Self::ViewFleet),
+            "/api/admin/audit_log" => Some(Self::ViewAudit),
+            _ if route.starts_with("/api/admin/models") => Some(if read {
+                Self::ViewFleet
+            } else {
+                Self::ManageModels
+            }),
+            "/api/models/insert" => Some(Self::ManageModels),
+            "/api/apk/upsert" => Some(Self::PublishApps),
+            "/api/payouts/rate_cards" if read => Some(Self::ViewFleet),
+            "/api/payouts/generate" | "/api/payouts/:id/status" | "/api/payouts/rate_cards" => {
+                Some(Self::ManagePayouts)
+            }
+            _ => None,
+        }
+    }
+
+    /// Permission an endpoint of the admin API needs, by its route
+    pub fn for_admin(method: &Method, route: &str) -> Self {
+        if method == Method::GET {
+            Self::ViewFleet
+        } else if route.starts_with("/admin/keys/") {
+            Self::ManageKeys
+        } else {
+            Self::ManageWorkers
+        }
+    }
+}
+
+#[test]
+fn test_permissions() {
+    assert_eq!(Role::parse("operator"), Some(Role::Operator));
+    assert_eq!(Role::parse("root"), None);
+    assert!(Role::Operator.allows(Permission::ManageWorkers));
+    assert!(!Role::Operator.allows(Permission::ManagePayouts));
+    assert!(Role::Admin.allows(Permission::ManageKeys));
+    assert!(!Role::Admin.allows(Permission::ManageRoles));
+    assert!(!Role::Viewer.allows(Permission::ManageWorkers));
+    assert!(Role::Admin.allows(Permission::ViewAudit));
+    assert!(!Role::Operator.allows(Permission::ViewAudit));
+
+    assert_eq!(
+        Permission::for_api(&Method::GET, "/api/admin/models/:id/rollout"),
+        Some(Permission::ViewFleet)
+    );
+    assert_eq!(
+        Permission::for_api(&Method::POST, "/api/admin/models/:id/rollout"),
+        Some(Permission::ManageModels)
+    );
+    assert_eq!(
+        Permission::for_api(&Method::POST, "/api/models/insert"),
+        Some(Permission::ManageModels)
+    );
+    assert_eq!(
+        Permission::for_api(&Method::POST, "/api/apk/upsert"),
+        Some(Permission::PublishApps)
+    );
+    assert_eq!(Permission::for_api(&Method::GET, "/api/apk/list"), None);
+    assert!(!Role::Operator.allows(Permission::PublishApps));
+    assert_eq!(
+        Permission::for_api(&Method::GET, "/api/payouts/rate_cards"),
+        Some(Permission::ViewFleet)
+    );
+    assert_eq!(
+        Permission::for_api(&Method::POST, "/api/payouts/:id/status"),
+        Some(Permission::ManagePayouts)
+    );
+    assert_eq!(Permission::for_api(&Method::GET, "/api/payouts"), None);
+    assert_eq!(
+        Permission::for_api(&Method::GET, "/api/admin/audit_log"),
+        Some(Permission::ViewAudit)
+    );
+    assert_eq!(
+        Permission::for_api(&Method::DELETE, "/api/admin/roles/:user_id"),
+        Some(Permission::ManageRoles)
+    );
+
+    assert_eq!(
+        Permission::for_admin(&Method::GET, "/admin/workers"),
+        Permission::ViewFleet
+    );
+    assert_eq!(
+        Permission::for_admin(&Method::POST, "/admin/keys/:key_id/region"),
+        Permission::ManageKeys
+    );
+    assert_eq!(
+        Permission::for_admin(&Method::DELETE, "/admin/workers/:client_id/pin"),
+        Permission::ManageWorkers
+    );
+}
@@ -0,0 +1,94 @@
+//! STUN binding responder (RFC 5389), so P2P peers learn their public
+//! address from gpuf-s itself and need no STUN server of their own
+
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const HEADER_LEN: usize = 20;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+/// Answer binding requests on `socket` until it fails
+pub async fn run(socket: UdpSocket) {
+    if let Ok(addr) = socket.local_addr() {
+        info!("STUN responder listening on {}", addr);
+    }
+    let mut buf = [0u8; 1500];
+    loop {
+        let (n, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("STUN responder stopped: {}", e);
+                return;
+            }
+        };
+        if let Some(response) = binding_response(&buf[..n], from) {
+            let _ = socket.send_to(&response, from).await;
+        }
+    }
+}
+
+/// Success response carrying `from` as XOR-MAPPED-ADDRESS, None for anything
+/// but a well-formed binding request
+fn binding_response(request: &[u8], from: SocketAddr) -> Option<Vec<u8>> {
+    if request.len() < HEADER_LEN
+        || u16::from_be_bytes([request[0], request[1]]) != BINDING_REQUEST
+        || u32::from_be_bytes([request[4], request[5], request[6], request[7]]) != MAGIC_COOKIE
+        || usize::from(u16::from_be_bytes([request[2], request[3]])) != request.len() - HEADER_LEN
+    {
+        return None;
+    }
+    let transaction_id = &request[8..HEADER_LEN];
+
+    // IPv4 clients of a dual-stack socket are told their plain IPv4 address
+    let ip = from.ip().to_canonical();
+    let mut value = vec![0, if ip.is_ipv4() { 0x01 } else { 0x02 }];
+    value.extend_from_slice(&(from.port() ^ (MAGIC_COOKIE >> 16) as u16).to_be_bytes());
+    let mask: Vec<u8> = MAGIC_COOKIE
+        .to_be_bytes()
+        .into_iter()
+        .chain(transaction_id.iter().copied())
+        .collect();
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    value.extend(octets.iter().zip(&mask).map(|(b, m)| b ^ m));
+
+    let mut response = Vec::with_capacity(HEADER_LEN + 4 + value.len());
+    response.extend_from_slice(&BINDING_SUCCESS.to_be_bytes());
+    response.extend_from_slice(&((4 + value.len()) as u16).to_be_bytes());
+    response.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    response.extend_from_slice(transaction_id);
+    response.extend_from_slice(&XOR_MAPPED_ADDRESS.to_be_bytes());
+    response.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    response.extend_from_slice(&value);
+    Some(response)
+}
+
+#[test]
+fn test_binding_response() {
+    let mut request = vec![0x00, 0x01, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42];
+    request.extend_from_slice(&[7; 12]);
+    let from: SocketAddr = "[::ffff:192.0.2.1]:32853".parse().unwrap();
+    let response = binding_response(&request, from).unwrap();
+    assert_eq!(&response[..2], &[0x01, 0x01]);
+    assert_eq!(&response[8..20], &[7; 12]);
+    // XOR-MAPPED-ADDRESS, IPv4, port and address xor the magic cookie
+    assert_eq!(
+        &response[20..],
+        &[0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43]
+    );
+
+    let from: SocketAddr = "[2001:db8::1]:3478".parse().unwrap();
+    let response = binding_response(&request, from).unwrap();
+    assert_eq!(response.len(), 20 + 4 + 20);
+    assert_eq!(response[25], 0x02);
+
+    assert!(binding_response(&request[..19], from).is_none());
+    request[1] = 0x03;
+    assert!(binding_response(&request, from).is_none());
+}
@@ -0,0 +1,236 @@
+//! TLS of the proxy port, with the certificate read from files that are
+//! watched for changes, or obtained and renewed over ACME, e.g. from
+//! Let's Encrypt
+
+use crate::util::cmd::Args;
+use anyhow::{anyhow, Context, Result};
+use notify::{RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::server::{
+    ClientHello, ResolvesServerCert, ServerConfig, ServerConnection,
+};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tracing::{info, warn};
+
+#[cfg(feature = "acme")]
+use futures::StreamExt;
+#[cfg(feature = "acme")]
+use rustls_acme::{caches::DirCache, AcmeConfig};
+#[cfg(feature = "acme")]
+use tracing::error;
+
+#[cfg(feature = "aws_lc_rs")]
+use tokio_rustls::rustls::crypto::aws_lc_rs;
+#[cfg(feature = "ring")]
+use tokio_rustls::rustls::crypto::ring;
+
+/// ALPN protocol of TLS-ALPN-01 challenge handshakes
+const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+/// Wait after a change to the certificate files, so tools writing the chain
+/// and the key one after the other are done with both
+const RELOAD_DELAY: Duration = Duration::from_secs(2);
+
+/// Install the crypto provider selected by the `ring` or `aws_lc_rs` feature,
+/// once per process
+pub fn install_crypto_provider() {
+    #[cfg(feature = "aws_lc_rs")]
+    let _ = aws_lc_rs::default_provider().install_default();
+
+    #[cfg(feature = "ring")]
+    let _ = ring::default_provider().install_default();
+}
+
+/// Server config of the proxy port. With `--acme-domains` the certificate
+/// is obtained in the background and swapped in on every renewal, otherwise
+/// it is read from `--proxy-cert-chain-path` and `--proxy-private-key-path`
+/// and read again whenever they change. Either way new handshakes get the
+/// new certificate while established connections are kept.
+pub fn proxy_tls_config(args: &Args) -> Result<Arc<ServerConfig>> {
+    install_crypto_provider();
+    if !args.acme_domains.is_empty() {
+        return acme_tls_config(args);
+    }
+
+    for path in [&args.proxy_cert_chain_path, &args.proxy_private_key_path] {
+        if !Path::new(path).exists() {
+            return Err(anyhow!(" TLS file does not exist: {}", path));
+        }
+    }
+    let cert = Arc::new(FileCert::load(
+        &args.proxy_cert_chain_path,
+        &args.proxy_private_key_path,
+    )?);
+    info!(
+        "Loaded proxy certificate from {}",
+        args.proxy_cert_chain_path
+    );
+    if let Err(e) = watch_cert_files(cert.clone()) {
+        warn!(
+            "Failed to watch certificate files, changes need a restart: {:#}",
+            e
+        );
+    }
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(cert);
+    Ok(Arc::new(config))
+}
+
+/// Certificate read from files, replaced by [`FileCert::reload`]
+#[derive(Debug)]
+pub struct FileCert {
+    cert_chain_path: String,
+    private_key_path: String,
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl FileCert {
+    pub fn load(cert_chain_path: &str, private_key_path: &str) -> Result<Self> {
+        Ok(Self {
+            cert_chain_path: cert_chain_path.to_string(),
+            private_key_path: private_key_path.to_string(),
+            key: RwLock::new(load_certified_key(cert_chain_path, private_key_path)?),
+        })
+    }
+
+    /// Read the files again; false when the certificate is unchanged. On
+    /// errors the current certificate stays.
+    pub fn reload(&self) -> Result<bool> {
+        let key = load_certified_key(&self.cert_chain_path, &self.private_key_path)?;
+        let mut current = self.key.write().unwrap();
+        if current.cert == key.cert {
+            return Ok(false);
+        }
+        *current = key;
+        Ok(true)
+    }
+
+    /// Directories holding the files. They are watched instead of the files,
+    /// which certbot and Kubernetes replace through symlinks and renames.
+    fn dirs(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = [&self.cert_chain_path, &self.private_key_path]
+            .iter()
+            .map(|path| match Path::new(path.as_str()).parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                _ => PathBuf::from("."),
+            })
+            .collect();
+        dirs.dedup();
+        dirs
+    }
+}
+
+impl ResolvesServerCert for FileCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.key.read().unwrap().clone())
+    }
+}
+
+fn load_certified_key(cert_chain_path: &str, private_key_path: &str) -> Result<Arc<CertifiedKey>> {
+    let cert_chain = crate::util::load_certs(cert_chain_path)?;
+    let priv_key = crate::util::load_private_key(private_key_path)?;
+    let provider =
+        CryptoProvider::get_default().ok_or_else(|| anyhow!("No TLS crypto provider installed"))?;
+    let signing_key = provider
+        .key_provider
+        .load_private_key(priv_key)
+        .with_context(|| format!("Unsupported private key in {}", private_key_path))?;
+    let key = CertifiedKey::new(cert_chain, signing_key);
+    key.keys_match().with_context(|| {
+        format!(
+            "Private key {} does not match certificate {}",
+            private_key_path, cert_chain_path
+        )
+    })?;
+    Ok(Arc::new(key))
+}
+
+/// Reload `cert` in the background whenever its files change
+fn watch_cert_files(cert: Arc<FileCert>) -> Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if !event.kind.is_access() => {
+                let _ = tx.send(());
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Certificate file watch error: {}", e),
+        })?;
+    for dir in cert.dirs() {
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("Failed to watch {}", dir.display()))?;
+    }
+
+    tokio::spawn(async move {
+        // Watching stops when the watcher is dropped
+        let _watcher = watcher;
+        while rx.recv().await.is_some() {
+            tokio::time::sleep(RELOAD_DELAY).await;
+            while rx.try_recv().is_ok() {}
+            match cert.reload() {
+                Ok(true) => info!("Reloaded proxy certificate from {}", cert.cert_chain_path),
+                Ok(false) => {}
+                Err(e) => warn!(
+                    "Failed to reload proxy certificate, keeping the current one: {:#}",
+                    e
+                ),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(feature = "acme")]
+fn acme_tls_config(args: &Args) -> Result<Arc<ServerConfig>> {
+    if args.acme_contact.is_empty() {
+        warn!("No --acme-contact set, expiry notices of the ACME directory are not delivered");
+    }
+    let mut state = AcmeConfig::new(args.acme_domains.clone())
+        .contact(
+            args.acme_contact
+                .iter()
+                .map(|email| format!("mailto:{}", email)),
+        )
+        .cache(DirCache::new(args.acme_cache_dir.clone()))
+        .directory_lets_encrypt(!args.acme_staging)
+        .state();
+
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(state.resolver());
+    config.alpn_protocols = vec![ACME_TLS_ALPN.to_vec()];
+
+    info!(
+        "Obtaining proxy certificate for {} over ACME{}, cached in {}",
+        args.acme_domains.join(", "),
+        if args.acme_staging { " (staging)" } else { "" },
+        args.acme_cache_dir
+    );
+    // Drives orders and renewals; failed orders are retried with backoff
+    tokio::spawn(async move {
+        while let Some(event) = state.next().await {
+            match event {
+                Ok(event) => info!("ACME: {:?}", event),
+                Err(e) => error!("ACME: {:?}", e),
+            }
+        }
+    });
+    Ok(Arc::new(config))
+}
+
+#[cfg(not(feature = "acme"))]
+fn acme_tls_config(_args: &Args) -> Result<Arc<ServerConfig>> {
+    Err(anyhow!(
+        "--acme-domains needs gpuf-s built with the acme feature"
+    ))
+}
+
+/// TLS-ALPN-01 challenges end after the handshake and carry no commands
+pub fn is_acme_challenge(conn: &ServerConnection) -> bool {
+    conn.alpn_protocol() == Some(ACME_TLS_ALPN)
+}
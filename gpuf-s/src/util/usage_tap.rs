@@ -0,0 +1,179 @@
+//! Status and token usage of worker responses relayed by the public proxy,
+//! which passes the bytes through without parsing the HTTP exchange
+
+use serde::Deserialize;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Bytes kept from the start of a response, enough for the status line
+const HEAD_BYTES: usize = 64;
+/// Bytes kept from the end of a response. The `usage` block is in the last
+/// chunk of a stream and at the end of a plain response.
+const TAIL_BYTES: usize = 16 * 1024;
+const USAGE_KEY: &[u8] = b"\"usage\"";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+impl TokenUsage {
+    pub fn total_tokens(&self) -> u32 {
+        self.prompt_tokens.saturating_add(self.completion_tokens)
+    }
+}
+
+/// What the proxy saw of the worker's side of a connection
+#[derive(Debug, Default)]
+pub struct ResponseTap {
+    head: Vec<u8>,
+    tail: Vec<u8>,
+}
+
+impl ResponseTap {
+    fn push(&mut self, bytes: &[u8]) {
+        if self.head.len() < HEAD_BYTES {
+            let n = (HEAD_BYTES - self.head.len()).min(bytes.len());
+            self.head.extend_from_slice(&bytes[..n]);
+        }
+        self.tail.extend_from_slice(bytes);
+        if self.tail.len() > 2 * TAIL_BYTES {
+            let excess = self.tail.len() - TAIL_BYTES;
+            self.tail.drain(..excess);
+        }
+    }
+
+    /// HTTP status of the response, None when the worker answered nothing
+    pub fn status(&self) -> Option<u16> {
+        let line = self.head.strip_prefix(b"HTTP/")?;
+        let code = line.split(|b| *b == b' ').nth(1)?.get(..3)?;
+        std::str::from_utf8(code).ok()?.parse().ok()
+    }
+
+    /// Token counts of the last `usage` object of the response, of the last
+    /// request when the client kept the connection alive
+    pub fn usage(&self) -> Option<TokenUsage> {
+        let mut end = self.tail.len();
+        while let Some(pos) = self.tail[..end]
+            .windows(USAGE_KEY.len())
+            .rposition(|window| window == USAGE_KEY)
+        {
+            if let Some(usage) = object_after(&self.tail[pos + USAGE_KEY.len()..])
+                .and_then(|object| serde_json::from_slice(object).ok())
+            {
+                return Some(usage);
+            }
+            end = pos;
+        }
+        None
+    }
+}
+
+/// The JSON object following `: ` at the start of `bytes`, e.g. not the
+/// `null` usage of intermediate stream chunks
+fn object_after(bytes: &[u8]) -> Option<&[u8]> {
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace() && *b != b':')?;
+    if bytes[start] != b'{' {
+        return None;
+    }
+    let mut depth = 0usize;
+    for (i, b) in bytes[start..].iter().enumerate() {
+        match b {
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&bytes[start..=start + i]);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Wraps the worker side of a proxied connection and feeds what is read from
+/// it to a [`ResponseTap`]
+pub struct UsageTap<'a, S> {
+    inner: S,
+    tap: &'a mut ResponseTap,
+}
+
+impl<'a, S> UsageTap<'a, S> {
+    pub fn new(inner: S, tap: &'a mut ResponseTap) -> Self {
+        Self { inner, tap }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for UsageTap<'_, S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            this.tap.push(&buf.filled()[filled..]);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for UsageTap<'_, S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[test]
+fn test_response_tap() {
+    let mut tap = ResponseTap::default();
+    tap.push(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n");
+    tap.push(b"data: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}],\"usage\":null}\n\n");
+    tap.push(
+        b"data: {\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":5,\
+          \"total_tokens\":17,\"prompt_tokens_details\":{\"cached_tokens\":0}}}\n\n",
+    );
+    tap.push(b"data: [DONE]\n\n");
+    assert_eq!(tap.status(), Some(200));
+    assert_eq!(
+        tap.usage(),
+        Some(TokenUsage {
+            prompt_tokens: 12,
+            completion_tokens: 5
+        })
+    );
+
+    // Only the tail is kept, the usage at the end of a long response survives
+    let mut tap = ResponseTap::default();
+    tap.push(b"HTTP/1.1 503 Service Unavailable\r\n\r\n");
+    for _ in 0..100 {
+        tap.push(&[b'x'; 1024]);
+    }
+    assert!(tap.tail.len() <= 2 * TAIL_BYTES);
+    tap.push(b"{\"usage\": {\"prompt_tokens\": 3, \"completion_tokens\": 4}}");
+    assert_eq!(tap.status(), Some(503));
+    assert_eq!(tap.usage().map(|usage| usage.total_tokens()), Some(7));
+
+    assert_eq!(ResponseTap::default().status(), None);
+    assert_eq!(ResponseTap::default().usage(), None);
+}
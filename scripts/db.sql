@@ -1,3 +1,6 @@
+-- Schema as of the baseline migration, plus test data. gpuf-s applies
+-- gpuf-s/migrations at startup, later schema changes only go there.
+
 -- Create database if not exists (for manual initialization)
 -- Note: Docker entrypoint will create database via POSTGRES_DB env var
 SELECT 'CREATE DATABASE "GPUFabric"'
@@ -21,6 +24,11 @@ CREATE INDEX IF NOT EXISTS idx_tokens_key ON "public"."tokens" ("key");
 CREATE INDEX IF NOT EXISTS idx_tokens_user_id ON "public"."tokens" ("user_id");
 CREATE INDEX IF NOT EXISTS idx_tokens_deleted_at ON "public"."tokens" ("deleted_at");
 
+-- Per-token rate limits, NULL means unlimited
+ALTER TABLE "public"."tokens" ADD COLUMN IF NOT EXISTS "rate_limit_rps" INTEGER;
+ALTER TABLE "public"."tokens" ADD COLUMN IF NOT EXISTS "tokens_per_minute" INTEGER;
+ALTER TABLE "public"."tokens" ADD COLUMN IF NOT EXISTS "max_concurrent" INTEGER;
+
 DO $$
 BEGIN
     BEGIN
@@ -51,6 +59,30 @@ CREATE TABLE  IF NOT EXISTS  "public"."gpu_assets" (
  CREATE INDEX IF NOT EXISTS idx_gpu_assets_user_id_client_name
  ON "public"."gpu_assets" ("user_id", "client_name");
 
+-- Workers in maintenance stay connected but receive no new work
+ALTER TABLE "public"."gpu_assets"
+ADD COLUMN IF NOT EXISTS "maintenance" BOOLEAN DEFAULT FALSE;
+
+-- pending, approved, rejected or banned. Workers registered before approvals
+-- existed stay approved, new ones wait for an operator or the approval policy.
+ALTER TABLE "public"."gpu_assets"
+ADD COLUMN IF NOT EXISTS "approval_status" VARCHAR DEFAULT 'approved';
+ALTER TABLE "public"."gpu_assets"
+ALTER COLUMN "approval_status" SET DEFAULT 'pending';
+
+-- Set by the heartbeat consumer when a client reports too many implausible
+-- heartbeats, its heartbeats are dropped until an operator clears it
+ALTER TABLE "public"."gpu_assets"
+ADD COLUMN IF NOT EXISTS "quarantined_at" TIMESTAMPTZ;
+
+-- 0 to 100, raised by passed liveness challenges and lowered by failed ones
+ALTER TABLE "public"."gpu_assets"
+ADD COLUMN IF NOT EXISTS "trust_score" SMALLINT NOT NULL DEFAULT 100;
+
+-- 0 to 1, lowered by wrong canary answers; the router avoids workers below 0.5
+ALTER TABLE "public"."gpu_assets"
+ADD COLUMN IF NOT EXISTS "quality_score" REAL NOT NULL DEFAULT 1.0;
+
 CREATE TABLE IF NOT EXISTS "public"."pod_info" (
     "pod_id" UUID PRIMARY KEY DEFAULT gen_random_uuid(),
     "client_id" BYTEA NOT NULL REFERENCES "public"."gpu_assets" ("client_id") ON DELETE CASCADE,
@@ -117,6 +149,10 @@ ALTER TABLE client_models
 ADD COLUMN IF NOT EXISTS download_url TEXT,
 ADD COLUMN IF NOT EXISTS checksum VARCHAR(128),
 ADD COLUMN IF NOT EXISTS expected_size BIGINT;
+-- Publisher's base64 Ed25519 signature over the SHA-256 digest of the model file
+ALTER TABLE client_models ADD COLUMN IF NOT EXISTS signature TEXT;
+-- Chunk hashes of the model file, lets workers update an older version by delta
+ALTER TABLE client_models ADD COLUMN IF NOT EXISTS chunk_manifest_url TEXT;
 
 CREATE UNIQUE INDEX IF NOT EXISTS idx_client_models_name_version_unique
 ON client_models (name, version);
@@ -319,6 +355,17 @@ WHERE points_multiplier = 1.0
   AND tflops > 0
   AND (SELECT tflops FROM device_types WHERE device_id = 9860) > 0;
 
+-- Liveness challenges answered per worker and day. Days with more failed
+-- than passed challenges earn no heartbeat points.
+CREATE TABLE IF NOT EXISTS "public"."client_challenge_daily" (
+    "client_id" BYTEA NOT NULL,
+    "date" DATE NOT NULL,
+    "passed" INTEGER NOT NULL DEFAULT 0,
+    "failed" INTEGER NOT NULL DEFAULT 0,
+    "updated_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY ("client_id", "date")
+);
+
 DROP MATERIALIZED VIEW IF EXISTS device_points_daily;
 DROP TABLE IF EXISTS device_points_daily;
 
@@ -333,7 +380,10 @@ SELECT
     dt.tflops,
     COALESCE(dt.points_multiplier, 1.0) AS multiplier,
     s.base_hours,
-    (s.base_hours::NUMERIC * COALESCE(dt.points_multiplier, 1.0)) AS points,
+    CASE
+        WHEN COALESCE(ccd.failed, 0) > COALESCE(ccd.passed, 0) THEN 0
+        ELSE (s.base_hours::NUMERIC * COALESCE(dt.points_multiplier, 1.0))
+    END AS points,
     NOW() AS refreshed_at
 FROM (
     SELECT
@@ -350,7 +400,10 @@ LEFT JOIN device_info di
     ON di.client_id = s.client_id
    AND di.device_index = s.device_index
 LEFT JOIN device_types dt
-    ON dt.device_id = di.device_id;
+    ON dt.device_id = di.device_id
+LEFT JOIN client_challenge_daily ccd
+    ON ccd.client_id = s.client_id
+   AND ccd.date = s.date;
 
 CREATE UNIQUE INDEX IF NOT EXISTS idx_device_points_daily_pk
 ON device_points_daily (client_id, device_index, date);
@@ -423,4 +476,157 @@ CREATE INDEX IF NOT EXISTS idx_apk_versions_package_active
 ON "public"."apk_versions" (package_name, is_active);
 
 CREATE INDEX IF NOT EXISTS idx_apk_versions_released_at
-ON "public"."apk_versions" (released_at DESC);
\ No newline at end of file
+ON "public"."apk_versions" (released_at DESC);
+-- Per-request model performance samples, used to compare model versions during rollouts
+CREATE TABLE IF NOT EXISTS "public"."model_perf_samples" (
+    "id" BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    "model_name" VARCHAR(255) NOT NULL,
+    "model_version" VARCHAR(64) NOT NULL DEFAULT 'unknown',
+    "client_id" BYTEA NOT NULL,
+    "latency_ms" BIGINT NOT NULL,
+    "prompt_tokens" INTEGER NOT NULL DEFAULT 0,
+    "completion_tokens" INTEGER NOT NULL DEFAULT 0,
+    "success" BOOLEAN NOT NULL,
+    "truncated" BOOLEAN NOT NULL DEFAULT FALSE,
+    "error" TEXT,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_model_perf_samples_model_version_created_at
+ON "public"."model_perf_samples" (model_name, model_version, created_at DESC);
+
+-- Per-request usage accounting, written in batches by the inference gateway and public proxy
+CREATE TABLE IF NOT EXISTS "public"."usage_records" (
+    "id" BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    "request_id" VARCHAR(64),
+    "api_key" CHAR(48) NOT NULL,
+    "client_id" BYTEA NOT NULL,
+    "model" VARCHAR(255) NOT NULL,
+    "prompt_tokens" INTEGER NOT NULL DEFAULT 0,
+    "completion_tokens" INTEGER NOT NULL DEFAULT 0,
+    "latency_ms" BIGINT NOT NULL DEFAULT 0,
+    "status" VARCHAR(16) NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_usage_records_created_at
+ON "public"."usage_records" (created_at DESC);
+
+CREATE INDEX IF NOT EXISTS idx_usage_records_api_key_created_at
+ON "public"."usage_records" (api_key, created_at DESC);
+
+CREATE INDEX IF NOT EXISTS idx_usage_records_client_id_created_at
+ON "public"."usage_records" (client_id, created_at DESC);
+
+-- Amount paid per point, by device tier (entry, mid, high, datacenter) and
+-- the class of the model served (none, small, medium, large). '*' matches
+-- every tier or class, the most specific card applies.
+CREATE TABLE IF NOT EXISTS "public"."payout_rate_cards" (
+    "id" SERIAL PRIMARY KEY,
+    "gpu_tier" VARCHAR(16) NOT NULL DEFAULT '*',
+    "model_class" VARCHAR(16) NOT NULL DEFAULT '*',
+    "rate_per_point" NUMERIC(18,6) NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updated_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE ("gpu_tier", "model_class")
+);
+
+-- Monthly earnings per user, computed from device_points_daily. Statements
+-- are recomputed while pending and frozen once approved.
+CREATE TABLE IF NOT EXISTS "public"."payout_statements" (
+    "id" BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    "user_id" VARCHAR NOT NULL,
+    "month" DATE NOT NULL,
+    "points" NUMERIC(20,4) NOT NULL DEFAULT 0,
+    "amount" NUMERIC(20,6) NOT NULL DEFAULT 0,
+    "currency" VARCHAR(8) NOT NULL DEFAULT 'USD',
+    "status" VARCHAR(16) NOT NULL DEFAULT 'pending',
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updated_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "approved_at" TIMESTAMPTZ,
+    "paid_at" TIMESTAMPTZ,
+    UNIQUE ("user_id", "month")
+);
+
+CREATE INDEX IF NOT EXISTS idx_payout_statements_status_month
+ON "public"."payout_statements" (status, month DESC);
+
+-- Points for inference work, per model class (small, medium, large, '*' for
+-- every class without a row). Each successful request earns
+-- completion_tokens / 1000 * points_per_1k_tokens, times sla_met_multiplier
+-- when it finished within sla_latency_ms and sla_missed_multiplier otherwise.
+CREATE TABLE IF NOT EXISTS "public"."work_points_formula" (
+    "model_class" VARCHAR(16) PRIMARY KEY,
+    "points_per_1k_tokens" NUMERIC(12,4) NOT NULL DEFAULT 1.0,
+    "sla_latency_ms" BIGINT NOT NULL DEFAULT 30000,
+    "sla_met_multiplier" NUMERIC(6,4) NOT NULL DEFAULT 1.0,
+    "sla_missed_multiplier" NUMERIC(6,4) NOT NULL DEFAULT 0.5,
+    "updated_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO "public"."work_points_formula" (model_class, points_per_1k_tokens, sla_latency_ms)
+VALUES
+    ('*', 1.0, 30000),
+    ('small', 1.0, 20000),
+    ('medium', 2.0, 30000),
+    ('large', 4.0, 60000)
+ON CONFLICT (model_class) DO NOTHING;
+
+-- Work points per worker and day, recomputed from usage_records by the heartbeat consumer
+CREATE TABLE IF NOT EXISTS "public"."client_work_points_daily" (
+    "client_id" BYTEA NOT NULL,
+    "date" DATE NOT NULL,
+    "requests" BIGINT NOT NULL DEFAULT 0,
+    "completion_tokens" BIGINT NOT NULL DEFAULT 0,
+    "sla_met_requests" BIGINT NOT NULL DEFAULT 0,
+    "points" NUMERIC(20,4) NOT NULL DEFAULT 0,
+    "updated_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY ("client_id", "date")
+);
+
+CREATE INDEX IF NOT EXISTS idx_client_work_points_daily_date
+ON "public"."client_work_points_daily" (date);
+
+-- Implausible heartbeats found by the heartbeat consumer. Rejected ones were
+-- dropped, the others were stored and only flagged.
+CREATE TABLE IF NOT EXISTS "public"."heartbeat_anomalies" (
+    "id" BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    "client_id" BYTEA NOT NULL,
+    "kind" VARCHAR(32) NOT NULL,
+    "detail" TEXT,
+    "rejected" BOOLEAN NOT NULL,
+    "heartbeat_at" TIMESTAMPTZ NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_heartbeat_anomalies_client_id_created_at
+ON "public"."heartbeat_anomalies" (client_id, created_at DESC);
+
+-- Known-answer prompts the server ran on workers to check their output
+CREATE TABLE IF NOT EXISTS "public"."canary_results" (
+    "id" BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    "client_id" BYTEA NOT NULL,
+    "prompt" TEXT NOT NULL,
+    "expected" TEXT NOT NULL,
+    "verdict" VARCHAR(32) NOT NULL,
+    "output" TEXT,
+    "latency_ms" BIGINT NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_canary_results_client_id_created_at
+ON "public"."canary_results" (client_id, created_at DESC);
+
+-- Worker connects, disconnects and model changes, written by the consumer
+-- from the worker-events topic
+CREATE TABLE IF NOT EXISTS "public"."worker_events" (
+    "id" BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    "client_id" BYTEA NOT NULL,
+    "kind" VARCHAR(32) NOT NULL,
+    "model" VARCHAR(255),
+    "detail" TEXT,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_worker_events_client_id_created_at
+ON "public"."worker_events" (client_id, created_at DESC);
@@ -0,0 +1,40 @@
+-- Raw telemetry written by the heartbeat consumer when started with
+-- --telemetry-database-url. Run against the TimescaleDB database, which may
+-- be separate from the main one. Retention is set by the consumer from
+-- --telemetry-retention-days.
+CREATE EXTENSION IF NOT EXISTS timescaledb;
+
+-- One row per stored heartbeat
+CREATE TABLE IF NOT EXISTS "public"."telemetry_heartbeats" (
+    "time" TIMESTAMPTZ NOT NULL,
+    "client_id" BYTEA NOT NULL,
+    "cpu_usage" SMALLINT,
+    "mem_usage" SMALLINT,
+    "disk_usage" SMALLINT,
+    "network_rx" BIGINT,
+    "network_tx" BIGINT,
+    "total_tflops" INTEGER,
+    "device_count" INTEGER
+);
+
+SELECT create_hypertable('telemetry_heartbeats', 'time', chunk_time_interval => INTERVAL '1 day', if_not_exists => TRUE);
+
+CREATE INDEX IF NOT EXISTS idx_telemetry_heartbeats_client_id_time
+ON "public"."telemetry_heartbeats" (client_id, time DESC);
+
+-- One row per device of a stored heartbeat
+CREATE TABLE IF NOT EXISTS "public"."telemetry_device_samples" (
+    "time" TIMESTAMPTZ NOT NULL,
+    "client_id" BYTEA NOT NULL,
+    "device_index" SMALLINT NOT NULL,
+    "device_name" VARCHAR(255),
+    "utilization" SMALLINT,
+    "temperature" SMALLINT,
+    "power_usage" SMALLINT,
+    "memory_usage" SMALLINT
+);
+
+SELECT create_hypertable('telemetry_device_samples', 'time', chunk_time_interval => INTERVAL '1 day', if_not_exists => TRUE);
+
+CREATE INDEX IF NOT EXISTS idx_telemetry_device_samples_client_id_time
+ON "public"."telemetry_device_samples" (client_id, device_index, time DESC);